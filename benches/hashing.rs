@@ -0,0 +1,60 @@
+//! Hashing throughput and verification of a large package
+//!
+//! cargo bench --bench hashing --features fixtures
+//! cargo bench --bench hashing --features fixtures,asm
+//!
+//! `EAPPX_BENCH_SIZE` sets the size of the verified file in MiB (default 256),
+//! e.g. `EAPPX_BENCH_SIZE=4096` for a 4 GiB package. The package is written to
+//! the temp directory once and removed afterwards.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use eappx::{
+    digest::HashAlgorithm,
+    fixtures::FixtureSpec,
+    utils::BLOCK_SIZE,
+    writer::WriterOptions,
+    EAppxFile,
+};
+
+fn bench_size() -> u64 {
+    std::env::var("EAPPX_BENCH_SIZE").ok()
+        .and_then(|size| size.parse::<u64>().ok())
+        .unwrap_or(256) * 1024 * 1024
+}
+
+fn block_digest(c: &mut Criterion) {
+    let block = vec![0x5Au8; BLOCK_SIZE];
+    let mut group = c.benchmark_group("block_digest");
+    group.throughput(Throughput::Bytes(BLOCK_SIZE as u64));
+    for algorithm in HashAlgorithm::ALL {
+        group.bench_with_input(BenchmarkId::from_parameter(algorithm), &block, |b, block| {
+            b.iter(|| algorithm.digest(block))
+        });
+    }
+    group.finish();
+}
+
+fn verify(c: &mut Criterion) {
+    let size = bench_size();
+    let mut group = c.benchmark_group("verify");
+    group.sample_size(10).throughput(Throughput::Bytes(size));
+
+    for (name, encrypted) in [("plain", false), ("encrypted", true)] {
+        let path = std::env::temp_dir().join(format!("eappx-bench-{name}-{}.eappx", std::process::id()));
+        let spec = FixtureSpec { entries: 1, total_size: size, compressed: 0.0, keys: encrypted as usize, ..Default::default() };
+        let (_, fixture) = spec.generate(std::fs::File::create(&path).unwrap(), WriterOptions::default()).unwrap();
+
+        let mut eappx = EAppxFile::from_source(&std::fs::File::open(&path).unwrap()).unwrap();
+        eappx.load_keys(&fixture.key_collection()).unwrap();
+        group.bench_function(BenchmarkId::new(name, size / (1024 * 1024)), |b| b.iter(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            assert!(eappx.verify(&file).is_valid());
+        }));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, block_digest, verify);
+criterion_main!(benches);
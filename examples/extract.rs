@@ -0,0 +1,28 @@
+//! Decrypt and extract a package into a directory
+//!
+//! cargo run --example extract <output directory> [package]
+//!
+//! Without package argument the generated sample package is extracted,
+//! packages are decrypted with the global test key.
+
+use eappx::{sample, EAppxFile};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let outdir = std::env::args().nth(1).ok_or("Usage: extract <output directory> [package]")?;
+    let package = match std::env::args().nth(2) {
+        Some(path) => std::fs::read(path)?,
+        None => sample::build_sample_package()?,
+    };
+
+    let mut eappx = EAppxFile::from_source(&package)?;
+    eappx.load_keys(&sample::sample_keys())?;
+
+    if !eappx.header.key_ids.iter().all(|key_id| eappx.keys.contains_key(key_id)) {
+        return Err("Package is encrypted with keys other than the test key".into());
+    }
+
+    eappx.extract(&package, outdir.as_ref())?;
+    println!("Extracted {} files to {outdir}", eappx.blockmap.files.len());
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+//! Print header information and the files of a package
+//!
+//! cargo run --example list [package]
+//!
+//! Without argument the generated sample package is listed.
+
+use eappx::{sample, utils, EAppxFile};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let package = match std::env::args().nth(1) {
+        Some(path) => std::fs::read(path)?,
+        None => sample::build_sample_package()?,
+    };
+
+    let eappx = EAppxFile::from_source(&package)?;
+
+    println!("{}", eappx.header);
+    println!("{:<60} {:>10} Encrypted", "Name", "Size");
+    for file in &eappx.blockmap.files {
+        println!("{:<60} {:>10} {}", file.name, utils::get_filesize_with_unit(file.size), file.is_encrypted());
+    }
+
+    Ok(())
+}
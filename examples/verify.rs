@@ -0,0 +1,26 @@
+//! Check all files of a package against their blockmap hashes
+//!
+//! cargo run --example verify [package]
+//!
+//! Without argument the generated sample package is verified. No keys are
+//! needed, block hashes cover the encrypted data.
+
+use eappx::{sample, EAppxFile};
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let package = match std::env::args().nth(1) {
+        Some(path) => std::fs::read(path)?,
+        None => sample::build_sample_package()?,
+    };
+
+    let eappx = EAppxFile::from_source(&package)?;
+
+    let report = eappx.verify(&package);
+    print!("{report}");
+
+    if !report.is_valid() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! Jobs file for `batch`, JSON
+//!
+//! ```json
+//! [
+//!     { "package": "TestApp.emsix", "action": "unpack", "output_directory": "out/TestApp" },
+//!     { "package": "Other.emsixbundle", "action": "verify" },
+//!     { "package": "Library" }
+//! ]
+//! ```
+//!
+//! Paths are relative to the jobs file. Directories stand for all encrypted
+//! packages below them, jobs without action run the one given by `--action`.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use eappx::{
+    extension::{file_extension, ContainerKind, PackageEra},
+    keys::KeyCollection,
+    EAppxFile,
+};
+use serde::Deserialize;
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchAction {
+    /// Summary as `info --json` prints it
+    #[default]
+    Info,
+    /// Check all block and file hashes
+    Verify,
+    /// Extract, each package into its own directory
+    Unpack,
+}
+
+impl BatchAction {
+    fn name(self) -> &'static str {
+        match self {
+            BatchAction::Info => "info",
+            BatchAction::Verify => "verify",
+            BatchAction::Unpack => "unpack",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub package: PathBuf,
+    pub action: Option<BatchAction>,
+    /// Unpack target, by default a directory named after the package below `--output-directory`
+    pub output_directory: Option<PathBuf>,
+}
+
+impl Job {
+    pub fn new(package: PathBuf) -> Self {
+        Self { package, action: None, output_directory: None }
+    }
+}
+
+/// Load a jobs file, resolving its paths relative to it
+pub fn load_jobs(path: &Path) -> Result<Vec<Job>> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read jobs file {}", path.display()))?;
+    let mut jobs: Vec<Job> = serde_json::from_str(&data)
+        .with_context(|| format!("Invalid jobs file {}", path.display()))?;
+
+    let base = path.parent().unwrap_or(Path::new(""));
+    for job in &mut jobs {
+        job.package = base.join(&job.package);
+        job.output_directory = job.output_directory.as_ref().map(|dir| base.join(dir));
+    }
+    Ok(jobs)
+}
+
+/// Replace jobs naming a directory by one job per encrypted package below it
+pub fn expand_directories(jobs: Vec<Job>) -> Result<Vec<Job>> {
+    let mut expanded = vec![];
+    for job in jobs {
+        if !job.package.is_dir() {
+            expanded.push(job);
+            continue;
+        }
+
+        let mut packages = vec![];
+        collect_packages(&job.package, &mut packages)?;
+        if packages.is_empty() {
+            log::warn!("No encrypted packages in {}", job.package.display());
+        }
+        packages.sort();
+        expanded.extend(packages.into_iter().map(|package| Job { package, ..job.clone() }));
+    }
+    Ok(expanded)
+}
+
+fn is_encrypted_package(path: &Path) -> bool {
+    let Some(extension) = path.extension().and_then(|e| e.to_str()) else {
+        return false;
+    };
+    [ContainerKind::Package, ContainerKind::Bundle].into_iter()
+        .flat_map(|kind| [PackageEra::Appx, PackageEra::Msix].map(|era| file_extension(kind, era, true)))
+        .any(|known| known.eq_ignore_ascii_case(extension))
+}
+
+/// Encrypted packages below `dir`, by extension
+pub fn collect_packages(dir: &Path, packages: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_packages(&path, packages)?;
+        } else if is_encrypted_package(&path) {
+            packages.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of one job, failures do not stop the batch
+pub struct JobResult {
+    pub package: PathBuf,
+    pub action: BatchAction,
+    pub result: Result<serde_json::Value>,
+}
+
+impl JobResult {
+    pub fn to_json(&self) -> serde_json::Value {
+        let (result, error) = match &self.result {
+            Ok(result) => (Some(result), None),
+            Err(e) => (None, Some(format!("{e:#}"))),
+        };
+        serde_json::json!({
+            "package": self.package,
+            "action": self.action.name(),
+            "ok": self.result.is_ok(),
+            "error": error,
+            "result": result,
+        })
+    }
+}
+
+impl std::fmt::Display for JobResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.result {
+            Ok(_) => write!(f, "OK   {} ({})", self.package.display(), self.action.name()),
+            Err(e) => write!(f, "FAIL {} ({}): {e:#}", self.package.display(), self.action.name()),
+        }
+    }
+}
+
+/// Runs jobs one after another with one set of keys
+pub struct BatchRunner<'a> {
+    pub keys: &'a KeyCollection,
+    pub default_action: BatchAction,
+    /// Parent of the per-package unpack directories
+    pub output_directory: Option<PathBuf>,
+}
+
+impl BatchRunner<'_> {
+    pub fn run(&self, job: &Job) -> JobResult {
+        let action = job.action.unwrap_or(self.default_action);
+        log::info!("{} {}", action.name(), job.package.display());
+        let result = self.run_action(job, action);
+        if let Err(e) = &result {
+            log::error!("{} failed: {e:#}", job.package.display());
+        }
+        JobResult { package: job.package.clone(), action, result }
+    }
+
+    fn run_action(&self, job: &Job, action: BatchAction) -> Result<serde_json::Value> {
+        let file = std::fs::File::open(&job.package)?;
+        let mut eappx = EAppxFile::from_source(&file)?;
+        eappx.load_keys(self.keys)?;
+        let missing_keys = eappx.missing_keys().len();
+
+        match action {
+            BatchAction::Info => Ok(crate::package_info_json(&eappx.package_info(&file)?)),
+            BatchAction::Verify => {
+                let report = eappx.verify_parallel(&file);
+                let mut failed = report.failed_files().map(|file| file.name.clone()).collect::<Vec<_>>();
+                if let Some(mismatch) = &report.publisher_mismatch {
+                    failed.push(format!("publisher {:?} does not hash to {}", mismatch.publisher, mismatch.found));
+                }
+                if !failed.is_empty() {
+                    anyhow::bail!("Verification failed: {}", failed.join(", "));
+                }
+                Ok(serde_json::json!({
+                    "files": report.files.len(),
+                    "missing_keys": missing_keys,
+                    "truncated": report.is_truncated(),
+                }))
+            },
+            BatchAction::Unpack => {
+                let outdir = match (&job.output_directory, &self.output_directory) {
+                    (Some(outdir), _) => outdir.clone(),
+                    (None, Some(parent)) => parent.join(job.package.file_name().context("Package path has no file name")?),
+                    (None, None) => anyhow::bail!("No output directory, pass --output-directory or set it in the jobs file"),
+                };
+                std::fs::create_dir_all(&outdir)?;
+                let report = eappx.extract_with_report(&file, &outdir)?;
+                if !report.is_valid() {
+                    let names = report.mismatched().map(|f| f.package_path.as_str()).collect::<Vec<_>>();
+                    anyhow::bail!("Extracted files do not match their hashes: {}", names.join(", "));
+                }
+                Ok(serde_json::json!({
+                    "output_directory": outdir,
+                    "files": report.files.len(),
+                    "missing_keys": missing_keys,
+                    "not_decrypted": report.missing_key().count(),
+                }))
+            },
+        }
+    }
+}
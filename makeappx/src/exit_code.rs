@@ -0,0 +1,82 @@
+//! Exit codes, so scripts can tell failure classes apart without parsing the log
+//!
+//! Commands checking a package return the code their report calls for, errors
+//! are mapped by the [`ErrorKind`] of the eappx error behind them. When a
+//! package fails several checks, a verification failure wins over a signature
+//! failure, which wins over missing keys.
+
+use eappx::error::{Error, ErrorKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Any other error, e.g. reading or writing files
+    Error = 1,
+    /// A hash, structure, content or capability check failed
+    VerificationFailed = 2,
+    /// Encrypted content could not be read or checked for lack of its key
+    MissingKeys = 3,
+    /// Package, manifest, blockmap or keyfile did not parse, or exceeds the resource limits
+    ParseError = 4,
+    /// Signature missing, unreadable or below the required level
+    SignatureFailure = 5,
+    /// Invalid command line, as `EX_USAGE` of sysexits.h
+    Usage = 64,
+}
+
+impl ExitCode {
+    /// Code for an error a command ended with
+    pub fn of_error(error: &anyhow::Error) -> Self {
+        if let Some(failure) = error.downcast_ref::<Failure>() {
+            return failure.code;
+        }
+        let kind = error.chain()
+            .find_map(|cause| cause.downcast_ref::<Error>())
+            .map(Error::kind);
+        match kind {
+            Some(ErrorKind::MissingKey) => ExitCode::MissingKeys,
+            Some(ErrorKind::Decode | ErrorKind::Unsupported | ErrorKind::Limit) => ExitCode::ParseError,
+            Some(ErrorKind::Data) => ExitCode::VerificationFailed,
+            Some(ErrorKind::Io) | None => ExitCode::Error,
+        }
+    }
+
+    /// Most severe of two outcomes, see the module documentation
+    pub fn max(self, other: Self) -> Self {
+        let rank = |code| match code {
+            ExitCode::Success => 0,
+            ExitCode::MissingKeys => 1,
+            ExitCode::SignatureFailure => 2,
+            _ => 3,
+        };
+        match rank(other) > rank(self) {
+            true => other,
+            false => self,
+        }
+    }
+
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+/// Error ending makeappx with a code of its own, for checks failing outside the library
+#[derive(Debug)]
+pub struct Failure {
+    pub code: ExitCode,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(code: ExitCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
@@ -0,0 +1,107 @@
+//! Watch mode for `ingest`, polling a directory for incoming packages
+//!
+//! A package is picked up once its size and modification time held still
+//! for one poll interval, so files still being copied are left alone. Every
+//! package is verified and summarized, optionally extracted, and gets a
+//! `<name>.json` record in the output directory. Packages with a record are
+//! skipped, which makes restarts pick up where the last run stopped.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+
+use crate::batch::{self, BatchAction, BatchRunner, Job};
+
+pub struct Ingest<'a> {
+    pub runner: BatchRunner<'a>,
+    pub watch_directory: PathBuf,
+    /// Records go here, extracted packages into a directory named after them
+    pub output_directory: PathBuf,
+    pub extract: bool,
+}
+
+/// Size and modification time seen on the last poll
+type Snapshot = (u64, Option<SystemTime>);
+
+impl Ingest<'_> {
+    fn record_path(&self, package: &Path) -> Option<PathBuf> {
+        let name = package.file_name()?;
+        Some(self.output_directory.join(format!("{}.json", name.to_string_lossy())))
+    }
+
+    /// Packages without a record, with their current snapshot
+    fn pending(&self) -> Result<Vec<(PathBuf, Snapshot)>> {
+        let mut packages = vec![];
+        batch::collect_packages(&self.watch_directory, &mut packages)?;
+        packages.sort();
+
+        let mut pending = vec![];
+        for package in packages {
+            if self.record_path(&package).is_none_or(|record| record.exists()) {
+                continue;
+            }
+            // Gone since the directory was listed
+            let Ok(metadata) = std::fs::metadata(&package) else {
+                continue;
+            };
+            pending.push((package, (metadata.len(), metadata.modified().ok())));
+        }
+        Ok(pending)
+    }
+
+    /// Verify, summarize and optionally extract one package, then write its record
+    fn ingest(&self, package: &Path) -> Result<bool> {
+        let mut actions = vec![BatchAction::Verify, BatchAction::Info];
+        if self.extract {
+            actions.push(BatchAction::Unpack);
+        }
+
+        let results = actions.into_iter()
+            .map(|action| self.runner.run(&Job { action: Some(action), ..Job::new(package.to_path_buf()) }))
+            .collect::<Vec<_>>();
+        let ok = results.iter().all(|result| result.result.is_ok());
+        let record = serde_json::json!({
+            "package": package,
+            "ok": ok,
+            "jobs": results.iter().map(batch::JobResult::to_json).collect::<Vec<_>>(),
+        });
+
+        let record_path = self.record_path(package).context("Package path has no file name")?;
+        std::fs::write(&record_path, serde_json::to_string_pretty(&record)?)
+            .with_context(|| format!("Failed to write {}", record_path.display()))?;
+        for result in &results {
+            println!("{result}");
+        }
+        Ok(ok)
+    }
+
+    /// Poll every `interval`, with `once` only ingest what is there already and return
+    pub fn run(&self, interval: Duration, once: bool) -> Result<()> {
+        std::fs::create_dir_all(&self.output_directory)?;
+        log::info!("Watching {} for packages", self.watch_directory.display());
+
+        let mut last_seen: HashMap<PathBuf, Snapshot> = HashMap::new();
+        loop {
+            let pending = self.pending()?;
+            for (package, snapshot) in &pending {
+                // Still growing or first seen, look again next time
+                if !once && last_seen.get(package) != Some(snapshot) {
+                    continue;
+                }
+                log::info!("Ingesting {}", package.display());
+                if !self.ingest(package)? {
+                    log::warn!("{} ingested with errors", package.display());
+                }
+            }
+            if once {
+                return Ok(());
+            }
+            last_seen = pending.into_iter().collect();
+            std::thread::sleep(interval);
+        }
+    }
+}
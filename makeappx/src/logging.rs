@@ -0,0 +1,79 @@
+//! Log output on stderr, human readable or as JSON lines for automation
+
+use std::{io::Write, time::SystemTime};
+
+use clap::ValueEnum;
+use log::{LevelFilter, Log, Metadata, Record};
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line: timestamp (unix seconds), level, target, message
+    Json,
+}
+
+/// Level for the `--quiet` and `--verbose` flags, verbose may be repeated
+pub fn level(quiet: bool, verbose: u8) -> LevelFilter {
+    match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    }
+}
+
+pub fn init(level: LevelFilter, format: LogFormat) -> anyhow::Result<()> {
+    match format {
+        LogFormat::Text => simple_logger::SimpleLogger::new().with_level(level).init()?,
+        LogFormat::Json => {
+            log::set_boxed_logger(Box::new(JsonLogger { level }))?;
+            log::set_max_level(level);
+        },
+    }
+    Ok(())
+}
+
+/// Error with its causes on one line, skipping causes the message includes already
+pub fn error_message(error: &anyhow::Error) -> String {
+    error.chain()
+        .map(|cause| cause.to_string())
+        .reduce(|message, cause| match message.ends_with(&cause) {
+            true => message,
+            false => format!("{message}: {cause}"),
+        })
+        .unwrap_or_default()
+}
+
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or_default();
+        let line = serde_json::json!({
+            "timestamp": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        });
+        // A line per record, written at once so threads do not interleave
+        let _ = writeln!(std::io::stderr().lock(), "{line}");
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
@@ -1,12 +1,66 @@
-use std::{io::BufReader, path::PathBuf};
-use uuid::Uuid;
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use std::{io::{BufReader, Read, Seek, Write}, path::{Path, PathBuf}, str::FromStr, time::{Duration, Instant}};
+use zeroize::Zeroizing;
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use batch::{BatchAction, BatchRunner, Job};
+use exit_code::{ExitCode, Failure};
+use logging::LogFormat;
+use pack_config::PackProject;
 use eappx::{
     EAppxFile,
-    keys::{KeyCollection, KeyId}
+    EAppxMagic,
+    Manifest,
+    audit,
+    bundle_manifest::BundleFilter,
+    checkpoint::{self, VerificationState},
+    compression::{self, TrialCompression},
+    crypto,
+    entry_list,
+    extension::{self, ContainerKind, PackageEra},
+    extraction_report::{ExtractionReport, ExtractionStatus},
+    format_spec,
+    identity::PackageVersion,
+    keys::{KeyCollection, KeyId},
+    layout::{CollisionPolicy, DiskSpacePolicy, DuplicatePolicy, ExtractOptions, MissingKeyPolicy, NameSanitization, OverwritePolicy},
+    layout_map::RegionKind,
+    lazy::LazyPackage,
+    limits::ResourceLimits,
+    manifest::{AppxManifest, Identity},
+    metadata_cache::MetadataCache,
+    msix::MsixWriter,
+    package_info::PackageInfo,
+    signature::SigningStatus,
+    signing::SigningIdentity,
+    sink::{EntrySink, SinkEntry},
+    timings::{Stage, Timings},
+    upload::UploadContainer,
+    validation,
+    verify::IntegrityPolicy,
+    writer::{EntryOptions, PackageWriter, WriterOptions},
 };
 
+mod batch;
+mod exit_code;
+mod ingest;
+mod logging;
+mod pack_config;
+mod self_test;
+#[cfg(all(unix, feature = "fuse"))]
+mod mount;
+
+/// Path standing for stdin or stdout
+const STDIO_PATH: &str = "-";
+
+/// Footprint files are generated while packing, never taken from the input directory
+const FOOTPRINT_FILES: &[&str] = &[
+    "AppxBlockMap.xml",
+    "AppxSignature.p7x",
+    "[Content_Types].xml",
+    "AppxMetadata\\CodeIntegrity.cat",
+    // Location `unpack` extracts the catalog to
+    "CodeIntegrity.cat",
+];
+
 /* Common arguments */
 
 #[derive(Parser, Clone, Debug)]
@@ -17,36 +71,204 @@ struct KeyOptions {
     /// Use keyfile
     #[arg(long = "kf")]
     key_file: Option<PathBuf>,
+    /// Read keyfile content from an environment variable
+    #[arg(long = "key-env", value_name = "VAR")]
+    key_env: Option<String>,
+    /// Read keyfile content from stdin
+    #[arg(long = "key-stdin")]
+    key_stdin: bool,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct SigningOptions {
+    /// Sign with this certificate, PFX or PEM (subject has to match the manifest publisher)
+    #[arg(long = "sign-cert")]
+    certificate: Option<PathBuf>,
+    /// Private key for a PEM certificate
+    #[arg(long = "sign-key", requires = "certificate")]
+    key: Option<PathBuf>,
+    /// Password of the PFX
+    #[arg(long = "sign-password", default_value = "", requires = "certificate")]
+    password: String,
+}
+
+impl SigningOptions {
+    fn load(&self) -> Result<Option<SigningIdentity>> {
+        let Some(certificate) = &self.certificate else {
+            return Ok(None);
+        };
+
+        let data = std::fs::read(certificate)?;
+        let identity = match &self.key {
+            Some(key) => SigningIdentity::from_pem(&data, &std::fs::read(key)?)?,
+            None => SigningIdentity::from_pkcs12(&data, &self.password)?,
+        };
+        log::info!("Signing as {:?}", identity.certificate().subject_name());
+        Ok(Some(identity))
+    }
 }
 
 #[derive(Parser, Clone, Debug)]
 struct InputFileOptions {
-    /// Input package filepath
+    /// Input package filepath, `-` reads it from stdin, store uploads (.msixupload, .appxupload) are unwrapped
     #[arg(short, long)]
     package_file: PathBuf,
 }
 
+impl InputFileOptions {
+    /// Locate the package, unwrapping store uploads
+    fn resolve(&self) -> Result<InputPackage> {
+        self.locate()?.unwrap_upload()
+    }
+
+    /// Stdin is spooled to a temporary file as parsing needs random access
+    fn locate(&self) -> Result<InputPackage> {
+        if self.package_file.as_os_str() != STDIO_PATH {
+            return Ok(InputPackage {
+                path: self.package_file.clone(),
+                _spool: None,
+            });
+        }
+
+        let mut spool = tempfile::NamedTempFile::new().context("Failed to create a file to spool stdin to")?;
+        let size = std::io::copy(&mut std::io::stdin().lock(), &mut spool).context("Failed to read the package from stdin")?;
+        log::debug!("Spooled {} from stdin to {}", eappx::utils::get_filesize_with_unit(size), spool.path().display());
+        let spool = spool.into_temp_path();
+        Ok(InputPackage {
+            path: spool.to_path_buf(),
+            _spool: Some(spool),
+        })
+    }
+}
+
+/// Package to read, a spooled copy of stdin lives as long as this
+struct InputPackage {
+    path: PathBuf,
+    _spool: Option<tempfile::TempPath>,
+}
+
+impl InputPackage {
+    fn open(&self) -> Result<std::fs::File> {
+        std::fs::File::open(&self.path)
+            .with_context(|| format!("Failed to open package {}", self.path.display()))
+    }
+
+    /// Parse the package from `file`, through the metadata cache if enabled
+    ///
+    /// Spooled packages live at throwaway paths and are never cached.
+    fn parse(&self, file: &std::fs::File, cache: &CacheOptions) -> Result<EAppxFile> {
+        match cache.cache().filter(|_| self._spool.is_none()) {
+            Some(cache) => Ok(EAppxFile::from_source_cached(file, &self.path, &cache)?),
+            None => Ok(EAppxFile::from_source(file)?),
+        }
+    }
+
+    /// The package a store upload (`.msixupload`, `.appxupload`) wraps, copied out to a temporary file
+    fn unwrap_upload(self) -> Result<Self> {
+        let file = self.open()?;
+        let Some(upload) = UploadContainer::detect(&file, ResourceLimits::default())? else {
+            return Ok(self);
+        };
+        let entry = upload.package_entry()?;
+        log::info!("Unwrapping {} from upload {}", entry.name, self.path.display());
+
+        let mut spool = tempfile::NamedTempFile::new().context("Failed to create a file to unwrap the upload to")?;
+        std::io::copy(&mut entry.reader(&file)?, &mut spool)
+            .with_context(|| format!("Failed to copy {} out of the upload", entry.name))?;
+        let spool = spool.into_temp_path();
+        Ok(InputPackage {
+            path: spool.to_path_buf(),
+            _spool: Some(spool),
+        })
+    }
+}
+
+#[derive(Parser, Clone, Debug)]
+struct CacheOptions {
+    /// Reuse footers and blockmap parsed by earlier runs, as long as the package is unchanged
+    #[arg(long)]
+    metadata_cache: bool,
+    /// Cache directory, defaults to $EAPPX_CACHE_DIR or eappx in the user's cache directory
+    #[arg(long, requires = "metadata_cache")]
+    cache_dir: Option<PathBuf>,
+}
+
+impl CacheOptions {
+    fn cache(&self) -> Option<MetadataCache> {
+        if !self.metadata_cache {
+            return None;
+        }
+        let dir = self.cache_dir.clone().or_else(MetadataCache::default_dir);
+        if dir.is_none() {
+            log::warn!("No cache directory found, pass --cache-dir to use the metadata cache");
+        }
+        dir.map(MetadataCache::new)
+    }
+}
+
 #[derive(Parser, Clone, Debug)]
 struct OutputFileOptions {
-    /// Output package filepath
+    /// Output package filepath, the extension is adjusted to the produced container
     #[arg(short, long)]
     output_file: PathBuf,
+    /// Override the extension derived from container type and manifest (e.g. "eappx")
+    #[arg(long)]
+    extension: Option<String>,
+}
+
+impl OutputFileOptions {
+    /// Output path carrying the conventional (or explicitly requested) extension
+    fn path_for(&self, kind: ContainerKind, era: PackageEra, encrypted: bool) -> PathBuf {
+        let extension = self.extension.as_deref()
+            .map(|ext| ext.trim_start_matches('.'))
+            .unwrap_or(extension::file_extension(kind, era, encrypted));
+
+        let path = &self.output_file;
+        let current = path.extension().and_then(|e| e.to_str());
+        if current == Some(extension) {
+            return path.clone();
+        }
+
+        // Names like TestApp_1.0.0.0_x64 have no real extension to replace
+        let path = match current {
+            Some(current) if extension::KNOWN_EXTENSIONS.contains(&current.to_lowercase().as_str()) => path.with_extension(extension),
+            _ => PathBuf::from(format!("{}.{extension}", path.display())),
+        };
+        log::info!("Writing to {} to match the produced container", path.display());
+        path
+    }
 }
 
 #[derive(Parser, Clone, Debug)]
 struct InputDirectoryOptions {
-    /// Input directory path
-    #[arg(short, long)]
-    directory: PathBuf,
+    /// Input directory path, overrides the one of the project file
+    #[arg(short, long, required_unless_present = "config")]
+    directory: Option<PathBuf>,
 }
 
 #[derive(Parser, Clone, Debug)]
 struct OutputDirectoryOptions {
-    /// Output directory path
-    #[arg(short, long)]
+    /// Output directory path, or archive filepath where `-` writes to stdout
+    #[arg(short, long, visible_alias = "output")]
     output_directory: PathBuf,
 }
 
+#[derive(Parser, Clone, Debug)]
+struct IntegrityOptions {
+    /// Skip checking block and file hashes while reading files
+    #[arg(long)]
+    no_verify: bool,
+}
+
+impl IntegrityOptions {
+    fn policy(&self) -> IntegrityPolicy {
+        match self.no_verify {
+            true => IntegrityPolicy::None,
+            false => IntegrityPolicy::Full,
+        }
+    }
+}
+
 /* Subcommand options */
 
 #[derive(Parser, Clone, Debug)]
@@ -59,6 +281,58 @@ struct PackOptions {
 
     #[clap(flatten)]
     output_file: OutputFileOptions,
+
+    #[clap(flatten)]
+    signing: SigningOptions,
+
+    /// Generate AppxMetadata/CodeIntegrity.cat for the PE files packed
+    #[arg(long)]
+    code_integrity: bool,
+
+    /// How files without a compression rule in the project file are picked for deflating
+    #[arg(long, value_enum, default_value_t)]
+    compression: CompressionMode,
+
+    /// Deflate level, higher is smaller and slower
+    #[arg(long, default_value_t = compression::DEFAULT_LEVEL, value_parser = clap::value_parser!(u32).range(0..=compression::MAX_LEVEL as i64))]
+    compression_level: u32,
+
+    /// Alignment of payload offsets in encrypted packages
+    #[arg(long, value_enum, default_value_t)]
+    align: PayloadAlignment,
+
+    /// Byte-identical output for identical input: no random catalog identifier or timestamp
+    #[arg(long)]
+    deterministic: bool,
+
+    /// Project file (TOML or JSON): identity, per-file compression and keys, exclusions, footprint options
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct ManifestGenOptions {
+    /// Directory of the files to pack
+    #[arg(short, long)]
+    directory: PathBuf,
+    /// Where AppxManifest.xml and AppxBlockMap.xml are written, defaults to the input directory
+    #[arg(short, long)]
+    output_directory: Option<PathBuf>,
+    /// Package name (manifest Identity Name)
+    #[arg(long)]
+    name: String,
+    /// Publisher, e.g. "CN=Contoso"
+    #[arg(long)]
+    publisher: String,
+    /// Version, four dot separated numbers
+    #[arg(long, default_value = "1.0.0.0")]
+    version: PackageVersion,
+    /// Processor architecture, neutral if not given
+    #[arg(long)]
+    arch: Option<String>,
+    /// Replace AppxManifest.xml and AppxBlockMap.xml if they exist
+    #[arg(long)]
+    force: bool,
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -69,6 +343,252 @@ struct UnpackOptions {
     input_file: InputFileOptions,
     #[clap(flatten)]
     output_directory: OutputDirectoryOptions,
+    #[clap(flatten)]
+    integrity: IntegrityOptions,
+    /// Strip directory components, all files land in the output directory
+    #[arg(long)]
+    flat: bool,
+    /// Keep directories differing only in case apart instead of merging them
+    #[arg(long)]
+    preserve_case: bool,
+    /// How to treat files already present in the output directory
+    #[arg(long, value_enum, default_value_t)]
+    overwrite: OverwriteMode,
+    /// How to treat entries sharing a file on case-insensitive filesystems
+    #[arg(long, value_enum, default_value_t)]
+    on_collision: CollisionMode,
+    /// How to write names the filesystem cannot store, the report maps them back
+    #[arg(long, value_enum, default_value_t)]
+    names: NamesMode,
+    /// How to treat encrypted files whose key was not given
+    #[arg(long, value_enum, default_value_t)]
+    on_missing_key: MissingKeyMode,
+    /// How to write files with the same content (file hash) as one extracted before
+    #[arg(long, value_enum, default_value_t)]
+    duplicates: DuplicatesMode,
+    /// Resume an interrupted extraction, same as `--overwrite skip-if-hash-matches`
+    #[arg(long, conflicts_with = "overwrite")]
+    resume: bool,
+    /// Extract even if the output volume looks too small, with a warning
+    #[arg(long)]
+    force: bool,
+    /// Go on past files failing to extract, list them at the end (directory output only)
+    #[arg(long)]
+    continue_on_error: bool,
+    /// Write a JSON report of all extracted files (directory output only)
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Rebuild the footer table from the blockmap if it is damaged, extracting what can be located
+    #[arg(long)]
+    recover: bool,
+    /// Output format, for archives the output path names the archive file
+    #[arg(long, value_enum, default_value_t)]
+    format: UnpackFormat,
+    /// Only extract AppxBlockMap.xml, AppxSignature.p7x, CodeIntegrity.cat, metadata files and the manifest
+    #[arg(long, conflicts_with = "format")]
+    footprint_only: bool,
+    /// Bundles: only extract application packages for these architectures
+    #[arg(long = "arch", value_delimiter = ',')]
+    architectures: Vec<String>,
+    /// Bundles: only extract resource packages for these languages (`en` matches `en-US`)
+    #[arg(long = "lang", value_delimiter = ',')]
+    languages: Vec<String>,
+    /// Bundles: only extract resource packages for these scales
+    #[arg(long = "scale", value_delimiter = ',')]
+    scales: Vec<String>,
+    /// Print the time spent per stage, per file and in total (directory output only)
+    #[arg(long)]
+    timings: bool,
+    /// Check the extracted executables and DLLs against CodeIntegrity.cat (directory output only)
+    #[arg(long)]
+    ci: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum UnpackFormat {
+    /// Plain files in a directory
+    #[default]
+    Dir,
+    Zip,
+    Tar,
+    /// Directory as Windows installs the package, with a blockmap for the plaintext.
+    /// Register it with `Add-AppxPackage -Register AppxManifest.xml`
+    Install,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum CompressionMode {
+    /// Store already compressed formats (png, mp4, zip, ...) like MakeAppx
+    #[default]
+    Extension,
+    /// Deflate the first 64KiB on trial, store files that do not shrink by 5%
+    Trial,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum PayloadAlignment {
+    /// Payloads follow each other directly, like MakeAppx
+    #[default]
+    None,
+    /// 0x200 byte sectors
+    Sector,
+    /// 64KiB blocks
+    Block,
+}
+
+impl PayloadAlignment {
+    fn bytes(self) -> u64 {
+        match self {
+            PayloadAlignment::None => 1,
+            PayloadAlignment::Sector => eappx::utils::SECTOR_SIZE as u64,
+            PayloadAlignment::Block => eappx::utils::BLOCK_SIZE as u64,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum OverwriteMode {
+    Skip,
+    #[default]
+    Overwrite,
+    ErrorIfExists,
+    SkipIfHashMatches,
+}
+
+impl From<OverwriteMode> for OverwritePolicy {
+    fn from(value: OverwriteMode) -> Self {
+        match value {
+            OverwriteMode::Skip => OverwritePolicy::Skip,
+            OverwriteMode::Overwrite => OverwritePolicy::Overwrite,
+            OverwriteMode::ErrorIfExists => OverwritePolicy::ErrorIfExists,
+            OverwriteMode::SkipIfHashMatches => OverwritePolicy::SkipIfHashMatches,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum CollisionMode {
+    #[default]
+    Overwrite,
+    Error,
+    Rename,
+}
+
+impl From<CollisionMode> for CollisionPolicy {
+    fn from(value: CollisionMode) -> Self {
+        match value {
+            CollisionMode::Overwrite => CollisionPolicy::Overwrite,
+            CollisionMode::Error => CollisionPolicy::Error,
+            CollisionMode::Rename => CollisionPolicy::Rename,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum NamesMode {
+    /// Replace what this system refuses with `_`
+    #[default]
+    Host,
+    /// Replace what Windows refuses with `_`, on every system
+    Portable,
+    /// Percent-encode what Windows refuses and `%`
+    Escape,
+    /// Percent-encode like `escape`, plus all non-ASCII characters
+    EscapeNonAscii,
+}
+
+impl From<NamesMode> for NameSanitization {
+    fn from(value: NamesMode) -> Self {
+        match value {
+            NamesMode::Host => NameSanitization::Host,
+            NamesMode::Portable => NameSanitization::Portable,
+            NamesMode::Escape => NameSanitization::Escape,
+            NamesMode::EscapeNonAscii => NameSanitization::EscapeNonAscii,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum MissingKeyMode {
+    /// Fail before extracting anything
+    #[default]
+    Fail,
+    /// Extract everything else
+    Skip,
+    /// Write the ciphertext instead
+    Ciphertext,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum SigningLevel {
+    /// Any readable signature, self-signed included
+    TestSigned,
+    /// Signed by a CA
+    Signed,
+    /// Signed by a Microsoft Store CA
+    StoreSigned,
+}
+
+impl From<SigningLevel> for SigningStatus {
+    fn from(value: SigningLevel) -> Self {
+        match value {
+            SigningLevel::TestSigned => SigningStatus::TestSigned,
+            SigningLevel::Signed => SigningStatus::Signed,
+            SigningLevel::StoreSigned => SigningStatus::StoreSigned,
+        }
+    }
+}
+
+impl From<MissingKeyMode> for MissingKeyPolicy {
+    fn from(value: MissingKeyMode) -> Self {
+        match value {
+            MissingKeyMode::Fail => MissingKeyPolicy::FailFast,
+            MissingKeyMode::Skip => MissingKeyPolicy::SkipEncryptedWithoutKey,
+            MissingKeyMode::Ciphertext => MissingKeyPolicy::ExtractCiphertext,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum DuplicatesMode {
+    /// Decrypt and write each of them
+    #[default]
+    Extract,
+    /// Hard link to the first one, copy where the filesystem cannot link
+    HardLink,
+    /// Copy the first one
+    Copy,
+}
+
+impl From<DuplicatesMode> for DuplicatePolicy {
+    fn from(value: DuplicatesMode) -> Self {
+        match value {
+            DuplicatesMode::Extract => DuplicatePolicy::Extract,
+            DuplicatesMode::HardLink => DuplicatePolicy::HardLink,
+            DuplicatesMode::Copy => DuplicatePolicy::Copy,
+        }
+    }
+}
+
+#[derive(Parser, Clone, Debug)]
+struct ExtractFileOptions {
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Path inside the package, matched case-insensitively (`\` or `/` separated)
+    #[arg(short, long)]
+    name: String,
+    /// Output filepath, `-` writes to stdout
+    #[arg(short, long)]
+    output_file: PathBuf,
+    /// Copy the payload as stored (deflated and/or encrypted), without decoding or checking it
+    #[arg(long)]
+    raw: bool,
+    #[clap(flatten)]
+    integrity: IntegrityOptions,
+    #[clap(flatten)]
+    cache: CacheOptions,
 }
 
 #[derive(Parser, Clone, Debug)]
@@ -89,116 +609,1820 @@ struct DecryptOptions {
     input_file: InputFileOptions,
     #[clap(flatten)]
     output_file: OutputFileOptions,
+    #[clap(flatten)]
+    integrity: IntegrityOptions,
+    #[clap(flatten)]
+    signing: SigningOptions,
 }
 
 #[derive(Parser, Clone, Debug)]
-struct InfoOptions {
+struct RekeyOptions {
     #[clap(flatten)]
     input_file: InputFileOptions,
+    #[clap(flatten)]
+    output_file: OutputFileOptions,
+    /// Keyfile holding the current keys
+    #[arg(long = "old-kf")]
+    old_key_file: PathBuf,
+    /// Keyfile holding the new key(s)
+    #[arg(long = "new-kf")]
+    new_key_file: PathBuf,
 }
 
-/* Subcommands */
+#[derive(Parser, Clone, Debug)]
+struct RenameOptions {
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    #[clap(flatten)]
+    output_file: OutputFileOptions,
+    /// New package name (manifest Identity Name)
+    #[arg(long, required_unless_present = "publisher")]
+    name: Option<String>,
+    /// New publisher, the publisher-id of the package full name follows it
+    #[arg(long)]
+    publisher: Option<String>,
+}
 
-#[derive(Subcommand, Clone, Debug)]
-enum Commands {
-    /// Pack bare files into msix
-    Pack(PackOptions),
-    /// Unpack msix into bare files
-    Unpack(UnpackOptions),
-    /// Create bundle from bare files
-    Bundle(PackOptions),
-    /// Extract bare files from bundle
-    Unbundle(UnpackOptions),
-    /// Encrypt
-    Encrypt(EncryptOptions),
-    /// Decrypt
-    Decrypt(DecryptOptions),
-    /// Print infos about a package
-    Info(InfoOptions)
+#[derive(Parser, Clone, Debug)]
+struct ReplaceOptions {
+    /// Keys of the replaced file, if it is encrypted
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Path of the file inside the package
+    #[arg(long)]
+    name: String,
+    /// File holding the new contents
+    #[arg(long = "with")]
+    with: PathBuf,
+    /// Write the patched package here instead of patching the input in place
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
 }
 
-/* Main opts */
+#[derive(Parser, Clone, Debug)]
+struct ValidateOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Fail on warnings as well
+    #[arg(long)]
+    strict: bool,
+}
 
-#[derive(Parser, Debug)]
-#[command(version = "1.0", author = "tuxuser", arg_required_else_help = true)]
-struct Opts {
-    /// Command
-    #[command(subcommand)]
-    cmd: Commands,
+#[derive(Parser, Clone, Debug)]
+struct AuditOptions {
+    /// Keys to read the manifests with
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Fail if a capability of this severity or above is declared
+    #[arg(long, value_enum, default_value_t)]
+    fail_on: AuditSeverity,
+    /// Print the findings as JSON
+    #[arg(long)]
+    json: bool,
+}
 
-    #[arg(long, short)]
-    verbose: bool,
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum AuditSeverity {
+    Low,
+    Medium,
+    High,
+    /// Full trust, elevation or access to all files of the user
+    #[default]
+    Critical,
 }
 
-fn main() -> Result<()>
-{
-    simple_logger::init_with_level(log::Level::Debug)?;
-    let opts: Opts = Opts::parse();
+impl From<AuditSeverity> for audit::Severity {
+    fn from(value: AuditSeverity) -> Self {
+        match value {
+            AuditSeverity::Low => audit::Severity::Low,
+            AuditSeverity::Medium => audit::Severity::Medium,
+            AuditSeverity::High => audit::Severity::High,
+            AuditSeverity::Critical => audit::Severity::Critical,
+        }
+    }
+}
 
-    let mut key_collection = KeyCollection::default();
+#[derive(Parser, Clone, Debug)]
+struct InfoOptions {
+    /// Keys to verify the file hashes of encrypted files with (optional)
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Only print the header, skipping footers, blockmap and verification
+    #[arg(long)]
+    header_only: bool,
+    /// Only print a summary: identity, sizes, encryption and footprint
+    #[arg(long, conflicts_with = "header_only")]
+    summary: bool,
+    /// List every footer instead of summarizing them
+    #[arg(long, conflicts_with_all = ["header_only", "summary"])]
+    detailed: bool,
+    /// Print the summary as JSON
+    #[arg(long, conflicts_with_all = ["header_only", "detailed"])]
+    json: bool,
+    /// Print the time spent reading, inflating, decrypting and hashing, per file and in total
+    #[arg(long, conflicts_with_all = ["header_only", "summary", "json"])]
+    timings: bool,
+    /// Also check executables and DLLs against CodeIntegrity.cat
+    #[arg(long, conflicts_with_all = ["header_only", "summary", "json"])]
+    ci: bool,
+}
 
-    match opts.cmd {
-        Commands::Pack(_args)
-        | Commands::Bundle(_args) => {
-            todo!("Repacking")
-        },
-        Commands::Unpack(args)
-        | Commands::Unbundle(args) => {
-            let infile = args.input_file.package_file;
-            let outdir = args.output_directory.output_directory;
-            println!("Using file path: {:?}", infile);
+#[derive(Parser, Clone, Debug)]
+struct VerifyOptions {
+    /// Keys to verify the file hashes of encrypted files with (optional)
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Save progress to this file while verifying and continue from it if it exists
+    #[arg(long)]
+    resume: Option<PathBuf>,
+    /// Blocks of 64 KiB verified between saving progress, larger files are verified in steps of this size
+    #[arg(long, default_value_t = checkpoint::DEFAULT_CHECKPOINT_BLOCKS, requires = "resume")]
+    checkpoint_blocks: usize,
+    /// Fail with a signature failure unless the package is signed at least this well
+    #[arg(long, value_enum)]
+    require_signing: Option<SigningLevel>,
+}
 
-            if let Some(key_file) = args.key_options.key_file {
-                let mut keyfile = std::fs::File::open(key_file)?;
-                let loaded_keys = KeyCollection::from_reader(&mut keyfile)?;
+#[derive(Parser, Clone, Debug)]
+struct StatsOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+}
 
-                key_collection.extend(loaded_keys.keys);
-            }
+#[derive(Parser, Clone, Debug)]
+struct ListOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    #[arg(long, value_enum, default_value_t)]
+    format: ListFormat,
+    #[clap(flatten)]
+    cache: CacheOptions,
+}
 
-            if args.key_options.key_test {
-                // Add global testkey
-                key_collection.add(
-                    KeyId::Guid((
-                        Uuid::parse_str("ddafcf67-7b2c-086d-302b-8adac1bdd3a7")?,
-                        Uuid::parse_str("7d53aeb8-5922-f062-b1d7-7e09f5a187a0")?
-                    )),
-                    hex::decode("9fe75f879e95a5d7f3715c30fce71067fc346efd680fa25e3c737d76acb72b9d")?
-                );
-            }
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum ListFormat {
+    /// Aligned columns
+    #[default]
+    Table,
+    /// An array of entry objects
+    Json,
+    /// A header line and one line per entry, for spreadsheets
+    Csv,
+}
 
-            let file = std::fs::File::open(infile)?;
-            let mut bufreader = BufReader::new(file);
-            let mut eappx = EAppxFile::from_stream(&mut bufreader)?;
-        
-            println!("Got all keys: {}", key_collection.has_required_keys(&eappx.header.key_ids));
-            println!("{eappx}");
-            eappx.load_keys(&key_collection)?;
-        
-            if !outdir.exists() {
-                println!("Create directory: {:?}", &outdir);
-                std::fs::create_dir_all(&outdir)?;
-            }
-            
-            eappx.extract(
-                &mut bufreader,
-                &outdir
-            )?;
-        },
-        Commands::Encrypt(_args) => {
-            todo!("Converting zip-style msix/appx to emsix/eappx")
-        },
-        Commands::Decrypt(_args) => {
-            todo!("Converting emsix/eappx to zip-style msix/appx")
-        },
-        Commands::Info(args) => {
-            let file = std::fs::File::open(args.input_file.package_file)?;
-            let mut bufreader = BufReader::new(file);
-            let eappx = EAppxFile::from_stream(&mut bufreader)?;
-            println!("{eappx}");
-            println!("Verifying");
-            eappx.verify_blockmap_files(&mut bufreader)?;
-        },
-    }
+#[derive(Parser, Clone, Debug)]
+struct LayoutOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Print the regions as JSON
+    #[arg(long)]
+    json: bool,
+}
 
-    Ok(())
+#[cfg(all(unix, feature = "fuse"))]
+#[derive(Parser, Clone, Debug)]
+struct MountOptions {
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Directory to mount the package at
+    mountpoint: PathBuf,
+    #[clap(flatten)]
+    integrity: IntegrityOptions,
+    /// MiB of decrypted blocks kept in memory for repeated reads, 0 disables the cache
+    #[arg(long, default_value_t = 64)]
+    cache_mib: usize,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct DiffOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Newer package filepath
+    #[arg(short, long)]
+    new_package_file: PathBuf,
+    /// List every changed block index
+    #[arg(long)]
+    blocks: bool,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct CompareOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Directory the package was extracted to
+    #[arg(short, long)]
+    directory: PathBuf,
+    /// The package was extracted with `--flat`
+    #[arg(long)]
+    flat: bool,
+    /// The package was extracted with `--preserve-case`
+    #[arg(long)]
+    preserve_case: bool,
+    /// Names were written with this `--names` mode
+    #[arg(long, value_enum, default_value_t)]
+    names: NamesMode,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct DeltaCreateOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Newer package filepath
+    #[arg(short, long)]
+    new_package_file: PathBuf,
+    /// Delta filepath
+    #[arg(short, long)]
+    output_file: PathBuf,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct DeltaApplyOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Delta filepath
+    #[arg(short, long)]
+    delta_file: PathBuf,
+    /// Reconstructed package filepath
+    #[arg(short, long)]
+    output_file: PathBuf,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum DeltaCommands {
+    /// Create a delta holding only the blocks missing from the older package
+    Create(DeltaCreateOptions),
+    /// Reconstruct the newer package from the older one and a delta
+    Apply(DeltaApplyOptions),
+}
+
+#[derive(Parser, Clone, Debug)]
+struct BlocksExportOptions {
+    #[clap(flatten)]
+    input_file: InputFileOptions,
+    /// Directory of the block store, created if missing
+    #[arg(short, long)]
+    store: PathBuf,
+    /// Block manifest filepath
+    #[arg(short, long)]
+    manifest_file: PathBuf,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct BlocksReassembleOptions {
+    /// Directory of the block store
+    #[arg(short, long)]
+    store: PathBuf,
+    /// Block manifest filepath
+    #[arg(short, long)]
+    manifest_file: PathBuf,
+    /// Reassembled package filepath
+    #[arg(short, long)]
+    output_file: PathBuf,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum BlocksCommands {
+    /// Put the blocks of a package into a content-addressed store and write its manifest
+    Export(BlocksExportOptions),
+    /// Rebuild a package from its manifest and the store
+    Reassemble(BlocksReassembleOptions),
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+enum SpecFormat {
+    #[default]
+    Markdown,
+    Json,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct CryptoDebugOptions {
+    /// Path inside the package, casing as recorded in the blockmap
+    #[arg(short, long)]
+    file: String,
+    /// Package family name (`Name_PublisherId`), taken from the package if omitted
+    #[arg(long, required_unless_present = "package_file")]
+    pfn: Option<String>,
+    /// Package to take the family name from and look the path up in
+    #[arg(short, long)]
+    package_file: Option<PathBuf>,
+    /// First sector to print the tweak of
+    #[arg(long, default_value_t = 0)]
+    sector: u128,
+    /// Number of sectors to print
+    #[arg(long, default_value_t = 1)]
+    count: u128,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct FormatSpecOptions {
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t)]
+    format: SpecFormat,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct CompletionsOptions {
+    /// Shell to generate the completion script for
+    #[arg(value_enum)]
+    shell: clap_complete::Shell,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct ManpageOptions {
+    /// Write makeappx.1 and a page per subcommand into this directory instead of printing makeappx.1
+    #[arg(short, long)]
+    output_directory: Option<PathBuf>,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct KeysNewOptions {
+    /// Keyfile to create, printed to stdout if omitted
+    #[arg(short, long)]
+    output_file: Option<PathBuf>,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct KeysInfoOptions {
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    /// Package whose key-ids to check, exits with 3 if the keys do not cover them
+    #[arg(short, long)]
+    package_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+enum KeysCommands {
+    /// Generate a random content key with a fresh key-id
+    New(KeysNewOptions),
+    /// Print key-ids in all representations and key lengths, never the keys themselves
+    Info(KeysInfoOptions),
+}
+
+/* Subcommands */
+
+#[derive(Subcommand, Clone, Debug)]
+enum Commands {
+    /// Pack bare files into msix
+    Pack(PackOptions),
+    /// Write a draft AppxManifest.xml and the AppxBlockMap.xml of a directory, to review before packing
+    ManifestGen(ManifestGenOptions),
+    /// Unpack msix into bare files
+    Unpack(UnpackOptions),
+    /// Create bundle from bare files
+    Bundle(PackOptions),
+    /// Extract bare files from bundle
+    Unbundle(UnpackOptions),
+    /// Extract a single file
+    ExtractFile(ExtractFileOptions),
+    /// Encrypt
+    Encrypt(EncryptOptions),
+    /// Decrypt into a standard zip-style msix/appx (or bundle)
+    Decrypt(DecryptOptions),
+    /// Re-encrypt a package with new keys
+    Rekey(RekeyOptions),
+    /// Change name and/or publisher of an encrypted package, re-encrypting its files
+    Rename(RenameOptions),
+    /// Replace a single file by appending its new payload, without repacking
+    Replace(ReplaceOptions),
+    /// Print infos about a package
+    Info(InfoOptions),
+    /// Verify block and file hashes, exits with 2 if a file failed, 3 if keys are missing, 5 if --require-signing is not met
+    Verify(VerifyOptions),
+    /// List every file with its sizes, hashes and flags
+    List(ListOptions),
+    /// Check the package structure without extracting, exits with 2 if problems were found
+    Validate(ValidateOptions),
+    /// Classify the declared capabilities by severity, exits with 2 if one reaches --fail-on
+    Audit(AuditOptions),
+    /// Print size statistics by compression, encryption and file extension
+    Stats(StatsOptions),
+    /// Print the byte ranges of header, footer table, footprint files and payloads
+    Layout(LayoutOptions),
+    /// Run info, verify or unpack across many packages, exits with 1 if any of them failed
+    Batch(BatchOptions),
+    /// Watch a directory, verifying, summarizing and optionally extracting every package arriving there
+    Ingest(IngestOptions),
+    /// Compare two packages by their block hashes
+    Diff(DiffOptions),
+    /// Check an extracted directory against the package hashes, exits with 2 if files differ
+    Compare(CompareOptions),
+    /// Mount the decrypted package contents read-only via FUSE
+    #[cfg(all(unix, feature = "fuse"))]
+    Mount(MountOptions),
+    /// Create and apply delta updates between packages
+    Delta {
+        #[command(subcommand)]
+        cmd: DeltaCommands,
+    },
+    /// Deduplicated storage of packages in a content-addressed block store
+    Blocks {
+        #[command(subcommand)]
+        cmd: BlocksCommands,
+    },
+    /// Print the binary layout of the container structures
+    FormatSpec(FormatSpecOptions),
+    /// Print how the XTS tweaks of a file are derived, to diagnose files failing to decrypt
+    CryptoDebug(CryptoDebugOptions),
+    /// Check this build against known-good crypto and format vectors, exits with 1 on failure
+    SelfTest,
+    /// Manage content keys
+    Keys {
+        #[command(subcommand)]
+        cmd: KeysCommands,
+    },
+    /// Print the shell completion script
+    Completions(CompletionsOptions),
+    /// Print the man page
+    Manpage(ManpageOptions),
+}
+
+#[derive(Parser, Clone, Debug)]
+struct BatchOptions {
+    /// Keys shared by all packages
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    /// Packages, directories stand for all encrypted packages below them
+    #[arg(required_unless_present = "manifest")]
+    packages: Vec<PathBuf>,
+    /// JSON file listing jobs, run after the packages given as arguments
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+    /// Action for packages without one of their own
+    #[arg(long, value_enum, default_value_t)]
+    action: BatchAction,
+    /// Unpack: every package goes into a directory named after it below this one
+    #[arg(short, long)]
+    output_directory: Option<PathBuf>,
+    /// Print the aggregated results as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Parser, Clone, Debug)]
+struct IngestOptions {
+    /// Keys shared by all packages
+    #[clap(flatten)]
+    key_options: KeyOptions,
+    /// Directory new packages arrive in
+    #[arg(long)]
+    watch: PathBuf,
+    /// Directory for the JSON records and extracted packages
+    #[arg(short, long)]
+    output_directory: PathBuf,
+    /// Also extract every package into a directory named after it
+    #[arg(long)]
+    extract: bool,
+    /// Seconds between polls, a package has to stay unchanged that long to be picked up
+    #[arg(long, default_value_t = 5)]
+    interval: u64,
+    /// Ingest the packages present now and exit instead of watching
+    #[arg(long)]
+    once: bool,
+}
+
+/* Main opts */
+
+#[derive(Parser, Debug)]
+#[command(version = "1.0", author = "tuxuser", arg_required_else_help = true)]
+struct Opts {
+    /// Command
+    #[command(subcommand)]
+    cmd: Commands,
+
+    /// Only log errors
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Log debug messages, repeat for trace messages
+    #[arg(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Log format on stderr, results are printed to stdout either way
+    #[arg(long, value_enum, global = true, default_value_t)]
+    log_format: LogFormat,
+}
+
+fn load_keys(key_options: &KeyOptions) -> Result<KeyCollection> {
+    let mut key_collection = KeyCollection::default();
+
+    if let Some(key_file) = &key_options.key_file {
+        let mut keyfile = std::fs::File::open(key_file)?;
+        let loaded_keys = KeyCollection::from_reader(&mut keyfile)?;
+
+        key_collection.merge(loaded_keys);
+    }
+
+    // Raw keyfile content is wiped as soon as it is parsed
+    if let Some(var) = &key_options.key_env {
+        let data = Zeroizing::new(std::env::var(var)
+            .with_context(|| format!("Failed to read keys from environment variable {var}"))?);
+        let loaded_keys = KeyCollection::from_str(&data)
+            .with_context(|| format!("Invalid keys in environment variable {var}"))?;
+
+        key_collection.merge(loaded_keys);
+    }
+
+    if key_options.key_stdin {
+        let mut data = Zeroizing::new(String::new());
+        std::io::stdin().read_to_string(&mut data).context("Failed to read keys from stdin")?;
+        let loaded_keys = KeyCollection::from_str(&data).context("Invalid keys on stdin")?;
+
+        key_collection.merge(loaded_keys);
+    }
+
+    if key_options.key_test {
+        key_collection.add_test_key();
+    }
+
+    Ok(key_collection)
+}
+
+/// Name the key-ids of the package no key was given for, files using them stay undecrypted
+fn log_missing_keys(eappx: &EAppxFile) {
+    let missing = eappx.missing_keys();
+    match missing.is_empty() {
+        true => log::info!("Got all {} key(s)", eappx.header.key_ids.len()),
+        false => log::warn!("Missing {} of {} key(s): {}", missing.len(), eappx.header.key_ids.len(),
+            missing.iter().map(KeyId::to_keyfile_string).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+/// Collect package-relative (backslash separated) paths of all files below `dir`
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+            continue;
+        }
+
+        let name = path.strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\\");
+        files.push(name);
+    }
+
+    Ok(())
+}
+
+fn manifest_gen(args: &ManifestGenOptions) -> Result<()> {
+    let manifest = eappx::skeleton::draft_manifest(Identity {
+        name: args.name.clone(),
+        publisher: args.publisher.clone(),
+        version: args.version,
+        arch: args.arch.clone(),
+        resource_id: None,
+    });
+    let manifest_xml = manifest.to_xml();
+
+    // An existing manifest is replaced by the draft, as it will be packed
+    let mut files = vec![];
+    collect_files(&args.directory, &args.directory, &mut files)?;
+    files.retain(|name| name != "AppxManifest.xml" && !FOOTPRINT_FILES.iter().any(|f| f.eq_ignore_ascii_case(name)));
+    files.sort();
+    let readers = files.iter()
+        .map(|name| Ok((name.as_str(), Box::new(std::fs::File::open(args.directory.join(name.replace('\\', std::path::MAIN_SEPARATOR_STR)))?) as Box<dyn Read>)))
+        .chain(std::iter::once(Ok(("AppxManifest.xml", Box::new(manifest_xml.as_bytes()) as Box<dyn Read>))))
+        .collect::<Result<Vec<_>>>()?;
+    let blockmap = eappx::skeleton::skeleton_blockmap(readers)?;
+
+    let outdir = args.output_directory.as_ref().unwrap_or(&args.directory);
+    std::fs::create_dir_all(outdir)?;
+    let outputs = [("AppxManifest.xml", manifest_xml), ("AppxBlockMap.xml", blockmap.to_xml())];
+    if let Some(path) = outputs.iter().map(|(name, _)| outdir.join(name)).find(|path| path.exists() && !args.force) {
+        anyhow::bail!("{} already exists, pass --force to replace it", path.display());
+    }
+    for (name, xml) in outputs {
+        std::fs::write(outdir.join(name), xml)?;
+    }
+
+    println!("Package full name: {}", manifest.identity.package_full_name());
+    for file in &blockmap.files {
+        println!("{:#06x} {} ({})", file.id(), file.name, eappx::utils::get_filesize_with_unit(file.size));
+    }
+    Ok(())
+}
+
+fn pack(args: &PackOptions) -> Result<()> {
+    let project = args.config.as_deref().map(PackProject::load).transpose()?;
+    let settings = |name: &str| project.as_ref().map(|project| project.settings(name)).unwrap_or_default();
+    let indir = args.input_directory.directory.clone()
+        .or_else(|| project.as_ref().and_then(|project| project.directory.clone()))
+        .context("No input directory given")?;
+
+    let manifest_xml = std::fs::read_to_string(indir.join("AppxManifest.xml"))
+        .context("Input directory has no AppxManifest.xml")?;
+    let mut manifest = AppxManifest::from_xml(manifest_xml.as_bytes())?;
+    let manifest_xml = match project.as_ref().map(|project| &project.config.identity).filter(|identity| !identity.is_empty()) {
+        Some(identity) => {
+            identity.apply(&mut manifest.identity);
+            manifest.to_xml()
+        },
+        None => manifest_xml,
+    };
+    let package_full_name = manifest.identity.package_full_name();
+    let code_integrity = args.code_integrity || project.as_ref().is_some_and(|project| project.config.footprint.code_integrity);
+
+    // Files are encrypted with the first key unless the project file says otherwise, sorted for a stable choice
+    let mut loaded: Vec<(KeyId, Vec<u8>)> = load_keys(&args.key_options)?.keys.drain().collect();
+    loaded.sort_by_key(|(key_id, _)| key_id.to_string());
+    // Key-ids are compared parsed, a numeric id matches its 32-byte form
+    let mut key_ids = vec![];
+    match project.as_ref().and_then(|project| project.config.default_key.as_deref()) {
+        Some(default_key) => key_ids.push(KeyId::from_str(default_key)?),
+        None => key_ids.extend(loaded.first().map(|(key_id, _)| key_id.clone())),
+    }
+    for key in project.iter().flat_map(|project| &project.config.files).filter_map(|rule| rule.key.as_deref()) {
+        let key = KeyId::from_str(key)?;
+        if !key_ids.contains(&key) {
+            key_ids.push(key);
+        }
+    }
+    let keys = key_ids.iter()
+        .map(|key_id| loaded.iter()
+            .position(|(loaded_id, _)| loaded_id == key_id)
+            .map(|index| loaded.swap_remove(index))
+            .with_context(|| format!("Key {} is not loaded", key_id.to_keyfile_string())))
+        .collect::<Result<Vec<_>>>()?;
+    let key_index = (!keys.is_empty()).then_some(0);
+    let signer = args.signing.load()?;
+    if key_index.is_some() && signer.is_some() {
+        anyhow::bail!("Signing is only supported for unencrypted packages");
+    }
+
+    let mut files = vec![];
+    collect_files(&indir, &indir, &mut files)?;
+    files.retain(|name| !FOOTPRINT_FILES.iter().any(|f| f.eq_ignore_ascii_case(name)));
+    files.retain(|name| name == "AppxManifest.xml" || !project.as_ref().is_some_and(|project| project.is_excluded(name)));
+    files.sort();
+
+    // The manifest is packed as patched by the project file
+    let open = |name: &str| -> Result<Box<dyn Read>> {
+        Ok(match name {
+            "AppxManifest.xml" => Box::new(std::io::Cursor::new(manifest_xml.clone().into_bytes())),
+            _ => Box::new(std::fs::File::open(indir.join(name.replace('\\', std::path::MAIN_SEPARATOR_STR)))?),
+        })
+    };
+
+    log::info!("Packing {} files as {package_full_name}, deflating with {} at level {}", files.len(), compression::BACKEND, args.compression_level);
+    let trial = TrialCompression { level: args.compression_level, ..Default::default() };
+    let output_path = args.output_file.path_for(ContainerKind::Package, PackageEra::detect(&manifest_xml), key_index.is_some());
+    // Readable as well, the package is read back for validation
+    let outfile = std::fs::File::options().read(true).write(true).create(true).truncate(true).open(output_path)?;
+
+    if key_index.is_none() {
+        log::warn!("No key given, writing a standard unencrypted package");
+        if args.align != PayloadAlignment::None {
+            log::warn!("--align only applies to encrypted packages, ignoring it");
+        }
+        // MakeAppx writes the manifest after all other payload files
+        files.sort_by_key(|name| name == "AppxManifest.xml");
+
+        let outfile = std::io::BufWriter::new(outfile);
+        let mut writer = match signer {
+            Some(identity) => MsixWriter::new_signed(outfile, ContainerKind::Package, identity),
+            None => MsixWriter::new(outfile, ContainerKind::Package),
+        };
+        if code_integrity {
+            writer = writer.generate_code_integrity();
+        }
+        if args.deterministic {
+            writer = writer.deterministic();
+        }
+        writer = writer.compression_level(args.compression_level)?;
+        if args.compression == CompressionMode::Trial {
+            writer = writer.compression_policy(trial);
+        }
+        for name in &files {
+            let settings = settings(name);
+            if settings.encrypt == Some(true) {
+                anyhow::bail!("{name} is to be encrypted, but no key was given");
+            }
+            match settings.compress {
+                Some(compress) => writer.add_file_with(name, &mut open(name)?, compress)?,
+                None => writer.add_file(name, &mut open(name)?)?,
+            };
+        }
+        writer.finish(None)?;
+        return Ok(());
+    }
+
+    // Manifest goes first, Windows expects it as first blockmap entry
+    files.sort_by_key(|name| name != "AppxManifest.xml");
+    let options = WriterOptions::default()
+        .code_integrity(code_integrity)
+        .deterministic(args.deterministic)
+        .alignment(args.align.bytes())
+        .compression_level(args.compression_level);
+    let mut writer = PackageWriter::new(outfile, EAppxMagic::EXPH, &package_full_name, keys, options)?;
+    if args.compression == CompressionMode::Trial {
+        writer = writer.compression_policy(trial);
+    }
+
+    for name in &files {
+        let settings = settings(name);
+        let key_index = match (name.as_str(), settings.encrypt, settings.key) {
+            ("AppxManifest.xml", ..) | (_, Some(false), _) => None,
+            (_, _, Some(key)) => {
+                let key = KeyId::from_str(key)?;
+                key_ids.iter().position(|key_id| *key_id == key).map(|index| index as u16)
+            },
+            _ => key_index,
+        };
+        // Without a rule the policy decides, encrypted files are stored
+        match settings.compress {
+            Some(compress) => writer.add_entry(name, &mut open(name)?, EntryOptions::default().compress(compress).key_index(key_index))?,
+            None => writer.add_file(name, &mut open(name)?, key_index)?,
+        }
+    }
+
+    // Read back to catch what parses here but fails to deploy
+    let (_, report) = writer.finish_validated()?;
+    for issue in report.warnings() {
+        log::warn!("{issue}");
+    }
+    let errors = report.errors().map(ToString::to_string).collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Err(Failure::new(ExitCode::VerificationFailed,
+            format!("Packed package would fail to deploy: {}", errors.join(", "))).into());
+    }
+    Ok(())
+}
+
+fn package_info_json(info: &PackageInfo) -> serde_json::Value {
+    let applications = info.applications.iter()
+        .map(|application| serde_json::json!({
+            "id": application.id,
+            "executable": application.executable,
+            "entry_point": application.entry_point,
+            "start_page": application.start_page,
+            "display_name": application.display_name,
+        }))
+        .collect::<Vec<_>>();
+    let payloads = info.payloads.iter()
+        .map(|payload| serde_json::json!({
+            "file_name": payload.file_name,
+            "architecture": payload.name(),
+            "resource": payload.is_resource,
+        }))
+        .collect::<Vec<_>>();
+    let signature = info.signature.as_ref().map(|signature| {
+        let certificates = std::iter::once(&signature.signer).chain(&signature.chain)
+            .map(|certificate| serde_json::json!({
+                "subject": certificate.subject,
+                "issuer": certificate.issuer,
+                "serial_number": certificate.serial_number,
+                "thumbprint": certificate.thumbprint,
+                "not_before": certificate.not_before,
+                "not_after": certificate.not_after,
+            }))
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "signer": signature.signer.subject,
+            "thumbprint": signature.signer.thumbprint,
+            "digest_algorithm": signature.digest_algorithm,
+            "signing_time": signature.signing_time,
+            "timestamp": signature.timestamp,
+            "certificates": certificates,
+        })
+    });
+    serde_json::json!({
+        "name": info.name,
+        "publisher": info.publisher,
+        "version": info.version,
+        "architecture": info.architecture,
+        "architectures": info.architectures(),
+        "package_full_name": info.package_full_name,
+        "bundle": info.is_bundle,
+        "file_count": info.file_count,
+        "package_count": info.package_count,
+        "payloads": payloads,
+        "applications": applications,
+        "total_size": info.total_size,
+        "stored_size": info.stored_size,
+        "file_len": info.file_len,
+        "crypto_algo": info.crypto_algo,
+        "key_ids": info.key_ids.iter().map(KeyId::to_keyfile_string).collect::<Vec<_>>(),
+        "signed": info.signed,
+        "signing_status": info.signing_status.to_string(),
+        "signature": signature,
+        "code_integrity": info.code_integrity,
+    })
+}
+
+fn batch(args: &BatchOptions) -> Result<bool> {
+    let mut jobs = args.packages.iter().cloned().map(Job::new).collect::<Vec<_>>();
+    if let Some(manifest) = &args.manifest {
+        jobs.extend(batch::load_jobs(manifest)?);
+    }
+    let jobs = batch::expand_directories(jobs)?;
+
+    let keys = load_keys(&args.key_options)?;
+    let runner = BatchRunner {
+        keys: &keys,
+        default_action: args.action,
+        output_directory: args.output_directory.clone(),
+    };
+    let results = jobs.iter().map(|job| runner.run(job)).collect::<Vec<_>>();
+    let failed = results.iter().filter(|result| result.result.is_err()).count();
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "succeeded": results.len() - failed,
+            "failed": failed,
+            "jobs": results.iter().map(batch::JobResult::to_json).collect::<Vec<_>>(),
+        }))?);
+    } else {
+        for result in &results {
+            println!("{result}");
+        }
+        println!("{} succeeded, {failed} failed", results.len() - failed);
+    }
+    Ok(failed == 0)
+}
+
+fn ingest(args: &IngestOptions) -> Result<()> {
+    let keys = load_keys(&args.key_options)?;
+    let ingest = ingest::Ingest {
+        runner: BatchRunner {
+            keys: &keys,
+            default_action: BatchAction::Info,
+            output_directory: Some(args.output_directory.clone()),
+        },
+        watch_directory: args.watch.clone(),
+        output_directory: args.output_directory.clone(),
+        extract: args.extract,
+    };
+    ingest.run(std::time::Duration::from_secs(args.interval), args.once)
+}
+
+fn list(args: &ListOptions) -> Result<()> {
+    let input = args.input_file.resolve()?;
+    let eappx = input.parse(&input.open()?, &args.cache)?;
+    let rows = eappx.entry_rows()?;
+    match args.format {
+        ListFormat::Table => {
+            println!("{:<60} {:>10} {:>10} {:<10} {:<9} Key", "Name", "Size", "Stored", "Compressed", "Encrypted");
+            for row in &rows {
+                let key_id = row.key_id.as_ref().map(KeyId::to_keyfile_string).unwrap_or_default();
+                println!("{:<60} {:>10} {:>10} {:<10} {:<9} {key_id}", row.name, eappx::utils::get_filesize_with_unit(row.size),
+                    row.stored.map(eappx::utils::get_filesize_with_unit).unwrap_or_default(), row.compressed, row.encrypted);
+            }
+        },
+        ListFormat::Json => {
+            let entries = rows.iter()
+                .map(|row| serde_json::json!({
+                    "name": row.name,
+                    "file_id": row.file_id,
+                    "size": row.size,
+                    "stored": row.stored,
+                    "offset": row.offset,
+                    "compressed": row.compressed,
+                    "encrypted": row.encrypted,
+                    "key_id": row.key_id.as_ref().map(KeyId::to_keyfile_string),
+                    "blocks": row.blocks,
+                    "hash_algorithm": row.hash_algorithm.to_string(),
+                    "filehash": row.filehash.as_ref().map(hex::encode),
+                }))
+                .collect::<Vec<_>>();
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        },
+        ListFormat::Csv => entry_list::write_csv(&rows, std::io::stdout().lock())?,
+    }
+    Ok(())
+}
+
+fn layout(args: &LayoutOptions) -> Result<()> {
+    let eappx = EAppxFile::from_source(&args.input_file.resolve()?.open()?)?;
+    let map = eappx.layout_map();
+    if !args.json {
+        print!("{map}");
+        return Ok(());
+    }
+
+    let regions = map.regions.iter()
+        .map(|region| {
+            let (kind, name, file_id) = match &region.kind {
+                RegionKind::Header => ("header", None, None),
+                RegionKind::FooterTable => ("footer_table", None, None),
+                RegionKind::Footprint(file) => ("footprint", Some(file.package_path().to_owned()), None),
+                RegionKind::Payload { file_id, name } => ("payload", name.clone(), Some(*file_id)),
+            };
+            serde_json::json!({
+                "kind": kind,
+                "name": name,
+                "file_id": file_id,
+                "offset": region.offset,
+                "length": region.length,
+            })
+        })
+        .collect::<Vec<_>>();
+    let gaps = map.gaps().into_iter()
+        .map(|(offset, length)| serde_json::json!({ "offset": offset, "length": length }))
+        .collect::<Vec<_>>();
+    let json = serde_json::json!({
+        "file_len": map.file_len,
+        "regions": regions,
+        "gaps": gaps,
+    });
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
+
+/// Run all structural checks, returns whether the package passed
+fn validate(args: &ValidateOptions) -> Result<bool> {
+    let file = args.input_file.resolve()?.open()?;
+    let eappx = EAppxFile::from_source(&file)?;
+
+    let mut report = validation::validate_package(&eappx);
+    report.issues.extend(validation::validate_bundle(&eappx, &file).issues);
+    report.issues.extend(validation::validate_identity(&eappx, &file).issues);
+    report.issues.sort_by_key(|issue| (std::cmp::Reverse(issue.severity), issue.rule));
+
+    for issue in &report.issues {
+        println!("{issue}");
+    }
+
+    let (errors, warnings) = (report.errors().count(), report.warnings().count());
+    let passed = errors == 0 && !(args.strict && warnings > 0);
+    println!("{errors} errors, {warnings} warnings => {}", if passed { "PASS" } else { "FAIL" });
+
+    Ok(passed)
+}
+
+/// Returns whether no capability reached `--fail-on`
+fn audit(args: &AuditOptions) -> Result<bool> {
+    let file = args.input_file.resolve()?.open()?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.load_keys(&load_keys(&args.key_options)?)?;
+
+    let audit = eappx.audit_capabilities(&file)?;
+    let fail_on = audit::Severity::from(args.fail_on);
+    let failed = audit.at_least(fail_on).count();
+
+    if args.json {
+        let findings = audit.findings.iter()
+            .map(|finding| serde_json::json!({
+                "package": finding.package,
+                "kind": finding.kind.tag(),
+                "name": finding.name,
+                "severity": finding.severity.to_string(),
+                "reason": finding.reason,
+            }))
+            .collect::<Vec<_>>();
+        let skipped = audit.skipped.iter()
+            .map(|(package, reason)| serde_json::json!({ "package": package, "reason": reason }))
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+            "findings": findings,
+            "skipped": skipped,
+            "max_severity": audit.max_severity().map(|severity| severity.to_string()),
+            "passed": failed == 0,
+        }))?);
+        return Ok(failed == 0);
+    }
+
+    let mut findings = audit.findings.iter().collect::<Vec<_>>();
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    for finding in findings {
+        println!("{finding}");
+    }
+    for (package, reason) in &audit.skipped {
+        println!("Skipped {package}: {reason}");
+    }
+    println!("{} capabilities, {} restricted, {failed} at {fail_on} or above => {}",
+        audit.findings.len(), audit.restricted().count(), if failed == 0 { "PASS" } else { "FAIL" });
+
+    Ok(failed == 0)
+}
+
+/// Returns the exit code of the most severe failure, see [`ExitCode::max`]
+fn verify(args: &VerifyOptions) -> Result<ExitCode> {
+    let package = args.input_file.resolve()?;
+    let file = package.open()?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.load_keys(&load_keys(&args.key_options)?)?;
+    log_missing_keys(&eappx);
+
+    let report = match &args.resume {
+        Some(state_path) => {
+            let mut state = match std::fs::read(state_path) {
+                Ok(data) => serde_json::from_slice::<VerificationState>(&data)
+                    .with_context(|| format!("Failed to parse verification state {}", state_path.display()))?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => VerificationState::default(),
+                Err(err) => return Err(err).with_context(|| format!("Failed to read verification state {}", state_path.display())),
+            };
+            if !state.files.is_empty() || state.partial.is_some() {
+                log::info!("Resuming from {}, {} file(s) done", state_path.display(), state.files.len());
+            }
+            // Written aside and renamed over, an interruption never leaves a torn state behind
+            let temp_path = state_path.with_extension("tmp");
+            eappx.verify_resumable(&file, &mut state, args.checkpoint_blocks, |state| {
+                std::fs::write(&temp_path, serde_json::to_vec(state).map_err(|e| eappx::error::Error::DataError(e.to_string()))?)?;
+                std::fs::rename(&temp_path, state_path)?;
+                Ok(())
+            })?
+        },
+        None => {
+            eappx.verify_parallel(&file)
+        },
+    };
+
+    print!("{report}");
+
+    let mut code = match report.is_valid() {
+        true => ExitCode::Success,
+        false => ExitCode::VerificationFailed,
+    };
+    let unverified = report.unverified_files().count();
+    if unverified > 0 {
+        log::warn!("{unverified} encrypted file(s) not verified for lack of a key");
+        code = code.max(ExitCode::MissingKeys);
+    }
+    if let Some(required) = args.require_signing {
+        let signature = eappx.signature_info(&file).unwrap_or_else(|e| {
+            log::warn!("Failed to parse AppxSignature.p7x: {e}");
+            None
+        });
+        let status = SigningStatus::new(eappx.header.is_signed(), signature.as_ref(), &eappx.header.key_ids);
+        if status < required.into() {
+            log::error!("Package is {status}, {} required", SigningStatus::from(required));
+            code = code.max(ExitCode::SignatureFailure);
+        }
+    }
+
+    Ok(code)
+}
+
+fn print_key_id(key_id: &KeyId) {
+    let (first, second) = key_id.to_guid();
+    println!("  keyfile: {}", key_id.to_keyfile_string());
+    println!("  guids:   {first} {second}");
+    println!("  short:   {}", key_id.to_short_keyfile_string().as_deref().unwrap_or("-"));
+    println!("  hex:     {}", key_id.to_hex());
+}
+
+/// Returns whether the keys cover all key-ids of the package, if one was given
+fn keys_info(args: &KeysInfoOptions) -> Result<bool> {
+    let keys = load_keys(&args.key_options)?;
+    let mut entries = keys.keys.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(key_id, _)| key_id.to_keyfile_string());
+    for (index, (key_id, key)) in entries.iter().enumerate() {
+        println!("Key {}/{}", index + 1, entries.len());
+        print_key_id(key_id);
+        println!("  length:  {:#x}", key.len());
+    }
+    println!("{} key(s)", entries.len());
+
+    let Some(package_file) = &args.package_file else {
+        return Ok(true);
+    };
+    let header = EAppxFile::peek_header(&std::fs::File::open(package_file)?)?;
+    println!("Package {} ({}, key length {:#x})", package_file.display(), header.crypto_algo(), header.key_length);
+
+    let mut covered = true;
+    for (index, key_id) in header.key_ids.iter().enumerate() {
+        let status = match keys.keys.get(key_id) {
+            None => "missing",
+            Some(key) if key.len() != header.key_length as usize => "wrong length",
+            Some(_) => "covered",
+        };
+        covered &= status == "covered";
+        println!("[{index}] {} {status}", key_id.to_keyfile_string());
+    }
+    for (key_id, _) in entries.iter().filter(|(key_id, _)| !header.key_ids.contains(key_id)) {
+        println!("Unused key-id {}", key_id.to_keyfile_string());
+    }
+    println!("=> {}", if covered { "COVERED" } else { "NOT COVERED" });
+
+    Ok(covered)
+}
+
+fn rekey(args: &RekeyOptions) -> Result<()> {
+    let old_keys = KeyCollection::from_reader(&mut std::fs::File::open(&args.old_key_file)?)?;
+    let mut new_keys: Vec<(KeyId, Vec<u8>)> = KeyCollection::from_reader(&mut std::fs::File::open(&args.new_key_file)?)?
+        .keys
+        .drain()
+        .collect();
+    new_keys.sort_by_key(|(key_id, _)| key_id.to_string());
+
+    let file = args.input_file.resolve()?.open()?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.load_keys(&old_keys)?;
+
+    let manifest_xml = match eappx.blockmap.files.first() {
+        Some(manifest) => std::io::read_to_string(eappx.file_reader(&file, manifest)?)?,
+        None => String::new(),
+    };
+    let output_path = args.output_file.path_for(ContainerKind::Package, PackageEra::detect(&manifest_xml), true);
+
+    log::info!("Rekeying {} with {} new key(s)", eappx.header.package_full_name(), new_keys.len());
+    let outfile = std::fs::File::create(output_path)?;
+    eappx::rekey::rekey(&eappx, &file, new_keys, outfile, WriterOptions::default())?;
+
+    Ok(())
+}
+
+fn rename(args: &RenameOptions) -> Result<()> {
+    let file = args.input_file.resolve()?.open()?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.load_keys(&load_keys(&args.key_options)?)?;
+
+    let manifest_xml = match eappx.blockmap.files.first() {
+        Some(manifest) => std::io::read_to_string(eappx.file_reader(&file, manifest)?)?,
+        None => String::new(),
+    };
+    let output_path = args.output_file.path_for(ContainerKind::Package, PackageEra::detect(&manifest_xml), true);
+
+    let outfile = std::fs::File::create(output_path)?;
+    eappx::rekey::rename(&eappx, &file, args.name.as_deref(), args.publisher.as_deref(), outfile, WriterOptions::default())?;
+
+    Ok(())
+}
+
+fn replace(args: &ReplaceOptions) -> Result<()> {
+    let input = args.input_file.resolve()?;
+    let target = match &args.output_file {
+        Some(output_file) => {
+            std::fs::copy(&input.path, output_file)
+                .with_context(|| format!("Failed to copy the package to {}", output_file.display()))?;
+            output_file.clone()
+        },
+        None if args.input_file.package_file.as_os_str() == STDIO_PATH => anyhow::bail!("A package read from stdin cannot be patched in place, pass --output-file"),
+        None => input.path.clone(),
+    };
+
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&target)
+        .with_context(|| format!("Failed to open {} for writing", target.display()))?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.load_keys(&load_keys(&args.key_options)?)?;
+
+    let mut replacement = BufReader::new(std::fs::File::open(&args.with)
+        .with_context(|| format!("Failed to open {}", args.with.display()))?);
+    let mut editor = eappx.edit(&mut file)?;
+    editor.replace_file(&args.name, &mut replacement)?;
+    editor.commit()?;
+
+    println!("Replaced {} in {}", args.name, target.display());
+    Ok(())
+}
+
+fn decrypt(args: &DecryptOptions) -> Result<()> {
+    let file = args.input_file.resolve()?.open()?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.load_keys(&load_keys(&args.key_options)?)?;
+    eappx.integrity = args.integrity.policy();
+    eappx.signer = args.signing.load()?;
+
+    let (manifest_xml, has_bundle_manifest) = match eappx.blockmap.files.first() {
+        Some(manifest) => (
+            std::io::read_to_string(eappx.file_reader(&file, manifest)?)?,
+            manifest.name.ends_with("AppxBundleManifest.xml"),
+        ),
+        None => (String::new(), false),
+    };
+    let kind = ContainerKind::from_magic(eappx.header.magic, has_bundle_manifest);
+    let output_path = args.output_file.path_for(kind, PackageEra::detect(&manifest_xml), false);
+
+    log::info!("Decrypting {} into {}", eappx.header.package_full_name(), output_path.display());
+    let outfile = std::io::BufWriter::new(std::fs::File::create(&output_path)?);
+    if let Err(e) = eappx.convert_to_msix(&file, outfile).and_then(|mut out| Ok(out.flush()?)) {
+        // A partial zip is of no use to anyone
+        std::fs::remove_file(&output_path)?;
+        return Err(e.into());
+    }
+
+    Ok(())
+}
+
+/// Create the output file, `-` writes to stdout
+fn create_output(path: &Path) -> Result<Box<dyn Write>> {
+    Ok(match path.as_os_str() == STDIO_PATH {
+        true => Box::new(std::io::BufWriter::new(std::io::stdout().lock())),
+        false => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+    })
+}
+
+/// Hands every entry the same writer
+struct WriterSink(Box<dyn Write>);
+
+impl EntrySink for WriterSink {
+    fn create(&mut self, _entry: &SinkEntry) -> std::result::Result<Box<dyn Write + '_>, eappx::error::Error> {
+        Ok(Box::new(&mut self.0))
+    }
+}
+
+fn extract_file(args: &ExtractFileOptions) -> Result<()> {
+    let input = args.input_file.resolve()?;
+    let package = input.open()?;
+    let mut eappx = input.parse(&package, &args.cache)?;
+    eappx.load_keys(&load_keys(&args.key_options)?)?;
+    eappx.integrity = args.integrity.policy();
+
+    if args.raw {
+        let name = args.name.replace('/', "\\");
+        let file = eappx.blockmap.find_file(&name)
+            .with_context(|| format!("File {name} not found in blockmap"))?;
+        let (footer, mut raw) = eappx.raw_reader(&package, file)?;
+        log::info!("{} stored at {:#x}: compressed={}, encrypted={}, key-id={}", file.name, footer.offset_to_file,
+            footer.compression_type == 0x1, footer.is_encrypted(eappx.header.is_bundle()),
+            eappx.header.key_ids.get(footer.key_id_index as usize).map(KeyId::to_keyfile_string).unwrap_or("-".into()));
+        let mut out = create_output(&args.output_file)?;
+        std::io::copy(&mut raw, &mut out)?;
+        out.flush()?;
+        return Ok(());
+    }
+
+    let mut sink = WriterSink(create_output(&args.output_file)?);
+    let extracted = eappx.extract_entry_to(&package, &args.name.replace('/', "\\"), &mut sink)?;
+    sink.0.flush()?;
+    if extracted.status == ExtractionStatus::HashMismatch {
+        return Err(Failure::new(ExitCode::VerificationFailed, format!("{} does not match its hash", extracted.package_path)).into());
+    }
+
+    Ok(())
+}
+
+fn crypto_debug(args: &CryptoDebugOptions) -> Result<()> {
+    let package = args.package_file.as_ref()
+        .map(|path| EAppxFile::from_source(&std::fs::File::open(path)?))
+        .transpose()?;
+    let pfn = match (&args.pfn, &package) {
+        (Some(pfn), _) => pfn.clone(),
+        (None, Some(eappx)) => format!("{}_{}", eappx.header.app_name(), eappx.header.publisher_id()),
+        (None, None) => unreachable!("clap requires --pfn or --package-file"),
+    };
+
+    if let Some(eappx) = &package {
+        let header_pfn = format!("{}_{}", eappx.header.app_name(), eappx.header.publisher_id());
+        if !header_pfn.eq_ignore_ascii_case(&pfn) {
+            log::warn!("Package family name of the package is {header_pfn}");
+        }
+        match eappx.blockmap.find(&args.file) {
+            Some((_, file)) if crypto::canonicalize_file_path(&file.name) != crypto::canonicalize_file_path(&args.file) =>
+                log::warn!("Blockmap records the path as {}, the tweak covers its exact casing", file.name),
+            Some(_) => {},
+            None => log::warn!("{} is not listed in the blockmap", args.file),
+        }
+    }
+
+    let hash = crypto::hash_for_file_tweak(&args.file, &pfn);
+    let tweak = crypto::get_tweak_value(&args.file, &pfn);
+    println!("Hashed path:  {}", crypto::canonicalize_file_path(&args.file));
+    println!("Hashed PFN:   {}", pfn.to_lowercase());
+    println!("Tweak hash:   {}", hex::encode(&hash));
+    println!("Folded tweak: {}", hex::encode(crypto::fold_hash_xor(&hash)));
+    println!("Tweak:        {tweak:#018x}");
+    for sector in args.sector..args.sector.saturating_add(args.count) {
+        println!("Sector {sector:>5}: {} (offset {:#x})", hex::encode(crypto::sector_tweak(tweak, sector)), sector * eappx::utils::SECTOR_SIZE as u128);
+    }
+
+    Ok(())
+}
+
+fn write_extraction_report(path: &Path, report: &ExtractionReport) -> Result<()> {
+    let files = report.files.iter()
+        .map(|file| serde_json::json!({
+            "path": file.path,
+            "package_path": file.package_path,
+            "size": file.size,
+            "sha256": file.sha256.as_ref().map(hex::encode),
+            "encrypted": file.encrypted,
+            "compressed": file.compressed,
+            "renamed": file.is_renamed(),
+            "duplicate_of": file.duplicate_of,
+            "status": file.status.to_string(),
+        }))
+        .collect::<Vec<_>>();
+
+    let json = serde_json::json!({
+        "files": files,
+        "collisions": report.collisions.iter().map(|collision| &collision.package_paths).collect::<Vec<_>>(),
+        "failures": report.failures.iter().map(|failure| serde_json::json!({
+            "package_path": failure.package_path,
+            "error": failure.message,
+        })).collect::<Vec<_>>(),
+        "written_size": report.written_size(),
+        "valid": report.is_valid(),
+    });
+    std::fs::write(path, serde_json::to_string_pretty(&json)?)
+        .with_context(|| format!("Failed to write report {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Print the time per stage of every file, their total and the stage bounding the run
+fn print_timings<'a>(files: impl Iterator<Item = (&'a str, Timings)>, total: Option<Timings>, elapsed: Duration) {
+    for (name, timings) in files {
+        println!("{name}:");
+        print!("{timings}");
+    }
+    let total = total.unwrap_or_default();
+    println!("Total, {elapsed:.3?} wall clock:");
+    print!("{total}");
+    if let Some(stage) = total.slowest() {
+        let bound = match stage {
+            Stage::Read | Stage::Write => "disk",
+            Stage::Inflate | Stage::Decrypt | Stage::Hash => "CPU",
+        };
+        println!("Slowest stage: {stage} ({bound} bound)");
+    }
+}
+
+fn diff(args: &DiffOptions) -> Result<()> {
+    let old = EAppxFile::from_source(&args.input_file.resolve()?.open()?)?;
+    let new = EAppxFile::from_source(&std::fs::File::open(&args.new_package_file)?)?;
+    if old.header.key_ids != new.header.key_ids {
+        log::warn!("Packages use different keys, encrypted blocks are not comparable");
+    }
+
+    // Encrypted packages carry 0.0.0.0 in the header, their version is only known from the manifest
+    if let (Ok(old_name), Ok(new_name)) = (old.header.full_name(), new.header.full_name()) {
+        if old_name.version != PackageVersion::ZERO && new_name.version != PackageVersion::ZERO {
+            println!("Version: {} -> {}", old_name.version, new_name.version);
+            if new_name.version < old_name.version {
+                log::warn!("The new package has a lower version, installing it is a downgrade");
+            }
+        }
+    }
+
+    let report = eappx::diff::diff_packages(&old, &new);
+    if args.blocks {
+        for file in &report.files {
+            if let eappx::diff::FileChange::Modified { changed_blocks, .. } = &file.change {
+                println!("{}: {changed_blocks:?}", file.name);
+            }
+        }
+    }
+    print!("{report}");
+
+    Ok(())
+}
+
+/// Print modified, missing and extra files, true if the directory is intact
+fn compare(args: &CompareOptions) -> Result<bool> {
+    let file = args.input_file.resolve()?.open()?;
+    let mut eappx = EAppxFile::from_source(&file)?;
+    eappx.extract_options = ExtractOptions::default()
+        .flat(args.flat)
+        .preserve_case(args.preserve_case)
+        .names(args.names.into());
+
+    let comparison = eappx.compare_directory(&file, &args.directory)?;
+    print!("{comparison}");
+
+    Ok(comparison.is_intact())
+}
+
+fn delta_create(args: &DeltaCreateOptions) -> Result<()> {
+    let base = EAppxFile::from_source(&args.input_file.resolve()?.open()?)?;
+    let target_file = std::fs::File::open(&args.new_package_file)?;
+    let target = EAppxFile::from_source(&target_file)?;
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output_file)?);
+    let stats = eappx::delta::create_delta(&base, &target, &target_file, &mut out)?;
+    std::io::Write::flush(&mut out)?;
+
+    println!("Copied from base: {}, shipped in delta: {}",
+        eappx::utils::get_filesize_with_unit(stats.copied), eappx::utils::get_filesize_with_unit(stats.shipped));
+    Ok(())
+}
+
+fn delta_apply(args: &DeltaApplyOptions) -> Result<()> {
+    let base_file = args.input_file.resolve()?.open()?;
+    let base = EAppxFile::from_source(&base_file)?;
+    let mut delta_reader = BufReader::new(std::fs::File::open(&args.delta_file)?);
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output_file)?);
+    eappx::delta::apply_delta(&base, &base_file, &mut delta_reader, &mut out)?;
+    std::io::Write::flush(&mut out)?;
+    drop(out);
+
+    let file = std::fs::File::open(&args.output_file)?;
+    let eappx = EAppxFile::from_source(&file)?;
+    let report = eappx.verify(&file);
+    print!("{report}");
+    if !report.is_valid() {
+        anyhow::bail!("Reconstructed package failed block hash verification");
+    }
+
+    Ok(())
+}
+
+fn blocks_export(args: &BlocksExportOptions) -> Result<()> {
+    let file = args.input_file.resolve()?.open()?;
+    let eappx = EAppxFile::from_source(&file)?;
+    let mut store = eappx::block_store::DirectoryStore::new(&args.store);
+
+    let (manifest, stats) = eappx::block_store::export_blocks(&eappx, &file, &mut store)?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.manifest_file)?);
+    manifest.write(&mut out)?;
+    std::io::Write::flush(&mut out)?;
+
+    println!("New in store: {} ({} blobs), already stored: {}",
+        eappx::utils::get_filesize_with_unit(stats.new_bytes), stats.new_blobs, eappx::utils::get_filesize_with_unit(stats.reused_bytes));
+    Ok(())
+}
+
+fn blocks_reassemble(args: &BlocksReassembleOptions) -> Result<()> {
+    let manifest = eappx::block_store::BlockManifest::read(&mut BufReader::new(std::fs::File::open(&args.manifest_file)?))?;
+    let store = eappx::block_store::DirectoryStore::new(&args.store);
+
+    let mut out = std::io::BufWriter::new(std::fs::File::create(&args.output_file)?);
+    eappx::block_store::reassemble(&manifest, &store, &mut out)?;
+    std::io::Write::flush(&mut out)?;
+    Ok(())
+}
+
+fn main()
+{
+    let opts = match Opts::try_parse() {
+        Ok(opts) => opts,
+        // Help and version succeed, usage errors get their own code instead of clap's 2
+        Err(err) => {
+            let code = match err.use_stderr() {
+                true => ExitCode::Usage,
+                false => ExitCode::Success,
+            };
+            let _ = err.print();
+            code.exit();
+        },
+    };
+    if let Err(e) = logging::init(logging::level(opts.quiet, opts.verbose), opts.log_format) {
+        eprintln!("Error: {e:?}");
+        ExitCode::Error.exit();
+    }
+
+    let code = run(opts.cmd).unwrap_or_else(|e| {
+        match opts.log_format {
+            // Keep stderr parseable
+            LogFormat::Json => log::error!("{}", logging::error_message(&e)),
+            LogFormat::Text => eprintln!("Error: {e:?}"),
+        }
+        ExitCode::of_error(&e)
+    });
+    code.exit();
+}
+
+fn run(cmd: Commands) -> Result<ExitCode> {
+    match cmd {
+        Commands::Pack(args) => {
+            pack(&args)?;
+        },
+        Commands::Bundle(_args) => {
+            todo!("Bundling")
+        },
+        Commands::ManifestGen(args) => {
+            manifest_gen(&args)?;
+        },
+        Commands::Unpack(args)
+        | Commands::Unbundle(args) => {
+            let outdir = args.output_directory.output_directory;
+            if outdir.as_os_str() == STDIO_PATH && matches!(args.format, UnpackFormat::Dir | UnpackFormat::Install) {
+                anyhow::bail!("Only archives can be written to stdout, pass --format zip or --format tar");
+            }
+            // Stdout may carry the archive, everything else goes through the log
+            log::info!("Using file path: {:?}", args.input_file.package_file);
+
+            let key_collection = load_keys(&args.key_options)?;
+
+            let file = args.input_file.resolve()?.open()?;
+            let mut eappx = match args.recover {
+                true => {
+                    let (eappx, recovery) = EAppxFile::recover(&file, ResourceLimits::default())?;
+                    if !recovery.is_intact() {
+                        log::warn!("{recovery}");
+                    }
+                    eappx
+                },
+                false => EAppxFile::from_source(&file)?,
+            };
+        
+            log::info!("{eappx}");
+            eappx.load_keys(&key_collection)?;
+            log_missing_keys(&eappx);
+            eappx.integrity = args.integrity.policy();
+            eappx.collect_timings = args.timings;
+            eappx.extract_options = ExtractOptions::default()
+                .flat(args.flat)
+                .preserve_case(args.preserve_case)
+                .overwrite(match args.resume {
+                    true => OverwritePolicy::SkipIfHashMatches,
+                    false => args.overwrite.into(),
+                })
+                .on_collision(args.on_collision.into())
+                .on_missing_key(args.on_missing_key.into())
+                .names(args.names.into())
+                .on_low_disk_space(match args.force {
+                    true => DiskSpacePolicy::Warn,
+                    false => DiskSpacePolicy::Fail,
+                })
+                .duplicates(args.duplicates.into())
+                .continue_on_error(args.continue_on_error);
+            eappx.bundle_filter = BundleFilter::default()
+                .architectures(args.architectures)
+                .languages(args.languages)
+                .scales(args.scales);
+        
+            match args.format {
+                UnpackFormat::Dir | UnpackFormat::Install => {},
+                // Zip needs a seekable output, stdout gets a spooled copy
+                UnpackFormat::Zip if outdir.as_os_str() == STDIO_PATH => {
+                    let mut spool = eappx.extract_to_zip(&file, tempfile::tempfile()?)?;
+                    spool.rewind()?;
+                    std::io::copy(&mut spool, &mut std::io::stdout().lock())?;
+                    return Ok(ExitCode::Success);
+                },
+                UnpackFormat::Zip => {
+                    let out = std::io::BufWriter::new(std::fs::File::create(&outdir)?);
+                    eappx.extract_to_zip(&file, out)?.flush()?;
+                    return Ok(ExitCode::Success);
+                },
+                UnpackFormat::Tar => {
+                    eappx.extract_to_tar(&file, create_output(&outdir)?)?.flush()?;
+                    return Ok(ExitCode::Success);
+                },
+            }
+
+            if !outdir.exists() {
+                log::info!("Create directory: {:?}", &outdir);
+                std::fs::create_dir_all(&outdir)?;
+            }
+            
+            let started = Instant::now();
+            let report = match (args.footprint_only, args.format) {
+                (true, _) => eappx.extract_footprint_files_with(&file, &outdir, |_| true)?,
+                (false, UnpackFormat::Install) => eappx.extract_install_layout(&file, &outdir)?,
+                (false, _) => eappx.extract_with_report(&file, &outdir)?,
+            };
+
+            if let Some(report_file) = &args.report {
+                write_extraction_report(report_file, &report)?;
+            }
+            if args.timings {
+                let files = report.files.iter().filter_map(|f| Some((f.package_path.as_str(), f.timings?)));
+                print_timings(files, report.timings(), started.elapsed());
+            }
+            if args.ci {
+                match eappx.verify_extracted_code_integrity(&file, &outdir, &report)? {
+                    Some(coverage) if !coverage.is_valid() => {
+                        print!("{coverage}");
+                        let names = coverage.failed().map(|binary| format!("{} ({})", binary.name, binary.coverage)).collect::<Vec<_>>();
+                        return Err(Failure::new(ExitCode::VerificationFailed,
+                            format!("Extracted binaries not covered by CodeIntegrity.cat: {}", names.join(", "))).into());
+                    },
+                    Some(coverage) => print!("{coverage}"),
+                    None => log::warn!("No CodeIntegrity.cat to check the extracted binaries against"),
+                }
+            }
+            let duplicates = report.duplicates().map(|f| f.size).collect::<Vec<_>>();
+            if !duplicates.is_empty() {
+                log::info!("{} duplicate file(s) not decoded again, {} shared", duplicates.len(),
+                    eappx::utils::get_filesize_with_unit(duplicates.iter().sum()));
+            }
+            let missing_key = report.missing_key().map(|f| format!("{} ({})", f.package_path, f.status)).collect::<Vec<_>>();
+            if !missing_key.is_empty() {
+                log::warn!("{} encrypted file(s) not decrypted for lack of a key: {}", missing_key.len(), missing_key.join(", "));
+            }
+            for failure in &report.failures {
+                log::error!("Failed to extract {failure}");
+            }
+            if report.mismatched().next().is_some() {
+                let names = report.mismatched().map(|f| f.package_path.as_str()).collect::<Vec<_>>();
+                return Err(Failure::new(ExitCode::VerificationFailed,
+                    format!("Extracted files do not match their hashes: {}", names.join(", "))).into());
+            }
+            if !report.failures.is_empty() {
+                anyhow::bail!("{} of {} file(s) failed to extract", report.failures.len(), report.failures.len() + report.files.len());
+            }
+            if !missing_key.is_empty() {
+                return Ok(ExitCode::MissingKeys);
+            }
+        },
+        Commands::ExtractFile(args) => {
+            extract_file(&args)?;
+        },
+        Commands::Encrypt(_args) => {
+            todo!("Converting zip-style msix/appx to emsix/eappx")
+        },
+        Commands::Decrypt(args) => {
+            decrypt(&args)?;
+        },
+        Commands::Rekey(args) => {
+            rekey(&args)?;
+        },
+        Commands::Rename(args) => {
+            rename(&args)?;
+        },
+        Commands::Replace(args) => {
+            replace(&args)?;
+        },
+        Commands::Info(args) => {
+            let package = args.input_file.resolve()?;
+            let file = package.open()?;
+            let lazy = LazyPackage::open(&file)?;
+            if args.header_only {
+                print!("{}", lazy.header());
+                return Ok(ExitCode::Success);
+            }
+            let mut eappx = lazy.into_package()?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&package_info_json(&eappx.package_info(&file)?))?);
+                return Ok(ExitCode::Success);
+            }
+            if args.summary {
+                print!("{}", eappx.package_info(&file)?);
+                return Ok(ExitCode::Success);
+            }
+            eappx.load_keys(&load_keys(&args.key_options)?)?;
+            log_missing_keys(&eappx);
+            eappx.collect_timings = args.timings;
+            match args.detailed {
+                true => println!("{}", eappx.detailed()),
+                false => println!("{eappx}"),
+            }
+            if let Manifest::Manifest(manifest) = eappx.manifest(&file)? {
+                for application in manifest.applications() {
+                    println!("Application: {application}");
+                }
+            }
+            if let Some(bundle_manifest) = eappx.bundle_manifest(&file)? {
+                println!("Bundle packages: {}", bundle_manifest.packages.package.len());
+                for package in &bundle_manifest.packages.package {
+                    println!("* {package}");
+                }
+            }
+            let signature = eappx.signature_info(&file).unwrap_or_else(|e| {
+                log::warn!("Failed to parse AppxSignature.p7x: {e}");
+                None
+            });
+            if let Some(signature) = &signature {
+                print!("{signature}");
+            }
+            println!("Signing status: {}", SigningStatus::new(eappx.header.is_signed(), signature.as_ref(), &eappx.header.key_ids));
+            log::info!("Verifying");
+            let started = Instant::now();
+            let report = eappx.verify_parallel(&file);
+            print!("{report}");
+            if args.timings {
+                let files = report.files.iter().filter_map(|f| Some((f.name.as_str(), f.timings?)));
+                print_timings(files, report.timings(), started.elapsed());
+            }
+            let bundle_report = eappx.verify_bundle_packages(&file)?;
+            if !bundle_report.packages.is_empty() {
+                print!("{bundle_report}");
+            }
+            if args.ci {
+                match eappx.verify_code_integrity(&file)? {
+                    Some(coverage) => print!("{coverage}"),
+                    None => println!("No CodeIntegrity.cat to check binaries against"),
+                }
+            }
+        },
+        Commands::Validate(args) => {
+            if !validate(&args)? {
+                return Ok(ExitCode::VerificationFailed);
+            }
+        },
+        Commands::Audit(args) => {
+            if !audit(&args)? {
+                return Ok(ExitCode::VerificationFailed);
+            }
+        },
+        Commands::Verify(args) => {
+            return verify(&args);
+        },
+        Commands::List(args) => {
+            list(&args)?;
+        },
+        Commands::Stats(args) => {
+            let eappx = EAppxFile::from_source(&args.input_file.resolve()?.open()?)?;
+            print!("{}", eappx.stats());
+        },
+        Commands::Layout(args) => {
+            layout(&args)?;
+        },
+        Commands::Batch(args) => {
+            if !batch(&args)? {
+                return Ok(ExitCode::Error);
+            }
+        },
+        Commands::Ingest(args) => {
+            ingest(&args)?;
+        },
+        Commands::Diff(args) => {
+            diff(&args)?;
+        },
+        Commands::Compare(args) => {
+            if !compare(&args)? {
+                return Ok(ExitCode::VerificationFailed);
+            }
+        },
+        #[cfg(all(unix, feature = "fuse"))]
+        Commands::Mount(args) => {
+            let package = args.input_file.resolve()?;
+            let file = package.open()?;
+            let mut eappx = EAppxFile::from_source(&file)?;
+            eappx.load_keys(&load_keys(&args.key_options)?)?;
+            eappx.integrity = args.integrity.policy();
+            eappx.block_cache = (args.cache_mib > 0).then(|| std::sync::Arc::new(eappx::block_cache::BlockCache::new(args.cache_mib << 20)));
+            mount::mount(eappx, file, &args.input_file.package_file, &args.mountpoint)?;
+        },
+        Commands::Delta { cmd: DeltaCommands::Create(args) } => {
+            delta_create(&args)?;
+        },
+        Commands::Delta { cmd: DeltaCommands::Apply(args) } => {
+            delta_apply(&args)?;
+        },
+        Commands::Blocks { cmd: BlocksCommands::Export(args) } => {
+            blocks_export(&args)?;
+        },
+        Commands::Blocks { cmd: BlocksCommands::Reassemble(args) } => {
+            blocks_reassemble(&args)?;
+        },
+        Commands::Keys { cmd: KeysCommands::New(args) } => {
+            let keys = KeyCollection::generate()?;
+            match args.output_file {
+                Some(path) => {
+                    // Never clobber an existing keyfile, its keys might still be needed
+                    let mut file = std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                        .with_context(|| format!("Failed to create keyfile {}", path.display()))?;
+                    keys.to_writer(&mut file)?;
+                    log::info!("Wrote new key to {}", path.display());
+                },
+                None => keys.to_writer(&mut std::io::stdout())?,
+            }
+        },
+        Commands::Keys { cmd: KeysCommands::Info(args) } => {
+            if !keys_info(&args)? {
+                return Ok(ExitCode::MissingKeys);
+            }
+        },
+        Commands::CryptoDebug(args) => {
+            crypto_debug(&args)?;
+        },
+        Commands::SelfTest => {
+            if !self_test::run() {
+                return Ok(ExitCode::Error);
+            }
+        },
+        Commands::FormatSpec(args) => {
+            match args.format {
+                SpecFormat::Markdown => {
+                    for spec in format_spec::ALL {
+                        println!("{}", spec.to_markdown());
+                    }
+                },
+                SpecFormat::Json => {
+                    let specs = format_spec::ALL.iter()
+                        .map(|spec| serde_json::json!({
+                            "name": spec.name,
+                            "description": spec.description,
+                            "fields": spec.fields.iter().map(|field| serde_json::json!({
+                                "name": field.name,
+                                "offset": field.offset,
+                                "size": field.size.fixed(),
+                                "size_expr": field.size.to_string(),
+                                "endianness": format!("{:?}", field.endianness),
+                                "description": field.description,
+                            })).collect::<Vec<_>>(),
+                        }))
+                        .collect::<Vec<_>>();
+                    println!("{}", serde_json::to_string_pretty(&specs)?);
+                },
+            }
+        },
+        Commands::Completions(args) => {
+            clap_complete::generate(args.shell, &mut Opts::command(), "makeappx", &mut std::io::stdout());
+        },
+        Commands::Manpage(args) => {
+            let cmd = Opts::command();
+            match args.output_directory {
+                Some(directory) => {
+                    std::fs::create_dir_all(&directory)?;
+                    clap_mangen::generate_to(cmd, &directory)
+                        .with_context(|| format!("Failed to write man pages to {}", directory.display()))?;
+                },
+                None => clap_mangen::Man::new(cmd).render(&mut std::io::stdout())?,
+            }
+        },
+    }
+
+    Ok(ExitCode::Success)
 }
\ No newline at end of file
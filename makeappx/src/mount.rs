@@ -0,0 +1,242 @@
+//! Read-only FUSE view of the blockmap files of a package
+//!
+//! Reads are served by [`EAppxFile::entry_reader`], which only decrypts and
+//! inflates the blocks a read touches, so large packages can be browsed
+//! without extracting them first.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use eappx::EAppxFile;
+use fuser::{FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, Request};
+
+const ROOT_INODE: u64 = 1;
+/// The package never changes while mounted
+const TTL: Duration = Duration::from_secs(60);
+
+enum NodeKind {
+    /// Children by lowercased name, lookups are case-insensitive like on Windows
+    Directory(BTreeMap<String, u64>),
+    /// Blockmap name and plaintext size
+    File(String, u64),
+}
+
+struct Node {
+    name: OsString,
+    parent: u64,
+    kind: NodeKind,
+}
+
+pub struct PackageFs {
+    eappx: EAppxFile,
+    file: std::fs::File,
+    /// Indexed by inode - 1
+    nodes: Vec<Node>,
+    mtime: SystemTime,
+    uid: u32,
+    gid: u32,
+}
+
+impl PackageFs {
+    pub fn new(eappx: EAppxFile, file: std::fs::File) -> Result<Self> {
+        let mtime = file.metadata()?.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut fs = Self {
+            eappx,
+            file,
+            nodes: vec![Node {
+                name: OsString::from("/"),
+                parent: ROOT_INODE,
+                kind: NodeKind::Directory(BTreeMap::new()),
+            }],
+            mtime,
+            // SAFETY: getuid and getgid cannot fail
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        };
+
+        let files = fs.eappx.blockmap.files.iter()
+            .map(|file| (file.name.clone(), file.size))
+            .collect::<Vec<_>>();
+        for (name, size) in files {
+            fs.insert(&name, size);
+        }
+        Ok(fs)
+    }
+
+    fn insert(&mut self, package_path: &str, size: u64) {
+        let components = package_path.split('\\').filter(|c| !c.is_empty()).collect::<Vec<_>>();
+        let Some((filename, directories)) = components.split_last() else {
+            return;
+        };
+
+        let mut parent = ROOT_INODE;
+        for directory in directories {
+            parent = match self.child(parent, directory) {
+                Some(ino) if matches!(self.node(ino).kind, NodeKind::Directory(_)) => ino,
+                Some(_) => {
+                    log::warn!("Skipping {package_path}, {directory} is a file");
+                    return;
+                },
+                None => self.add_child(parent, directory, NodeKind::Directory(BTreeMap::new())),
+            };
+        }
+
+        if self.child(parent, filename).is_some() {
+            log::warn!("Skipping {package_path}, it collides with an earlier entry");
+            return;
+        }
+        self.add_child(parent, filename, NodeKind::File(package_path.to_owned(), size));
+    }
+
+    fn add_child(&mut self, parent: u64, name: &str, kind: NodeKind) -> u64 {
+        self.nodes.push(Node {
+            name: OsString::from(name),
+            parent,
+            kind,
+        });
+        let ino = self.nodes.len() as u64;
+        if let NodeKind::Directory(children) = &mut self.nodes[parent as usize - 1].kind {
+            children.insert(name.to_lowercase(), ino);
+        }
+        ino
+    }
+
+    fn node(&self, ino: u64) -> &Node {
+        &self.nodes[ino as usize - 1]
+    }
+
+    fn get(&self, ino: u64) -> Option<&Node> {
+        self.nodes.get((ino as usize).checked_sub(1)?)
+    }
+
+    fn child(&self, parent: u64, name: &str) -> Option<u64> {
+        match &self.get(parent)?.kind {
+            NodeKind::Directory(children) => children.get(&name.to_lowercase()).copied(),
+            NodeKind::File(..) => None,
+        }
+    }
+
+    fn attr(&self, ino: u64) -> FileAttr {
+        let (kind, size, perm, nlink) = match &self.node(ino).kind {
+            NodeKind::Directory(_) => (FileType::Directory, 0, 0o555, 2),
+            NodeKind::File(_, size) => (FileType::RegularFile, *size, 0o444, 1),
+        };
+        FileAttr {
+            ino,
+            size,
+            blocks: size.div_ceil(512),
+            atime: self.mtime,
+            mtime: self.mtime,
+            ctime: self.mtime,
+            crtime: self.mtime,
+            kind,
+            perm,
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            blksize: 0x10000,
+            flags: 0,
+        }
+    }
+
+    fn read_file(&self, name: &str, offset: u64, size: usize) -> Result<Vec<u8>> {
+        let mut reader = self.eappx.entry_reader(&self.file, name)?;
+        reader.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::with_capacity(size);
+        reader.take(size as u64).read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+impl Filesystem for PackageFs {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match name.to_str().and_then(|name| self.child(parent, name)) {
+            Some(ino) => reply.entry(&TTL, &self.attr(ino), 0),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.get(ino) {
+            Some(_) => reply.attr(&TTL, &self.attr(ino)),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn open(&mut self, _req: &Request<'_>, ino: u64, flags: i32, reply: ReplyOpen) {
+        match self.get(ino).map(|node| &node.kind) {
+            Some(NodeKind::File(..)) if flags & libc::O_ACCMODE == libc::O_RDONLY => reply.opened(0, 0),
+            Some(NodeKind::File(..)) => reply.error(libc::EROFS),
+            Some(NodeKind::Directory(_)) => reply.error(libc::EISDIR),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Some(NodeKind::File(name, _)) = self.get(ino).map(|node| &node.kind) else {
+            reply.error(libc::ENOENT);
+            return;
+        };
+        match self.read_file(name, offset.max(0) as u64, size as usize) {
+            Ok(data) => reply.data(&data),
+            Err(e) => {
+                log::error!("Failed to read {name} at {offset:#x}: {e:#}");
+                reply.error(libc::EIO);
+            },
+        }
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let Some(NodeKind::Directory(children)) = self.get(ino).map(|node| &node.kind) else {
+            reply.error(libc::ENOTDIR);
+            return;
+        };
+
+        let entries = [(ino, FileType::Directory, OsStr::new(".")), (self.node(ino).parent, FileType::Directory, OsStr::new(".."))]
+            .into_iter()
+            .chain(children.values().map(|child| {
+                let node = self.node(*child);
+                let kind = match node.kind {
+                    NodeKind::Directory(_) => FileType::Directory,
+                    NodeKind::File(..) => FileType::RegularFile,
+                };
+                (*child, kind, node.name.as_os_str())
+            }));
+        for (index, (ino, kind, name)) in entries.enumerate().skip(offset.max(0) as usize) {
+            if reply.add(ino, index as i64 + 1, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mount the package at `mountpoint` until it is unmounted again
+pub fn mount(eappx: EAppxFile, file: std::fs::File, source: &Path, mountpoint: &Path) -> Result<()> {
+    let fs = PackageFs::new(eappx, file)?;
+    let options = [
+        MountOption::RO,
+        MountOption::FSName(source.display().to_string()),
+        MountOption::Subtype("eappx".to_owned()),
+    ];
+    log::info!("Mounted {} at {}, unmount with `fusermount -u {}`", source.display(), mountpoint.display(), mountpoint.display());
+    fuser::mount2(fs, mountpoint, &options)?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! Project file for `pack`, TOML or JSON (by extension)
+//!
+//! ```toml
+//! directory = "TestApp"              # relative to the project file
+//! exclude = ["*.pdb", "obj/**"]
+//! default_key = "Z8+v3Sx7bQgwK4rawb3Tp7iuU30iWWLwsdd+CfWhh6A="
+//!
+//! [identity]
+//! version = "1.0.4.0"
+//!
+//! [footprint]
+//! code_integrity = true
+//!
+//! [[files]]
+//! pattern = "Assets/**"
+//! encrypt = false
+//!
+//! [[files]]
+//! pattern = "*.txt"
+//! compress = true
+//! ```
+//!
+//! Patterns without `/` match file names anywhere, others the whole path.
+//! Matching is case-insensitive, later rules override earlier ones.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use eappx::{identity::PackageVersion, manifest::Identity};
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PackConfig {
+    /// Input directory, relative to the project file
+    pub directory: Option<PathBuf>,
+    #[serde(default)]
+    pub identity: IdentityOverrides,
+    /// Files left out of the package
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Key-id (as in keyfiles) files are encrypted with, the first loaded key if unset
+    pub default_key: Option<String>,
+    #[serde(default)]
+    pub footprint: FootprintOptions,
+    #[serde(default)]
+    pub files: Vec<FileRule>,
+}
+
+/// Replacements for the manifest identity, the packed manifest is updated to match
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityOverrides {
+    pub name: Option<String>,
+    pub publisher: Option<String>,
+    pub version: Option<PackageVersion>,
+    pub architecture: Option<String>,
+}
+
+impl IdentityOverrides {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.publisher.is_none() && self.version.is_none() && self.architecture.is_none()
+    }
+
+    pub fn apply(&self, identity: &mut Identity) {
+        let fields = [
+            (&self.name, &mut identity.name),
+            (&self.publisher, &mut identity.publisher),
+        ];
+        for (value, field) in fields {
+            if let Some(value) = value {
+                field.clone_from(value);
+            }
+        }
+        if let Some(version) = self.version {
+            identity.version = version;
+        }
+        if let Some(architecture) = &self.architecture {
+            identity.arch = Some(architecture.clone());
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FootprintOptions {
+    /// Generate AppxMetadata/CodeIntegrity.cat, same as `--code-integrity`
+    #[serde(default)]
+    pub code_integrity: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FileRule {
+    pub pattern: String,
+    pub compress: Option<bool>,
+    pub encrypt: Option<bool>,
+    /// Key-id (as in keyfiles) to encrypt with, implies `encrypt`
+    pub key: Option<String>,
+}
+
+/// What the rules decided for a file, `None` keeps the default
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FileSettings<'a> {
+    pub compress: Option<bool>,
+    pub encrypt: Option<bool>,
+    pub key: Option<&'a str>,
+}
+
+/// Project file with its patterns compiled
+#[derive(Debug)]
+pub struct PackProject {
+    pub config: PackConfig,
+    /// Input directory resolved against the project file
+    pub directory: Option<PathBuf>,
+    exclude: Vec<Pattern>,
+    rules: Vec<Pattern>,
+}
+
+impl PackProject {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read project file {}", path.display()))?;
+        let is_json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        let config = match is_json {
+            true => serde_json::from_str(&text).map_err(anyhow::Error::from),
+            false => toml::from_str(&text).map_err(anyhow::Error::from),
+        }.with_context(|| format!("Invalid project file {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        Self::new(config, base)
+    }
+
+    fn new(config: PackConfig, base: &Path) -> Result<Self> {
+        let compile = |pattern: &String| Pattern::new(pattern)
+            .with_context(|| format!("Invalid pattern {pattern:?}"));
+        Ok(Self {
+            directory: config.directory.as_ref().map(|directory| base.join(directory)),
+            exclude: config.exclude.iter().map(compile).collect::<Result<_>>()?,
+            rules: config.files.iter().map(|rule| compile(&rule.pattern)).collect::<Result<_>>()?,
+            config,
+        })
+    }
+
+    /// Whether the package path `name` (`\` separated) is left out
+    pub fn is_excluded(&self, name: &str) -> bool {
+        self.exclude.iter().any(|pattern| matches(pattern, name))
+    }
+
+    pub fn settings(&self, name: &str) -> FileSettings<'_> {
+        let mut settings = FileSettings::default();
+        for (rule, pattern) in self.config.files.iter().zip(&self.rules) {
+            if !matches(pattern, name) {
+                continue;
+            }
+            settings.compress = rule.compress.or(settings.compress);
+            settings.encrypt = rule.encrypt.or(rule.key.as_ref().map(|_| true)).or(settings.encrypt);
+            settings.key = rule.key.as_deref().or(settings.key);
+        }
+        settings
+    }
+}
+
+fn matches(pattern: &Pattern, name: &str) -> bool {
+    let path = name.replace('\\', "/");
+    match pattern.as_str().contains('/') {
+        true => pattern.matches_with(&path, MATCH_OPTIONS),
+        false => pattern.matches_with(path.rsplit('/').next().unwrap_or_default(), MATCH_OPTIONS),
+    }
+}
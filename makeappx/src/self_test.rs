@@ -0,0 +1,176 @@
+//! `self-test`, checks the build against known-good format vectors
+//!
+//! Tweak derivation, publisher ids, hash folding, sector tweaks, XTS, header
+//! parsing and key-id encodings are compared with `eappx::testvectors`, then
+//! a package is packed and unpacked in memory. Meant to be run once on a new
+//! platform or architecture before trusting it with real packages.
+
+use std::io::{Cursor, Read};
+
+use anyhow::{ensure, Context, Result};
+use eappx::{
+    container::ContainerParams,
+    crypto::{self, CryptoFileContext},
+    keys::{KeyId, TEST_KEY, TEST_KEY_ID},
+    testvectors,
+    utils,
+    writer::{EntryOptions, PackageWriter, WriterOptions},
+    EAppxFile,
+    EAppxMagic,
+};
+
+/// Name of a check and what it does, returning the number of vectors it covered
+type Check = (&'static str, fn() -> Result<usize>);
+
+const CHECKS: &[Check] = &[
+    ("publisher ids", publisher_ids),
+    ("file tweaks", tweaks),
+    ("hash folding", folds),
+    ("sector tweaks", sector_tweaks),
+    ("XTS-AES-128", xts),
+    ("headers", headers),
+    ("key ids", key_ids),
+    ("pack/unpack round trip", round_trip),
+];
+
+/// Run every check and print its outcome, true if all of them passed
+pub fn run() -> bool {
+    let mut failed = 0;
+    for (name, check) in CHECKS {
+        match check() {
+            Ok(count) => println!("ok      {name} ({count})"),
+            Err(err) => {
+                println!("FAILED  {name}: {err:#}");
+                failed += 1;
+            },
+        }
+    }
+
+    match failed {
+        0 => println!("All {} checks passed", CHECKS.len()),
+        _ => println!("{failed} of {} checks failed, this build does not process packages correctly", CHECKS.len()),
+    }
+    failed == 0
+}
+
+fn publisher_ids() -> Result<usize> {
+    for v in testvectors::PUBLISHER_IDS {
+        let publisher_id = utils::generate_publisher_id(v.publisher);
+        ensure!(publisher_id == v.publisher_id, "{:?} gives {publisher_id}, expected {}", v.publisher, v.publisher_id);
+    }
+    Ok(testvectors::PUBLISHER_IDS.len())
+}
+
+fn tweaks() -> Result<usize> {
+    for v in testvectors::TWEAKS {
+        let hash = crypto::hash_for_file_tweak(v.filepath, v.pfn);
+        ensure!(hex::encode(&hash) == v.tweak_hash, "Tweak hash of {} is {}, expected {}", v.filepath, hex::encode(&hash), v.tweak_hash);
+        let folded = hex::encode(crypto::fold_hash_xor(&hash));
+        ensure!(folded == v.folded_hash, "Folded hash of {} is {folded}, expected {}", v.filepath, v.folded_hash);
+        let tweak = crypto::get_tweak_value(v.filepath, v.pfn);
+        ensure!(tweak == v.tweak, "Tweak of {} is {tweak:#x}, expected {:#x}", v.filepath, v.tweak);
+    }
+    Ok(testvectors::TWEAKS.len())
+}
+
+fn folds() -> Result<usize> {
+    for v in testvectors::FOLDS {
+        let folded = hex::encode(crypto::fold_hash_xor(&hex::decode(v.hash)?));
+        ensure!(folded == v.folded, "{} folds to {folded}, expected {}", v.hash, v.folded);
+    }
+    Ok(testvectors::FOLDS.len())
+}
+
+fn sector_tweaks() -> Result<usize> {
+    for v in testvectors::SECTOR_TWEAKS {
+        let ctx = CryptoFileContext {
+            cipher: crypto::create_cipher(&[0u8; 32]),
+            tweak: v.tweak,
+            params: ContainerParams::default(),
+        };
+        let sector_tweak = hex::encode(ctx.for_sector(v.sector));
+        ensure!(sector_tweak == v.sector_tweak, "Sector {} of tweak {:#x} is {sector_tweak}, expected {}", v.sector, v.tweak, v.sector_tweak);
+    }
+    Ok(testvectors::SECTOR_TWEAKS.len())
+}
+
+fn xts() -> Result<usize> {
+    for v in testvectors::XTS {
+        let key: [u8; 32] = hex::decode(v.key)?.try_into().ok().context("Key is not 32 bytes")?;
+        let tweak: [u8; 16] = hex::decode(v.tweak)?.try_into().ok().context("Tweak is not 16 bytes")?;
+        let cipher = crypto::create_cipher(&key);
+        let mut data = hex::decode(v.plaintext)?;
+        cipher.0.encrypt_sector(&mut data, tweak);
+        ensure!(hex::encode(&data) == v.ciphertext, "Encrypting with tweak {} gives {}, expected {}", v.tweak, hex::encode(&data), v.ciphertext);
+        cipher.0.decrypt_sector(&mut data, tweak);
+        ensure!(hex::encode(&data) == v.plaintext, "Decrypting with tweak {} does not give the plaintext back", v.tweak);
+    }
+    Ok(testvectors::XTS.len())
+}
+
+fn headers() -> Result<usize> {
+    for v in testvectors::HEADERS {
+        let header = EAppxFile::peek_header(&v.to_bytes())?;
+        let name = header.package_full_name();
+        ensure!(name == v.package_full_name, "Package full name is {name}, expected {}", v.package_full_name);
+        ensure!((header.footer_offset, header.footer_length, header.file_count) == (v.footer_offset, v.footer_length, v.file_count),
+            "Footer table of {name} is at {:#x}+{:#x} with {} files, expected {:#x}+{:#x} with {}",
+            header.footer_offset, header.footer_length, header.file_count, v.footer_offset, v.footer_length, v.file_count);
+        ensure!(header.block_map_file_id == v.block_map_file_id, "Blockmap id of {name} is {:#x}", header.block_map_file_id);
+        ensure!(hex::encode(&header.block_map_hash) == v.block_map_hash, "Blockmap hash of {name} differs");
+
+        let key_ids = header.key_ids.iter()
+            .map(|key_id| {
+                let (first, second) = key_id.to_guid();
+                (first.to_string(), second.to_string())
+            })
+            .collect::<Vec<_>>();
+        let expected = v.key_ids.iter()
+            .map(|(first, second)| (first.to_string(), second.to_string()))
+            .collect::<Vec<_>>();
+        ensure!(key_ids == expected, "Key ids of {name} are {key_ids:?}, expected {expected:?}");
+    }
+    Ok(testvectors::HEADERS.len())
+}
+
+fn key_ids() -> Result<usize> {
+    for v in testvectors::KEY_IDS {
+        let bytes: [u8; 32] = hex::decode(v.header_bytes)?.try_into().ok().context("Key id is not 32 bytes")?;
+        let key_id = KeyId::from_bytes(bytes);
+        ensure!(key_id.to_keyfile_string() == v.long, "{} encodes as {}, expected {}", v.header_bytes, key_id.to_keyfile_string(), v.long);
+        let short = key_id.to_short_keyfile_string().unwrap_or_default();
+        ensure!(short == v.short, "{} has short form {short:?}, expected {:?}", v.header_bytes, v.short);
+    }
+    Ok(testvectors::KEY_IDS.len())
+}
+
+/// Pack compressed, encrypted and both, read everything back with verification
+fn round_trip() -> Result<usize> {
+    let data = |len: usize| (0..len).map(|i| (i % 251) as u8 ^ (i / 4096) as u8).collect::<Vec<_>>();
+    let files = [
+        ("AppxManifest.xml", b"<Package/>".to_vec(), EntryOptions::default().compress(true)),
+        ("Assets\\big.bin", data(5 * utils::BLOCK_SIZE + 0x596), EntryOptions::default().key_index(Some(0))),
+        ("small.bin", data(0x596), EntryOptions::default().compress(true).key_index(Some(0))),
+        ("odd.bin", data(0x211), EntryOptions::default().key_index(Some(0))),
+    ];
+
+    let keys = vec![(TEST_KEY_ID, TEST_KEY.to_vec())];
+    let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, "TestApp_1.0.0.0_x64__nh20k94c8ngfj", keys, WriterOptions::default())?;
+    for (name, content, options) in &files {
+        writer.add_entry(name, &mut content.as_slice(), *options)?;
+    }
+    let package = writer.finish()?.into_inner();
+
+    let mut eappx = EAppxFile::from_source(&package)?;
+    eappx.keys.insert(TEST_KEY_ID, TEST_KEY.to_vec());
+    let report = eappx.verify(&package);
+    ensure!(report.is_valid(), "Packed package does not verify: {:?}", report.failed_files().map(|file| &file.name).collect::<Vec<_>>());
+
+    for (name, content, _) in &files {
+        let file = eappx.blockmap.find_file(name).with_context(|| format!("{name} is missing from the blockmap"))?;
+        let mut unpacked = vec![];
+        eappx.file_reader(&package, file)?.read_to_end(&mut unpacked)?;
+        ensure!(unpacked == *content, "{name} does not unpack to what was packed");
+    }
+    Ok(files.len())
+}
@@ -0,0 +1,41 @@
+//! Exit codes scripts rely on, see `exit_code.rs`
+
+use std::{path::PathBuf, process::Command};
+
+use eappx::EAppxFile;
+
+fn testdata(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../testdata").join(name)
+}
+
+fn makeappx(args: &[&str]) -> i32 {
+    Command::new(env!("CARGO_BIN_EXE_makeappx"))
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+        .unwrap()
+}
+
+#[test]
+fn test_truncated_package_is_parse_error() {
+    let package = std::fs::read(testdata("TestApp_1.0.3.0_x64.emsix")).unwrap();
+    let header = EAppxFile::peek_header(&package).unwrap();
+    let path = std::env::temp_dir().join(format!("makeappx-truncated-{}.emsix", std::process::id()));
+    // Footer table cut off
+    std::fs::write(&path, &package[..header.footer_offset as usize + 0x10]).unwrap();
+    let code = makeappx(&["verify", "-p", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(code, 4);
+}
+
+#[test]
+fn test_exit_codes() {
+    let package = testdata("TestApp_1.0.3.0_x64.emsix");
+    let package = package.to_str().unwrap();
+    assert_eq!(makeappx(&["verify", "-p", package]), 3);
+    assert_eq!(makeappx(&["verify", "-p", package, "--require-signing", "store-signed"]), 5);
+    assert_eq!(makeappx(&["verify", "--no-such-option"]), 64);
+    assert_eq!(makeappx(&["--help"]), 0);
+}
@@ -0,0 +1,156 @@
+//! Extraction straight into zip or tar archives
+//!
+//! Payloads are decrypted block-wise and streamed into the archive, nothing
+//! touches the disk. Entry paths follow the same [`OutputLayout`] as
+//! [`EAppxFile::extract`], always with `/` separators.
+
+use std::io::{Read, Seek, Write};
+
+use crate::{error::Error, layout::OutputLayout, source::PackageSource, EAppxFile};
+
+/// Archive format entries get written to
+trait ArchiveSink {
+    fn add(&mut self, path: &str, size: u64, reader: &mut dyn Read) -> Result<(), Error>;
+}
+
+#[cfg(feature = "zip")]
+impl<W: Write + Seek> ArchiveSink for zip::ZipWriter<W> {
+    fn add(&mut self, path: &str, size: u64, reader: &mut dyn Read) -> Result<(), Error> {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(size >= u32::MAX as u64);
+
+        self.start_file(path, options)
+            .map_err(|e| Error::data(format!("Failed to add {path} to zip"), e))?;
+        std::io::copy(reader, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tar")]
+impl<W: Write> ArchiveSink for tar::Builder<W> {
+    fn add(&mut self, path: &str, size: u64, reader: &mut dyn Read) -> Result<(), Error> {
+        // Fixed mode and mtime keep the archive reproducible
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+
+        self.append_data(&mut header, path, reader)?;
+        Ok(())
+    }
+}
+
+impl EAppxFile {
+    fn extract_to_archive<P: PackageSource + ?Sized, S: ArchiveSink>(&self, source: &P, sink: &mut S) -> Result<(), Error> {
+        let total_size = self.footers.iter().fold(0u64, |total, footer| total.saturating_add(footer.uncompressed_length));
+        self.limits.check_extraction_size(total_size)?;
+
+        for entry in self.footprint_entries() {
+            if let Some(data) = self.read_footprint_file(source, entry.file)? {
+                sink.add(entry.file.file_name(), data.len() as u64, &mut data.as_slice())?;
+            }
+        }
+
+        let mut payloads = vec![];
+        for file in &self.blockmap.files {
+            let footer = self.find_footer_for_file(file.id())
+                .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+            payloads.push((file.name.clone(), footer));
+        }
+        payloads.extend(self.applicable_bundle_payloads(source)?);
+
+        let mut layout = OutputLayout::new(self.extract_options);
+        for (name, footer) in payloads {
+            let path = layout.map(&name)?
+                .iter()
+                .map(|component| component.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            log::info!("* Archiving {name} as {path}");
+            let mut reader = self.payload_reader(source, footer, &name)?;
+            sink.add(&path, footer.uncompressed_length, &mut reader)?;
+        }
+
+        Ok(())
+    }
+
+    /// Extract all files into a zip archive written to `writer`
+    #[cfg(feature = "zip")]
+    pub fn extract_to_zip<P: PackageSource + ?Sized, W: Write + Seek>(&self, source: &P, writer: W) -> Result<W, Error> {
+        let mut zip = zip::ZipWriter::new(writer);
+        self.extract_to_archive(source, &mut zip)?;
+        zip.finish()
+            .map_err(|e| Error::data("Failed to finish zip", e))
+    }
+
+    /// Extract all files into a tar archive written to `writer`, which needs no seeking
+    #[cfg(feature = "tar")]
+    pub fn extract_to_tar<P: PackageSource + ?Sized, W: Write>(&self, source: &P, writer: W) -> Result<W, Error> {
+        let mut tar = tar::Builder::new(writer);
+        self.extract_to_archive(source, &mut tar)?;
+        Ok(tar.into_inner()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    fn open() -> EAppxFile {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        eappx
+    }
+
+    fn plaintext(eappx: &EAppxFile, name: &str) -> Vec<u8> {
+        let file = eappx.blockmap.files.iter().find(|f| f.name == name).unwrap();
+        let mut data = vec![];
+        eappx.file_reader(EMSIX, file).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_extract_to_zip() {
+        let eappx = open();
+        let zip = eappx.extract_to_zip(EMSIX, Cursor::new(vec![])).unwrap();
+
+        let mut archive = zip::ZipArchive::new(zip).unwrap();
+        // Blockmap files plus blockmap and code integrity catalog
+        assert_eq!(archive.len(), eappx.blockmap.files.len() + 2);
+
+        let mut data = vec![];
+        archive.by_name("Assets/LockScreenLogo.scale-200.png").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, plaintext(&eappx, "Assets\\LockScreenLogo.scale-200.png"));
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn test_extract_to_tar() {
+        let eappx = open();
+        let tar = eappx.extract_to_tar(EMSIX, vec![]).unwrap();
+
+        let mut archive = tar::Archive::new(tar.as_slice());
+        let mut names = vec![];
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            if name == "TestApp.dll" {
+                let mut data = vec![];
+                entry.read_to_end(&mut data).unwrap();
+                assert_eq!(data, plaintext(&eappx, "TestApp.dll"));
+            }
+            names.push(name);
+        }
+
+        assert_eq!(names.len(), eappx.blockmap.files.len() + 2);
+        assert!(names.contains(&"Assets/StoreLogo.png".to_owned()));
+    }
+}
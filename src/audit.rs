@@ -0,0 +1,212 @@
+//! Audit of the capabilities a package requests
+//!
+//! Every declared capability is classified by how far it reaches beyond the
+//! app container, so packages asking for full trust or access to the whole
+//! file system can be flagged before they are deployed. Capabilities not
+//! listed below fall back to a severity by their kind: restricted ones are
+//! [`Severity::High`], device capabilities [`Severity::Medium`] and the
+//! remaining general-use ones [`Severity::Low`].
+//!
+//! Reference: <https://learn.microsoft.com/en-us/windows/uwp/packaging/app-capability-declarations>
+
+use crate::manifest::{AppxManifest, CapabilityKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Network access and similar, stays inside the app container
+    Low,
+    /// Personal data or devices, e.g. libraries, contacts, microphone
+    Medium,
+    /// Restricted capability, needs approval for the Store
+    High,
+    /// Leaves the app container, runs elevated or reaches every file of the user
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Capabilities classified other than by their kind, with the reason
+const CLASSIFICATION: &[(&str, Severity, &str)] = &[
+    ("runFullTrust", Severity::Critical, "runs as a full trust desktop process outside the app container"),
+    ("allowElevation", Severity::Critical, "may run elevated as administrator"),
+    ("broadFileSystemAccess", Severity::Critical, "reads and writes every file the user can access"),
+    ("unvirtualizedResources", Severity::Critical, "writes to the registry and file system without virtualization"),
+    ("packagedServices", Severity::Critical, "installs Windows services"),
+    ("localSystemServices", Severity::Critical, "installs services running as LocalSystem"),
+    ("inputInjectionBrokered", Severity::Critical, "injects input into other applications"),
+    ("packageManagement", Severity::High, "installs and removes other packages"),
+    ("packageQuery", Severity::High, "enumerates installed packages"),
+    ("enterpriseAuthentication", Severity::High, "authenticates with the user's domain credentials"),
+    ("sharedUserCertificates", Severity::High, "uses certificates and smart cards of the user"),
+    ("documentsLibrary", Severity::High, "reads and writes the documents library"),
+    ("internetClientServer", Severity::Medium, "accepts inbound connections from the internet"),
+    ("privateNetworkClientServer", Severity::Medium, "accepts inbound connections from the local network"),
+    ("picturesLibrary", Severity::Medium, "reads and writes the pictures library"),
+    ("videosLibrary", Severity::Medium, "reads and writes the videos library"),
+    ("musicLibrary", Severity::Medium, "reads and writes the music library"),
+    ("removableStorage", Severity::Medium, "reads and writes removable storage"),
+    ("userAccountInformation", Severity::Medium, "reads the user's name and picture"),
+    ("contacts", Severity::Medium, "reads the user's contacts"),
+    ("appointments", Severity::Medium, "reads the user's calendar"),
+    ("phoneCall", Severity::Medium, "places phone calls"),
+];
+
+/// Severity of the capability `name` declared as `kind`, and the reason for it
+pub fn classify(kind: CapabilityKind, name: &str) -> (Severity, &'static str) {
+    if let Some((_, severity, reason)) = CLASSIFICATION.iter().find(|(known, _, _)| *known == name) {
+        return (*severity, reason);
+    }
+    match kind {
+        CapabilityKind::Restricted => (Severity::High, "restricted capability"),
+        CapabilityKind::Device => (Severity::Medium, "device access"),
+        CapabilityKind::General | CapabilityKind::Uap => (Severity::Low, "general-use capability"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CapabilityFinding {
+    /// File name of the bundle package declaring it, `None` for the package itself
+    pub package: Option<String>,
+    pub kind: CapabilityKind,
+    pub name: String,
+    pub severity: Severity,
+    pub reason: &'static str,
+}
+
+impl std::fmt::Display for CapabilityFinding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {} ({}): {}", self.severity, self.name, self.kind.tag(), self.reason)?;
+        if let Some(package) = &self.package {
+            write!(f, " in {package}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct CapabilityAudit {
+    /// Declared capabilities, in document order
+    pub findings: Vec<CapabilityFinding>,
+    /// Bundle packages whose manifest could not be read, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+impl CapabilityAudit {
+    /// Audit of the capabilities `manifest` declares, attributed to `package`
+    pub fn from_manifest(manifest: &AppxManifest, package: Option<&str>) -> Self {
+        Self {
+            findings: manifest.declared_capabilities().into_iter()
+                .map(|(kind, name)| {
+                    let (severity, reason) = classify(kind, name);
+                    CapabilityFinding { package: package.map(str::to_owned), kind, name: name.to_owned(), severity, reason }
+                })
+                .collect(),
+            skipped: vec![],
+        }
+    }
+
+    /// Capabilities of the `rescap` namespace
+    pub fn restricted(&self) -> impl Iterator<Item = &CapabilityFinding> {
+        self.findings.iter().filter(|finding| finding.kind == CapabilityKind::Restricted)
+    }
+
+    /// Findings of `severity` or above
+    pub fn at_least(&self, severity: Severity) -> impl Iterator<Item = &CapabilityFinding> {
+        self.findings.iter().filter(move |finding| finding.severity >= severity)
+    }
+
+    /// Highest severity found, `None` if no capabilities are declared
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|finding| finding.severity).max()
+    }
+}
+
+#[cfg(feature = "package")]
+impl crate::EAppxFile {
+    /// Audit the manifest's capabilities, those of every inner package for bundles
+    ///
+    /// Inner packages are read with the keys loaded into the bundle. Those that
+    /// cannot be read, zip based ones among them, are listed as skipped.
+    pub fn audit_capabilities<P: crate::source::PackageSource + ?Sized>(&self, source: &P) -> Result<CapabilityAudit, crate::error::Error> {
+        let crate::Manifest::Manifest(manifest) = self.manifest(source)? else {
+            let mut audit = CapabilityAudit::default();
+            for (filename, footer) in self.bundle_payloads(source)? {
+                match self.embedded_manifest(source, footer, &filename) {
+                    Ok(manifest) => audit.findings.extend(CapabilityAudit::from_manifest(&manifest, Some(&filename)).findings),
+                    Err(err) => {
+                        log::warn!("Skipping capabilities of {filename}: {err}");
+                        audit.skipped.push((filename, err.to_string()));
+                    },
+                }
+            }
+            return Ok(audit);
+        };
+        Ok(CapabilityAudit::from_manifest(manifest, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EAppxFile;
+
+    const XML_DATA_FULL: &str = include_str!("../testdata/manifest_full.xml");
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(CapabilityKind::Restricted, "runFullTrust").0, Severity::Critical);
+        assert_eq!(classify(CapabilityKind::Restricted, "broadFileSystemAccess").0, Severity::Critical);
+        assert_eq!(classify(CapabilityKind::Restricted, "confirmAppClose").0, Severity::High);
+        assert_eq!(classify(CapabilityKind::Uap, "picturesLibrary").0, Severity::Medium);
+        assert_eq!(classify(CapabilityKind::Device, "webcam").0, Severity::Medium);
+        assert_eq!(classify(CapabilityKind::General, "internetClient").0, Severity::Low);
+    }
+
+    #[test]
+    fn test_audit_manifest() {
+        let manifest = AppxManifest::from_xml(XML_DATA_FULL.as_bytes()).unwrap();
+        let audit = CapabilityAudit::from_manifest(&manifest, None);
+        let findings = audit.findings.iter().map(|finding| (finding.name.as_str(), finding.severity)).collect::<Vec<_>>();
+        assert_eq!(findings, [
+            ("internetClient", Severity::Low),
+            ("runFullTrust", Severity::Critical),
+            ("microphone", Severity::Medium),
+            ("graphicsCapture", Severity::Low),
+        ]);
+        assert_eq!(audit.max_severity(), Some(Severity::Critical));
+        assert_eq!(audit.restricted().count(), 1);
+        assert_eq!(audit.at_least(Severity::Medium).count(), 2);
+        assert!(CapabilityAudit::default().max_severity().is_none());
+    }
+
+    #[test]
+    fn test_audit_package() {
+        let package: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+        let mut eappx = EAppxFile::from_source(package).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let audit = eappx.audit_capabilities(package).unwrap();
+        let names = audit.findings.iter().map(|finding| (finding.package.as_deref(), finding.name.as_str())).collect::<Vec<_>>();
+        assert_eq!(names, [(None, "internetClient")]);
+        assert_eq!(audit.max_severity(), Some(Severity::Low));
+
+        let package: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+        let mut eappx = EAppxFile::from_source(package).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let audit = eappx.audit_capabilities(package).unwrap();
+        assert!(audit.skipped.is_empty());
+        let names = audit.findings.iter().map(|finding| (finding.package.as_deref(), finding.name.as_str())).collect::<Vec<_>>();
+        assert_eq!(names, [(Some("TestApp_1.0.3.0_x64.msix"), "internetClient")]);
+    }
+}
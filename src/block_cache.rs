@@ -0,0 +1,175 @@
+//! Cache of decrypted blocks for repeated random access
+//!
+//! [`EntryReader`](crate::entry_reader::EntryReader) inflates and decrypts every
+//! block it touches. An asset browser or a mounted package going back to the
+//! same files pays for that on every visit, a [`BlockCache`] set as
+//! [`EAppxFile::block_cache`](crate::EAppxFile::block_cache) keeps the plaintext
+//! of recently used blocks up to a memory budget, evicting the least recently
+//! used ones first. Blocks are cached once their hash was checked. Entries are
+//! keyed by file id, every package needs a cache of its own.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+};
+
+/// File id and block index within that file
+pub type BlockKey = (u64, usize);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub blocks: usize,
+    /// Plaintext bytes held
+    pub used: usize,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Plaintext and last use of each block
+    blocks: HashMap<BlockKey, (Arc<[u8]>, u64)>,
+    /// Blocks by last use, oldest first
+    order: BTreeMap<u64, BlockKey>,
+    tick: u64,
+    used: usize,
+    hits: u64,
+    misses: u64,
+}
+
+/// LRU cache of block plaintext, shareable between threads
+pub struct BlockCache {
+    budget: usize,
+    inner: Mutex<Inner>,
+}
+
+impl std::fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("budget", &self.budget)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl BlockCache {
+    /// Cache holding up to `budget` bytes of plaintext
+    pub fn new(budget: usize) -> Self {
+        Self { budget, inner: Mutex::new(Inner::default()) }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        // Blocks are inserted whole, a panicking reader leaves nothing half done
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn get(&self, key: BlockKey) -> Option<Arc<[u8]>> {
+        let mut inner = self.lock();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let Some((data, last_use)) = inner.blocks.get_mut(&key) else {
+            inner.misses += 1;
+            return None;
+        };
+        let (data, previous) = (data.clone(), std::mem::replace(last_use, tick));
+        inner.order.remove(&previous);
+        inner.order.insert(tick, key);
+        inner.hits += 1;
+        Some(data)
+    }
+
+    /// Store the plaintext of a block, evicting the least recently used ones to stay in budget
+    pub fn insert(&self, key: BlockKey, data: &[u8]) {
+        if data.len() > self.budget {
+            return;
+        }
+
+        let mut inner = self.lock();
+        if let Some((old, last_use)) = inner.blocks.remove(&key) {
+            inner.order.remove(&last_use);
+            inner.used -= old.len();
+        }
+        while inner.used + data.len() > self.budget {
+            let Some((_, oldest)) = inner.order.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = inner.blocks.remove(&oldest) {
+                inner.used -= evicted.len();
+            }
+        }
+
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.blocks.insert(key, (Arc::from(data), tick));
+        inner.order.insert(tick, key);
+        inner.used += data.len();
+    }
+
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.blocks.clear();
+        inner.order.clear();
+        inner.used = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.lock();
+        CacheStats { hits: inner.hits, misses: inner.misses, blocks: inner.blocks.len(), used: inner.used }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::{Read, Seek, SeekFrom}, sync::Arc};
+
+    use super::BlockCache;
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_eviction() {
+        let cache = BlockCache::new(10);
+        cache.insert((1, 0), &[1; 4]);
+        cache.insert((1, 1), &[2; 4]);
+        assert!(cache.get((1, 0)).is_some());
+
+        // Block 1 was used last before, it goes first
+        cache.insert((2, 0), &[3; 4]);
+        assert!(cache.get((1, 1)).is_none());
+        assert_eq!(cache.get((1, 0)).unwrap().as_ref(), &[1; 4]);
+        assert_eq!(cache.get((2, 0)).unwrap().as_ref(), &[3; 4]);
+
+        // Too large to ever fit
+        cache.insert((3, 0), &[0; 11]);
+        assert!(cache.get((3, 0)).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.blocks, stats.used), (3, 2, 2, 8));
+    }
+
+    #[test]
+    fn test_cached_entry_reader() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let mut expected = vec![];
+        eappx.entry_reader(EMSIX, "TestApp.dll").unwrap().read_to_end(&mut expected).unwrap();
+
+        let cache = Arc::new(BlockCache::new(16 << 20));
+        eappx.block_cache = Some(cache.clone());
+        let mut reader = eappx.entry_reader(EMSIX, "TestApp.dll").unwrap();
+        let mut first = vec![];
+        reader.read_to_end(&mut first).unwrap();
+        let blocks = cache.stats().blocks;
+        assert!(blocks > 1);
+
+        // Second pass is served from the cache, even if the payload is gone
+        let mut again = vec![];
+        let mut reader = eappx.entry_reader(vec![0u8; EMSIX.len()], "TestApp.dll").unwrap();
+        reader.seek(SeekFrom::Start(0)).unwrap();
+        reader.read_to_end(&mut again).unwrap();
+        assert_eq!((first.as_slice(), again.as_slice()), (expected.as_slice(), expected.as_slice()));
+        assert_eq!(cache.stats().hits, blocks as u64);
+    }
+}
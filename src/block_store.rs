@@ -0,0 +1,326 @@
+//! Content-addressed storage of packages
+//!
+//! [`export_blocks`] splits a package into segments and puts each one into a
+//! [`BlockStore`] under the SHA-256 of its bytes. The returned
+//! [`BlockManifest`] lists the segments in order, [`reassemble`] turns it back
+//! into the original package, byte for byte.
+//!
+//! Segment boundaries follow the stored blocks of the blockmap, so an
+//! unchanged 64KiB block of another version lands on the blob already stored.
+//! For uncompressed files of SHA-256 blockmaps the key is the block hash of
+//! the blockmap, which is checked on export. Header, footers, blockmap and
+//! signature are stored in 64KiB chunks.
+//!
+//! As with [`crate::delta`], encrypted blocks only deduplicate between
+//! packages sharing key and identity.
+
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, Write},
+    path::PathBuf,
+};
+
+use base64ct::{Base64, Encoding};
+use binrw::{binrw, BinRead, BinWrite};
+use sha2::{Digest, Sha256};
+
+use crate::{delta::stored_blocks, error::Error, source::PackageSource, utils, EAppxFile};
+
+pub const BLOCK_MANIFEST_VERSION: u16 = 1;
+
+/// Blobs keyed by the SHA-256 of their content
+pub trait BlockStore {
+    fn contains(&self, hash: &[u8; 32]) -> Result<bool, Error>;
+    fn put(&mut self, hash: &[u8; 32], data: &[u8]) -> Result<(), Error>;
+    fn get(&self, hash: &[u8; 32]) -> Result<Vec<u8>, Error>;
+}
+
+impl BlockStore for HashMap<[u8; 32], Vec<u8>> {
+    fn contains(&self, hash: &[u8; 32]) -> Result<bool, Error> {
+        Ok(self.contains_key(hash))
+    }
+
+    fn put(&mut self, hash: &[u8; 32], data: &[u8]) -> Result<(), Error> {
+        self.insert(*hash, data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, hash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        self.get(hash).cloned()
+            .ok_or(Error::DataError(format!("Blob {} not in store", hex::encode(hash))))
+    }
+}
+
+/// Store keeping every blob in a file `<root>/<first two hex digits>/<hex>`
+#[derive(Debug, Clone)]
+pub struct DirectoryStore {
+    root: PathBuf,
+}
+
+impl DirectoryStore {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, hash: &[u8; 32]) -> PathBuf {
+        let name = hex::encode(hash);
+        self.root.join(&name[..2]).join(name)
+    }
+}
+
+impl BlockStore for DirectoryStore {
+    fn contains(&self, hash: &[u8; 32]) -> Result<bool, Error> {
+        Ok(self.path(hash).try_exists()?)
+    }
+
+    fn put(&mut self, hash: &[u8; 32], data: &[u8]) -> Result<(), Error> {
+        let path = self.path(hash);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Never leave a truncated blob behind under its final name
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, data)?;
+        std::fs::rename(partial, path)?;
+        Ok(())
+    }
+
+    fn get(&self, hash: &[u8; 32]) -> Result<Vec<u8>, Error> {
+        std::fs::read(self.path(hash))
+            .map_err(|e| Error::data(format!("Failed to read blob {}", hex::encode(hash)), e))
+    }
+}
+
+#[binrw]
+#[brw(little, magic = b"EXBM")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ManifestHeader {
+    version: u16,
+    package_length: u64,
+    package_hash: [u8; 32],
+    segment_count: u64,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    /// SHA-256 of the segment, its key in the store
+    pub hash: [u8; 32],
+    pub length: u64,
+}
+
+/// Ordered segments making up a package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockManifest {
+    pub package_length: u64,
+    /// SHA-256 over the complete package
+    pub package_hash: [u8; 32],
+    pub segments: Vec<Segment>,
+}
+
+impl BlockManifest {
+    pub fn read<R: Read + Seek>(reader: &mut R) -> Result<Self, Error> {
+        let header = ManifestHeader::read(reader)
+            .map_err(|e| Error::decode("Failed to read block manifest header", e))?;
+        if header.version != BLOCK_MANIFEST_VERSION {
+            return Err(Error::DataError(format!("Unsupported block manifest version {}", header.version)));
+        }
+
+        // Segment count is untrusted, grow as segments are actually read
+        let mut segments = vec![];
+        for _ in 0..header.segment_count {
+            segments.push(Segment::read(reader)
+                .map_err(|e| Error::decode("Failed to read block manifest segment", e))?);
+        }
+
+        Ok(Self { package_length: header.package_length, package_hash: header.package_hash, segments })
+    }
+
+    pub fn write<W: Write + Seek>(&self, writer: &mut W) -> Result<(), Error> {
+        let header = ManifestHeader {
+            version: BLOCK_MANIFEST_VERSION,
+            package_length: self.package_length,
+            package_hash: self.package_hash,
+            segment_count: self.segments.len() as u64,
+        };
+        header.write(writer)
+            .map_err(|e| Error::data("Failed to write block manifest", e))?;
+        for segment in &self.segments {
+            segment.write(writer)
+                .map_err(|e| Error::data("Failed to write block manifest", e))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExportStats {
+    /// Blobs added to the store
+    pub new_blobs: u64,
+    pub new_bytes: u64,
+    /// Bytes already present in the store
+    pub reused_bytes: u64,
+}
+
+/// Range of the package, with the blockmap hash its bytes must have
+struct PlannedSegment {
+    offset: u64,
+    length: u64,
+    expected: Option<[u8; 32]>,
+}
+
+/// Cover `from..to` with chunks of at most a block
+fn push_chunks(segments: &mut Vec<PlannedSegment>, from: u64, to: u64) {
+    for offset in (from..to).step_by(utils::BLOCK_SIZE) {
+        let length = std::cmp::min(utils::BLOCK_SIZE as u64, to - offset);
+        segments.push(PlannedSegment { offset, length, expected: None });
+    }
+}
+
+fn plan_segments(eappx: &EAppxFile) -> Vec<PlannedSegment> {
+    let mut blocks = stored_blocks(eappx);
+    blocks.sort_by_key(|block| block.offset);
+
+    let mut segments = vec![];
+    let mut pos = 0;
+    for block in blocks {
+        if block.offset < pos || block.length == 0 || block.offset.checked_add(block.length).is_none_or(|end| end > eappx.file_len) {
+            continue;
+        }
+
+        push_chunks(&mut segments, pos, block.offset);
+        // Compressed blocks are hashed before compression
+        let expected = match block.compressed {
+            true => None,
+            false => Base64::decode_vec(block.hash).ok()
+                .and_then(|hash| <[u8; 32]>::try_from(hash).ok()),
+        };
+        segments.push(PlannedSegment { offset: block.offset, length: block.length, expected });
+        pos = block.offset + block.length;
+    }
+    push_chunks(&mut segments, pos, eappx.file_len);
+
+    segments
+}
+
+/// Put every segment of the package into `store`, skipping blobs it already has
+pub fn export_blocks<P: PackageSource + ?Sized, S: BlockStore>(
+    eappx: &EAppxFile,
+    source: &P,
+    store: &mut S,
+) -> Result<(BlockManifest, ExportStats), Error> {
+    let mut stats = ExportStats::default();
+    let mut package_hasher = Sha256::new();
+    let mut segments = vec![];
+    let mut buf = vec![];
+
+    for planned in plan_segments(eappx) {
+        buf.resize(planned.length as usize, 0);
+        source.read_exact_at(planned.offset, &mut buf)?;
+        package_hasher.update(&buf);
+
+        let hash: [u8; 32] = Sha256::digest(&buf).into();
+        if planned.expected.is_some_and(|expected| expected != hash) {
+            return Err(Error::DataError(format!("Block at {:#x} does not match its blockmap hash", planned.offset)));
+        }
+
+        if store.contains(&hash)? {
+            stats.reused_bytes += planned.length;
+        } else {
+            store.put(&hash, &buf)?;
+            stats.new_blobs += 1;
+            stats.new_bytes += planned.length;
+        }
+        segments.push(Segment { hash, length: planned.length });
+    }
+
+    log::debug!("Exported {} segments, {:#x} new bytes, {:#x} reused bytes", segments.len(), stats.new_bytes, stats.reused_bytes);
+    let manifest = BlockManifest {
+        package_length: eappx.file_len,
+        package_hash: package_hasher.finalize().into(),
+        segments,
+    };
+    Ok((manifest, stats))
+}
+
+/// Write the package described by `manifest` into `out`
+///
+/// Every blob and the complete package are checked against their hashes. On
+/// error, `out` holds partial and unusable data.
+pub fn reassemble<S: BlockStore, W: Write>(manifest: &BlockManifest, store: &S, out: &mut W) -> Result<(), Error> {
+    let mut package_hasher = Sha256::new();
+    let mut written = 0u64;
+
+    for segment in &manifest.segments {
+        let data = store.get(&segment.hash)?;
+        if data.len() as u64 != segment.length || Sha256::digest(&data).as_slice() != segment.hash {
+            return Err(Error::DataError(format!("Blob {} is corrupt", hex::encode(segment.hash))));
+        }
+        package_hasher.update(&data);
+        out.write_all(&data)?;
+        written += segment.length;
+    }
+
+    if written != manifest.package_length || package_hasher.finalize().as_slice() != manifest.package_hash {
+        return Err(Error::DataError("Reassembled package does not match the manifest".into()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::sample;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_block_store_roundtrip() {
+        let mut store = HashMap::new();
+
+        for data in [EMSIX.to_vec(), sample::build_sample_package().unwrap()] {
+            let eappx = EAppxFile::from_source(data.as_slice()).unwrap();
+            let (manifest, stats) = export_blocks(&eappx, data.as_slice(), &mut store).unwrap();
+            assert_eq!(stats.new_bytes + stats.reused_bytes, data.len() as u64);
+
+            let mut serialized = Cursor::new(vec![]);
+            manifest.write(&mut serialized).unwrap();
+            serialized.set_position(0);
+            assert_eq!(BlockManifest::read(&mut serialized).unwrap(), manifest);
+
+            let mut out = vec![];
+            reassemble(&manifest, &store, &mut out).unwrap();
+            assert_eq!(out, data);
+
+            // Exporting again adds nothing
+            let (_, stats) = export_blocks(&eappx, data.as_slice(), &mut store).unwrap();
+            assert_eq!(stats.new_blobs, 0);
+            assert_eq!(stats.reused_bytes, data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_block_store_corrupt() {
+        let mut store = HashMap::new();
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let (manifest, _) = export_blocks(&eappx, EMSIX, &mut store).unwrap();
+
+        // Blocks of unencrypted, uncompressed files are checked against the blockmap
+        let planned = plan_segments(&eappx);
+        let checked = planned.iter().find(|segment| segment.expected.is_some()).unwrap();
+        let mut tampered = EMSIX.to_vec();
+        tampered[checked.offset as usize] ^= 0x01;
+        assert!(export_blocks(&eappx, tampered.as_slice(), &mut HashMap::new()).is_err());
+
+        let hash = manifest.segments[0].hash;
+        store.get_mut(&hash).unwrap()[0] ^= 0x01;
+        assert!(reassemble(&manifest, &store, &mut vec![]).is_err());
+        store.remove(&hash);
+        assert!(reassemble(&manifest, &store, &mut vec![]).is_err());
+    }
+}
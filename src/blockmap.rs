@@ -1,26 +1,74 @@
+//! AppxBlockMap.xml
+//!
+//! Elements and attributes are matched by local name in the 2010 blockmap
+//! namespace or any of its extensions, whichever prefix a package binds them
+//! to. Everything the typed fields do not cover (unknown attributes and
+//! elements, comments, whitespace) is kept verbatim, so [`AppxBlockMap::to_xml`]
+//! gives back a parsed document unchanged unless its typed fields were modified.
+
 use base64ct::{Base64, Encoding};
-use xmlserde_derives::{XmlDeserialize, XmlSerialize};
+use xmlserde::quick_xml::{events::{BytesStart, Event}, Reader};
+
+use crate::{
+    crypto::{canonicalize_file_path, CryptoFileContext},
+    digest::HashAlgorithm,
+    error::Error,
+    utils::{self, xml_escape},
+};
+
+pub const NAMESPACE: &str = "http://schemas.microsoft.com/appx/2010/blockmap";
+/// Later revisions extending the 2010 schema
+pub const EXTENSION_NAMESPACES: &[&str] = &[
+    "http://schemas.microsoft.com/appx/2015/blockmap",
+    "http://schemas.microsoft.com/appx/2016/blockmap",
+    "http://schemas.microsoft.com/appx/2017/blockmap",
+    "http://schemas.microsoft.com/appx/2018/blockmap",
+    "http://schemas.microsoft.com/appx/2021/blockmap",
+];
+
+/// Package manifest, first blockmap file of a package
+pub const APPX_MANIFEST: &str = "AppxManifest.xml";
+/// Bundle manifest, first blockmap file of a bundle
+pub const BUNDLE_MANIFEST: &str = "AppxMetadata\\AppxBundleManifest.xml";
+/// Directory holding footprint files that are listed in the blockmap
+pub const METADATA_DIR: &str = "AppxMetadata";
 
 const DEFAULT_HASH_METHOD: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#;
 
 pub trait Hash {
-    fn hash_bytes(&self) -> Vec<u8>;
+    /// Decoded hash, an error if the attribute is not valid base64
+    fn hash_bytes(&self) -> Result<Vec<u8>, Error>;
+}
+
+/// Parts of an element the typed fields do not cover, kept as read
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Preserved {
+    /// Qualified name as read, elements built in code get the name eappx writes
+    tag: Option<String>,
+    /// Attributes in document order, `None` marks the place of a typed one
+    attributes: Vec<(String, Option<String>)>,
+    /// Raw unknown elements, comments and whitespace, each with the amount of
+    /// typed children preceding it
+    content: Vec<(usize, String)>,
 }
 
 /// Defines the root element of the app package block map. The BlockMap element
 /// specifies the algorithm that is used to compute cryptographic hashes and
 /// contains a sequence of File child elements that are associated with each
 /// file that is stored in the package.
-#[derive(Clone, Debug, PartialEq, Eq, XmlDeserialize, XmlSerialize)]
-#[xmlserde(root=b"b2:BlockMap")]
-#[xmlserde(with_ns = b"http://schemas.microsoft.com/appx/2010/blockmap")]
-#[xmlserde(with_custom_ns(b"b2", b"http://schemas.microsoft.com/appx/2015/blockmap"))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AppxBlockMap {
-    #[xmlserde(name = b"HashMethod", ty = "attr")]
     hash_method: String,
     /// Files in the package.
-    #[xmlserde(name = b"b2:File", ty = "child")]
     pub files: Vec<File>,
+    /// XML declaration and whatever else precedes the root element
+    prolog: String,
+    /// Whatever follows the root element
+    epilog: String,
+    pub preserved: Preserved,
 }
 
 impl Default for AppxBlockMap {
@@ -28,92 +76,699 @@ impl Default for AppxBlockMap {
         Self {
             hash_method: DEFAULT_HASH_METHOD.into(),
             files: Default::default(),
+            prolog: format!("{XML_DECLARATION}\n"),
+            epilog: String::new(),
+            preserved: Preserved {
+                tag: None,
+                attributes: vec![
+                    ("xmlns".into(), Some(NAMESPACE.into())),
+                    ("xmlns:b2".into(), Some(EXTENSION_NAMESPACES[0].into())),
+                ],
+                content: vec![],
+            },
+        }
+    }
+}
+
+impl AppxBlockMap {
+    pub fn from_xml(xml: &[u8]) -> Result<Self, Error> {
+        let mut parser = Parser::new(xml);
+        let root = loop {
+            match parser.next()? {
+                Node::Element(element) => break element,
+                Node::Raw(_) => {},
+                Node::End | Node::Eof => return Err(Error::DecodeError("Blockmap has no root element".into())),
+            }
+        };
+        let name = parser.name(&root)?;
+        if parser.blockmap_local(&name, false) != Some("BlockMap") {
+            return Err(Error::DecodeError(format!("Unexpected blockmap root element {name}")));
+        }
+
+        let prolog = utf8(&xml[..root.offset])?;
+        let (typed, mut preserved) = parser.attributes(&root, &["HashMethod"])?;
+        let hash_method = typed.into_iter().next().flatten()
+            .ok_or(Error::DecodeError("Blockmap has no HashMethod".into()))?;
+        if HashAlgorithm::from_uri(&hash_method).is_none() {
+            log::warn!("Unsupported blockmap HashMethod {hash_method}, hashes are checked by their length");
+        }
+
+        let mut files = vec![];
+        parser.children(root, &mut preserved, &["File"], |parser, _, element| {
+            files.push(File::parse(parser, element)?);
+            Ok(())
+        })?;
+        let epilog = utf8(&xml[parser.reader.buffer_position()..])?;
+
+        let mut blockmap = Self { hash_method, files, prolog, epilog, preserved };
+        let scheme = match blockmap.files.iter().filter(|file| file.id.is_empty()).count() {
+            0 => IdScheme::Hex,
+            missing if missing == blockmap.files.len() => IdScheme::Position,
+            _ => return Err(Error::DecodeError("Blockmap lists files both with and without Id".into())),
+        };
+        blockmap.resolve_ids(scheme)?;
+        Ok(blockmap)
+    }
+
+    /// Resolve the footer table id of every file by `scheme`
+    ///
+    /// Ids are left as they were if any of them does not fit the scheme.
+    pub fn resolve_ids(&mut self, scheme: IdScheme) -> Result<(), Error> {
+        let ids = self.files.iter()
+            .enumerate()
+            .map(|(position, file)| match scheme {
+                IdScheme::Position => Ok(position as u64),
+                _ => scheme.parse(&file.id)
+                    .ok_or(Error::DecodeError(format!("Id {:?} of {} does not fit the {scheme:?} blockmap schema", file.id, file.name))),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        for (file, id) in self.files.iter_mut().zip(ids) {
+            file.file_id = Some(id);
+        }
+        Ok(())
+    }
+
+    /// Read the ids as decimal if only that way every file finds its footer
+    ///
+    /// Digit-only ids are taken as hex when the blockmap is read, which is
+    /// ambiguous once there are ten files or more.
+    pub fn detect_decimal_ids(&mut self, has_footer: impl Fn(u64) -> bool) {
+        let all_found = |scheme: IdScheme| self.files.iter()
+            .all(|file| scheme.parse(&file.id).is_some_and(&has_footer));
+        if !self.files.is_empty() && !all_found(IdScheme::Hex) && all_found(IdScheme::Decimal) {
+            log::info!("Blockmap ids are decimal");
+            let _ = self.resolve_ids(IdScheme::Decimal);
+        }
+    }
+
+    /// Complete document, including the XML declaration
+    pub fn to_xml(&self) -> String {
+        let files = self.files.iter().map(File::to_xml).collect::<Vec<_>>();
+        let mut xml = self.prolog.clone();
+        write_element(&mut xml, "b2:BlockMap", &self.preserved, &[("HashMethod", Some(self.hash_method.clone()))], &files);
+        xml + &self.epilog
+    }
+
+    pub fn hash_method(&self) -> &str {
+        &self.hash_method
+    }
+
+    /// Digest the `HashMethod` names, `None` if it is not supported
+    pub fn hash_algorithm(&self) -> Option<HashAlgorithm> {
+        HashAlgorithm::from_uri(&self.hash_method)
+    }
+
+    /// Digest of the block hashes of `file`
+    pub fn block_hash_algorithm(&self, file: &File) -> HashAlgorithm {
+        let digest_len = file.blocks.first().map_or(0, |block| decoded_len(&block.hash));
+        HashAlgorithm::select(self.hash_algorithm(), digest_len)
+    }
+
+    /// Digest of the file hash of `file`, its own `HashMethod` takes precedence over the blockmap one
+    pub fn filehash_algorithm(&self, file: &File) -> HashAlgorithm {
+        match &file.filehash {
+            Some(filehash) => filehash.algorithm(self.hash_algorithm()),
+            None => self.hash_algorithm().unwrap_or_default(),
+        }
+    }
+
+    /// File by its package path, compared case-insensitively like Windows does
+    pub fn find_file(&self, name: &str) -> Option<&File> {
+        self.find(name).map(|(_, file)| file)
+    }
+
+    /// File and its index by package path with Windows path semantics
+    ///
+    /// Case is ignored, `/` and `\` are both separators, leading, repeated and
+    /// `.` components are dropped, so `./assets//Logo.png` finds `Assets\Logo.png`.
+    pub fn find(&self, path: &str) -> Option<(usize, &File)> {
+        let path = canonicalize_file_path(path);
+        if path.is_empty() {
+            return None;
+        }
+        self.files.iter().enumerate()
+            .find(|(_, file)| canonicalize_file_path(&file.name).eq_ignore_ascii_case(&path))
+    }
+
+    /// Manifest of the package or bundle, found by name rather than position
+    ///
+    /// Packages naming it differently are assumed to list it first, as MakeAppx does.
+    pub fn manifest_file(&self) -> Option<&File> {
+        self.find_file(BUNDLE_MANIFEST)
+            .or_else(|| self.find_file(APPX_MANIFEST))
+            .or_else(|| {
+                let first = self.files.first()?;
+                log::warn!("No manifest found by name, assuming the first file {} is the manifest", first.name);
+                Some(first)
+            })
+    }
+
+    /// Files below `AppxMetadata`, e.g. the bundle manifest
+    pub fn metadata_files(&self) -> impl Iterator<Item = &File> {
+        self.files.iter().filter(|file| file.is_metadata())
+    }
+}
+
+/// How the `Id` of blockmap files maps to footer file ids
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IdScheme {
+    /// Hexadecimal, as MakeAppx writes them
+    #[default]
+    Hex,
+    Decimal,
+    /// No `Id` at all, files are addressed by their position in the blockmap
+    Position,
+}
+
+impl IdScheme {
+    fn parse(self, id: &str) -> Option<u64> {
+        match self {
+            IdScheme::Hex => u64::from_str_radix(id, 16).ok(),
+            IdScheme::Decimal => id.parse().ok(),
+            IdScheme::Position => None,
         }
     }
 }
 
 /// Represents a file contained in the package.
-#[derive(Clone, Debug, PartialEq, Eq, Default, XmlDeserialize, XmlSerialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct File {
     /// Root path and file name.
-    #[xmlserde(name = b"Name", ty = "attr")]
     pub name: String,
-    #[xmlserde(name = b"Id", ty = "attr")]
+    /// Footer table id, only in encrypted packages
     pub id: String,
+    /// Footer table id `id` resolved to when the blockmap was read, see [`IdScheme`]
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub file_id: Option<u64>,
     /// Size, in bytes, of the file's uncompressed data.
-    #[xmlserde(name = b"Size", ty = "attr")]
     pub size: u64,
-    
-    #[xmlserde(name = b"Encrypted", ty = "attr")]
-    pub encrypted: String,
+    /// `Encrypted` attribute, only in encrypted packages
+    #[cfg_attr(feature = "serde", serde(default, deserialize_with = "encrypted_flag::deserialize"))]
+    pub encrypted: Option<bool>,
     /// Blocks that make up the file.
-    #[xmlserde(name = b"Block", ty = "child")]
     pub blocks: Vec<Block>,
-
-    #[xmlserde(name = b"b2:FileHash", ty = "child")]
     pub filehash: Option<FileHash>,
+    pub preserved: Preserved,
 }
 
 impl File {
+    /// Footer table id, ids that do not resolve match no footer
     pub fn id(&self) -> u64 {
-        u64::from_str_radix(&self.id, 16).unwrap()
+        self.try_id().unwrap_or(u64::MAX)
+    }
+
+    /// Footer table id, resolved when the blockmap was read, else `id` as hex
+    pub fn try_id(&self) -> Result<u64, Error> {
+        match self.file_id {
+            Some(id) => Ok(id),
+            None => IdScheme::Hex.parse(&self.id)
+                .ok_or(Error::DecodeError(format!("Invalid Id {:?} for {} in blockmap", self.id, self.name))),
+        }
     }
 
+    /// Whether the blockmap marks the file encrypted, the footer key index
+    /// decides, see [`EAppxFile::is_file_encrypted`](crate::EAppxFile::is_file_encrypted)
     pub fn is_encrypted(&self) -> bool {
-        self.encrypted == "true"
+        self.encrypted == Some(true)
     }
 
-    pub fn filehash_bytes(&self) -> Option<Vec<u8>> {
+    /// Whether the file is a footprint file below `AppxMetadata`
+    pub fn is_metadata(&self) -> bool {
+        self.name.split_once(['\\', '/'])
+            .is_some_and(|(dir, name)| dir.eq_ignore_ascii_case(METADATA_DIR) && !name.is_empty())
+    }
+
+    pub fn filehash_bytes(&self) -> Result<Option<Vec<u8>>, Error> {
         self.filehash.as_ref()
-            .and_then(|h|Some(h.hash_bytes()))
+            .map(|h| decode_hash(&h.hash, || format!("Invalid file hash for {}", self.name)))
+            .transpose()
     }
 
-    pub fn block_hashes(&self) -> Vec<Vec<u8>> {
-        self.blocks.iter().map(|b|b.hash_bytes())
+    /// Decoded block hashes, the error names the first malformed block
+    pub fn block_hashes(&self) -> Result<Vec<Vec<u8>>, Error> {
+        self.blocks.iter()
+            .enumerate()
+            .map(|(index, b)| decode_hash(&b.hash, || format!("Invalid hash for block {index} of {}", self.name)))
             .collect()
     }
+
+    /// Stored size of every block, `None` unless all blocks list one (compressed files)
+    pub fn block_sizes(&self) -> Option<Vec<u32>> {
+        self.blocks.iter().map(|b| b.size)
+            .collect()
+    }
+
+    fn parse<'a>(parser: &mut Parser<'a>, element: Element<'a>) -> Result<Self, Error> {
+        let (typed, mut preserved) = parser.attributes(&element, &["Name", "Id", "Size", "Encrypted"])?;
+        let [name, id, size, encrypted] = <[Option<String>; 4]>::try_from(typed).unwrap();
+        let name = name.ok_or(Error::DecodeError("Blockmap file without Name".into()))?;
+        let size = parse_number(size.as_deref(), "Size", &name)?
+            .ok_or(Error::DecodeError(format!("Blockmap file {name} without Size")))?;
+        let encrypted = encrypted.as_deref()
+            .map(|value| parse_bool(value).ok_or(Error::DecodeError(format!("Invalid Encrypted {value:?} for {name} in blockmap"))))
+            .transpose()?;
+
+        let mut blocks = vec![];
+        let mut filehash = None;
+        parser.children(element, &mut preserved, &["Block", "FileHash"], |parser, local, element| {
+            match local {
+                "Block" => blocks.push(Block::parse(parser, element, &name)?),
+                _ => filehash = Some(FileHash::parse(parser, element, &name)?),
+            }
+            Ok(())
+        })?;
+
+        Ok(Self { name, id: id.unwrap_or_default(), file_id: None, size, encrypted, blocks, filehash, preserved })
+    }
+
+    fn to_xml(&self) -> String {
+        let non_empty = |value: &String| (!value.is_empty()).then(|| value.clone());
+        let attributes = [
+            ("Name", Some(self.name.clone())),
+            ("Id", non_empty(&self.id)),
+            ("Size", Some(self.size.to_string())),
+            ("Encrypted", self.encrypted.map(|encrypted| encrypted.to_string())),
+        ];
+        let children = self.blocks.iter().map(Block::to_xml)
+            .chain(self.filehash.iter().map(FileHash::to_xml))
+            .collect::<Vec<_>>();
+
+        let mut xml = String::new();
+        write_element(&mut xml, "b2:File", &self.preserved, &attributes, &children);
+        xml
+    }
 }
 
 /// Represents a 64kib block of binary data contained in a file.
-#[derive(Clone, Debug, PartialEq, Eq, Default, XmlDeserialize, XmlSerialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block {
     /// The hash value of the uncompressed data block.
-    #[xmlserde(name = b"Hash", ty = "attr")]
     pub hash: String,
     /// The size, in bytes, of the data block when stored in the package. If
     /// the file data is compressed, the size of each compressed block
     /// potentially varies in size.
-    #[xmlserde(name = b"Size", ty = "attr")]
-    pub size: Option<u16>,
+    pub size: Option<u32>,
+    pub preserved: Preserved,
+}
+
+impl Block {
+    fn parse<'a>(parser: &mut Parser<'a>, element: Element<'a>, file: &str) -> Result<Self, Error> {
+        let (typed, mut preserved) = parser.attributes(&element, &["Hash", "Size"])?;
+        let [hash, size] = <[Option<String>; 2]>::try_from(typed).unwrap();
+        let hash = hash.ok_or(Error::DecodeError(format!("Block of {file} without Hash")))?;
+        let size = parse_number(size.as_deref(), "Block Size", file)?;
+        parser.children(element, &mut preserved, &[], |_, _, _| Ok(()))?;
+
+        Ok(Self { hash, size, preserved })
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        let attributes = [("Hash", Some(self.hash.clone())), ("Size", self.size.map(|size| size.to_string()))];
+        write_element(&mut xml, "Block", &self.preserved, &attributes, &[]);
+        xml
+    }
 }
 
 impl Hash for Block {
-    fn hash_bytes(&self) -> Vec<u8> {
-        Base64::decode_vec(&self.hash).unwrap()
+    fn hash_bytes(&self) -> Result<Vec<u8>, Error> {
+        decode_hash(&self.hash, || "Invalid block hash".into())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Default, XmlDeserialize, XmlSerialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FileHash {
     /// The hash value of the entire uncompressed file.
-    #[xmlserde(name = b"Hash", ty = "attr")]
     pub hash: String,
+    /// Digest of this hash, the blockmap `HashMethod` applies if there is none
+    pub hash_method: Option<String>,
+    pub preserved: Preserved,
+}
+
+impl FileHash {
+    fn parse<'a>(parser: &mut Parser<'a>, element: Element<'a>, file: &str) -> Result<Self, Error> {
+        let (typed, mut preserved) = parser.attributes(&element, &["Hash", "HashMethod"])?;
+        let [hash, hash_method] = <[Option<String>; 2]>::try_from(typed).unwrap();
+        let hash = hash.ok_or(Error::DecodeError(format!("FileHash of {file} without Hash")))?;
+        parser.children(element, &mut preserved, &[], |_, _, _| Ok(()))?;
+
+        Ok(Self { hash, hash_method, preserved })
+    }
+
+    /// Digest of the hash, `blockmap` being the one the blockmap `HashMethod` names
+    pub fn algorithm(&self, blockmap: Option<HashAlgorithm>) -> HashAlgorithm {
+        let declared = self.hash_method.as_deref().and_then(HashAlgorithm::from_uri).or(blockmap);
+        HashAlgorithm::select(declared, decoded_len(&self.hash))
+    }
+
+    fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        let attributes = [("Hash", Some(self.hash.clone())), ("HashMethod", self.hash_method.clone())];
+        write_element(&mut xml, "b2:FileHash", &self.preserved, &attributes, &[]);
+        xml
+    }
 }
 
 impl Hash for FileHash {
-    fn hash_bytes(&self) -> Vec<u8> {
-        Base64::decode_vec(&self.hash).unwrap()
+    fn hash_bytes(&self) -> Result<Vec<u8>, Error> {
+        decode_hash(&self.hash, || "Invalid file hash".into())
+    }
+}
+
+/// Base64 form hashes take in the blockmap
+pub fn encode_hash(hash: &[u8]) -> String {
+    Base64::encode_string(hash)
+}
+
+/// Turn the plaintext of block `index` into the data its block hash covers, in place
+///
+/// Block hashes are taken after encryption but before compression: an
+/// unencrypted block stays as it is, an encrypted one is padded to whole
+/// sectors and encrypted at its offset in the file. Compressed packages
+/// deflate the result afterwards.
+pub fn stored_block(block: &mut Vec<u8>, index: u64, crypto: Option<&CryptoFileContext>) {
+    if let Some(crypto) = crypto {
+        block.resize(crypto.params.align_to_sector(block.len()), 0);
+        crypto.encrypt_at(block, index * crypto.params.block_size() as u64);
+    }
+}
+
+/// Hash the blockmap records for block `index` with plaintext `block`, see [`stored_block`]
+pub fn block_hash(block: &[u8], index: u64, algorithm: HashAlgorithm, crypto: Option<&CryptoFileContext>) -> Vec<u8> {
+    match crypto {
+        Some(_) => {
+            let mut stored = block.to_vec();
+            stored_block(&mut stored, index, crypto);
+            algorithm.digest(&stored)
+        },
+        None => algorithm.digest(block),
+    }
+}
+
+/// Blockmap entry of the plaintext read from `reader`, `id` being its footer table id
+///
+/// The file is split into 64 KiB blocks hashed by [`block_hash`], the file
+/// hash covers the whole plaintext. Both use `algorithm`, which the
+/// `HashMethod` of the blockmap has to name. Files encrypted with `crypto` are
+/// marked so, packers deflating a file set [`Block::size`] to the stored size
+/// of each block.
+pub fn hash_file<R: std::io::Read>(
+    name: &str,
+    id: u64,
+    reader: &mut R,
+    algorithm: HashAlgorithm,
+    crypto: Option<&CryptoFileContext>,
+) -> Result<File, Error> {
+    let mut filehash = algorithm.hasher();
+    let mut blocks = vec![];
+    let mut size = 0u64;
+    let mut buf = vec![0u8; crypto.map_or(utils::BLOCK_SIZE, |crypto| crypto.params.block_size())];
+    loop {
+        let read = utils::read_up_to(reader, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        filehash.update(&buf[..read]);
+        let hash = block_hash(&buf[..read], blocks.len() as u64, algorithm, crypto);
+        blocks.push(Block { hash: encode_hash(&hash), ..Default::default() });
+        size += read as u64;
+        if read < buf.len() {
+            break;
+        }
+    }
+
+    Ok(File {
+        name: name.to_owned(),
+        id: format!("{id:X}"),
+        size,
+        encrypted: crypto.map(|_| true),
+        blocks,
+        filehash: Some(FileHash { hash: encode_hash(&filehash.finalize()), ..Default::default() }),
+        ..Default::default()
+    })
+}
+
+fn decode_hash(hash: &str, what: impl FnOnce() -> String) -> Result<Vec<u8>, Error> {
+    Base64::decode_vec(hash).map_err(|e| Error::decode(format!("{} {hash:?}", what()), e))
+}
+
+/// Length of a base64 hash, 0 if it is not valid base64
+fn decoded_len(hash: &str) -> usize {
+    Base64::decode_vec(hash).map_or(0, |bytes| bytes.len())
+}
+
+fn utf8(data: &[u8]) -> Result<String, Error> {
+    String::from_utf8(data.to_vec())
+        .map_err(|e| Error::decode("Blockmap is not valid UTF-8", e))
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<&str>, what: &str, file: &str) -> Result<Option<T>, Error> {
+    value.map(|value| value.parse()
+        .map_err(|_| Error::DecodeError(format!("Invalid {what} {value:?} for {file} in blockmap"))))
+        .transpose()
+}
+
+/// `xs:boolean`, written back as `true` or `false`
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Accepts the flag as boolean or as the attribute text, e.g. `"true"`
+#[cfg(feature = "serde")]
+mod encrypted_flag {
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Text(String),
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<bool>, D::Error> {
+        match Option::<Flag>::deserialize(deserializer)? {
+            None => Ok(None),
+            Some(Flag::Bool(value)) => Ok(Some(value)),
+            Some(Flag::Text(text)) => super::parse_bool(&text)
+                .map(Some)
+                .ok_or_else(|| D::Error::custom(format!("invalid Encrypted {text:?}"))),
+        }
+    }
+}
+
+/// Write an element, merging typed attributes and children back into what was preserved
+fn write_element(xml: &mut String, default_tag: &str, preserved: &Preserved, typed: &[(&str, Option<String>)], children: &[String]) {
+    let tag = preserved.tag.as_deref().unwrap_or(default_tag);
+    let attribute = |name: &str, value: &str| match value.contains('"') {
+        true => format!(" {name}='{value}'"),
+        false => format!(r#" {name}="{value}""#),
+    };
+
+    *xml += "<";
+    *xml += tag;
+    let mut written = vec![false; typed.len()];
+    for (name, raw) in &preserved.attributes {
+        match raw {
+            Some(raw) => *xml += &attribute(name, raw),
+            None => {
+                let local = name.rsplit(':').next().unwrap_or(name);
+                if let Some(index) = typed.iter().position(|(typed_name, _)| *typed_name == local) {
+                    if let (false, Some(value)) = (written[index], &typed[index].1) {
+                        *xml += &attribute(name, &xml_escape(value));
+                    }
+                    written[index] = true;
+                }
+            },
+        }
+    }
+    for ((name, value), written) in typed.iter().zip(written) {
+        if let (false, Some(value)) = (written, value) {
+            *xml += &attribute(name, &xml_escape(value));
+        }
+    }
+
+    if children.is_empty() && preserved.content.is_empty() {
+        *xml += "/>";
+        return;
+    }
+
+    *xml += ">";
+    let mut content = preserved.content.iter().peekable();
+    for (index, child) in children.iter().enumerate() {
+        while let Some((_, raw)) = content.next_if(|(before, _)| *before <= index) {
+            *xml += raw;
+        }
+        *xml += child;
+    }
+    for (_, raw) in content {
+        *xml += raw;
+    }
+    *xml += &format!("</{tag}>");
+}
+
+/// Element whose start tag was just read
+struct Element<'a> {
+    start: BytesStart<'a>,
+    /// Position of the start tag in the document
+    offset: usize,
+    empty: bool,
+    /// Namespace bindings in scope before the element
+    scope: usize,
+}
+
+enum Node<'a> {
+    Element(Element<'a>),
+    /// Anything else, as written in the document
+    Raw(String),
+    End,
+    Eof,
+}
+
+struct Parser<'a> {
+    xml: &'a [u8],
+    reader: Reader<&'a [u8]>,
+    /// Prefix ("" for the default namespace) and namespace of every binding in scope
+    bindings: Vec<(String, String)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(xml: &'a [u8]) -> Self {
+        Self { xml, reader: Reader::from_reader(xml), bindings: vec![] }
+    }
+
+    fn next(&mut self) -> Result<Node<'a>, Error> {
+        let offset = self.reader.buffer_position();
+        let event = self.reader.read_event()
+            .map_err(|e| Error::decode(format!("Invalid blockmap XML at {offset:#x}"), e))?;
+
+        match event {
+            Event::Start(start) => self.enter(start, offset, false).map(Node::Element),
+            Event::Empty(start) => self.enter(start, offset, true).map(Node::Element),
+            Event::End(_) => Ok(Node::End),
+            Event::Eof => Ok(Node::Eof),
+            _ => utf8(&self.xml[offset..self.reader.buffer_position()]).map(Node::Raw),
+        }
+    }
+
+    fn enter(&mut self, start: BytesStart<'a>, offset: usize, empty: bool) -> Result<Element<'a>, Error> {
+        let scope = self.bindings.len();
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| Error::decode("Invalid blockmap attribute", e))?;
+            let key = utf8(attribute.key.as_ref())?;
+            let prefix = match key.split_once(':') {
+                Some(("xmlns", prefix)) => prefix,
+                _ if key == "xmlns" => "",
+                _ => continue,
+            };
+            self.bindings.push((prefix.to_owned(), utf8(&attribute.value)?));
+        }
+        Ok(Element { start, offset, empty, scope })
+    }
+
+    fn name(&self, element: &Element) -> Result<String, Error> {
+        utf8(element.start.name().as_ref())
+    }
+
+    /// Local part of `name` if it lies in a blockmap namespace, unprefixed attributes belong to their element
+    fn blockmap_local<'n>(&self, name: &'n str, attribute: bool) -> Option<&'n str> {
+        let (prefix, local) = name.split_once(':').unwrap_or(("", name));
+        if attribute && prefix.is_empty() {
+            return Some(local);
+        }
+
+        let (_, namespace) = self.bindings.iter().rev().find(|(bound, _)| bound == prefix)?;
+        (namespace == NAMESPACE || EXTENSION_NAMESPACES.contains(&namespace.as_str())).then_some(local)
+    }
+
+    /// Values of the typed attributes `names`, all others preserved raw
+    fn attributes(&self, element: &Element, names: &[&str]) -> Result<(Vec<Option<String>>, Preserved), Error> {
+        let mut typed = vec![None; names.len()];
+        let mut preserved = Preserved { tag: Some(self.name(element)?), ..Default::default() };
+
+        for attribute in element.start.attributes() {
+            let attribute = attribute.map_err(|e| Error::decode("Invalid blockmap attribute", e))?;
+            let key = utf8(attribute.key.as_ref())?;
+            let index = self.blockmap_local(&key, true)
+                .and_then(|local| names.iter().position(|name| *name == local));
+
+            match index {
+                Some(index) => {
+                    let value = attribute.unescape_value()
+                        .map_err(|e| Error::decode(format!("Invalid value of blockmap attribute {key}"), e))?;
+                    typed[index] = Some(value.into_owned());
+                    preserved.attributes.push((key, None));
+                },
+                None => {
+                    let raw = utf8(&attribute.value)?;
+                    preserved.attributes.push((key, Some(raw)));
+                },
+            }
+        }
+
+        Ok((typed, preserved))
+    }
+
+    /// Walk the children of `element`, typed ones (by local name) go to `child`, the rest is preserved
+    fn children<F>(&mut self, element: Element, preserved: &mut Preserved, names: &[&str], mut child: F) -> Result<(), Error>
+    where
+        F: FnMut(&mut Self, &str, Element<'a>) -> Result<(), Error>,
+    {
+        if element.empty {
+            self.bindings.truncate(element.scope);
+            return Ok(());
+        }
+
+        let mut typed = 0;
+        loop {
+            match self.next()? {
+                Node::End => break,
+                Node::Eof => return Err(Error::DecodeError("Blockmap ends inside an element".into())),
+                Node::Raw(raw) => preserved.content.push((typed, raw)),
+                Node::Element(element) => {
+                    let name = self.name(&element)?;
+                    match self.blockmap_local(&name, false).filter(|local| names.contains(local)) {
+                        Some(local) => {
+                            child(self, local, element)?;
+                            typed += 1;
+                        },
+                        None => {
+                            let raw = self.skip(element)?;
+                            preserved.content.push((typed, raw));
+                        },
+                    }
+                },
+            }
+        }
+
+        self.bindings.truncate(element.scope);
+        Ok(())
+    }
+
+    /// Consume an element without looking at it, returns it as written in the document
+    fn skip(&mut self, element: Element) -> Result<String, Error> {
+        if !element.empty {
+            self.reader.read_to_end(element.start.name())
+                .map_err(|e| Error::decode("Invalid blockmap XML", e))?;
+        }
+        self.bindings.truncate(element.scope);
+        utf8(&self.xml[element.offset..self.reader.buffer_position()])
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use xmlserde::{xml_deserialize_from_str, xml_serialize};
     const XML_DATA: &str = include_str!("../testdata/blockmap.xml");
     const XML_DATA_BIG: &str = include_str!("../testdata/blockmap_big.xml");
     const XML_DATA_SIZE0: &str = include_str!("../testdata/blockmap_size_0.xml");
-    const XML_ENCODING: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#;
 
     #[test]
     fn test_serialize() {
@@ -123,28 +778,30 @@ mod tests {
                     name: "AppxManifest.xml".into(),
                     id: format!("{:X}", 0),
                     size: 3337,
-                    encrypted: "false".into(),
+                    encrypted: Some(false),
                     filehash: Some(FileHash {
                         hash: "KNW6qWLAKsPZKbVF0DQc4gxxL0eAsCtFxUa+stWfKB8=".into(),
+                        ..Default::default()
                     }),
                     blocks: vec![
                         Block {
                             hash: "KNW6qWLAKsPZKbVF0DQc4gxxL0eAsCtFxUa+stWfKB8=".into(),
-                            size: Some(1236)
+                            size: Some(1236),
+                            ..Default::default()
                         }
-                    ]
+                    ],
+                    ..Default::default()
                 }
             ],
             ..Default::default()
         };
 
-        let ser = xml_serialize(map);
-        assert_eq!(XML_ENCODING.to_owned() + "\n" + &ser, XML_DATA);
+        assert_eq!(map.to_xml(), XML_DATA);
     }
 
     #[test]
     fn test_deserialize() {
-        let res = xml_deserialize_from_str::<AppxBlockMap>(XML_DATA)
+        let res = AppxBlockMap::from_xml(XML_DATA.as_bytes())
             .expect("Failed to deserialize XML");
 
         assert_eq!(res.hash_method, "http://www.w3.org/2001/04/xmlenc#sha256");
@@ -153,19 +810,213 @@ mod tests {
         assert_eq!(res.files.first().unwrap().id, "0");
         assert_eq!(res.files.first().unwrap().id(), 0);
         assert_eq!(res.files.first().unwrap().size, 3337);
-        assert_eq!(res.files.first().unwrap().encrypted, "false");
+        assert_eq!(res.files.first().unwrap().encrypted, Some(false));
         assert!(!res.files.first().unwrap().is_encrypted());
-        assert_eq!(res.files.first().unwrap().blocks.first().unwrap().hash_bytes(), hex::decode("28d5baa962c02ac3d929b545d0341ce20c712f4780b02b45c546beb2d59f281f").unwrap());
-        assert_eq!(res.files.first().unwrap().filehash.as_ref().unwrap().hash_bytes(), hex::decode("28d5baa962c02ac3d929b545d0341ce20c712f4780b02b45c546beb2d59f281f").unwrap());
+        assert_eq!(res.files.first().unwrap().blocks.first().unwrap().hash_bytes().unwrap(), hex::decode("28d5baa962c02ac3d929b545d0341ce20c712f4780b02b45c546beb2d59f281f").unwrap());
+        assert_eq!(res.files.first().unwrap().filehash.as_ref().unwrap().hash_bytes().unwrap(), hex::decode("28d5baa962c02ac3d929b545d0341ce20c712f4780b02b45c546beb2d59f281f").unwrap());
+    }
+
+    #[test]
+    fn test_encrypted_flag() {
+        let xml = |encrypted: &str| format!(r#"<BlockMap xmlns="http://schemas.microsoft.com/appx/2010/blockmap" HashMethod="http://www.w3.org/2001/04/xmlenc#sha256"><File Name="a" Size="0"{encrypted}/></BlockMap>"#);
+        for (attribute, expected) in [("", None), (r#" Encrypted="true""#, Some(true)), (r#" Encrypted="0""#, Some(false))] {
+            let map = AppxBlockMap::from_xml(xml(attribute).as_bytes()).unwrap();
+            assert_eq!(map.files[0].encrypted, expected, "{attribute}");
+            assert_eq!(map.files[0].is_encrypted(), expected == Some(true));
+        }
+        assert!(AppxBlockMap::from_xml(xml(r#" Encrypted="yes""#).as_bytes()).is_err());
+        assert!(!AppxBlockMap::from_xml(xml("").as_bytes()).unwrap().to_xml().contains("Encrypted"));
+    }
+
+    #[test]
+    fn test_file_ids() {
+        let xml = |ids: &[&str]| {
+            let files = ids.iter().enumerate()
+                .map(|(i, id)| format!(r#"<File Name="{i}" Size="0"{id}/>"#))
+                .collect::<String>();
+            format!(r#"<BlockMap xmlns="http://schemas.microsoft.com/appx/2010/blockmap" HashMethod="http://www.w3.org/2001/04/xmlenc#sha256">{files}</BlockMap>"#)
+        };
+        let ids = |map: &AppxBlockMap| map.files.iter().map(File::id).collect::<Vec<_>>();
+
+        let map = AppxBlockMap::from_xml(xml(&[r#" Id="A""#, r#" Id="1f""#]).as_bytes()).unwrap();
+        assert_eq!(ids(&map), [0xa, 0x1f]);
+        let map = AppxBlockMap::from_xml(xml(&["", "", ""]).as_bytes()).unwrap();
+        assert_eq!(ids(&map), [0, 1, 2]);
+        assert!(!map.to_xml().contains("Id="));
+
+        let mut map = AppxBlockMap::from_xml(xml(&[r#" Id="9""#, r#" Id="10""#]).as_bytes()).unwrap();
+        assert_eq!(ids(&map), [9, 0x10]);
+        map.detect_decimal_ids(|id| id == 9 || id == 10);
+        assert_eq!(ids(&map), [9, 10]);
+
+        assert!(AppxBlockMap::from_xml(xml(&[r#" Id="0""#, ""]).as_bytes()).is_err());
+        assert!(AppxBlockMap::from_xml(xml(&[r#" Id="0x1""#]).as_bytes()).is_err());
+        let file = File { name: "a".into(), id: "zz".into(), ..Default::default() };
+        assert!(file.try_id().is_err());
+        assert_eq!(file.id(), u64::MAX);
+    }
+
+    #[test]
+    fn test_metadata_files() {
+        let mut map = AppxBlockMap::default();
+        for name in ["Assets\\Logo.png", "appxmetadata\\AppxBundleManifest.xml", "AppxMetadata", "AppxMetadataFoo\\x.png"] {
+            map.files.push(File { name: name.into(), ..Default::default() });
+        }
+
+        let metadata: Vec<_> = map.metadata_files().map(|file| file.name.as_str()).collect();
+        assert_eq!(metadata, ["appxmetadata\\AppxBundleManifest.xml"]);
+        assert_eq!(map.manifest_file().unwrap().name, "appxmetadata\\AppxBundleManifest.xml");
+        assert!(map.find_file("assets\\logo.PNG").is_some());
+
+        map.files.insert(0, File { name: "appxMANIFEST.xml".into(), ..Default::default() });
+        map.files.retain(|file| !file.is_metadata());
+        map.files.rotate_left(1);
+        assert_eq!(map.manifest_file().unwrap().name, "appxMANIFEST.xml");
+
+        // Nothing named like a manifest, the first file is taken
+        map.files.retain(|file| file.name != "appxMANIFEST.xml");
+        assert_eq!(map.manifest_file().unwrap().name, "Assets\\Logo.png");
+    }
+
+    #[test]
+    fn test_find() {
+        let mut map = AppxBlockMap::default();
+        for name in ["AppxManifest.xml", "Assets\\Logo.png", "Assets\\Sub\\Icon.png"] {
+            map.files.push(File { name: name.into(), ..Default::default() });
+        }
+
+        for path in ["Assets\\Logo.png", "assets/logo.PNG", "./Assets//Logo.png", "\\Assets\\.\\Logo.png"] {
+            let (index, file) = map.find(path).unwrap();
+            assert_eq!((index, file.name.as_str()), (1, "Assets\\Logo.png"), "{path}");
+        }
+        assert_eq!(map.find("ASSETS/SUB/icon.png").unwrap().0, 2);
+        assert!(map.find("Assets").is_none());
+        assert!(map.find("Logo.png").is_none());
+        assert!(map.find("").is_none());
     }
 
     #[test]
     fn test_deserialize_big() {
-        xml_deserialize_from_str::<AppxBlockMap>(XML_DATA_BIG).expect("Failed to deserialize XML (big)");
+        let res = AppxBlockMap::from_xml(XML_DATA_BIG.as_bytes()).expect("Failed to deserialize XML (big)");
+        assert_eq!(res.to_xml(), XML_DATA_BIG);
     }
 
     #[test]
     fn test_deserialize_size0() {
-        xml_deserialize_from_str::<AppxBlockMap>(XML_DATA_SIZE0).expect("Failed to deserialize XML (size 0)");
+        let res = AppxBlockMap::from_xml(XML_DATA_SIZE0.as_bytes()).expect("Failed to deserialize XML (size 0)");
+        assert_eq!(res.to_xml(), XML_DATA_SIZE0);
+    }
+
+    /// Zip based packages bind the schema differently and carry attributes eappx does not know
+    #[test]
+    fn test_roundtrip_msix() {
+        let xml = concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\r\n",
+            "<BlockMap xmlns=\"http://schemas.microsoft.com/appx/2010/blockmap\" xmlns:b4=\"http://schemas.microsoft.com/appx/2021/blockmap\" IgnorableNamespaces=\"b4\" HashMethod=\"http://www.w3.org/2001/04/xmlenc#sha256\">\n",
+            "  <!-- generated -->\n",
+            "  <File Name=\"Assets\\Logo &amp; Icon.png\" Size=\"70000\" LfhSize=\"64\"><Block Hash=\"KNW6qWLAKsPZKbVF0DQc4gxxL0eAsCtFxUa+stWfKB8=\" Size=\"1236\"/>",
+            "<x:Unknown xmlns:x=\"urn:other\"><x:Child/></x:Unknown><Block Hash=\"/Oix3xoR/lLIv0FX/YTVoiEX1HodTok9MjAeU/ZYf+s=\"/>",
+            "<b4:FileHash Hash=\"KNW6qWLAKsPZKbVF0DQc4gxxL0eAsCtFxUa+stWfKB8=\"/></File>\n",
+            "</BlockMap>",
+        );
+
+        let mut res = AppxBlockMap::from_xml(xml.as_bytes()).unwrap();
+        let file = &res.files[0];
+        assert_eq!(file.name, "Assets\\Logo & Icon.png");
+        assert_eq!(file.size, 70000);
+        assert_eq!(file.blocks.len(), 2);
+        assert_eq!(file.blocks[1].size, None);
+        assert!(file.filehash.is_some());
+        assert_eq!(res.to_xml(), xml);
+
+        // Modified fields land where they were read
+        res.files[0].size = 1;
+        res.files[0].blocks.pop();
+        let modified = res.to_xml();
+        assert!(modified.contains(r#"Size="1" LfhSize="64"><Block "#));
+        assert!(modified.contains("</x:Unknown><b4:FileHash "));
+
+        // Unknown namespaces are not mistaken for the blockmap schema
+        let other = xml.replace("2021/blockmap", "2021/other");
+        assert!(AppxBlockMap::from_xml(other.as_bytes()).unwrap().files[0].filehash.is_none());
+        assert!(AppxBlockMap::from_xml(b"<Other/>").is_err());
+    }
+
+    #[test]
+    fn test_hash_algorithms() {
+        let data = b"payload";
+        let sha512 = Base64::encode_string(&HashAlgorithm::Sha512.digest(data));
+        let sha384 = Base64::encode_string(&HashAlgorithm::Sha384.digest(data));
+        let xml = format!(concat!(
+            "<BlockMap xmlns=\"http://schemas.microsoft.com/appx/2010/blockmap\" xmlns:b4=\"http://schemas.microsoft.com/appx/2021/blockmap\" HashMethod=\"http://www.w3.org/2001/04/xmlenc#sha256\">",
+            "<File Name=\"a.bin\" Size=\"7\"><Block Hash=\"KNW6qWLAKsPZKbVF0DQc4gxxL0eAsCtFxUa+stWfKB8=\"/>",
+            "<b4:FileHash Hash=\"{}\" b4:HashMethod=\"http://www.w3.org/2001/04/xmlenc#sha512\"/></File>",
+            "<File Name=\"b.bin\" Size=\"7\"><Block Hash=\"KNW6qWLAKsPZKbVF0DQc4gxxL0eAsCtFxUa+stWfKB8=\"/><b4:FileHash Hash=\"{}\"/></File>",
+            "</BlockMap>",
+        ), sha512, sha384);
+
+        let blockmap = AppxBlockMap::from_xml(xml.as_bytes()).unwrap();
+        assert_eq!(blockmap.hash_algorithm(), Some(HashAlgorithm::Sha256));
+        let [own_method, by_length] = [&blockmap.files[0], &blockmap.files[1]];
+        assert_eq!(own_method.filehash.as_ref().unwrap().hash_method.as_deref(), Some(HashAlgorithm::Sha512.uri()));
+        assert_eq!(blockmap.filehash_algorithm(own_method), HashAlgorithm::Sha512);
+        assert_eq!(blockmap.block_hash_algorithm(own_method), HashAlgorithm::Sha256);
+        // No method of its own, the blockmap one cannot have produced 48 bytes
+        assert_eq!(blockmap.filehash_algorithm(by_length), HashAlgorithm::Sha384);
+        assert_eq!(blockmap.to_xml().split_once('>').unwrap().1, xml.split_once('>').unwrap().1);
+
+        #[cfg(feature = "package")]
+        {
+            let index = crate::blockmap_index::BlockMapIndex::from_reader(xml.as_bytes()).unwrap();
+            assert_eq!(index.block_hash_algorithm(), HashAlgorithm::Sha256);
+            assert_eq!(index.files().iter().map(|file| file.filehash_algorithm).collect::<Vec<_>>(), [HashAlgorithm::Sha512, HashAlgorithm::Sha384]);
+        }
+    }
+
+    #[test]
+    fn test_block_hash() {
+        let plaintext = vec![0x5a; 1000];
+        assert_eq!(block_hash(&plaintext, 3, HashAlgorithm::Sha256, None), HashAlgorithm::Sha256.digest(&plaintext));
+
+        // Encrypted blocks are hashed padded to whole sectors and encrypted at their offset
+        let crypto = CryptoFileContext::new(&[7; 32], "TestApp_bst25f6z33ccc", "\\data.bin");
+        let mut stored = plaintext.clone();
+        stored_block(&mut stored, 3, Some(&crypto));
+        assert_eq!(stored.len(), 0x400);
+        let mut expected = plaintext.clone();
+        expected.resize(0x400, 0);
+        crypto.encrypt_at(&mut expected, 3 * utils::BLOCK_SIZE as u64);
+        assert_eq!(stored, expected);
+        assert_eq!(block_hash(&plaintext, 3, HashAlgorithm::Sha256, Some(&crypto)), HashAlgorithm::Sha256.digest(&expected));
+        assert_ne!(block_hash(&plaintext, 2, HashAlgorithm::Sha256, Some(&crypto)), HashAlgorithm::Sha256.digest(&expected));
+
+        let file = hash_file("data.bin", 0x1f, &mut vec![1u8; utils::BLOCK_SIZE + 1].as_slice(), HashAlgorithm::Sha512, None).unwrap();
+        assert_eq!((file.id.as_str(), file.size, file.blocks.len(), file.encrypted), ("1F", utils::BLOCK_SIZE as u64 + 1, 2, None));
+        assert_eq!(file.blocks[1].hash_bytes().unwrap(), HashAlgorithm::Sha512.digest(&[1]));
+        assert!(hash_file("empty", 0, &mut [].as_slice(), HashAlgorithm::Sha256, None).unwrap().blocks.is_empty());
+    }
+
+    /// Hashes recomputed from the plaintext match those of a package written by MakeAppx
+    #[cfg(feature = "package")]
+    #[test]
+    fn test_hash_file_matches_package() {
+        use std::io::Read;
+
+        let package: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+        let mut eappx = crate::EAppxFile::from_source(package).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        for name in ["AppxManifest.xml", "TestApp.dll", "TestApp.exe", "resources.pri"] {
+            let expected = eappx.blockmap.find_file(name).unwrap().clone();
+            let mut plaintext = vec![];
+            eappx.entry_reader(package, name).unwrap().read_to_end(&mut plaintext).unwrap();
+            let crypto = eappx.crypto_context_for(name).ok();
+
+            let file = hash_file(name, expected.id(), &mut plaintext.as_slice(), HashAlgorithm::Sha256, crypto.as_ref()).unwrap();
+            assert_eq!(file.encrypted.unwrap_or_default(), expected.is_encrypted(), "{name}");
+            assert_eq!(file.size, expected.size, "{name}");
+            assert_eq!(file.filehash_bytes().unwrap(), expected.filehash_bytes().unwrap(), "{name}");
+            assert_eq!(file.block_hashes().unwrap(), expected.block_hashes().unwrap(), "{name}");
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,407 @@
+//! Compact blockmap index for very large packages
+//!
+//! [`AppxBlockMap`](crate::blockmap::AppxBlockMap) keeps the whole document,
+//! including an owned `Block` with its base64 hash for every 64 KiB of
+//! payload. For packages with hundreds of thousands of blocks that costs a lot
+//! of memory and startup time. [`BlockMapIndex`] is built with a pull parser
+//! straight from the (decompressed) blockmap stream and only keeps what reading
+//! and verifying files needs: names, footer ids, sizes and the decoded hashes
+//! in one flat table.
+
+use std::{collections::HashMap, io::{BufRead, BufReader, Read}};
+
+use base64ct::{Base64, Encoding};
+use sha2::{Digest, Sha256};
+use xmlserde::quick_xml::{events::{BytesStart, Event}, name::ResolveResult, NsReader};
+
+use crate::{blockmap::{self, EXTENSION_NAMESPACES, NAMESPACE}, digest::HashAlgorithm, error::Error, limits::ResourceLimits, source::PackageSource, EAppxFile, EAppxFooter, EAppxHeader, FileInfo};
+
+/// File entry of a [`BlockMapIndex`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedFile {
+    pub name: String,
+    /// Footer table id, only in encrypted packages
+    pub id: Option<u64>,
+    /// Size, in bytes, of the file's uncompressed data.
+    pub size: u64,
+    pub encrypted: bool,
+    pub filehash: Option<Vec<u8>>,
+    /// Digest of `filehash`, its own `HashMethod` takes precedence over the blockmap one
+    pub filehash_algorithm: HashAlgorithm,
+    /// Range of the file's blocks in the index' hash table
+    first_block: usize,
+    block_count: usize,
+    /// Whether every block lists its stored size
+    sized: bool,
+}
+
+impl IndexedFile {
+    pub fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    /// Whether the file is a footprint file below `AppxMetadata`
+    pub fn is_metadata(&self) -> bool {
+        self.name.split_once(['\\', '/'])
+            .is_some_and(|(dir, name)| dir.eq_ignore_ascii_case(blockmap::METADATA_DIR) && !name.is_empty())
+    }
+}
+
+/// Files of a blockmap with their block hashes, without the document around them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockMapIndex {
+    hash_method: String,
+    files: Vec<IndexedFile>,
+    /// Length of a single decoded hash
+    hash_length: usize,
+    /// Block hashes of all files, back to back
+    hashes: Vec<u8>,
+    /// Stored block sizes, parallel to `hashes`, 0 where a block lists none
+    sizes: Vec<u32>,
+    /// Lowercase name -> position in `files`
+    names: HashMap<String, usize>,
+}
+
+impl BlockMapIndex {
+    /// Index a blockmap document, reading it front to back exactly once
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self, Error> {
+        Self::from_reader_with_limits(reader, &ResourceLimits::default())
+    }
+
+    pub fn from_reader_with_limits<R: BufRead>(reader: R, limits: &ResourceLimits) -> Result<Self, Error> {
+        let mut reader = NsReader::from_reader(reader);
+        let mut index = Self::default();
+        let mut buf = vec![];
+        // Kind of every open element, `None` for anything not indexed
+        let mut open: Vec<Option<&str>> = vec![];
+        let mut file: Option<IndexedFile> = None;
+
+        loop {
+            buf.clear();
+            let offset = reader.buffer_position();
+            let (namespace, event) = reader.read_resolved_event_into(&mut buf)
+                .map_err(|e| Error::decode(format!("Invalid blockmap XML at {offset:#x}"), e))?;
+            let in_blockmap = match namespace {
+                ResolveResult::Bound(namespace) => is_blockmap_namespace(namespace.as_ref()),
+                _ => false,
+            };
+
+            let (start, empty) = match event {
+                Event::Start(start) => (start, false),
+                Event::Empty(start) => (start, true),
+                Event::End(_) => {
+                    if let Some(Some("File")) = open.pop() {
+                        let mut file = file.take().unwrap();
+                        file.block_count = index.sizes.len() - file.first_block;
+                        index.push(file, limits)?;
+                    }
+                    continue;
+                },
+                Event::Eof => break,
+                _ => continue,
+            };
+
+            let local = local_name(&start)?;
+            let kind = match (open.last(), in_blockmap.then_some(local.as_str())) {
+                (None, Some("BlockMap")) => {
+                    index.hash_method = attribute(&reader, &start, "HashMethod")?
+                        .ok_or(Error::DecodeError("Blockmap has no HashMethod".into()))?;
+                    Some("BlockMap")
+                },
+                (None, _) => return Err(Error::DecodeError(format!("Unexpected blockmap root element {local}"))),
+                (Some(Some("BlockMap")), Some("File")) => {
+                    file = Some(index.parse_file(&reader, &start)?);
+                    Some("File")
+                },
+                (Some(Some("File")), Some(kind @ ("Block" | "FileHash"))) => {
+                    let file = file.as_mut().unwrap();
+                    let hash = attribute(&reader, &start, "Hash")?
+                        .ok_or(Error::DecodeError(format!("{kind} of {} without Hash", file.name)))?;
+                    let hash = Base64::decode_vec(&hash)
+                        .map_err(|_| Error::DecodeError(format!("Invalid {kind} Hash for {} in blockmap", file.name)))?;
+
+                    match kind {
+                        "Block" => {
+                            let size = parse_number::<u32>(attribute(&reader, &start, "Size")?, "Block Size", &file.name)?;
+                            file.sized &= size.is_some();
+                            index.push_block(&hash, size.unwrap_or_default(), &file.name)?;
+                        },
+                        _ => {
+                            let declared = attribute(&reader, &start, "HashMethod")?.as_deref()
+                                .and_then(HashAlgorithm::from_uri)
+                                .or(HashAlgorithm::from_uri(&index.hash_method));
+                            file.filehash_algorithm = HashAlgorithm::select(declared, hash.len());
+                            file.filehash = Some(hash);
+                        },
+                    }
+                    None
+                },
+                _ => None,
+            };
+
+            if !empty {
+                open.push(kind);
+            }
+            else if kind == Some("File") {
+                let mut file = file.take().unwrap();
+                file.block_count = 0;
+                index.push(file, limits)?;
+            }
+        }
+
+        if !open.is_empty() || index.hash_method.is_empty() {
+            return Err(Error::DecodeError("Blockmap has no root element".into()));
+        }
+
+        // Files without Id are addressed by their position, see `blockmap::IdScheme`
+        match index.files.iter().filter(|file| file.id.is_none()).count() {
+            0 => {},
+            missing if missing == index.files.len() => {
+                for (position, file) in index.files.iter_mut().enumerate() {
+                    file.id = Some(position as u64);
+                }
+            },
+            _ => return Err(Error::DecodeError("Blockmap lists files both with and without Id".into())),
+        }
+        Ok(index)
+    }
+
+    fn parse_file<R>(&self, reader: &NsReader<R>, start: &BytesStart) -> Result<IndexedFile, Error> {
+        let name = attribute(reader, start, "Name")?
+            .ok_or(Error::DecodeError("Blockmap file without Name".into()))?;
+        let size = parse_number(attribute(reader, start, "Size")?, "Size", &name)?
+            .ok_or(Error::DecodeError(format!("Blockmap file {name} without Size")))?;
+        let id = attribute(reader, start, "Id")?
+            .map(|id| u64::from_str_radix(&id, 16)
+                .map_err(|_| Error::DecodeError(format!("Invalid Id {id:?} for {name} in blockmap"))))
+            .transpose()?;
+        let encrypted = attribute(reader, start, "Encrypted")?.as_deref() == Some("true");
+
+        Ok(IndexedFile { name, id, size, encrypted, filehash: None, filehash_algorithm: HashAlgorithm::default(), first_block: self.sizes.len(), block_count: 0, sized: true })
+    }
+
+    fn push_block(&mut self, hash: &[u8], size: u32, file: &str) -> Result<(), Error> {
+        if self.hash_length == 0 {
+            self.hash_length = hash.len();
+        }
+        if hash.len() != self.hash_length {
+            return Err(Error::DecodeError(format!("Block hash of {file} has {} bytes, expected {}", hash.len(), self.hash_length)));
+        }
+
+        self.hashes.extend_from_slice(hash);
+        self.sizes.push(size);
+        Ok(())
+    }
+
+    fn push(&mut self, file: IndexedFile, limits: &ResourceLimits) -> Result<(), Error> {
+        limits.check_file_count("Blockmap file count", self.files.len() as u64 + 1)?;
+        self.names.entry(file.name.to_lowercase()).or_insert(self.files.len());
+        self.files.push(file);
+        Ok(())
+    }
+
+    pub fn hash_method(&self) -> &str {
+        &self.hash_method
+    }
+
+    /// Digest of the block hashes, by `HashMethod` and the length of the hashes
+    pub fn block_hash_algorithm(&self) -> HashAlgorithm {
+        HashAlgorithm::select(HashAlgorithm::from_uri(&self.hash_method), self.hash_length)
+    }
+
+    /// Files in blockmap order
+    pub fn files(&self) -> &[IndexedFile] {
+        &self.files
+    }
+
+    /// File by its package path, ignoring case like the platform does
+    pub fn find(&self, name: &str) -> Option<&IndexedFile> {
+        self.names.get(&name.to_lowercase())
+            .map(|&index| &self.files[index])
+    }
+
+    /// Total amount of blocks over all files
+    pub fn block_count(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Decoded block hashes of a file
+    pub fn block_hashes(&self, file: &IndexedFile) -> impl ExactSizeIterator<Item = &[u8]> {
+        let range = file.first_block * self.hash_length..(file.first_block + file.block_count) * self.hash_length;
+        self.hashes[range].chunks_exact(self.hash_length.max(1))
+    }
+
+    /// Stored size of every block, `None` unless all blocks list one (compressed files)
+    pub fn block_sizes(&self, file: &IndexedFile) -> Option<&[u32]> {
+        file.sized.then(|| &self.sizes[file.first_block..file.first_block + file.block_count])
+    }
+}
+
+/// Header, footers and blockmap index of a package, see [`BlockMapIndex`]
+///
+/// Opening is the streaming counterpart of [`EAppxFile::from_source`]: the
+/// blockmap is decompressed, hashed and indexed in a single pass and never held
+/// in memory as a whole.
+#[derive(Debug)]
+pub struct PackageIndex {
+    pub header: EAppxHeader,
+    pub file_len: u64,
+    pub footers: Vec<EAppxFooter>,
+    pub blockmap: BlockMapIndex,
+}
+
+impl PackageIndex {
+    pub fn from_source<P: PackageSource + ?Sized>(source: &P) -> Result<Self, Error> {
+        Self::from_source_with_limits(source, ResourceLimits::default())
+    }
+
+    pub fn from_source_with_limits<P: PackageSource + ?Sized>(source: &P, limits: ResourceLimits) -> Result<Self, Error> {
+        let header = EAppxFile::peek_header(source)?;
+        let (footers, file_len, blockmap_fileinfo) = EAppxFile::read_layout(source, &header, &limits)?;
+
+        let stored = source.range(blockmap_fileinfo.offset_to_file, blockmap_fileinfo.compressed_length);
+        let compressed = blockmap_fileinfo.compression_type == 0x1;
+        let mut hashing = HashingReader { inner: EAppxFile::create_reader(stored, false, compressed, None)?, hasher: Sha256::new(), length: 0 };
+        let blockmap = BlockMapIndex::from_reader_with_limits(BufReader::new(&mut hashing), &limits)?;
+
+        // The parser stops at the end of the document, anything behind it still counts
+        std::io::copy(&mut hashing, &mut std::io::sink())?;
+        if hashing.length != blockmap_fileinfo.uncompressed_length {
+            return Err(Error::DataError(format!(
+                "Blockmap has {:#x} bytes, footer declares {:#x}", hashing.length, blockmap_fileinfo.uncompressed_length
+            )));
+        }
+        if hashing.hasher.finalize().as_slice() != header.block_map_hash.as_slice() {
+            return Err(Error::DataError("Blockmap does not match the header hash".into()));
+        }
+
+        Ok(Self { header, file_len, footers, blockmap })
+    }
+
+    pub fn find_footer_for_file(&self, file_id: u64) -> Option<&EAppxFooter> {
+        self.footers.iter().find(|footer| footer.file_id == file_id)
+    }
+
+    /// Footer of an indexed file along with its hashes, ready for [`EAppxFile::read_file`]
+    pub fn fileinfo(&self, file: &IndexedFile) -> Result<FileInfo, Error> {
+        let id = file.id
+            .ok_or(Error::DataError(format!("{} has no footer id", file.name)))?;
+        let mut fileinfo: FileInfo = self.find_footer_for_file(id)
+            .ok_or(Error::DataError(format!("Could not get Footer info for {}", file.name)))?
+            .into();
+        fileinfo.filehash = file.filehash.clone();
+        fileinfo.block_hashes = Some(self.blockmap.block_hashes(file).map(<[u8]>::to_vec).collect());
+        fileinfo.filehash_algorithm = file.filehash_algorithm;
+        fileinfo.block_hash_algorithm = self.blockmap.block_hash_algorithm();
+        fileinfo.block_sizes = self.blockmap.block_sizes(file).map(<[u32]>::to_vec);
+        Ok(fileinfo)
+    }
+}
+
+/// Counts and hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+    length: u64,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.length += read as u64;
+        Ok(read)
+    }
+}
+
+fn is_blockmap_namespace(namespace: &[u8]) -> bool {
+    std::str::from_utf8(namespace)
+        .is_ok_and(|namespace| namespace == NAMESPACE || EXTENSION_NAMESPACES.contains(&namespace))
+}
+
+fn local_name(start: &BytesStart) -> Result<String, Error> {
+    String::from_utf8(start.local_name().as_ref().to_vec())
+        .map_err(|_| Error::DecodeError("Blockmap element name is not UTF-8".into()))
+}
+
+/// Value of an attribute by local name, unprefixed attributes belong to their element
+fn attribute<R>(reader: &NsReader<R>, start: &BytesStart, name: &str) -> Result<Option<String>, Error> {
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| Error::decode("Invalid blockmap attribute", e))?;
+        let (namespace, local) = reader.resolve_attribute(attribute.key);
+        let in_blockmap = match namespace {
+            ResolveResult::Unbound => true,
+            ResolveResult::Bound(namespace) => is_blockmap_namespace(namespace.as_ref()),
+            ResolveResult::Unknown(_) => false,
+        };
+
+        if in_blockmap && local.as_ref() == name.as_bytes() {
+            let value = attribute.unescape_value()
+                .map_err(|e| Error::decode(format!("Invalid value of blockmap attribute {name}"), e))?;
+            return Ok(Some(value.into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn parse_number<T: std::str::FromStr>(value: Option<String>, what: &str, file: &str) -> Result<Option<T>, Error> {
+    value.map(|value| value.parse()
+        .map_err(|_| Error::DecodeError(format!("Invalid {what} {value:?} for {file} in blockmap"))))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockmap::AppxBlockMap;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_index_matches_blockmap() {
+        let xml = include_bytes!("../testdata/blockmap_size_0.xml");
+        let full = AppxBlockMap::from_xml(xml).unwrap();
+        let index = BlockMapIndex::from_reader(&xml[..]).unwrap();
+
+        assert_eq!(index.hash_method(), full.hash_method());
+        assert_eq!(index.files().len(), full.files.len());
+        for (indexed, file) in index.files().iter().zip(&full.files) {
+            assert_eq!(indexed.name, file.name);
+            assert_eq!(indexed.id, Some(file.id()));
+            assert_eq!(indexed.size, file.size);
+            assert_eq!(indexed.filehash, file.filehash_bytes().unwrap());
+            assert_eq!(index.block_hashes(indexed).map(<[u8]>::to_vec).collect::<Vec<_>>(), file.block_hashes().unwrap());
+            assert_eq!(index.block_sizes(indexed).map(<[u32]>::to_vec), file.block_sizes());
+        }
+        assert_eq!(index.find("appxmanifest.xml").unwrap().name, "AppxManifest.xml");
+
+        // Without any Id files are addressed by position like in the full blockmap
+        let idless = String::from_utf8(xml.to_vec()).unwrap().replace(r#" Id="0""#, "").replace(r#" Id="1""#, "");
+        let full = AppxBlockMap::from_xml(idless.as_bytes()).unwrap();
+        let index = BlockMapIndex::from_reader(idless.as_bytes()).unwrap();
+        let ids = index.files().iter().map(|file| file.id).collect::<Vec<_>>();
+        assert_eq!(ids, full.files.iter().map(|file| Some(file.id())).collect::<Vec<_>>());
+        assert_eq!(ids, [Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn test_package_index() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let package = PackageIndex::from_source(EMSIX).unwrap();
+        assert_eq!(package.blockmap.files().len(), eappx.blockmap.files.len());
+
+        let file = package.blockmap.find(blockmap::APPX_MANIFEST).unwrap();
+        let mut manifest = vec![];
+        EAppxFile::read_file(EMSIX, &mut manifest, package.fileinfo(file).unwrap(), false, None, crate::verify::IntegrityPolicy::Full).unwrap();
+        assert_eq!(manifest.len() as u64, file.size);
+
+        // Corrupt the header hash
+        let mut data = EMSIX.to_vec();
+        let position = data.windows(package.header.block_map_hash.len())
+            .position(|window| window == package.header.block_map_hash)
+            .unwrap();
+        data[position] ^= 0xff;
+        let err = PackageIndex::from_source(&data).unwrap_err();
+        assert!(matches!(err, Error::DataError(ref message) if message.contains("header hash")));
+    }
+}
@@ -0,0 +1,366 @@
+//! Builders producing consistent headers and footers for the write path
+
+use std::io::Cursor;
+
+use binrw::BinWrite;
+
+use crate::{digest::HashAlgorithm, error::Error, keys::{self, KeyId}, EAppxFooter, EAppxHeader, EAppxMagic, HeaderVersion};
+
+pub const DEFAULT_CRYPTO_ALGO: &str = "XTS-AES";
+pub const DEFAULT_BLOCK_MAP_HASH_ALGO: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+pub const DEFAULT_KEY_LENGTH: u32 = 0x20;
+
+/// Payload stored outside of the footer table (signature, code integrity)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FootprintPayload {
+    pub offset: u64,
+    pub compressed: bool,
+    pub uncompressed_length: u32,
+    pub length: u32,
+}
+
+/// Builds an `EAppxHeader`, deriving all length fields and `header_size`
+#[derive(Debug, Clone)]
+pub struct EAppxHeaderBuilder {
+    magic: EAppxMagic,
+    version: HeaderVersion,
+    footer_offset: u64,
+    file_count: u64,
+    signature: FootprintPayload,
+    code_integrity: FootprintPayload,
+    block_map_file_id: u64,
+    key_length: u32,
+    key_ids: Vec<KeyId>,
+    package_full_name: String,
+    crypto_algo: String,
+    diffusion_support_enabled: bool,
+    block_map_hash_algo: String,
+    block_map_hash: Vec<u8>,
+}
+
+impl EAppxHeaderBuilder {
+    pub fn new(magic: EAppxMagic) -> Self {
+        Self {
+            magic,
+            version: HeaderVersion::V1_0,
+            footer_offset: 0,
+            file_count: 0,
+            signature: FootprintPayload::default(),
+            code_integrity: FootprintPayload::default(),
+            block_map_file_id: 0,
+            key_length: DEFAULT_KEY_LENGTH,
+            key_ids: vec![],
+            package_full_name: String::new(),
+            crypto_algo: DEFAULT_CRYPTO_ALGO.into(),
+            diffusion_support_enabled: false,
+            block_map_hash_algo: DEFAULT_BLOCK_MAP_HASH_ALGO.into(),
+            block_map_hash: vec![],
+        }
+    }
+
+    pub fn version(mut self, version: HeaderVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Location of the footer table and the amount of entries in it
+    pub fn footer_table(mut self, offset: u64, file_count: u64) -> Self {
+        self.footer_offset = offset;
+        self.file_count = file_count;
+        self
+    }
+
+    pub fn signature(mut self, signature: FootprintPayload) -> Self {
+        self.signature = signature;
+        self
+    }
+
+    pub fn code_integrity(mut self, code_integrity: FootprintPayload) -> Self {
+        self.code_integrity = code_integrity;
+        self
+    }
+
+    /// File id and hash of the uncompressed AppxBlockMap.xml
+    pub fn block_map(mut self, file_id: u64, hash: Vec<u8>) -> Self {
+        self.block_map_file_id = file_id;
+        self.block_map_hash = hash;
+        self
+    }
+
+    pub fn block_map_hash_algo(mut self, algo: &str) -> Self {
+        self.block_map_hash_algo = algo.into();
+        self
+    }
+
+    pub fn key_length(mut self, key_length: u32) -> Self {
+        self.key_length = key_length;
+        self
+    }
+
+    pub fn key_ids(mut self, key_ids: Vec<KeyId>) -> Self {
+        self.key_ids = key_ids;
+        self
+    }
+
+    pub fn package_full_name(mut self, package_full_name: &str) -> Self {
+        self.package_full_name = package_full_name.into();
+        self
+    }
+
+    /// Also sets the key length if the algorithm is known, [`Self::key_length`] overrides it
+    pub fn crypto_algo(mut self, crypto_algo: &str) -> Self {
+        self.crypto_algo = crypto_algo.into();
+        if let Some(key_length) = keys::key_length_for_algorithm(crypto_algo) {
+            self.key_length = key_length as u32;
+        }
+        self
+    }
+
+    pub fn diffusion_support(mut self, enabled: bool) -> Self {
+        self.diffusion_support_enabled = enabled;
+        self
+    }
+
+    /// Build the header, rejecting combinations Windows refuses
+    ///
+    /// Encrypted packages need the key length of their crypto algorithm,
+    /// `XTS-AES` unless known otherwise, and a blockmap hash has to be as long
+    /// as its algorithm's digests.
+    pub fn build(self) -> Result<EAppxHeader, Error> {
+        let to_u16 = |len: usize, what: &str| u16::try_from(len)
+            .map_err(|_| Error::DataError(format!("{what} too long for header ({len:#x})")));
+
+        to_u16(self.key_ids.len(), "Key id list")?;
+        let key_length = keys::key_length_for_algorithm(&self.crypto_algo).unwrap_or(keys::KEY_LENGTH);
+        if !self.key_ids.is_empty() && self.key_length as usize != key_length {
+            return Err(Error::DataError(format!("Key length {:#x} does not fit {}, expected {key_length:#x}", self.key_length, self.crypto_algo)));
+        }
+        if let Some(algorithm) = HashAlgorithm::from_uri(&self.block_map_hash_algo) {
+            if !self.block_map_hash.is_empty() && self.block_map_hash.len() != algorithm.output_len() {
+                return Err(Error::DataError(format!("Blockmap hash is {:#x} bytes, {algorithm} digests are {:#x}", self.block_map_hash.len(), algorithm.output_len())));
+            }
+        }
+        let footer_length = self.file_count.checked_mul(EAppxFooter::SIZE as u64)
+            .ok_or(Error::DataError(format!("Too many files for a footer table ({:#x})", self.file_count)))?;
+
+        let package_full_name: Vec<u16> = self.package_full_name.encode_utf16().collect();
+        let crypto_algo: Vec<u16> = self.crypto_algo.encode_utf16().collect();
+        let block_map_hash_algo: Vec<u16> = self.block_map_hash_algo.encode_utf16().collect();
+
+        let mut header = EAppxHeader {
+            magic: self.magic,
+            header_size: 0,
+            version: self.version.0,
+            footer_offset: self.footer_offset,
+            footer_length,
+            file_count: self.file_count,
+            signature_offset: self.signature.offset,
+            signature_compression_type: self.signature.compressed as u16,
+            signature_uncompressed_length: self.signature.uncompressed_length,
+            signature_length: self.signature.length,
+            code_integrity_offset: self.code_integrity.offset,
+            code_integrity_compression_type: self.code_integrity.compressed as u16,
+            code_integrity_uncompressed_length: self.code_integrity.uncompressed_length,
+            code_integrity_length: self.code_integrity.length,
+            block_map_file_id: self.block_map_file_id,
+            key_length: self.key_length,
+            key_ids: self.key_ids,
+            package_full_name,
+            crypto_algo,
+            diffusion_support_enabled: self.diffusion_support_enabled as u16,
+            block_map_hash_algo,
+            block_map_hash: self.block_map_hash,
+        };
+
+        header.header_size = to_u16(header.to_bytes()?.len(), "Header")?;
+        Ok(header)
+    }
+}
+
+impl EAppxHeader {
+    /// Serialize the header
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut cursor = Cursor::new(vec![]);
+        self.write(&mut cursor)
+            .map_err(|e| Error::data("Failed to serialize header", e))?;
+        Ok(cursor.into_inner())
+    }
+
+    /// Replace the package full name, `header_size` follows its length
+    ///
+    /// Offsets are not moved and the XTS tweaks of encrypted files derive from
+    /// the name, so files have to be written anew, see [`crate::rekey::rename`].
+    pub fn set_package_full_name(&mut self, package_full_name: &str) -> Result<(), Error> {
+        self.set_package_full_name_raw(package_full_name.encode_utf16().collect())
+    }
+
+    /// [`Self::set_package_full_name`] from UTF-16 code units, stored as given
+    pub fn set_package_full_name_raw(&mut self, package_full_name: Vec<u16>) -> Result<(), Error> {
+        if u16::try_from(package_full_name.len() * 2).is_err() {
+            return Err(Error::DataError(format!("Package full name too long for header ({:#x})", package_full_name.len() * 2)));
+        }
+
+        let header_size = self.to_bytes()?.len() - self.package_full_name.len() * 2 + package_full_name.len() * 2;
+        self.header_size = u16::try_from(header_size)
+            .map_err(|_| Error::DataError(format!("Header too long ({header_size:#x})")))?;
+        self.package_full_name = package_full_name;
+        Ok(())
+    }
+}
+
+impl EAppxFooter {
+    /// Serialize the footer entry
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut cursor = Cursor::new(vec![]);
+        self.write(&mut cursor)
+            .map_err(|e| Error::data("Failed to serialize footer", e))?;
+        Ok(cursor.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinRead;
+    use uuid::uuid;
+
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_header_roundtrip() {
+        let header = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+        let bytes = header.to_bytes().unwrap();
+
+        assert_eq!(bytes.len(), header.header_size as usize);
+        assert_eq!(bytes, &EMSIX[..bytes.len()]);
+    }
+
+    #[test]
+    fn test_footer_roundtrip() {
+        let header = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+        let mut cursor = Cursor::new(EMSIX);
+        cursor.set_position(header.footer_offset);
+
+        for _ in 0..header.file_count {
+            let offset = cursor.position() as usize;
+            let footer = EAppxFooter::read(&mut cursor).unwrap();
+            assert_eq!(footer.to_bytes().unwrap(), &EMSIX[offset..offset + EAppxFooter::SIZE as usize]);
+        }
+    }
+
+    #[test]
+    fn test_footer_new() {
+        let footer = EAppxFooter::new(0xa, 0x15f71, Some(0), false, 0x100e00, 0x100e00);
+        let parsed = EAppxFooter::read(&mut Cursor::new(&EMSIX[0x11bc24 + 10 * 0x28..])).unwrap();
+        assert_eq!(footer, parsed);
+    }
+
+    #[test]
+    fn test_header_builder() {
+        let parsed = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+
+        let header = EAppxHeaderBuilder::new(EAppxMagic::EXPH)
+            .footer_table(0x11bc24, 0xd)
+            .code_integrity(FootprintPayload {
+                offset: 0x11be2c,
+                compressed: true,
+                uncompressed_length: 0x2fc,
+                length: 0x19d,
+            })
+            .block_map(0xc, parsed.block_map_hash.clone())
+            .key_ids(vec![KeyId::Guid((
+                uuid!("ddafcf67-7b2c-086d-302b-8adac1bdd3a7"),
+                uuid!("7d53aeb8-5922-f062-b1d7-7e09f5a187a0"),
+            ))])
+            .package_full_name("8b18b0ca-7bac-4263-8be8-9a7a1292c90d_0.0.0.0_x64__nh20k94c8ngfj")
+            .build()
+            .unwrap();
+
+        assert_eq!(header, parsed);
+        assert_eq!(header.to_bytes().unwrap(), &EMSIX[..0x17e]);
+    }
+
+    #[test]
+    fn test_set_package_full_name() {
+        let mut header = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+        header.set_package_full_name("Contoso.Renamed_0.0.0.0_x64__8wekyb3d8bbwe").unwrap();
+        assert_eq!(header.app_name(), "Contoso.Renamed");
+        assert_eq!(header.header_size as usize, header.to_bytes().unwrap().len());
+
+        let parsed = EAppxHeader::read(&mut Cursor::new(header.to_bytes().unwrap())).unwrap();
+        assert_eq!(parsed, header);
+
+        // Unpaired surrogates are kept as stored
+        header.set_package_full_name_raw(vec![0xD800, 0x41]).unwrap();
+        assert_eq!(header.package_full_name_raw(), [0xD800, 0x41]);
+        assert_eq!(header.header_size as usize, header.to_bytes().unwrap().len());
+
+        assert!(header.set_package_full_name(&"a".repeat(0x8000)).is_err());
+        assert_eq!(header.package_full_name_raw(), [0xD800, 0x41]);
+    }
+
+    #[test]
+    fn test_header_builder_numeric_key_id() {
+        let header = EAppxHeaderBuilder::new(EAppxMagic::EXPH)
+            .key_ids(vec![KeyId::Numeric(1)])
+            .build()
+            .unwrap();
+        let parsed = EAppxHeader::read(&mut Cursor::new(header.to_bytes().unwrap())).unwrap();
+        assert_eq!(parsed.key_ids, vec![KeyId::Numeric(1)]);
+    }
+
+    #[test]
+    fn test_header_builder_key_length() {
+        let header = EAppxHeaderBuilder::new(EAppxMagic::EXPH).build().unwrap();
+        assert_eq!(header.key_length, 0x20);
+
+        let header = EAppxHeaderBuilder::new(EAppxMagic::EXPH).crypto_algo("XTS-AES-256").build().unwrap();
+        assert_eq!((header.crypto_algo(), header.key_length), ("XTS-AES-256".into(), 0x40));
+
+        let header = EAppxHeaderBuilder::new(EAppxMagic::EXPH).crypto_algo("Unknown").build().unwrap();
+        assert_eq!(header.key_length, 0x20);
+    }
+
+    #[test]
+    fn test_header_builder_invariants() {
+        let key_ids = vec![KeyId::Numeric(1), KeyId::Guid((uuid!("ddafcf67-7b2c-086d-302b-8adac1bdd3a7"), uuid!("7d53aeb8-5922-f062-b1d7-7e09f5a187a0")))];
+        let sha512 = HashAlgorithm::Sha512;
+
+        for (magic, name, key_ids) in [
+            (EAppxMagic::EXPH, "Contoso.Sample_1.0.0.0_x64__8wekyb3d8bbwe", key_ids.clone()),
+            (EAppxMagic::EXBH, "Contoso.Bundle_1.0.0.0_neutral_~_8wekyb3d8bbwe", vec![]),
+            (EAppxMagic::EXPH, "", vec![KeyId::Numeric(7)]),
+        ] {
+            let header = EAppxHeaderBuilder::new(magic)
+                .version(HeaderVersion::V1_0)
+                .footer_table(0x123456, 0x42)
+                .signature(FootprintPayload { offset: 0x200, compressed: true, uncompressed_length: 0x1000, length: 0x800 })
+                .block_map(0x41, vec![0xAB; sha512.output_len()])
+                .block_map_hash_algo(sha512.uri())
+                .key_ids(key_ids.clone())
+                .package_full_name(name)
+                .diffusion_support(true)
+                .build()
+                .unwrap();
+
+            let bytes = header.to_bytes().unwrap();
+            assert_eq!(bytes.len(), header.header_size as usize, "{name}");
+            assert_eq!(header.footer_length, 0x42 * EAppxFooter::SIZE as u64);
+            assert_eq!(EAppxHeader::read(&mut Cursor::new(&bytes)).unwrap(), header);
+        }
+
+        // Key length has to fit the crypto algorithm once there are keys
+        let encrypted = || EAppxHeaderBuilder::new(EAppxMagic::EXPH).key_ids(key_ids.clone());
+        assert!(encrypted().key_length(0x10).build().is_err());
+        assert!(encrypted().crypto_algo("XTS-AES-256").key_length(0x20).build().is_err());
+        assert!(encrypted().crypto_algo("XTS-AES-256").build().is_ok());
+        assert!(EAppxHeaderBuilder::new(EAppxMagic::EXPH).key_length(0x10).build().is_ok());
+
+        assert!(EAppxHeaderBuilder::new(EAppxMagic::EXPH).block_map(0, vec![0; 0x20]).block_map_hash_algo(sha512.uri()).build().is_err());
+        assert!(EAppxHeaderBuilder::new(EAppxMagic::EXPH).footer_table(0, u64::MAX).build().is_err());
+        assert!(EAppxHeaderBuilder::new(EAppxMagic::EXPH).key_ids(vec![KeyId::Numeric(1); 0x10000]).build().is_err());
+    }
+}
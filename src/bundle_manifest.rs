@@ -1,4 +1,4 @@
-use crate::manifest::{Identity, Packages, Package};
+use crate::{identity::PackageVersion, manifest::{Identity, Packages, Package}};
 use xmlserde_derives::{XmlDeserialize, XmlSerialize};
 
 fn default_ignorable_namespaces_bundle() -> String {
@@ -6,6 +6,7 @@ fn default_ignorable_namespaces_bundle() -> String {
 }
 
 #[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[xmlserde(root=b"Bundle")]
 #[xmlserde(with_ns = b"http://schemas.microsoft.com/appx/2013/bundle")]
 #[xmlserde(with_custom_ns(b"b4", b"http://schemas.microsoft.com/appx/2018/bundle"))]
@@ -32,6 +33,7 @@ pub struct AppxBundleManifest {
 /// 
 /// Reference: <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-optionalbundle>
 #[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OptionalBundle {
     /// Name
     #[xmlserde(name = b"Name", ty = "attr")]
@@ -41,7 +43,7 @@ pub struct OptionalBundle {
     pub publisher: String,
     /// Version
     #[xmlserde(name = b"Version", ty = "attr")]
-    pub version: Option<String>,
+    pub version: Option<PackageVersion>,
     /// File name
     #[xmlserde(name = b"FileName", ty = "attr")]
     pub filename: Option<String>,
@@ -50,13 +52,111 @@ pub struct OptionalBundle {
     pub packages: Vec<Package>,
 }
 
+/// Selects the packages of a bundle that apply to a device, an empty filter selects all
+///
+/// Architectures select among application packages, neutral ones always
+/// apply. Languages and scales select among resource packages, a resource
+/// package applies if it lists no value of that kind or one of the requested
+/// ones. Languages match case-insensitively, `en` also selects `en-US`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BundleFilter {
+    pub architectures: Vec<String>,
+    pub languages: Vec<String>,
+    pub scales: Vec<String>,
+}
+
+impl BundleFilter {
+    pub fn architectures(mut self, architectures: Vec<String>) -> Self {
+        self.architectures = architectures;
+        self
+    }
+
+    pub fn languages(mut self, languages: Vec<String>) -> Self {
+        self.languages = languages;
+        self
+    }
+
+    pub fn scales(mut self, scales: Vec<String>) -> Self {
+        self.scales = scales;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.architectures.is_empty() && self.languages.is_empty() && self.scales.is_empty()
+    }
+
+    /// Whether `package` is needed on a device matching the filter
+    ///
+    /// Examples
+    /// ```
+    /// use eappx::{bundle_manifest::BundleFilter, manifest::Package};
+    ///
+    /// let filter = BundleFilter::default().architectures(vec!["x64".into()]);
+    /// let package = Package { typ: "application".into(), arch: Some("arm64".into()), ..Default::default() };
+    /// assert!(!filter.applies(&package));
+    /// ```
+    pub fn applies(&self, package: &Package) -> bool {
+        if !package.is_resource() {
+            return match package.arch.as_deref() {
+                None | Some("neutral") => true,
+                Some(arch) => self.architectures.is_empty()
+                    || self.architectures.iter().any(|wanted| wanted.eq_ignore_ascii_case(arch)),
+            };
+        }
+
+        let language_matches = |language: &&str| self.languages.iter().any(|wanted| {
+            language.eq_ignore_ascii_case(wanted)
+                || language.split_once('-').is_some_and(|(primary, _)| primary.eq_ignore_ascii_case(wanted))
+        });
+        let scale_matches = |scale: &&str| self.scales.iter().any(|wanted| wanted == scale);
+
+        let languages = package.languages();
+        let scales = package.scales();
+        (self.languages.is_empty() || languages.is_empty() || languages.iter().any(language_matches))
+            && (self.scales.is_empty() || scales.is_empty() || scales.iter().any(scale_matches))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use xmlserde::xml_deserialize_from_str;
     const XML_DATA_BUNDLE: &str = include_str!("../testdata/manifest_bundle.xml");
+    #[allow(dead_code)]
     const XML_ENCODING: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
 
+    #[test]
+    fn test_bundle_filter() {
+        let package = |typ: &str, arch: Option<&str>, language: Option<&str>, scale: Option<&str>| Package {
+            typ: typ.into(),
+            arch: arch.map(Into::into),
+            resources: Some(crate::manifest::Resources {
+                resource: vec![crate::manifest::Resource {
+                    language: language.map(Into::into),
+                    scale: scale.map(Into::into),
+                    ..Default::default()
+                }],
+            }),
+            ..Default::default()
+        };
+        let filter = BundleFilter {
+            architectures: vec!["x64".into()],
+            languages: vec!["en".into()],
+            scales: vec!["200".into()],
+        };
+
+        assert!(BundleFilter::default().is_empty());
+        assert!(BundleFilter::default().applies(&package("application", Some("arm64"), None, None)));
+        assert!(filter.applies(&package("application", Some("X64"), Some("de-DE"), None)));
+        assert!(!filter.applies(&package("application", Some("arm64"), None, None)));
+        assert!(filter.applies(&package("application", Some("neutral"), None, None)));
+        assert!(filter.applies(&package("resource", None, Some("EN-US"), None)));
+        assert!(!filter.applies(&package("resource", None, Some("de-DE"), None)));
+        assert!(!filter.applies(&package("resource", None, None, Some("100"))));
+        assert!(filter.applies(&package("resource", None, None, Some("200"))));
+    }
+
     #[test]
     fn test_serialize_bundle() {
 
@@ -68,14 +168,18 @@ mod tests {
         assert_eq!(manifest.ignorable_namespaces, "b4 b5");
         assert_eq!(manifest.identity.name, "SomeGame");
         assert_eq!(manifest.identity.publisher, "CN=A68B71A2-D31D-464B-859A-CCB951AA6E69");
-        assert_eq!(manifest.identity.version, "1.5.54.2");
+        assert_eq!(manifest.identity.version, PackageVersion::new(1, 5, 54, 2));
         assert_eq!(manifest.identity.arch, None);
         assert_eq!(manifest.packages.package.len(), 1);
         assert_eq!(manifest.packages.package.first().unwrap().typ, "resource");
-        assert_eq!(manifest.packages.package.first().unwrap().version, "1.5.54.2");
+        assert_eq!(manifest.packages.package.first().unwrap().version, PackageVersion::new(1, 5, 54, 2));
         assert_eq!(manifest.packages.package.first().unwrap().resource_id, Some("split.scale-100".into()));
         assert_eq!(manifest.packages.package.first().unwrap().filename, "SomeGame_1.5.54.2_scale-100.msix");
         assert_eq!(manifest.packages.package.first().unwrap().offset, 392);
         assert_eq!(manifest.packages.package.first().unwrap().size, 576406);
+        assert!(manifest.packages.package.first().unwrap().is_resource());
+        assert_eq!(manifest.packages.package.first().unwrap().scales(), ["100"]);
+        assert_eq!(manifest.packages.package.first().unwrap().to_string(),
+            "SomeGame_1.5.54.2_scale-100.msix (type=resource, version=1.5.54.2, arch=neutral, resource-id=split.scale-100, scales=100) offset=0x188 size=0x8cb96");
     }
 }
\ No newline at end of file
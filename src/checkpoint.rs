@@ -0,0 +1,211 @@
+//! Verification that can be interrupted and picked up again
+//!
+//! Verifying packages of 100 GB and more does not always finish in one go.
+//! [`EAppxFile::verify_resumable`] records the files it is done with, and how
+//! far it got into a large one, in a [`VerificationState`] it hands out every
+//! few blocks to be saved. Passing the last saved state in again skips
+//! everything verified before.
+//!
+//! Large files are checked step by step against their block hashes. The hash
+//! over the whole file cannot be carried from one run to the next, so it is
+//! not checked for them.
+
+use crate::{blockmap, error::Error, source::PackageSource, verify::{FileReport, FileStatus, SizeMismatch, VerificationReport}, EAppxFile};
+
+/// Blocks verified between two checkpoints by default, 1 GiB of 64 KiB blocks
+pub const DEFAULT_CHECKPOINT_BLOCKS: usize = 16384;
+
+/// Progress of [`EAppxFile::verify_resumable`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct VerificationState {
+    /// Hex of the blockmap hash in the header, ties the progress to one package
+    pub block_map_hash: String,
+    /// Files verified completely, in blockmap order
+    pub files: Vec<FileReport>,
+    /// File verification stopped within
+    pub partial: Option<PartialFile>,
+}
+
+impl VerificationState {
+    /// Whether the file `file_id` was verified completely
+    pub fn is_done(&self, file_id: u64) -> bool {
+        self.files.iter().any(|file| file.file_id == file_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct PartialFile {
+    pub file_id: u64,
+    /// First block not verified yet
+    pub next_block: usize,
+    /// Indices of blocks whose hash did not match so far
+    pub failed_blocks: Vec<usize>,
+}
+
+impl EAppxFile {
+    /// [`EAppxFile::verify`], continuing from `state` and saving progress through `checkpoint`
+    ///
+    /// `checkpoint` is called with the updated state after every
+    /// `checkpoint_blocks` blocks and once all files are verified, an error it
+    /// returns stops the verification. Files of more blocks than that are
+    /// verified in steps of `checkpoint_blocks`, against their block hashes
+    /// only. A state recorded for another package is refused.
+    pub fn verify_resumable<P, F>(
+        &self,
+        source: &P,
+        state: &mut VerificationState,
+        checkpoint_blocks: usize,
+        mut checkpoint: F,
+    ) -> Result<VerificationReport, Error>
+    where
+        P: PackageSource + ?Sized,
+        F: FnMut(&VerificationState) -> Result<(), Error>,
+    {
+        let block_map_hash = hex::encode(&self.header.block_map_hash);
+        if state.block_map_hash.is_empty() {
+            state.block_map_hash = block_map_hash;
+        } else if state.block_map_hash != block_map_hash {
+            return Err(Error::DataError(format!("Verification state belongs to another package (blockmap hash {}, expected {block_map_hash})", state.block_map_hash)));
+        }
+        let checkpoint_blocks = checkpoint_blocks.max(1);
+
+        log::info!("Verifying blockmap files, {} of {} done before...", state.files.len(), self.blockmap.files.len());
+        let mut pending = 0;
+        for file in &self.blockmap.files {
+            if state.is_done(file.id()) {
+                continue;
+            }
+            let resumed = state.partial.take().filter(|partial| partial.file_id == file.id());
+            if (resumed.is_none() && file.blocks.len() <= checkpoint_blocks) || !self.locates_blocks(file) {
+                state.files.push(self.verify_blockmap_file(source, file));
+                pending += file.blocks.len().max(1);
+                if pending >= checkpoint_blocks {
+                    checkpoint(state)?;
+                    pending = 0;
+                }
+                continue;
+            }
+
+            let mut partial = resumed.unwrap_or(PartialFile { file_id: file.id(), next_block: 0, failed_blocks: vec![] });
+            if partial.next_block > 0 {
+                log::info!("Resuming {} at block {} of {}", file.name, partial.next_block, file.blocks.len());
+            }
+            let mut report = FileReport::new(&file.name, file.id());
+            while partial.next_block < file.blocks.len() {
+                let end = std::cmp::min(partial.next_block + checkpoint_blocks, file.blocks.len());
+                match self.verify_entry(source, &file.name, partial.next_block..end) {
+                    Ok(step) => partial.failed_blocks.extend(step.failed_blocks),
+                    Err(err) => {
+                        log::warn!("Failed to read {}: {err}", file.name);
+                        report.status = FileStatus::ReadError(err.to_string());
+                        break;
+                    },
+                }
+                pending += end - partial.next_block;
+                partial.next_block = end;
+                if pending >= checkpoint_blocks && end < file.blocks.len() {
+                    state.partial = Some(partial.clone());
+                    checkpoint(state)?;
+                    state.partial = None;
+                    pending = 0;
+                }
+            }
+
+            report.failed_blocks = partial.failed_blocks;
+            if let Some(footer) = self.find_footer_for_file(file.id()).filter(|footer| footer.uncompressed_length != file.size) {
+                log::warn!("BlockMap vs. Footer size mismatch (blockmap: {}, footer: {})", file.size, footer.uncompressed_length);
+                report.size_mismatch = Some(SizeMismatch { blockmap: file.size, footer: footer.uncompressed_length });
+            }
+            if report.status == FileStatus::Valid && (!report.failed_blocks.is_empty() || report.size_mismatch.is_some()) {
+                report.status = FileStatus::Corrupt;
+            }
+            state.files.push(report);
+            if pending >= checkpoint_blocks {
+                checkpoint(state)?;
+                pending = 0;
+            }
+        }
+        checkpoint(state)?;
+
+        Ok(VerificationReport {
+            files: state.files.clone(),
+            publisher_mismatch: self.check_publisher(source),
+            skipped_footprints: self.skipped_footprints(),
+        })
+    }
+
+    /// Whether blocks of `file` can be read on their own, see [`EAppxFile::verify_entry`]
+    fn locates_blocks(&self, file: &blockmap::File) -> bool {
+        self.find_footer_for_file(file.id())
+            .is_some_and(|footer| footer.compression_type != 0x1 || file.block_sizes().is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    fn open() -> EAppxFile {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        eappx
+    }
+
+    #[test]
+    fn test_verify_resumable() {
+        let eappx = open();
+        let expected = eappx.verify(EMSIX);
+        assert!(eappx.blockmap.files.iter().any(|file| file.blocks.len() > 1));
+
+        // Stop at the first checkpoint, then resume from what was saved there
+        let mut saved = None;
+        let mut state = VerificationState::default();
+        let result = eappx.verify_resumable(EMSIX, &mut state, 1, |state| {
+            saved = Some(state.clone());
+            Err(Error::DataError("interrupted".into()))
+        });
+        assert!(result.is_err());
+        let mut state = saved.unwrap();
+        assert!(state.files.len() < expected.files.len() || state.partial.is_some());
+
+        let mut checkpoints = 0;
+        let report = eappx.verify_resumable(EMSIX, &mut state, 1, |_| {
+            checkpoints += 1;
+            Ok(())
+        }).unwrap();
+        assert!(checkpoints > 0);
+        assert!(state.partial.is_none());
+        assert!(report.is_valid());
+        let files = |report: &VerificationReport| report.files.iter()
+            .map(|file| (file.file_id, file.status.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(files(&report), files(&expected));
+
+        // Nothing left to do once every file is done
+        let report = eappx.verify_resumable(EMSIX, &mut state, 1, |_| Ok(())).unwrap();
+        assert_eq!(files(&report), files(&expected));
+    }
+
+    #[test]
+    fn test_verify_resumable_other_package() {
+        let eappx = open();
+        let mut state = VerificationState { block_map_hash: "00".repeat(32), ..Default::default() };
+        assert!(eappx.verify_resumable(EMSIX, &mut state, 1, |_| Ok(())).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_state_roundtrip() {
+        let eappx = open();
+        let mut state = VerificationState::default();
+        eappx.verify_resumable(EMSIX, &mut state, DEFAULT_CHECKPOINT_BLOCKS, |_| Ok(())).unwrap();
+        let json = serde_json::to_string(&state).unwrap();
+        assert_eq!(serde_json::from_str::<VerificationState>(&json).unwrap(), state);
+    }
+}
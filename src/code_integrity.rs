@@ -0,0 +1,655 @@
+//! CodeIntegrity.cat generation for the PE files of a package
+//!
+//! The catalog is a PKCS#7 SignedData carrying a certificate trust list (CTL)
+//! with two members per PE file, keyed by its Authenticode SHA-1 and SHA-256
+//! hash. The SHA-256 member additionally carries `SpcIndirectDataContent`
+//! with the flat image hash. Page hashes are left out, MakeAppx does not
+//! write them either. Unsigned catalogs have no signer infos at all, signing
+//! attaches the same Authenticode attributes the package signature uses.
+//!
+//! [`Catalog`] reads the members back, [`EAppxFile::verify_code_integrity`]
+//! and [`EAppxFile::verify_extracted_code_integrity`] hash the PE files of a
+//! package or an extraction and tell which of them the catalog covers.
+
+use std::{io::Read, path::Path};
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    der::{
+        algorithm, attribute, bmp_string, der, integer, octet_string, oid, sequence, set_of, utc_time, DerReader, DerValue,
+        OID_SHA256, OID_SIGNED_DATA, OID_SPC_INDIRECT_DATA, TAG_BMP_STRING, TAG_CONTEXT_0, TAG_INTEGER, TAG_OCTET_STRING,
+        TAG_SEQUENCE, TAG_SET,
+    },
+    error::Error,
+    extraction_report::{ExtractionReport, ExtractionStatus},
+    source::PackageSource,
+    utils, EAppxFile,
+};
+
+const OID_CTL: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 10, 1];
+const OID_CATALOG_LIST: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 12, 1, 1];
+const OID_CATALOG_LIST_MEMBER_V2: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 12, 1, 3];
+const OID_CAT_NAMEVALUE: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 12, 2, 1];
+const OID_CAT_MEMBERINFO2: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 12, 2, 3];
+const OID_SPC_PE_IMAGE_DATA: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 15];
+
+/// `SpcPeImageData` as MakeAppx writes it: all flags, empty file link
+const PE_IMAGE_DATA: &[u8] = &[0x30, 0x0a, 0x03, 0x02, 0x05, 0xa0, 0xa0, 0x04, 0xa2, 0x02, 0x80, 0x00];
+/// `CAT_MEMBERINFO2` value, an empty `[0]`
+const MEMBER_INFO: &[u8] = &[0x80, 0x00];
+/// Name-value attribute every MakeAppx catalog carries
+const OS_ATTRIBUTE: (&str, u32, &str) = ("OSAttr", 0x10010001, "2:6.2");
+
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+const OPTIONAL_HEADER_PE32: u16 = 0x10b;
+const OPTIONAL_HEADER_PE32_PLUS: u16 = 0x20b;
+/// Data directory holding the certificate table
+const CERTIFICATE_DIRECTORY: usize = 4;
+
+/// Authenticode image hashes of a PE file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeHashes {
+    pub sha1: Vec<u8>,
+    pub sha256: Vec<u8>,
+}
+
+/// Streaming Authenticode hash of a PE image
+///
+/// Everything but the checksum, the certificate table directory entry and the
+/// certificate table itself gets hashed, in file order.
+pub struct AuthenticodeHasher {
+    sha1: Sha1,
+    sha256: Sha256,
+    position: u64,
+    /// Checksum and certificate directory entry
+    skip: [(u64, u64); 2],
+    /// Start of the certificate table, nothing after it is hashed
+    end: u64,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+impl AuthenticodeHasher {
+    /// Hasher for the file starting with `header`, `None` if it is no PE image
+    ///
+    /// `header` has to reach past the data directories, the first block of a
+    /// file always does for regular images.
+    pub fn new(header: &[u8]) -> Option<Self> {
+        if !header.starts_with(b"MZ") {
+            return None;
+        }
+        let pe_offset = read_u32(header, 0x3c)? as usize;
+        if header.get(pe_offset..pe_offset + 4)? != PE_SIGNATURE {
+            return None;
+        }
+
+        let optional_header = pe_offset + 24;
+        let (directories, directory_count) = match read_u16(header, optional_header)? {
+            OPTIONAL_HEADER_PE32 => (optional_header + 96, read_u32(header, optional_header + 92)?),
+            OPTIONAL_HEADER_PE32_PLUS => (optional_header + 112, read_u32(header, optional_header + 108)?),
+            _ => return None,
+        };
+        if directory_count as usize <= CERTIFICATE_DIRECTORY {
+            return None;
+        }
+
+        let checksum = optional_header as u64 + 64;
+        let certificate_entry = directories + CERTIFICATE_DIRECTORY * 8;
+        let certificate_offset = read_u32(header, certificate_entry)?;
+        let certificate_size = read_u32(header, certificate_entry + 4)?;
+        let end = match (certificate_offset, certificate_size) {
+            (0, _) | (_, 0) => u64::MAX,
+            (offset, _) => offset as u64,
+        };
+
+        Some(Self {
+            sha1: Sha1::new(),
+            sha256: Sha256::new(),
+            position: 0,
+            skip: [(checksum, checksum + 4), (certificate_entry as u64, certificate_entry as u64 + 8)],
+            end,
+        })
+    }
+
+    fn hash(&mut self, data: &[u8]) {
+        self.sha1.update(data);
+        self.sha256.update(data);
+    }
+
+    /// Feed the next chunk of the file
+    pub fn update(&mut self, data: &[u8]) {
+        let start = self.position;
+        self.position += data.len() as u64;
+        let end = self.end.clamp(start, self.position);
+
+        let mut from = start;
+        for (skip_start, skip_end) in self.skip {
+            let until = skip_start.clamp(from, end);
+            self.hash(&data[(from - start) as usize..(until - start) as usize]);
+            from = from.max(skip_end.min(end));
+        }
+        self.hash(&data[(from - start) as usize..(end - start) as usize]);
+    }
+
+    pub fn finalize(self) -> PeHashes {
+        PeHashes {
+            sha1: self.sha1.finalize().to_vec(),
+            sha256: self.sha256.finalize().to_vec(),
+        }
+    }
+}
+
+/// Authenticode hashes of a whole file, `None` if it is no PE image
+pub fn pe_hashes<R: Read>(reader: &mut R) -> Result<Option<PeHashes>, Error> {
+    let mut block = Vec::with_capacity(utils::BLOCK_SIZE);
+    reader.take(utils::BLOCK_SIZE as u64).read_to_end(&mut block)?;
+    let Some(mut hasher) = AuthenticodeHasher::new(&block) else {
+        return Ok(None);
+    };
+
+    hasher.update(&block);
+    let mut buf = vec![0u8; utils::BLOCK_SIZE];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => break,
+            read => hasher.update(&buf[..read]),
+        }
+    }
+    Ok(Some(hasher.finalize()))
+}
+
+/// Catalog collecting the PE files of a package
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeIntegrityCatalog {
+    members: Vec<PeHashes>,
+    deterministic: bool,
+}
+
+impl CodeIntegrityCatalog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Derive list identifier and timestamp from the members instead of a
+    /// random UUID and the current time, so equal members give equal catalogs
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn add(&mut self, hashes: PeHashes) {
+        self.members.push(hashes);
+    }
+
+    /// Hash a whole file, returns whether it was a PE image and got added
+    pub fn add_file<R: Read>(&mut self, reader: &mut R) -> Result<bool, Error> {
+        match pe_hashes(reader)? {
+            Some(hashes) => {
+                self.add(hashes);
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Content of the certificate trust list, without its SEQUENCE header
+    fn trust_list(&self, identifier: &[u8; 16], timestamp: u64) -> Vec<u8> {
+        let member_info = attribute(OID_CAT_MEMBERINFO2, MEMBER_INFO);
+
+        let mut members = vec![];
+        for hashes in &self.members {
+            members.push((hashes.sha1.clone(), set_of(vec![member_info.clone()])));
+
+            let indirect_data = sequence(&[
+                &sequence(&[&oid(OID_SPC_PE_IMAGE_DATA), PE_IMAGE_DATA]),
+                &sequence(&[&algorithm(OID_SHA256), &octet_string(&hashes.sha256)]),
+            ]);
+            let attributes = set_of(vec![member_info.clone(), attribute(OID_SPC_INDIRECT_DATA, &indirect_data)]);
+            members.push((hashes.sha256.clone(), attributes));
+        }
+        // Members are ordered by their tag, the hash
+        members.sort();
+        let members: Vec<u8> = members.iter()
+            .flat_map(|(hash, attributes)| sequence(&[&octet_string(hash), attributes]))
+            .collect();
+
+        let (name, flags, value) = OS_ATTRIBUTE;
+        let value: Vec<u8> = value.encode_utf16().chain([0]).flat_map(u16::to_le_bytes).collect();
+        let name_value = sequence(&[&bmp_string(name), &integer(&flags.to_be_bytes()), &octet_string(&value)]);
+        let extensions = sequence(&[&sequence(&[&oid(OID_CAT_NAMEVALUE), &octet_string(&name_value)])]);
+
+        [
+            sequence(&[&oid(OID_CATALOG_LIST)]),
+            octet_string(identifier),
+            utc_time(timestamp),
+            algorithm(OID_CATALOG_LIST_MEMBER_V2),
+            der(TAG_SEQUENCE, &members),
+            der(TAG_CONTEXT_0, &extensions),
+        ].concat()
+    }
+
+    fn unsigned_der(&self, identifier: &[u8; 16], timestamp: u64) -> Vec<u8> {
+        let signed_data = sequence(&[
+            &integer(&[1]),
+            &der(TAG_SET, &[]),
+            &sequence(&[&oid(OID_CTL), &der(TAG_CONTEXT_0, &der(TAG_SEQUENCE, &self.trust_list(identifier, timestamp)))]),
+            &der(TAG_SET, &[]),
+        ]);
+        sequence(&[&oid(OID_SIGNED_DATA), &der(TAG_CONTEXT_0, &signed_data)])
+    }
+
+    /// Fresh list identifier and the current time, like every MakeAppx run,
+    /// or a hash of the members and the Unix epoch when deterministic
+    fn identity(&self) -> ([u8; 16], u64) {
+        if self.deterministic {
+            let mut hashes: Vec<&[u8]> = self.members.iter().map(|hashes| hashes.sha256.as_slice()).collect();
+            hashes.sort();
+            let digest = hashes.into_iter().fold(Sha256::new(), |hasher, hash| hasher.chain_update(hash)).finalize();
+            return (digest[..16].try_into().unwrap(), 0);
+        }
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        (uuid::Uuid::new_v4().to_bytes_le(), timestamp)
+    }
+
+    /// Unsigned catalog, as stored in encrypted packages
+    pub fn to_der(&self) -> Vec<u8> {
+        let (identifier, timestamp) = self.identity();
+        self.unsigned_der(&identifier, timestamp)
+    }
+
+    /// Catalog signed by `identity`, as stored in signed packages
+    #[cfg(feature = "signing")]
+    pub fn to_signed_der(&self, identity: &crate::signing::SigningIdentity) -> Result<Vec<u8>, Error> {
+        let (identifier, timestamp) = self.identity();
+        identity.signed_data(OID_CTL, &self.trust_list(&identifier, timestamp))
+    }
+}
+
+/// Member of a parsed catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogMember {
+    /// SHA-1 or SHA-256 Authenticode hash
+    pub hash: Vec<u8>,
+    /// File name from a `File` name-value attribute, MakeAppx writes none
+    pub file: Option<String>,
+}
+
+/// Members of an existing catalog, e.g. CodeIntegrity.cat
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalog {
+    pub members: Vec<CatalogMember>,
+}
+
+impl Catalog {
+    /// Parse a signed or unsigned catalog, signatures are not checked
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let mut content_info = DerReader::new(data).expect(TAG_SEQUENCE)?.children();
+        if content_info.next()?.oid()? != OID_SIGNED_DATA {
+            return Err(Error::DecodeError("Catalog is not PKCS#7 SignedData".into()));
+        }
+        let mut signed_data = content_info.expect(TAG_CONTEXT_0)?.children().expect(TAG_SEQUENCE)?.children();
+        signed_data.expect(TAG_INTEGER)?;
+        signed_data.expect(TAG_SET)?;
+        let mut content = signed_data.expect(TAG_SEQUENCE)?.children();
+        if content.next()?.oid()? != OID_CTL {
+            return Err(Error::DecodeError("Catalog does not carry a certificate trust list".into()));
+        }
+        let Some(trust_list) = content.optional(TAG_CONTEXT_0)? else {
+            return Ok(Self::default());
+        };
+
+        // Optional fields come before the subjects, they are the sequence whose entries start with their hash
+        let mut fields = trust_list.children().expect(TAG_SEQUENCE)?.children();
+        let mut members = vec![];
+        while !fields.is_empty() {
+            let field = fields.next()?;
+            if field.tag != TAG_SEQUENCE || !is_subject_list(&field) {
+                continue;
+            }
+            let mut subjects = field.children();
+            while !subjects.is_empty() {
+                members.push(parse_member(subjects.next()?)?);
+            }
+        }
+        Ok(Self { members })
+    }
+
+    /// Whether either hash of a PE file is a member
+    pub fn contains(&self, hashes: &PeHashes) -> bool {
+        self.members.iter().any(|member| member.hash == hashes.sha1 || member.hash == hashes.sha256)
+    }
+
+    /// Coverage of the PE file `name` (package path) with `hashes`
+    pub fn coverage(&self, name: &str, hashes: &PeHashes) -> Coverage {
+        let file_name = name.rsplit(['\\', '/']).next().unwrap_or(name);
+        match self.contains(hashes) {
+            true => Coverage::Covered,
+            false if self.members.iter().any(|member| member.file.as_deref().is_some_and(|file| file.eq_ignore_ascii_case(file_name))) =>
+                Coverage::Mismatched,
+            false => Coverage::Uncovered,
+        }
+    }
+
+    /// Report over `binaries`, listing the members none of them matches
+    pub fn report(&self, binaries: Vec<BinaryCoverage>, skipped: Vec<String>) -> CoverageReport {
+        let unmatched = self.members.iter()
+            .filter(|member| !binaries.iter().any(|binary| member.hash == binary.hashes.sha1 || member.hash == binary.hashes.sha256))
+            .map(|member| member.hash.clone())
+            .collect();
+        CoverageReport { binaries, unmatched, skipped }
+    }
+}
+
+/// Whether `field` is a (possibly empty) sequence of `SEQUENCE { OCTET STRING, ... }`
+fn is_subject_list(field: &DerValue<'_>) -> bool {
+    let mut subjects = field.children();
+    while !subjects.is_empty() {
+        match subjects.next() {
+            Ok(subject) if subject.tag == TAG_SEQUENCE && subject.content.first() == Some(&TAG_OCTET_STRING) => {},
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// NUL terminated UTF-16LE text as catalogs store it
+fn utf16_text(data: &[u8]) -> String {
+    let units = data.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect::<Vec<_>>();
+    String::from_utf16_lossy(&units).trim_end_matches('\0').to_owned()
+}
+
+/// Hash and file name of a catalog subject
+fn parse_member(subject: DerValue<'_>) -> Result<CatalogMember, Error> {
+    let mut fields = subject.children();
+    let identifier = fields.expect(TAG_OCTET_STRING)?.content;
+    // Catalogs of signtool store the hash as UTF-16 hex text
+    let hash = hex::decode(utf16_text(identifier)).ok()
+        .filter(|hash| identifier.len() % 2 == 0 && [20, 32].contains(&hash.len()))
+        .unwrap_or_else(|| identifier.to_vec());
+
+    let mut file = None;
+    if let Some(attributes) = fields.optional(TAG_SET)? {
+        let mut attributes = attributes.children();
+        while !attributes.is_empty() {
+            let mut attribute = attributes.expect(TAG_SEQUENCE)?.children();
+            if attribute.next()?.oid()? != OID_CAT_NAMEVALUE {
+                continue;
+            }
+            let mut values = attribute.expect(TAG_SET)?.children();
+            while !values.is_empty() {
+                // Some tools wrap the value in an OCTET STRING
+                let value = match values.next()? {
+                    value if value.tag == TAG_OCTET_STRING => DerReader::new(value.content).expect(TAG_SEQUENCE)?,
+                    value => value,
+                };
+                let mut name_value = value.children();
+                let name = name_value.expect(TAG_BMP_STRING)?.string()?;
+                name_value.expect(TAG_INTEGER)?;
+                let data = name_value.expect(TAG_OCTET_STRING)?.content;
+                if name.eq_ignore_ascii_case("File") {
+                    file = Some(utf16_text(data));
+                }
+            }
+        }
+    }
+    Ok(CatalogMember { hash, file })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coverage {
+    /// The catalog lists the file's hash
+    Covered,
+    /// The catalog names the file, with another hash
+    Mismatched,
+    /// The catalog neither lists its hash nor names it
+    Uncovered,
+}
+
+impl std::fmt::Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Coverage::Covered => "covered",
+            Coverage::Mismatched => "mismatched",
+            Coverage::Uncovered => "uncovered",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BinaryCoverage {
+    /// Path inside the package
+    pub name: String,
+    pub hashes: PeHashes,
+    pub coverage: Coverage,
+}
+
+/// Which PE files a catalog covers
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CoverageReport {
+    pub binaries: Vec<BinaryCoverage>,
+    /// Member hashes matching no binary, left over when binaries were replaced
+    pub unmatched: Vec<Vec<u8>>,
+    /// Encrypted files that could not be checked, their key is not loaded
+    pub skipped: Vec<String>,
+}
+
+impl CoverageReport {
+    /// Binaries the catalog does not cover, mismatched or not
+    pub fn failed(&self) -> impl Iterator<Item = &BinaryCoverage> {
+        self.binaries.iter().filter(|binary| binary.coverage != Coverage::Covered)
+    }
+
+    /// Whether the catalog covers every binary
+    pub fn is_valid(&self) -> bool {
+        self.failed().next().is_none()
+    }
+}
+
+impl std::fmt::Display for CoverageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for binary in &self.binaries {
+            writeln!(f, "* {}: {} (sha256: {})", binary.name, binary.coverage, hex::encode(&binary.hashes.sha256))?;
+        }
+        if !self.unmatched.is_empty() {
+            writeln!(f, "{} catalog member(s) match no binary, uncovered binaries may have been replaced", self.unmatched.len())?;
+        }
+        if !self.skipped.is_empty() {
+            writeln!(f, "{} encrypted file(s) not checked without key: {}", self.skipped.len(), self.skipped.join(", "))?;
+        }
+        writeln!(f, "Checked {} binaries, {} not covered => {}",
+            self.binaries.len(), self.failed().count(), if self.is_valid() { "PASS" } else { "FAIL" })
+    }
+}
+
+impl EAppxFile {
+    /// CodeIntegrity.cat of the package, parsed, `None` if there is none
+    pub fn code_integrity_catalog<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Option<Catalog>, Error> {
+        self.read_code_integrity(source)?.map(|data| Catalog::parse(&data)).transpose()
+    }
+
+    /// Check the PE files of the package against its catalog, `None` if it has none
+    ///
+    /// Encrypted files whose key is not loaded are listed as skipped.
+    pub fn verify_code_integrity<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Option<CoverageReport>, Error> {
+        let Some(catalog) = self.code_integrity_catalog(source)? else {
+            return Ok(None);
+        };
+
+        let (mut binaries, mut skipped) = (vec![], vec![]);
+        for file in self.blockmap.files.iter().filter(|file| !file.is_metadata()) {
+            let missing_key = self.find_footer_for_file(file.id())
+                .filter(|footer| self.is_footer_encrypted(footer))
+                .is_some_and(|footer| !self.header.key_ids.get(footer.key_id_index as usize).is_some_and(|key_id| self.keys.contains_key(key_id)));
+            if missing_key {
+                skipped.push(file.name.clone());
+                continue;
+            }
+            if let Some(hashes) = pe_hashes(&mut self.file_reader(source, file)?)? {
+                let coverage = catalog.coverage(&file.name, &hashes);
+                binaries.push(BinaryCoverage { name: file.name.clone(), hashes, coverage });
+            }
+        }
+        Ok(Some(catalog.report(binaries, skipped)))
+    }
+
+    /// Check the files `report` wrote below `directory` against the package's catalog, `None` if it has none
+    ///
+    /// Run after extraction, catches binaries modified on disk since.
+    pub fn verify_extracted_code_integrity<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        directory: &Path,
+        report: &ExtractionReport,
+    ) -> Result<Option<CoverageReport>, Error> {
+        let Some(catalog) = self.code_integrity_catalog(source)? else {
+            return Ok(None);
+        };
+
+        let (mut binaries, mut skipped) = (vec![], vec![]);
+        for file in &report.files {
+            match file.status {
+                ExtractionStatus::SkippedKeyMissing | ExtractionStatus::Ciphertext => skipped.push(file.package_path.clone()),
+                _ => {
+                    let path = utils::long_path(&directory.join(&file.path))?;
+                    let Ok(local) = std::fs::File::open(&path) else {
+                        continue;
+                    };
+                    if let Some(hashes) = pe_hashes(&mut std::io::BufReader::new(local))? {
+                        let coverage = catalog.coverage(&file.package_path, &hashes);
+                        binaries.push(BinaryCoverage { name: file.package_path.clone(), hashes, coverage });
+                    }
+                },
+            }
+        }
+        Ok(Some(catalog.report(binaries, skipped)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_authenticode_hashes() {
+        let mut header = vec![0u8; 0x200];
+        header[..2].copy_from_slice(b"MZ");
+        header[0x3c] = 0x80;
+        header[0x80..0x84].copy_from_slice(PE_SIGNATURE);
+        header[0x98..0x9a].copy_from_slice(&OPTIONAL_HEADER_PE32_PLUS.to_le_bytes());
+        header[0x98 + 108] = 16;
+        // Checksum and certificate table (at 0x180) are not hashed
+        header[0x98 + 64] = 0xff;
+        let certificate_entry = 0x98 + 112 + 4 * 8;
+        header[certificate_entry..certificate_entry + 2].copy_from_slice(&0x180u16.to_le_bytes());
+        header[certificate_entry + 2 + 4] = 0x10;
+        header[certificate_entry + 8] = 0xee;
+        header[0x180..].fill(0xcc);
+
+        let mut expected = header[..0x180].to_vec();
+        expected.drain(certificate_entry..certificate_entry + 8);
+        expected.drain(0x98 + 64..0x98 + 68);
+
+        // Any chunking gives the same result
+        for chunk_size in [1, 7, 0x200] {
+            let mut hasher = AuthenticodeHasher::new(&header).unwrap();
+            header.chunks(chunk_size).for_each(|chunk| hasher.update(chunk));
+            assert_eq!(hasher.finalize().sha256, Sha256::digest(&expected).to_vec());
+        }
+
+        assert!(AuthenticodeHasher::new(b"MZ not a pe file").is_none());
+        assert!(AuthenticodeHasher::new(&[0u8; 0x200]).is_none());
+    }
+
+    /// Same catalog MakeAppx generated for the test package, given its identifier and time
+    #[test]
+    fn test_catalog_matches_makeappx() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let expected = eappx.read_code_integrity(EMSIX).unwrap().unwrap();
+
+        let mut catalog = CodeIntegrityCatalog::new();
+        for file in &eappx.blockmap.files {
+            let is_pe = catalog.add_file(&mut eappx.file_reader(EMSIX, file).unwrap()).unwrap();
+            assert_eq!(is_pe, [".dll", ".exe"].iter().any(|ext| file.name.ends_with(ext)), "{}", file.name);
+        }
+        assert_eq!(catalog.len(), 3);
+
+        let identifier = hex::decode("d312ba943603a743841e83fdf7546822").unwrap().try_into().unwrap();
+        assert_eq!(catalog.unsigned_der(&identifier, 1710108359), expected);
+    }
+
+    #[test]
+    fn test_verify_code_integrity() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        assert_eq!(eappx.code_integrity_catalog(EMSIX).unwrap().unwrap().members.len(), 6);
+
+        // The main binary is encrypted, it cannot be checked without key
+        let report = eappx.verify_code_integrity(EMSIX).unwrap().unwrap();
+        assert!(report.skipped.contains(&"TestApp.dll".to_owned()));
+
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let report = eappx.verify_code_integrity(EMSIX).unwrap().unwrap();
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.binaries.len(), 3);
+        assert!(report.unmatched.is_empty() && report.skipped.is_empty());
+
+        // A binary modified after extraction is no longer covered, its members are left over
+        let outdir = std::env::temp_dir().join(format!("eappx-code-integrity-{}", std::process::id()));
+        let extraction = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        let dll = outdir.join("TestApp.dll");
+        let mut data = std::fs::read(&dll).unwrap();
+        data[0x400] ^= 0xFF;
+        std::fs::write(&dll, data).unwrap();
+        let report = eappx.verify_extracted_code_integrity(EMSIX, &outdir, &extraction).unwrap().unwrap();
+        std::fs::remove_dir_all(&outdir).unwrap();
+        let failed = report.failed().map(|binary| (binary.name.as_str(), binary.coverage)).collect::<Vec<_>>();
+        assert_eq!(failed, [("TestApp.dll", Coverage::Uncovered)]);
+        assert_eq!((report.binaries.len(), report.unmatched.len()), (3, 2));
+    }
+
+    #[test]
+    fn test_catalog_coverage() {
+        let hashes = PeHashes { sha1: vec![1; 20], sha256: vec![2; 32] };
+        let member = |hash: Vec<u8>, file: Option<&str>| CatalogMember { hash, file: file.map(str::to_owned) };
+        let catalog = Catalog { members: vec![member(vec![2; 32], None), member(vec![3; 32], Some("Game.exe"))] };
+        assert_eq!(catalog.coverage("Bin\\Other.exe", &hashes), Coverage::Covered);
+
+        let hashes = PeHashes { sha1: vec![4; 20], sha256: vec![5; 32] };
+        assert_eq!(catalog.coverage("Bin\\game.EXE", &hashes), Coverage::Mismatched);
+        assert_eq!(catalog.coverage("Bin\\Other.exe", &hashes), Coverage::Uncovered);
+
+        // Round trip of a written catalog
+        let mut written = CodeIntegrityCatalog::new().deterministic(true);
+        written.add(hashes.clone());
+        let parsed = Catalog::parse(&written.to_der()).unwrap();
+        assert!(parsed.contains(&hashes));
+        assert_eq!(parsed.members.len(), 2);
+        assert!(Catalog::parse(b"not a catalog").is_err());
+    }
+}
@@ -0,0 +1,225 @@
+//! Comparison of a package against a directory it was extracted to
+//!
+//! Local files are hashed and checked against the blockmap, nothing is
+//! decrypted. Package paths are mapped the way extraction with
+//! [`EAppxFile::extract_options`] maps them. Footprint files and packages
+//! embedded in a bundle have no blockmap hash, they are compared against the
+//! bytes in the package instead.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::Error,
+    layout::{self, ExtractOptions, OutputLayout},
+    source::PackageSource,
+    utils,
+    EAppxFile,
+    FileInfo,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalStatus {
+    Unchanged,
+    Modified,
+    /// Not in the directory
+    Missing,
+    /// Only in the directory
+    Extra,
+    /// Size matches, but the file is encrypted and the blockmap has no file hash
+    Unverifiable,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalFile {
+    /// `None` for extra files
+    pub package_path: Option<String>,
+    /// Relative to the compared directory
+    pub path: PathBuf,
+    pub status: LocalStatus,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DirectoryComparison {
+    /// Package files in extraction order, followed by extra files
+    pub files: Vec<LocalFile>,
+}
+
+impl DirectoryComparison {
+    pub fn with_status(&self, status: LocalStatus) -> impl Iterator<Item = &LocalFile> {
+        self.files.iter().filter(move |file| file.status == status)
+    }
+
+    /// No file modified, missing or extra, unverifiable files count as intact
+    pub fn is_intact(&self) -> bool {
+        self.files.iter().all(|file| matches!(file.status, LocalStatus::Unchanged | LocalStatus::Unverifiable))
+    }
+}
+
+impl std::fmt::Display for DirectoryComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in &self.files {
+            let marker = match file.status {
+                LocalStatus::Unchanged => continue,
+                LocalStatus::Modified => "~",
+                LocalStatus::Missing => "-",
+                LocalStatus::Extra => "+",
+                LocalStatus::Unverifiable => "?",
+            };
+            writeln!(f, "{marker} {}", file.path.display())?;
+        }
+
+        writeln!(f, "{} unchanged, {} modified, {} missing, {} extra, {} unverifiable => {}",
+            self.with_status(LocalStatus::Unchanged).count(),
+            self.with_status(LocalStatus::Modified).count(),
+            self.with_status(LocalStatus::Missing).count(),
+            self.with_status(LocalStatus::Extra).count(),
+            self.with_status(LocalStatus::Unverifiable).count(),
+            if self.is_intact() { "INTACT" } else { "CHANGED" })
+    }
+}
+
+fn sha256_of_file(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Relative paths of all files below `dir`
+fn collect_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        match path.is_dir() {
+            true => collect_files(root, &path, files)?,
+            false => files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf()),
+        }
+    }
+    Ok(())
+}
+
+impl EAppxFile {
+    /// Compare `directory` against what extracting the package there produces
+    ///
+    /// Only reads payloads of footprint files and embedded packages, blockmap
+    /// files are checked against their hashes.
+    pub fn compare_directory<P: PackageSource + ?Sized>(&self, source: &P, directory: &Path) -> Result<DirectoryComparison, Error> {
+        let root = utils::long_path(directory)?;
+        if !root.is_dir() {
+            return Err(Error::DataError(format!("{} is not a directory", directory.display())));
+        }
+
+        let mut comparison = DirectoryComparison::default();
+        let mut push = |package_path: &str, path: PathBuf, check: &mut dyn FnMut(&Path) -> Result<LocalStatus, Error>| -> Result<(), Error> {
+            let local = root.join(&path);
+            let status = match local.is_file() {
+                true => check(&local)?,
+                false => LocalStatus::Missing,
+            };
+            log::debug!("{package_path}: {status:?}");
+            comparison.files.push(LocalFile { package_path: Some(package_path.to_owned()), path, status });
+            Ok(())
+        };
+        let same_hash = |expected: &[u8], local: &Path| -> Result<LocalStatus, Error> {
+            Ok(match sha256_of_file(local)? == expected {
+                true => LocalStatus::Unchanged,
+                false => LocalStatus::Modified,
+            })
+        };
+
+        for entry in self.footprint_entries() {
+            let Some(data) = self.read_footprint_file(source, entry.file)? else {
+                continue;
+            };
+            let expected = Sha256::digest(&data);
+            push(entry.file.package_path(), entry.file.file_name().into(), &mut |local| same_hash(&expected, local))?;
+        }
+
+        // Same layouts as extraction, collisions get the same names
+        let mut metadata_layout = OutputLayout::new(ExtractOptions { flat: false, ..self.extract_options });
+        let mut layout = OutputLayout::new(self.extract_options);
+        for file in &self.blockmap.files {
+            let path = match file.is_metadata() {
+                true => metadata_layout.map(&file.name)?,
+                false => layout.map(&file.name)?,
+            };
+            let fileinfo = self.blockmap_fileinfo(file)?;
+            let encrypted = fileinfo.key_id_index != 0xFFFF;
+            push(&file.name, path, &mut |local| Ok(match layout::check_local_file(local, &fileinfo, encrypted)? {
+                Some(true) => LocalStatus::Unchanged,
+                Some(false) => LocalStatus::Modified,
+                None => LocalStatus::Unverifiable,
+            }))?;
+        }
+
+        for (name, footer) in self.applicable_bundle_payloads(source)? {
+            let path = layout.map(&name)?;
+            let fileinfo = FileInfo::from(footer);
+            push(&name, path, &mut |local| {
+                if std::fs::metadata(local)?.len() != fileinfo.uncompressed_length {
+                    return Ok(LocalStatus::Modified);
+                }
+                let mut hasher = Sha256::new();
+                Self::read_file(source, &mut hasher, fileinfo.clone(), true, None, self.integrity)?;
+                same_hash(&hasher.finalize(), local)
+            })?;
+        }
+
+        let expected = comparison.files.iter().map(|file| file.path.clone()).collect::<HashSet<_>>();
+        let mut local_files = vec![];
+        collect_files(&root, &root, &mut local_files)?;
+        local_files.sort();
+        comparison.files.extend(local_files.into_iter()
+            .filter(|path| !expected.contains(path))
+            .map(|path| LocalFile { package_path: None, path, status: LocalStatus::Extra }));
+
+        Ok(comparison)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_compare_directory() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&sample::sample_keys()).unwrap();
+
+        let outdir = std::env::temp_dir().join("eappx_test_compare_directory");
+        let _ = std::fs::remove_dir_all(&outdir);
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+
+        // No key needed to compare
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let comparison = eappx.compare_directory(EMSIX, &outdir).unwrap();
+        assert!(comparison.is_intact(), "{comparison}");
+        assert_eq!(comparison.files.len(), report.files.len());
+        assert_eq!(comparison.with_status(LocalStatus::Unchanged).count(), report.files.len());
+
+        let modified = &report.files.iter().find(|file| file.package_path == "AppxManifest.xml").unwrap().path;
+        std::fs::write(outdir.join(modified), b"changed").unwrap();
+        let missing = &report.files.iter().find(|file| file.package_path == "AppxBlockMap.xml").unwrap().path;
+        std::fs::remove_file(outdir.join(missing)).unwrap();
+        std::fs::create_dir_all(outdir.join("New")).unwrap();
+        std::fs::write(outdir.join("New").join("extra.txt"), b"extra").unwrap();
+
+        let comparison = eappx.compare_directory(EMSIX, &outdir).unwrap();
+        assert!(!comparison.is_intact());
+        let paths = |status| comparison.with_status(status).map(|file| &file.path).collect::<Vec<_>>();
+        assert_eq!(paths(LocalStatus::Modified), [modified]);
+        assert_eq!(paths(LocalStatus::Missing), [missing]);
+        assert_eq!(paths(LocalStatus::Extra), [&Path::new("New").join("extra.txt")]);
+        assert!(comparison.to_string().contains("1 modified, 1 missing, 1 extra"));
+
+        std::fs::remove_dir_all(&outdir).unwrap();
+        assert!(eappx.compare_directory(EMSIX, &outdir).is_err());
+    }
+}
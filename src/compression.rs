@@ -0,0 +1,148 @@
+//! Per-file choice between deflating and storing during packing
+//!
+//! Already compressed formats only cost time when deflated and may even grow.
+//! MakeAppx stores them by extension, [`TrialCompression`] instead deflates
+//! the first block and looks at what it saves. Closures work as policies too:
+//!
+//! ```
+//! use eappx::compression::CompressionPolicy;
+//! let policy = |name: &str, _: &[u8]| !name.ends_with(".pak");
+//! assert!(!policy.should_compress("Data\\level1.pak", b""));
+//! ```
+//!
+//! Deflating runs on zlib-ng with the default `zlib-ng` feature, on the pure
+//! Rust miniz_oxide without it. zlib-ng roughly doubles throughput, the
+//! level trades time for size with either.
+
+use crate::{error::Error, writer::deflate_block};
+
+/// Deflate implementation compiled in
+pub const BACKEND: &str = if cfg!(feature = "zlib-ng") { "zlib-ng" } else { "miniz_oxide" };
+
+/// Level packing uses by default, as MakeAppx does
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Highest deflate level, 0 stores within deflate streams
+pub const MAX_LEVEL: u32 = 9;
+
+/// Reject levels beyond [`MAX_LEVEL`]
+pub fn check_level(level: u32) -> Result<(), Error> {
+    match level > MAX_LEVEL {
+        true => Err(Error::DataError(format!("Compression level {level} out of range, expected 0 to {MAX_LEVEL}"))),
+        false => Ok(()),
+    }
+}
+
+/// Formats that do not get smaller by deflating, MakeAppx stores them
+const STORED_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "gif", "mp3", "mp4", "wmv", "ogg",
+    "zip", "7z", "gz", "cab", "appx", "msix",
+];
+
+pub trait CompressionPolicy: Send + Sync {
+    /// Whether to deflate the file `name` (package path, either separator),
+    /// given its first block of up to 64KiB
+    fn should_compress(&self, name: &str, first_block: &[u8]) -> bool;
+}
+
+impl<F: Fn(&str, &[u8]) -> bool + Send + Sync> CompressionPolicy for F {
+    fn should_compress(&self, name: &str, first_block: &[u8]) -> bool {
+        self(name, first_block)
+    }
+}
+
+/// Store files by extension (case-insensitive), deflate everything else
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByExtension {
+    stored: Vec<String>,
+}
+
+impl ByExtension {
+    /// Store the given extensions, without leading dot
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(stored: I) -> Self {
+        Self { stored: stored.into_iter().map(|ext| ext.as_ref().to_lowercase()).collect() }
+    }
+
+    fn extension(name: &str) -> Option<String> {
+        let file_name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+        file_name.rsplit_once('.').map(|(_, ext)| ext.to_lowercase())
+    }
+}
+
+impl Default for ByExtension {
+    /// The formats MakeAppx stores
+    fn default() -> Self {
+        Self::new(STORED_EXTENSIONS)
+    }
+}
+
+impl CompressionPolicy for ByExtension {
+    fn should_compress(&self, name: &str, _first_block: &[u8]) -> bool {
+        Self::extension(name).is_none_or(|ext| !self.stored.contains(&ext))
+    }
+}
+
+/// Deflate the first block on trial, compress if it shrinks to `max_ratio` or less
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrialCompression {
+    /// Deflate level of the trial, best matches the level packed with
+    pub level: u32,
+    /// Largest compressed to uncompressed size ratio still worth deflating
+    pub max_ratio: f64,
+}
+
+impl Default for TrialCompression {
+    fn default() -> Self {
+        Self { level: DEFAULT_LEVEL, max_ratio: 0.95 }
+    }
+}
+
+impl CompressionPolicy for TrialCompression {
+    fn should_compress(&self, _name: &str, first_block: &[u8]) -> bool {
+        if first_block.is_empty() {
+            return false;
+        }
+        deflate_block(first_block, false, self.level)
+            .is_ok_and(|compressed| compressed.len() as f64 <= first_block.len() as f64 * self.max_ratio)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let data = b"AppxManifest.xml ".repeat(0x400);
+        let sizes = [1, DEFAULT_LEVEL, MAX_LEVEL].map(|level| deflate_block(&data, true, level).unwrap().len());
+        assert!(sizes[0] >= sizes[1] && sizes[1] >= sizes[2]);
+        assert!(check_level(MAX_LEVEL).is_ok());
+        assert!(check_level(MAX_LEVEL + 1).is_err());
+
+        let options = crate::writer::WriterOptions::default().compression_level(MAX_LEVEL + 1);
+        let writer = crate::writer::PackageWriter::new(std::io::Cursor::new(vec![]), crate::EAppxMagic::EXPH, "TestApp_1.0.0.0_x64__nh20k94c8ngfj", vec![], options);
+        assert!(writer.is_err());
+    }
+
+    #[test]
+    fn test_policies() {
+        let by_extension = ByExtension::default();
+        assert!(by_extension.should_compress("AppxManifest.xml", b""));
+        assert!(by_extension.should_compress("Assets.v2/README", b""));
+        assert!(!by_extension.should_compress("Assets\\Logo.scale-100.PNG", b""));
+        assert!(!ByExtension::new(["pak"]).should_compress("Data/level1.pak", b""));
+
+        let mut noise = vec![0u8; 0x4000];
+        let mut state = 0x2545f491u32;
+        for byte in noise.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *byte = state as u8;
+        }
+        let trial = TrialCompression::default();
+        assert!(trial.should_compress("Logo.png", &[b'A'; 0x4000]));
+        assert!(!trial.should_compress("level1.txt", &noise));
+        assert!(!trial.should_compress("empty.txt", b""));
+    }
+}
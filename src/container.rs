@@ -0,0 +1,643 @@
+//! Header and footer table of the container
+//!
+//! Everything needed to read package full name, key ids, footprint locations
+//! and the footer table, without blockmap, manifests or decompression. Always
+//! built, embedders that only need these can turn off the default features.
+
+use std::io::{Cursor, Seek};
+
+use binrw::{binrw, BinRead};
+
+use crate::{digest::HashAlgorithm, error::Error, identity::PackageFullName, keys::KeyId, limits::ResourceLimits, source::{PackageSource, SourceReader}, utils};
+
+#[binrw]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EAppxMagic {
+    /// Single
+    #[brw(magic(0x48505845u32))]
+    EXPH,
+    /// Undocumented variant, shares the header layout with EXPH.
+    /// Whether it wraps a package or a bundle is decided by its manifest.
+    #[brw(magic(0x48535845u32))]
+    EXSH,
+    /// Bundle
+    #[brw(magic(0x48425845u32))]
+    EXBH,
+}
+
+impl EAppxMagic {
+    pub fn description(&self) -> &'static str {
+        match self {
+            EAppxMagic::EXPH => "Encrypted package",
+            EAppxMagic::EXSH => "Encrypted package (EXSH variant)",
+            EAppxMagic::EXBH => "Encrypted bundle",
+        }
+    }
+}
+
+/// Header format version, packed as four u16 parts (major.minor.build.revision)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HeaderVersion(pub u64);
+
+impl HeaderVersion {
+    /// Version 1.0.0.0, used by all packages seen so far
+    pub const V1_0: HeaderVersion = HeaderVersion(0x0001_0000_0000_0000);
+    /// Versions this crate knows how to parse
+    pub const SUPPORTED: &'static [HeaderVersion] = &[HeaderVersion::V1_0];
+
+    pub fn major(&self) -> u16 {
+        (self.0 >> 48) as u16
+    }
+
+    pub fn minor(&self) -> u16 {
+        (self.0 >> 32) as u16
+    }
+
+    pub fn build(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    pub fn revision(&self) -> u16 {
+        self.0 as u16
+    }
+
+    /// Known version, parsed deterministically
+    pub fn is_known(&self) -> bool {
+        Self::SUPPORTED.contains(self)
+    }
+
+    /// Same major version as a known one, layout is expected to be compatible
+    pub fn is_compatible(&self) -> bool {
+        Self::SUPPORTED.iter().any(|v| v.major() == self.major())
+    }
+}
+
+impl std::fmt::Display for HeaderVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major(), self.minor(), self.build(), self.revision())
+    }
+}
+
+/// Granularities of a container: the XTS sector encrypted payloads are padded
+/// to and the block the blockmap hashes
+///
+/// Defaults to [`utils::SECTOR_SIZE`] and [`utils::BLOCK_SIZE`], which every
+/// known header version uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContainerParams {
+    sector_size: usize,
+    block_size: usize,
+}
+
+impl Default for ContainerParams {
+    fn default() -> Self {
+        Self { sector_size: utils::SECTOR_SIZE, block_size: utils::BLOCK_SIZE }
+    }
+}
+
+impl ContainerParams {
+    /// Blocks have to be made of whole sectors, sectors of whole AES blocks
+    pub fn new(sector_size: usize, block_size: usize) -> Result<Self, Error> {
+        if sector_size == 0 || !sector_size.is_multiple_of(crate::crypto::AES_BLOCK_SIZE)
+            || block_size == 0 || !block_size.is_multiple_of(sector_size) {
+            return Err(Error::DataError(format!("Invalid sector size {sector_size:#x} for block size {block_size:#x}")));
+        }
+        Ok(Self { sector_size, block_size })
+    }
+
+    /// Granularities of header version `version`, compatible unknown versions get those of 1.0
+    pub fn for_version(version: HeaderVersion) -> Self {
+        if !version.is_known() {
+            log::debug!("Assuming the sector and block size of {} for header version {version}", HeaderVersion::V1_0);
+        }
+        Self::default()
+    }
+
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Number of blocks `length` bytes of plaintext span
+    pub fn block_count(&self, length: u64) -> usize {
+        length.div_ceil(self.block_size as u64) as usize
+    }
+
+    /// `length` padded to whole sectors
+    pub fn align_to_sector(&self, length: usize) -> usize {
+        length.div_ceil(self.sector_size) * self.sector_size
+    }
+
+    /// Whether encrypted data of `length` bytes, starting on a sector, can be
+    /// decrypted, see [`crypto::is_decryptable_len`](crate::crypto::is_decryptable_len)
+    pub fn is_decryptable_len(&self, length: usize) -> bool {
+        let tail = length % self.sector_size;
+        tail == 0 || tail >= crate::crypto::AES_BLOCK_SIZE
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileInfo {
+    pub key_id_index: u16,
+    pub compression_type: u16,
+    pub offset_to_file: u64,
+    pub uncompressed_length: u64,
+    pub compressed_length: u64,
+    pub filehash: Option<Vec<u8>>,
+    pub block_hashes: Option<Vec<Vec<u8>>>,
+    /// Digest of `filehash`
+    pub filehash_algorithm: HashAlgorithm,
+    /// Digest of `block_hashes`
+    pub block_hash_algorithm: HashAlgorithm,
+    /// Stored size of every block, known for compressed files
+    pub block_sizes: Option<Vec<u32>>,
+    /// Footer table id, `None` for payloads outside the footer table
+    pub file_id: Option<u64>,
+    /// Granularities of the container holding the payload
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub params: ContainerParams,
+}
+
+impl From<&EAppxFooter> for FileInfo {
+    fn from(value: &EAppxFooter) -> Self {
+        FileInfo {
+            key_id_index: value.key_id_index,
+            compression_type: value.compression_type,
+            offset_to_file: value.offset_to_file,
+            uncompressed_length: value.uncompressed_length,
+            compressed_length: value.compressed_length,
+            filehash: None,
+            block_hashes: None,
+            filehash_algorithm: HashAlgorithm::default(),
+            block_hash_algorithm: HashAlgorithm::default(),
+            block_sizes: None,
+            file_id: Some(value.file_id),
+            params: ContainerParams::default(),
+        }
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EAppxHeader {
+    pub magic: EAppxMagic,
+    pub header_size: u16,
+    pub version: u64,
+    pub footer_offset: u64,
+    pub footer_length: u64,
+    pub file_count: u64,
+    pub signature_offset: u64,
+    pub signature_compression_type: u16,
+    pub signature_uncompressed_length: u32,
+    pub signature_length: u32,
+    pub code_integrity_offset: u64,
+    pub code_integrity_compression_type: u16,
+    pub code_integrity_uncompressed_length: u32,
+    pub code_integrity_length: u32,
+    pub block_map_file_id: u64,
+    pub key_length: u32,
+    #[bw(try_calc(u16::try_from(key_ids.len())))]
+    pub(crate) key_id_count: u16,
+    #[br(count = key_id_count)]
+    pub key_ids: Vec<KeyId>,
+    #[bw(try_calc(u16::try_from(package_full_name.len())))]
+    pub(crate) _package_full_name_str_len: u16,
+    #[bw(try_calc(u16::try_from(package_full_name.len() * 2)))]
+    pub(crate) package_full_name_byte_len: u16,
+    #[br(count = package_full_name_byte_len / 2)]
+    #[cfg_attr(feature = "serde", serde(with = "utils::utf16_string"))]
+    pub(crate) package_full_name: Vec<u16>,
+    #[bw(try_calc(u16::try_from(crypto_algo.len() * 2)))]
+    pub(crate) crypto_algo_length: u16,
+    #[br(count = crypto_algo_length / 2)]
+    #[cfg_attr(feature = "serde", serde(with = "utils::utf16_string"))]
+    pub(crate) crypto_algo: Vec<u16>,
+    pub diffusion_support_enabled: u16,
+    #[bw(try_calc(u16::try_from(block_map_hash_algo.len() * 2)))]
+    pub(crate) block_map_hash_algo_length: u16,
+    #[br(count = block_map_hash_algo_length / 2)]
+    #[cfg_attr(feature = "serde", serde(with = "utils::utf16_string"))]
+    pub(crate) block_map_hash_algo: Vec<u16>,
+    #[bw(try_calc(u16::try_from(block_map_hash.len())))]
+    pub(crate) block_map_hash_length: u16,
+    #[br(count = block_map_hash_length)]
+    pub block_map_hash: Vec<u8>,
+}
+
+impl EAppxHeader {
+    pub fn is_bundle(&self) -> bool {
+        self.magic == EAppxMagic::EXBH
+    }
+
+    pub fn is_exsh(&self) -> bool {
+        self.magic == EAppxMagic::EXSH
+    }
+
+    pub fn header_version(&self) -> HeaderVersion {
+        HeaderVersion(self.version)
+    }
+
+    /// Sector and block size of this header's version
+    pub fn params(&self) -> ContainerParams {
+        ContainerParams::for_version(self.header_version())
+    }
+
+    /// Reject versions with an unknown major, warn about unknown minor revisions
+    pub fn check_version(&self) -> Result<(), Error> {
+        let version = self.header_version();
+
+        if !version.is_compatible() {
+            return Err(Error::UnsupportedVersion(version.0));
+        }
+        if !version.is_known() {
+            log::warn!("Unknown header version {version}, parsing it like {}", HeaderVersion::V1_0);
+        }
+
+        Ok(())
+    }
+
+    /// Package full name, invalid UTF-16 is replaced by U+FFFD
+    pub fn package_full_name(&self) -> String {
+        String::from_utf16_lossy(&self.package_full_name)
+    }
+
+    /// Crypto algorithm name, invalid UTF-16 is replaced by U+FFFD
+    pub fn crypto_algo(&self) -> String {
+        String::from_utf16_lossy(&self.crypto_algo)
+    }
+
+    /// Blockmap hash algorithm URI, invalid UTF-16 is replaced by U+FFFD
+    pub fn block_map_hash_algo(&self) -> String {
+        String::from_utf16_lossy(&self.block_map_hash_algo)
+    }
+
+    /// Package full name as stored, UTF-16 code units
+    pub fn package_full_name_raw(&self) -> &[u16] {
+        &self.package_full_name
+    }
+
+    /// Crypto algorithm name as stored, UTF-16 code units
+    pub fn crypto_algo_raw(&self) -> &[u16] {
+        &self.crypto_algo
+    }
+
+    /// Blockmap hash algorithm URI as stored, UTF-16 code units
+    pub fn block_map_hash_algo_raw(&self) -> &[u16] {
+        &self.block_map_hash_algo
+    }
+
+    pub fn has_footer(&self) -> bool {
+        self.footer_offset > 0 && self.footer_length > 0
+    }
+
+    pub fn is_code_integrity_protected(&self) -> bool {
+        self.code_integrity_offset > 0 && self.code_integrity_length > 0
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.signature_offset > 0 && self.signature_length > 0
+    }
+
+    pub fn appx_signature_fileinfo(&self) -> Option<FileInfo> {
+        if !self.is_signed() {
+            return None;
+        }
+
+        Some(FileInfo {
+            key_id_index: 0xFFFF,
+            compression_type: self.signature_compression_type,
+            offset_to_file: self.signature_offset,
+            uncompressed_length: self.signature_uncompressed_length as u64,
+            compressed_length: self.signature_length as u64,
+            filehash: None,
+            block_hashes: None,
+            filehash_algorithm: HashAlgorithm::default(),
+            block_hash_algorithm: HashAlgorithm::default(),
+            block_sizes: None,
+            file_id: None,
+            params: self.params(),
+        })
+    }
+
+    pub fn code_integrity_fileinfo(&self) -> Option<FileInfo> {
+        if !self.is_code_integrity_protected() {
+            return None;
+        }
+
+        Some(FileInfo {
+            key_id_index: 0xFFFF,
+            compression_type: self.code_integrity_compression_type,
+            offset_to_file: self.code_integrity_offset,
+            uncompressed_length: self.code_integrity_uncompressed_length as u64,
+            compressed_length: self.code_integrity_length as u64,
+            filehash: None,
+            block_hashes: None,
+            filehash_algorithm: HashAlgorithm::default(),
+            block_hash_algorithm: HashAlgorithm::default(),
+            block_sizes: None,
+            file_id: None,
+            params: self.params(),
+        })
+    }
+
+    /// Number of footer table entries, entries may be larger than [`EAppxFooter::SIZE`]
+    pub fn footer_count(&self) -> usize {
+        self.file_count as usize
+    }
+
+    /// [`EAppxHeader::package_full_name`] split into its fields
+    pub fn full_name(&self) -> Result<PackageFullName, Error> {
+        self.package_full_name().parse()
+    }
+
+    pub fn app_name(&self) -> String {
+        self.package_full_name()
+            .split("_")
+            .next()
+            .unwrap()
+            .to_owned()
+    }
+
+    pub fn publisher_id(&self) -> String {
+        self.package_full_name()
+            .split("_")
+            .last()
+            .unwrap()
+            .to_owned()
+    }
+}
+
+impl std::fmt::Display for EAppxHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "EAppxHeader {{ ")?;
+        writeln!(f, "  Magic: {:?} ({})", self.magic, self.magic.description())?;
+        writeln!(f, "  HeaderSize: {:#02x}", self.header_size)?;
+        writeln!(f, "  Version: {:#08x} ({})", self.version, self.header_version())?;
+        writeln!(f, "  FooterOffset: {:#08x}", self.footer_offset)?;
+        writeln!(f, "  FooterLength: {:#08x}", self.footer_length)?;
+        writeln!(f, "  FileCount: {:#08x}", self.file_count)?;
+        writeln!(f, "  SignatureOffset: {:#08x}", self.signature_offset)?;
+        writeln!(f, "  SignatureCompressionType: {:#02x}", self.signature_compression_type)?;
+        writeln!(f, "  SignatureUncompressedLength: {:#04x}", self.signature_uncompressed_length)?;
+        writeln!(f, "  SignatureLength: {:#04x}", self.signature_length)?;
+        writeln!(f, "  CodeIntegrityOffset: {:#08x}", self.code_integrity_offset)?;
+        writeln!(f, "  CodeIntegrityCompressionType: {:#02x}", self.code_integrity_compression_type)?;
+        writeln!(f, "  CodeIntegrityUncompressedLength: {:#04x}", self.code_integrity_uncompressed_length)?;
+        writeln!(f, "  CodeIntegrityLength: {:#04x}", self.code_integrity_length)?;
+        writeln!(f, "  BlockMapFileId: {:#08x}", self.block_map_file_id)?;
+        writeln!(f, "  KeyLength: {:#04x}", self.key_length)?;
+        writeln!(f, "  KeyIds: {}", self.key_ids.len())?;
+        for key in &self.key_ids {
+            writeln!(f, "  - {}", key)?;
+        }
+        writeln!(f, "  PackageFullName: {} (name={}, publisherId={})", self.package_full_name(), self.app_name(), self.publisher_id())?;
+        writeln!(f, "  CryptoAlgo: {}", self.crypto_algo())?;
+        writeln!(f, "  DiffusionSupportEnabled: {:#02x}", self.diffusion_support_enabled)?;
+        writeln!(f, "  BlockMapHashAlgoLength: {:#02x}", self.block_map_hash_algo.len() * 2)?;
+        writeln!(f, "  BlockMapHashAlgo: {}", self.block_map_hash_algo())?;
+        writeln!(f, "  BlockMapHash: {}", hex::encode(&self.block_map_hash))?;
+        writeln!(f, "}}")?;
+
+        Ok(())
+    }
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EAppxFooter {
+    pub magic: u16, // "EF" read as little endian: 0x4645
+    pub footer_size: u16,
+    pub key_id_index: u16,
+    pub compression_type: u16,
+    pub file_id: u64,
+    pub offset_to_file: u64,
+    pub uncompressed_length: u64,
+    pub compressed_length: u64,
+}
+
+impl EAppxFooter {
+    pub const MAGIC: u16 = 0x4645;
+    pub const SIZE: u16 = 0x28;
+
+    /// Create a footer entry, `key_id_index` of `None` marks the file unencrypted
+    pub fn new(
+        file_id: u64,
+        offset_to_file: u64,
+        key_id_index: Option<u16>,
+        compressed: bool,
+        uncompressed_length: u64,
+        compressed_length: u64,
+    ) -> Self {
+        Self {
+            magic: Self::MAGIC,
+            footer_size: Self::SIZE,
+            key_id_index: key_id_index.unwrap_or(0xFFFF),
+            compression_type: compressed as u16,
+            file_id,
+            offset_to_file,
+            uncompressed_length,
+            compressed_length,
+        }
+    }
+
+    /// Whether the key index marks the payload encrypted, ignored inside bundles
+    ///
+    /// Bundles mostly keep the key index of inner packages stored in the clear,
+    /// [`EAppxFile::is_footer_encrypted`](crate::EAppxFile::is_footer_encrypted)
+    /// tells those apart from encrypted ones.
+    pub fn is_encrypted(&self, from_bundle: bool) -> bool {
+        self.key_id_index != 0xFFFF && !from_bundle
+    }
+
+    /// Amount of bytes the payload occupies in the container,
+    /// uncompressed encrypted payloads are padded to sector size
+    pub fn stored_length(&self, from_bundle: bool) -> u64 {
+        self.stored_length_with(from_bundle, &ContainerParams::default())
+    }
+
+    /// [`EAppxFooter::stored_length`] in a container with `params`
+    pub fn stored_length_with(&self, from_bundle: bool, params: &ContainerParams) -> u64 {
+        self.padded_length(self.is_encrypted(from_bundle), params)
+    }
+
+    /// [`EAppxFooter::stored_length_with`] for a payload known to be `encrypted` or not
+    pub fn padded_length(&self, encrypted: bool, params: &ContainerParams) -> u64 {
+        if encrypted && self.compression_type == 0 && self.compressed_length > 0 {
+            self.compressed_length.checked_next_multiple_of(params.sector_size as u64).unwrap_or(u64::MAX)
+        } else {
+            self.compressed_length
+        }
+    }
+}
+
+impl std::fmt::Display for EAppxFooter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EAppxFooter {{ ")?;
+        write!(f, "Magic: {:?}, ", self.magic)?;
+        write!(f, "FooterSize: {:#02x}, ", self.footer_size)?;
+        write!(f, "KeyIDIndex: {:#06x}, ", self.key_id_index)?;
+        write!(f, "CompressionType: {:#02x}, ", self.compression_type)?;
+        write!(f, "FileId: {:#08x}, ", self.file_id)?;
+        write!(f, "OffsetToFile: {:#08x}, ", self.offset_to_file)?;
+        write!(f, "UncompressedLength: {:#08x}, ", self.uncompressed_length)?;
+        write!(f, "CompressedLength: {:#08x}", self.compressed_length)?;
+        write!(f, " }}")?;
+
+        Ok(())
+    }
+}
+
+/// Read the footer table in one go and parse it from memory
+///
+/// Entries are walked by their own `footer_size`, fields a newer format
+/// appends to an entry are skipped.
+fn read_footers<P: PackageSource + ?Sized>(source: &P, header: &EAppxHeader) -> Result<Vec<EAppxFooter>, Error> {
+    let mut table = vec![0u8; header.footer_length as usize];
+    source.read_exact_at(header.footer_offset, &mut table)?;
+
+    // The table bounds the count, whatever the header declares
+    let mut footers = Vec::with_capacity(header.footer_count().min(table.len() / EAppxFooter::SIZE as usize));
+    let mut position = 0usize;
+    for index in 0..header.footer_count() {
+        let entry = table.get(position..)
+            .filter(|&entry| entry.len() >= EAppxFooter::SIZE as usize)
+            .ok_or_else(|| Error::DecodeError(format!(
+                "Footer table of {:#x} bytes ends before footer {index} of {}", table.len(), header.footer_count())))?;
+        let footer = EAppxFooter::read(&mut Cursor::new(entry))
+            .map_err(|e| Error::decode(format!("Failed to read footer {index}"), e))?;
+        if footer.magic != EAppxFooter::MAGIC {
+            return Err(Error::DecodeError(format!("Footer {index} has magic {:#06x}, expected {:#06x}", footer.magic, EAppxFooter::MAGIC)));
+        }
+        match footer.footer_size {
+            size if size < EAppxFooter::SIZE => return Err(Error::DecodeError(
+                format!("Footer {index} has size {size:#x}, expected at least {:#x}", EAppxFooter::SIZE))),
+            size if size > EAppxFooter::SIZE => log::debug!("Footer {index} has size {size:#x}, skipping unknown trailing fields"),
+            _ => {},
+        }
+        if entry.len() < footer.footer_size as usize {
+            return Err(Error::DecodeError(format!("Footer {index} of size {:#x} exceeds the footer table", footer.footer_size)));
+        }
+        position += footer.footer_size as usize;
+        footers.push(footer);
+    }
+
+    if position != table.len() {
+        log::warn!("Footer table is {:#x} bytes, {} footers only use {position:#x}", table.len(), footers.len());
+    }
+    Ok(footers)
+}
+
+/// Ensure a region lies completely inside the package
+pub(crate) fn check_region(what: &str, offset: u64, length: u64, file_len: u64) -> Result<(), Error> {
+    match offset.checked_add(length) {
+        Some(end) if end <= file_len => Ok(()),
+        _ => Err(Error::OutOfBounds { what: what.to_owned(), offset, length, file_len }),
+    }
+}
+
+/// Deflate cannot expand data beyond this ratio
+const MAX_DEFLATE_RATIO: u64 = 1032;
+
+/// Ensure the declared uncompressed length fits the stored payload
+pub(crate) fn check_lengths(what: &str, compressed: bool, uncompressed_length: u64, stored_length: u64) -> Result<(), Error> {
+    let plausible = match compressed {
+        true => uncompressed_length <= stored_length.saturating_mul(MAX_DEFLATE_RATIO),
+        false => uncompressed_length == stored_length,
+    };
+
+    match plausible {
+        true => Ok(()),
+        false => Err(Error::ImplausibleLength { what: what.to_owned(), uncompressed_length, stored_length }),
+    }
+}
+
+/// Validate every offset and length of header and footers against the package size
+fn check_layout(header: &EAppxHeader, footers: &[EAppxFooter], file_len: u64) -> Result<(), Error> {
+    for (what, fileinfo) in [
+        ("Signature", header.appx_signature_fileinfo()),
+        ("Code integrity", header.code_integrity_fileinfo()),
+    ] {
+        // Packages embedded in a bundle point at footprint data the bundle
+        // stores behind them, offsets are relative to the bundle then
+        if let Some(fileinfo) = fileinfo.filter(|fileinfo| fileinfo.offset_to_file < file_len) {
+            check_region(what, fileinfo.offset_to_file, fileinfo.compressed_length, file_len)?;
+            check_lengths(what, fileinfo.compression_type == 0x1, fileinfo.uncompressed_length, fileinfo.compressed_length)?;
+        }
+    }
+
+    for footer in footers {
+        let what = format!("File {:#x}", footer.file_id);
+        check_region(&what, footer.offset_to_file, footer.stored_length_with(header.is_bundle(), &header.params()), file_len)?;
+        check_lengths(&what, footer.compression_type == 0x1, footer.uncompressed_length, footer.compressed_length)?;
+    }
+
+    Ok(())
+}
+
+/// Read the header at the start of `source` and check its version
+pub fn read_header<P: PackageSource + ?Sized>(source: &P) -> Result<EAppxHeader, Error> {
+    let mut stream = SourceReader::new(source);
+    let header = EAppxHeader::read(&mut stream)
+        .map_err(|e| Error::decode("Failed to read header", e))?;
+    header.check_version()?;
+
+    // Counts and lengths are u16, the fields cannot take more than a few MiB.
+    // They may not reach past the declared size, a larger one leaves room for fields of newer versions.
+    let consumed = stream.stream_position()?;
+    match consumed.cmp(&(header.header_size as u64)) {
+        std::cmp::Ordering::Greater => return Err(Error::DecodeError(format!(
+            "Header fields take {consumed:#x} bytes, header size is {:#x}", header.header_size))),
+        std::cmp::Ordering::Less => log::debug!("Header size {:#x} exceeds its fields ({consumed:#x}), skipping unknown trailing fields",
+            header.header_size),
+        std::cmp::Ordering::Equal => {},
+    }
+    if header.is_exsh() {
+        log::warn!("EXSH package detected, layout is not fully understood - treating it like EXPH");
+    }
+
+    Ok(header)
+}
+
+/// Check the counts and sizes `header` declares against `limits` and each other
+///
+/// The footer count drives allocations and loops, the footer table is read
+/// into memory at once. Entries are at least [`EAppxFooter::SIZE`] bytes and
+/// at most `u16::MAX`, so the table length bounds the count both ways.
+pub fn check_header(header: &EAppxHeader, limits: &ResourceLimits) -> Result<(), Error> {
+    ResourceLimits::check("Header size", header.header_size as u64, limits.max_header_size)?;
+    ResourceLimits::check("Key id count", header.key_ids.len() as u64, limits.max_key_id_count)?;
+    limits.check_file_count("Footer count", header.file_count)?;
+    limits.check_in_memory_size("Footer table size", header.footer_length)?;
+
+    let min_length = header.file_count.saturating_mul(EAppxFooter::SIZE as u64);
+    let max_length = header.file_count.saturating_mul(u16::MAX as u64);
+    if header.footer_length < min_length || header.footer_length > max_length {
+        return Err(Error::DecodeError(format!("Footer length {:#x} does not fit {} footers ({min_length:#x} to {max_length:#x} bytes)",
+            header.footer_length, header.file_count)));
+    }
+
+    Ok(())
+}
+
+/// Footer table of `header`, every entry checked against the package size
+///
+/// Returns the footers along with the size of the package.
+pub fn read_footer_table<P: PackageSource + ?Sized>(source: &P, header: &EAppxHeader, limits: &ResourceLimits) -> Result<(Vec<EAppxFooter>, u64), Error> {
+    let file_len = source.len()?;
+
+    check_region("Footer table", header.footer_offset, header.footer_length, file_len)?;
+    check_header(header, limits)?;
+    let footers = read_footers(source, header)?;
+    check_layout(header, &footers, file_len)?;
+
+    Ok((footers, file_len))
+}
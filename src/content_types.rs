@@ -0,0 +1,216 @@
+//! `[Content_Types].xml` for zip based containers
+//!
+//! Open Packaging Conventions require a content type for every part of the
+//! zip. Payload files are covered by a `Default` for their extension, footprint
+//! files and files without extension by an `Override` for their part name. The
+//! encrypted format carries no content types, conversions and packing derive
+//! them from the packaged files.
+
+use crate::{extension::ContainerKind, utils::xml_escape};
+
+pub const BLOCKMAP: &str = "application/vnd.ms-appx.blockmap+xml";
+pub const SIGNATURE: &str = "application/vnd.ms-appx.signature";
+pub const CODE_INTEGRITY: &str = "application/vnd.ms-pkiseccat";
+pub const PACKAGE_MANIFEST: &str = "application/vnd.ms-appx.manifest+xml";
+pub const BUNDLE_MANIFEST: &str = "application/vnd.ms-appx.bundlemanifest+xml";
+pub const PACKAGE: &str = "application/vnd.ms-appx";
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+const NAMESPACE: &str = "http://schemas.openxmlformats.org/package/2006/content-types";
+
+/// Content type MakeAppx uses for an extension (lowercase, without dot)
+///
+/// Examples
+/// ```
+/// use eappx::{content_types::default_content_type, extension::ContainerKind};
+///
+/// assert_eq!(default_content_type("png", ContainerKind::Package), "image/png");
+/// assert_eq!(default_content_type("xml", ContainerKind::Bundle), "application/vnd.ms-appx.bundlemanifest+xml");
+/// assert_eq!(default_content_type("unknown", ContainerKind::Package), "application/octet-stream");
+/// ```
+pub fn default_content_type(extension: &str, kind: ContainerKind) -> &'static str {
+    match (extension, kind) {
+        // Manifests are the only xml parts MakeAppx types explicitly
+        ("xml", ContainerKind::Package) => PACKAGE_MANIFEST,
+        ("xml", ContainerKind::Bundle) => BUNDLE_MANIFEST,
+        ("appx" | "msix", _) => PACKAGE,
+        ("dll" | "exe" | "com" | "sys", _) => "application/x-msdownload",
+        ("png", _) => "image/png",
+        ("jpg" | "jpeg", _) => "image/jpeg",
+        ("gif", _) => "image/gif",
+        ("bmp", _) => "image/bmp",
+        ("ico", _) => "image/vnd.microsoft.icon",
+        ("svg", _) => "image/svg+xml",
+        ("tif" | "tiff", _) => "image/tiff",
+        ("txt", _) => "text/plain",
+        ("htm" | "html", _) => "text/html",
+        ("css", _) => "text/css",
+        ("js", _) => "application/x-javascript",
+        ("json", _) => "application/json",
+        ("mp3", _) => "audio/mpeg",
+        ("wav", _) => "audio/wav",
+        ("wma", _) => "audio/x-ms-wma",
+        ("mp4", _) => "video/mp4",
+        ("wmv", _) => "video/x-ms-wmv",
+        ("ttf", _) => "application/x-font-ttf",
+        ("cat", _) => CODE_INTEGRITY,
+        _ => OCTET_STREAM,
+    }
+}
+
+/// Whether a byte may appear unescaped in a part name segment (RFC 3986 pchar)
+fn is_pchar(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&byte)
+}
+
+/// OPC part name of a package path, e.g. `Assets\My Logo.png` becomes `/Assets/My%20Logo.png`
+///
+/// Zip entry names are part names without the leading slash.
+///
+/// Examples
+/// ```
+/// # use eappx::content_types::part_name;
+/// assert_eq!(part_name("Assets\\My Logo.png"), "/Assets/My%20Logo.png");
+/// assert_eq!(part_name("Strings/de-DE/Über.txt"), "/Strings/de-DE/%C3%9Cber.txt");
+/// ```
+pub fn part_name(name: &str) -> String {
+    let mut part_name = String::with_capacity(name.len() + 1);
+    for segment in name.split(['\\', '/']).filter(|segment| !segment.is_empty()) {
+        part_name.push('/');
+        for byte in segment.bytes() {
+            match is_pchar(byte) {
+                true => part_name.push(byte as char),
+                false => part_name.push_str(&format!("%{byte:02X}")),
+            }
+        }
+    }
+    part_name
+}
+
+/// Lowercase extension of the file a package path points at
+fn extension(name: &str) -> Option<String> {
+    let filename = name.rsplit(['\\', '/']).next().unwrap_or(name);
+    filename.rsplit_once('.')
+        .filter(|(_, extension)| !extension.is_empty())
+        .map(|(_, extension)| extension.to_lowercase())
+}
+
+/// Content types of a container, filled file by file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentTypes {
+    kind: ContainerKind,
+    /// Extension -> content type, in the order they were first seen
+    defaults: Vec<(String, &'static str)>,
+    /// Part name -> content type
+    overrides: Vec<(String, String)>,
+}
+
+impl ContentTypes {
+    pub fn new(kind: ContainerKind) -> Self {
+        Self { kind, defaults: vec![], overrides: vec![] }
+    }
+
+    pub fn kind(&self) -> ContainerKind {
+        self.kind
+    }
+
+    /// Cover a payload file, `name` is its package path
+    pub fn add_file(&mut self, name: &str) {
+        match extension(name) {
+            Some(extension) => {
+                if !self.defaults.iter().any(|(known, _)| *known == extension) {
+                    let content_type = default_content_type(&extension, self.kind);
+                    self.defaults.push((extension, content_type));
+                }
+            },
+            None => self.add_override(name, OCTET_STREAM),
+        }
+    }
+
+    /// Type a single part explicitly, replacing an earlier override of the same part
+    pub fn add_override(&mut self, name: &str, content_type: &str) {
+        let part_name = part_name(name);
+        self.overrides.retain(|(known, _)| !known.eq_ignore_ascii_case(&part_name));
+        self.overrides.push((part_name, content_type.to_owned()));
+    }
+
+    /// Overrides for the footprint files present next to the payload
+    pub fn add_footprint(&mut self, signed: bool, code_integrity: bool) {
+        self.add_override("AppxBlockMap.xml", BLOCKMAP);
+        if signed {
+            self.add_override("AppxSignature.p7x", SIGNATURE);
+        }
+        if code_integrity {
+            self.add_override("AppxMetadata\\CodeIntegrity.cat", CODE_INTEGRITY);
+        }
+    }
+
+    /// Content type a part resolves to, overrides take precedence
+    pub fn content_type(&self, name: &str) -> Option<&str> {
+        let part_name = part_name(name);
+        if let Some((_, content_type)) = self.overrides.iter().find(|(known, _)| known.eq_ignore_ascii_case(&part_name)) {
+            return Some(content_type);
+        }
+
+        let extension = extension(name)?;
+        self.defaults.iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, content_type)| *content_type)
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = format!(r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>{}<Types xmlns="{NAMESPACE}">"#, "\r\n");
+        for (extension, content_type) in &self.defaults {
+            xml += &format!(r#"<Default Extension="{}" ContentType="{content_type}"/>"#, xml_escape(extension));
+        }
+        for (part_name, content_type) in &self.overrides {
+            xml += &format!(r#"<Override PartName="{}" ContentType="{}"/>"#, xml_escape(part_name), xml_escape(content_type));
+        }
+        xml + "</Types>"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_types() {
+        let mut content_types = ContentTypes::new(ContainerKind::Package);
+        for name in ["TestApp.dll", "Assets\\Logo.PNG", "Assets\\Other.png", "LICENSE", "AppxManifest.xml"] {
+            content_types.add_file(name);
+        }
+        content_types.add_footprint(true, false);
+
+        assert_eq!(content_types.content_type("assets\\logo.png"), Some("image/png"));
+        assert_eq!(content_types.content_type("LICENSE"), Some(OCTET_STREAM));
+        assert_eq!(content_types.content_type("AppxBlockMap.xml"), Some(BLOCKMAP));
+        assert_eq!(content_types.content_type("AppxManifest.xml"), Some(PACKAGE_MANIFEST));
+
+        let xml = content_types.to_xml();
+        assert_eq!(xml.matches("Extension=\"png\"").count(), 1);
+        assert!(xml.contains(r#"<Override PartName="/LICENSE" ContentType="application/octet-stream"/>"#));
+        assert!(!xml.contains("CodeIntegrity"));
+    }
+
+    /// Same content types MakeAppx wrote for the test package
+    #[cfg(feature = "zip")]
+    #[test]
+    fn test_content_types_match_makeappx() {
+        use std::io::{Cursor, Read};
+
+        const MSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.msix");
+        let mut archive = zip::ZipArchive::new(Cursor::new(MSIX)).unwrap();
+        let mut content_types = ContentTypes::new(ContainerKind::Package);
+        for name in archive.file_names() {
+            if !["AppxBlockMap.xml", "[Content_Types].xml", "AppxSignature.p7x", "AppxMetadata/CodeIntegrity.cat"].contains(&name) {
+                content_types.add_file(name);
+            }
+        }
+        content_types.add_footprint(true, true);
+
+        let mut expected = String::new();
+        archive.by_name("[Content_Types].xml").unwrap().read_to_string(&mut expected).unwrap();
+        assert_eq!(content_types.to_xml(), expected);
+    }
+}
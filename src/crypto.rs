@@ -4,12 +4,20 @@ use aes::{Aes128, cipher::KeyInit, cipher::generic_array::GenericArray};
 use sha2::{Sha256, Digest};
 use xts_mode::Xts128;
 
-use crate::utils::{self};
+use crate::{container::ContainerParams, error::Error, utils};
 
+/// Decrypts the data read from `inner`, starting at sector 0 of a file
+///
+/// The data should be padded to whole sectors as in the container, a short
+/// last sector is decrypted as described at [`is_decryptable_len`].
 pub struct AesXtsReader<T: Read> {
     inner: T,
     ctx: CryptoFileContext,
+    /// Next sector to decrypt
     sector: u128,
+    /// Decrypted sectors not handed out yet start at `pos`
+    buf: Vec<u8>,
+    pos: usize,
 }
 
 impl<T: Read> AesXtsReader<T>
@@ -17,39 +25,139 @@ impl<T: Read> AesXtsReader<T>
     pub fn new(inner: T, ctx: CryptoFileContext) -> Self {
         Self {
             inner,
+            sector: 0,
+            buf: Vec::with_capacity(ctx.params.block_size()),
+            pos: 0,
             ctx,
-            sector: 0
         }
     }
+
+    /// Read and decrypt whole sectors covering up to `wanted` bytes, fewer at the end of the data
+    fn refill(&mut self, wanted: usize) -> std::io::Result<()> {
+        let params = self.ctx.params;
+        self.buf.resize(params.align_to_sector(wanted).clamp(params.sector_size(), params.block_size()), 0);
+        self.pos = 0;
+
+        let filled = utils::read_up_to(&mut self.inner, &mut self.buf)?;
+        self.buf.truncate(filled);
+
+        if !params.is_decryptable_len(filled) {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof,
+                format!("Encrypted data ends {:#x} bytes into sector {}", filled % params.sector_size(),
+                    self.sector + (filled / params.sector_size()) as u128)));
+        }
+
+        self.ctx.decrypt_area(&mut self.buf, self.sector);
+        self.sector += filled.div_ceil(params.sector_size()) as u128;
+        Ok(())
+    }
 }
 
 impl<T: Read> Read for AesXtsReader<T>
 {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let aligned_size = utils::align_to_sector(buf.len());
-        let mut tmp = vec![0u8; aligned_size];
-
-        self.inner.read_exact(&mut tmp)?;
-        self.ctx.cipher.0.decrypt_area(&mut tmp, utils::SECTOR_SIZE, self.sector, |sector| self.ctx.for_sector(sector));
-
-        buf.copy_from_slice(&tmp[..buf.len()]);
-        self.sector += (aligned_size / utils::SECTOR_SIZE) as u128;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.pos == self.buf.len() {
+            self.refill(buf.len())?;
+        }
 
-        Ok(buf.len())
+        let amount = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..amount].copy_from_slice(&self.buf[self.pos..self.pos + amount]);
+        self.pos += amount;
+        Ok(amount)
     }
 }
 
 pub struct CryptoFileContext {
     pub cipher: AesXtsCipher,
-    pub tweak: u128
+    pub tweak: u128,
+    /// Sector size of the container, sectors are XTS data units
+    pub params: ContainerParams,
 }
 
 impl CryptoFileContext {
+    /// Context for `filename` of the package with family name `pfn` (`Name_PublisherId`)
+    pub fn new(key: &[u8; 32], pfn: &str, filename: &str) -> Self {
+        Self {
+            cipher: create_cipher(key),
+            tweak: get_tweak_value(filename, pfn),
+            params: ContainerParams::default(),
+        }
+    }
+
     pub fn for_sector(&self, sector: u128) -> [u8; 16] {
-        let val = (self.tweak + sector).to_le_bytes();
+        let val = sector_tweak(self.tweak, sector);
         log::trace!("Tweak for sector {sector}: {}", hex::encode(val));
         val
     }
+
+    /// Encrypt sector-aligned `data` in place, starting at sector `first_sector` of the file
+    pub fn encrypt_area(&self, data: &mut [u8], first_sector: u128) {
+        self.cipher.0.encrypt_area(data, self.params.sector_size(), first_sector, |sector| self.for_sector(sector));
+    }
+
+    /// Decrypt `data` in place, starting at sector `first_sector` of the file
+    ///
+    /// Its length has to pass [`ContainerParams::is_decryptable_len`].
+    pub fn decrypt_area(&self, data: &mut [u8], first_sector: u128) {
+        self.cipher.0.decrypt_area(data, self.params.sector_size(), first_sector, |sector| self.for_sector(sector));
+    }
+
+    /// Encrypt `data` in place, which starts `offset` bytes into the file, a multiple of the sector size
+    pub fn encrypt_at(&self, data: &mut [u8], offset: u64) {
+        self.encrypt_area(data, (offset / self.params.sector_size() as u64) as u128);
+    }
+
+    /// Decrypt `data` in place, which starts `offset` bytes into the file, a multiple of the sector size
+    pub fn decrypt_at(&self, data: &mut [u8], offset: u64) {
+        self.decrypt_area(data, (offset / self.params.sector_size() as u64) as u128);
+    }
+}
+
+/// Smallest unit of AES-XTS, a short last sector needs at least this many bytes
+pub const AES_BLOCK_SIZE: usize = 16;
+
+/// Whether encrypted data of `len` bytes, starting on a 0x200 byte sector, can be decrypted
+///
+/// The container pads encrypted files with zeros to whole sectors. Data ending
+/// early, e.g. at the end of a truncated stream, still decrypts as long as its
+/// last sector holds an AES block: XTS decrypts a sector's AES blocks one by one,
+/// so a sector cut on an AES block yields the same plaintext as with padding.
+/// A sector cut inside an AES block is taken as ciphertext stealing, which is
+/// how XTS encrypts data not padded to AES blocks; zero padding cut there can
+/// not be recovered and decrypts to garbage at the end.
+///
+/// Examples
+/// ```
+/// # use eappx::crypto::is_decryptable_len;
+/// assert!(is_decryptable_len(0x400));
+/// assert!(is_decryptable_len(0x210));
+/// assert!(is_decryptable_len(0x21F));
+/// assert!(!is_decryptable_len(0x20F));
+/// ```
+pub fn is_decryptable_len(len: usize) -> bool {
+    ContainerParams::default().is_decryptable_len(len)
+}
+
+/// Decrypt a region of an encrypted file in place, without the container around it
+///
+/// `offset` is where `data` starts in the file's encrypted data as stored in the
+/// container, `filename` its path inside the package and `pfn` the package family
+/// name. Each sector decrypts on its own, so data carved from a damaged package can
+/// be decrypted as long as its offset into the file is known. The offset has to
+/// be a multiple of the 0x200 byte sector size, so does the length unless the
+/// region ends where the file's data ends, see [`is_decryptable_len`].
+pub fn decrypt_region(key: &[u8; 32], pfn: &str, filename: &str, offset: u64, data: &mut [u8]) -> Result<(), Error> {
+    let sector_size = utils::SECTOR_SIZE as u64;
+    if !offset.is_multiple_of(sector_size) || !is_decryptable_len(data.len()) {
+        return Err(Error::DataError(format!(
+            "Region at {offset:#x} with length {:#x} is not aligned to {sector_size:#x} byte sectors", data.len())));
+    }
+
+    CryptoFileContext::new(key, pfn, filename).decrypt_area(data, (offset / sector_size) as u128);
+    Ok(())
 }
 
 pub struct AesXtsCipher(pub Xts128::<Aes128>);
@@ -65,10 +173,19 @@ pub fn get_tweak_for_file(app_name: &str, publisher_id: &str, filename: &str) ->
     get_tweak_value(filename, &pfn)
 }
 
+/// Split a 32 byte key into the AES-128 data key and tweak key
+///
+/// Same order as Key1 and Key2 of IEEE 1619, keyfiles carry them concatenated.
+pub fn split_xts_key(key: &[u8; 32]) -> (&[u8; 16], &[u8; 16]) {
+    let (data_key, tweak_key) = key.split_at(16);
+    (data_key.try_into().unwrap(), tweak_key.try_into().unwrap())
+}
+
 pub fn create_cipher(key: &[u8; 32]) -> AesXtsCipher {
+    let (data_key, tweak_key) = split_xts_key(key);
     AesXtsCipher(Xts128::<Aes128>::new(
-        Aes128::new(GenericArray::from_slice(&key[..16])),
-        Aes128::new(GenericArray::from_slice(&key[16..]))
+        Aes128::new(GenericArray::from_slice(data_key)),
+        Aes128::new(GenericArray::from_slice(tweak_key))
     ))
 }
 
@@ -90,15 +207,27 @@ pub fn fold_hash_xor(hash: &[u8]) -> Vec<u8> {
     folded_hash
 }
 
+/// Path in the form Windows hashes for the file tweak
+///
+/// Separators become a single backslash, `.` segments are dropped and the path
+/// is rooted, e.g. `Assets//Logo.png` and `/Assets/./Logo.png` both become
+/// `\Assets\Logo.png`. Case is kept: the tweak covers the path as the
+/// blockmap records it, so a differently cased path derives a different tweak.
+///
+/// Examples
+/// ```
+/// # use eappx::crypto::canonicalize_file_path;
+/// assert_eq!(canonicalize_file_path("Assets/Logo.png"), "\\Assets\\Logo.png");
+/// assert_eq!(canonicalize_file_path("\\\\Assets\\.\\Sub//Logo.png"), "\\Assets\\Sub\\Logo.png");
+/// ```
+pub fn canonicalize_file_path(filepath: &str) -> String {
+    filepath.split(['\\', '/'])
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .fold(String::with_capacity(filepath.len() + 1), |path, segment| path + "\\" + segment)
+}
+
 pub fn hash_for_file_tweak(filepath: &str, pfn: &str) -> Vec<u8> {
-    let prefixed_path = match filepath.starts_with('\\') {
-        true => {
-            filepath.to_string()
-        },
-        false => {
-            "\\".to_string() + filepath
-        },
-    };
+    let prefixed_path = canonicalize_file_path(filepath);
     let lowercase_pfn = pfn.to_lowercase();
 
     log::trace!("Hashing Filepath: {prefixed_path} PFN: {lowercase_pfn} for file tweak");
@@ -114,6 +243,11 @@ pub fn hash_for_file_tweak(filepath: &str, pfn: &str) -> Vec<u8> {
     hasher.finalize().to_vec()
 }
 
+/// XTS tweak of sector `sector` of a file whose tweak is `tweak`, as passed to AES
+pub fn sector_tweak(tweak: u128, sector: u128) -> [u8; 16] {
+    (tweak + sector).to_le_bytes()
+}
+
 pub fn get_tweak_value(filepath: &str, pfn: &str) -> u128 {
     
     let tweak_hash = hash_for_file_tweak(filepath, pfn);
@@ -139,7 +273,8 @@ mod tests {
     fn test_tweak() {
         let tweak = CryptoFileContext {
             cipher: xts128_cipher(),
-            tweak: 0x2A7D4F58F4A696A3
+            tweak: 0x2A7D4F58F4A696A3,
+            params: ContainerParams::default(),
         };
         assert_eq!(hex::encode(tweak.for_sector(0)), "a396a6f4584f7d2a0000000000000000".to_lowercase())
     }
@@ -166,6 +301,81 @@ mod tests {
         assert_eq!(hex::encode(hash), expected);
     }
 
+    #[test]
+    fn test_tweak_hash_canonical_filepath() {
+        let expected = "98254280ac79f4b4799b1cd78bffb41ffeaa59f1ee70268b7f0c38dddc8ab195";
+        for filepath in ["Assets/LockScreenLogo.scale-200.png", "/Assets//LockScreenLogo.scale-200.png", r#".\Assets\LockScreenLogo.scale-200.png"#] {
+            let hash = hash_for_file_tweak(filepath, "testapp_bst25f6z33ccc");
+            assert_eq!(hex::encode(hash), expected, "{filepath}");
+        }
+    }
+
+    #[test]
+    fn test_xts_reader() {
+        let plaintext = (0..0x1234u32).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        let ctx = || CryptoFileContext::new(&[7u8; 32], "testapp_bst25f6z33ccc", "Assets\\Logo.png");
+        let mut stored = plaintext.clone();
+        stored.resize(utils::align_to_sector(stored.len()), 0);
+        ctx().encrypt_area(&mut stored, 0);
+
+        // Odd sized and single byte reads see the same plaintext
+        for chunk in [1, 7, 0x1FF, 0x201, 0x1001] {
+            let mut reader = AesXtsReader::new(stored.as_slice(), ctx());
+            let mut decrypted = vec![];
+            let mut buf = vec![0u8; chunk];
+            loop {
+                match reader.read(&mut buf).unwrap() {
+                    0 => break,
+                    read => decrypted.extend_from_slice(&buf[..read]),
+                }
+            }
+            assert_eq!(&decrypted[..plaintext.len()], plaintext, "{chunk:#x}");
+            assert_eq!(decrypted.len(), stored.len());
+        }
+
+        // Padding cut on an AES block leaves the plaintext as is
+        let mut decrypted = vec![];
+        AesXtsReader::new(&stored[..0x1240], ctx()).read_to_end(&mut decrypted).unwrap();
+        assert_eq!(&decrypted[..plaintext.len()], plaintext);
+
+        // Unpadded data ends in ciphertext stealing
+        for len in [0x10, 0x1F, 0x200, 0x211, 0x3FF, 0x1234] {
+            let mut stored = plaintext[..len].to_vec();
+            ctx().encrypt_area(&mut stored, 0);
+            let mut decrypted = vec![];
+            AesXtsReader::new(stored.as_slice(), ctx()).read_to_end(&mut decrypted).unwrap();
+            assert_eq!(decrypted, plaintext[..len], "{len:#x}");
+        }
+
+        let mut truncated = AesXtsReader::new(&stored[..0x20F], ctx());
+        let err = truncated.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_sector_size() {
+        let plaintext = (0..0x2345u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let params = ContainerParams::new(0x1000, 0x10000).unwrap();
+        let ctx = || CryptoFileContext { params, ..CryptoFileContext::new(&[7u8; 32], "testapp_bst25f6z33ccc", "Assets\\Logo.png") };
+
+        // Offsets count in sectors of the container's size
+        let mut at = plaintext[..0x1000].to_vec();
+        let mut area = at.clone();
+        ctx().encrypt_at(&mut at, 0x3000);
+        ctx().encrypt_area(&mut area, 3);
+        assert_eq!(at, area);
+
+        let mut stored = plaintext.clone();
+        ctx().encrypt_at(&mut stored, 0);
+        let mut decrypted = vec![];
+        AesXtsReader::new(stored.as_slice(), ctx()).read_to_end(&mut decrypted).unwrap();
+        assert_eq!(decrypted, plaintext);
+
+        assert!(ContainerParams::new(0x208, 0x10000).is_err());
+        assert!(ContainerParams::new(0x400, 0x10200).is_err());
+        assert!(ContainerParams::new(0, 0).is_err());
+    }
+
     #[test]
     fn test_fold_sha256_xor() {
         let hash = hex::decode("446dc620c5e5a6bb3566b6314f129ae8dcb7b752f39e14640e2a61b72126551d").unwrap();
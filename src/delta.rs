@@ -0,0 +1,324 @@
+//! Delta artifacts between two versions of a package
+//!
+//! A delta describes the new package as a sequence of byte ranges, each either
+//! copied from the old package or carried verbatim in the delta. Stored blocks
+//! whose hash and size match a block of the old package are copied, everything
+//! else (changed blocks, header, blockmap, footers) is shipped. Applying a delta
+//! checks the old package against the delta and the SHA-256 of the
+//! reconstructed package against the one recorded at creation.
+//!
+//! Like [`crate::diff`], this only pays off between packages sharing key and
+//! identity, otherwise no encrypted block matches.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Seek, Write},
+};
+
+use binrw::{binrw, BinRead, BinWrite};
+use sha2::{Digest, Sha256};
+
+use crate::{diff::block_stored_sizes, error::Error, source::PackageSource, utils, EAppxFile};
+
+pub const DELTA_VERSION: u16 = 1;
+
+#[binrw]
+#[brw(little, magic = b"EXDL")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeltaHeader {
+    pub version: u16,
+    /// Blockmap hash of the package the delta applies to
+    pub base_block_map_hash: [u8; 32],
+    pub base_length: u64,
+    /// SHA-256 over the complete reconstructed package
+    pub target_hash: [u8; 32],
+    pub target_length: u64,
+    pub op_count: u64,
+}
+
+#[binrw]
+#[brw(little)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaOp {
+    /// Range of the base package
+    #[brw(magic(0u8))]
+    Copy { offset: u64, length: u64 },
+    /// `length` bytes following the op
+    #[brw(magic(1u8))]
+    Data { length: u64 },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DeltaStats {
+    /// Bytes taken from the base package
+    pub copied: u64,
+    /// Bytes carried in the delta
+    pub shipped: u64,
+}
+
+/// Range of the target package and where its bytes come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Range {
+    Base { offset: u64, length: u64 },
+    Target { offset: u64, length: u64 },
+}
+
+pub(crate) struct StoredBlock<'a> {
+    pub(crate) offset: u64,
+    pub(crate) length: u64,
+    pub(crate) compressed: bool,
+    pub(crate) hash: &'a str,
+}
+
+/// Location of every stored block, files whose blocks cannot be located are skipped
+pub(crate) fn stored_blocks(eappx: &EAppxFile) -> Vec<StoredBlock<'_>> {
+    let mut blocks = vec![];
+
+    for file in &eappx.blockmap.files {
+        let Some(footer) = eappx.find_footer_for_file(file.id()) else {
+            continue;
+        };
+        let compressed = footer.compression_type == 0x1;
+        if compressed && file.blocks.iter().any(|block| block.size.is_none()) {
+            continue;
+        }
+
+        let mut offset = footer.offset_to_file;
+        for (block, length) in file.blocks.iter().zip(block_stored_sizes(eappx, file)) {
+            blocks.push(StoredBlock { offset, length, compressed, hash: &block.hash });
+            offset += length;
+        }
+    }
+
+    blocks
+}
+
+fn block_map_hash(eappx: &EAppxFile) -> Result<[u8; 32], Error> {
+    eappx.header.block_map_hash
+        .as_slice()
+        .try_into()
+        .map_err(|_| Error::DataError(format!("Unexpected blockmap hash length {:#x}", eappx.header.block_map_hash.len())))
+}
+
+fn plan_ranges(base: &EAppxFile, target: &EAppxFile) -> Vec<Range> {
+    let base_blocks: HashMap<(&str, u64, bool), u64> = stored_blocks(base)
+        .into_iter()
+        .map(|block| ((block.hash, block.length, block.compressed), block.offset))
+        .collect();
+
+    let mut target_blocks = stored_blocks(target);
+    target_blocks.sort_by_key(|block| block.offset);
+
+    let mut ranges: Vec<Range> = vec![];
+    let mut push = |range: Range| {
+        match (ranges.last_mut(), range) {
+            (Some(Range::Base { offset, length }), Range::Base { offset: next, length: next_length }) if *offset + *length == next => {
+                *length += next_length;
+            },
+            (Some(Range::Target { length, .. }), Range::Target { length: next_length, .. }) => *length += next_length,
+            _ => ranges.push(range),
+        }
+    };
+
+    let mut pos = 0;
+    for block in target_blocks {
+        let Some(base_offset) = base_blocks.get(&(block.hash, block.length, block.compressed)) else {
+            continue;
+        };
+        if block.offset < pos || block.length == 0 {
+            continue;
+        }
+
+        if block.offset > pos {
+            push(Range::Target { offset: pos, length: block.offset - pos });
+        }
+        push(Range::Base { offset: *base_offset, length: block.length });
+        pos = block.offset + block.length;
+    }
+
+    if pos < target.file_len {
+        push(Range::Target { offset: pos, length: target.file_len - pos });
+    }
+
+    ranges
+}
+
+/// Copy exactly `length` bytes, feeding them into `hasher` on the way
+fn copy_hashed<R: Read, W: Write>(reader: &mut R, length: u64, out: &mut W, hasher: &mut Sha256) -> Result<(), Error> {
+    let mut buf = vec![0u8; utils::BLOCK_SIZE];
+    let mut remaining = length;
+
+    while remaining > 0 {
+        let chunk = std::cmp::min(remaining, buf.len() as u64) as usize;
+        reader.read_exact(&mut buf[..chunk])?;
+        hasher.update(&buf[..chunk]);
+        out.write_all(&buf[..chunk])?;
+        remaining -= chunk as u64;
+    }
+
+    Ok(())
+}
+
+fn write_binrw<T: for<'a> BinWrite<Args<'a> = ()>, W: Write>(value: &T, out: &mut W) -> Result<(), Error> {
+    let mut cursor = Cursor::new(vec![]);
+    value.write_le(&mut cursor)
+        .map_err(|e| Error::data("Failed to serialize delta", e))?;
+    out.write_all(cursor.get_ref())?;
+    Ok(())
+}
+
+/// Write a delta turning `base` into `target`
+///
+/// Only metadata of the base package is needed, the target package is read
+/// twice: once for its hash, once for the shipped ranges.
+pub fn create_delta<P: PackageSource + ?Sized, W: Write>(
+    base: &EAppxFile,
+    target: &EAppxFile,
+    target_source: &P,
+    out: &mut W,
+) -> Result<DeltaStats, Error> {
+    let ranges = plan_ranges(base, target);
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut target_source.range(0, target_source.len()?), &mut hasher)?;
+
+    let header = DeltaHeader {
+        version: DELTA_VERSION,
+        base_block_map_hash: block_map_hash(base)?,
+        base_length: base.file_len,
+        target_hash: hasher.finalize().into(),
+        target_length: target.file_len,
+        op_count: ranges.len() as u64,
+    };
+    write_binrw(&header, out)?;
+
+    let mut stats = DeltaStats::default();
+    for range in ranges {
+        match range {
+            Range::Base { offset, length } => {
+                write_binrw(&DeltaOp::Copy { offset, length }, out)?;
+                stats.copied += length;
+            },
+            Range::Target { offset, length } => {
+                write_binrw(&DeltaOp::Data { length }, out)?;
+                if std::io::copy(&mut target_source.range(offset, length), out)? != length {
+                    return Err(Error::DataError(format!("Target package ended before {:#x}", offset + length)));
+                }
+                stats.shipped += length;
+            },
+        }
+    }
+
+    log::debug!("Delta copies {:#x} bytes and ships {:#x} bytes", stats.copied, stats.shipped);
+    Ok(stats)
+}
+
+/// Reconstruct the target package of `delta` from `base` into `out`
+///
+/// On error, `out` holds partial and unusable data.
+pub fn apply_delta<P: PackageSource + ?Sized, D: Read + Seek, W: Write>(
+    base: &EAppxFile,
+    base_source: &P,
+    delta: &mut D,
+    out: &mut W,
+) -> Result<DeltaHeader, Error> {
+    let header = DeltaHeader::read(delta)
+        .map_err(|e| Error::decode("Failed to read delta header", e))?;
+    if header.version != DELTA_VERSION {
+        return Err(Error::DataError(format!("Unsupported delta version {}", header.version)));
+    }
+    if header.base_block_map_hash != block_map_hash(base)? || header.base_length != base.file_len {
+        return Err(Error::DataError("Delta was created against a different base package".into()));
+    }
+
+    let mut hasher = Sha256::new();
+    let mut written = 0u64;
+    for _ in 0..header.op_count {
+        let op = DeltaOp::read(delta)
+            .map_err(|e| Error::decode("Failed to read delta op", e))?;
+
+        match op {
+            DeltaOp::Copy { offset, length } => {
+                if offset.checked_add(length).is_none_or(|end| end > base.file_len) {
+                    return Err(Error::DataError(format!("Copy of {length:#x} bytes at {offset:#x} exceeds base package")));
+                }
+                copy_hashed(&mut base_source.range(offset, length), length, out, &mut hasher)?;
+                written += length;
+            },
+            DeltaOp::Data { length } => {
+                copy_hashed(delta, length, out, &mut hasher)?;
+                written += length;
+            },
+        }
+    }
+
+    if written != header.target_length || hasher.finalize().as_slice() != header.target_hash {
+        return Err(Error::DataError("Reconstructed package does not match the delta target".into()));
+    }
+
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sample,
+        writer::{EntryOptions, PackageWriter, WriterOptions},
+        EAppxMagic,
+    };
+
+    /// Sample package with one block of numbers.bin changed and a file added
+    fn build_updated_sample() -> Vec<u8> {
+        let keys = vec![(sample::TEST_KEY_ID, sample::TEST_KEY.to_vec())];
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, &sample::sample_package_full_name(), keys, WriterOptions::default()).unwrap();
+
+        for (name, mut data, encrypted) in sample::sample_files() {
+            if name == "Data\\numbers.bin" {
+                data[utils::BLOCK_SIZE..].fill(0x42);
+            }
+            let options = EntryOptions { compress: !encrypted, key_index: encrypted.then_some(0) };
+            writer.add_entry(name, &mut data.as_slice(), options).unwrap();
+        }
+        writer.add_entry("Changelog.txt", &mut b"Fixed everything\n".as_slice(), EntryOptions { compress: false, key_index: Some(0) }).unwrap();
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base_bytes = sample::build_sample_package().unwrap();
+        let target_bytes = build_updated_sample();
+        let base = EAppxFile::from_source(base_bytes.as_slice()).unwrap();
+        let target = EAppxFile::from_source(target_bytes.as_slice()).unwrap();
+
+        let mut delta = vec![];
+        let stats = create_delta(&base, &target, target_bytes.as_slice(), &mut delta).unwrap();
+        // First block of numbers.bin is unchanged
+        assert!(stats.copied >= utils::BLOCK_SIZE as u64);
+        assert_eq!(stats.copied + stats.shipped, target_bytes.len() as u64);
+        assert!(delta.len() < target_bytes.len());
+
+        let mut out = vec![];
+        let header = apply_delta(&base, base_bytes.as_slice(), &mut Cursor::new(delta.as_slice()), &mut out).unwrap();
+        assert_eq!(header.target_length, target_bytes.len() as u64);
+        assert_eq!(out, target_bytes);
+
+        assert!(EAppxFile::from_source(out.as_slice()).unwrap().verify(out.as_slice()).is_valid());
+    }
+
+    #[test]
+    fn test_delta_wrong_base() {
+        let base_bytes = sample::build_sample_package().unwrap();
+        let target_bytes = build_updated_sample();
+        let base = EAppxFile::from_source(base_bytes.as_slice()).unwrap();
+        let target = EAppxFile::from_source(target_bytes.as_slice()).unwrap();
+
+        let mut delta = vec![];
+        create_delta(&base, &target, target_bytes.as_slice(), &mut delta).unwrap();
+
+        let result = apply_delta(&target, target_bytes.as_slice(), &mut Cursor::new(delta.as_slice()), &mut vec![]);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! Minimal DER encoding for the PKCS#7 structures written by the tool
+//!
+//! Only what signatures and catalogs need: every value is built bottom-up
+//! from already encoded children. [`DerReader`] walks existing signatures
+//! for inspection, it checks lengths but not the rest of DER's rules.
+
+// Encoders are shared with code integrity catalogs, which need `package`
+#![cfg_attr(not(feature = "package"), allow(dead_code))]
+
+use crate::error::Error;
+
+pub(crate) const TAG_INTEGER: u8 = 0x02;
+pub(crate) const TAG_OCTET_STRING: u8 = 0x04;
+pub(crate) const TAG_NULL: u8 = 0x05;
+pub(crate) const TAG_OID: u8 = 0x06;
+pub(crate) const TAG_UTF8_STRING: u8 = 0x0c;
+pub(crate) const TAG_PRINTABLE_STRING: u8 = 0x13;
+pub(crate) const TAG_T61_STRING: u8 = 0x14;
+pub(crate) const TAG_IA5_STRING: u8 = 0x16;
+pub(crate) const TAG_UTC_TIME: u8 = 0x17;
+pub(crate) const TAG_GENERALIZED_TIME: u8 = 0x18;
+pub(crate) const TAG_BMP_STRING: u8 = 0x1e;
+pub(crate) const TAG_SEQUENCE: u8 = 0x30;
+pub(crate) const TAG_SET: u8 = 0x31;
+pub(crate) const TAG_CONTEXT_0: u8 = 0xa0;
+pub(crate) const TAG_CONTEXT_1: u8 = 0xa1;
+
+pub(crate) const OID_SIGNED_DATA: &[u64] = &[1, 2, 840, 113549, 1, 7, 2];
+pub(crate) const OID_SHA256: &[u64] = &[2, 16, 840, 1, 101, 3, 4, 2, 1];
+pub(crate) const OID_SPC_INDIRECT_DATA: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 4];
+
+pub(crate) fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        length @ 0..=0x7f => out.push(length as u8),
+        length => {
+            let bytes = length.to_be_bytes();
+            let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+            out.push(0x80 | (bytes.len() - skip) as u8);
+            out.extend_from_slice(&bytes[skip..]);
+        },
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+pub(crate) fn sequence(items: &[&[u8]]) -> Vec<u8> {
+    der(TAG_SEQUENCE, &items.concat())
+}
+
+/// SET OF, sorted by encoding as DER requires
+pub(crate) fn set_of(mut items: Vec<Vec<u8>>) -> Vec<u8> {
+    items.sort();
+    der(TAG_SET, &items.concat())
+}
+
+pub(crate) fn oid(arcs: &[u64]) -> Vec<u8> {
+    let mut content = vec![];
+    let first = arcs[0] * 40 + arcs[1];
+    for arc in std::iter::once(first).chain(arcs[2..].iter().copied()) {
+        let mut encoded = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            encoded.push(0x80 | (rest & 0x7f) as u8);
+            rest >>= 7;
+        }
+        content.extend(encoded.iter().rev());
+    }
+    der(TAG_OID, &content)
+}
+
+/// INTEGER from an unsigned big-endian magnitude
+pub(crate) fn integer(magnitude: &[u8]) -> Vec<u8> {
+    let skip = magnitude.iter().take_while(|byte| **byte == 0).count();
+    let mut content = magnitude[skip..].to_vec();
+    if content.first().is_none_or(|byte| byte & 0x80 != 0) {
+        content.insert(0, 0);
+    }
+    der(TAG_INTEGER, &content)
+}
+
+pub(crate) fn octet_string(data: &[u8]) -> Vec<u8> {
+    der(TAG_OCTET_STRING, data)
+}
+
+/// BMPString, big-endian UTF-16
+pub(crate) fn bmp_string(value: &str) -> Vec<u8> {
+    let content: Vec<u8> = value.encode_utf16().flat_map(u16::to_be_bytes).collect();
+    der(TAG_BMP_STRING, &content)
+}
+
+/// `SEQUENCE { algorithm, NULL }`
+pub(crate) fn algorithm(arcs: &[u64]) -> Vec<u8> {
+    sequence(&[&oid(arcs), &der(TAG_NULL, &[])])
+}
+
+/// Attribute `SEQUENCE { type, SET { value } }`
+pub(crate) fn attribute(arcs: &[u64], value: &[u8]) -> Vec<u8> {
+    sequence(&[&oid(arcs), &der(TAG_SET, value)])
+}
+
+/// UTCTime (`YYMMDDHHMMSSZ`) of seconds since the unix epoch
+pub(crate) fn utc_time(timestamp: u64) -> Vec<u8> {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as u64;
+
+    let value = format!(
+        "{:02}{month:02}{day:02}{:02}{:02}{:02}Z",
+        year % 100, seconds / 3600, seconds / 60 % 60, seconds % 60,
+    );
+    der(TAG_UTC_TIME, value.as_bytes())
+}
+
+/// One element: tag, content and the whole encoding including the header
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DerValue<'a> {
+    pub tag: u8,
+    pub content: &'a [u8],
+    pub raw: &'a [u8],
+}
+
+impl<'a> DerValue<'a> {
+    /// Reader over the children of a constructed value
+    pub fn children(&self) -> DerReader<'a> {
+        DerReader::new(self.content)
+    }
+
+    pub fn oid(&self) -> Result<Vec<u64>, Error> {
+        self.check_tag(TAG_OID)?;
+        let mut arcs = vec![];
+        let mut arc = 0u64;
+        for byte in self.content {
+            arc = arc.checked_mul(0x80)
+                .ok_or_else(|| Error::DecodeError("OID arc too large".into()))?
+                | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                match arcs.is_empty() {
+                    true => arcs.extend([(arc / 40).min(2), arc - (arc / 40).min(2) * 40]),
+                    false => arcs.push(arc),
+                }
+                arc = 0;
+            }
+        }
+        Ok(arcs)
+    }
+
+    /// Text of the string types names and certificates use
+    pub fn string(&self) -> Result<String, Error> {
+        match self.tag {
+            TAG_UTF8_STRING | TAG_PRINTABLE_STRING | TAG_IA5_STRING => Ok(String::from_utf8_lossy(self.content).into_owned()),
+            // Latin-1 in practice
+            TAG_T61_STRING => Ok(self.content.iter().map(|byte| *byte as char).collect()),
+            TAG_BMP_STRING => Ok(String::from_utf16_lossy(&self.content.chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<_>>())),
+            tag => Err(Error::DecodeError(format!("Expected string, got tag {tag:#x}"))),
+        }
+    }
+
+    /// UTCTime or GeneralizedTime as `YYYY-MM-DD HH:MM:SS UTC`, fractions are dropped
+    pub fn time(&self) -> Result<String, Error> {
+        let text = std::str::from_utf8(self.content)
+            .map_err(|_| Error::DecodeError("Time is not ASCII".into()))?;
+        let digits = |range: std::ops::Range<usize>| text.get(range)
+            .filter(|part| part.bytes().all(|byte| byte.is_ascii_digit()))
+            .ok_or_else(|| Error::DecodeError(format!("Malformed time {text}")));
+        let (year, rest) = match self.tag {
+            TAG_UTC_TIME => match digits(0..2)?.parse::<u32>().unwrap() {
+                year @ 0..=49 => (2000 + year, 2),
+                year => (1900 + year, 2),
+            },
+            TAG_GENERALIZED_TIME => (digits(0..4)?.parse().unwrap(), 4),
+            tag => return Err(Error::DecodeError(format!("Expected time, got tag {tag:#x}"))),
+        };
+        Ok(format!("{year:04}-{}-{} {}:{}:{} UTC",
+            digits(rest..rest + 2)?, digits(rest + 2..rest + 4)?, digits(rest + 4..rest + 6)?,
+            digits(rest + 6..rest + 8)?, digits(rest + 8..rest + 10)?))
+    }
+
+    pub fn check_tag(&self, tag: u8) -> Result<(), Error> {
+        match self.tag == tag {
+            true => Ok(()),
+            false => Err(Error::DecodeError(format!("Expected tag {tag:#x}, got {:#x}", self.tag))),
+        }
+    }
+}
+
+/// Sequential reader over DER elements, single byte tags only
+#[derive(Debug, Clone)]
+pub(crate) struct DerReader<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> DerReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { rest: data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rest.is_empty()
+    }
+
+    pub fn next(&mut self) -> Result<DerValue<'a>, Error> {
+        let truncated = || Error::DecodeError("Truncated DER value".into());
+        let data = self.rest;
+        let (&tag, &first) = data.first().zip(data.get(1)).ok_or_else(truncated)?;
+        let (header_len, length) = match first {
+            0..=0x7f => (2, first as usize),
+            0x81..=0x84 => {
+                let count = (first & 0x7f) as usize;
+                let bytes = data.get(2..2 + count).ok_or_else(truncated)?;
+                (2 + count, bytes.iter().fold(0usize, |length, byte| length << 8 | *byte as usize))
+            },
+            _ => return Err(Error::DecodeError(format!("Unsupported DER length {first:#x}"))),
+        };
+        let end = header_len.checked_add(length).filter(|end| *end <= data.len()).ok_or_else(truncated)?;
+        self.rest = &data[end..];
+        Ok(DerValue { tag, content: &data[header_len..end], raw: &data[..end] })
+    }
+
+    pub fn expect(&mut self, tag: u8) -> Result<DerValue<'a>, Error> {
+        let value = self.next()?;
+        value.check_tag(tag)?;
+        Ok(value)
+    }
+
+    /// Next element if it has `tag`, e.g. OPTIONAL fields
+    pub fn optional(&mut self, tag: u8) -> Result<Option<DerValue<'a>>, Error> {
+        match self.rest.first() == Some(&tag) {
+            true => self.next().map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_der_primitives() {
+        assert_eq!(oid(OID_SHA256), hex::decode("0609608648016503040201").unwrap());
+        assert_eq!(integer(&0x01010000u32.to_be_bytes()), hex::decode("020401010000").unwrap());
+        assert_eq!(integer(&[0x00, 0x8b]), hex::decode("0202008b").unwrap());
+        assert_eq!(integer(&[]), hex::decode("020100").unwrap());
+        assert_eq!(der(TAG_OCTET_STRING, &[0; 0x100])[..4], [0x04, 0x82, 0x01, 0x00]);
+        assert_eq!(bmp_string("OS"), hex::decode("1e04004f0053").unwrap());
+        assert_eq!(set_of(vec![vec![0x05, 0x00], vec![0x02, 0x01, 0x00]]), hex::decode("31050201000500").unwrap());
+    }
+
+    #[test]
+    fn test_der_reader() {
+        let encoded = sequence(&[&oid(OID_SPC_INDIRECT_DATA), &octet_string(&[0; 0x100]), &bmp_string("OS")]);
+        let value = DerReader::new(&encoded).expect(TAG_SEQUENCE).unwrap();
+        assert_eq!(value.raw, encoded);
+
+        let mut children = value.children();
+        assert_eq!(children.next().unwrap().oid().unwrap(), OID_SPC_INDIRECT_DATA);
+        assert!(children.optional(TAG_NULL).unwrap().is_none());
+        assert_eq!(children.expect(TAG_OCTET_STRING).unwrap().content, [0; 0x100]);
+        assert_eq!(children.next().unwrap().string().unwrap(), "OS");
+        assert!(children.is_empty());
+        assert!(children.next().is_err());
+
+        assert!(DerReader::new(&encoded[..encoded.len() - 1]).next().is_err());
+        assert!(DerReader::new(&encoded).expect(TAG_SET).is_err());
+    }
+
+    #[test]
+    fn test_utc_time() {
+        assert_eq!(utc_time(0), der(TAG_UTC_TIME, b"700101000000Z"));
+        // Catalog of the test package
+        assert_eq!(utc_time(1710107759), der(TAG_UTC_TIME, b"240310215559Z"));
+        assert_eq!(utc_time(951782400), der(TAG_UTC_TIME, b"000229000000Z"));
+
+        let time = |encoded: &[u8]| DerReader::new(encoded).next().unwrap().time().unwrap();
+        assert_eq!(time(&utc_time(1710107759)), "2024-03-10 21:55:59 UTC");
+        assert_eq!(time(&der(TAG_UTC_TIME, b"991231235959Z")), "1999-12-31 23:59:59 UTC");
+        assert_eq!(time(&der(TAG_GENERALIZED_TIME, b"20240310215559.123Z")), "2024-03-10 21:55:59 UTC");
+        assert!(DerReader::new(&der(TAG_UTC_TIME, b"2403")).next().unwrap().time().is_err());
+    }
+}
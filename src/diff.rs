@@ -0,0 +1,188 @@
+//! Comparison of two packages by their blockmaps
+//!
+//! Only metadata is compared, no payload is read. Block hashes cover the
+//! stored (encrypted) data, so they are only comparable between packages
+//! sharing key and identity - otherwise every encrypted block shows up as
+//! changed while the file hash tells the content is the same.
+
+use std::collections::HashMap;
+
+use crate::{blockmap, utils, EAppxFile};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileChange {
+    /// Only present in the new package
+    Added,
+    /// Only present in the old package
+    Removed,
+    Unchanged,
+    Modified {
+        /// Indices of new-package blocks that differ from the old package
+        changed_blocks: Vec<usize>,
+        /// False if only the stored representation changed (e.g. other key)
+        content_changed: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub name: String,
+    pub change: FileChange,
+    /// Stored bytes the new package needs for this file on top of the old one
+    pub patch_size: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackageDiff {
+    pub files: Vec<FileDiff>,
+}
+
+impl PackageDiff {
+    pub fn added(&self) -> impl Iterator<Item = &FileDiff> {
+        self.files.iter().filter(|f| f.change == FileChange::Added)
+    }
+
+    pub fn removed(&self) -> impl Iterator<Item = &FileDiff> {
+        self.files.iter().filter(|f| f.change == FileChange::Removed)
+    }
+
+    pub fn modified(&self) -> impl Iterator<Item = &FileDiff> {
+        self.files.iter().filter(|f| matches!(f.change, FileChange::Modified { .. }))
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.files.iter().all(|f| f.change == FileChange::Unchanged)
+    }
+
+    /// Estimated amount of stored bytes an update from old to new has to transfer
+    pub fn patch_size(&self) -> u64 {
+        self.files.iter().map(|f| f.patch_size).sum()
+    }
+}
+
+impl std::fmt::Display for PackageDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in &self.files {
+            match &file.change {
+                FileChange::Unchanged => continue,
+                FileChange::Added => writeln!(f, "+ {}", file.name)?,
+                FileChange::Removed => writeln!(f, "- {}", file.name)?,
+                FileChange::Modified { changed_blocks, content_changed } => writeln!(f, "~ {} ({} blocks changed{})",
+                    file.name, changed_blocks.len(), if *content_changed { "" } else { ", content identical" })?,
+            }
+        }
+
+        writeln!(f, "{} added, {} removed, {} modified, estimated patch size: {}",
+            self.added().count(), self.removed().count(), self.modified().count(), utils::get_filesize_with_unit(self.patch_size()))
+    }
+}
+
+/// Stored size of every block of a file
+pub(crate) fn block_stored_sizes(eappx: &EAppxFile, file: &blockmap::File) -> Vec<u64> {
+    let encrypted = eappx.is_file_encrypted(file);
+
+    file.blocks.iter()
+        .enumerate()
+        .map(|(idx, block)| match block.size {
+            Some(size) => size as u64,
+            None => {
+                let len = std::cmp::min(utils::BLOCK_SIZE as u64, file.size - (idx * utils::BLOCK_SIZE) as u64);
+                match encrypted {
+                    true => utils::align_to_sector(len as usize) as u64,
+                    false => len,
+                }
+            },
+        })
+        .collect()
+}
+
+fn diff_file(old: &blockmap::File, new: &blockmap::File, new_sizes: &[u64]) -> (FileChange, u64) {
+    // Hashes are compared as written, malformed ones need no decoding
+    let changed_blocks = new.blocks
+        .iter()
+        .enumerate()
+        .filter(|(idx, block)| old.blocks.get(*idx).map(|old| &old.hash) != Some(&block.hash))
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+
+    let content_changed = match (&old.filehash, &new.filehash) {
+        (Some(old_hash), Some(new_hash)) => old_hash.hash != new_hash.hash || old.size != new.size,
+        _ => old.size != new.size || !changed_blocks.is_empty(),
+    };
+
+    // Shrinking files change without any differing block
+    if changed_blocks.is_empty() && old.size == new.size && old.blocks.len() == new.blocks.len() {
+        return (FileChange::Unchanged, 0);
+    }
+
+    let patch_size = changed_blocks.iter().filter_map(|idx| new_sizes.get(*idx)).sum();
+    (FileChange::Modified { changed_blocks, content_changed }, patch_size)
+}
+
+/// Compare the blockmaps of two packages, files are matched by name (case-insensitive)
+pub fn diff_packages(old: &EAppxFile, new: &EAppxFile) -> PackageDiff {
+    let old_files: HashMap<String, &blockmap::File> = old.blockmap.files
+        .iter()
+        .map(|f| (f.name.to_lowercase(), f))
+        .collect();
+    let new_names = new.blockmap.files
+        .iter()
+        .map(|f| f.name.to_lowercase())
+        .collect::<std::collections::HashSet<_>>();
+
+    let mut files = vec![];
+    for file in &new.blockmap.files {
+        let new_sizes = block_stored_sizes(new, file);
+        let (change, patch_size) = match old_files.get(&file.name.to_lowercase()) {
+            None => (FileChange::Added, new_sizes.iter().sum()),
+            Some(old_file) => diff_file(old_file, file, &new_sizes),
+        };
+        files.push(FileDiff { name: file.name.clone(), change, patch_size });
+    }
+
+    for file in &old.blockmap.files {
+        if !new_names.contains(&file.name.to_lowercase()) {
+            files.push(FileDiff { name: file.name.clone(), change: FileChange::Removed, patch_size: 0 });
+        }
+    }
+
+    PackageDiff { files }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_diff_identical() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let diff = diff_packages(&eappx, &eappx);
+
+        assert!(diff.is_identical());
+        assert_eq!(diff.patch_size(), 0);
+    }
+
+    #[test]
+    fn test_diff_changes() {
+        let old = EAppxFile::from_source(EMSIX).unwrap();
+        let mut new = EAppxFile::from_source(EMSIX).unwrap();
+
+        // Change block 3 of TestApp.dll, drop resources.pri, rename TestApp.exe
+        let dll = new.blockmap.files.iter_mut().find(|f| f.name == "TestApp.dll").unwrap();
+        dll.blocks[3].hash = dll.blocks[2].hash.clone();
+        dll.filehash.as_mut().unwrap().hash = dll.blocks[2].hash.clone();
+        new.blockmap.files.retain(|f| f.name != "resources.pri");
+        new.blockmap.files.iter_mut().find(|f| f.name == "TestApp.exe").unwrap().name = "Renamed.exe".into();
+
+        let diff = diff_packages(&old, &new);
+        let dll = diff.files.iter().find(|f| f.name == "TestApp.dll").unwrap();
+        assert_eq!(dll.change, FileChange::Modified { changed_blocks: vec![3], content_changed: true });
+        assert_eq!(dll.patch_size, utils::BLOCK_SIZE as u64);
+
+        assert_eq!(diff.added().map(|f| f.name.as_str()).collect::<Vec<_>>(), vec!["Renamed.exe"]);
+        assert_eq!(diff.removed().count(), 2);
+        assert_eq!(diff.patch_size(), utils::BLOCK_SIZE as u64 + 0x4800);
+    }
+}
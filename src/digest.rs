@@ -0,0 +1,147 @@
+//! Digests blockmaps name in their `HashMethod`
+//!
+//! Packages built by MakeAppx hash blocks and files with SHA-256, the schema
+//! allows SHA-384 and SHA-512 as well. The blockmap `HashMethod` applies to
+//! block hashes, a `FileHash` may name a method of its own.
+
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [Self; 3] = [Self::Sha256, Self::Sha384, Self::Sha512];
+
+    /// `HashMethod` URI as written in blockmaps
+    pub fn uri(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "http://www.w3.org/2001/04/xmlenc#sha256",
+            Self::Sha384 => "http://www.w3.org/2001/04/xmldsig-more#sha384",
+            Self::Sha512 => "http://www.w3.org/2001/04/xmlenc#sha512",
+        }
+    }
+
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.uri() == uri.trim())
+    }
+
+    /// Length of a digest in bytes
+    pub fn output_len(&self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+        }
+    }
+
+    pub fn from_output_len(len: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|algorithm| algorithm.output_len() == len)
+    }
+
+    /// Algorithm of a hash element with a digest of `digest_len` bytes
+    ///
+    /// The declared algorithm wins if its digests have that length, otherwise
+    /// the one producing digests of that length is used. Unknown lengths keep
+    /// the declared algorithm, the hash simply won't match.
+    pub fn select(declared: Option<Self>, digest_len: usize) -> Self {
+        match declared {
+            Some(algorithm) if algorithm.output_len() == digest_len => algorithm,
+            declared => Self::from_output_len(digest_len).or(declared).unwrap_or_default(),
+        }
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => Sha256::digest(data).to_vec(),
+            Self::Sha384 => Sha384::digest(data).to_vec(),
+            Self::Sha512 => Sha512::digest(data).to_vec(),
+        }
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Sha384 => Hasher::Sha384(Sha384::new()),
+            Self::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+}
+
+impl std::fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sha256 => write!(f, "SHA-256"),
+            Self::Sha384 => write!(f, "SHA-384"),
+            Self::Sha512 => write!(f, "SHA-512"),
+        }
+    }
+}
+
+/// Running digest of a [`HashAlgorithm`]
+#[derive(Clone, Debug)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha384(hasher) => hasher.update(data),
+            Self::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Sha384(hasher) => hasher.finalize().to_vec(),
+            Self::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+impl std::io::Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashAlgorithm;
+
+    #[test]
+    fn test_select() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(HashAlgorithm::from_uri(algorithm.uri()), Some(algorithm));
+            assert_eq!(algorithm.digest(b"data").len(), algorithm.output_len());
+
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"da");
+            hasher.update(b"ta");
+            assert_eq!(hasher.finalize(), algorithm.digest(b"data"));
+        }
+        assert_eq!(HashAlgorithm::from_uri("http://www.w3.org/2000/09/xmldsig#sha1"), None);
+
+        // The digest length overrules a declared algorithm that cannot have produced it
+        assert_eq!(HashAlgorithm::select(Some(HashAlgorithm::Sha512), 64), HashAlgorithm::Sha512);
+        assert_eq!(HashAlgorithm::select(Some(HashAlgorithm::Sha256), 48), HashAlgorithm::Sha384);
+        assert_eq!(HashAlgorithm::select(None, 64), HashAlgorithm::Sha512);
+        assert_eq!(HashAlgorithm::select(Some(HashAlgorithm::Sha384), 20), HashAlgorithm::Sha384);
+        assert_eq!(HashAlgorithm::select(None, 0), HashAlgorithm::Sha256);
+    }
+}
@@ -0,0 +1,116 @@
+//! Byte ranges of the container needed for a set of files
+//!
+//! With header, footer table and blockmap fetched, the planner tells which
+//! parts of a remote package to download for selected files, so tools can
+//! issue range requests instead of fetching the whole container. Ranges cover
+//! the payload as stored: compressed extents, encrypted data padded to sectors.
+
+use std::ops::Range;
+
+use crate::{error::Error, EAppxFile};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedFile {
+    pub name: String,
+    pub file_id: u64,
+    /// Where the payload is stored in the container
+    pub range: Range<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DownloadPlan {
+    /// In the order they were requested
+    pub files: Vec<PlannedFile>,
+}
+
+impl DownloadPlan {
+    /// Sorted ranges to download, ranges at most `max_gap` bytes apart are joined
+    ///
+    /// A larger gap downloads a few bytes too many in exchange for fewer requests.
+    pub fn ranges(&self, max_gap: u64) -> Vec<Range<u64>> {
+        let mut ranges = self.files.iter()
+            .map(|file| file.range.clone())
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<u64>> = vec![];
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end.saturating_add(max_gap) => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+
+    /// Bytes to download for [`DownloadPlan::ranges`] with the same `max_gap`
+    pub fn download_size(&self, max_gap: u64) -> u64 {
+        self.ranges(max_gap).iter().map(|range| range.end - range.start).sum()
+    }
+
+    /// Value of an HTTP `Range` header requesting [`DownloadPlan::ranges`]
+    pub fn http_range(&self, max_gap: u64) -> String {
+        let ranges = self.ranges(max_gap).iter()
+            .map(|range| format!("{}-{}", range.start, range.end - 1))
+            .collect::<Vec<_>>();
+        format!("bytes={}", ranges.join(","))
+    }
+}
+
+impl EAppxFile {
+    /// Plan downloading the blockmap files `names`, matched case-insensitively
+    pub fn plan_download<'a, I: IntoIterator<Item = &'a str>>(&self, names: I) -> Result<DownloadPlan, Error> {
+        let files = names.into_iter()
+            .map(|name| {
+                let file = self.blockmap.find_file(name)
+                    .ok_or(Error::DataError(format!("File {name} not found in blockmap")))?;
+                let footer = self.find_footer_for_file(file.id())
+                    .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+                let start = footer.offset_to_file;
+
+                Ok(PlannedFile {
+                    name: file.name.clone(),
+                    file_id: footer.file_id,
+                    range: start..start + self.stored_length(footer),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Ok(DownloadPlan { files })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_plan_download() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let plan = eappx.plan_download(["TestApp.dll", "clrcompression.dll", "testapp.exe"]).unwrap();
+
+        // Encrypted payloads are padded to whole sectors
+        assert_eq!(plan.files[1].name, "clrcompression.dll");
+        assert_eq!(plan.files[1].range, 0x53bf..0x159bf);
+        assert_eq!(plan.files[2].name, "TestApp.exe");
+
+        // resources.pri lies between clrcompression.dll and TestApp.dll
+        assert_eq!(plan.ranges(0), [0x53bf..0x159bf, 0x15f71..0x11b571]);
+        assert_eq!(plan.http_range(0x1000), "bytes=21439-1160560");
+        assert_eq!(plan.download_size(0), 0x10600 + 0x100e00 + 0x4800);
+        assert_eq!(plan.http_range(0), "bytes=21439-88510,89969-1160560");
+
+        // The planned ranges hold everything needed to verify the file
+        let mut partial = vec![0u8; EMSIX.len()];
+        for range in plan.ranges(0) {
+            partial[range.start as usize..range.end as usize].copy_from_slice(&EMSIX[range.start as usize..range.end as usize]);
+        }
+        let report = eappx.verify_entry(&partial, "TestApp.dll", 0..17).unwrap();
+        assert!(report.is_valid());
+
+        assert!(eappx.plan_download(["Missing.dll"]).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Editing a package in place
+//!
+//! [`ContainerEditor`] replaces, appends and removes single files of a parsed
+//! package without repacking it. New payloads are appended behind the existing
+//! data, [`ContainerEditor::commit`] then writes blockmap and footer table
+//! behind them and patches the header, the last write to the package. Until
+//! then the package on disk stays the old one, untouched payloads are never
+//! moved.
+//!
+//! Replaced and removed payloads, the old blockmap and footer table stay in
+//! the container as unreferenced space, a full repack reclaims it. Signature
+//! and code integrity catalog no longer match and are dropped from the header.
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use base64ct::{Base64, Encoding};
+
+use crate::{
+    blockmap,
+    digest::HashAlgorithm,
+    error::Error,
+    utils,
+    writer::{deflate_all, deflate_block, read_block, EntryOptions},
+    EAppxFile,
+    EAppxFooter,
+};
+
+/// Deflate level of rewritten payloads and blockmap, the writer's default
+const COMPRESSION_LEVEL: u32 = 6;
+
+/// Pending changes to a package, see the module documentation
+pub struct ContainerEditor<'a, S: Read + Write + Seek> {
+    eappx: &'a mut EAppxFile,
+    stream: &'a mut S,
+    /// Where the next payload goes
+    end: u64,
+    changed: bool,
+}
+
+impl EAppxFile {
+    /// Start editing the package `stream` holds, it was parsed into `self`
+    ///
+    /// Bundles reference their packages by offset from the bundle manifest
+    /// and cannot be edited this way.
+    pub fn edit<'a, S: Read + Write + Seek>(&'a mut self, stream: &'a mut S) -> Result<ContainerEditor<'a, S>, Error> {
+        if self.header.is_bundle() {
+            return Err(Error::DataError("Bundles cannot be edited in place".into()));
+        }
+        let end = stream.seek(SeekFrom::End(0))?;
+
+        Ok(ContainerEditor { eappx: self, stream, end, changed: false })
+    }
+}
+
+impl<S: Read + Write + Seek> ContainerEditor<'_, S> {
+    /// Replace the contents of `name`, keeping its compression and key
+    pub fn replace_file<R: Read>(&mut self, name: &str, reader: &mut R) -> Result<(), Error> {
+        let (index, file) = self.eappx.blockmap.find(name)
+            .ok_or_else(|| Error::DataError(format!("{name} not found in blockmap")))?;
+        let footer_index = self.eappx.footers.iter().position(|footer| footer.file_id == file.id())
+            .ok_or_else(|| Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+        let footer = &self.eappx.footers[footer_index];
+        let options = EntryOptions {
+            compress: footer.compression_type == 0x1,
+            key_index: footer.is_encrypted(false).then_some(footer.key_id_index),
+        };
+
+        let name = file.name.clone();
+        let (footer, file) = self.write_entry(&name, footer.file_id, reader, options)?;
+        log::info!("Replaced {name} ({:#x} bytes at {:#x})", file.size, footer.offset_to_file);
+        self.eappx.footers[footer_index] = footer;
+        self.eappx.blockmap.files[index] = blockmap::File { preserved: self.eappx.blockmap.files[index].preserved.clone(), ..file };
+        Ok(())
+    }
+
+    /// Add a new file, `name` is the package-relative path
+    pub fn append_file<R: Read>(&mut self, name: &str, reader: &mut R, options: EntryOptions) -> Result<(), Error> {
+        let name = name.replace('/', "\\");
+        if self.eappx.blockmap.find(&name).is_some() {
+            return Err(Error::DataError(format!("{name} already exists, replace it instead")));
+        }
+
+        // Renumbered by commit
+        let file_id = self.eappx.footers.iter().map(|footer| footer.file_id + 1).max().unwrap_or_default();
+        let (footer, file) = self.write_entry(&name, file_id, reader, options)?;
+        log::info!("Appended {name} ({:#x} bytes at {:#x})", file.size, footer.offset_to_file);
+        self.eappx.footers.push(footer);
+        self.eappx.blockmap.files.push(file);
+        Ok(())
+    }
+
+    /// Drop `name` from blockmap and footer table, its payload stays as unreferenced space
+    pub fn remove_file(&mut self, name: &str) -> Result<(), Error> {
+        let (index, file) = self.eappx.blockmap.find(name)
+            .ok_or_else(|| Error::DataError(format!("{name} not found in blockmap")))?;
+        if self.eappx.blockmap.manifest_file().is_some_and(|manifest| manifest.name == file.name) {
+            return Err(Error::DataError(format!("{} is the manifest, replace it instead", file.name)));
+        }
+
+        let file_id = file.id();
+        log::info!("Removed {}", file.name);
+        self.eappx.footers.retain(|footer| footer.file_id != file_id);
+        self.eappx.blockmap.files.remove(index);
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Encrypt, hash and compress `reader` into a payload at the end of the container
+    fn write_entry<R: Read>(&mut self, name: &str, file_id: u64, reader: &mut R, options: EntryOptions) -> Result<(EAppxFooter, blockmap::File), Error> {
+        let crypto = match options.key_index {
+            Some(key_index) => Some(self.eappx.crypto_context(key_index, name)?
+                .ok_or_else(|| Error::DataError(format!("Key of index {key_index} for {name} is not loaded")))?),
+            None => None,
+        };
+        let algorithm = self.eappx.blockmap.hash_algorithm().unwrap_or_default();
+        let mut filehash = algorithm.hasher();
+        let mut blocks = vec![];
+        let (mut size, mut stored) = (0u64, 0u64);
+
+        self.stream.seek(SeekFrom::Start(self.end))?;
+        let mut buf = read_block(reader)?;
+        for index in 0.. {
+            let next = match buf.len() {
+                utils::BLOCK_SIZE => read_block(reader)?,
+                _ => vec![],
+            };
+            if buf.is_empty() {
+                break;
+            }
+            filehash.update(&buf);
+            size += buf.len() as u64;
+
+            // Block hashes cover the uncompressed, encrypted data
+            blockmap::stored_block(&mut buf, index as u64, crypto.as_ref());
+            let hash = blockmap::encode_hash(&algorithm.digest(&buf));
+            if options.compress {
+                buf = deflate_block(&buf, next.is_empty(), COMPRESSION_LEVEL)?;
+            }
+            let block_size = match options.compress {
+                true => Some(u32::try_from(buf.len())
+                    .map_err(|_| Error::DataError(format!("Compressed block of {name} too large for blockmap ({:#x})", buf.len())))?),
+                false => None,
+            };
+            self.stream.write_all(&buf)?;
+            stored += buf.len() as u64;
+            blocks.push(blockmap::Block { hash, size: block_size, ..Default::default() });
+
+            buf = next;
+        }
+
+        let compress = options.compress && size > 0;
+        let footer = EAppxFooter::new(file_id, self.end, options.key_index, compress, size, if compress { stored } else { size });
+        let file = blockmap::File {
+            name: name.to_owned(),
+            id: format!("{file_id:X}"),
+            size,
+            encrypted: Some(options.key_index.is_some()),
+            blocks,
+            filehash: Some(blockmap::FileHash { hash: Base64::encode_string(&filehash.finalize()), ..Default::default() }),
+            ..Default::default()
+        };
+
+        self.end += stored;
+        self.changed = true;
+        Ok((footer, file))
+    }
+
+    /// Write blockmap, footer table and header, nothing changed on disk without it
+    pub fn commit(self) -> Result<(), Error> {
+        if !self.changed {
+            return Ok(());
+        }
+        let eappx = self.eappx;
+        let old_blockmap_id = eappx.header.block_map_file_id;
+
+        // Files in blockmap order, then footers nothing refers to, the blockmap last.
+        // Its file id is also its index in the table, ids are renumbered to match.
+        let referenced = eappx.blockmap.files.iter().map(|file| file.id()).collect::<Vec<_>>();
+        let mut footers = vec![];
+        for file in eappx.blockmap.files.iter_mut() {
+            let mut footer = eappx.footers.iter().find(|footer| footer.file_id == file.id())
+                .ok_or_else(|| Error::DataError(format!("Failed to find footer for file {}", file.name)))?
+                .clone();
+            footer.file_id = footers.len() as u64;
+            file.id = format!("{:X}", footer.file_id);
+            footers.push(footer);
+        }
+        for footer in &eappx.footers {
+            if footer.file_id != old_blockmap_id && !referenced.contains(&footer.file_id) {
+                log::warn!("Keeping footer {:#x} no blockmap file refers to", footer.file_id);
+                footers.push(EAppxFooter { file_id: footers.len() as u64, ..footer.clone() });
+            }
+        }
+
+        let xml = eappx.blockmap.to_xml();
+        let blockmap_hash = HashAlgorithm::from_uri(&eappx.header.block_map_hash_algo()).unwrap_or_default().digest(xml.as_bytes());
+        let data = deflate_all(xml.as_bytes(), COMPRESSION_LEVEL)?;
+        let blockmap_id = footers.len() as u64;
+        footers.push(EAppxFooter::new(blockmap_id, self.end, None, true, xml.len() as u64, data.len() as u64));
+
+        let mut table = vec![];
+        for footer in &footers {
+            table.extend(footer.to_bytes()?);
+        }
+        self.stream.seek(SeekFrom::Start(self.end))?;
+        self.stream.write_all(&data)?;
+        self.stream.write_all(&table)?;
+
+        let mut header = eappx.header.clone();
+        if header.is_signed() || header.is_code_integrity_protected() {
+            log::warn!("Signature and code integrity catalog are dropped while editing");
+        }
+        header.signature_offset = 0;
+        header.signature_compression_type = 0;
+        header.signature_uncompressed_length = 0;
+        header.signature_length = 0;
+        header.code_integrity_offset = 0;
+        header.code_integrity_compression_type = 0;
+        header.code_integrity_uncompressed_length = 0;
+        header.code_integrity_length = 0;
+        header.footer_offset = self.end + data.len() as u64;
+        header.footer_length = table.len() as u64;
+        header.file_count = footers.len() as u64;
+        header.block_map_file_id = blockmap_id;
+        header.block_map_hash = blockmap_hash;
+
+        let bytes = header.to_bytes()?;
+        if bytes.len() != header.header_size as usize {
+            return Err(Error::DataError(format!("Header size changed from {:#x} to {:#x}", header.header_size, bytes.len())));
+        }
+        self.stream.seek(SeekFrom::Start(0))?;
+        self.stream.write_all(&bytes)?;
+        self.stream.flush()?;
+
+        eappx.file_len = header.footer_offset + header.footer_length;
+        eappx.header = header;
+        eappx.footers = footers;
+        eappx.manifest = std::sync::OnceLock::new();
+        if let Some(cache) = eappx.block_cache.as_ref() {
+            cache.clear();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::sample::sample_keys;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    fn open(data: &[u8]) -> EAppxFile {
+        let mut eappx = EAppxFile::from_source(&data).unwrap();
+        eappx.load_keys(&sample_keys()).unwrap();
+        eappx
+    }
+
+    fn read(eappx: &EAppxFile, data: &[u8], name: &str) -> Vec<u8> {
+        let file = eappx.blockmap.find_file(name).unwrap();
+        let mut plaintext = vec![];
+        eappx.file_reader(&data, file).unwrap().read_to_end(&mut plaintext).unwrap();
+        plaintext
+    }
+
+    #[test]
+    fn test_edit() {
+        let original = open(EMSIX);
+        let untouched = original.blockmap.find_file("clrcompression.dll").unwrap();
+        let untouched_footer = original.find_footer_for_file(untouched.id()).unwrap().clone();
+        assert!(untouched.is_encrypted());
+
+        let mut stream = Cursor::new(EMSIX.to_vec());
+        let mut eappx = open(EMSIX);
+        let mut editor = eappx.edit(&mut stream).unwrap();
+        let hotfix = (0..utils::BLOCK_SIZE * 2 + 0x35).map(|i| (i % 253) as u8).collect::<Vec<_>>();
+        editor.replace_file("TestApp.exe", &mut hotfix.as_slice()).unwrap();
+        editor.append_file("Data/new.txt", &mut &b"appended"[..], EntryOptions { compress: true, key_index: None }).unwrap();
+        editor.append_file("Data\\secret.bin", &mut hotfix.as_slice(), EntryOptions { compress: false, key_index: Some(0) }).unwrap();
+        editor.remove_file("Assets\\SplashScreen.scale-200.png").unwrap();
+        assert!(editor.remove_file("AppxManifest.xml").is_err());
+        assert!(editor.append_file("TestApp.exe", &mut &b""[..], EntryOptions::default()).is_err());
+        editor.commit().unwrap();
+
+        let data = stream.into_inner();
+        let edited = open(&data);
+        assert!(edited.verify(data.as_slice()).is_valid());
+        assert_eq!(edited.header.footer_offset, eappx.header.footer_offset);
+        assert_eq!(edited.blockmap.files.len(), original.blockmap.files.len() + 1);
+        assert!(edited.blockmap.find_file("Assets\\SplashScreen.scale-200.png").is_none());
+        assert!(!edited.header.is_code_integrity_protected());
+
+        assert_eq!(read(&edited, &data, "TestApp.exe"), hotfix);
+        assert_eq!(read(&edited, &data, "Data\\new.txt"), b"appended");
+        assert_eq!(read(&edited, &data, "Data\\secret.bin"), hotfix);
+        assert_eq!(read(&edited, &data, "clrcompression.dll"), read(&original, EMSIX, "clrcompression.dll"));
+
+        // Untouched payloads stay where they were
+        let untouched = edited.blockmap.find_file("clrcompression.dll").unwrap();
+        assert_eq!(edited.find_footer_for_file(untouched.id()).unwrap().offset_to_file, untouched_footer.offset_to_file);
+        assert_eq!(data[..EMSIX.len()][untouched_footer.offset_to_file as usize..], EMSIX[untouched_footer.offset_to_file as usize..]);
+    }
+
+    #[test]
+    fn test_edit_uncommitted() {
+        let mut stream = Cursor::new(EMSIX.to_vec());
+        let mut eappx = open(EMSIX);
+        eappx.edit(&mut stream).unwrap().replace_file("AppxManifest.xml", &mut &b"<Package/>"[..]).unwrap();
+
+        // Only appended to, the header still describes the old package
+        let data = stream.into_inner();
+        assert_eq!(data[..EMSIX.len()], *EMSIX);
+        assert!(open(&data).verify(data.as_slice()).is_valid());
+    }
+}
@@ -0,0 +1,145 @@
+//! One row per blockmap entry, for listings and spreadsheets
+//!
+//! Names, sizes, hashes and flags come from blockmap and footer table alone,
+//! no payload is read. [`write_csv`] writes the rows as RFC 4180 CSV, so QA
+//! can compare the contents of two builds in a spreadsheet.
+
+use std::io::Write;
+
+use crate::{digest::HashAlgorithm, error::Error, keys::KeyId, EAppxFile};
+
+/// Column names of [`write_csv`], in order
+pub const CSV_HEADER: [&str; 11] = [
+    "name",
+    "file_id",
+    "size",
+    "stored",
+    "offset",
+    "compressed",
+    "encrypted",
+    "key_id",
+    "blocks",
+    "hash_algorithm",
+    "filehash",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryRow {
+    pub name: String,
+    pub file_id: u64,
+    /// Uncompressed size from the blockmap
+    pub size: u64,
+    /// Bytes occupied in the container, `None` without a footer
+    pub stored: Option<u64>,
+    /// Offset of the payload, `None` without a footer
+    pub offset: Option<u64>,
+    pub compressed: bool,
+    pub encrypted: bool,
+    /// Key the file is encrypted with, `None` for plain files or unknown key indices
+    pub key_id: Option<KeyId>,
+    pub blocks: usize,
+    pub hash_algorithm: HashAlgorithm,
+    /// File hash over the plaintext, `None` if the blockmap has none
+    pub filehash: Option<Vec<u8>>,
+}
+
+impl EntryRow {
+    /// Fields in the order of [`CSV_HEADER`]
+    pub fn csv_fields(&self) -> [String; 11] {
+        let optional = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+        [
+            self.name.clone(),
+            format!("{:#x}", self.file_id),
+            self.size.to_string(),
+            optional(self.stored),
+            optional(self.offset),
+            self.compressed.to_string(),
+            self.encrypted.to_string(),
+            self.key_id.as_ref().map(KeyId::to_keyfile_string).unwrap_or_default(),
+            self.blocks.to_string(),
+            self.hash_algorithm.to_string(),
+            self.filehash.as_ref().map(hex::encode).unwrap_or_default(),
+        ]
+    }
+}
+
+impl EAppxFile {
+    /// A row for every blockmap file, in blockmap order
+    pub fn entry_rows(&self) -> Result<Vec<EntryRow>, Error> {
+        self.blockmap.files.iter()
+            .map(|file| {
+                let footer = self.find_footer_for_file(file.id());
+                let encrypted = self.is_file_encrypted(file);
+                Ok(EntryRow {
+                    name: file.name.clone(),
+                    file_id: file.id(),
+                    size: file.size,
+                    stored: footer.map(|footer| self.stored_length(footer)),
+                    offset: footer.map(|footer| footer.offset_to_file),
+                    compressed: footer.is_some_and(|footer| footer.compression_type == 0x1),
+                    encrypted,
+                    key_id: footer
+                        .filter(|_| encrypted)
+                        .and_then(|footer| self.header.key_ids.get(footer.key_id_index as usize))
+                        .cloned(),
+                    blocks: file.blocks.len(),
+                    hash_algorithm: self.blockmap.filehash_algorithm(file),
+                    filehash: file.filehash_bytes()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Quote `field` if it holds a separator, quote or line break
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    match field.contains([',', '"', '\r', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")).into(),
+        false => field.into(),
+    }
+}
+
+/// Write a header line and one line per row, lines end in CRLF
+pub fn write_csv<W: Write>(rows: &[EntryRow], mut writer: W) -> std::io::Result<()> {
+    writeln!(writer, "{}\r", CSV_HEADER.join(","))?;
+    for row in rows {
+        let fields = row.csv_fields();
+        let line = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",");
+        writeln!(writer, "{line}\r")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_entry_rows() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let rows = eappx.entry_rows().unwrap();
+        assert_eq!(rows.len(), eappx.blockmap.files.len());
+
+        let dll = rows.iter().find(|row| row.name == "TestApp.dll").unwrap();
+        assert!(dll.encrypted && !dll.compressed);
+        assert_eq!(dll.key_id.as_ref(), Some(&eappx.header.key_ids[0]));
+        assert_eq!(dll.stored, Some(dll.size.next_multiple_of(0x200)));
+        assert_eq!(dll.filehash.as_ref().map(Vec::len), Some(32));
+
+        let mut csv = vec![];
+        write_csv(&rows, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(csv.lines().count(), rows.len() + 1);
+        assert!(csv.starts_with("name,file_id,size,stored,offset,compressed,encrypted,key_id,blocks,hash_algorithm,filehash\r\n"));
+        assert!(csv.contains(&format!("\r\nTestApp.dll,{:#x},{},", dll.file_id, dll.size)));
+    }
+
+    #[test]
+    fn test_csv_quoting() {
+        assert_eq!(csv_field("Assets\\Logo.png"), "Assets\\Logo.png");
+        assert_eq!(csv_field("a,b.txt"), "\"a,b.txt\"");
+        assert_eq!(csv_field("say \"hi\".txt"), "\"say \"\"hi\"\".txt\"");
+    }
+}
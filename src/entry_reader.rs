@@ -0,0 +1,348 @@
+//! Random access to the plaintext of a blockmap file
+//!
+//! Every 64 KiB block of a file is stored on its own: uncompressed blocks sit
+//! at fixed offsets, compressed ones end on a flush and the blockmap records
+//! their stored sizes. So any block can be read, checked against its block
+//! hash, inflated and decrypted without touching the blocks before it, which
+//! lets [`EntryReader`] seek freely, e.g. to back a virtual filesystem.
+
+use std::{
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use crate::{
+    block_cache::BlockCache,
+    blockmap,
+    container::ContainerParams,
+    crypto::CryptoFileContext,
+    digest::HashAlgorithm,
+    error::{EntryContext, Error, Operation},
+    source::PackageSource,
+    utils, EAppxFile, EAppxFooter,
+};
+
+/// Plaintext of one blockmap file, decrypted and inflated block by block
+pub struct EntryReader<'a, S: PackageSource> {
+    source: S,
+    name: String,
+    footer: &'a EAppxFooter,
+    size: u64,
+    block_hashes: Vec<Vec<u8>>,
+    block_hash_algorithm: HashAlgorithm,
+    check_blocks: bool,
+    /// Where each block is stored, relative to the payload, `None` if uncompressed
+    block_offsets: Option<Vec<u64>>,
+    encrypted: bool,
+    crypto: Option<CryptoFileContext>,
+    params: ContainerParams,
+    pos: u64,
+    /// Index and plaintext of the block read last
+    block: Option<(usize, Vec<u8>)>,
+    cache: Option<&'a BlockCache>,
+    stored: Vec<u8>,
+    decompress: flate2::Decompress,
+}
+
+/// Where one block of a blockmap file is stored
+///
+/// Compressed blocks end on a flush, so inflating can start at any of them.
+/// Encrypted blocks inflate to their plaintext padded to whole sectors and
+/// decrypt with sector numbers starting at `plain.start / sector_size`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockExtent {
+    /// Bytes of the plaintext the block holds
+    pub plain: Range<u64>,
+    /// Bytes of the container the block is stored in
+    pub stored: Range<u64>,
+}
+
+/// Where each compressed block starts, relative to the payload, `None` if uncompressed
+fn compressed_block_offsets(file: &blockmap::File, footer: &EAppxFooter) -> Result<Option<Vec<u64>>, Error> {
+    match (footer.compression_type == 0x1, file.block_sizes()) {
+        (false, _) => Ok(None),
+        (true, Some(sizes)) if sizes.len() == file.blocks.len() => Ok(Some(
+            sizes.iter()
+                .scan(0u64, |offset, size| {
+                    let start = *offset;
+                    *offset += *size as u64;
+                    Some(start)
+                })
+                .collect()
+        )),
+        (true, _) => Err(Error::DataError(format!("Blocks of {} cannot be located without their stored sizes", file.name))),
+    }
+}
+
+impl EAppxFile {
+    /// Stored extent of every block of the blockmap file `name`, matched case-insensitively
+    ///
+    /// Lets a reader fetch and inflate a single block instead of the payload
+    /// from the start. Compressed files need the stored block sizes
+    /// from the blockmap.
+    pub fn block_extents(&self, name: &str) -> Result<Vec<BlockExtent>, Error> {
+        let file = self.blockmap.find_file(name)
+            .ok_or(Error::DataError(format!("File {name} not found in blockmap")))?;
+        let footer = self.find_footer_for_file(file.id())
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+        let offsets = compressed_block_offsets(file, footer)?;
+        let encrypted = self.is_footer_encrypted(footer);
+        let block_size = self.params.block_size() as u64;
+
+        let extents = (0..file.blocks.len())
+            .map(|index| {
+                let start = index as u64 * block_size;
+                let plain = start..std::cmp::min(start + block_size, file.size);
+                let stored = match &offsets {
+                    Some(offsets) => offsets[index]..offsets.get(index + 1).copied().unwrap_or(footer.compressed_length),
+                    None if encrypted => start..start + self.params.align_to_sector((plain.end - plain.start) as usize) as u64,
+                    None => plain.clone(),
+                };
+                BlockExtent {
+                    plain,
+                    stored: footer.offset_to_file + stored.start..footer.offset_to_file + stored.end,
+                }
+            })
+            .collect();
+
+        Ok(extents)
+    }
+
+    /// Seekable reader over the plaintext of the blockmap file `name`, matched case-insensitively
+    ///
+    /// Block hashes are checked for every block read unless [`EAppxFile::integrity`]
+    /// skips them, the file hash never is.
+    /// Compressed files need the stored block sizes from the blockmap, encrypted
+    /// ones a loaded key.
+    pub fn entry_reader<S: PackageSource>(&self, source: S, name: &str) -> Result<EntryReader<'_, S>, Error> {
+        let file = self.blockmap.find_file(name)
+            .ok_or(Error::DataError(format!("File {name} not found in blockmap")))?;
+        let footer = self.find_footer_for_file(file.id())
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+
+        let block_hashes = file.block_hashes()?;
+        let block_offsets = compressed_block_offsets(file, footer)?;
+
+        let encrypted = self.is_footer_encrypted(footer);
+        let crypto = self.crypto_context(footer.key_id_index, &file.name)?.filter(|_| encrypted);
+        if encrypted && crypto.is_none() {
+            return Err(Error::MissingKey {
+                key_ids: self.header.key_ids.get(footer.key_id_index as usize).cloned().into_iter().collect(),
+                files: vec![file.name.clone()],
+            });
+        }
+
+        Ok(EntryReader {
+            source,
+            name: file.name.clone(),
+            footer,
+            size: file.size,
+            block_hashes,
+            block_hash_algorithm: self.blockmap.block_hash_algorithm(file),
+            check_blocks: self.integrity.checks_blocks(),
+            block_offsets,
+            encrypted,
+            crypto,
+            params: self.params,
+            pos: 0,
+            block: None,
+            cache: self.block_cache.as_deref(),
+            stored: vec![],
+            decompress: flate2::Decompress::new(false),
+        })
+    }
+}
+
+impl<S: PackageSource> EntryReader<'_, S> {
+    /// Plaintext size of the file
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Read, check and decrypt block `index`, unless the block cache has it
+    fn load_block(&mut self, index: usize) -> Result<(), Error> {
+        let pos = index * self.params.block_size();
+        let plain_amount = std::cmp::min(self.params.block_size() as u64, self.size - pos as u64) as usize;
+        let read_amount = match self.encrypted {
+            true => self.params.align_to_sector(plain_amount),
+            false => plain_amount,
+        };
+        let context = |operation| EntryContext {
+            operation,
+            name: Some(self.name.clone()),
+            file_id: Some(self.footer.file_id),
+            offset: self.footer.offset_to_file,
+            position: pos as u64,
+        };
+
+        let mut buf = self.block.take().map(|(_, buf)| buf).unwrap_or_default();
+        if let Some(cached) = self.cache.and_then(|cache| cache.get((self.footer.file_id, index))) {
+            buf.clear();
+            buf.extend_from_slice(&cached);
+            self.block = Some((index, buf));
+            return Ok(());
+        }
+        buf.resize(read_amount, 0);
+        match &self.block_offsets {
+            Some(offsets) => {
+                let end = offsets.get(index + 1).copied()
+                    .unwrap_or(self.footer.compressed_length);
+                self.stored.resize(end.saturating_sub(offsets[index]) as usize, 0);
+                self.source.read_exact_at(self.footer.offset_to_file + offsets[index], &mut self.stored)
+                    .map_err(|e| context(Operation::Read).wrap(e.into()))?;
+                self.decompress.reset(false);
+                if !utils::inflate_block(&mut self.decompress, &self.stored, &mut buf) {
+                    return Err(context(Operation::Inflate).wrap(Error::DataError(format!("Failed to inflate block {index}"))));
+                }
+            },
+            None => {
+                self.source.read_exact_at(self.footer.offset_to_file + pos as u64, &mut buf)
+                    .map_err(|e| context(Operation::Read).wrap(e.into()))?;
+            },
+        }
+
+        if self.check_blocks && self.block_hashes.get(index).is_some_and(|hash| self.block_hash_algorithm.digest(&buf) != *hash) {
+            return Err(context(Operation::Verify).wrap(Error::DataError(format!("Invalid block hash for block {index}"))));
+        }
+        if let Some(crypto) = &self.crypto {
+            crypto.decrypt_at(&mut buf, pos as u64);
+        }
+
+        buf.truncate(plain_amount);
+        if let Some(cache) = self.cache {
+            cache.insert((self.footer.file_id, index), &buf);
+        }
+        self.block = Some((index, buf));
+        Ok(())
+    }
+}
+
+impl<S: PackageSource> Read for EntryReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.size {
+            return Ok(0);
+        }
+
+        let index = (self.pos / self.params.block_size() as u64) as usize;
+        if self.block.as_ref().is_none_or(|(cached, _)| *cached != index) {
+            self.load_block(index).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        }
+
+        let (_, block) = self.block.as_ref().unwrap();
+        let start = (self.pos % self.params.block_size() as u64) as usize;
+        let amount = std::cmp::min(buf.len(), block.len() - start);
+        buf[..amount].copy_from_slice(&block[start..start + amount]);
+        self.pos += amount as u64;
+        Ok(amount)
+    }
+}
+
+impl<S: PackageSource> Seek for EntryReader<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+        self.pos = target.ok_or(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek before the start of the file"))?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    use crate::{
+        utils,
+        writer::{EntryOptions, PackageWriter, WriterOptions},
+        EAppxFile,
+        EAppxMagic,
+    };
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_entry_reader() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let err = eappx.entry_reader(EMSIX, "TestApp.dll").err().unwrap();
+        assert_eq!(err.kind(), crate::error::ErrorKind::MissingKey);
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        // Encrypted and uncompressed, compressed
+        for name in ["TestApp.dll", "AppxManifest.xml"] {
+            let file = eappx.blockmap.find_file(name).unwrap();
+            let mut plaintext = vec![];
+            eappx.file_reader(EMSIX, file).unwrap().read_to_end(&mut plaintext).unwrap();
+
+            let mut reader = eappx.entry_reader(EMSIX, name).unwrap();
+            assert_eq!(reader.len(), plaintext.len() as u64);
+            let offsets = [plaintext.len() - 1, 0x10000 - 3, 0, plaintext.len() / 2];
+            for offset in offsets.into_iter().filter(|offset| *offset < plaintext.len()) {
+                let mut buf = [0u8; 0x20];
+                reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+                let mut read = 0;
+                while let Ok(amount @ 1..) = reader.read(&mut buf[read..]) {
+                    read += amount;
+                }
+                let end = std::cmp::min(offset + 0x20, plaintext.len());
+                assert_eq!(buf[..read], plaintext[offset..end], "{name} at {offset:#x}");
+            }
+
+            let mut all = vec![];
+            reader.seek(SeekFrom::Start(0)).unwrap();
+            reader.read_to_end(&mut all).unwrap();
+            assert_eq!(all, plaintext);
+        }
+
+        let mut data = EMSIX.to_vec();
+        let footer = eappx.find_footer_for_file(0xa).unwrap();
+        data[footer.offset_to_file as usize + 0x10010] ^= 0xFF;
+        let mut reader = eappx.entry_reader(&data, "TestApp.dll").unwrap();
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        reader.seek(SeekFrom::Start(0x10000)).unwrap();
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_block_extents() {
+        let keys = vec![(crate::sample::TEST_KEY_ID, crate::sample::TEST_KEY.to_vec())];
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, &crate::sample::sample_package_full_name(), keys, WriterOptions::default()).unwrap();
+        let data = (0..0x2C000u32).map(|i| (i / 0x300) as u8).collect::<Vec<_>>();
+        writer.add_entry("Data\\both.bin", &mut data.as_slice(), EntryOptions { compress: true, key_index: Some(0) }).unwrap();
+        let package = writer.finish().unwrap().into_inner();
+
+        let mut eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let footer = eappx.find_footer_for_file(eappx.blockmap.find_file("Data\\both.bin").unwrap().id()).unwrap().clone();
+        let crypto = eappx.crypto_context(footer.key_id_index, "Data\\both.bin").unwrap().unwrap();
+
+        let extents = eappx.block_extents("Data\\both.bin").unwrap();
+        assert_eq!(extents.len(), 3);
+        assert_eq!(extents.last().unwrap().stored.end, footer.offset_to_file + footer.compressed_length);
+
+        // Any block on its own, back to front
+        for extent in extents.iter().rev() {
+            let plain_len = (extent.plain.end - extent.plain.start) as usize;
+            let mut block = vec![0u8; utils::align_to_sector(plain_len)];
+            let stored = &package[extent.stored.start as usize..extent.stored.end as usize];
+            assert!(utils::inflate_block(&mut flate2::Decompress::new(false), stored, &mut block));
+            crypto.decrypt_area(&mut block, (extent.plain.start / utils::SECTOR_SIZE as u64) as u128);
+            assert_eq!(block[..plain_len], data[extent.plain.start as usize..extent.plain.end as usize]);
+        }
+
+        // Uncompressed blocks are padded to sectors when encrypted
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let file = eappx.blockmap.find_file("TestApp.dll").unwrap();
+        let extents = eappx.block_extents("TestApp.dll").unwrap();
+        assert_eq!(extents.len(), file.blocks.len());
+        assert_eq!(extents.last().unwrap().plain.end, file.size);
+        assert!(extents.iter().all(|extent| (extent.stored.end - extent.stored.start) % utils::SECTOR_SIZE as u64 == 0));
+        assert!(eappx.block_extents("missing.bin").is_err());
+    }
+}
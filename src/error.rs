@@ -1,11 +1,239 @@
 use thiserror::Error;
 
+use crate::keys::KeyId;
+
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("Decode error")]
+    #[error("Decode error: {0}")]
     DecodeError(String),
-    #[error("I/O Error")]
+    /// Decode error caused by a lower level one, e.g. from binrw or the XML parser
+    #[error("Decode error: {message}: {source}")]
+    Decode {
+        message: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
-    #[error("Invalid data error")]
+    #[error("Invalid data: {0}")]
     DataError(String),
-}
\ No newline at end of file
+    /// Invalid data reported by a lower level error, e.g. a failing zip or deflate stream
+    #[error("Invalid data: {message}: {source}")]
+    Data {
+        message: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("Unsupported header version {0:#x}")]
+    UnsupportedVersion(u64),
+    #[error("{what} ({offset:#x}+{length:#x}) exceeds package size {file_len:#x}")]
+    OutOfBounds {
+        what: String,
+        offset: u64,
+        length: u64,
+        file_len: u64,
+    },
+    #[error("{what} exceeds limit ({value:#x} > {limit:#x})")]
+    LimitExceeded {
+        what: String,
+        value: u64,
+        limit: u64,
+    },
+    #[error("{what} declares implausible uncompressed length {uncompressed_length:#x} ({stored_length:#x} stored)")]
+    ImplausibleLength {
+        what: String,
+        uncompressed_length: u64,
+        stored_length: u64,
+    },
+    /// Encrypted content whose key is not loaded
+    #[error("Missing key(s) {} for {}", .key_ids.iter().map(KeyId::to_keyfile_string).collect::<Vec<_>>().join(", "), .files.join(", "))]
+    MissingKey {
+        key_ids: Vec<KeyId>,
+        files: Vec<String>,
+    },
+    #[error("Failed to {context}: {source}")]
+    Entry {
+        context: Box<EntryContext>,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+/// Category of an [`Error`], see [`Error::kind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Reading or writing failed
+    Io,
+    /// Structure that does not parse: header, footers, XML, DER, keyfile, or
+    /// a layout that does not fit the package, e.g. regions outside of it
+    Decode,
+    /// Parsed but inconsistent or corrupt content, e.g. hash mismatches
+    Data,
+    /// Valid data this crate does not handle
+    Unsupported,
+    /// Exceeds a [`ResourceLimits`](crate::limits::ResourceLimits) limit
+    Limit,
+    /// Key needed for encrypted content is not loaded
+    MissingKey,
+}
+
+impl Error {
+    /// Decode error keeping `source` as its cause
+    pub(crate) fn decode(message: impl Into<String>, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Error::Decode { message: message.into(), source: Box::new(source) }
+    }
+
+    /// Data error keeping `source` as its cause
+    #[cfg_attr(not(feature = "package"), allow(dead_code))]
+    pub(crate) fn data(message: impl Into<String>, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        Error::Data { message: message.into(), source: Box::new(source) }
+    }
+
+    /// Category of the error, entry context is looked through
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::IoError(_) => ErrorKind::Io,
+            Error::DecodeError(_) | Error::Decode { .. } | Error::OutOfBounds { .. } | Error::ImplausibleLength { .. } => ErrorKind::Decode,
+            Error::DataError(_) | Error::Data { .. } => ErrorKind::Data,
+            Error::UnsupportedVersion(_) => ErrorKind::Unsupported,
+            Error::LimitExceeded { .. } => ErrorKind::Limit,
+            Error::MissingKey { .. } => ErrorKind::MissingKey,
+            Error::Entry { source, .. } => source.kind(),
+        }
+    }
+
+    /// Worth skipping or retrying: failures confined to one entry and
+    /// transient I/O errors. Anything else needs a different package, keys or
+    /// limits before another attempt.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            Error::Entry { .. } => true,
+            Error::IoError(e) => matches!(e.kind(),
+                std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut),
+            _ => false,
+        }
+    }
+
+    /// Entry the error occurred in, if it happened while processing one
+    pub fn entry(&self) -> Option<&EntryContext> {
+        match self {
+            Error::Entry { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The error itself, without entry context
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::Entry { source, .. } => source.root_cause(),
+            other => other,
+        }
+    }
+}
+
+/// Step of processing an entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Read,
+    Decrypt,
+    Inflate,
+    Verify,
+    /// Writing the extracted data
+    Write,
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::Read => "read",
+            Operation::Decrypt => "decrypt",
+            Operation::Inflate => "inflate",
+            Operation::Verify => "verify",
+            Operation::Write => "write",
+        })
+    }
+}
+
+/// Where processing an entry failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryContext {
+    pub operation: Operation,
+    /// Package path, unknown when reading a bare footer
+    pub name: Option<String>,
+    /// Footer table id
+    pub file_id: Option<u64>,
+    /// Offset of the stored entry in the package
+    pub offset: u64,
+    /// Position in the uncompressed entry data
+    pub position: u64,
+}
+
+impl EntryContext {
+    /// Wrap `source`, an error that already carries context is passed through
+    #[cfg_attr(not(feature = "package"), allow(dead_code))]
+    pub(crate) fn wrap(self, source: Error) -> Error {
+        match source {
+            Error::Entry { .. } => source,
+            source => Error::Entry { context: Box::new(self), source: Box::new(source) },
+        }
+    }
+}
+
+impl std::fmt::Display for EntryContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.operation, self.name.as_deref().unwrap_or("entry"))?;
+        if let Some(file_id) = self.file_id {
+            write!(f, " (id {file_id:#x})")?;
+        }
+        write!(f, " at position {:#x} (stored at {:#x})", self.position, self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_context() {
+        let context = EntryContext {
+            operation: Operation::Inflate,
+            name: Some("TestApp.dll".into()),
+            file_id: Some(0xa),
+            offset: 0x1000,
+            position: 0x10000,
+        };
+        let err = context.clone().wrap(Error::DataError("corrupt deflate stream".into()));
+        assert_eq!(err.to_string(), "Failed to inflate TestApp.dll (id 0xa) at position 0x10000 (stored at 0x1000): Invalid data: corrupt deflate stream");
+        assert_eq!(err.entry(), Some(&context));
+        assert!(matches!(err.root_cause(), Error::DataError(_)));
+
+        // Innermost context wins
+        let outer = EntryContext { operation: Operation::Read, ..context.clone() };
+        assert_eq!(outer.wrap(err).entry(), Some(&context));
+    }
+
+    #[test]
+    fn test_kind() {
+        let source = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "short read");
+        let err = Error::decode("Failed to read header", source);
+        assert_eq!(err.to_string(), "Decode error: Failed to read header: short read");
+        assert_eq!(err.kind(), ErrorKind::Decode);
+        assert!(!err.is_recoverable());
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.downcast_ref::<std::io::Error>().unwrap().kind(), std::io::ErrorKind::UnexpectedEof);
+
+        assert_eq!(Error::OutOfBounds { what: "Footer table".into(), offset: 0x10, length: 0x20, file_len: 0x18 }.kind(), ErrorKind::Decode);
+        assert_eq!(Error::UnsupportedVersion(2).kind(), ErrorKind::Unsupported);
+        assert_eq!(Error::LimitExceeded { what: "Footer count".into(), value: 2, limit: 1 }.kind(), ErrorKind::Limit);
+        assert!(Error::IoError(std::io::ErrorKind::Interrupted.into()).is_recoverable());
+
+        let err = Error::MissingKey { key_ids: vec![KeyId::Numeric(1)], files: vec!["TestApp.dll".into(), "TestApp.exe".into()] };
+        assert_eq!(err.kind(), ErrorKind::MissingKey);
+        assert_eq!(err.to_string(), format!("Missing key(s) {} for TestApp.dll, TestApp.exe", KeyId::Numeric(1).to_keyfile_string()));
+
+        let context = EntryContext { operation: Operation::Inflate, name: None, file_id: None, offset: 0, position: 0 };
+        let err = context.wrap(Error::DataError("corrupt deflate stream".into()));
+        assert_eq!(err.kind(), ErrorKind::Data);
+        assert!(err.is_recoverable());
+    }
+}
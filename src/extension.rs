@@ -0,0 +1,124 @@
+//! File extension conventions for produced containers
+//!
+//! Encrypted containers carry an `e` prefix (`.eappx`, `.emsix`), bundles a
+//! `bundle` suffix. Whether a package is named appx or msix depends on the era
+//! its manifest targets: MSIX arrived with Windows 10 1809 (build 17763).
+
+use crate::EAppxMagic;
+
+/// First Windows build shipping MSIX support
+pub const MSIX_MIN_BUILD: u32 = 17763;
+
+/// Namespaces that only exist in MSIX-era manifests
+const MSIX_NAMESPACES: &[&str] = &[
+    "http://schemas.microsoft.com/appx/manifest/uap/windows10/7",
+    "http://schemas.microsoft.com/appx/manifest/uap/windows10/8",
+    "http://schemas.microsoft.com/appx/manifest/uap/windows10/10",
+    "http://schemas.microsoft.com/appx/manifest/uap/windows10/11",
+    "http://schemas.microsoft.com/appx/manifest/uap/windows10/13",
+    "http://schemas.microsoft.com/appx/manifest/desktop/windows10/6",
+    "http://schemas.microsoft.com/appx/manifest/desktop/windows10/7",
+    "http://schemas.microsoft.com/appx/2018/bundle",
+    "http://schemas.microsoft.com/appx/2019/bundle",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageEra {
+    Appx,
+    Msix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerKind {
+    Package,
+    Bundle,
+}
+
+impl PackageEra {
+    /// Guess the era from a (bundle) manifest
+    ///
+    /// MSIX-only namespaces or a TargetDeviceFamily MinVersion of build
+    /// 17763 or newer mark a manifest as MSIX, everything else is appx.
+    pub fn detect(manifest_xml: &str) -> Self {
+        if MSIX_NAMESPACES.iter().any(|ns| manifest_xml.contains(&format!("\"{ns}\""))) {
+            return PackageEra::Msix;
+        }
+
+        let targets_msix = manifest_xml.split("<TargetDeviceFamily")
+            .skip(1)
+            .filter_map(|element| element.split("MinVersion=\"").nth(1))
+            .filter_map(|version| version.split('.').nth(2))
+            .filter_map(|build| build.parse::<u32>().ok())
+            .any(|build| build >= MSIX_MIN_BUILD);
+
+        match targets_msix {
+            true => PackageEra::Msix,
+            false => PackageEra::Appx,
+        }
+    }
+}
+
+impl ContainerKind {
+    /// Kind of container a header magic describes, EXSH is decided by its manifest
+    pub fn from_magic(magic: EAppxMagic, has_bundle_manifest: bool) -> Self {
+        match (magic, has_bundle_manifest) {
+            (EAppxMagic::EXBH, _) | (EAppxMagic::EXSH, true) => ContainerKind::Bundle,
+            _ => ContainerKind::Package,
+        }
+    }
+}
+
+/// All extensions [`file_extension`] produces
+pub const KNOWN_EXTENSIONS: &[&str] = &[
+    "appx", "eappx", "msix", "emsix",
+    "appxbundle", "eappxbundle", "msixbundle", "emsixbundle",
+];
+
+/// Conventional file extension (without dot)
+///
+/// Examples
+/// ```
+/// use eappx::extension::{file_extension, ContainerKind, PackageEra};
+///
+/// assert_eq!(file_extension(ContainerKind::Package, PackageEra::Msix, true), "emsix");
+/// assert_eq!(file_extension(ContainerKind::Bundle, PackageEra::Appx, false), "appxbundle");
+/// ```
+pub fn file_extension(kind: ContainerKind, era: PackageEra, encrypted: bool) -> &'static str {
+    match (kind, era, encrypted) {
+        (ContainerKind::Package, PackageEra::Appx, false) => "appx",
+        (ContainerKind::Package, PackageEra::Appx, true) => "eappx",
+        (ContainerKind::Package, PackageEra::Msix, false) => "msix",
+        (ContainerKind::Package, PackageEra::Msix, true) => "emsix",
+        (ContainerKind::Bundle, PackageEra::Appx, false) => "appxbundle",
+        (ContainerKind::Bundle, PackageEra::Appx, true) => "eappxbundle",
+        (ContainerKind::Bundle, PackageEra::Msix, false) => "msixbundle",
+        (ContainerKind::Bundle, PackageEra::Msix, true) => "emsixbundle",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = include_str!("../testdata/manifest.xml");
+    const BUNDLE_MANIFEST: &str = include_str!("../testdata/manifest_bundle.xml");
+
+    #[test]
+    fn test_detect_era() {
+        assert_eq!(PackageEra::detect(MANIFEST), PackageEra::Appx);
+        assert_eq!(PackageEra::detect(BUNDLE_MANIFEST), PackageEra::Msix);
+
+        let targeting_1809 = MANIFEST.replace("<Identity", r#"<TargetDeviceFamily Name="Windows.Universal" MinVersion="10.0.17763.0"/><Identity"#);
+        assert_eq!(PackageEra::detect(&targeting_1809), PackageEra::Msix);
+
+        let targeting_1803 = MANIFEST.replace("<Identity", r#"<TargetDeviceFamily Name="Windows.Universal" MinVersion="10.0.17134.0"/><Identity"#);
+        assert_eq!(PackageEra::detect(&targeting_1803), PackageEra::Appx);
+    }
+
+    #[test]
+    fn test_container_kind() {
+        assert_eq!(ContainerKind::from_magic(EAppxMagic::EXPH, false), ContainerKind::Package);
+        assert_eq!(ContainerKind::from_magic(EAppxMagic::EXBH, false), ContainerKind::Bundle);
+        assert_eq!(ContainerKind::from_magic(EAppxMagic::EXSH, true), ContainerKind::Bundle);
+    }
+}
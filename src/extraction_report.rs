@@ -0,0 +1,258 @@
+//! Record of what an extraction produced
+//!
+//! Every written (or kept) file is listed with its local path, size, SHA-256
+//! of the written plaintext and whether that matched the package's file hash,
+//! so pipelines can audit the output without re-reading the package.
+
+use std::{io::Write, path::PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::{digest::{HashAlgorithm, Hasher}, error::{EntryContext, Error}, layout::Collision, timings::Timings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractionStatus {
+    /// Written, content matches the hash recorded in the package
+    Verified,
+    /// Written, the package carries no hash covering the plaintext
+    Unverified,
+    /// Written, but content differs from the hash recorded in the package
+    HashMismatch,
+    /// Existing file left in place due to the overwrite policy
+    Kept,
+    /// Not written, the file is encrypted and its key is not loaded
+    SkippedKeyMissing,
+    /// Written as ciphertext, the file is encrypted and its key is not loaded
+    Ciphertext,
+}
+
+impl ExtractionStatus {
+    /// Whether the file was written by this extraction
+    pub fn is_written(self) -> bool {
+        !matches!(self, ExtractionStatus::Kept | ExtractionStatus::SkippedKeyMissing)
+    }
+
+    /// Status of written plaintext with `digest`, computed like the package's `filehash`
+    pub(crate) fn of_plaintext(filehash: Option<&[u8]>, digest: &[u8]) -> Self {
+        match filehash {
+            Some(filehash) if filehash == digest => ExtractionStatus::Verified,
+            Some(_) => ExtractionStatus::HashMismatch,
+            None => ExtractionStatus::Unverified,
+        }
+    }
+}
+
+impl std::fmt::Display for ExtractionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ExtractionStatus::Verified => "verified",
+            ExtractionStatus::Unverified => "unverified",
+            ExtractionStatus::HashMismatch => "hash-mismatch",
+            ExtractionStatus::Kept => "kept",
+            ExtractionStatus::SkippedKeyMissing => "skipped-key-missing",
+            ExtractionStatus::Ciphertext => "ciphertext",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExtractedFile {
+    /// Path inside the package
+    pub package_path: String,
+    /// Path relative to the extraction directory
+    pub path: PathBuf,
+    pub size: u64,
+    /// SHA-256 of the written data, `None` for kept files
+    pub sha256: Option<Vec<u8>>,
+    pub encrypted: bool,
+    pub compressed: bool,
+    pub status: ExtractionStatus,
+    /// Time spent per stage, set if [`EAppxFile::collect_timings`](crate::EAppxFile::collect_timings) is
+    pub timings: Option<Timings>,
+    /// Earlier file with the same content this one was linked to or copied from,
+    /// relative to the extraction directory, see [`DuplicatePolicy`](crate::layout::DuplicatePolicy)
+    pub duplicate_of: Option<PathBuf>,
+}
+
+impl ExtractedFile {
+    /// Whether the file was written under another name than its package path
+    ///
+    /// Sanitized or escaped names, case-folded directories, flat extraction
+    /// and renames on collision all count, `package_path` maps it back.
+    pub fn is_renamed(&self) -> bool {
+        let package_components = self.package_path.split(['\\', '/'])
+            .filter(|component| !component.is_empty() && *component != ".");
+        let components = self.path.iter().map(|component| component.to_string_lossy());
+        !package_components.eq(components)
+    }
+}
+
+/// Entry that failed to extract, recorded with [`ExtractOptions::continue_on_error`](crate::layout::ExtractOptions::continue_on_error)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExtractionFailure {
+    /// Path inside the package
+    pub package_path: String,
+    pub message: String,
+    /// Where reading the entry failed, if it got that far
+    pub context: Option<EntryContext>,
+}
+
+impl ExtractionFailure {
+    pub(crate) fn new(package_path: &str, error: &Error) -> Self {
+        Self {
+            package_path: package_path.to_owned(),
+            message: error.to_string(),
+            context: error.entry().cloned(),
+        }
+    }
+}
+
+impl std::fmt::Display for ExtractionFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.package_path, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExtractionReport {
+    pub files: Vec<ExtractedFile>,
+    /// Blockmap entries sharing a local file on case-insensitive filesystems
+    pub collisions: Vec<Collision>,
+    /// Entries extraction went past, in the order they failed
+    pub failures: Vec<ExtractionFailure>,
+}
+
+impl ExtractionReport {
+    pub fn mismatched(&self) -> impl Iterator<Item = &ExtractedFile> {
+        self.files.iter().filter(|f| f.status == ExtractionStatus::HashMismatch)
+    }
+
+    /// No file mismatches its hash and none failed to extract
+    pub fn is_valid(&self) -> bool {
+        self.mismatched().next().is_none() && self.failures.is_empty()
+    }
+
+    /// Files written under another name than their package path
+    pub fn renamed(&self) -> impl Iterator<Item = &ExtractedFile> {
+        self.files.iter().filter(|f| f.is_renamed())
+    }
+
+    /// Encrypted files skipped or written as ciphertext for lack of a key
+    pub fn missing_key(&self) -> impl Iterator<Item = &ExtractedFile> {
+        self.files.iter().filter(|f| matches!(f.status, ExtractionStatus::SkippedKeyMissing | ExtractionStatus::Ciphertext))
+    }
+
+    /// Total amount of bytes written
+    pub fn written_size(&self) -> u64 {
+        self.files.iter()
+            .filter(|f| f.status.is_written())
+            .map(|f| f.size)
+            .sum()
+    }
+
+    /// Files linked to or copied from an earlier file with the same content
+    pub fn duplicates(&self) -> impl Iterator<Item = &ExtractedFile> {
+        self.files.iter().filter(|f| f.duplicate_of.is_some())
+    }
+
+    /// Time spent per stage across all files, `None` if none were collected
+    pub fn timings(&self) -> Option<Timings> {
+        self.files.iter().filter_map(|f| f.timings).reduce(|total, timings| total + timings)
+    }
+}
+
+/// Writer hashing everything passing through
+pub(crate) struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Sha256,
+    /// Digest of a file hash that is not SHA-256
+    filehash: Option<Hasher>,
+    written: u64,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, hasher: Sha256::new(), filehash: None, written: 0 }
+    }
+
+    /// Also compute the digest file hashes of `algorithm` are compared with
+    pub(crate) fn with_filehash(inner: W, algorithm: HashAlgorithm) -> Self {
+        let filehash = (algorithm != HashAlgorithm::Sha256).then(|| algorithm.hasher());
+        Self { filehash, ..Self::new(inner) }
+    }
+
+    /// Bytes written and their SHA-256
+    pub(crate) fn finalize(self) -> (u64, Vec<u8>) {
+        let (written, sha256, _) = self.finalize_digests();
+        (written, sha256)
+    }
+
+    /// Bytes written, their SHA-256 and their digest in the file hash algorithm
+    pub(crate) fn finalize_digests(self) -> (u64, Vec<u8>, Vec<u8>) {
+        let sha256 = self.hasher.finalize().to_vec();
+        let filehash = self.filehash.map_or_else(|| sha256.clone(), Hasher::finalize);
+        (self.written, sha256, filehash)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        if let Some(filehash) = self.filehash.as_mut() {
+            filehash.update(&buf[..written]);
+        }
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashing_writer() {
+        let mut writer = HashingWriter::new(vec![]);
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"world").unwrap();
+
+        let (size, hash) = writer.finalize();
+        assert_eq!(size, 11);
+        assert_eq!(hex::encode(hash), "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+    }
+
+    #[test]
+    fn test_renamed() {
+        let file = |package_path: &str, path: &str| ExtractedFile {
+            package_path: package_path.into(),
+            path: path.into(),
+            size: 0,
+            sha256: None,
+            encrypted: false,
+            compressed: false,
+            status: ExtractionStatus::Verified,
+            timings: None,
+            duplicate_of: None,
+        };
+        let report = ExtractionReport {
+            files: vec![
+                file("Assets\\Logo.png", "Assets/Logo.png"),
+                file("\\.\\Readme.txt", "Readme.txt"),
+                file("Data\\a<b>.txt", "Data/a%3Cb%3E.txt"),
+                file("assets\\Icon.png", "Assets/Icon.png"),
+            ],
+            ..Default::default()
+        };
+        let renamed = report.renamed().map(|file| file.package_path.as_str()).collect::<Vec<_>>();
+        assert_eq!(renamed, ["Data\\a<b>.txt", "assets\\Icon.png"]);
+    }
+}
@@ -0,0 +1,216 @@
+//! Synthetic packages for tests and benchmarks
+//!
+//! Generates encrypted packages of any size from a [`FixtureSpec`]: entry
+//! count, total payload size, share of compressed entries and number of keys.
+//! Content and keys derive from a seed, so a fixture is reproducible and
+//! [`FixtureEntry::reader`] streams the expected plaintext of an entry again
+//! without keeping it around, even for packages of several GiB.
+
+use std::io::{Cursor, Read, Seek, Write};
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    keys::{KeyCollection, KeyId},
+    sample,
+    writer::{EntryOptions, PackageWriter, WriterOptions},
+    EAppxMagic,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixtureSpec {
+    /// Payload entries besides `AppxManifest.xml`
+    pub entries: usize,
+    /// Plaintext bytes spread evenly over the entries
+    pub total_size: u64,
+    /// Share of entries deflated, between 0.0 and 1.0
+    ///
+    /// Ciphertext is deflated, so only unencrypted entries actually shrink.
+    pub compressed: f64,
+    /// Keys entries are encrypted with in turn, 0 leaves them unencrypted
+    pub keys: usize,
+    pub seed: u64,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            entries: 8,
+            total_size: 0x100000,
+            compressed: 0.5,
+            keys: 1,
+            seed: 0x9E3779B97F4A7C15,
+        }
+    }
+}
+
+/// Entry of a generated package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixtureEntry {
+    pub name: String,
+    pub size: u64,
+    pub options: EntryOptions,
+    seed: u64,
+}
+
+impl FixtureEntry {
+    /// Plaintext of the entry, generated anew
+    pub fn reader(&self) -> impl Read {
+        let compressible = self.options.compress;
+        Noise { state: self.seed, left: self.size, compressible }
+    }
+
+    /// SHA-256 of the plaintext
+    pub fn sha256(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut self.reader(), &mut hasher).expect("Generated data is infallible");
+        hasher.finalize().to_vec()
+    }
+}
+
+/// Description of a generated package, everything needed to check it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fixture {
+    pub package_full_name: String,
+    /// Keys in header order, as handed to [`PackageWriter::new`]
+    pub keys: Vec<(KeyId, Vec<u8>)>,
+    /// Payload entries in package order, after `AppxManifest.xml`
+    pub entries: Vec<FixtureEntry>,
+}
+
+impl Fixture {
+    pub fn key_collection(&self) -> KeyCollection {
+        let mut keys = KeyCollection::default();
+        for (id, key) in &self.keys {
+            keys.add(id.clone(), key.clone());
+        }
+        keys
+    }
+}
+
+/// Deterministic data, noise or noise over 16 symbols that deflates to about half
+struct Noise {
+    state: u64,
+    left: u64,
+    compressible: bool,
+}
+
+impl Noise {
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Read for Noise {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.left as usize);
+        for chunk in buf[..len].chunks_mut(8) {
+            let mut value = self.next().to_le_bytes();
+            if self.compressible {
+                value.iter_mut().for_each(|byte| *byte = b'a' + (*byte & 0x0F));
+            }
+            chunk.copy_from_slice(&value[..chunk.len()]);
+        }
+        self.left -= len as u64;
+        Ok(len)
+    }
+}
+
+impl FixtureSpec {
+    /// Keys and entries of the package, without generating it
+    pub fn fixture(&self) -> Fixture {
+        let mut rng = Noise { state: self.seed | 1, left: 0, compressible: false };
+
+        let keys = (0..self.keys)
+            .map(|_| {
+                let id = KeyId::Guid((Uuid::from_u64_pair(rng.next(), rng.next()), Uuid::from_u64_pair(rng.next(), rng.next())));
+                (id, (0..4).flat_map(|_| rng.next().to_le_bytes()).collect())
+            })
+            .collect();
+
+        let entries = self.entries as u64;
+        let entries = (0..entries)
+            .map(|index| {
+                let size = self.total_size / entries + u64::from(index < self.total_size % entries);
+                // Spread compressed entries evenly
+                let compress = ((index + 1) as f64 * self.compressed).floor() > (index as f64 * self.compressed).floor();
+                let key_index = (self.keys > 0).then(|| (index % self.keys as u64) as u16);
+                FixtureEntry {
+                    name: format!("Data\\{:02x}\\entry{index:05}.bin", index % 0x100),
+                    size,
+                    options: EntryOptions { compress, key_index },
+                    seed: rng.next() | 1,
+                }
+            })
+            .collect();
+
+        Fixture { package_full_name: sample::sample_package_full_name(), keys, entries }
+    }
+
+    /// Write the package into `sink`
+    pub fn generate<W: Write + Seek + Send + 'static>(&self, sink: W, options: WriterOptions) -> Result<(W, Fixture), Error> {
+        let fixture = self.fixture();
+        let mut writer = PackageWriter::new(sink, EAppxMagic::EXPH, &fixture.package_full_name, fixture.keys.clone(), options)?;
+        writer.add_entry("AppxManifest.xml", &mut sample::sample_manifest().as_bytes(), EntryOptions { compress: true, key_index: None })?;
+        for entry in &fixture.entries {
+            writer.add_entry(&entry.name, &mut entry.reader(), entry.options)?;
+        }
+
+        Ok((writer.finish()?, fixture))
+    }
+
+    /// Generate the package in memory
+    pub fn generate_to_vec(&self) -> Result<(Vec<u8>, Fixture), Error> {
+        let (cursor, fixture) = self.generate(Cursor::new(vec![]), WriterOptions::default())?;
+        Ok((cursor.into_inner(), fixture))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory, EAppxFile};
+
+    #[test]
+    fn test_fixture() {
+        let spec = FixtureSpec { entries: 6, total_size: 0x50001, compressed: 0.5, keys: 2, ..Default::default() };
+        let (package, fixture) = spec.generate_to_vec().unwrap();
+        assert_eq!(package, spec.generate_to_vec().unwrap().0, "Fixtures must be deterministic");
+        assert_eq!(fixture.entries.iter().map(|entry| entry.size).sum::<u64>(), spec.total_size);
+        assert_eq!(fixture.entries.iter().filter(|entry| entry.options.compress).count(), 3);
+
+        let reader = package.as_slice();
+        let mut eappx = EAppxFile::from_source(&reader).unwrap();
+        eappx.load_keys(&fixture.key_collection()).unwrap();
+        assert_eq!(eappx.header.key_ids.len(), 2);
+        assert!(eappx.verify(&reader).is_valid());
+
+        let files = memory::unpack(&package, &fixture.key_collection()).unwrap();
+        for entry in &fixture.entries {
+            let mut expected = vec![];
+            entry.reader().read_to_end(&mut expected).unwrap();
+            assert_eq!(files[&entry.name], expected, "{}", entry.name);
+            assert_eq!(Sha256::digest(&expected).to_vec(), entry.sha256());
+        }
+
+        for entry in &fixture.entries {
+            let file = eappx.blockmap.find_file(&entry.name).unwrap();
+            assert_eq!(eappx.find_footer_for_file(file.id()).unwrap().compression_type == 0x1, entry.options.compress);
+        }
+
+        // Unencrypted, the compressed entries shrink
+        let (package, fixture) = FixtureSpec { keys: 0, ..spec }.generate_to_vec().unwrap();
+        assert!(fixture.keys.is_empty());
+        assert_eq!(memory::unpack(&package, &KeyCollection::default()).unwrap().len(), spec.entries + 1);
+        let eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        for entry in fixture.entries.iter().filter(|entry| entry.options.compress) {
+            let footer = eappx.find_footer_for_file(eappx.blockmap.find_file(&entry.name).unwrap().id()).unwrap();
+            assert!(footer.compressed_length < entry.size * 3 / 4);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+//! Footprint files, located through the header instead of the blockmap
+//!
+//! AppxBlockMap.xml is a footer table entry the header names by file id,
+//! signature and code integrity catalog sit behind the payloads at offsets
+//! the header records. None of them is listed in the blockmap itself.
+
+use crate::{error::Error, source::PackageSource, EAppxFile, FileInfo};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FootprintFile {
+    BlockMap,
+    Signature,
+    CodeIntegrity,
+}
+
+impl FootprintFile {
+    pub const ALL: [FootprintFile; 3] = [Self::BlockMap, Self::Signature, Self::CodeIntegrity];
+
+    /// Name the file is extracted as
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Self::BlockMap => "AppxBlockMap.xml",
+            Self::Signature => "AppxSignature.p7x",
+            Self::CodeIntegrity => "CodeIntegrity.cat",
+        }
+    }
+
+    /// Path inside the package, the catalog lives in AppxMetadata
+    pub fn package_path(self) -> &'static str {
+        match self {
+            Self::BlockMap => "AppxBlockMap.xml",
+            Self::Signature => "AppxSignature.p7x",
+            Self::CodeIntegrity => "AppxMetadata\\CodeIntegrity.cat",
+        }
+    }
+
+    /// For logs and errors
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::BlockMap => "Blockmap",
+            Self::Signature => "Signature",
+            Self::CodeIntegrity => "Code integrity",
+        }
+    }
+}
+
+impl std::fmt::Display for FootprintFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file_name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FootprintEntry {
+    pub file: FootprintFile,
+    pub offset: u64,
+    /// As stored in the container
+    pub stored_length: u64,
+    pub uncompressed_length: u64,
+    pub compressed: bool,
+}
+
+/// Footprint file the header points at, but which starts beyond the end of the package
+///
+/// A package cut short loses its signature and catalog this way. Packages
+/// embedded in a bundle point at footprints behind them, in the bundle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SkippedFootprint {
+    pub file: FootprintFile,
+    pub offset: u64,
+    /// As stored in the container
+    pub length: u64,
+}
+
+impl SkippedFootprint {
+    /// First offset past the footprint file
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(self.length)
+    }
+}
+
+impl std::fmt::Display for SkippedFootprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {:#x} ({:#x} bytes) lies beyond the end of the package", self.file.description(), self.offset, self.length)
+    }
+}
+
+impl EAppxFile {
+    /// Footprint files the package contains, in [`FootprintFile::ALL`] order
+    ///
+    /// Footprints a package embedded in a bundle points at lie in the bundle,
+    /// behind the package, and are left out.
+    pub fn footprint_entries(&self) -> Vec<FootprintEntry> {
+        FootprintFile::ALL.into_iter()
+            .filter_map(|file| {
+                let fileinfo = self.footprint_fileinfo(file)?;
+                Some(FootprintEntry {
+                    file,
+                    offset: fileinfo.offset_to_file,
+                    stored_length: fileinfo.compressed_length,
+                    uncompressed_length: fileinfo.uncompressed_length,
+                    compressed: fileinfo.compression_type == 0x1,
+                })
+            })
+            .collect()
+    }
+
+    /// Footprint files the header points at beyond the end of the package
+    ///
+    /// [`EAppxFile::footprint_entries`] and extraction leave them out.
+    pub fn skipped_footprints(&self) -> Vec<SkippedFootprint> {
+        FootprintFile::ALL.into_iter()
+            .filter_map(|file| {
+                let fileinfo = self.referenced_footprint_fileinfo(file)?;
+                (fileinfo.offset_to_file >= self.file_len).then_some(SkippedFootprint {
+                    file,
+                    offset: fileinfo.offset_to_file,
+                    length: fileinfo.compressed_length,
+                })
+            })
+            .collect()
+    }
+
+    /// Where the header says `file` is stored, whether or not that is within the package
+    fn referenced_footprint_fileinfo(&self, file: FootprintFile) -> Option<FileInfo> {
+        match file {
+            FootprintFile::BlockMap => {
+                let mut fileinfo: FileInfo = self.find_footer_for_file(self.header.block_map_file_id)?.into();
+                fileinfo.filehash = Some(self.header.block_map_hash.clone());
+                Some(fileinfo)
+            },
+            FootprintFile::Signature => self.header.appx_signature_fileinfo(),
+            FootprintFile::CodeIntegrity => self.header.code_integrity_fileinfo(),
+        }
+    }
+
+    /// Where `file` is stored, `None` if the package has none within its bounds
+    pub(crate) fn footprint_fileinfo(&self, file: FootprintFile) -> Option<FileInfo> {
+        let fileinfo = self.referenced_footprint_fileinfo(file)?;
+        if fileinfo.offset_to_file >= self.file_len {
+            log::warn!("{} at {:#x} lies beyond the end of the package, skipping", file.description(), fileinfo.offset_to_file);
+            return None;
+        }
+        Some(fileinfo)
+    }
+
+    /// Read a footprint file, `None` if the package has none
+    pub fn read_footprint_file<P: PackageSource + ?Sized>(&self, source: &P, file: FootprintFile) -> Result<Option<Vec<u8>>, Error> {
+        if file == FootprintFile::BlockMap {
+            return self.read_raw_blockmap(source).map(Some);
+        }
+        let Some(fileinfo) = self.footprint_fileinfo(file) else {
+            return Ok(None);
+        };
+
+        log::debug!("Reading {} (offset={:#x}, size={:#x})", file.description(), fileinfo.offset_to_file, fileinfo.compressed_length);
+        Self::read_file_to_buf(source, fileinfo, self.header.is_bundle(), self.limits.max_in_memory_size).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FootprintFile, SkippedFootprint};
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_footprint_entries() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+
+        // Code integrity protected, but unsigned
+        let entries = eappx.footprint_entries();
+        assert_eq!(entries.iter().map(|entry| entry.file).collect::<Vec<_>>(), [FootprintFile::BlockMap, FootprintFile::CodeIntegrity]);
+
+        let blockmap = eappx.find_footer_for_file(eappx.header.block_map_file_id).unwrap();
+        assert_eq!((entries[0].offset, entries[0].stored_length), (blockmap.offset_to_file, blockmap.compressed_length));
+        assert!(entries[0].compressed);
+        assert_eq!((entries[1].offset, entries[1].stored_length), (eappx.header.code_integrity_offset, eappx.header.code_integrity_length as u64));
+
+        for entry in &entries {
+            let data = eappx.read_footprint_file(EMSIX, entry.file).unwrap().unwrap();
+            assert_eq!(data.len() as u64, entry.uncompressed_length);
+        }
+        assert_eq!(eappx.read_footprint_file(EMSIX, FootprintFile::Signature).unwrap(), None);
+        assert!(eappx.skipped_footprints().is_empty());
+    }
+
+    #[test]
+    fn test_skipped_footprints() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        assert!(!eappx.is_truncated());
+        assert_eq!(eappx.referenced_end(), eappx.file_len);
+
+        // As if the download stopped before the catalog
+        let length = eappx.header.code_integrity_length as u64;
+        eappx.header.code_integrity_offset = eappx.file_len;
+        let skipped = eappx.skipped_footprints();
+        assert_eq!(skipped, [SkippedFootprint { file: FootprintFile::CodeIntegrity, offset: eappx.file_len, length }]);
+        assert!(eappx.is_truncated());
+        assert_eq!(eappx.referenced_end(), eappx.file_len + length);
+        assert_eq!(eappx.read_code_integrity(EMSIX).unwrap(), None);
+
+        let report = eappx.verify(EMSIX);
+        assert_eq!(report.skipped_footprints, skipped);
+        assert!(report.is_truncated() && report.is_valid());
+        assert!(report.to_string().contains("Code integrity at"));
+    }
+}
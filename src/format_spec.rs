@@ -0,0 +1,240 @@
+//! Field-by-field layout description of the binary EAppx structures
+//!
+//! binrw offers no reflection, so the layout is described here by hand and
+//! kept in sync with the parser by the unit tests below, which decode the
+//! sample package using these offsets and compare against binrw's result.
+
+/// Size of a field on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldSize {
+    /// Fixed amount of bytes
+    Fixed(usize),
+    /// Length is determined by the referenced field
+    Variable(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+    /// Byte sequence, endianness does not apply
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    /// Absolute offset inside the structure, `None` if it follows a variable-length field
+    pub offset: Option<usize>,
+    pub size: FieldSize,
+    pub endianness: Endianness,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StructSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub fields: &'static [FieldSpec],
+}
+
+const fn fixed(name: &'static str, offset: usize, size: usize, description: &'static str) -> FieldSpec {
+    FieldSpec { name, offset: Some(offset), size: FieldSize::Fixed(size), endianness: Endianness::Little, description }
+}
+
+const fn trailing(name: &'static str, size: usize, description: &'static str) -> FieldSpec {
+    FieldSpec { name, offset: None, size: FieldSize::Fixed(size), endianness: Endianness::Little, description }
+}
+
+const fn variable(name: &'static str, offset: Option<usize>, length_field: &'static str, endianness: Endianness, description: &'static str) -> FieldSpec {
+    FieldSpec { name, offset, size: FieldSize::Variable(length_field), endianness, description }
+}
+
+pub const EAPPX_HEADER: StructSpec = StructSpec {
+    name: "EAppxHeader",
+    description: "Package header, located at offset 0 of the container",
+    fields: &[
+        fixed("magic", 0x00, 4, "EXPH (package), EXSH or EXBH (bundle)"),
+        fixed("header_size", 0x04, 2, "Total size of the header in bytes"),
+        fixed("version", 0x06, 8, "Format version"),
+        fixed("footer_offset", 0x0E, 8, "Absolute offset of the footer table"),
+        fixed("footer_length", 0x16, 8, "Size of the footer table in bytes"),
+        fixed("file_count", 0x1E, 8, "Number of file entries"),
+        fixed("signature_offset", 0x26, 8, "Absolute offset of AppxSignature.p7x, 0 if unsigned"),
+        fixed("signature_compression_type", 0x2E, 2, "0 = stored, 1 = deflate"),
+        fixed("signature_uncompressed_length", 0x30, 4, "Uncompressed size of the signature"),
+        fixed("signature_length", 0x34, 4, "Stored size of the signature"),
+        fixed("code_integrity_offset", 0x38, 8, "Absolute offset of CodeIntegrity.cat, 0 if absent"),
+        fixed("code_integrity_compression_type", 0x40, 2, "0 = stored, 1 = deflate"),
+        fixed("code_integrity_uncompressed_length", 0x42, 4, "Uncompressed size of the catalog"),
+        fixed("code_integrity_length", 0x46, 4, "Stored size of the catalog"),
+        fixed("block_map_file_id", 0x4A, 8, "File id of AppxBlockMap.xml in the footer table"),
+        fixed("key_length", 0x52, 4, "Size of a content key in bytes"),
+        fixed("key_id_count", 0x56, 2, "Number of key ids"),
+        variable("key_ids", Some(0x58), "key_id_count * 32", Endianness::Little, "Key ids, each a pair of mixed-endian GUIDs"),
+        trailing("package_full_name_str_len", 2, "Length of the package full name in UTF-16 code units"),
+        trailing("package_full_name_byte_len", 2, "Length of the package full name in bytes"),
+        variable("package_full_name", None, "package_full_name_byte_len", Endianness::Little, "Package full name, UTF-16LE"),
+        trailing("crypto_algo_length", 2, "Length of the crypto algorithm name in bytes"),
+        variable("crypto_algo", None, "crypto_algo_length", Endianness::Little, "Crypto algorithm name, UTF-16LE (XTS-AES)"),
+        trailing("diffusion_support_enabled", 2, "Diffusion support flag"),
+        trailing("block_map_hash_algo_length", 2, "Length of the blockmap hash algorithm URI in bytes"),
+        variable("block_map_hash_algo", None, "block_map_hash_algo_length", Endianness::Little, "Blockmap hash algorithm URI, UTF-16LE"),
+        trailing("block_map_hash_length", 2, "Length of the blockmap hash in bytes"),
+        variable("block_map_hash", None, "block_map_hash_length", Endianness::None, "Hash over the uncompressed AppxBlockMap.xml"),
+    ],
+};
+
+/// Footer magic description, rendered from [`EAppxFooter::MAGIC`](crate::EAppxFooter::MAGIC)
+const FOOTER_MAGIC: &str = match std::str::from_utf8(&magic_description(crate::EAppxFooter::MAGIC)) {
+    Ok(description) => description,
+    Err(_) => panic!("Footer magic description is not UTF-8"),
+};
+
+const fn magic_description(magic: u16) -> [u8; 21] {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+    let mut out = *b"Footer magic (0x0000)";
+    let mut index = 0;
+    while index < 4 {
+        out[16 + index] = DIGITS[(magic >> (12 - 4 * index)) as usize & 0xF];
+        index += 1;
+    }
+    out
+}
+
+pub const EAPPX_FOOTER: StructSpec = StructSpec {
+    name: "EAppxFooter",
+    description: "File entry, `file_count` of them are stored at `footer_offset`, each `footer_size` bytes long",
+    fields: &[
+        fixed("magic", 0x00, 2, FOOTER_MAGIC),
+        fixed("footer_size", 0x02, 2, "Size of this footer entry in bytes"),
+        fixed("key_id_index", 0x04, 2, "Index into the header key ids, 0xFFFF if unencrypted"),
+        fixed("compression_type", 0x06, 2, "0 = stored, 1 = deflate"),
+        fixed("file_id", 0x08, 8, "File id, referenced by the blockmap"),
+        fixed("offset_to_file", 0x10, 8, "Absolute offset of the file payload"),
+        fixed("uncompressed_length", 0x18, 8, "Uncompressed (plaintext) size"),
+        fixed("compressed_length", 0x20, 8, "Stored size"),
+    ],
+};
+
+pub const ALL: &[StructSpec] = &[EAPPX_HEADER, EAPPX_FOOTER];
+
+impl FieldSize {
+    pub fn fixed(&self) -> Option<usize> {
+        match self {
+            FieldSize::Fixed(size) => Some(*size),
+            FieldSize::Variable(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for FieldSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldSize::Fixed(size) => write!(f, "{size}"),
+            FieldSize::Variable(length) => write!(f, "{length}"),
+        }
+    }
+}
+
+impl StructSpec {
+    /// Render the layout as Markdown table
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("## {}\n\n{}\n\n", self.name, self.description);
+        out += "| Offset | Size | Endianness | Field | Description |\n";
+        out += "|--------|------|------------|-------|-------------|\n";
+
+        for field in self.fields {
+            let offset = field.offset
+                .map(|o| format!("{o:#04x}"))
+                .unwrap_or("-".into());
+            out += &format!("| {offset} | {} | {:?} | `{}` | {} |\n",
+                field.size, field.endianness, field.name, field.description);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use binrw::BinRead;
+
+    use super::*;
+    use crate::{EAppxFooter, EAppxHeader};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    fn read_field(data: &[u8], field: &FieldSpec) -> u64 {
+        let offset = field.offset.unwrap();
+        let size = field.size.fixed().unwrap();
+        let mut buf = [0u8; 8];
+        buf[..size].copy_from_slice(&data[offset..offset + size]);
+        u64::from_le_bytes(buf)
+    }
+
+    fn field<'a>(spec: &'a StructSpec, name: &str) -> &'a FieldSpec {
+        spec.fields.iter().find(|f| f.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_header_layout() {
+        let header = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+        let spec = &EAPPX_HEADER;
+
+        assert_eq!(read_field(EMSIX, field(spec, "header_size")), header.header_size as u64);
+        assert_eq!(read_field(EMSIX, field(spec, "version")), header.version);
+        assert_eq!(read_field(EMSIX, field(spec, "footer_offset")), header.footer_offset);
+        assert_eq!(read_field(EMSIX, field(spec, "footer_length")), header.footer_length);
+        assert_eq!(read_field(EMSIX, field(spec, "file_count")), header.file_count);
+        assert_eq!(read_field(EMSIX, field(spec, "code_integrity_offset")), header.code_integrity_offset);
+        assert_eq!(read_field(EMSIX, field(spec, "code_integrity_compression_type")), header.code_integrity_compression_type as u64);
+        assert_eq!(read_field(EMSIX, field(spec, "code_integrity_uncompressed_length")), header.code_integrity_uncompressed_length as u64);
+        assert_eq!(read_field(EMSIX, field(spec, "code_integrity_length")), header.code_integrity_length as u64);
+        assert_eq!(read_field(EMSIX, field(spec, "block_map_file_id")), header.block_map_file_id);
+        assert_eq!(read_field(EMSIX, field(spec, "key_length")), header.key_length as u64);
+        assert_eq!(read_field(EMSIX, field(spec, "key_id_count")), header.key_ids.len() as u64);
+    }
+
+    #[test]
+    fn test_fixed_offsets_contiguous() {
+        for spec in ALL {
+            let mut expected = 0;
+            for field in spec.fields.iter().take_while(|f| f.offset.is_some()) {
+                assert_eq!(field.offset, Some(expected), "{}::{}", spec.name, field.name);
+                expected += field.size.fixed().unwrap_or(0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_footer_layout() {
+        let header = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+        let data = &EMSIX[header.footer_offset as usize..];
+        let footer = EAppxFooter::read(&mut Cursor::new(data)).unwrap();
+        let spec = &EAPPX_FOOTER;
+
+        let total: usize = spec.fields.iter().filter_map(|f| f.size.fixed()).sum();
+        assert_eq!(total, std::mem::size_of::<EAppxFooter>());
+
+        assert_eq!(read_field(data, field(spec, "magic")), footer.magic as u64);
+        assert_eq!(read_field(data, field(spec, "footer_size")), footer.footer_size as u64);
+        assert_eq!(read_field(data, field(spec, "key_id_index")), footer.key_id_index as u64);
+        assert_eq!(read_field(data, field(spec, "compression_type")), footer.compression_type as u64);
+        assert_eq!(read_field(data, field(spec, "file_id")), footer.file_id);
+        assert_eq!(read_field(data, field(spec, "offset_to_file")), footer.offset_to_file);
+        assert_eq!(read_field(data, field(spec, "uncompressed_length")), footer.uncompressed_length);
+        assert_eq!(read_field(data, field(spec, "compressed_length")), footer.compressed_length);
+    }
+
+    #[test]
+    fn test_footer_magic() {
+        let header = EAppxHeader::read(&mut Cursor::new(EMSIX)).unwrap();
+        let magic = field(&EAPPX_FOOTER, "magic");
+        let value = read_field(&EMSIX[header.footer_offset as usize..], magic);
+        assert_eq!(value, EAppxFooter::MAGIC as u64);
+        assert_eq!(magic.description, format!("Footer magic ({value:#06x})"));
+    }
+}
@@ -0,0 +1,181 @@
+//! Package versions and package full names
+//!
+//! Manifests, bundle manifests and the package full name in headers all carry
+//! the four-part package version. [`PackageVersion`] orders them the way
+//! Windows does, so updates can be told from downgrades.
+
+use std::str::FromStr;
+
+use crate::{error::Error, utils};
+
+/// `Major.Minor.Build.Revision`, each part 0 to 65535
+///
+/// ```
+/// # use eappx::identity::PackageVersion;
+/// let version: PackageVersion = "1.0.10.0".parse().unwrap();
+/// assert!(version > "1.0.9.0".parse().unwrap());
+/// assert_eq!(version.to_string(), "1.0.10.0");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "String", into = "String"))]
+pub struct PackageVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub build: u16,
+    pub revision: u16,
+}
+
+impl PackageVersion {
+    /// `0.0.0.0`, which MakeAppx writes into the package full name of encrypted packages
+    pub const ZERO: Self = Self::new(0, 0, 0, 0);
+
+    pub const fn new(major: u16, minor: u16, build: u16, revision: u16) -> Self {
+        Self { major, minor, build, revision }
+    }
+
+    /// Packed into 64 bits like `PACKAGE_VERSION`, major in the upper 16
+    pub fn to_u64(&self) -> u64 {
+        (self.major as u64) << 48 | (self.minor as u64) << 32 | (self.build as u64) << 16 | self.revision as u64
+    }
+
+    pub fn from_u64(version: u64) -> Self {
+        Self::new((version >> 48) as u16, (version >> 32) as u16, (version >> 16) as u16, version as u16)
+    }
+}
+
+impl FromStr for PackageVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || Error::DecodeError(format!("Invalid package version {s:?}, expected four numbers from 0 to 65535"));
+        let parts = s.split('.')
+            .map(|part| match part.bytes().all(|c| c.is_ascii_digit()) {
+                true => part.parse::<u16>().map_err(|_| error()),
+                false => Err(error()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        match parts[..] {
+            [major, minor, build, revision] => Ok(Self::new(major, minor, build, revision)),
+            _ => Err(error()),
+        }
+    }
+}
+
+impl std::fmt::Display for PackageVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.major, self.minor, self.build, self.revision)
+    }
+}
+
+impl TryFrom<String> for PackageVersion {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<PackageVersion> for String {
+    fn from(value: PackageVersion) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(feature = "xml")]
+impl xmlserde::XmlValue for PackageVersion {
+    fn serialize(&self) -> String {
+        self.to_string()
+    }
+
+    fn deserialize(s: &str) -> Result<Self, String> {
+        s.parse().map_err(|e: Error| e.to_string())
+    }
+}
+
+/// `Name_Version_Architecture_ResourceId_PublisherId`
+///
+/// ```
+/// # use eappx::identity::{PackageFullName, PackageVersion};
+/// let full_name: PackageFullName = "TestApp_1.0.3.0_x64__bst25f6z33ccc".parse().unwrap();
+/// assert_eq!(full_name.version, PackageVersion::new(1, 0, 3, 0));
+/// assert_eq!(full_name.resource_id, "");
+/// assert_eq!(full_name.to_string(), "TestApp_1.0.3.0_x64__bst25f6z33ccc");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PackageFullName {
+    pub name: String,
+    pub version: PackageVersion,
+    /// e.g. `x64`, `neutral`
+    pub architecture: String,
+    /// Empty for most packages, `~` for bundles
+    pub resource_id: String,
+    pub publisher_id: String,
+}
+
+impl PackageFullName {
+    /// Full name of a package, `publisher` is the full distinguished name the id is derived from
+    pub fn new(name: &str, version: PackageVersion, architecture: Option<&str>, resource_id: Option<&str>, publisher: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            version,
+            architecture: architecture.unwrap_or("neutral").to_owned(),
+            resource_id: resource_id.unwrap_or_default().to_owned(),
+            publisher_id: utils::generate_publisher_id(publisher),
+        }
+    }
+}
+
+impl FromStr for PackageFullName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split('_').collect::<Vec<_>>()[..] {
+            [name, version, architecture, resource_id, publisher_id] => Ok(Self {
+                name: name.to_owned(),
+                version: version.parse()?,
+                architecture: architecture.to_owned(),
+                resource_id: resource_id.to_owned(),
+                publisher_id: publisher_id.to_owned(),
+            }),
+            _ => Err(Error::DecodeError(format!("Invalid package full name {s:?}, expected five fields separated by _"))),
+        }
+    }
+}
+
+impl std::fmt::Display for PackageFullName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}_{}_{}_{}", self.name, self.version, self.architecture, self.resource_id, self.publisher_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_order() {
+        let mut versions = ["1.10.0.0", "1.2.0.0", "1.2.0.10", "0.0.0.0", "1.2.0.9"]
+            .map(|version| version.parse::<PackageVersion>().unwrap());
+        versions.sort();
+        assert_eq!(versions.map(|version| version.to_string()), ["0.0.0.0", "1.2.0.0", "1.2.0.9", "1.2.0.10", "1.10.0.0"]);
+        assert_eq!(PackageVersion::from_u64(PackageVersion::new(1, 2, 3, 4).to_u64()), PackageVersion::new(1, 2, 3, 4));
+        assert_eq!(PackageVersion::new(1, 2, 3, 4).to_u64(), 0x0001_0002_0003_0004);
+    }
+
+    #[test]
+    fn test_version_invalid() {
+        for version in ["", "1.0.0", "1.0.0.0.0", "1.0.0.65536", "1.0.0.-1", "1.0.0.+1", "1.0. 0.0", "a.b.c.d"] {
+            assert!(version.parse::<PackageVersion>().is_err(), "{version}");
+        }
+    }
+
+    #[test]
+    fn test_full_name() {
+        let full_name = PackageFullName::new("TestApp", PackageVersion::new(1, 0, 24, 0), Some("x64"), None, "CN=SomeCommonName");
+        assert_eq!(full_name.to_string(), "TestApp_1.0.24.0_x64__bst25f6z33ccc");
+        assert_eq!(full_name.to_string().parse::<PackageFullName>().unwrap(), full_name);
+        assert!("TestApp_1.0.24.0_x64_bst25f6z33ccc".parse::<PackageFullName>().is_err());
+        assert!("TestApp_1.0.24_x64__bst25f6z33ccc".parse::<PackageFullName>().is_err());
+    }
+}
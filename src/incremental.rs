@@ -0,0 +1,213 @@
+//! Planning of incremental packs
+//!
+//! Encryption is deterministic (AES-XTS with a tweak derived from file path and
+//! PFN), so a file whose plaintext did not change produces the exact same
+//! stored payload as in the previous build - as long as package identity and
+//! key match. Such payloads can be copied verbatim instead of being
+//! recompressed and re-encrypted.
+
+use std::collections::HashMap;
+
+use crate::{error::Error, keys::KeyId, EAppxFile, EAppxFooter};
+
+/// File of the new build, as seen by the packer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewEntry {
+    /// Package-relative path (backslash separated)
+    pub name: String,
+    /// Uncompressed size
+    pub size: u64,
+    /// SHA256 over the uncompressed plaintext
+    pub filehash: Vec<u8>,
+    /// Key id the file is going to be encrypted with, `None` if unencrypted
+    pub key_id: Option<KeyId>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryPlan {
+    /// Payload and blockmap entry can be copied from the previous package
+    Reuse {
+        /// File id in the previous package
+        file_id: u64,
+        /// Stored bytes that don't need to be processed again
+        stored_length: u64,
+    },
+    /// File is new or changed, or reuse is not possible
+    Rebuild(RebuildReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebuildReason {
+    /// Not part of the previous package
+    New,
+    /// Content differs from the previous package
+    Changed,
+    /// Previous package lacks a file hash to compare against
+    NoFileHash,
+    /// Encrypted with another key, or encryption state differs
+    KeyMismatch,
+    /// Package family name changed, so encryption tweaks differ
+    IdentityMismatch,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReusePlan {
+    pub entries: Vec<(String, EntryPlan)>,
+}
+
+impl ReusePlan {
+    pub fn reused(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.entries.iter().filter_map(|(name, plan)| match plan {
+            EntryPlan::Reuse { file_id, .. } => Some((name.as_str(), *file_id)),
+            EntryPlan::Rebuild(_) => None,
+        })
+    }
+
+    /// Amount of stored bytes that can be copied verbatim
+    pub fn reused_bytes(&self) -> u64 {
+        self.entries.iter().map(|(_, plan)| match plan {
+            EntryPlan::Reuse { stored_length, .. } => *stored_length,
+            EntryPlan::Rebuild(_) => 0,
+        }).sum()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&EntryPlan> {
+        self.entries.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, plan)| plan)
+    }
+}
+
+/// Decide for every entry of a new build whether its payload can be reused
+///
+/// `pfn` is the package family name of the new build (`Name_PublisherId`).
+pub fn plan_reuse<I: IntoIterator<Item = NewEntry>>(
+    previous: &EAppxFile,
+    pfn: &str,
+    entries: I,
+) -> ReusePlan {
+    let previous_pfn = format!("{}_{}", previous.header.app_name(), previous.header.publisher_id());
+    let same_identity = previous_pfn.eq_ignore_ascii_case(pfn);
+
+    let previous_files: HashMap<String, &crate::blockmap::File> = previous.blockmap.files
+        .iter()
+        .map(|f| (f.name.to_lowercase(), f))
+        .collect();
+
+    let plan_entry = |entry: &NewEntry| -> EntryPlan {
+        let Some(file) = previous_files.get(&entry.name.to_lowercase()) else {
+            return EntryPlan::Rebuild(RebuildReason::New);
+        };
+        let Some(footer) = previous.find_footer_for_file(file.id()) else {
+            return EntryPlan::Rebuild(RebuildReason::New);
+        };
+        let Ok(Some(filehash)) = file.filehash_bytes() else {
+            return EntryPlan::Rebuild(RebuildReason::NoFileHash);
+        };
+
+        if file.size != entry.size || filehash != entry.filehash {
+            return EntryPlan::Rebuild(RebuildReason::Changed);
+        }
+
+        let previous_key = match previous.is_footer_encrypted(footer) {
+            true => previous.header.key_ids.get(footer.key_id_index as usize),
+            false => None,
+        };
+        if previous_key != entry.key_id.as_ref() {
+            return EntryPlan::Rebuild(RebuildReason::KeyMismatch);
+        }
+        if previous_key.is_some() && !same_identity {
+            return EntryPlan::Rebuild(RebuildReason::IdentityMismatch);
+        }
+
+        EntryPlan::Reuse {
+            file_id: footer.file_id,
+            stored_length: previous.stored_length(footer),
+        }
+    };
+
+    let entries = entries.into_iter()
+        .map(|entry| {
+            let plan = plan_entry(&entry);
+            log::debug!("Incremental plan for {}: {plan:?}", entry.name);
+            (entry.name, plan)
+        })
+        .collect();
+
+    ReusePlan { entries }
+}
+
+/// Copy the stored (compressed/encrypted) payload of a footer verbatim
+pub fn copy_stored_payload<P: crate::source::PackageSource + ?Sized, W: std::io::Write>(
+    source: &P,
+    footer: &EAppxFooter,
+    from_bundle: bool,
+    writer: &mut W,
+) -> Result<u64, Error> {
+    let length = footer.stored_length(from_bundle);
+    let copied = std::io::copy(&mut source.range(footer.offset_to_file, length), writer)?;
+    if copied != length {
+        return Err(Error::DataError(format!("Payload of file {:#x} truncated ({copied:#x} of {length:#x} bytes)", footer.file_id)));
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const PFN: &str = "8b18b0ca-7bac-4263-8be8-9a7a1292c90d_nh20k94c8ngfj";
+
+    fn entries_from(eappx: &EAppxFile) -> Vec<NewEntry> {
+        eappx.blockmap.files.iter()
+            .map(|f| {
+                let footer = eappx.find_footer_for_file(f.id()).unwrap();
+                NewEntry {
+                    name: f.name.clone(),
+                    size: f.size,
+                    filehash: f.filehash_bytes().unwrap().unwrap(),
+                    key_id: footer.is_encrypted(false).then(|| eappx.header.key_ids[0].clone()),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_unchanged() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let plan = plan_reuse(&eappx, PFN, entries_from(&eappx));
+
+        assert_eq!(plan.reused().count(), eappx.blockmap.files.len());
+        assert_eq!(plan.get("testapp.dll"), Some(&EntryPlan::Reuse { file_id: 0xa, stored_length: 0x100e00 }));
+    }
+
+    #[test]
+    fn test_plan_changes() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let mut entries = entries_from(&eappx);
+        entries[0].filehash[0] ^= 0xFF;
+        entries.push(NewEntry { name: "new.bin".into(), size: 1, filehash: vec![0; 32], key_id: None });
+
+        let plan = plan_reuse(&eappx, PFN, entries.clone());
+        assert_eq!(plan.get("AppxManifest.xml"), Some(&EntryPlan::Rebuild(RebuildReason::Changed)));
+        assert_eq!(plan.get("new.bin"), Some(&EntryPlan::Rebuild(RebuildReason::New)));
+
+        // Other identity invalidates encrypted entries only
+        let plan = plan_reuse(&eappx, "Other_nh20k94c8ngfj", entries);
+        assert_eq!(plan.get("TestApp.exe"), Some(&EntryPlan::Rebuild(RebuildReason::IdentityMismatch)));
+        assert!(matches!(plan.get("resources.pri"), Some(EntryPlan::Reuse { .. })));
+    }
+
+    #[test]
+    fn test_copy_stored_payload() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let footer = eappx.find_footer_for_file(1).unwrap();
+
+        let mut out = vec![];
+        let copied = copy_stored_payload(EMSIX, footer, false, &mut out).unwrap();
+        assert_eq!(copied, 0x600);
+        assert_eq!(out, &EMSIX[0x6ad..0x6ad + 0x600]);
+    }
+}
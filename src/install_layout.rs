@@ -0,0 +1,157 @@
+//! Extraction into the layout Windows installs packages as
+//!
+//! An installed package is the plaintext payload under its package paths,
+//! next to AppxManifest.xml, AppxBlockMap.xml and AppxMetadata. The stored
+//! blockmap of an encrypted package hashes ciphertext and the signature covers
+//! that blockmap, so both are useless for decrypted files. Instead the
+//! footprint is regenerated the way converting to msix does, which lets the
+//! output be registered with `Add-AppxPackage -Register` during development.
+
+use std::{io::{Read, Write}, path::Path};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::Error,
+    extension::ContainerKind,
+    extraction_report::{ExtractedFile, ExtractionReport, ExtractionStatus, HashingWriter},
+    footprint::FootprintFile,
+    layout::{ExtractOptions, OutputLayout},
+    msix::MsixWriter,
+    source::PackageSource,
+    utils,
+    EAppxFile,
+};
+
+/// Copies everything read into `out`
+struct TeeReader<R: Read, W: Write> {
+    reader: R,
+    out: W,
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.out.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+impl EAppxFile {
+    /// Extract a package into its install layout below `target_filepath`
+    ///
+    /// Payload and metadata files keep their package paths, even with
+    /// [`ExtractOptions::flat`], existing files are overwritten. AppxBlockMap.xml
+    /// and `[Content_Types].xml` are generated for the plaintext, the catalog
+    /// goes to AppxMetadata. A signature is dropped, it does not cover the
+    /// generated blockmap. Bundles have to be unbundled first.
+    pub fn extract_install_layout<P: PackageSource + ?Sized>(&self, source: &P, target_filepath: &Path) -> Result<ExtractionReport, Error> {
+        if self.header.is_bundle() || !self.bundle_payloads(source)?.is_empty() {
+            return Err(Error::DataError("Install layout is only available for packages, extract the packages of a bundle first".into()));
+        }
+        // Ciphertext would end up in the generated blockmap, every key is needed
+        self.check_keys()?;
+        let mut report = self.begin_extraction()?;
+        self.check_disk_space(target_filepath)?;
+        let code_integrity = self.read_code_integrity(source)?;
+
+        let mut layout = OutputLayout::new(ExtractOptions { flat: false, ..self.extract_options });
+        let mut create = |package_path: &str| -> Result<(std::path::PathBuf, std::fs::File), Error> {
+            let relative_path = layout.map(package_path)?;
+            let path = utils::long_path(&target_filepath.join(&relative_path))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Ok((relative_path, std::fs::File::create(path)?))
+        };
+
+        let mut msix = MsixWriter::new(std::io::sink(), ContainerKind::Package);
+        for file in &self.blockmap.files {
+            log::info!("* Extracting {}", file.name);
+            let (relative_path, out) = create(&file.name)?;
+            let out = HashingWriter::with_filehash(std::io::BufWriter::new(out), self.blockmap.filehash_algorithm(file));
+            let mut reader = TeeReader { reader: self.file_reader(source, file)?, out };
+            let sha256 = msix.add_file(&file.name, &mut reader)?;
+            reader.out.flush()?;
+
+            let (_, _, digest) = reader.out.finalize_digests();
+            let status = ExtractionStatus::of_plaintext(file.filehash_bytes()?.as_deref(), &digest);
+            report.files.push(ExtractedFile {
+                package_path: file.name.clone(),
+                path: relative_path,
+                size: file.size,
+                sha256: Some(sha256),
+                encrypted: self.is_file_encrypted(file),
+                compressed: self.find_footer_for_file(file.id()).is_some_and(|footer| footer.compression_type == 0x1),
+                status,
+                timings: None,
+                duplicate_of: None,
+            });
+        }
+
+        if self.header.is_signed() {
+            log::warn!("Signature does not cover the generated blockmap and is dropped");
+        }
+        let footprint = msix.footprint(code_integrity.as_deref())?;
+        let footprint_files = [
+            (FootprintFile::BlockMap.package_path(), Some(footprint.blockmap.into_bytes())),
+            ("[Content_Types].xml", Some(footprint.content_types.into_bytes())),
+            (FootprintFile::CodeIntegrity.package_path(), footprint.code_integrity),
+        ];
+        for (package_path, data) in footprint_files {
+            let Some(data) = data else {
+                continue;
+            };
+            log::info!("* Generating {package_path}");
+            let (relative_path, mut out) = create(package_path)?;
+            out.write_all(&data)?;
+            report.files.push(ExtractedFile {
+                package_path: package_path.to_owned(),
+                path: relative_path,
+                size: data.len() as u64,
+                sha256: Some(Sha256::digest(&data).to_vec()),
+                encrypted: false,
+                compressed: false,
+                status: ExtractionStatus::Unverified,
+                timings: None,
+                duplicate_of: None,
+            });
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sha2::{Digest, Sha256};
+
+    use crate::{blockmap::AppxBlockMap, extraction_report::ExtractionStatus, EAppxFile};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    #[test]
+    fn test_extract_install_layout() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("eappx-install-layout-{}", std::process::id()));
+        let report = eappx.extract_install_layout(EMSIX, &dir).unwrap();
+        assert!(report.is_valid());
+        assert!(report.files.iter().any(|file| file.status == ExtractionStatus::Verified));
+
+        // Generated blockmap covers the plaintext on disk
+        let blockmap = AppxBlockMap::from_xml(&std::fs::read(dir.join("AppxBlockMap.xml")).unwrap()).unwrap();
+        assert_eq!(blockmap.files.len(), eappx.blockmap.files.len());
+        let dll = std::fs::read(dir.join("TestApp.dll")).unwrap();
+        assert_eq!(blockmap.find_file("TestApp.dll").unwrap().block_hashes().unwrap()[0], Sha256::digest(&dll[..0x10000]).to_vec());
+        assert!(dir.join("[Content_Types].xml").is_file());
+        assert!(dir.join("AppxMetadata").join("CodeIntegrity.cat").is_file());
+        assert!(dir.join("Assets").join("StoreLogo.png").is_file());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let bundle = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        assert!(bundle.extract_install_layout(EMSIXBUNDLE, &dir).is_err());
+    }
+}
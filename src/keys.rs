@@ -6,14 +6,181 @@ use uuid::{uuid, Uuid};
 use base64ct::{Base64, Encoding};
 use crate::error::Error;
 
-const SHORT_KEY_GUID_PREFIX: Uuid = uuid!("BB1755DB-5052-4B10-B2AB-F3ABF5CA5B41");
+/// GUID a short 16-byte key-id follows in a header, making it 32 bytes
+pub const SHORT_KEY_GUID_PREFIX: Uuid = uuid!("BB1755DB-5052-4B10-B2AB-F3ABF5CA5B41");
+/// Content keys are XTS-AES-128 key pairs
+pub const KEY_LENGTH: usize = 0x20;
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+/// Key-id of the global test key, which MakeAppx uses for `/kt`
+pub const TEST_KEY_ID: KeyId = KeyId::Guid((
+    uuid!("ddafcf67-7b2c-086d-302b-8adac1bdd3a7"),
+    uuid!("7d53aeb8-5922-f062-b1d7-7e09f5a187a0"),
+));
+
+/// Global test key, publicly known, so packages encrypted with it are not protected
+pub const TEST_KEY: [u8; KEY_LENGTH] = [
+    0x9f, 0xe7, 0x5f, 0x87, 0x9e, 0x95, 0xa5, 0xd7, 0xf3, 0x71, 0x5c, 0x30, 0xfc, 0xe7, 0x10, 0x67,
+    0xfc, 0x34, 0x6e, 0xfd, 0x68, 0x0f, 0xa2, 0x5e, 0x3c, 0x73, 0x7d, 0x76, 0xac, 0xb7, 0x2b, 0x9d,
+];
+
+/// Content key size the header crypto algorithm calls for, `None` if unknown
+///
+/// XTS takes two AES keys, 16 bytes each for XTS-AES (AES-128) and 32 each for
+/// XTS-AES-256. Only XTS-AES is implemented.
+pub fn key_length_for_algorithm(crypto_algo: &str) -> Option<usize> {
+    match crypto_algo {
+        "XTS-AES" | "XTS-AES-128" => Some(KEY_LENGTH),
+        "XTS-AES-256" => Some(2 * KEY_LENGTH),
+        _ => None,
+    }
+}
+
+/// Overwrite key material before it is freed, a no-op without the `zeroize` feature
+pub(crate) fn wipe(key: &mut [u8]) {
+    #[cfg(feature = "zeroize")]
+    zeroize::Zeroize::zeroize(key);
+    #[cfg(not(feature = "zeroize"))]
+    let _ = key;
+}
+
+/// Key-id of a content key
+///
+/// Equality and hashing go by [`KeyId::canonical`], so every representation of
+/// the same key-id finds the same key.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum KeyId {
     Numeric(u16),
     Guid((Uuid, Uuid))
 }
 
+impl KeyId {
+    /// GUID pair the key id is stored as in a header
+    ///
+    /// Numeric ids have no binary form of their own, `n` is written as the
+    /// nil GUID followed by `Uuid::from_u128(n)` and read back as `Numeric(n)`.
+    ///
+    /// ```
+    /// # use eappx::keys::KeyId;
+    /// # use uuid::{uuid, Uuid};
+    /// assert_eq!(KeyId::Numeric(1).to_guid(), (Uuid::nil(), uuid!("00000000-0000-0000-0000-000000000001")));
+    /// ```
+    pub fn to_guid(&self) -> (Uuid, Uuid) {
+        match self {
+            KeyId::Numeric(numeric) => (Uuid::nil(), Uuid::from_u128(*numeric as u128)),
+            KeyId::Guid(guid) => *guid,
+        }
+    }
+
+    /// Inverse of [`KeyId::to_guid`]
+    pub fn from_guid(guid: (Uuid, Uuid)) -> Self {
+        match (guid.0.is_nil(), u16::try_from(guid.1.as_u128())) {
+            (true, Ok(numeric)) => KeyId::Numeric(numeric),
+            _ => KeyId::Guid(guid),
+        }
+    }
+
+    /// Short 16-byte key-id, stored behind the `BB1755DB-5052-4B10-B2AB-F3ABF5CA5B41` prefix GUID
+    pub fn from_short(short: Uuid) -> Self {
+        KeyId::Guid((SHORT_KEY_GUID_PREFIX, short))
+    }
+
+    /// Key-id from the 32 bytes of a header or keyfile, see [`KeyId::to_bytes`]
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        KeyId::from_guid((
+            Uuid::from_bytes_le(bytes[..16].try_into().unwrap()),
+            Uuid::from_bytes_le(bytes[16..].try_into().unwrap()),
+        ))
+    }
+
+    /// Key-id from the 16 bytes of a short keyfile entry
+    ///
+    /// Windows expands them to 32 bytes by putting [`SHORT_KEY_GUID_PREFIX`]
+    /// in front, in the same mixed-endian byte order.
+    ///
+    /// ```
+    /// # use eappx::keys::KeyId;
+    /// let short: [u8; 16] = hex::decode("f98e3c2a5abb49ef1187897e0e87e215").unwrap().try_into().unwrap();
+    /// let key_id = KeyId::from_short_bytes(short);
+    /// assert_eq!(hex::encode(key_id.to_bytes()), "db5517bb5250104bb2abf3abf5ca5b41f98e3c2a5abb49ef1187897e0e87e215");
+    /// assert_eq!(key_id.to_short_bytes(), Some(short));
+    /// ```
+    pub fn from_short_bytes(bytes: [u8; 16]) -> Self {
+        KeyId::from_short(Uuid::from_bytes_le(bytes))
+    }
+
+    /// The 16 bytes of the short form, only key-ids behind the prefix (or nil) GUID have one
+    pub fn to_short_bytes(&self) -> Option<[u8; 16]> {
+        match self.canonical() {
+            (first, second) if first == SHORT_KEY_GUID_PREFIX => Some(second.to_bytes_le()),
+            _ => None,
+        }
+    }
+
+    /// GUID pair shared by all representations of the key-id
+    ///
+    /// A short key-id is the same whether it follows the prefix GUID or the
+    /// nil GUID, and numeric ids equal their GUID form.
+    ///
+    /// ```
+    /// # use eappx::keys::KeyId;
+    /// # use uuid::{uuid, Uuid};
+    /// let short = uuid!("a04720f2-1ee7-edb8-25b2-6891349c8002");
+    /// assert_eq!(KeyId::Guid((Uuid::nil(), short)), KeyId::from_short(short));
+    /// assert_eq!(KeyId::Guid((Uuid::nil(), Uuid::from_u128(7))), KeyId::Numeric(7));
+    /// ```
+    pub fn canonical(&self) -> (Uuid, Uuid) {
+        match self.to_guid() {
+            (first, second) if first.is_nil() && u16::try_from(second.as_u128()).is_err() => (SHORT_KEY_GUID_PREFIX, second),
+            guid => guid,
+        }
+    }
+
+    /// Key-id as written in keyfiles, see [`KeyCollection::from_str`]
+    pub fn to_keyfile_string(&self) -> String {
+        match self {
+            KeyId::Numeric(numeric) => numeric.to_string(),
+            KeyId::Guid(_) => Base64::encode_string(&self.to_bytes()),
+        }
+    }
+
+    /// 16-byte keyfile form, only short key-ids have one
+    pub fn to_short_keyfile_string(&self) -> Option<String> {
+        self.to_short_bytes().map(|bytes| Base64::encode_string(&bytes))
+    }
+
+    /// The 32 bytes as stored in a header, as hex
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    /// The 32 bytes as stored in a header and base64 encoded in keyfiles
+    ///
+    /// Both GUIDs are in the mixed-endian order of Windows GUID structs, the
+    /// first three fields little-endian.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let (first, second) = self.to_guid();
+        let mut bytes = [0u8; 32];
+        bytes[..16].copy_from_slice(&first.to_bytes_le());
+        bytes[16..].copy_from_slice(&second.to_bytes_le());
+        bytes
+    }
+}
+
+impl PartialEq for KeyId {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical() == other.canonical()
+    }
+}
+
+impl Eq for KeyId {}
+
+impl std::hash::Hash for KeyId {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.canonical().hash(state);
+    }
+}
+
 impl BinRead for KeyId {
     type Args<'a> = ();
 
@@ -25,22 +192,18 @@ impl BinRead for KeyId {
         let mut buf = [0u8; 32];
         reader.read_exact(&mut buf)?;
 
-        let key_id = match endian {
-            binrw::Endian::Big => {
-                KeyId::Guid((
-                    Uuid::from_bytes(buf[..16].try_into().unwrap()),
-                    Uuid::from_bytes(buf[16..].try_into().unwrap()),
-                ))
-            },
-            binrw::Endian::Little => {
-                KeyId::Guid((
-                    Uuid::from_bytes_le(buf[..16].try_into().unwrap()),
-                    Uuid::from_bytes_le(buf[16..].try_into().unwrap()),
-                ))
-            },
+        let guid = match endian {
+            binrw::Endian::Big => (
+                Uuid::from_bytes(buf[..16].try_into().unwrap()),
+                Uuid::from_bytes(buf[16..].try_into().unwrap()),
+            ),
+            binrw::Endian::Little => (
+                Uuid::from_bytes_le(buf[..16].try_into().unwrap()),
+                Uuid::from_bytes_le(buf[16..].try_into().unwrap()),
+            ),
         };
 
-        Ok(key_id)
+        Ok(KeyId::from_guid(guid))
     }
 }
 
@@ -53,19 +216,15 @@ impl BinWrite for KeyId {
         endian: binrw::Endian,
         _: Self::Args<'_>,
     ) -> binrw::prelude::BinResult<()> {
-        match self {
-            KeyId::Numeric(_) => panic!("Cannot serialize numeric keyid into binary"),
-            KeyId::Guid(keyid) => {
-                match endian {
-                    binrw::Endian::Big => {
-                        let _ = writer.write(keyid.0.as_bytes())?;
-                        let _ = writer.write(keyid.1.as_bytes())?;
-                    },
-                    binrw::Endian::Little => {
-                        let _ = writer.write(&keyid.0.to_bytes_le())?;
-                        let _ = writer.write(&keyid.1.to_bytes_le())?;
-                    },
-                }
+        let keyid = self.to_guid();
+        match endian {
+            binrw::Endian::Big => {
+                writer.write_all(keyid.0.as_bytes())?;
+                writer.write_all(keyid.1.as_bytes())?;
+            },
+            binrw::Endian::Little => {
+                writer.write_all(&keyid.0.to_bytes_le())?;
+                writer.write_all(&keyid.1.to_bytes_le())?;
             },
         }
 
@@ -87,11 +246,21 @@ impl std::fmt::Display for KeyId {
     }
 }
 
+/// Content keys by key-id
+///
+/// With the `zeroize` feature the keys are wiped on drop, move them out with
+/// [`KeyCollection::merge`] or `keys.drain()`.
 #[derive(Debug, Default)]
 pub struct KeyCollection {
     pub keys: HashMap<KeyId, Vec<u8>>,
 }
 
+impl Drop for KeyCollection {
+    fn drop(&mut self) {
+        self.keys.values_mut().for_each(|key| wipe(key));
+    }
+}
+
 impl KeyCollection {
     /// Check if all keys are contained
     /// It gets passed the list of key-ids in the file-header
@@ -99,6 +268,17 @@ impl KeyCollection {
         key_ids.iter().all(|k|self.keys.contains_key(k))
     }
 
+    /// Key-ids of `key_ids` without a key in the collection, in their order
+    pub fn missing_keys(&self, key_ids: &[KeyId]) -> Vec<KeyId> {
+        let mut missing: Vec<KeyId> = vec![];
+        for key_id in key_ids.iter().filter(|k| !self.keys.contains_key(k)) {
+            if !missing.contains(key_id) {
+                missing.push(key_id.clone());
+            }
+        }
+        missing
+    }
+
     /// Create a new instance of KeyCollection
     pub fn new(keys: &HashMap<KeyId, Vec<u8>>) -> Self {
         Self {
@@ -108,85 +288,174 @@ impl KeyCollection {
 
     /// Add a key by key-id and keydata
     pub fn add(&mut self, keyid: KeyId, keydata: Vec<u8>) {
-        self.keys.insert(keyid, keydata);
+        if let Some(mut replaced) = self.keys.insert(keyid, keydata) {
+            wipe(&mut replaced);
+        }
     }
 
     /// Extend the key collection with a mapping of key-id -> keydata
     pub fn extend(&mut self, entries: HashMap<KeyId, Vec<u8>>) {
-        self.keys.extend(entries)
+        entries.into_iter().for_each(|(keyid, keydata)| self.add(keyid, keydata));
+    }
+
+    /// Take over all keys of `other`
+    pub fn merge(&mut self, mut other: KeyCollection) {
+        other.keys.drain().for_each(|(keyid, keydata)| self.add(keyid, keydata));
     }
 
     pub fn from_reader<T: std::io::Read>(reader: &mut T) -> Result<Self, Error> {
         let mut buf = String::new();
-        reader.read_to_string(&mut buf)?;
-        Self::from_str(&buf)
+        let result = reader.read_to_string(&mut buf)
+            .map_err(Error::from)
+            .and_then(|_| Self::from_str(&buf));
+        wipe(&mut buf.into_bytes());
+        result
+    }
+
+    /// Collection holding just the global test key, see [`TEST_KEY_ID`]
+    pub fn with_test_key() -> Self {
+        let mut keys = Self::default();
+        keys.add_test_key();
+        keys
+    }
+
+    /// Add the global test key
+    pub fn add_test_key(&mut self) {
+        self.add(TEST_KEY_ID, TEST_KEY.to_vec());
+    }
+
+    /// Create a collection holding a single random 256-bit key with a fresh key-id
+    pub fn generate() -> Result<Self, Error> {
+        let mut key = vec![0u8; KEY_LENGTH];
+        getrandom::getrandom(&mut key)
+            .map_err(|e| Error::DataError(format!("Failed to gather randomness: {e}")))?;
+
+        let mut keys = HashMap::new();
+        keys.insert(KeyId::Guid((Uuid::new_v4(), Uuid::new_v4())), key);
+
+        Ok(Self { keys })
+    }
+
+    /// Serialize in keyfile format, see [`KeyCollection::from_str`]
+    pub fn to_writer<T: std::io::Write>(&self, writer: &mut T) -> Result<(), Error> {
+        let mut entries = self.keys.iter()
+            .map(|(key_id, key)| {
+                (key_id.to_keyfile_string(), Base64::encode_string(key))
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        writeln!(writer, "[Keys]")?;
+        for (key_id, key) in entries {
+            writeln!(writer, "\"{key_id}\" \"{key}\"")?;
+        }
+
+        Ok(())
     }
 }
 
 
+/// Key-id of a keyfile entry: a decimal number, or base64 of 16 or 32 GUID bytes
+fn parse_key_id(key_id: &str) -> Result<KeyId, String> {
+    if let Ok(numeric) = key_id.parse::<u16>() {
+        return Ok(KeyId::Numeric(numeric));
+    }
+
+    let bytes = Base64::decode_vec(key_id)
+        .map_err(|e| format!("Invalid key-id \"{key_id}\": {e}"))?;
+    match bytes.len() {
+        16 => Ok(KeyId::from_short_bytes(bytes.try_into().unwrap())),
+        32 => Ok(KeyId::from_bytes(bytes.try_into().unwrap())),
+        len => Err(format!("Unsupported key-id length {len}, expected 16 or 32 bytes")),
+    }
+}
+
+impl FromStr for KeyId {
+    type Err = Error;
+
+    /// Key-id in any form keyfiles accept
+    ///
+    /// A numeric id equals its 32-byte header form, so either finds the key of
+    /// a package keyed with it.
+    ///
+    /// ```
+    /// # use eappx::keys::KeyId;
+    /// # use std::str::FromStr;
+    /// let header_form = KeyId::from_str("AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAE=").unwrap();
+    /// assert_eq!(header_form, KeyId::from_str("1").unwrap());
+    /// assert_eq!(header_form.to_keyfile_string(), "1");
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_key_id(s).map_err(Error::DecodeError)
+    }
+}
+
+/// Split `"<key-id>" "<key>"`, a trailing comment is allowed
+fn split_entry(line: &str) -> Option<(&str, &str)> {
+    let (key_id, rest) = line.strip_prefix('"')?.split_once('"')?;
+    let (key, rest) = rest.trim_start().strip_prefix('"')?.split_once('"')?;
+    let rest = rest.trim_start();
+    (rest.is_empty() || rest.starts_with([';', '#'])).then_some((key_id, key))
+}
+
 impl FromStr for KeyCollection {
     type Err = Error;
 
     /// Deserialize a string
-    /// 
+    ///
+    /// The keyfile starts with a `[Keys]` section holding one
+    /// `"<key-id>" "<key>"` entry per line. Lines starting with `;` or `#`
+    /// are comments, other sections are skipped.
+    ///
     /// ```
     /// # use eappx::keys::KeyCollection;
     /// # use std::str::FromStr;
     /// let keystr = r#"
+    /// ; Exported keys
     /// [Keys]
     /// "8iBHoOceuO0lsmiRNJyAAvmOPCpau0nvEYeJfg6H4hU=" "BAheoEHgSsMqshmRvAQMO5/dff91n42OYG4Va0bqgL4="
     /// "#;
-    /// 
+    ///
     /// assert!(KeyCollection::from_str(keystr).is_ok());
+    /// assert!(KeyCollection::from_str("[Licenses]").is_err());
     /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut keys = HashMap::new();
-        let data = s.trim();
-
-        if !data.starts_with("[Keys]") {
-            panic!("Invalid keyfile magic, expected [Keys]");
-        }
+        // Already parsed keys are wiped on errors, too
+        let mut collection = Self::default();
+        let mut section: Option<&str> = None;
 
-        for line in data.split('\n') {
+        for (index, line) in s.lines().enumerate() {
+            let error = |message: String| Error::DecodeError(format!("Keyfile line {}: {message}", index + 1));
             let line = line.trim();
-            if line.is_empty() {
+
+            if line.is_empty() || line.starts_with([';', '#']) {
                 continue;
-            } else if line.starts_with('\"') {
-                let mut parts = line.split_whitespace();
-                let key_id_str = parts.next().unwrap().replace('\"', "");
-                let key_str = parts.next().unwrap().replace('\"', "");
-
-                let key = Base64::decode_vec(&key_str)
-                    .map_err(|e| Error::DecodeError(e.to_string()))?;
-
-                if let Ok(bytes_keyid) = Base64::decode_vec(&key_id_str) {
-                    let keyid = match bytes_keyid.len() {
-                        16 => {
-                            // 16 bytes KeyID - prefix it with a static value
-                            KeyId::Guid((
-                                SHORT_KEY_GUID_PREFIX,
-                                Uuid::from_bytes_le(bytes_keyid.try_into().unwrap())
-                            ))
-                        },
-                        32 => {
-                            KeyId::Guid((
-                                Uuid::from_bytes_le(bytes_keyid[..16].try_into().unwrap()),
-                                Uuid::from_bytes_le(bytes_keyid[16..32].try_into().unwrap())
-                            )) 
-                        },
-                        _len => {
-                            return Err(Error::DecodeError("Unsupported KeyId Guid length".into()))
-                        }
-                    };
-                    keys.insert(keyid, key);
-                } else if let Ok(numeric_keyid) = key_id_str.parse::<u16>() {
-                    let keyid = KeyId::Numeric(numeric_keyid);
-                    keys.insert(keyid, key);
+            } else if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                if section.is_none() && name != "Keys" {
+                    return Err(error("Invalid keyfile magic, expected [Keys]".into()));
                 }
+                section = Some(name);
+            } else if section.is_none() {
+                return Err(error("Invalid keyfile magic, expected [Keys]".into()));
+            } else if section == Some("Keys") {
+                let (key_id, key) = split_entry(line)
+                    .ok_or_else(|| error("Expected \"<key-id>\" \"<key>\"".into()))?;
+                let key_id = parse_key_id(key_id).map_err(error)?;
+                let key = Base64::decode_vec(key)
+                    .map_err(|e| Error::decode(format!("Keyfile line {}: Invalid key", index + 1), e))?;
+
+                if collection.keys.contains_key(&key_id) {
+                    return Err(error(format!("Duplicate key-id {key_id}")));
+                }
+                collection.add(key_id, key);
             }
         }
 
-        Ok(Self { keys })
+        if section.is_none() {
+            return Err(Error::DecodeError("Invalid keyfile magic, expected [Keys]".into()));
+        }
+
+        Ok(collection)
     }
 }
 
@@ -209,6 +478,124 @@ mod tests {
         assert_eq!(keys.keys.values().next().unwrap(), &hex::decode(KEY_DATA).unwrap())
     }
 
+    #[test]
+    fn test_missing_keys() {
+        let keys = KeyCollection::from_str(KEY_FILE).unwrap();
+        let known = KeyId::Guid((KEY_ID_0, KEY_ID_1));
+        let unknown = KeyId::Numeric(7);
+        assert!(keys.missing_keys(std::slice::from_ref(&known)).is_empty());
+        assert_eq!(keys.missing_keys(&[unknown.clone(), known.clone(), unknown.clone()]), std::slice::from_ref(&unknown));
+        assert!(!keys.has_required_keys(&[known, unknown]));
+    }
+
+    #[test]
+    fn test_generate_roundtrip() {
+        let generated = KeyCollection::generate().unwrap();
+        assert_eq!(generated.keys.len(), 1);
+        assert_eq!(generated.keys.values().next().unwrap().len(), KEY_LENGTH);
+        assert_ne!(generated.keys, KeyCollection::generate().unwrap().keys);
+
+        let mut buf = vec![];
+        generated.to_writer(&mut buf).unwrap();
+        let parsed = KeyCollection::from_reader(&mut buf.as_slice()).unwrap();
+        assert_eq!(parsed.keys, generated.keys);
+    }
+
+    #[test]
+    fn test_to_writer() {
+        let keys = KeyCollection::from_str(KEY_FILE).unwrap();
+        let mut buf = vec![];
+        keys.to_writer(&mut buf).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap().trim(), KEY_FILE.trim());
+
+        assert_eq!(TEST_KEY_ID.to_keyfile_string(), "Z8+v3Sx7bQgwK4rawb3Tp7iuU30iWWLwsdd+CfWhh6A=");
+        assert!(KeyCollection::with_test_key().has_required_keys(&[TEST_KEY_ID]));
+        assert_eq!(KeyId::Numeric(7).to_keyfile_string(), "7");
+    }
+
+    #[test]
+    fn test_short_key_id_lookup() {
+        let short = KeyId::from_short(KEY_ID_1);
+        let bare = KeyId::Guid((Uuid::nil(), KEY_ID_1));
+        assert_eq!(short, bare);
+        assert_eq!(bare.to_short_keyfile_string(), short.to_short_keyfile_string());
+        assert_ne!(short, KeyId::Guid((KEY_ID_0, KEY_ID_1)));
+
+        // A key given with the bare GUID covers a header listing the prefixed one, and the other way around
+        let mut keys = KeyCollection::default();
+        keys.add(bare.clone(), vec![0; KEY_LENGTH]);
+        assert!(keys.keys.contains_key(&short));
+        assert!(keys.missing_keys(std::slice::from_ref(&short)).is_empty());
+        keys.add(short, vec![1; KEY_LENGTH]);
+        assert_eq!(keys.keys.len(), 1);
+        assert_eq!(keys.keys[&bare], vec![1; KEY_LENGTH]);
+
+        // Headers keep the form they were written with
+        use binrw::{BinReaderExt, BinWriterExt};
+        let mut buf = std::io::Cursor::new(vec![]);
+        buf.write_le(&bare).unwrap();
+        assert_eq!(&buf.get_ref()[..16], &[0; 16]);
+        buf.set_position(0);
+        assert_eq!(buf.read_le::<KeyId>().unwrap().to_guid(), bare.to_guid());
+    }
+
+    #[test]
+    fn test_key_id_representations() {
+        let key_id = KeyId::Guid((KEY_ID_0, KEY_ID_1));
+        assert_eq!(key_id.to_hex(), hex::encode(Base64::decode_vec(&key_id.to_keyfile_string()).unwrap()));
+        assert!(key_id.to_hex().starts_with("f22047a0e71eb8ed"));
+        assert_eq!(key_id.to_short_keyfile_string(), None);
+        assert_eq!(KeyId::Numeric(1).to_hex(), format!("{}1", "0".repeat(63)));
+
+        // Short key-ids parse back to the same id from either form
+        let short = parse_key_id(&Base64::encode_string(&KEY_ID_1.to_bytes_le())).unwrap();
+        let encoded = short.to_short_keyfile_string().unwrap();
+        assert_eq!(parse_key_id(&encoded).unwrap(), short);
+        assert_eq!(parse_key_id(&short.to_keyfile_string()).unwrap(), short);
+    }
+
+    #[test]
+    fn test_numeric_key_id_binary() {
+        use binrw::{BinReaderExt, BinWriterExt};
+
+        for key_id in [KeyId::Numeric(0), KeyId::Numeric(0xffff), KeyId::Guid((KEY_ID_0, KEY_ID_1))] {
+            let mut buf = std::io::Cursor::new(vec![]);
+            buf.write_le(&key_id).unwrap();
+            assert_eq!(buf.get_ref().len(), 32);
+            buf.set_position(0);
+            assert_eq!(buf.read_le::<KeyId>().unwrap(), key_id);
+        }
+    }
+
+    #[test]
+    fn test_from_str_lenient() {
+        let keystr = "; exported\r\n[Keys]\r\n\r\n# numeric\r\n\"1234\" \"AAAA\" ; trailing\r\n[Licenses]\r\nignored\r\n";
+        let keys = KeyCollection::from_str(keystr).unwrap();
+        assert_eq!(keys.keys.get(&KeyId::Numeric(1234)), Some(&vec![0, 0, 0]));
+
+        assert!(KeyCollection::from_str("[Keys]\n[Other]").unwrap().keys.is_empty());
+    }
+
+    #[test]
+    fn test_from_str_errors() {
+        let message = |keystr: &str| KeyCollection::from_str(keystr).unwrap_err().to_string();
+
+        assert!(message("").contains("expected [Keys]"));
+        assert!(message("\"1\" \"AAAA\"").contains("line 1: Invalid keyfile magic"));
+        assert!(message("[Keys]\n\"1\"").contains("line 2: Expected"));
+        assert!(message("[Keys]\n\"AAAA\" \"AAAA\"").contains("line 2: Unsupported key-id length 3"));
+        assert!(message("[Keys]\n\"1\" \"!\"").contains("line 2: Invalid key"));
+        assert!(message("[Keys]\r\n\"1\" \"AAAA\"\r\n\"1\" \"AAAA\"").contains("line 3: Duplicate key-id KeyId { 1 }"));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn test_wipe() {
+        let mut key = hex::decode(KEY_DATA).unwrap();
+        wipe(&mut key);
+        assert_eq!(key, [0; KEY_LENGTH]);
+    }
+
     #[test]
     fn test_from_reader() {
         let mut cursor = std::io::Cursor::new(KEY_FILE.as_bytes());
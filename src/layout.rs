@@ -0,0 +1,533 @@
+//! Mapping of package paths onto the local filesystem during extraction
+//!
+//! Package paths use backslashes and come from a case-insensitive world:
+//! `Assets\Logo.png` and `assets\Icon.png` share one directory on Windows, but
+//! would end up in two on Linux. Unless case is preserved, directories are
+//! folded onto the spelling seen first. Components are sanitized for the host,
+//! or escaped reversibly, see [`NameSanitization`]. `..` is rejected so a
+//! package cannot write outside the target directory.
+
+use std::{collections::{HashMap, HashSet}, io::Read, path::{Path, PathBuf}};
+
+use crate::{error::Error, utils, FileInfo};
+
+/// Names Windows reserves for devices, regardless of extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// What to do with files already present in the target directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Leave existing files untouched
+    Skip,
+    #[default]
+    Overwrite,
+    /// Fail on the first existing file
+    ErrorIfExists,
+    /// Keep existing files matching the blockmap hashes, re-extract missing or
+    /// corrupt ones. Resumes an interrupted extraction.
+    SkipIfHashMatches,
+}
+
+/// What to do with entries landing on the same file of a case-insensitive filesystem
+///
+/// Flat extraction always renames, file names of different directories clash there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Extract all of them, the last one wins
+    #[default]
+    Overwrite,
+    /// Fail before extracting anything
+    Error,
+    /// Append a counter to the file name of later entries
+    Rename,
+}
+
+/// What to do with encrypted files whose key is not loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingKeyPolicy {
+    /// Fail before extracting anything
+    #[default]
+    FailFast,
+    /// Extract everything else, the report lists the skipped files
+    SkipEncryptedWithoutKey,
+    /// Write the ciphertext (inflated, padded to sectors) to decrypt it later
+    ExtractCiphertext,
+}
+
+/// How names the filesystem cannot store are written
+///
+/// Windows refuses control characters, `<>:"|?*`, device names like `CON` and
+/// trailing dots and spaces. Other systems only refuse NUL, but a tree
+/// extracted there with such names cannot be copied to Windows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameSanitization {
+    /// Replace what the host refuses with `_`
+    #[default]
+    Host,
+    /// Replace what Windows refuses with `_` on every host
+    Portable,
+    /// Percent-encode what Windows refuses and `%` itself, [`unescape_path`] restores the package path
+    Escape,
+    /// [`NameSanitization::Escape`], plus every non-ASCII character as its UTF-8 bytes
+    EscapeNonAscii,
+}
+
+impl NameSanitization {
+    fn escapes(self) -> bool {
+        matches!(self, Self::Escape | Self::EscapeNonAscii)
+    }
+
+    /// Whether Windows rules apply
+    fn portable(self) -> bool {
+        cfg!(windows) || self != Self::Host
+    }
+}
+
+/// How files with the same content as one extracted before are written
+///
+/// Contents are told apart by file hash and size, only files whose first copy
+/// was verified against its file hash are deduplicated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Decrypt and write every file on its own
+    #[default]
+    Extract,
+    /// Hard link to the first copy, copy it where linking fails
+    HardLink,
+    /// Copy the first copy instead of decoding the file again
+    Copy,
+}
+
+/// What to do when the target volume has less free space than the extracted files need
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DiskSpacePolicy {
+    /// Fail before extracting anything
+    #[default]
+    Fail,
+    /// Log a warning and extract anyway
+    Warn,
+    /// Do not look at the free space
+    Ignore,
+}
+
+/// Set through the builder methods, e.g. `ExtractOptions::default().flat(true)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExtractOptions {
+    /// Strip directory components, all files land in the target directory
+    pub flat: bool,
+    /// Keep each path's own casing instead of folding directories differing only in case
+    pub preserve_case: bool,
+    pub overwrite: OverwritePolicy,
+    pub on_collision: CollisionPolicy,
+    pub on_missing_key: MissingKeyPolicy,
+    pub names: NameSanitization,
+    pub on_low_disk_space: DiskSpacePolicy,
+    pub duplicates: DuplicatePolicy,
+    /// Record entries failing to extract in the report and go on with the
+    /// others instead of stopping at the first one. A file failing midway is
+    /// left as far as it was written.
+    pub continue_on_error: bool,
+}
+
+impl ExtractOptions {
+    pub fn flat(mut self, flat: bool) -> Self {
+        self.flat = flat;
+        self
+    }
+
+    pub fn preserve_case(mut self, preserve_case: bool) -> Self {
+        self.preserve_case = preserve_case;
+        self
+    }
+
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn on_collision(mut self, on_collision: CollisionPolicy) -> Self {
+        self.on_collision = on_collision;
+        self
+    }
+
+    pub fn on_missing_key(mut self, on_missing_key: MissingKeyPolicy) -> Self {
+        self.on_missing_key = on_missing_key;
+        self
+    }
+
+    pub fn names(mut self, names: NameSanitization) -> Self {
+        self.names = names;
+        self
+    }
+
+    pub fn on_low_disk_space(mut self, on_low_disk_space: DiskSpacePolicy) -> Self {
+        self.on_low_disk_space = on_low_disk_space;
+        self
+    }
+
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    pub fn continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+}
+
+/// Entries extracting to the same file on a case-insensitive filesystem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    /// Package paths in the order they are extracted, the first one keeps its name
+    pub package_paths: Vec<String>,
+}
+
+impl std::fmt::Display for Collision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.package_paths.join(" <-> "))
+    }
+}
+
+/// Group `package_paths` by the local file they extract to, ignoring case
+///
+/// Paths that cannot be extracted at all are left out, extraction rejects them.
+pub fn find_collisions<'a, I: IntoIterator<Item = &'a str>>(package_paths: I, flat: bool, names: NameSanitization) -> Vec<Collision> {
+    let mut groups: Vec<Collision> = vec![];
+    let mut by_key = HashMap::<String, usize>::new();
+    for package_path in package_paths {
+        let Ok(mut components) = package_path.split(['\\', '/'])
+            .filter_map(|component| sanitize_component(component, package_path, names).transpose())
+            .collect::<Result<Vec<_>, _>>() else {
+            continue;
+        };
+        if flat {
+            components.drain(..components.len().saturating_sub(1));
+        }
+        if components.is_empty() {
+            continue;
+        }
+
+        let key = components.join("\\").to_lowercase();
+        match by_key.get(&key) {
+            Some(&idx) => groups[idx].package_paths.push(package_path.to_owned()),
+            None => {
+                by_key.insert(key, groups.len());
+                groups.push(Collision { package_paths: vec![package_path.to_owned()] });
+            },
+        }
+    }
+
+    groups.retain(|group| group.package_paths.len() > 1);
+    groups
+}
+
+/// Character Windows cannot store in a path component, separators are split off before
+fn is_invalid_char(c: char, portable: bool) -> bool {
+    match portable {
+        true => c.is_control() || ['<', '>', ':', '"', '|', '?', '*'].contains(&c),
+        false => c == '\0',
+    }
+}
+
+fn escape_char(c: char, out: &mut String) {
+    let mut utf8 = [0u8; 4];
+    for byte in c.encode_utf8(&mut utf8).bytes() {
+        out.push_str(&format!("%{byte:02X}"));
+    }
+}
+
+/// Sanitize a single path component for the host, `None` for components to drop
+fn sanitize_component(component: &str, path: &str, names: NameSanitization) -> Result<Option<String>, Error> {
+    match component {
+        "" | "." => return Ok(None),
+        ".." => return Err(Error::DataError(format!("Refusing path traversal in {path}"))),
+        _ => {},
+    }
+
+    let portable = names.portable();
+    let stem = component.split('.').next().unwrap_or_default().to_uppercase();
+    let reserved = portable && RESERVED_NAMES.contains(&stem.as_str());
+    // Trailing dots and spaces are silently stripped by Windows
+    let trailing = portable && component.ends_with(['.', ' ']);
+
+    if !names.escapes() {
+        let mut sanitized: String = component.chars()
+            .map(|c| if is_invalid_char(c, portable) { '_' } else { c })
+            .collect();
+        if reserved {
+            sanitized.insert(0, '_');
+        }
+        if trailing {
+            sanitized.push('_');
+        }
+        return Ok(Some(sanitized));
+    }
+
+    let last = component.chars().count() - 1;
+    let mut escaped = String::with_capacity(component.len());
+    for (idx, c) in component.chars().enumerate() {
+        let escape = is_invalid_char(c, portable)
+            || c == '%'
+            || (names == NameSanitization::EscapeNonAscii && !c.is_ascii())
+            || (idx == 0 && reserved)
+            || (idx == last && trailing);
+        match escape {
+            true => escape_char(c, &mut escaped),
+            false => escaped.push(c),
+        }
+    }
+
+    Ok(Some(escaped))
+}
+
+/// Package path of a path written with [`NameSanitization::Escape`] or [`NameSanitization::EscapeNonAscii`]
+///
+/// Takes `/` or `\` as separator and returns the path with `\`. Directories
+/// folded onto another spelling or files renamed on collision come back as
+/// written, the extraction report has their package paths.
+pub fn unescape_path(path: &str) -> String {
+    path.split(['\\', '/'])
+        .filter(|component| !component.is_empty())
+        .map(|component| {
+            let bytes = component.as_bytes();
+            let mut decoded = Vec::with_capacity(bytes.len());
+            let mut idx = 0;
+            while idx < bytes.len() {
+                let hex = bytes.get(idx + 1..idx + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match (bytes[idx], hex) {
+                    (b'%', Some(byte)) => {
+                        decoded.push(byte);
+                        idx += 3;
+                    },
+                    (byte, _) => {
+                        decoded.push(byte);
+                        idx += 1;
+                    },
+                }
+            }
+            String::from_utf8_lossy(&decoded).into_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+/// Whether an already extracted file matches the hashes of `fileinfo`
+///
+/// The file hash covers the plaintext, block hashes only do so for unencrypted
+/// files. Files without usable hashes never match.
+pub(crate) fn local_file_matches(path: &Path, fileinfo: &FileInfo, encrypted: bool) -> Result<bool, Error> {
+    Ok(check_local_file(path, fileinfo, encrypted)?.unwrap_or(false))
+}
+
+/// [`local_file_matches`], `None` if the size matches but no hash can tell
+pub(crate) fn check_local_file(path: &Path, fileinfo: &FileInfo, encrypted: bool) -> Result<Option<bool>, Error> {
+    if std::fs::metadata(path)?.len() != fileinfo.uncompressed_length {
+        return Ok(Some(false));
+    }
+
+    let mut file = std::fs::File::open(path)?;
+    if let Some(filehash) = &fileinfo.filehash {
+        let mut hasher = fileinfo.filehash_algorithm.hasher();
+        std::io::copy(&mut file, &mut hasher)?;
+        return Ok(Some(hasher.finalize() == *filehash));
+    }
+
+    match (&fileinfo.block_hashes, encrypted) {
+        (Some(block_hashes), false) => {
+            let mut buf = vec![0u8; utils::BLOCK_SIZE];
+            for block_hash in block_hashes {
+                let read = file.read(&mut buf)?;
+                if fileinfo.block_hash_algorithm.digest(&buf[..read]) != *block_hash {
+                    return Ok(Some(false));
+                }
+            }
+            Ok(Some(true))
+        },
+        _ => Ok(None),
+    }
+}
+
+/// Maps package paths to paths relative to the extraction directory
+///
+/// Keeps state across files, so one layout has to be used for a whole extraction.
+#[derive(Debug, Default)]
+pub struct OutputLayout {
+    options: ExtractOptions,
+    /// Lowercased directory path -> spelling seen first
+    directories: HashMap<String, PathBuf>,
+    /// Lowercased relative paths handed out
+    files: HashSet<String>,
+}
+
+impl OutputLayout {
+    pub fn new(options: ExtractOptions) -> Self {
+        Self { options, ..Default::default() }
+    }
+
+    /// Local relative path for a package path like `Assets\Logo.png`
+    pub fn map(&mut self, package_path: &str) -> Result<PathBuf, Error> {
+        let mut components = vec![];
+        for component in package_path.split(['\\', '/']) {
+            if let Some(component) = sanitize_component(component, package_path, self.options.names)? {
+                components.push(component);
+            }
+        }
+
+        let Some(filename) = components.pop() else {
+            return Err(Error::DataError(format!("No filename in path {package_path:?}")));
+        };
+
+        if self.options.flat {
+            return self.claim(PathBuf::from(filename), package_path);
+        }
+
+        let mut path = PathBuf::new();
+        for (idx, component) in components.iter().enumerate() {
+            match self.options.preserve_case {
+                true => path.push(component),
+                false => {
+                    let key = components[..=idx].join("\\").to_lowercase();
+                    path = self.directories
+                        .entry(key)
+                        .or_insert_with(|| path.join(component))
+                        .clone();
+                },
+            }
+        }
+
+        self.claim(path.join(filename), package_path)
+    }
+
+    /// Hand out `path`, unless another entry got it already
+    fn claim(&mut self, path: PathBuf, package_path: &str) -> Result<PathBuf, Error> {
+        if self.files.insert(path.to_string_lossy().to_lowercase()) {
+            return Ok(path);
+        }
+
+        match (self.options.flat, self.options.on_collision) {
+            (true, _) | (false, CollisionPolicy::Rename) => {},
+            (false, CollisionPolicy::Overwrite) => {
+                log::warn!("{package_path} collides with an entry extracted before, overwriting {}", path.display());
+                return Ok(path);
+            },
+            (false, CollisionPolicy::Error) => {
+                return Err(Error::DataError(format!("{package_path} collides with an entry extracted before at {}", path.display())));
+            },
+        }
+
+        let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let (stem, extension) = match filename.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem.to_owned(), format!(".{extension}")),
+            _ => (filename.clone(), String::new()),
+        };
+
+        let mut counter = 1;
+        loop {
+            let candidate = path.with_file_name(format!("{stem}_{counter}{extension}"));
+            if self.files.insert(candidate.to_string_lossy().to_lowercase()) {
+                log::warn!("{} already exists in output, writing {} for {package_path}", path.display(), candidate.display());
+                return Ok(candidate);
+            }
+            counter += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_case_folding() {
+        let mut layout = OutputLayout::new(ExtractOptions::default());
+        assert_eq!(layout.map("Assets\\Logo.png").unwrap(), PathBuf::from("Assets/Logo.png"));
+        assert_eq!(layout.map("assets\\Icon.png").unwrap(), PathBuf::from("Assets/Icon.png"));
+        assert_eq!(layout.map("ASSETS\\Sub\\a.png").unwrap(), PathBuf::from("Assets/Sub/a.png"));
+
+        let mut layout = OutputLayout::new(ExtractOptions { preserve_case: true, ..Default::default() });
+        assert_eq!(layout.map("Assets\\Logo.png").unwrap(), PathBuf::from("Assets/Logo.png"));
+        assert_eq!(layout.map("assets\\Icon.png").unwrap(), PathBuf::from("assets/Icon.png"));
+    }
+
+    #[test]
+    fn test_layout_flat() {
+        let mut layout = OutputLayout::new(ExtractOptions { flat: true, ..Default::default() });
+        assert_eq!(layout.map("Assets\\Logo.png").unwrap(), PathBuf::from("Logo.png"));
+        assert_eq!(layout.map("Other\\logo.png").unwrap(), PathBuf::from("logo_1.png"));
+        assert_eq!(layout.map("Third\\Logo.png").unwrap(), PathBuf::from("Logo_2.png"));
+        assert_eq!(layout.map("README").unwrap(), PathBuf::from("README"));
+    }
+
+    #[test]
+    fn test_layout_collisions() {
+        let paths = ["Assets\\Logo.png", "assets\\logo.png", "Assets/Logo.png", "Other\\Logo.png", "..\\Logo.png"];
+        let collisions = find_collisions(paths, false, NameSanitization::Host);
+        assert_eq!(collisions, [Collision { package_paths: vec!["Assets\\Logo.png".into(), "assets\\logo.png".into(), "Assets/Logo.png".into()] }]);
+        assert_eq!(find_collisions(paths, true, NameSanitization::Host)[0].package_paths.len(), 4);
+
+        // Replaced characters clash with the replacement, escaped ones do not
+        assert_eq!(find_collisions(["a<b.txt", "a_b.txt"], false, NameSanitization::Portable).len(), 1);
+        assert!(find_collisions(["a<b.txt", "a_b.txt"], false, NameSanitization::Escape).is_empty());
+
+        let mut layout = OutputLayout::new(ExtractOptions { on_collision: CollisionPolicy::Rename, ..Default::default() });
+        assert_eq!(layout.map("Assets\\Logo.png").unwrap(), PathBuf::from("Assets/Logo.png"));
+        assert_eq!(layout.map("assets\\logo.png").unwrap(), PathBuf::from("Assets/logo_1.png"));
+        assert_eq!(layout.map("Assets\\LOGO.png").unwrap(), PathBuf::from("Assets/LOGO_2.png"));
+
+        // Directories are kept apart, names still clash on case-insensitive filesystems
+        let mut layout = OutputLayout::new(ExtractOptions { preserve_case: true, on_collision: CollisionPolicy::Error, ..Default::default() });
+        assert_eq!(layout.map("Assets\\Logo.png").unwrap(), PathBuf::from("Assets/Logo.png"));
+        assert!(layout.map("assets\\Logo.png").is_err());
+
+        let mut layout = OutputLayout::new(ExtractOptions::default());
+        assert_eq!(layout.map("Assets\\Logo.png").unwrap(), layout.map("Assets\\Logo.png").unwrap());
+    }
+
+    #[test]
+    fn test_layout_sanitize() {
+        let mut layout = OutputLayout::new(ExtractOptions::default());
+        assert!(layout.map("..\\..\\etc\\passwd").is_err());
+        assert!(layout.map("\\.\\").is_err());
+        assert_eq!(layout.map("\\.\\Assets\\\\Logo.png").unwrap(), PathBuf::from("Assets/Logo.png"));
+
+        #[cfg(not(windows))]
+        assert_eq!(layout.map("Assets\\a\0b.png").unwrap(), PathBuf::from("Assets/a_b.png"));
+        #[cfg(windows)]
+        assert_eq!(layout.map("Assets\\CON.txt").unwrap(), PathBuf::from("Assets/_CON.txt"));
+
+        let mut layout = OutputLayout::new(ExtractOptions { names: NameSanitization::Portable, ..Default::default() });
+        assert_eq!(layout.map("Assets\\a<b>.png").unwrap(), PathBuf::from("Assets/a_b_.png"));
+        assert_eq!(layout.map("Assets\\con.txt").unwrap(), PathBuf::from("Assets/_con.txt"));
+        assert_eq!(layout.map("Dir. \\Ünïcode.txt").unwrap(), PathBuf::from("Dir. _/Ünïcode.txt"));
+    }
+
+    #[test]
+    fn test_layout_escape() {
+        let cases = [
+            (NameSanitization::Escape, "Data\\a<b>:c?.txt", "Data/a%3Cb%3E%3Ac%3F.txt"),
+            (NameSanitization::Escape, "Data\\CON.txt", "Data/%43ON.txt"),
+            (NameSanitization::Escape, "Dir.\\name ", "Dir%2E/name%20"),
+            (NameSanitization::Escape, "100%.txt", "100%25.txt"),
+            (NameSanitization::Escape, "Ünïcode\\日本.txt", "Ünïcode/日本.txt"),
+            (NameSanitization::EscapeNonAscii, "Ünïcode\\日本.txt", "%C3%9Cn%C3%AFcode/%E6%97%A5%E6%9C%AC.txt"),
+            (NameSanitization::EscapeNonAscii, "tab\tname", "tab%09name"),
+        ];
+        for (names, package_path, expected) in cases {
+            let mut layout = OutputLayout::new(ExtractOptions { names, preserve_case: true, ..Default::default() });
+            let path = layout.map(package_path).unwrap();
+            assert_eq!(path, PathBuf::from(expected), "{package_path}");
+            assert_eq!(unescape_path(&path.to_string_lossy()), package_path);
+        }
+
+        // Stray percent signs of names not escaped by us stay as they are
+        assert_eq!(unescape_path("a%zz/%4"), "a%zz\\%4");
+    }
+}
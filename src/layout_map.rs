@@ -0,0 +1,175 @@
+//! Byte ranges of the container structures
+//!
+//! Locates header, footer table, footprint files and every payload from the
+//! header and footer table alone, for hex editors and carving tools that need
+//! to know where a structure starts and ends. Bytes no region claims, such as
+//! alignment padding, are reported as gaps.
+
+use std::collections::HashMap;
+
+use crate::{footprint::{FootprintFile, SkippedFootprint}, EAppxFile};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RegionKind {
+    Header,
+    FooterTable,
+    /// AppxBlockMap.xml, signature or code integrity catalog
+    Footprint(FootprintFile),
+    /// A footer table entry, named if the blockmap lists it (packages of a bundle are not)
+    Payload { file_id: u64, name: Option<String> },
+}
+
+impl std::fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Header => write!(f, "Header"),
+            Self::FooterTable => write!(f, "Footer table"),
+            Self::Footprint(file) => write!(f, "{}", file.description()),
+            Self::Payload { file_id, name } => write!(f, "{file_id:#06x} {}", name.as_deref().unwrap_or("-")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Region {
+    pub kind: RegionKind,
+    /// Absolute offset in the container
+    pub offset: u64,
+    /// As stored, including sector padding of encrypted payloads
+    pub length: u64,
+}
+
+impl Region {
+    /// First offset past the region
+    pub fn end(&self) -> u64 {
+        self.offset.saturating_add(self.length)
+    }
+
+    pub fn contains(&self, offset: u64) -> bool {
+        (self.offset..self.end()).contains(&offset)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayoutMap {
+    pub file_len: u64,
+    /// Sorted by offset, empty regions are left out
+    pub regions: Vec<Region>,
+}
+
+impl LayoutMap {
+    /// Region the byte at `offset` belongs to, the first one if regions overlap
+    pub fn find(&self, offset: u64) -> Option<&Region> {
+        self.regions.iter().find(|region| region.contains(offset))
+    }
+
+    /// Ranges (offset, length) no region covers, up to the end of the file
+    pub fn gaps(&self) -> Vec<(u64, u64)> {
+        let mut gaps = vec![];
+        let mut position = 0;
+        for region in &self.regions {
+            if region.offset > position {
+                gaps.push((position, region.offset - position));
+            }
+            position = position.max(region.end());
+        }
+        if self.file_len > position {
+            gaps.push((position, self.file_len - position));
+        }
+        gaps
+    }
+}
+
+impl std::fmt::Display for LayoutMap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for region in &self.regions {
+            writeln!(f, "{:#010x}-{:#010x} {:>#10x} {}", region.offset, region.end(), region.length, region.kind)?;
+        }
+        for (offset, length) in self.gaps() {
+            writeln!(f, "{:#010x}-{:#010x} {:>#10x} (unused)", offset, offset + length, length)?;
+        }
+        Ok(())
+    }
+}
+
+impl EAppxFile {
+    /// Byte ranges of all structures, no payload is read
+    pub fn layout_map(&self) -> LayoutMap {
+        let names = self.blockmap.files.iter()
+            .map(|file| (file.id(), file.name.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let mut regions = vec![
+            Region { kind: RegionKind::Header, offset: 0, length: self.header.header_size as u64 },
+            Region { kind: RegionKind::FooterTable, offset: self.header.footer_offset, length: self.header.footer_length },
+        ];
+        regions.extend(self.footprint_entries().into_iter()
+            .filter(|entry| entry.file != FootprintFile::BlockMap)
+            .map(|entry| Region { kind: RegionKind::Footprint(entry.file), offset: entry.offset, length: entry.stored_length }));
+        regions.extend(self.footers.iter().map(|footer| {
+            let kind = match footer.file_id == self.header.block_map_file_id {
+                true => RegionKind::Footprint(FootprintFile::BlockMap),
+                false => RegionKind::Payload { file_id: footer.file_id, name: names.get(&footer.file_id).map(|name| name.to_string()) },
+            };
+            Region { kind, offset: footer.offset_to_file, length: self.stored_length(footer) }
+        }));
+
+        regions.retain(|region| region.length > 0);
+        regions.sort_by_key(|region| (region.offset, region.length));
+        LayoutMap { file_len: self.file_len, regions }
+    }
+
+    /// First offset past every structure header and footer table refer to
+    ///
+    /// Unlike [`EAppxFile::layout_map`], footprint files beyond the end of the
+    /// package count as well.
+    pub fn referenced_end(&self) -> u64 {
+        let regions = self.layout_map().regions.iter().map(Region::end).max().unwrap_or(0);
+        let skipped = self.skipped_footprints().iter().map(SkippedFootprint::end).max().unwrap_or(0);
+        regions.max(skipped)
+    }
+
+    /// Whether the package ends before a structure it refers to, e.g. a download cut short
+    pub fn is_truncated(&self) -> bool {
+        self.referenced_end() > self.file_len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegionKind;
+    use crate::{footprint::FootprintFile, EAppxFile};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_layout_map() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let map = eappx.layout_map();
+
+        assert_eq!(map.regions[0].kind, RegionKind::Header);
+        assert_eq!(map.find(0x10).unwrap().kind, RegionKind::Header);
+        assert_eq!(map.regions.len(), eappx.footers.len() + 1 + eappx.footprint_entries().len());
+        assert!(map.regions.windows(2).all(|pair| pair[0].end() <= pair[1].offset), "{map}");
+        assert!(map.regions.iter().all(|region| region.end() <= EMSIX.len() as u64));
+
+        let table = map.find(eappx.header.footer_offset).unwrap();
+        assert_eq!((table.kind.clone(), table.length), (RegionKind::FooterTable, eappx.header.footer_length));
+
+        let blockmap = map.regions.iter().find(|region| region.kind == RegionKind::Footprint(FootprintFile::BlockMap)).unwrap();
+        assert_eq!(blockmap.offset, eappx.find_footer_for_file(eappx.header.block_map_file_id).unwrap().offset_to_file);
+
+        let dll = map.regions.iter()
+            .find(|region| matches!(&region.kind, RegionKind::Payload { name: Some(name), .. } if name == "TestApp.dll"))
+            .unwrap();
+        assert_eq!(dll.length % 0x200, 0);
+
+        // Whatever is not a region is padding
+        let covered = map.regions.iter().map(|region| region.length).sum::<u64>();
+        let gaps = map.gaps().iter().map(|(_, length)| length).sum::<u64>();
+        assert_eq!(covered + gaps, EMSIX.len() as u64);
+    }
+}
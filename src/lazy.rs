@@ -0,0 +1,174 @@
+//! Package opened header first, the rest parsed on first use
+//!
+//! The header alone carries the package full name, key ids and where the
+//! footprint files are. A [`LazyPackage`] reads just that when opened and
+//! parses the footer table and the blockmap the first time they are asked
+//! for, so looking at the identity of a package with a huge file count stays
+//! instant. [`LazyPackage::into_package`] turns it into a full [`EAppxFile`]
+//! without parsing anything twice.
+//!
+//! ```
+//! # #[cfg(feature = "package")] {
+//! use eappx::lazy::LazyPackage;
+//!
+//! let data = eappx::sample::build_sample_package()?;
+//! let package = LazyPackage::open(&data)?;
+//! println!("{}", package.header().package_full_name());
+//! println!("{} files", package.blockmap()?.files.len());
+//!
+//! let eappx = package.into_package()?;
+//! assert!(eappx.verify(&data).is_valid());
+//! # }
+//! # Ok::<(), eappx::error::Error>(())
+//! ```
+
+use std::sync::OnceLock;
+
+use crate::{
+    blockmap::AppxBlockMap,
+    container::{EAppxFooter, EAppxHeader, FileInfo},
+    error::Error,
+    limits::ResourceLimits,
+    source::PackageSource,
+    EAppxFile,
+};
+
+/// Footer table of a package, its size and where the blockmap is stored
+#[derive(Debug)]
+struct Layout {
+    footers: Vec<EAppxFooter>,
+    file_len: u64,
+    blockmap: FileInfo,
+}
+
+/// Package whose footers and blockmap are read on first access
+#[derive(Debug)]
+pub struct LazyPackage<S: PackageSource> {
+    source: S,
+    header: EAppxHeader,
+    limits: ResourceLimits,
+    /// Filled by [`LazyPackage::footers`]
+    layout: OnceLock<Layout>,
+    /// Filled by [`LazyPackage::blockmap`]
+    blockmap: OnceLock<AppxBlockMap>,
+}
+
+impl<S: PackageSource> LazyPackage<S> {
+    /// Read the header of the package in `source`, nothing else
+    pub fn open(source: S) -> Result<Self, Error> {
+        Self::open_with_limits(source, ResourceLimits::default())
+    }
+
+    pub fn open_with_limits(source: S, limits: ResourceLimits) -> Result<Self, Error> {
+        let header = EAppxFile::peek_header(&source)?;
+        Ok(Self::from_header(source, header, limits))
+    }
+
+    /// Continue from a header read with [`EAppxFile::peek_header`]
+    pub fn from_header(source: S, header: EAppxHeader, limits: ResourceLimits) -> Self {
+        Self { source, header, limits, layout: OnceLock::new(), blockmap: OnceLock::new() }
+    }
+
+    pub fn header(&self) -> &EAppxHeader {
+        &self.header
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// Footer table, read and checked against the package size once
+    pub fn footers(&self) -> Result<&[EAppxFooter], Error> {
+        Ok(&self.layout()?.footers)
+    }
+
+    /// Size of the package, known once the footer table was read
+    pub fn file_len(&self) -> Result<u64, Error> {
+        Ok(self.layout()?.file_len)
+    }
+
+    /// Blockmap, read and parsed once, along with the footer table locating it
+    pub fn blockmap(&self) -> Result<&AppxBlockMap, Error> {
+        if let Some(blockmap) = self.blockmap.get() {
+            return Ok(blockmap);
+        }
+        let blockmap = self.read_blockmap(self.layout()?)?;
+        Ok(self.blockmap.get_or_init(|| blockmap))
+    }
+
+    /// The full package, reading whatever was not read yet
+    pub fn into_package(mut self) -> Result<EAppxFile, Error> {
+        let layout = match self.layout.take() {
+            Some(layout) => layout,
+            None => self.read_layout()?,
+        };
+        let blockmap = match self.blockmap.take() {
+            Some(blockmap) => blockmap,
+            None => self.read_blockmap(&layout)?,
+        };
+
+        let mut eappx = EAppxFile::new(self.header, layout.file_len, layout.footers, blockmap, self.limits);
+        eappx.detect_encrypted_payloads(&self.source)?;
+        Ok(eappx)
+    }
+
+    fn layout(&self) -> Result<&Layout, Error> {
+        if let Some(layout) = self.layout.get() {
+            return Ok(layout);
+        }
+        let layout = self.read_layout()?;
+        Ok(self.layout.get_or_init(|| layout))
+    }
+
+    fn read_layout(&self) -> Result<Layout, Error> {
+        let (footers, file_len, blockmap) = EAppxFile::read_layout(&self.source, &self.header, &self.limits)?;
+        Ok(Layout { footers, file_len, blockmap })
+    }
+
+    fn read_blockmap(&self, layout: &Layout) -> Result<AppxBlockMap, Error> {
+        let buf = EAppxFile::read_file_to_buf(&self.source, layout.blockmap.clone(), self.header.is_bundle(), self.limits.max_in_memory_size)?;
+        let blockmap = AppxBlockMap::from_xml(&buf)?;
+        self.limits.check_file_count("Blockmap file count", blockmap.files.len() as u64)?;
+        Ok(blockmap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LazyPackage;
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    #[test]
+    fn test_header_only() {
+        // Nothing behind the header, only reading footers or blockmap notices
+        let header_size = EAppxFile::peek_header(EMSIX).unwrap().header_size as usize;
+        let package = LazyPackage::open(&EMSIX[..header_size]).unwrap();
+        assert_eq!(package.header().package_full_name(), "8b18b0ca-7bac-4263-8be8-9a7a1292c90d_0.0.0.0_x64__nh20k94c8ngfj");
+        assert!(!package.header().key_ids.is_empty());
+        assert!(package.footers().is_err());
+        assert!(package.blockmap().is_err());
+        assert!(package.into_package().is_err());
+    }
+
+    #[test]
+    fn test_into_package() {
+        for data in [EMSIX, EMSIXBUNDLE] {
+            let expected = EAppxFile::from_source(data).unwrap();
+
+            let package = LazyPackage::open(data).unwrap();
+            assert_eq!(package.footers().unwrap(), expected.footers);
+            assert_eq!(package.file_len().unwrap(), expected.file_len);
+            let eappx = package.into_package().unwrap();
+            assert_eq!(eappx.blockmap, expected.blockmap);
+            assert!(eappx.verify(data).is_valid());
+
+            // Straight from the header, footers and blockmap read in one go
+            let eappx = LazyPackage::open(data).unwrap().into_package().unwrap();
+            assert_eq!(eappx.footers, expected.footers);
+            assert_eq!(eappx.blockmap, expected.blockmap);
+        }
+    }
+}
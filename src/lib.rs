@@ -1,261 +1,191 @@
-use std::{collections::HashMap, io::{Cursor, Read}, path::Path};
-use std::convert::From;
-use binrw::{binrw, BinRead};
-use blockmap::AppxBlockMap;
-use crypto::{create_cipher, get_tweak_for_file, AesXtsReader, CryptoFileContext};
-use keys::{KeyCollection, KeyId};
-use manifest::AppxManifest;
+//! Reader and writer for encrypted appx/msix containers (EAppx/EMsix)
+//!
+//! ```
+//! # #[cfg(feature = "package")] {
+//! use eappx::{sample, EAppxFile};
+//!
+//! // Any `PackageSource` works, e.g. a `File` or bytes in memory
+//! let package = sample::build_sample_package()?;
+//!
+//! let mut eappx = EAppxFile::from_source(&package)?;
+//! eappx.load_keys(&sample::sample_keys())?;
+//!
+//! for file in &eappx.blockmap.files {
+//!     println!("{} ({} bytes, encrypted: {})", file.name, file.size, file.is_encrypted());
+//! }
+//!
+//! assert!(eappx.verify(&package).is_valid());
+//! # }
+//! # Ok::<(), eappx::error::Error>(())
+//! ```
+//!
+//! See `examples/` for listing, extracting and verifying packages.
+//!
+//! Everything beyond the container structures sits behind the default
+//! `package` feature. Without it [`container`], [`keys`] and [`crypto`]
+//! still parse headers and footers, handle key ids and decrypt blocks;
+//! `xml`, `compression` and `signature` add back single pieces.
+//!
+//! [`prelude`] imports the commonly used types in one go.
+
+#![allow(clippy::unnecessary_fallible_conversions)] // binrw-generated `count` conversions
+
+#[cfg(feature = "package")]
+use std::{collections::{HashMap, HashSet}, io::{Cursor, Read}, path::Path};
+#[cfg(feature = "package")]
+use std::{cell::Cell, time::{Duration, Instant}};
+#[cfg(feature = "package")]
 use sha2::{Digest, Sha256};
+#[cfg(feature = "package")]
 use xmlserde::xml_deserialize_from_reader;
 
-use crate::{error::Error, bundle_manifest::AppxBundleManifest};
-
+#[cfg(feature = "package")]
+use crate::{
+    blockmap::{AppxBlockMap, Hash as _},
+    bundle_manifest::{AppxBundleManifest, BundleFilter},
+    crypto::{create_cipher, get_tweak_for_file, AesXtsReader, CryptoFileContext},
+    error::{EntryContext, Error, Operation},
+    extraction_report::{ExtractedFile, ExtractionFailure, ExtractionReport, ExtractionStatus, HashingWriter},
+    footprint::FootprintFile,
+    keys::{KeyCollection, KeyId},
+    layout::{Collision, CollisionPolicy, DiskSpacePolicy, DuplicatePolicy, ExtractOptions, MissingKeyPolicy, OutputLayout, OverwritePolicy},
+    limits::ResourceLimits,
+    manifest::AppxManifest,
+    pipeline::{Block, BlockPipeline, BlockVerifier},
+    source::{PackageSource, SourceReader, StreamSource},
+    timings::{Stage, Throughput, TimedRead, Timings},
+    verify::{BundlePackageReport, BundleVerificationReport, FileReport, FileStatus, IntegrityPolicy, PublisherMismatch, SizeMismatch, VerificationReport},
+};
+
+pub use container::{ContainerParams, EAppxFooter, EAppxHeader, EAppxMagic, FileInfo, HeaderVersion};
+
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub mod archive;
+#[cfg(feature = "xml")]
+pub mod audit;
+#[cfg(feature = "package")]
+pub mod block_cache;
+#[cfg(feature = "package")]
+pub mod block_store;
+#[cfg(feature = "xml")]
 pub mod blockmap;
+#[cfg(feature = "package")]
+pub mod blockmap_index;
+#[cfg(feature = "package")]
+pub mod builder;
+#[cfg(feature = "xml")]
 pub mod bundle_manifest;
+#[cfg(feature = "package")]
+pub mod checkpoint;
+#[cfg(feature = "package")]
+pub mod code_integrity;
+#[cfg(feature = "package")]
+pub mod compare;
+#[cfg(feature = "package")]
+pub mod compression;
+pub mod container;
+#[cfg(feature = "xml")]
+pub mod content_types;
 pub mod crypto;
+#[cfg(feature = "signature")]
+pub(crate) mod der;
+#[cfg(feature = "package")]
+pub mod delta;
+#[cfg(feature = "package")]
+pub mod diff;
+pub mod digest;
+#[cfg(feature = "package")]
+pub mod download_plan;
+#[cfg(feature = "package")]
+pub mod edit;
+#[cfg(feature = "package")]
+pub mod entry_list;
+#[cfg(feature = "package")]
+pub mod entry_reader;
 pub mod error;
+pub mod extension;
+#[cfg(feature = "package")]
+pub mod extraction_report;
+#[cfg(all(feature = "package", any(test, feature = "fixtures")))]
+pub mod fixtures;
+#[cfg(feature = "package")]
+pub mod footprint;
+pub mod format_spec;
+pub mod identity;
+#[cfg(feature = "package")]
+pub mod incremental;
+#[cfg(feature = "package")]
+pub mod install_layout;
 pub mod keys;
+#[cfg(feature = "package")]
+pub mod layout;
+#[cfg(feature = "package")]
+pub mod layout_map;
+#[cfg(feature = "package")]
+pub mod lazy;
+pub mod limits;
+#[cfg(feature = "xml")]
 pub mod manifest;
+#[cfg(feature = "package")]
+pub mod memory;
+#[cfg(feature = "cache")]
+pub mod metadata_cache;
+#[cfg(feature = "package")]
+pub mod msix;
+#[cfg(feature = "package")]
+pub mod package_info;
+pub mod prelude;
+#[cfg(feature = "package")]
+mod pipeline;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "package")]
+pub mod recovery;
+#[cfg(feature = "package")]
+pub mod rekey;
+#[cfg(feature = "package")]
+pub mod resolve;
+#[cfg(feature = "package")]
+pub mod sample;
+#[cfg(feature = "package")]
+pub mod shared;
+#[cfg(feature = "signature")]
+pub mod signature;
+#[cfg(feature = "signing")]
+pub mod signing;
+#[cfg(feature = "package")]
+pub mod sink;
+#[cfg(feature = "package")]
+pub mod skeleton;
+pub mod source;
+#[cfg(feature = "package")]
+pub mod stats;
+#[cfg(any(test, feature = "testvectors"))]
+pub mod testvectors;
+#[cfg(feature = "package")]
+pub mod timings;
+#[cfg(feature = "package")]
+pub mod untrusted;
+#[cfg(feature = "package")]
+pub mod upload;
 pub mod utils;
-
-
-#[binrw]
-#[derive(Debug, PartialEq, Eq)]
-pub enum EAppxMagic {
-    /// Single
-    #[brw(magic(0x48505845u32))]
-    EXPH,
-    /// ?
-    #[brw(magic(0x48535845u32))]
-    EXSH,
-    /// Bundle
-    #[brw(magic(0x48425845u32))]
-    EXBH,
-}
-
-#[derive(Debug, PartialEq, Eq, Clone)]
-pub struct FileInfo {
-    pub key_id_index: u16,
-    pub compression_type: u16,
-    pub offset_to_file: u64,
-    pub uncompressed_length: u64,
-    pub compressed_length: u64,
-    pub filehash: Option<Vec<u8>>,
-    pub block_hashes: Option<Vec<Vec<u8>>>,
-}
-
-impl From<&EAppxFooter> for FileInfo {
-    fn from(value: &EAppxFooter) -> Self {
-        FileInfo {
-            key_id_index: value.key_id_index,
-            compression_type: value.compression_type,
-            offset_to_file: value.offset_to_file,
-            uncompressed_length: value.uncompressed_length,
-            compressed_length: value.compressed_length,
-            filehash: None,
-            block_hashes: None
-        }
-    }
-}
-
-#[binrw]
-#[brw(little)]
-#[derive(Debug, PartialEq, Eq)]
-pub struct EAppxHeader {
-    pub magic: EAppxMagic,
-    pub header_size: u16,
-    pub version: u64,
-    pub footer_offset: u64,
-    pub footer_length: u64,
-    pub file_count: u64,
-    pub signature_offset: u64,
-    pub signature_compression_type: u16,
-    pub signature_uncompressed_length: u32,
-    pub signature_length: u32,
-    pub code_integrity_offset: u64,
-    pub code_integrity_compression_type: u16,
-    pub code_integrity_uncompressed_length: u32,
-    pub code_integrity_length: u32,
-    pub block_map_file_id: u64,
-    pub key_length: u32,
-    #[bw(try_calc(u16::try_from(key_ids.len())))]
-    pub(crate) key_id_count: u16,
-    #[br(count = key_id_count)]
-    pub key_ids: Vec<KeyId>,
-    #[bw(try_calc(u16::try_from(package_full_name.len())))]
-    pub(crate) _package_full_name_str_len: u16,
-    #[bw(try_calc(u16::try_from(package_full_name.len() * 2)))]
-    pub(crate) package_full_name_byte_len: u16,
-    #[br(count = package_full_name_byte_len / 2)]
-    pub(crate) package_full_name: Vec<u16>,
-    #[bw(try_calc(u16::try_from(crypto_algo.len() * 2)))]
-    pub(crate) crypto_algo_length: u16,
-    #[br(count = crypto_algo_length / 2)]
-    pub(crate) crypto_algo: Vec<u16>,
-    pub diffusion_support_enabled: u16,
-    pub(crate) block_map_hash_algo_length: u16,
-    #[br(count = block_map_hash_algo_length / 2)]
-    pub(crate) block_map_hash_algo: Vec<u16>,
-    #[bw(try_calc(u16::try_from(block_map_hash.len())))]
-    pub(crate) block_map_hash_length: u16,
-    #[br(count = block_map_hash_length)]
-    pub block_map_hash: Vec<u8>,
-}
-
-impl EAppxHeader {
-    pub fn is_bundle(&self) -> bool {
-        self.magic == EAppxMagic::EXBH
-    }
-
-    pub fn package_full_name(&self) -> String {
-        String::from_utf16(&self.package_full_name).unwrap()
-    }
-
-    pub fn crypto_algo(&self) -> String {
-        String::from_utf16(&self.crypto_algo).unwrap()
-    }
-
-    pub fn block_map_hash_algo(&self) -> String {
-        String::from_utf16(&self.block_map_hash_algo).unwrap()
-    }
-
-    pub fn has_footer(&self) -> bool {
-        self.footer_offset > 0 && self.footer_length > 0
-    }
-
-    pub fn is_code_integrity_protected(&self) -> bool {
-        self.code_integrity_offset > 0 && self.code_integrity_length > 0
-    }
-
-    pub fn is_signed(&self) -> bool {
-        self.signature_offset > 0 && self.signature_length > 0
-    }
-
-    pub fn appx_signature_fileinfo(&self) -> Option<FileInfo> {
-        if !self.is_signed() {
-            return None;
-        }
-
-        Some(FileInfo {
-            key_id_index: 0xFFFF,
-            compression_type: self.signature_compression_type,
-            offset_to_file: self.signature_offset,
-            uncompressed_length: self.signature_uncompressed_length as u64,
-            compressed_length: self.signature_length as u64,
-            filehash: None,
-            block_hashes: None,
-        })
-    }
-
-    pub fn code_integrity_fileinfo(&self) -> Option<FileInfo> {
-        if !self.is_code_integrity_protected() {
-            return None;
-        }
-
-        Some(FileInfo {
-            key_id_index: 0xFFFF,
-            compression_type: self.code_integrity_compression_type,
-            offset_to_file: self.code_integrity_offset,
-            uncompressed_length: self.code_integrity_uncompressed_length as u64,
-            compressed_length: self.code_integrity_length as u64,
-            filehash: None,
-            block_hashes: None,
-        })
-    }
-
-    pub fn footer_count(&self) -> usize {
-        self.footer_length as usize / std::mem::size_of::<EAppxFooter>()
-    }
-
-    pub fn app_name(&self) -> String {
-        self.package_full_name()
-            .split("_")
-            .next()
-            .unwrap()
-            .to_owned()
-    }
-
-    pub fn publisher_id(&self) -> String {
-        self.package_full_name()
-            .split("_")
-            .last()
-            .unwrap()
-            .to_owned()
-    }
-}
-
-impl std::fmt::Display for EAppxHeader {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "EAppxHeader {{ ")?;
-        writeln!(f, "  Magic: {:?}", self.magic)?;
-        writeln!(f, "  HeaderSize: {:#02x}", self.header_size)?;
-        writeln!(f, "  Version: {:#08x}", self.version)?;
-        writeln!(f, "  FooterOffset: {:#08x}", self.footer_offset)?;
-        writeln!(f, "  FooterLength: {:#08x}", self.footer_length)?;
-        writeln!(f, "  FileCount: {:#08x}", self.file_count)?;
-        writeln!(f, "  SignatureOffset: {:#08x}", self.signature_offset)?;
-        writeln!(f, "  SignatureCompressionType: {:#02x}", self.signature_compression_type)?;
-        writeln!(f, "  SignatureUncompressedLength: {:#04x}", self.signature_uncompressed_length)?;
-        writeln!(f, "  SignatureLength: {:#04x}", self.signature_length)?;
-        writeln!(f, "  CodeIntegrityOffset: {:#08x}", self.code_integrity_offset)?;
-        writeln!(f, "  CodeIntegrityCompressionType: {:#02x}", self.code_integrity_compression_type)?;
-        writeln!(f, "  CodeIntegrityUncompressedLength: {:#04x}", self.code_integrity_uncompressed_length)?;
-        writeln!(f, "  CodeIntegrityLength: {:#04x}", self.code_integrity_length)?;
-        writeln!(f, "  BlockMapFileId: {:#08x}", self.block_map_file_id)?;
-        writeln!(f, "  KeyLength: {:#04x}", self.key_length)?;
-        writeln!(f, "  KeyIds: {}", self.key_ids.len())?;
-        for key in &self.key_ids {
-            writeln!(f, "  - {}", key)?;
-        }
-        writeln!(f, "  PackageFullName: {} (name={}, publisherId={})", self.package_full_name(), self.app_name(), self.publisher_id())?;
-        writeln!(f, "  CryptoAlgo: {}", self.crypto_algo())?;
-        writeln!(f, "  DiffusionSupportEnabled: {:#02x}", self.diffusion_support_enabled)?;
-        writeln!(f, "  BlockMapHashAlgoLength: {:#02x}", self.block_map_hash_algo_length)?;
-        writeln!(f, "  BlockMapHashAlgo: {}", self.block_map_hash_algo())?;
-        writeln!(f, "  BlockMapHash: {}", hex::encode(&self.block_map_hash))?;
-        writeln!(f, "}}")?;
-
-        Ok(())
-    }
-}
-
-#[binrw]
-#[brw(little)]
-#[derive(Debug, PartialEq, Eq)]
-pub struct EAppxFooter {
-    pub magic: u16, // Assuming "EF" is represented as 0x4546
-    pub footer_size: u16,
-    pub key_id_index: u16,
-    pub compression_type: u16,
-    pub file_id: u64,
-    pub offset_to_file: u64,
-    pub uncompressed_length: u64,
-    pub compressed_length: u64,
-}
-
-impl std::fmt::Display for EAppxFooter {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "EAppxFooter {{ ")?;
-        write!(f, "Magic: {:?}, ", self.magic)?;
-        write!(f, "FooterSize: {:#02x}, ", self.footer_size)?;
-        write!(f, "KeyIDIndex: {:#06x}, ", self.key_id_index)?;
-        write!(f, "CompressionType: {:#02x}, ", self.compression_type)?;
-        write!(f, "FileId: {:#08x}, ", self.file_id)?;
-        write!(f, "OffsetToFile: {:#08x}, ", self.offset_to_file)?;
-        write!(f, "UncompressedLength: {:#08x}, ", self.uncompressed_length)?;
-        write!(f, "CompressedLength: {:#08x}", self.compressed_length)?;
-        write!(f, " }}")?;
-
-        Ok(())
-    }
-}
-
-#[derive(Debug)]
+#[cfg(feature = "package")]
+pub mod validation;
+#[cfg(feature = "package")]
+pub mod verify;
+#[cfg(feature = "package")]
+pub mod writer;
+#[cfg(feature = "xml")]
+mod xml_tree;
+
+#[cfg(feature = "package")]
+#[derive(Debug, Clone)]
 pub enum Manifest {
     Manifest(AppxManifest),
     BundleManifest(AppxBundleManifest),
 }
 
+#[cfg(feature = "package")]
 impl Manifest {
     pub fn get_name(&self) -> String {
         match self {
@@ -272,6 +202,11 @@ impl Manifest {
     }
 }
 
+/// Leading bytes of packages stored in the clear inside a bundle
+#[cfg(feature = "package")]
+const PLAINTEXT_PACKAGE_MAGICS: [[u8; 4]; 4] = [*b"EXPH", *b"EXSH", *b"EXBH", *b"PK\x03\x04"];
+
+#[cfg(feature = "package")]
 #[derive(Debug)]
 pub struct EAppxFile {
     pub header: EAppxHeader,
@@ -279,12 +214,38 @@ pub struct EAppxFile {
     pub footers: Vec<EAppxFooter>,
     pub blockmap: AppxBlockMap,
     pub keys: HashMap<KeyId, Vec<u8>>,
-    pub do_checksum_check: bool,
+    /// Hashes checked when reading files, by extraction, conversion and [`EAppxFile::entry_reader`]
+    pub integrity: IntegrityPolicy,
+    pub limits: ResourceLimits,
+    /// Sector and block size, those of the header version unless set otherwise
+    pub params: ContainerParams,
+    /// Attach the time spent per stage to extraction and verification reports
+    pub collect_timings: bool,
+    pub extract_options: ExtractOptions,
+    /// Inner packages of a bundle to extract, conversions always keep all of them
+    pub bundle_filter: BundleFilter,
+    /// Plaintext of recently read blocks, used by [`EAppxFile::entry_reader`]
+    pub block_cache: Option<std::sync::Arc<block_cache::BlockCache>>,
+    /// Filled by [`EAppxFile::manifest`]
+    manifest: std::sync::OnceLock<Manifest>,
+    /// File ids of bundle payloads stored encrypted, see [`EAppxFile::is_footer_encrypted`]
+    encrypted_bundle_payloads: HashSet<u64>,
+    /// Identity to sign zip based output of conversions with
+    #[cfg(feature = "signing")]
+    pub signer: Option<signing::SigningIdentity>,
+}
+
+#[cfg(feature = "package")]
+impl Drop for EAppxFile {
+    fn drop(&mut self) {
+        self.keys.values_mut().for_each(|key| keys::wipe(key));
+    }
 }
 
+#[cfg(feature = "package")]
 impl EAppxFile {
     fn create_reader<'a, R: std::io::Read + 'a>(
-        stream: &'a mut R,
+        stream: R,
         encrypted: bool,
         compressed: bool,
         crypto: Option<CryptoFileContext>,
@@ -296,397 +257,1554 @@ impl EAppxFile {
         }
 
         if encrypted {
-            if let Some(crypto) = crypto {
-                reader = Box::new(AesXtsReader::new(reader, crypto));
-            } else {
-                panic!("File is encrypted but no CryptoContext was passed. Were the apprioriate keys loaded?");
-            }
+            let crypto = crypto
+                .ok_or(Error::DataError("File is encrypted but no key was loaded".into()))?;
+            reader = Box::new(AesXtsReader::new(reader, crypto));
         }
 
         Ok(reader)
     }
 
-    pub fn read_file<R: std::io::Read + std::io::Seek, W: std::io::Write, I: Into<FileInfo>>(
-        stream: &mut R,
+    /// Inflated ciphertext of an encrypted entry, sector padded as it was encrypted
+    pub(crate) fn read_ciphertext<P: PackageSource + ?Sized, W: std::io::Write>(
+        source: &P,
+        writer: &mut W,
+        fileinfo: &FileInfo,
+        name: &str,
+    ) -> Result<u64, Error> {
+        let context = |operation| EntryContext {
+            operation,
+            name: Some(name.to_owned()),
+            file_id: fileinfo.file_id,
+            offset: fileinfo.offset_to_file,
+            position: 0,
+        };
+        let reader = Self::create_reader(source.reader_at(fileinfo.offset_to_file), false, fileinfo.compression_type == 0x1, None)
+            .map_err(|e| context(Operation::Read).wrap(e))?;
+        let length = fileinfo.params.align_to_sector(fileinfo.uncompressed_length as usize) as u64;
+        let copied = std::io::copy(&mut reader.take(length), writer)
+            .map_err(|e| context(Operation::Read).wrap(e.into()))?;
+        if copied != length {
+            return Err(context(Operation::Read).wrap(Error::DataError(format!("Ciphertext ends after {copied:#x} of {length:#x} bytes"))));
+        }
+        Ok(copied)
+    }
+
+    pub fn read_file<P: PackageSource + ?Sized, W: std::io::Write, I: Into<FileInfo>>(
+        source: &P,
         writer: &mut W,
         fileinfo: I,
         from_bundle: bool,
         crypto: Option<CryptoFileContext>,
-        do_checksum_checks: bool,
+        integrity: IntegrityPolicy,
     ) -> Result<(), Error> {
-        let fileinfo: FileInfo = fileinfo.into();
-        // Files itself in bundles are not encrypted
-        let is_encrypted = fileinfo.key_id_index != 0xFFFF && !from_bundle;
-        let is_compressed = fileinfo.compression_type == 0x1;
+        Self::read_entry(source, writer, fileinfo.into(), None, from_bundle, crypto, integrity)?;
+        Ok(())
+    }
 
-        stream.seek(std::io::SeekFrom::Start(fileinfo.offset_to_file))?;
-
-        let mut reader = Self::create_reader(
-            stream,
-            is_encrypted,
-            is_compressed,
-            crypto
-        )?;
-
-        let mut pos = 0;
-        let mut block = 0;
-        let chunk_size = utils::BLOCK_SIZE;
-        let mut buf = vec![0u8; chunk_size];
-        let mut hasher = Sha256::new();
-
-        loop {
-            let read_amount = std::cmp::min(chunk_size, (fileinfo.uncompressed_length as usize) - pos);
-            reader.read_exact(&mut buf[..read_amount])?;
-
-            if !is_encrypted && do_checksum_checks {
-                // Hashblocks are calculated over the uncompressed, encrypted data
-                if let Some(block_hash) = fileinfo.block_hashes.as_ref().and_then(|sq| sq.get(block)) {
-                    println!("Verifying block hash, block size: {:#X} (total: {:#X}", read_amount, fileinfo.uncompressed_length);
-                    assert_eq!(hex::encode(Sha256::digest(&buf[..read_amount])), hex::encode(block_hash), "Invalid block hash");
-                }
-            }
+    /// Whether the payload `fileinfo` describes is stored encrypted
+    ///
+    /// A key index marks it encrypted. Bundles mostly keep the key index of
+    /// inner packages stored in the clear, with `from_bundle` a payload only
+    /// counts as encrypted if it does not start with a package header.
+    pub fn is_stored_encrypted<P: PackageSource + ?Sized>(source: &P, fileinfo: &FileInfo, from_bundle: bool) -> Result<bool, Error> {
+        if fileinfo.key_id_index == 0xFFFF {
+            return Ok(false);
+        }
+        if !from_bundle {
+            return Ok(true);
+        }
 
-            let _ = writer.write(&buf[..read_amount])?;
-            if do_checksum_checks {
-                hasher.update(&buf[..read_amount]);
-            }
+        let mut reader = Self::create_reader(source.reader_at(fileinfo.offset_to_file), false, fileinfo.compression_type == 0x1, None)?;
+        let mut magic = [0u8; 4];
+        let read = utils::read_up_to(&mut reader.by_ref().take(fileinfo.uncompressed_length), &mut magic)?;
+        Ok(read == magic.len() && !PLAINTEXT_PACKAGE_MAGICS.contains(&magic))
+    }
+
+    /// [`Self::read_file`], errors carry `name` and where reading failed
+    fn read_entry<P: PackageSource + ?Sized, W: std::io::Write>(
+        source: &P,
+        writer: &mut W,
+        fileinfo: FileInfo,
+        name: Option<&str>,
+        from_bundle: bool,
+        crypto: Option<CryptoFileContext>,
+        integrity: IntegrityPolicy,
+    ) -> Result<Timings, Error> {
+        let is_compressed = fileinfo.compression_type == 0x1;
+        let context = |operation, position| EntryContext {
+            operation,
+            name: name.map(str::to_owned),
+            file_id: fileinfo.file_id,
+            offset: fileinfo.offset_to_file,
+            position,
+        };
+        let is_encrypted = Self::is_stored_encrypted(source, &fileinfo, from_bundle)
+            .map_err(|e| context(Operation::Read, 0).wrap(e))?;
+
+        if is_encrypted && crypto.is_none() {
+            return Err(context(Operation::Decrypt, 0).wrap(Error::DataError("File is encrypted but no key was loaded".into())));
+        }
 
-            pos += read_amount;
+        // Inflated here, the pipeline checks, decrypts and hashes the blocks
+        let stored = Cell::new(Throughput::default());
+        let mut timed = TimedRead { inner: source.reader_at(fileinfo.offset_to_file), spent: &stored };
+        let mut reader = Self::create_reader(&mut timed, false, is_compressed, None)
+            .map_err(|e| context(Operation::Read, 0).wrap(e))?;
+        let mut read_time = Duration::ZERO;
+
+        let params = fileinfo.params;
+        let pipeline = BlockPipeline {
+            block_size: params.block_size(),
+            crypto: crypto.as_ref().filter(|_| is_encrypted),
+            block_hashes: fileinfo.block_hashes.as_deref().filter(|_| integrity.checks_blocks()),
+            filehash: fileinfo.filehash.as_deref().filter(|_| integrity.checks_file()),
+            block_hash_algorithm: fileinfo.block_hash_algorithm,
+            filehash_algorithm: fileinfo.filehash_algorithm,
+            context: &context,
+        };
 
-            if pos >= (fileinfo.uncompressed_length) as usize {
-                break;
+        let block_count = params.block_count(fileinfo.uncompressed_length);
+        let read_block = |block: &mut Block| {
+            block.len = std::cmp::min(block.size, fileinfo.uncompressed_length as usize - block.pos() as usize);
+            let read_amount = match is_encrypted {
+                true => params.align_to_sector(block.len),
+                false => block.len,
+            };
+            block.data.resize(read_amount, 0);
+            let start = Instant::now();
+            let read = utils::read_up_to(&mut reader, &mut block.data);
+            read_time += start.elapsed();
+            let read = read.map_err(|e| {
+                // Corrupt deflate streams surface as invalid input/data
+                let operation = match e.kind() {
+                    std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData if is_compressed => Operation::Inflate,
+                    _ => Operation::Read,
+                };
+                context(operation, block.pos()).wrap(e.into())
+            })?;
+
+            let padded_last = is_encrypted && block.index + 1 == block_count;
+            if !block.is_complete(read, read_amount, padded_last, &params) {
+                return Err(context(Operation::Read, block.pos()).wrap(
+                    std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Data ends inside the file").into()));
             }
+            block.data.truncate(read);
+            Ok(())
+        };
+        let write_block = |block: &Block| {
+            writer.write_all(block.plaintext())
+                .map_err(|e| context(Operation::Write, block.pos()).wrap(e.into()))
+        };
 
-            block += 1;
+        let mut timings = pipeline.run(block_count, read_block, write_block)?;
+        // Reading the deflate stream includes reading what it inflates from
+        timings.read = stored.get();
+        if is_compressed {
+            timings.inflate = Throughput {
+                bytes: fileinfo.uncompressed_length,
+                time: read_time.saturating_sub(timings.read.time),
+            };
         }
+        Ok(timings)
+    }
 
-        if fileinfo.uncompressed_length != pos as u64 {
-            return Err(Error::DataError("Invalid filesize".into()));
-        }
+    pub fn verify_file<P: PackageSource + ?Sized, I: Into<FileInfo>>(
+        source: &P,
+        fileinfo: I,
+        from_bundle: bool,
+    ) -> Result<(), Error> {
+        let mut report = FileReport::new("", 0);
+        Self::verify_file_blocks(source, fileinfo, from_bundle, None, &mut report)?;
 
-        if do_checksum_checks {
-            if let Some(hash) = fileinfo.filehash {
-                let final_hash = hasher.finalize();
-                assert_eq!(hex::encode(&final_hash.as_slice()), hex::encode(&hash), "Hash mismatch for file");
-            }
+        if !report.failed_blocks.is_empty() {
+            return Err(Error::DataError(format!("Invalid block hash for blocks {:?}", report.failed_blocks)));
+        }
+        if report.filehash_matches == Some(false) {
+            return Err(Error::DataError("Hash mismatch for file".into()));
         }
 
         Ok(())
     }
 
-    pub fn verify_file<R: std::io::Read + std::io::Seek, I: Into<FileInfo>>(
-        stream: &mut R,
+    /// Walk the blocks of a file, recording hash mismatches in `report`
+    ///
+    /// Block hashes cover the stored (encrypted) data including the sector
+    /// padding, the file hash the plaintext, which for encrypted files needs
+    /// `crypto`. Reads stay within the stored payload, a last sector stored
+    /// without its padding fails its block but still counts for the file hash.
+    /// Returns the time spent per stage.
+    fn verify_file_blocks<P: PackageSource + ?Sized, I: Into<FileInfo>>(
+        source: &P,
         fileinfo: I,
         from_bundle: bool,
-    ) -> Result<(), Error> {
+        crypto: Option<CryptoFileContext>,
+        report: &mut FileReport,
+    ) -> Result<Timings, Error> {
         let fileinfo: FileInfo = fileinfo.into();
-        let is_encrypted = fileinfo.key_id_index != 0xFFFF && !from_bundle;
         let is_compressed = fileinfo.compression_type == 0x1;
-
-        stream.seek(std::io::SeekFrom::Start(fileinfo.offset_to_file))?;
-
-        let mut reader = Self::create_reader(
-            stream,
-            false,
-            is_compressed,
-            None
-        )?;
-
-        let mut pos = 0;
-        let mut block = 0;
-        let chunk_size = utils::BLOCK_SIZE;
-        let mut buf = vec![0u8; chunk_size];
-
-        loop {
-            let mut read_amount = std::cmp::min(chunk_size, (fileinfo.uncompressed_length as usize) - pos);
-            if is_encrypted {
-                read_amount = utils::align_to_sector(read_amount);
-            }
-
-            reader.read_exact(&mut buf[..read_amount])?;
-            if let Some(block_hash) = fileinfo.block_hashes.as_ref().and_then(|sq| sq.get(block)) {
-                // println!("Verifying block hash, block size: {:#X} (total: {:#X})", read_amount, fileinfo.uncompressed_length);
-                assert_eq!(hex::encode(Sha256::digest(&buf[..read_amount])), hex::encode(block_hash), "Invalid block hash");
+        let context = |operation, position: usize| EntryContext {
+            operation,
+            name: (!report.name.is_empty()).then(|| report.name.clone()),
+            file_id: fileinfo.file_id,
+            offset: fileinfo.offset_to_file,
+            position: position as u64,
+        };
+        let is_encrypted = Self::is_stored_encrypted(source, &fileinfo, from_bundle)
+            .map_err(|e| context(Operation::Read, 0).wrap(e))?;
+
+        // Compressed blocks end on a flush, knowing their stored sizes every block is inflated on its own
+        let params = fileinfo.params;
+        let block_count = params.block_count(fileinfo.uncompressed_length);
+        let block_sizes = fileinfo.block_sizes.as_ref()
+            .filter(|sizes| is_compressed && sizes.len() == block_count);
+        let mut decompress = flate2::Decompress::new(false);
+        let mut stored = vec![];
+
+        let stored_length = match is_encrypted && !is_compressed {
+            true => params.align_to_sector(fileinfo.compressed_length as usize) as u64,
+            false => fileinfo.compressed_length,
+        };
+        let payload_read = Cell::new(Throughput::default());
+        let mut payload = TimedRead { inner: source.range(fileinfo.offset_to_file, stored_length), spent: &payload_read };
+        let inflates_stream = is_compressed && block_sizes.is_none();
+        let mut reader = Self::create_reader(&mut payload, false, inflates_stream, None)
+            .map_err(|e| context(Operation::Read, 0).wrap(e))?;
+        let mut timings = Timings::default();
+        let mut read_time = Duration::ZERO;
+
+        let crypto = crypto.filter(|_| is_encrypted);
+        let hasher = (fileinfo.filehash.is_some() && (!is_encrypted || crypto.is_some())).then(|| fileinfo.filehash_algorithm.hasher());
+        let read_block = |block: &mut Block| {
+            let pos = block.pos() as usize;
+            block.len = std::cmp::min(block.size, (fileinfo.uncompressed_length as usize) - pos);
+            let read_amount = match is_encrypted {
+                true => params.align_to_sector(block.len),
+                false => block.len,
+            };
+            block.data.resize(read_amount, 0);
+
+            match block_sizes.and_then(|sizes| sizes.get(block.index)) {
+                Some(stored_size) => {
+                    stored.resize(*stored_size as usize, 0);
+                    reader.read_exact(&mut stored)
+                        .map_err(|e| context(Operation::Read, pos).wrap(e.into()))?;
+                    let inflated = timings.measure(Stage::Inflate, block.len, || utils::inflate_block(&mut decompress, &stored, &mut block.data));
+                    if !inflated {
+                        decompress.reset(false);
+                    }
+                    Ok(inflated)
+                },
+                None => {
+                    let start = Instant::now();
+                    let read = utils::read_up_to(&mut reader, &mut block.data);
+                    read_time += start.elapsed();
+                    let read = read.map_err(|e| context(Operation::Read, pos).wrap(e.into()))?;
+                    let padded_last = is_encrypted && block.index + 1 == block_count;
+                    if !block.is_complete(read, read_amount, padded_last, &params) {
+                        return Err(context(Operation::Read, pos).wrap(
+                            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Data ends inside the file").into()));
+                    }
+                    block.data.truncate(read);
+                    Ok(true)
+                },
             }
+        };
 
-            pos += read_amount;
-
-            if pos >= (fileinfo.uncompressed_length) as usize {
-                break;
-            }
+        let verifier = BlockVerifier {
+            block_size: params.block_size(),
+            block_hashes: fileinfo.block_hashes.as_deref(),
+            block_hash_algorithm: fileinfo.block_hash_algorithm,
+            crypto: crypto.as_ref(),
+            hasher,
+            failed_blocks: vec![],
+            timings: Timings::default(),
+        }.run(block_count, read_block)?;
+
+        report.failed_blocks.extend(verifier.failed_blocks);
+        if let (Some(hasher), Some(filehash)) = (verifier.hasher, fileinfo.filehash.as_ref()) {
+            report.filehash_matches = Some(hasher.finalize() == *filehash);
+        }
 
-            block += 1;
+        if !report.failed_blocks.is_empty() || report.filehash_matches == Some(false) {
+            report.status = FileStatus::Corrupt;
+        } else if report.status == FileStatus::Valid && is_encrypted && crypto.is_none() && fileinfo.filehash.is_some() {
+            report.status = FileStatus::KeyMissing;
         }
 
-        Ok(())
+        // Reading the deflate stream includes reading what it inflates from
+        timings += verifier.timings;
+        timings.read = payload_read.get();
+        if inflates_stream {
+            timings.inflate = Throughput {
+                bytes: fileinfo.uncompressed_length,
+                time: read_time.saturating_sub(timings.read.time),
+            };
+        }
+        Ok(timings)
     }
 
-    pub fn read_file_to_buf<R: std::io::Read + std::io::Seek, I: Into<FileInfo> + Clone>(
-        stream: &mut R,
+    /// Read a whole file into memory, refusing files larger than `max_size`
+    pub fn read_file_to_buf<P: PackageSource + ?Sized, I: Into<FileInfo> + Clone>(
+        source: &P,
         fileinfo: I,
         is_bundle: bool,
+        max_size: u64,
     ) -> Result<Vec<u8>, Error> {
+        let fileinfo: FileInfo = fileinfo.into();
+        ResourceLimits::check("In-memory file size", fileinfo.uncompressed_length, max_size)?;
+
         let mut buf = vec![];
         let mut c = Cursor::new(&mut buf);
-        Self::read_file(stream, &mut c, fileinfo, is_bundle, None, true)?;
+        Self::read_file(source, &mut c, fileinfo, is_bundle, None, IntegrityPolicy::Full)?;
 
         Ok(buf)
     }
 
-    pub fn save_file_to_fs<R: std::io::BufRead + std::io::Seek, I: Into<FileInfo>>(
+    pub fn save_file_to_fs<P: PackageSource + ?Sized, I: Into<FileInfo>>(
         &self,
-        stream: &mut R,
+        source: &P,
         fileinfo: I,
         destination_path: &Path,
         filename: &str
     ) -> Result<(), Error> {
-        let fileinfo: FileInfo = fileinfo.into();
-        let crypto =self.get_cipher_for_key_index(fileinfo.key_id_index).map(|cipher| 
-            CryptoFileContext {
-                cipher: create_cipher(&cipher),
-                tweak: get_tweak_for_file(&self.header.app_name(), &self.header.publisher_id(), filename)
-            }
-        );
+        let relative_path = OutputLayout::new(self.extract_options).map(filename)?;
+        self.save_file_to_path(source, fileinfo, destination_path, relative_path, filename)?;
+        Ok(())
+    }
 
-        // Convert to os-specific seperators
-        let filename = match cfg!(windows) {
-            true => filename.to_owned(),
-            false => filename.replace("\\", "/"),
+    /// Write a file to `relative_path` below `destination_path`, `filename` is its path inside the package
+    fn save_file_to_path<P: PackageSource + ?Sized, I: Into<FileInfo>>(
+        &self,
+        source: &P,
+        fileinfo: I,
+        destination_path: &Path,
+        relative_path: std::path::PathBuf,
+        filename: &str
+    ) -> Result<ExtractedFile, Error> {
+        let fileinfo = FileInfo { params: self.params, ..fileinfo.into() };
+        let crypto = self.crypto_context(fileinfo.key_id_index, filename)?;
+        let target_filepath = utils::long_path(&destination_path.join(&relative_path))?;
+        let is_encrypted = self.is_entry_encrypted(&fileinfo);
+        let missing_key = is_encrypted && crypto.is_none();
+
+        let mut entry = ExtractedFile {
+            package_path: filename.to_owned(),
+            path: relative_path,
+            size: fileinfo.uncompressed_length,
+            sha256: None,
+            encrypted: is_encrypted,
+            compressed: fileinfo.compression_type == 0x1,
+            status: ExtractionStatus::Kept,
+            timings: None,
+            duplicate_of: None,
         };
 
-        // Assemble target filepath
-        let target_filepath = destination_path.join(filename);
-        std::fs::create_dir_all(target_filepath.parent().unwrap())?;
+        if missing_key && self.extract_options.on_missing_key == MissingKeyPolicy::SkipEncryptedWithoutKey {
+            log::warn!("Skipping {filename}, its key is not loaded");
+            entry.status = ExtractionStatus::SkippedKeyMissing;
+            return Ok(entry);
+        }
+
+        // Ciphertext has no hash to compare an existing file against
+        if target_filepath.exists()
+            && (!self.may_write(&target_filepath)?
+                || (self.extract_options.overwrite == OverwritePolicy::SkipIfHashMatches
+                    && !missing_key
+                    && layout::local_file_matches(&target_filepath, &fileinfo, is_encrypted)?))
+        {
+            log::info!("Keeping existing {}", target_filepath.display());
+            return Ok(entry);
+        }
+
+        if let Some(parent) = target_filepath.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
 
         // Open target file handle and read data into it
-        let mut file = std::fs::File::create(target_filepath)?;
-        Self::read_file(stream, &mut file, fileinfo, self.header.is_bundle(), crypto, self.do_checksum_check)
-    }
+        let filehash = fileinfo.filehash.clone();
+        let mut file = HashingWriter::with_filehash(std::fs::File::create(&target_filepath)?, fileinfo.filehash_algorithm);
+        if missing_key && self.extract_options.on_missing_key == MissingKeyPolicy::ExtractCiphertext {
+            log::warn!("Writing {filename} as ciphertext, its key is not loaded");
+            Self::read_ciphertext(source, &mut file, &fileinfo, filename)?;
+            let (size, sha256) = file.finalize();
+            entry.status = ExtractionStatus::Ciphertext;
+            entry.size = size;
+            entry.sha256 = Some(sha256);
+            return Ok(entry);
+        }
+        let timings = Self::read_entry(source, &mut file, fileinfo, Some(filename), self.header.is_bundle(), crypto, self.integrity)?;
+        entry.timings = self.collect_timings.then_some(timings);
 
-    pub fn load_keys(&mut self, key_collection: &KeyCollection) -> Result<(), Error> {
-        key_collection.keys.iter()
-            .for_each(|(key_id, keydata)| {
-                self.keys.insert(key_id.clone(), keydata.to_vec());
-            });
-        
-        Ok(())
-    }
+        let (size, sha256, digest) = file.finalize_digests();
+        entry.status = ExtractionStatus::of_plaintext(filehash.as_deref(), &digest);
+        entry.size = size;
+        entry.sha256 = Some(sha256);
 
-    pub fn find_footer_for_file(&self, file_id: u64) -> Option<&EAppxFooter> {
-        return self.footers
-            .iter()
-            .find(|footer| footer.file_id == file_id)
+        Ok(entry)
     }
 
-    fn get_cipher_for_key_index(&self, key_index: u16) -> Option<[u8; 32]> {
-        if key_index == 0xFFFF {
-            return None;
+    /// Link or copy `original` to `relative_path` below `destination_path` instead of decoding the file again
+    fn write_duplicate(
+        &self,
+        original: &ExtractedFile,
+        destination_path: &Path,
+        relative_path: std::path::PathBuf,
+        fileinfo: FileInfo,
+        filename: &str
+    ) -> Result<ExtractedFile, Error> {
+        let source = utils::long_path(&destination_path.join(&original.path))?;
+        let target_filepath = utils::long_path(&destination_path.join(&relative_path))?;
+        let mut entry = ExtractedFile {
+            package_path: filename.to_owned(),
+            path: relative_path,
+            size: fileinfo.uncompressed_length,
+            sha256: None,
+            encrypted: self.is_entry_encrypted(&fileinfo),
+            compressed: fileinfo.compression_type == 0x1,
+            status: ExtractionStatus::Kept,
+            timings: None,
+            duplicate_of: None,
+        };
+
+        if target_filepath.exists() {
+            if !self.may_write(&target_filepath)? {
+                log::info!("Keeping existing {}", target_filepath.display());
+                return Ok(entry);
+            }
+            // Links are not created over existing files
+            std::fs::remove_file(&target_filepath)?;
         }
-        else if let Some(key_id) = self.header.key_ids.get(key_index as usize) {
-            return self.keys
-                .get(key_id)
-                .map(|e| e.to_vec().try_into().unwrap());
+        if let Some(parent) = target_filepath.parent() {
+            std::fs::create_dir_all(parent)?;
         }
 
-        None
-    }
+        let linked = self.extract_options.duplicates == DuplicatePolicy::HardLink
+            && std::fs::hard_link(&source, &target_filepath)
+                .inspect_err(|err| log::debug!("Failed to link {}, copying it instead: {err}", target_filepath.display()))
+                .is_ok();
+        if !linked {
+            std::fs::copy(&source, &target_filepath)?;
+        }
+        log::info!("{} {filename} from {}", if linked { "Linked" } else { "Copied" }, original.package_path);
 
-    fn read_footers<S: std::io::BufRead + std::io::Seek>(stream: &mut S, offset: u64, count: usize) -> Result<Vec<EAppxFooter>, Error> {
-        stream.seek(std::io::SeekFrom::Start(offset))?;
-        
-        let footers = (0..count)
-            .map(|_| EAppxFooter::read(stream).unwrap())
-            .collect::<Vec<_>>();
-    
-        Ok(footers)
+        entry.sha256 = original.sha256.clone();
+        entry.status = original.status;
+        entry.duplicate_of = Some(original.path.clone());
+        Ok(entry)
     }
 
-    pub fn from_stream<S: std::io::BufRead + std::io::Seek>(stream: &mut S) -> Result<Self, Error> {        
-        let file_len = stream.seek(std::io::SeekFrom::End(0)).unwrap();
-        stream.rewind().unwrap();
+    /// Whether the overwrite policy allows replacing an existing file
+    fn may_write(&self, target_filepath: &Path) -> Result<bool, Error> {
+        if !target_filepath.exists() {
+            return Ok(true);
+        }
 
-        // Read header
-        let header = EAppxHeader::read(stream).unwrap();
+        match self.extract_options.overwrite {
+            OverwritePolicy::Skip => Ok(false),
+            OverwritePolicy::Overwrite | OverwritePolicy::SkipIfHashMatches => Ok(true),
+            OverwritePolicy::ErrorIfExists => Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("{} already exists", target_filepath.display()),
+            ).into()),
+        }
+    }
 
-        // Read footers
-        let footers: Vec<EAppxFooter> = Self::read_footers(stream, header.footer_offset, header.footer_count())?;
-     
-        // Get blockmap metadata
-        let mut blockmap_fileinfo: FileInfo = footers.get(header.block_map_file_id as usize)
-            .ok_or(Error::DataError("Failed to find blockmap file".into()))?
-            .into();
-        blockmap_fileinfo.filehash = Some(header.block_map_hash.clone());
+    /// Footprint files are rewritten unless the policy forbids it, only the blockmap has a known hash
+    fn write_footprint_file(&self, target_filepath: &Path, file: FootprintFile, data: &[u8], compressed: bool) -> Result<ExtractedFile, Error> {
+        let name = file.file_name();
+        let mut entry = ExtractedFile {
+            package_path: name.to_owned(),
+            path: name.into(),
+            size: data.len() as u64,
+            sha256: None,
+            encrypted: false,
+            compressed,
+            status: ExtractionStatus::Kept,
+            timings: None,
+            duplicate_of: None,
+        };
 
-        // Deserialize blockmap
-        let buf = Self::read_file_to_buf(stream, blockmap_fileinfo, header.is_bundle())?;
-        let blockmap: AppxBlockMap = xml_deserialize_from_reader(Cursor::new(buf))
-            .map_err(Error::DecodeError)?;
+        let path = utils::long_path(&target_filepath.join(name))?;
+        if self.may_write(&path)? {
+            std::fs::write(path, data)?;
+
+            let sha256 = Sha256::digest(data).to_vec();
+            entry.status = match file == FootprintFile::BlockMap {
+                true if sha256 == self.header.block_map_hash => ExtractionStatus::Verified,
+                true => ExtractionStatus::HashMismatch,
+                false => ExtractionStatus::Unverified,
+            };
+            entry.sha256 = Some(sha256);
+        }
 
-        Ok(Self {
-            header,
-            file_len,
-            footers,
-            blockmap,
-            keys: HashMap::new(),
-            do_checksum_check: false,
-        })
+        Ok(entry)
     }
 
-    pub fn read_manifest<S: std::io::BufRead + std::io::Seek>(&self, stream: &mut S) -> Result<Manifest, Error> {
-        // First entry should always be the bundle-/manifest
-        let file = self.blockmap.files
-            .first()
-            .ok_or(Error::DataError("Could not get first blockmap file".into()))?;
+    /// Payload of a blockmap file as stored: raw deflate of the (sector padded)
+    /// ciphertext for encrypted files, deflated or plain data otherwise
+    ///
+    /// No key is needed and nothing is checked, the footer tells how to decode
+    /// the bytes. Moves entries between containers without re-encrypting them.
+    pub fn raw_reader<'a, P: PackageSource + ?Sized>(
+        &self,
+        source: &'a P,
+        file: &blockmap::File,
+    ) -> Result<(&EAppxFooter, std::io::Take<SourceReader<&'a P>>), Error> {
         let footer = self.find_footer_for_file(file.id())
-            .ok_or(Error::DataError("Could not get Footer info for blockmap file".into()))?;
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+        let length = self.stored_length(footer);
+        if footer.offset_to_file.saturating_add(length) > self.file_len {
+            return Err(Error::DataError(format!("Payload of {} ends beyond the end of the package", file.name)));
+        }
+        Ok((footer, source.range(footer.offset_to_file, length)))
+    }
 
-        let buf = Self::read_file_to_buf(stream, footer, self.header.is_bundle())?;
-        let manifest = match file.name.split('\\').last().ok_or(Error::DataError("Could not determine filename from blockmap filename".into()))? {
-            "AppxManifest.xml" => {
-                let res: AppxManifest = xml_deserialize_from_reader(Cursor::new(buf))
-                    .map_err(Error::DecodeError)?;
-                Manifest::Manifest(res)
-            },
-            "AppxBundleManifest.xml" => {
-                let res: AppxBundleManifest = xml_deserialize_from_reader(Cursor::new(buf))
-                    .map_err(Error::DecodeError)?;
-                Manifest::BundleManifest(res)
+    /// Plaintext of a blockmap file, decrypted and decompressed on the fly
+    ///
+    /// Fails if the file is encrypted with a key that was not loaded.
+    pub fn file_reader<'a, P: PackageSource + ?Sized>(
+        &self,
+        source: &'a P,
+        file: &blockmap::File,
+    ) -> Result<utils::BlockReader<Box<dyn Read + 'a>>, Error> {
+        let footer = self.find_footer_for_file(file.id())
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+        self.payload_reader(source, footer, &file.name)
+    }
+
+    /// Plaintext of the payload described by `footer`, `filename` is its path inside the package
+    pub(crate) fn payload_reader<'a, P: PackageSource + ?Sized>(
+        &self,
+        source: &'a P,
+        footer: &EAppxFooter,
+        filename: &str,
+    ) -> Result<utils::BlockReader<Box<dyn Read + 'a>>, Error> {
+        let is_encrypted = self.is_footer_encrypted(footer);
+
+        let crypto = match is_encrypted {
+            true => {
+                let context = || EntryContext {
+                    operation: Operation::Decrypt,
+                    name: Some(filename.to_owned()),
+                    file_id: Some(footer.file_id),
+                    offset: footer.offset_to_file,
+                    position: 0,
+                };
+                let missing = || Error::MissingKey {
+                    key_ids: self.header.key_ids.get(footer.key_id_index as usize).cloned().into_iter().collect(),
+                    files: vec![filename.to_owned()],
+                };
+                Some(self.crypto_context(footer.key_id_index, filename)
+                    .map_err(|e| context().wrap(e))?
+                    .ok_or_else(|| context().wrap(missing()))?)
             },
-            _ => return Err(Error::DataError("Expected Manifest to be first file in blockmap".into()))
+            false => None,
         };
 
-        Ok(manifest)
+        let reader = Self::create_reader(source.reader_at(footer.offset_to_file), is_encrypted, footer.compression_type == 0x1, crypto)?;
+
+        Ok(utils::BlockReader::with_block_size(reader, footer.uncompressed_length, self.params.block_size()))
     }
 
-    pub fn extract_footprint_files<T: std::io::BufRead + std::io::Seek>(
-        &self,
-        stream: &mut T,
-        target_filepath: &Path,
-    ) -> Result<(), Error> {
-        // Read blockmap here again, to have the original representation instead
-        // of the already deserialized
-        // reason: the schema is not implemented 100%
-        let blockmap_fileinfo = self.find_footer_for_file(self.header.block_map_file_id)
-            .ok_or(Error::DataError("Failed to find blockmap file".into()))?;
-        self.save_file_to_fs(stream, blockmap_fileinfo, target_filepath, "AppxBlockmap.xml")?;
-
-        if let Some(signature_fileinfo) = self.header.appx_signature_fileinfo() {
-            println!("Saving signature..");
-            if signature_fileinfo.offset_to_file < self.file_len {
-                self.save_file_to_fs(stream, signature_fileinfo, target_filepath, "AppxSignature.p7x")?;
+    /// Context for decrypting `filename`, `None` if unencrypted or its key is not loaded
+    fn crypto_context(&self, key_index: u16, filename: &str) -> Result<Option<CryptoFileContext>, Error> {
+        Ok(self.get_cipher_for_key_index(key_index)?.map(|mut key| {
+            let cipher = create_cipher(&key);
+            keys::wipe(&mut key);
+            CryptoFileContext {
+                cipher,
+                tweak: get_tweak_for_file(&self.header.app_name(), &self.header.publisher_id(), filename),
+                params: self.params,
             }
+        }))
+    }
+
+    /// Context for decrypting blockmap file `entry_name` read through [`EAppxFile::raw_reader`]
+    ///
+    /// `entry_name` is looked up like [`AppxBlockMap::find_file`] does, the
+    /// tweak derives from the name as stored. Fails if the file is unknown, not
+    /// encrypted or its key is not loaded.
+    pub fn crypto_context_for(&self, entry_name: &str) -> Result<CryptoFileContext, Error> {
+        let file = self.blockmap.find_file(entry_name)
+            .ok_or_else(|| Error::DataError(format!("No file {entry_name} in the blockmap")))?;
+        let footer = self.find_footer_for_file(file.id())
+            .ok_or_else(|| Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+        if !self.is_footer_encrypted(footer) {
+            return Err(Error::DataError(format!("{} is not encrypted", file.name)));
+        }
+        self.crypto_context(footer.key_id_index, &file.name)?
+            .ok_or_else(|| Error::MissingKey {
+                key_ids: self.header.key_ids.get(footer.key_id_index as usize).cloned().into_iter().collect(),
+                files: vec![file.name.clone()],
+            })
+    }
+
+    /// Add the keys of `key_collection`, rejecting keys of the wrong length before loading any
+    ///
+    /// Every key has to fit AES-XTS-128, keys this package references also the
+    /// header's `key_length`.
+    pub fn load_keys(&mut self, key_collection: &KeyCollection) -> Result<(), Error> {
+        let crypto_algo = self.header.crypto_algo();
+        match keys::key_length_for_algorithm(&crypto_algo) {
+            Some(expected) if expected != self.header.key_length as usize =>
+                log::warn!("Header declares {:#x} byte keys, {crypto_algo} uses {expected:#x}", self.header.key_length),
+            _ => {},
         }
-        
-        if let Some(ci_fileinfo) = self.header.code_integrity_fileinfo() {
-            println!("Saving code integrity..");
-            if ci_fileinfo.offset_to_file < self.file_len {
-                self.save_file_to_fs(stream, ci_fileinfo, target_filepath, "CodeIntegrity.cat")?;
+
+        for (key_id, keydata) in &key_collection.keys {
+            if keydata.len() != keys::KEY_LENGTH {
+                return Err(Error::DataError(format!("Key {} is {:#x} bytes, expected {:#x}", key_id.to_keyfile_string(), keydata.len(), keys::KEY_LENGTH)));
+            }
+            if self.header.key_ids.contains(key_id) && keydata.len() != self.header.key_length as usize {
+                return Err(Error::DataError(format!("Key {} is {:#x} bytes, the header declares {:#x}", key_id.to_keyfile_string(), keydata.len(), self.header.key_length)));
             }
         }
 
+        key_collection.keys.iter()
+            .for_each(|(key_id, keydata)| {
+                if let Some(mut replaced) = self.keys.insert(key_id.clone(), keydata.to_vec()) {
+                    keys::wipe(&mut replaced);
+                }
+            });
+
         Ok(())
     }
 
-    pub fn verify_blockmap_files<T: std::io::BufRead + std::io::Seek>(
-        &self,
-        stream: &mut T
-    ) -> Result<(), Error> {
-        println!("Verifying blockmap files...");
-
-        for file in &self.blockmap.files {
-            let mut file_footer: FileInfo = self.find_footer_for_file(file.id())
-                .ok_or(Error::DataError(format!("Failed to find footer for file {file:?}")))?
-                .into();
+    /// Fail if a blockmap file is encrypted with a key that is not loaded
+    pub(crate) fn check_keys(&self) -> Result<(), Error> {
+        if self.header.is_bundle() {
+            return Ok(());
+        }
+        let missing = self.missing_keys();
+        let files = self.blockmap.files.iter()
+            .filter(|file| self.find_footer_for_file(file.id())
+                .and_then(|footer| self.header.key_ids.get(footer.key_id_index as usize))
+                .is_some_and(|key_id| missing.contains(key_id)))
+            .map(|file| file.name.as_str())
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            return Ok(());
+        }
+        Err(Error::MissingKey { key_ids: missing, files: files.into_iter().map(str::to_owned).collect() })
+    }
 
-            file_footer.filehash = file.filehash_bytes();
-            file_footer.block_hashes = Some(file.block_hashes());
+    /// Key-ids of the header without a loaded key
+    pub fn missing_keys(&self) -> Vec<KeyId> {
+        let mut missing: Vec<KeyId> = vec![];
+        for key_id in self.header.key_ids.iter().filter(|key_id| !self.keys.contains_key(key_id)) {
+            if !missing.contains(key_id) {
+                missing.push(key_id.clone());
+            }
+        }
+        missing
+    }
 
-            assert_eq!(file.size, file_footer.uncompressed_length,
-                "BlockMap vs. Footer file offset mismatch (manifest: {}, footer: {})", file.size, file_footer.uncompressed_length);
+    pub fn find_footer_for_file(&self, file_id: u64) -> Option<&EAppxFooter> {
+        self.footers
+            .iter()
+            .find(|footer| footer.file_id == file_id)
+    }
 
-            println!("* File: {} (encrypted={}, compressed={} id: {}) size: {}",
-                file.name, file.is_encrypted(), file_footer.compression_type, file.id(), utils::get_filesize_with_unit(file.size));
+    /// Whether the payload of `file` is encrypted
+    ///
+    /// The footer decides, see [`EAppxFile::is_footer_encrypted`], the blockmap `Encrypted` flag only counts
+    /// for files without a footer. Validation reports where both disagree.
+    pub fn is_file_encrypted(&self, file: &blockmap::File) -> bool {
+        self.find_footer_for_file(file.id())
+            .map(|footer| self.is_footer_encrypted(footer))
+            .unwrap_or(file.is_encrypted())
+    }
 
-            Self::verify_file(stream, file_footer, self.header.is_bundle())?;
+    /// Whether the payload of `footer` is encrypted
+    ///
+    /// Its key index decides, inside bundles only for inner packages found
+    /// encrypted on opening, see [`EAppxFile::is_stored_encrypted`].
+    pub fn is_footer_encrypted(&self, footer: &EAppxFooter) -> bool {
+        match self.header.is_bundle() {
+            true => footer.is_encrypted(false) && self.encrypted_bundle_payloads.contains(&footer.file_id),
+            false => footer.is_encrypted(false),
         }
+    }
 
-        Ok(())
+    /// [`EAppxFile::is_footer_encrypted`] for a payload described by `fileinfo`
+    fn is_entry_encrypted(&self, fileinfo: &FileInfo) -> bool {
+        match (self.header.is_bundle(), fileinfo.file_id) {
+            (true, Some(file_id)) => fileinfo.key_id_index != 0xFFFF && self.encrypted_bundle_payloads.contains(&file_id),
+            (true, None) => false,
+            (false, _) => fileinfo.key_id_index != 0xFFFF,
+        }
     }
 
-    pub fn extract_blockmap_files<T: std::io::BufRead + std::io::Seek>(
-        &self,
-        stream: &mut T,
-        target_filepath: &Path
-    ) -> Result<(), Error> {
-        println!("Extracting blockmap files...");
+    /// Bytes the payload of `footer` occupies, encrypted payloads are padded to sectors
+    pub fn stored_length(&self, footer: &EAppxFooter) -> u64 {
+        footer.padded_length(self.is_footer_encrypted(footer), &self.params)
+    }
 
-        for file in &self.blockmap.files {
-            let mut file_footer: FileInfo = self.find_footer_for_file(file.id())
-                .ok_or(Error::DataError(format!("Failed to find footer for file {file:?}")))?
-                .into();
+    /// Packages embedded in a bundle, with their footers
+    pub(crate) fn bundle_payloads<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Vec<(String, &EAppxFooter)>, Error> {
+        Ok(self.bundle_packages(source)?
+            .into_iter()
+            .map(|(package, footer)| (package.filename, footer))
+            .collect())
+    }
 
-            file_footer.filehash = file.filehash_bytes();
-            file_footer.block_hashes = Some(file.block_hashes());
+    /// Packages embedded in a bundle that pass [`EAppxFile::bundle_filter`], with their footers
+    pub(crate) fn applicable_bundle_payloads<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Vec<(String, &EAppxFooter)>, Error> {
+        Ok(self.bundle_packages(source)?
+            .into_iter()
+            .filter(|(package, _)| {
+                let applies = self.bundle_filter.applies(package);
+                if !applies {
+                    log::info!("* Skipping bundle file {package}");
+                }
+                applies
+            })
+            .map(|(package, footer)| (package.filename, footer))
+            .collect())
+    }
 
-            assert_eq!(file.size, file_footer.uncompressed_length,
-                "BlockMap vs. Footer file offset mismatch (manifest: {}, footer: {})", file.size, file_footer.uncompressed_length);
+    fn bundle_packages<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Vec<(manifest::Package, &EAppxFooter)>, Error> {
+        let bundle_manifest = match (self.header.magic, self.read_manifest(source)?) {
+            (EAppxMagic::EXBH | EAppxMagic::EXSH, Manifest::BundleManifest(bundle_manifest)) => bundle_manifest,
+            (EAppxMagic::EXBH, Manifest::Manifest(_)) => return Err(Error::DataError("Expected bundle manifest".into())),
+            _ => return Ok(vec![]),
+        };
 
-            println!("* File: {} (encrypted={}, compressed={} id: {}) size: {}",
-                file.name, file.is_encrypted(), file_footer.compression_type, file.id(), utils::get_filesize_with_unit(file.size));
+        bundle_manifest.packages.package
+            .into_iter()
+            .enumerate()
+            .map(|(idx, package)| {
+                let footer = self.find_footer_for_file(idx as u64)
+                    .ok_or(Error::DataError(format!("File {} not found in footers", package.filename)))?;
+                Ok((package, footer))
+            })
+            .collect()
+    }
 
-            self.save_file_to_fs(stream, file_footer, target_filepath, &file.name)?;
+    /// Key for a footer's `key_index`, `None` if unencrypted or the key is not loaded
+    fn get_cipher_for_key_index(&self, key_index: u16) -> Result<Option<[u8; 32]>, Error> {
+        if key_index == 0xFFFF {
+            return Ok(None);
         }
+        let key_id = self.header.key_ids.get(key_index as usize)
+            .ok_or_else(|| Error::DataError(format!("Key index {key_index} is out of range, the header lists {} key-id(s)", self.header.key_ids.len())))?;
 
-        Ok(())
+        self.keys.get(key_id)
+            .map(|key| key.as_slice().try_into()
+                .map_err(|_| Error::DataError(format!("Key {} (index {key_index}) is {:#x} bytes, expected {:#x}", key_id.to_keyfile_string(), key.len(), keys::KEY_LENGTH))))
+            .transpose()
     }
 
-    pub fn extract_bundle_files<T: std::io::BufRead + std::io::Seek>(
-        &self,
-        stream: &mut T,
-        target_filepath: &Path,
-    ) -> Result<(), Error> {
-        let manifest = self.read_manifest(stream)?;
-        let bundle_manifest = match manifest {
-            Manifest::Manifest(_) => return Err(Error::DataError("Expected bundle manifest".into())),
-            Manifest::BundleManifest(bundle_manifest) => bundle_manifest,
-        };
+    /// Open the package `source` holds, reading its header, footers and blockmap
+    pub fn from_source<P: PackageSource + ?Sized>(source: &P) -> Result<Self, Error> {
+        Self::from_source_with_limits(source, ResourceLimits::default())
+    }
 
-        for (bundle_file_index, package) in bundle_manifest.packages.package.into_iter().enumerate() {
-            println!("* Bundle file: {}", &package.filename);
-            let file_meta = self.find_footer_for_file(bundle_file_index as u64)
-                .ok_or(Error::DataError(format!("File {} not found in footers", package.filename)))?;
+    /// [`EAppxFile::from_source`] for a stream, read through a [`StreamSource`]
+    pub fn from_stream<S: std::io::Read + std::io::Seek + Send>(stream: &mut S) -> Result<Self, Error> {
+        Self::from_source(&StreamSource::new(stream))
+    }
 
-            assert_eq!(package.offset, file_meta.offset_to_file,
-                "Bundle Manifest vs. Footer file offset mismatch (manifest: {}, footer: {})", package.offset, file_meta.offset_to_file);
+    /// Read only the header, without footers or blockmap
+    ///
+    /// Enough for the package full name, key ids and footprint locations. The
+    /// rest can be loaded later on with [`EAppxFile::from_header`], or on
+    /// first use through a [`LazyPackage`](lazy::LazyPackage).
+    pub fn peek_header<P: PackageSource + ?Sized>(source: &P) -> Result<EAppxHeader, Error> {
+        container::read_header(source)
+    }
 
-            self.save_file_to_fs(stream, file_meta, target_filepath, &package.filename)?;
-        }
+    /// Footers of a package, checked against its size, and where the blockmap is stored
+    pub(crate) fn read_layout<P: PackageSource + ?Sized>(
+        source: &P,
+        header: &EAppxHeader,
+        limits: &ResourceLimits,
+    ) -> Result<(Vec<EAppxFooter>, u64, FileInfo), Error> {
+        let (footers, file_len) = container::read_footer_table(source, header, limits)?;
 
-        Ok(())
+        // Get blockmap metadata
+        let mut blockmap_fileinfo: FileInfo = footers.get(header.block_map_file_id as usize)
+            .ok_or(Error::DecodeError("Failed to find blockmap file".into()))?
+            .into();
+        blockmap_fileinfo.filehash = Some(header.block_map_hash.clone());
+        blockmap_fileinfo.params = header.params();
+
+        Ok((footers, file_len, blockmap_fileinfo))
     }
 
-    pub fn extract<T: std::io::BufRead + std::io::Seek>(
-        &self,
-        stream: &mut T,
-        target_filepath: &Path
-    ) -> Result<(), Error> {
-        self.extract_footprint_files(stream, target_filepath)?;
-        self.extract_blockmap_files(stream, target_filepath)?;
-        if self.header.is_bundle()
-        {
-            self.extract_bundle_files(stream, target_filepath)?;
-        }
+    /// [`EAppxFile::from_source_with_limits`] for a stream, read through a [`StreamSource`]
+    pub fn from_stream_with_limits<S: std::io::Read + std::io::Seek + Send>(stream: &mut S, limits: ResourceLimits) -> Result<Self, Error> {
+        Self::from_source_with_limits(&StreamSource::new(stream), limits)
+    }
 
-        Ok(())
+    pub fn from_source_with_limits<P: PackageSource + ?Sized>(source: &P, limits: ResourceLimits) -> Result<Self, Error> {
+        let header = Self::peek_header(source)?;
+        Self::from_header(source, header, limits)
     }
-}
 
-impl std::fmt::Display for EAppxFile {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.header)?;
+    /// Finish opening a package whose header was read with [`EAppxFile::peek_header`]
+    pub fn from_header<P: PackageSource + ?Sized>(source: &P, header: EAppxHeader, limits: ResourceLimits) -> Result<Self, Error> {
+        lazy::LazyPackage::from_header(source, header, limits).into_package()
+    }
 
-        for (footer_idx, footer) in self.footers.iter().enumerate() {
-            writeln!(f, "{footer_idx:#04x}: {footer}")?;
+    fn new(header: EAppxHeader, file_len: u64, footers: Vec<EAppxFooter>, mut blockmap: AppxBlockMap, limits: ResourceLimits) -> Self {
+        blockmap.detect_decimal_ids(|id| footers.iter().any(|footer| footer.file_id == id));
+        Self {
+            params: header.params(),
+            header,
+            file_len,
+            footers,
+            blockmap,
+            keys: HashMap::new(),
+            integrity: IntegrityPolicy::default(),
+            limits,
+            extract_options: ExtractOptions::default(),
+            bundle_filter: BundleFilter::default(),
+            collect_timings: false,
+            block_cache: None,
+            manifest: std::sync::OnceLock::new(),
+            encrypted_bundle_payloads: HashSet::new(),
+            #[cfg(feature = "signing")]
+            signer: None,
+        }
+    }
+
+    /// Find the inner packages of a bundle stored encrypted, see [`EAppxFile::is_stored_encrypted`]
+    pub(crate) fn detect_encrypted_payloads<P: PackageSource + ?Sized>(&mut self, source: &P) -> Result<(), Error> {
+        self.encrypted_bundle_payloads.clear();
+        if !self.header.is_bundle() {
+            return Ok(());
+        }
+        for footer in self.footers.iter().filter(|footer| footer.is_encrypted(false)) {
+            if Self::is_stored_encrypted(source, &footer.into(), true)? {
+                self.encrypted_bundle_payloads.insert(footer.file_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Manifest of the package or bundle, read from `source` once and cached
+    pub fn manifest<P: PackageSource + ?Sized>(&self, source: &P) -> Result<&Manifest, Error> {
+        if let Some(manifest) = self.manifest.get() {
+            return Ok(manifest);
+        }
+        let manifest = self.parse_manifest(source)?;
+        Ok(self.manifest.get_or_init(|| manifest))
+    }
+
+    /// Owned copy of [`EAppxFile::manifest`]
+    pub fn read_manifest<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Manifest, Error> {
+        self.manifest(source).cloned()
+    }
+
+    fn parse_manifest<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Manifest, Error> {
+        let file = self.blockmap.manifest_file()
+            .ok_or(Error::DataError("Could not find manifest in blockmap".into()))?;
+        let footer = self.find_footer_for_file(file.id())
+            .ok_or(Error::DataError("Could not get Footer info for blockmap file".into()))?;
+
+        let buf = Self::read_file_to_buf(source, footer, self.header.is_bundle(), self.limits.max_in_memory_size)?;
+        // A manifest found by position tells its kind only by the container
+        let is_bundle_manifest = match file.name.rsplit(['\\', '/']).next().unwrap_or_default() {
+            name if name.eq_ignore_ascii_case("AppxBundleManifest.xml") => true,
+            name if name.eq_ignore_ascii_case("AppxManifest.xml") => false,
+            _ => self.header.is_bundle(),
+        };
+        match is_bundle_manifest {
+            true => Ok(Manifest::BundleManifest(xml_deserialize_from_reader(Cursor::new(buf)).map_err(Error::DecodeError)?)),
+            false => Ok(Manifest::Manifest(AppxManifest::from_xml(&buf)?)),
+        }
+    }
+
+    /// Bundle manifest listing the embedded packages, `None` for packages
+    pub fn bundle_manifest<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Option<&AppxBundleManifest>, Error> {
+        match self.manifest(source)? {
+            Manifest::BundleManifest(bundle_manifest) => Ok(Some(bundle_manifest)),
+            Manifest::Manifest(_) => Ok(None),
+        }
+    }
+
+    /// Read AppxBlockMap.xml as stored in the package
+    ///
+    /// These are the exact bytes the header hash covers, `blockmap` holds the
+    /// parsed form.
+    pub fn read_raw_blockmap<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+    ) -> Result<Vec<u8>, Error> {
+        let blockmap_fileinfo = self.footprint_fileinfo(FootprintFile::BlockMap)
+            .ok_or(Error::DecodeError("Failed to find blockmap file".into()))?;
+
+        Self::read_file_to_buf(source, blockmap_fileinfo, self.header.is_bundle(), self.limits.max_in_memory_size)
+    }
+
+    /// Read AppxSignature.p7x, `None` if the package is unsigned
+    pub fn read_signature<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.read_footprint_file(source, FootprintFile::Signature)
+    }
+
+    /// Read CodeIntegrity.cat, `None` if the package carries no catalog
+    pub fn read_code_integrity<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        self.read_footprint_file(source, FootprintFile::CodeIntegrity)
+    }
+
+    pub fn extract_footprint_files<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path,
+    ) -> Result<(), Error> {
+        self.extract_footprint_files_into(source, target_filepath, &mut ExtractionReport::default(), &|_| true)
+    }
+
+    /// Extract footprint files, metadata files and the package manifest, skipping the payload
+    ///
+    /// `filter` gets the package path of each candidate, e.g. `AppxBlockMap.xml`
+    /// or `AppxMetadata\CodeIntegrity.cat`, and decides whether it is written.
+    /// Footprint files land in the root of `target_filepath`, the others keep
+    /// their directory.
+    pub fn extract_footprint_files_with<P: PackageSource + ?Sized, F: Fn(&str) -> bool>(
+        &self,
+        source: &P,
+        target_filepath: &Path,
+        filter: F,
+    ) -> Result<ExtractionReport, Error> {
+        let mut report = ExtractionReport::default();
+        self.extract_footprint_files_into(source, target_filepath, &mut report, &filter)?;
+
+        // Bundle manifests are metadata files already
+        if let Some(manifest) = self.blockmap.manifest_file().filter(|file| !file.is_metadata() && filter(&file.name)) {
+            log::info!("* Manifest: {} (id: {})", manifest.name, manifest.id());
+            let fileinfo = self.blockmap_fileinfo(manifest)?;
+            let relative_path = OutputLayout::new(ExtractOptions { flat: false, ..self.extract_options }).map(&manifest.name)?;
+            report.files.push(self.save_file_to_path(source, fileinfo, target_filepath, relative_path, &manifest.name)?);
+        }
+
+        Ok(report)
+    }
+
+    fn extract_footprint_files_into<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path,
+        report: &mut ExtractionReport,
+        filter: &dyn Fn(&str) -> bool,
+    ) -> Result<(), Error> {
+        std::fs::create_dir_all(utils::long_path(target_filepath)?)?;
+        for entry in self.footprint_entries().into_iter().filter(|entry| filter(entry.file.package_path())) {
+            let result = self.read_footprint_file(source, entry.file)
+                .and_then(|data| data.map(|data| self.write_footprint_file(target_filepath, entry.file, &data, entry.compressed)).transpose());
+            if let Some(written) = self.skip_failed(report, entry.file.package_path(), result)?.flatten() {
+                report.files.push(written);
+            }
+        }
+
+        // Metadata files keep their directory, even when extracting flat
+        let mut layout = OutputLayout::new(ExtractOptions { flat: false, ..self.extract_options });
+        for file in self.blockmap.metadata_files().filter(|file| filter(&file.name)) {
+            log::info!("* Metadata file: {} (id: {})", file.name, file.id());
+            let result = self.blockmap_fileinfo(file).and_then(|fileinfo| {
+                let relative_path = layout.map(&file.name)?;
+                self.save_file_to_path(source, fileinfo, target_filepath, relative_path, &file.name)
+            });
+            if let Some(entry) = self.skip_failed(report, &file.name, result)? {
+                report.files.push(entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pass on the outcome of extracting `package_path`
+    ///
+    /// With [`ExtractOptions::continue_on_error`] a failure is recorded in
+    /// `report` and yields `None`, otherwise it ends the extraction.
+    pub(crate) fn skip_failed<E>(&self, report: &mut ExtractionReport, package_path: &str, result: Result<E, Error>) -> Result<Option<E>, Error> {
+        match result {
+            Ok(entry) => Ok(Some(entry)),
+            Err(err) if self.extract_options.continue_on_error => {
+                log::error!("Failed to extract {package_path}, continuing: {err}");
+                report.failures.push(ExtractionFailure::new(package_path, &err));
+                Ok(None)
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Blockmap files extracting to the same local file on case-insensitive filesystems
+    pub fn collisions(&self) -> Vec<Collision> {
+        layout::find_collisions(self.blockmap.files.iter().map(|file| file.name.as_str()), self.extract_options.flat, self.extract_options.names)
+    }
+
+    /// Footer of a blockmap file, along with the hashes the blockmap lists for it
+    fn blockmap_fileinfo(&self, file: &blockmap::File) -> Result<FileInfo, Error> {
+        let mut fileinfo: FileInfo = self.find_footer_for_file(file.id())
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?
+            .into();
+
+        fileinfo.filehash = file.filehash_bytes()?;
+        fileinfo.block_hashes = Some(file.block_hashes()?);
+        fileinfo.filehash_algorithm = self.blockmap.filehash_algorithm(file);
+        fileinfo.block_hash_algorithm = self.blockmap.block_hash_algorithm(file);
+        fileinfo.block_sizes = file.block_sizes();
+        fileinfo.params = self.params;
+
+        Ok(fileinfo)
+    }
+
+    /// Verify all blockmap files against their block- and file hashes
+    pub fn verify<P: PackageSource + ?Sized>(
+        &self,
+        source: &P
+    ) -> VerificationReport {
+        log::info!("Verifying blockmap files...");
+        VerificationReport {
+            files: self.blockmap.files.iter()
+                .map(|file| self.verify_blockmap_file(source, file))
+                .collect(),
+            publisher_mismatch: self.check_publisher(source),
+            skipped_footprints: self.skipped_footprints(),
+        }
+    }
+
+    /// Compare the manifest publisher against the publisher-id in the header
+    fn check_publisher<P: PackageSource + ?Sized>(&self, source: &P) -> Option<PublisherMismatch> {
+        let publisher = match self.manifest(source) {
+            Ok(manifest) => manifest.get_publisher(),
+            Err(err) => {
+                log::debug!("Skipping publisher check, manifest not readable: {err}");
+                return None;
+            },
+        };
+        let found = self.header.publisher_id();
+        if utils::pfn_matches_publisher(&found, &publisher) {
+            return None;
+        }
+
+        let expected = utils::generate_publisher_id(&publisher);
+        log::warn!("Manifest publisher {publisher:?} hashes to {expected}, package full name has {found}");
+        Some(PublisherMismatch { publisher, expected, found })
+    }
+
+    /// [`EAppxFile::verify`] spread across threads, all reading from `source`
+    ///
+    /// Files are verified as a whole, a single large file still takes one thread.
+    #[cfg(feature = "rayon")]
+    pub fn verify_parallel<P: PackageSource + ?Sized>(&self, source: &P) -> VerificationReport {
+        use rayon::prelude::*;
+
+        log::info!("Verifying blockmap files on {} threads...", rayon::current_num_threads());
+        VerificationReport {
+            files: self.blockmap.files.par_iter()
+                .map(|file| self.verify_blockmap_file(source, file))
+                .collect(),
+            publisher_mismatch: self.check_publisher(source),
+            skipped_footprints: self.skipped_footprints(),
+        }
+    }
+
+    /// Check the packages embedded in a bundle, without reading their payloads
+    ///
+    /// Every package of the bundle manifest has its size compared against its
+    /// footer and is verified against a bundle blockmap entry of the same name,
+    /// if there is one. The package is then opened on its own, so a footer
+    /// table beyond its end or a blockmap not matching its header hash fails
+    /// it, packages stored encrypted are decrypted into memory for that.
+    /// Packages without a bundle manifest yield an empty report.
+    pub fn verify_bundle_packages<P: PackageSource + ?Sized>(&self, source: &P) -> Result<BundleVerificationReport, Error> {
+        let Some(bundle_manifest) = self.bundle_manifest(source)? else {
+            return Ok(BundleVerificationReport::default());
+        };
+        log::info!("Verifying bundle packages...");
+        Ok(BundleVerificationReport {
+            packages: bundle_manifest.packages.package.iter()
+                .enumerate()
+                .map(|(file_id, package)| self.verify_bundle_package(source, file_id as u64, package))
+                .collect(),
+        })
+    }
+
+    fn verify_bundle_package<P: PackageSource + ?Sized>(&self, source: &P, file_id: u64, package: &manifest::Package) -> BundlePackageReport {
+        let mut report = BundlePackageReport {
+            name: package.filename.clone(),
+            file_id,
+            manifest_size: package.size,
+            footer_size: None,
+            blocks: None,
+            error: None,
+        };
+
+        let Some(footer) = self.find_footer_for_file(file_id) else {
+            report.error = Some("no footer".into());
+            return report;
+        };
+        report.footer_size = Some(footer.uncompressed_length);
+        report.blocks = self.blockmap.files.iter()
+            .find(|file| file.name.eq_ignore_ascii_case(&package.filename))
+            .map(|file| self.verify_blockmap_file(source, file));
+
+        report.error = if package.offset != footer.offset_to_file {
+            Some(format!("offset mismatch (manifest: {:#x}, footer: {:#x})", package.offset, footer.offset_to_file))
+        } else if footer.compression_type != 0 {
+            Some("not stored uncompressed".into())
+        } else if self.is_footer_encrypted(footer) {
+            self.read_encrypted_payload(source, footer, &package.filename)
+                .and_then(|plaintext| blockmap_index::PackageIndex::from_source_with_limits(plaintext.as_slice(), self.limits))
+                .err()
+                .map(|err| err.to_string())
+        } else {
+            let inner = source.slice(footer.offset_to_file, footer.uncompressed_length);
+            blockmap_index::PackageIndex::from_source_with_limits(&inner, self.limits).err().map(|err| err.to_string())
+        };
+        report
+    }
+
+    fn verify_blockmap_file<P: PackageSource + ?Sized>(&self, source: &P, file: &blockmap::File) -> FileReport {
+        let mut file_report = FileReport::new(&file.name, file.id());
+
+        let Some(footer) = self.find_footer_for_file(file.id()) else {
+            log::warn!("Failed to find footer for file {} (id: {})", file.name, file.id());
+            file_report.status = FileStatus::MissingFooter;
+            return file_report;
+        };
+
+        // Malformed hashes match nothing, they fail their block or the file instead of the run
+        let mut file_footer: FileInfo = footer.into();
+        file_footer.filehash = file.filehash_bytes().unwrap_or_else(|err| {
+            log::warn!("{err}");
+            Some(vec![])
+        });
+        file_footer.block_hashes = Some(file.blocks.iter()
+            .enumerate()
+            .map(|(index, block)| block.hash_bytes().unwrap_or_else(|err| {
+                log::warn!("{err} for block {index} of {}", file.name);
+                vec![]
+            }))
+            .collect());
+        file_footer.filehash_algorithm = self.blockmap.filehash_algorithm(file);
+        file_footer.block_hash_algorithm = self.blockmap.block_hash_algorithm(file);
+        file_footer.block_sizes = file.block_sizes();
+        file_footer.params = self.params;
+
+        log::info!("* File: {} (encrypted={}, compressed={} id: {}) size: {}",
+            file.name, self.is_file_encrypted(file), file_footer.compression_type, file.id(), utils::get_filesize_with_unit(file.size));
+
+        if file.size != file_footer.uncompressed_length {
+            log::warn!("BlockMap vs. Footer size mismatch (blockmap: {}, footer: {})", file.size, file_footer.uncompressed_length);
+            file_report.size_mismatch = Some(SizeMismatch {
+                blockmap: file.size,
+                footer: file_footer.uncompressed_length,
+            });
+            file_report.status = FileStatus::Corrupt;
+        }
+
+        let key_index = file_footer.key_id_index;
+        let crypto = match self.crypto_context(key_index, &file.name) {
+            Ok(crypto) => crypto,
+            Err(err) => {
+                file_report.status = FileStatus::ReadError(err.to_string());
+                return file_report;
+            },
+        };
+        match Self::verify_file_blocks(source, file_footer, self.header.is_bundle(), crypto, &mut file_report) {
+            Ok(timings) => file_report.timings = self.collect_timings.then_some(timings),
+            Err(err) => {
+                log::warn!("Failed to read {}: {err}", file.name);
+                file_report.status = FileStatus::ReadError(err.to_string());
+            },
+        }
+        if file_report.status == FileStatus::KeyMissing {
+            file_report.missing_key = self.header.key_ids.get(key_index as usize).cloned();
+        }
+
+        file_report
+    }
+
+    /// Verify only `blocks` of the blockmap file `name` against their block hashes
+    ///
+    /// Blocks cover 64 KiB of the file each, are read on their own and checked as
+    /// stored, so no key is needed. The file hash covers the whole file and is not
+    /// checked, `filehash_matches` of the report stays `None`.
+    pub fn verify_entry<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        name: &str,
+        blocks: std::ops::Range<usize>,
+    ) -> Result<FileReport, Error> {
+        let file = self.blockmap.find_file(name)
+            .ok_or(Error::DataError(format!("File {name} not found in blockmap")))?;
+        let footer = self.find_footer_for_file(file.id())
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+        let block_hashes = file.block_hashes()?;
+        let algorithm = self.blockmap.block_hash_algorithm(file);
+        // The blockmap may list more blocks than the file size covers
+        let block_count = std::cmp::min(block_hashes.len(), self.params.block_count(file.size));
+        if blocks.start > blocks.end || blocks.end > block_count {
+            return Err(Error::DataError(format!("Blocks {blocks:?} out of range, {} has {} blocks", file.name, block_count)));
+        }
+
+        let is_encrypted = self.is_footer_encrypted(footer);
+        let is_compressed = footer.compression_type == 0x1;
+        let block_sizes = match (is_compressed, file.block_sizes()) {
+            (false, _) => None,
+            (true, Some(sizes)) if sizes.len() == block_hashes.len() => Some(sizes),
+            (true, _) => return Err(Error::DataError(format!("Blocks of {} cannot be located without their stored sizes", file.name))),
+        };
+
+        let mut report = FileReport::new(&file.name, file.id());
+        let mut decompress = flate2::Decompress::new(false);
+        let mut buf = vec![0u8; self.params.block_size()];
+        let mut stored = vec![];
+
+        for block in blocks {
+            let pos = block * self.params.block_size();
+            let plain_amount = std::cmp::min(self.params.block_size(), file.size as usize - pos);
+            let read_amount = match is_encrypted {
+                true => self.params.align_to_sector(plain_amount),
+                false => plain_amount,
+            };
+            let context = |operation| EntryContext {
+                operation,
+                name: Some(file.name.clone()),
+                file_id: Some(footer.file_id),
+                offset: footer.offset_to_file,
+                position: pos as u64,
+            };
+
+            let inflated = match &block_sizes {
+                Some(sizes) => {
+                    let offset = sizes[..block].iter().map(|size| *size as u64).sum::<u64>();
+                    if sizes[block] as usize > utils::max_deflated_size(read_amount) {
+                        return Err(Error::DataError(format!("Stored size {:#x} of block {block} of {} exceeds the block size", sizes[block], file.name)));
+                    }
+                    stored.resize(sizes[block] as usize, 0);
+                    source.read_exact_at(footer.offset_to_file + offset, &mut stored)
+                        .map_err(|e| context(Operation::Read).wrap(e.into()))?;
+                    decompress.reset(false);
+                    utils::inflate_block(&mut decompress, &stored, &mut buf[..read_amount])
+                },
+                None => {
+                    source.read_exact_at(footer.offset_to_file + pos as u64, &mut buf[..read_amount])
+                        .map_err(|e| context(Operation::Read).wrap(e.into()))?;
+                    true
+                },
+            };
+
+            if !inflated || algorithm.digest(&buf[..read_amount]) != block_hashes[block] {
+                log::warn!("Invalid block hash for block {block} of {}", file.name);
+                report.failed_blocks.push(block);
+            }
+        }
+
+        if !report.failed_blocks.is_empty() {
+            report.status = FileStatus::Corrupt;
+        }
+
+        Ok(report)
+    }
+
+    pub fn verify_blockmap_files<P: PackageSource + ?Sized>(
+        &self,
+        source: &P
+    ) -> Result<(), Error> {
+        let report = self.verify(source);
+
+        if let Some(failed) = report.failed_files().next() {
+            return Err(Error::DataError(format!("Verification failed for {}: {:?}", failed.name, failed.status)));
+        }
+
+        Ok(())
+    }
+
+    pub fn extract_blockmap_files<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path
+    ) -> Result<(), Error> {
+        self.extract_blockmap_files_into(source, target_filepath, &mut OutputLayout::new(self.extract_options), &mut ExtractionReport::default())
+    }
+
+    fn extract_blockmap_files_into<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path,
+        layout: &mut OutputLayout,
+        report: &mut ExtractionReport,
+    ) -> Result<(), Error> {
+        log::info!("Extracting blockmap files...");
+
+        // Verified files by file hash and size, later files with the same content reuse them
+        let mut extracted = HashMap::<(Vec<u8>, u64), usize>::new();
+
+        // Metadata files are extracted along with the footprint
+        for file in self.blockmap.files.iter().filter(|file| !file.is_metadata()) {
+            let result = self.blockmap_fileinfo(file).and_then(|file_footer| {
+                if file.size != file_footer.uncompressed_length {
+                    return Err(Error::DataError(format!("BlockMap vs. Footer size mismatch for {} (blockmap: {}, footer: {})",
+                        file.name, file.size, file_footer.uncompressed_length)));
+                }
+
+                log::info!("* File: {} (encrypted={}, compressed={} id: {}) size: {}",
+                    file.name, self.is_file_encrypted(file), file_footer.compression_type, file.id(), utils::get_filesize_with_unit(file.size));
+
+                let relative_path = layout.map(&file.name)?;
+                let key = match self.extract_options.duplicates {
+                    DuplicatePolicy::Extract => None,
+                    DuplicatePolicy::HardLink | DuplicatePolicy::Copy => file.filehash_bytes()?.map(|filehash| (filehash, file.size)),
+                };
+                let original = key.as_ref()
+                    .and_then(|key| extracted.get(key))
+                    .map(|&idx| &report.files[idx])
+                    // Same local file on a case-insensitive filesystem, replacing it would remove the original
+                    .filter(|original| !original.path.to_string_lossy().eq_ignore_ascii_case(&relative_path.to_string_lossy()));
+                let entry = match original {
+                    Some(original) => self.write_duplicate(original, target_filepath, relative_path, file_footer, &file.name)?,
+                    None => self.save_file_to_path(source, file_footer, target_filepath, relative_path, &file.name)?,
+                };
+                Ok((key, entry))
+            });
+            let Some((key, entry)) = self.skip_failed(report, &file.name, result)? else {
+                continue;
+            };
+            if let Some(key) = key.filter(|_| entry.status == ExtractionStatus::Verified && entry.duplicate_of.is_none()) {
+                extracted.insert(key, report.files.len());
+            }
+            report.files.push(entry);
+        }
+
+        Ok(())
+    }
+
+    /// Extract the packages of a bundle that pass [`EAppxFile::bundle_filter`]
+    pub fn extract_bundle_files<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path,
+    ) -> Result<(), Error> {
+        self.extract_bundle_files_into(source, target_filepath, &mut OutputLayout::new(self.extract_options), &mut ExtractionReport::default())
+    }
+
+    fn extract_bundle_files_into<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path,
+        layout: &mut OutputLayout,
+        report: &mut ExtractionReport,
+    ) -> Result<(), Error> {
+        let manifest = self.read_manifest(source)?;
+        let bundle_manifest = match manifest {
+            Manifest::Manifest(_) => return Err(Error::DataError("Expected bundle manifest".into())),
+            Manifest::BundleManifest(bundle_manifest) => bundle_manifest,
+        };
+
+        let packages = bundle_manifest.packages.package.into_iter()
+            .enumerate()
+            .filter(|(_, package)| {
+                let applies = self.bundle_filter.applies(package);
+                if !applies {
+                    log::info!("* Skipping bundle file {package}");
+                }
+                applies
+            })
+            .collect::<Vec<_>>();
+
+        // Headers and blockmaps only, a corrupt package fails before hours go into extracting the others
+        let mut checked = Vec::with_capacity(packages.len());
+        for (bundle_file_index, package) in packages {
+            if self.integrity != IntegrityPolicy::None {
+                let check = self.verify_bundle_package(source, bundle_file_index as u64, &package);
+                log::info!("* Checked bundle file {check}");
+                match (check.is_valid(), self.extract_options.continue_on_error) {
+                    (true, _) => {},
+                    (false, true) => {
+                        log::error!("Skipping bundle file {check}");
+                        report.failures.push(ExtractionFailure::new(&package.filename, &Error::DataError(format!("Bundle file {check}"))));
+                        continue;
+                    },
+                    (false, false) => return Err(Error::DataError(format!("Bundle file {check}, no package extracted"))),
+                }
+            }
+            checked.push((bundle_file_index, package));
+        }
+
+        for (bundle_file_index, package) in checked {
+            log::info!("* Bundle file: {} (offset={:#x}, size={:#x})", &package.filename, package.offset, package.size);
+            let result = self.find_footer_for_file(bundle_file_index as u64)
+                .ok_or(Error::DataError(format!("File {} not found in footers", package.filename)))
+                .and_then(|file_meta| {
+                    // See validation::validate_bundle for the complete cross-check
+                    if package.offset != file_meta.offset_to_file {
+                        return Err(Error::DataError(format!("Bundle Manifest vs. Footer file offset mismatch for {} (manifest: {:#x}, footer: {:#x})",
+                            package.filename, package.offset, file_meta.offset_to_file)));
+                    }
+                    if package.size != file_meta.uncompressed_length {
+                        return Err(Error::DataError(format!("Bundle Manifest vs. Footer file size mismatch for {} (manifest: {:#x}, footer: {:#x})",
+                            package.filename, package.size, file_meta.uncompressed_length)));
+                    }
+
+                    let relative_path = layout.map(&package.filename)?;
+                    self.save_file_to_path(source, file_meta, target_filepath, relative_path, &package.filename)
+                });
+            if let Some(entry) = self.skip_failed(report, &package.filename, result)? {
+                report.files.push(entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn extract<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path
+    ) -> Result<(), Error> {
+        self.extract_with_report(source, target_filepath)?;
+        Ok(())
+    }
+
+    /// Plaintext bytes of every file in the package
+    fn extraction_size(&self) -> u64 {
+        self.footers.iter().fold(0u64, |total, footer| total.saturating_add(footer.uncompressed_length))
+    }
+
+    /// Check the volume of `target_filepath` has room for the extracted files
+    ///
+    /// Follows [`ExtractOptions::on_low_disk_space`]. Unknown free space passes.
+    pub fn check_disk_space(&self, target_filepath: &Path) -> Result<(), Error> {
+        if self.extract_options.on_low_disk_space == DiskSpacePolicy::Ignore {
+            return Ok(());
+        }
+
+        let required = self.extraction_size();
+        let Some(available) = utils::available_space(target_filepath)? else {
+            return Ok(());
+        };
+        if required <= available {
+            return Ok(());
+        }
+
+        let message = format!(
+            "Extraction needs {} ({required} bytes), only {} ({available} bytes) available at {}",
+            utils::get_filesize_with_unit(required), utils::get_filesize_with_unit(available), target_filepath.display()
+        );
+        match self.extract_options.on_low_disk_space {
+            DiskSpacePolicy::Fail => Err(Error::DataError(message)),
+            _ => {
+                log::warn!("{message}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Checks ahead of a full extraction, the report lists collisions found
+    fn begin_extraction(&self) -> Result<ExtractionReport, Error> {
+        self.limits.check_extraction_size(self.extraction_size())?;
+        if self.extract_options.on_missing_key == MissingKeyPolicy::FailFast {
+            self.check_keys()?;
+        }
+
+        // Going on past errors, these files end up as failures of their own
+        let missing = self.resolve().missing_footers.iter().map(|file| file.name.as_str()).collect::<Vec<_>>();
+        if !missing.is_empty() && !self.extract_options.continue_on_error {
+            return Err(Error::DataError(format!("Failed to find footers for files {}", missing.join(", "))));
+        }
+
+        let report = ExtractionReport { collisions: self.collisions(), ..Default::default() };
+        for collision in &report.collisions {
+            log::warn!("Entries collide on case-insensitive filesystems: {collision}");
+        }
+        if !self.extract_options.flat && self.extract_options.on_collision == CollisionPolicy::Error && !report.collisions.is_empty() {
+            let collisions = report.collisions.iter().map(Collision::to_string).collect::<Vec<_>>();
+            return Err(Error::DataError(format!("Entries collide on case-insensitive filesystems: {}", collisions.join(", "))));
+        }
+
+        Ok(report)
+    }
+
+    /// Extract everything, returning a record of each produced file
+    pub fn extract_with_report<P: PackageSource + ?Sized>(
+        &self,
+        source: &P,
+        target_filepath: &Path
+    ) -> Result<ExtractionReport, Error> {
+        let mut report = self.begin_extraction()?;
+        self.check_disk_space(target_filepath)?;
+
+        // Footprint files always land in the root, blockmap and bundle files share one layout
+        let mut layout = OutputLayout::new(self.extract_options);
+        self.extract_footprint_files_into(source, target_filepath, &mut report, &|_| true)?;
+        self.extract_blockmap_files_into(source, target_filepath, &mut layout, &mut report)?;
+        match self.header.magic {
+            EAppxMagic::EXBH => self.extract_bundle_files_into(source, target_filepath, &mut layout, &mut report)?,
+            EAppxMagic::EXPH => {},
+            EAppxMagic::EXSH => {
+                // No magic to tell us, let the manifest decide whether packages are embedded
+                if let Manifest::BundleManifest(_) = self.manifest(source)? {
+                    log::info!("EXSH package carries a bundle manifest, extracting bundle files");
+                    self.extract_bundle_files_into(source, target_filepath, &mut layout, &mut report)?;
+                }
+            },
+        }
+
+        Ok(report)
+    }
+}
+
+/// Files listed as largest in the summary
+#[cfg(feature = "package")]
+const SUMMARY_LARGEST_FILES: usize = 5;
+
+#[cfg(feature = "package")]
+impl EAppxFile {
+    /// Display with every footer listed, unlike the summary of `Display`
+    pub fn detailed(&self) -> DetailedDisplay<'_> {
+        DetailedDisplay(self)
+    }
+}
+
+/// Summary: header, counts and sizes per compression type and the largest files
+#[cfg(feature = "package")]
+impl std::fmt::Display for EAppxFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.header)?;
+
+        let encrypted = self.footers.iter().filter(|footer| self.is_footer_encrypted(footer)).count();
+        writeln!(f, "Footers: {} (encrypted: {encrypted})", self.footers.len())?;
+
+        // Count, uncompressed and stored size per compression type
+        let mut by_compression = std::collections::BTreeMap::<u16, (usize, u64, u64)>::new();
+        for footer in &self.footers {
+            let entry = by_compression.entry(footer.compression_type).or_default();
+            entry.0 += 1;
+            entry.1 = entry.1.saturating_add(footer.uncompressed_length);
+            entry.2 = entry.2.saturating_add(self.stored_length(footer));
+        }
+        let (size, stored) = by_compression.values()
+            .fold((0u64, 0u64), |(size, stored), entry| (size.saturating_add(entry.1), stored.saturating_add(entry.2)));
+        writeln!(f, "Size: {} ({size:#x}), stored: {} ({stored:#x})", utils::get_filesize_with_unit(size), utils::get_filesize_with_unit(stored))?;
+        for (compression_type, (count, size, stored)) in &by_compression {
+            let compression = match compression_type {
+                0 => "uncompressed".to_owned(),
+                1 => "deflate".to_owned(),
+                other => format!("{other:#x}"),
+            };
+            writeln!(f, "  {compression}: {count} files, size: {size:#x}, stored: {stored:#x}")?;
+        }
+
+        let mut largest = self.footers.iter().collect::<Vec<_>>();
+        largest.sort_by_key(|footer| std::cmp::Reverse(footer.uncompressed_length));
+        writeln!(f, "Largest files:")?;
+        for footer in largest.into_iter().take(SUMMARY_LARGEST_FILES) {
+            let name = self.blockmap.files.iter()
+                .find(|file| file.id() == footer.file_id)
+                .map_or("-", |file| file.name.as_str());
+            writeln!(f, "  {:#06x} {name}: {}", footer.file_id, utils::get_filesize_with_unit(footer.uncompressed_length))?;
         }
 
         writeln!(f, "* Code Integrity: {}", self.header.is_code_integrity_protected())?;
@@ -696,18 +1814,1028 @@ impl std::fmt::Display for EAppxFile {
     }
 }
 
-#[cfg(test)]
+/// Header and every footer of an [`EAppxFile`], see [`EAppxFile::detailed`]
+#[cfg(feature = "package")]
+pub struct DetailedDisplay<'a>(&'a EAppxFile);
+
+#[cfg(feature = "package")]
+impl std::fmt::Display for DetailedDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.header)?;
+
+        for (footer_idx, footer) in self.0.footers.iter().enumerate() {
+            writeln!(f, "{footer_idx:#04x}: {footer}")?;
+        }
+
+        writeln!(f, "* Code Integrity: {}", self.0.header.is_code_integrity_protected())?;
+        writeln!(f, "* Signed: {}", self.0.header.is_signed())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "package"))]
 mod tests {
-    use std::io::Cursor;
+    use std::io::{Cursor, Read};
+
+    use sha2::{Digest, Sha256};
 
-    use crate::EAppxFile;
+    use crate::{blockmap::AppxBlockMap, error::{Error, Operation}, extraction_report::{ExtractionReport, ExtractionStatus}, layout::{CollisionPolicy, MissingKeyPolicy, OverwritePolicy}, limits::ResourceLimits, verify::{FileStatus, IntegrityPolicy}, EAppxFile, FileInfo, Manifest};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
 
     #[test]
     #[should_panic(expected = "parsing field 'magic'")]
     pub fn parse_invalid_header() {
         let data = [0u8; 0x1000];
-        let mut reader = Cursor::new(&data);
 
-        EAppxFile::from_stream(&mut reader).unwrap();
+        EAppxFile::from_source(data.as_slice()).unwrap();
+    }
+
+    #[test]
+    pub fn parse_header_versions() {
+        let mut data = EMSIX.to_vec();
+
+        // Unknown minor version is still parsed
+        data[6..14].copy_from_slice(&0x0001_0002_0000_0000u64.to_le_bytes());
+        let eappx = EAppxFile::from_source(&data).unwrap();
+        assert_eq!(eappx.header.header_version().to_string(), "1.2.0.0");
+
+        // Unknown major version is rejected
+        data[6..14].copy_from_slice(&0x0002_0000_0000_0000u64.to_le_bytes());
+        let err = EAppxFile::from_source(&data).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedVersion(0x0002_0000_0000_0000)));
+    }
+
+    #[test]
+    pub fn parse_footer_table() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let table = eappx.header.footer_offset as usize;
+
+        let mut data = EMSIX.to_vec();
+        data[table] ^= 0xFF;
+        let err = EAppxFile::from_source(&data).unwrap_err();
+        assert!(err.to_string().contains("Footer 0 has magic"), "{err}");
+
+        // Entries of a newer format carry extra fields, move the table to the end with larger entries
+        let mut data = EMSIX.to_vec();
+        let offset = data.len() as u64;
+        for footer in &eappx.footers {
+            let mut entry = footer.to_bytes().unwrap();
+            entry[2..4].copy_from_slice(&0x30u16.to_le_bytes());
+            entry.extend_from_slice(&[0xAA; 8]);
+            data.extend_from_slice(&entry);
+        }
+        data[0x0E..0x16].copy_from_slice(&offset.to_le_bytes());
+        data[0x16..0x1E].copy_from_slice(&(eappx.footers.len() as u64 * 0x30).to_le_bytes());
+        let extended = EAppxFile::from_source(&data).unwrap();
+        assert_eq!(extended.footers.len(), eappx.footers.len());
+        assert!(extended.footers.iter().zip(&eappx.footers).all(|(a, b)| a.file_id == b.file_id && a.offset_to_file == b.offset_to_file));
+
+        // Table too short for the header file count
+        data[0x16..0x1E].copy_from_slice(&(eappx.footers.len() as u64 * 0x30 - 1).to_le_bytes());
+        assert!(EAppxFile::from_source(&data).is_err());
+    }
+
+    #[test]
+    pub fn header_strings_invalid_utf16() {
+        let mut data = EMSIX.to_vec();
+        let eappx = EAppxFile::from_source(&data).unwrap();
+        let pfn: Vec<u8> = eappx.header.package_full_name_raw().iter().flat_map(|unit| unit.to_le_bytes()).collect();
+        let offset = data.windows(pfn.len()).position(|window| window == pfn).unwrap();
+
+        // Lone surrogate in place of the first character
+        data[offset..offset + 2].copy_from_slice(&0xD800u16.to_le_bytes());
+        let eappx = EAppxFile::from_source(&data).unwrap();
+        assert_eq!(eappx.header.package_full_name_raw()[0], 0xD800);
+        assert!(eappx.header.package_full_name().starts_with("\u{FFFD}b18b0ca-"));
+        assert_eq!(eappx.header.crypto_algo(), "XTS-AES");
+    }
+
+    #[test]
+    pub fn peek_header_only() {
+        // Footer table cut off, the header alone still reads
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let truncated = &EMSIX[..eappx.header.footer_offset as usize];
+        let header = EAppxFile::peek_header(&truncated).unwrap();
+        assert_eq!(header.package_full_name(), eappx.header.package_full_name());
+        assert_eq!(header.key_ids, eappx.header.key_ids);
+        assert!(EAppxFile::from_header(&truncated, header, ResourceLimits::default()).is_err());
+
+        let header = EAppxFile::peek_header(EMSIX).unwrap();
+        let loaded = EAppxFile::from_header(EMSIX, header, ResourceLimits::default()).unwrap();
+        assert_eq!(loaded.blockmap, eappx.blockmap);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    pub fn serde_roundtrip() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+
+        let json = serde_json::to_value(&eappx.header).unwrap();
+        assert_eq!(json["crypto_algo"], "XTS-AES");
+        assert_eq!(json["magic"], "EXPH");
+        let header: crate::EAppxHeader = serde_json::from_value(json).unwrap();
+        assert_eq!(header, eappx.header);
+
+        let footers: Vec<crate::EAppxFooter> = serde_json::from_str(&serde_json::to_string(&eappx.footers).unwrap()).unwrap();
+        assert_eq!(footers, eappx.footers);
+
+        let blockmap: AppxBlockMap = serde_json::from_str(&serde_json::to_string(&eappx.blockmap).unwrap()).unwrap();
+        assert_eq!(blockmap, eappx.blockmap);
+        // The flag used to be serialized as the attribute text
+        let legacy = serde_json::to_string(&eappx.blockmap).unwrap().replace(r#""encrypted":true"#, r#""encrypted":"true""#);
+        assert_eq!(serde_json::from_str::<AppxBlockMap>(&legacy).unwrap(), eappx.blockmap);
+
+        let Manifest::Manifest(manifest) = eappx.read_manifest(EMSIX).unwrap() else { panic!("Expected package manifest") };
+        let json = serde_json::to_value(&manifest).unwrap();
+        let parsed: crate::manifest::AppxManifest = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+    }
+
+    #[test]
+    pub fn parse_exsh_package() {
+        let mut data = EMSIX.to_vec();
+        data[..4].copy_from_slice(b"EXSH");
+
+        let eappx = EAppxFile::from_source(&data).unwrap();
+        assert!(eappx.header.is_exsh());
+        assert!(!eappx.header.is_bundle());
+        assert!(matches!(eappx.read_manifest(&data).unwrap(), Manifest::Manifest(_)));
+        assert!(eappx.verify(&data).is_valid());
+    }
+
+    #[test]
+    pub fn reject_out_of_bounds() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let footer_pos = eappx.header.footer_offset as usize;
+
+        // Footer table cut off
+        let err = EAppxFile::from_source(&EMSIX[..footer_pos + 0x10]).unwrap_err();
+        assert!(matches!(err, Error::OutOfBounds { ref what, .. } if what == "Footer table"));
+
+        // File payload pointing past the end
+        let mut data = EMSIX.to_vec();
+        data[footer_pos + 0x10..footer_pos + 0x18].copy_from_slice(&(EMSIX.len() as u64).to_le_bytes());
+        let err = EAppxFile::from_source(&data).unwrap_err();
+        assert!(matches!(err, Error::OutOfBounds { offset, .. } if offset == EMSIX.len() as u64));
+
+        // Terabytes of uncompressed data declared
+        let mut data = EMSIX.to_vec();
+        data[footer_pos + 0x18..footer_pos + 0x20].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let err = EAppxFile::from_source(&data).unwrap_err();
+        assert!(matches!(err, Error::ImplausibleLength { uncompressed_length, .. } if uncompressed_length == 1 << 40));
+
+        // Footer entry smaller than the known format
+        let mut data = EMSIX.to_vec();
+        data[footer_pos + 0x28 + 2..footer_pos + 0x28 + 4].copy_from_slice(&0x20u16.to_le_bytes());
+        let err = EAppxFile::from_source(&data).unwrap_err();
+        assert!(matches!(err, Error::DecodeError(ref message) if message.starts_with("Footer 1 has size 0x20")));
+    }
+
+    #[test]
+    pub fn extract_flat() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        eappx.extract_options.flat = true;
+
+        let outdir = std::env::temp_dir().join("eappx_test_extract_flat");
+        let _ = std::fs::remove_dir_all(&outdir);
+        eappx.extract(EMSIX, &outdir).unwrap();
+
+        assert!(outdir.join("StoreLogo.png").is_file());
+        assert!(!outdir.join("Assets").exists());
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn extract_collisions() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        assert!(eappx.collisions().is_empty());
+
+        // Both unencrypted, the tweak of an encrypted file would change along with its name
+        let splash = eappx.blockmap.files.iter().position(|file| file.name == "Assets\\SplashScreen.scale-200.png").unwrap();
+        eappx.blockmap.files[splash].name = "assets\\SQUARE150x150Logo.scale-200.png".into();
+        assert_eq!(eappx.collisions().len(), 1);
+
+        let outdir = std::env::temp_dir().join("eappx_test_extract_collisions");
+        let _ = std::fs::remove_dir_all(&outdir);
+        eappx.extract_options.on_collision = CollisionPolicy::Error;
+        assert!(matches!(eappx.extract_with_report(EMSIX, &outdir), Err(Error::DataError(_))));
+        assert!(!outdir.exists());
+
+        eappx.extract_options.on_collision = CollisionPolicy::Rename;
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.collisions[0].package_paths, ["assets\\SQUARE150x150Logo.scale-200.png", "Assets\\Square150x150Logo.scale-200.png"]);
+        assert!(outdir.join("Assets").join("SQUARE150x150Logo.scale-200.png").is_file());
+        assert!(outdir.join("Assets").join("Square150x150Logo.scale-200_1.png").is_file());
+
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn extract_duplicates() {
+        use crate::{keys::{TEST_KEY, TEST_KEY_ID}, layout::DuplicatePolicy};
+
+        let logo = vec![0x5Au8; 0x10100];
+        let files = [("AppxManifest.xml", b"<Package/>".to_vec()), ("logo.png", logo.clone()), ("Assets\\logo.png", logo.clone()),
+            ("other.png", vec![0xA5; 0x10100])];
+        let package = crate::memory::pack("TestApp_1.0.0.0_x64__nh20k94c8ngfj", vec![(TEST_KEY_ID, TEST_KEY.to_vec())], files).unwrap();
+        let mut eappx = EAppxFile::from_source(&package).unwrap();
+        eappx.keys.insert(TEST_KEY_ID, TEST_KEY.to_vec());
+
+        let outdir = std::env::temp_dir().join(format!("eappx_test_extract_duplicates_{}", std::process::id()));
+        for duplicates in [DuplicatePolicy::HardLink, DuplicatePolicy::Copy] {
+            eappx.extract_options.duplicates = duplicates;
+            // Twice, links replace the files of the first run
+            for _ in 0..2 {
+                let report = eappx.extract_with_report(&package, &outdir).unwrap();
+                assert!(report.is_valid());
+                let duplicates = report.duplicates().collect::<Vec<_>>();
+                assert_eq!(duplicates.len(), 1);
+                assert_eq!((duplicates[0].package_path.as_str(), duplicates[0].duplicate_of.as_deref()),
+                    ("Assets\\logo.png", Some(std::path::Path::new("logo.png"))));
+                assert_eq!(duplicates[0].status, ExtractionStatus::Verified);
+                assert_eq!(std::fs::read(outdir.join("Assets").join("logo.png")).unwrap(), logo);
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                let links = std::fs::metadata(outdir.join("logo.png")).unwrap().nlink();
+                assert_eq!(links, if duplicates == DuplicatePolicy::HardLink { 2 } else { 1 });
+            }
+            std::fs::remove_dir_all(&outdir).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn extract_overwrite_policies() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let outdir = std::env::temp_dir().join("eappx_test_extract_overwrite");
+        let _ = std::fs::remove_dir_all(&outdir);
+        eappx.extract(EMSIX, &outdir).unwrap();
+        let logo = outdir.join("Assets").join("StoreLogo.png");
+        let original = std::fs::read(&logo).unwrap();
+
+        eappx.extract_options.overwrite = OverwritePolicy::ErrorIfExists;
+        assert!(matches!(eappx.extract(EMSIX, &outdir), Err(Error::IoError(_))));
+
+        // Interrupted extraction: one file missing, one truncated
+        std::fs::write(&logo, b"garbage").unwrap();
+        eappx.extract_options.overwrite = OverwritePolicy::Skip;
+        eappx.extract(EMSIX, &outdir).unwrap();
+        assert_eq!(std::fs::read(&logo).unwrap(), b"garbage");
+
+        std::fs::remove_file(outdir.join("AppxManifest.xml")).unwrap();
+        eappx.extract_options.overwrite = OverwritePolicy::SkipIfHashMatches;
+        eappx.extract(EMSIX, &outdir).unwrap();
+        assert_eq!(std::fs::read(&logo).unwrap(), original);
+        assert!(outdir.join("AppxManifest.xml").is_file());
+
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn extract_report() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let outdir = std::env::temp_dir().join("eappx_test_extract_report");
+        let _ = std::fs::remove_dir_all(&outdir);
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        assert!(report.is_valid());
+        // Blockmap files plus blockmap and code integrity catalog
+        assert_eq!(report.files.len(), eappx.blockmap.files.len() + 2);
+
+        let dll = report.files.iter().find(|f| f.package_path == "TestApp.dll").unwrap();
+        assert_eq!(dll.status, ExtractionStatus::Verified);
+        assert_eq!(dll.sha256, eappx.blockmap.files.iter().find(|f| f.name == "TestApp.dll").unwrap().filehash_bytes().unwrap());
+        assert_eq!(report.files.iter().find(|f| f.package_path == "CodeIntegrity.cat").unwrap().status, ExtractionStatus::Unverified);
+
+        // Kept files are reported as such
+        eappx.extract_options.overwrite = OverwritePolicy::Skip;
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        assert!(report.files.iter().all(|f| f.status == ExtractionStatus::Kept));
+        assert_eq!(report.written_size(), 0);
+
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn extract_bundle_metadata() {
+        let mut eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        eappx.extract_options.flat = true;
+
+        let outdir = std::env::temp_dir().join("eappx_test_extract_bundle_metadata");
+        let _ = std::fs::remove_dir_all(&outdir);
+        let mut report = ExtractionReport::default();
+        eappx.extract_footprint_files_into(EMSIXBUNDLE, &outdir, &mut report, &|_| true).unwrap();
+
+        let manifest = report.files.iter().find(|f| f.package_path == "AppxMetadata\\AppxBundleManifest.xml").unwrap();
+        assert_eq!(manifest.status, ExtractionStatus::Verified);
+        assert!(outdir.join("AppxMetadata").join("AppxBundleManifest.xml").is_file());
+        assert!(matches!(eappx.read_manifest(EMSIXBUNDLE).unwrap(), Manifest::BundleManifest(_)));
+
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn extract_footprint_only() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let outdir = std::env::temp_dir().join(format!("eappx_test_extract_footprint_only_{}", std::process::id()));
+        let report = eappx.extract_footprint_files_with(EMSIX, &outdir, |_| true).unwrap();
+        let mut names: Vec<_> = report.files.iter().map(|f| f.package_path.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["AppxBlockMap.xml", "AppxManifest.xml", "CodeIntegrity.cat"]);
+        assert!(report.is_valid());
+        assert_eq!(report.files.iter().find(|f| f.package_path == "AppxManifest.xml").unwrap().status, ExtractionStatus::Verified);
+        assert!(outdir.join("AppxManifest.xml").is_file());
+        assert!(!outdir.join("TestApp.dll").exists());
+        std::fs::remove_dir_all(&outdir).unwrap();
+
+        let report = eappx.extract_footprint_files_with(EMSIX, &outdir, |name| name.ends_with(".cat")).unwrap();
+        assert_eq!(report.files.len(), 1);
+        assert!(outdir.join("CodeIntegrity.cat").is_file());
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn load_keys_checks_lengths() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let key_id = eappx.header.key_ids[0].clone();
+
+        let mut keys = crate::sample::sample_keys();
+        keys.keys.insert(key_id.clone(), vec![0; 0x10]);
+        let err = eappx.load_keys(&keys).unwrap_err();
+        assert!(err.to_string().contains("is 0x10 bytes, expected 0x20"), "{err}");
+        assert!(eappx.keys.is_empty());
+
+        eappx.header.key_length = 0x40;
+        let err = eappx.load_keys(&crate::sample::sample_keys()).unwrap_err();
+        assert!(err.to_string().ends_with("is 0x20 bytes, the header declares 0x40"), "{err}");
+
+        // Footer pointing past the key-ids of the header
+        eappx.header.key_length = 0x20;
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let dll = eappx.blockmap.find_file("TestApp.dll").unwrap().clone();
+        eappx.footers.iter_mut().find(|footer| footer.file_id == dll.id()).unwrap().key_id_index = 7;
+        let err = eappx.file_reader(EMSIX, &dll).err().unwrap();
+        assert!(err.to_string().ends_with("Key index 7 is out of range, the header lists 1 key-id(s)"), "{err}");
+        let report = eappx.verify(EMSIX);
+        assert!(matches!(&report.failed_files().next().unwrap().status, FileStatus::ReadError(message) if message.contains("Key index 7")));
+    }
+
+    #[test]
+    pub fn extract_missing_key_policies() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let outdir = std::env::temp_dir().join(format!("eappx_test_missing_key_{}", std::process::id()));
+
+        // Nothing is written when a key is missing
+        let err = eappx.extract_with_report(EMSIX, &outdir).unwrap_err();
+        assert!(err.to_string().contains("TestApp.dll"), "{err}");
+        assert_eq!(err.kind(), crate::error::ErrorKind::MissingKey);
+        assert!(!outdir.exists());
+
+        eappx.extract_options.on_missing_key = MissingKeyPolicy::SkipEncryptedWithoutKey;
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        let skipped = report.missing_key().map(|f| f.package_path.as_str()).collect::<Vec<_>>();
+        assert_eq!(skipped.len(), 4);
+        assert!(skipped.iter().all(|name| !outdir.join(name.replace('\\', "/")).exists()));
+        assert_eq!(report.files.iter().find(|f| f.package_path == "resources.pri").unwrap().status, ExtractionStatus::Verified);
+        std::fs::remove_dir_all(&outdir).unwrap();
+
+        // Block hashes cover the ciphertext as written
+        eappx.extract_options.on_missing_key = MissingKeyPolicy::ExtractCiphertext;
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        let dll = report.files.iter().find(|f| f.package_path == "TestApp.dll").unwrap();
+        assert_eq!(dll.status, ExtractionStatus::Ciphertext);
+        let ciphertext = std::fs::read(outdir.join("TestApp.dll")).unwrap();
+        assert_eq!(ciphertext.len() % 0x200, 0);
+        let block_hashes = eappx.blockmap.find_file("TestApp.dll").unwrap().block_hashes().unwrap();
+        for (block, hash) in ciphertext.chunks(0x10000).zip(&block_hashes) {
+            assert_eq!(&Sha256::digest(block).to_vec(), hash);
+        }
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn read_raw_payload() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+
+        // Stored uncompressed, the ciphertext is covered by the block hashes
+        let dll = eappx.blockmap.find_file("TestApp.dll").unwrap();
+        let (footer, mut raw) = eappx.raw_reader(EMSIX, dll).unwrap();
+        let (offset, compression_type) = (footer.offset_to_file, footer.compression_type);
+        let mut data = vec![];
+        raw.read_to_end(&mut data).unwrap();
+        assert_eq!(compression_type, 0);
+        assert_eq!(&data[..], &EMSIX[offset as usize..offset as usize + data.len()]);
+        assert_eq!(Sha256::digest(&data[..0x10000]).to_vec(), dll.block_hashes().unwrap()[0]);
+
+        // Deflated manifest inflates to the plaintext
+        let manifest = eappx.blockmap.find_file("AppxManifest.xml").unwrap();
+        let (footer, raw) = eappx.raw_reader(EMSIX, manifest).unwrap();
+        assert_eq!(footer.compression_type, 1);
+        let mut plain = vec![];
+        flate2::read::DeflateDecoder::new(raw).read_to_end(&mut plain).unwrap();
+        assert!(plain.starts_with(b"<?xml"));
+        assert_eq!(plain.len() as u64, manifest.size);
+    }
+
+    #[test]
+    pub fn read_bundle_manifest() {
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let packages = &eappx.bundle_manifest(EMSIXBUNDLE).unwrap().unwrap().packages.package;
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].arch.as_deref(), Some("x64"));
+        assert_eq!(packages[0].languages(), ["EN-US"]);
+        assert_eq!(packages[0].scales(), ["200"]);
+        assert!(!packages[0].is_resource());
+
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        assert!(eappx.bundle_manifest(EMSIX).unwrap().is_none());
+    }
+
+    #[test]
+    pub fn manifest_is_cached() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let Manifest::Manifest(manifest) = eappx.manifest(EMSIX).unwrap() else { panic!("Expected package manifest") };
+        assert_eq!(manifest.identity.version, crate::identity::PackageVersion::new(1, 0, 3, 0));
+
+        // Served without reading the stream again
+        let first = eappx.manifest(EMSIX).unwrap() as *const Manifest;
+        let again = eappx.manifest(&vec![0u8; EMSIX.len()]).unwrap() as *const Manifest;
+        assert_eq!(first, again);
+
+        // Located by name, not by position in the blockmap
+        let manifest_file = eappx.blockmap.files.iter().position(|file| file.name == "AppxManifest.xml").unwrap();
+        let manifest_file = eappx.blockmap.files.remove(manifest_file);
+        eappx.blockmap.files.push(manifest_file);
+        eappx.manifest = Default::default();
+        assert!(matches!(eappx.manifest(EMSIX).unwrap(), Manifest::Manifest(_)));
+    }
+
+    #[test]
+    pub fn verify_bundle_packages() {
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let report = eappx.verify_bundle_packages(EMSIXBUNDLE).unwrap();
+        assert_eq!(report.packages.len(), 1);
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.packages[0].footer_size, Some(report.packages[0].manifest_size));
+        assert!(EAppxFile::from_source(EMSIX).unwrap().verify_bundle_packages(EMSIX).unwrap().packages.is_empty());
+
+        // Flip a byte of the inner blockmap hash, the package no longer opens cleanly
+        let package = &report.packages[0];
+        let offset = eappx.find_footer_for_file(package.file_id).unwrap().offset_to_file as usize;
+        let inner = EAppxFile::peek_header(&EMSIXBUNDLE[offset..]).unwrap();
+        let hash = offset + EMSIXBUNDLE[offset..].windows(inner.block_map_hash.len()).position(|w| w == inner.block_map_hash).unwrap();
+        let mut data = EMSIXBUNDLE.to_vec();
+        data[hash] ^= 0xFF;
+        let report = eappx.verify_bundle_packages(&data).unwrap();
+        assert!(!report.is_valid());
+        assert!(report.to_string().contains("does not match the header hash"));
+
+        let outdir = std::env::temp_dir().join("eappx_test_verify_bundle_packages");
+        let err = eappx.extract_with_report(&data, &outdir).unwrap_err();
+        assert!(err.to_string().contains("no package extracted"));
+        assert!(!outdir.join(&package.name).exists());
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    /// The test bundle with its package stored encrypted, padded to whole sectors with the later payloads moved behind it
+    ///
+    /// Returns the bundle and the package as it decrypts.
+    fn bundle_with_encrypted_package() -> (Vec<u8>, Vec<u8>) {
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let (filename, footer) = eappx.bundle_payloads(EMSIXBUNDLE).unwrap().remove(0);
+        let plain = footer.offset_to_file as usize..(footer.offset_to_file + footer.compressed_length) as usize;
+
+        let key = crate::sample::sample_keys().keys[&eappx.header.key_ids[footer.key_id_index as usize]].clone();
+        let context = crate::crypto::CryptoFileContext {
+            cipher: crate::crypto::create_cipher(key.as_slice().try_into().unwrap()),
+            tweak: crate::crypto::get_tweak_for_file(&eappx.header.app_name(), &eappx.header.publisher_id(), &filename),
+            params: eappx.params,
+        };
+        let mut payload = EMSIXBUNDLE[plain.clone()].to_vec();
+        let shift = (crate::utils::align_to_sector(payload.len()) - plain.len()) as u64;
+        let moved = |offset: u64| if offset >= plain.end as u64 { offset + shift } else { offset };
+
+        // The package locates its catalog behind it by bundle offset
+        let mut inner = EAppxFile::peek_header(&payload).unwrap();
+        inner.code_integrity_offset = moved(inner.code_integrity_offset);
+        payload[..inner.header_size as usize].copy_from_slice(&inner.to_bytes().unwrap());
+        let package = payload.clone();
+        payload.resize(crate::utils::align_to_sector(payload.len()), 0);
+        context.encrypt_at(&mut payload, 0);
+        let mut header = eappx.header.clone();
+        header.footer_offset = moved(header.footer_offset);
+        header.signature_offset = moved(header.signature_offset);
+        header.code_integrity_offset = moved(header.code_integrity_offset);
+
+        let mut data = [&EMSIXBUNDLE[..plain.start], &payload, &EMSIXBUNDLE[plain.end..]].concat();
+        data[..header.header_size as usize].copy_from_slice(&header.to_bytes().unwrap());
+        for (index, footer) in eappx.footers.iter().enumerate() {
+            let footer = crate::EAppxFooter { offset_to_file: moved(footer.offset_to_file), ..footer.clone() };
+            let position = header.footer_offset as usize + index * crate::EAppxFooter::SIZE as usize;
+            data[position..position + crate::EAppxFooter::SIZE as usize].copy_from_slice(&footer.to_bytes().unwrap());
+        }
+        (data, package)
+    }
+
+    #[test]
+    pub fn bundle_package_encryption() {
+        // Inner packages in the clear keep their key index
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let (_, footer) = eappx.bundle_payloads(EMSIXBUNDLE).unwrap().remove(0);
+        assert_ne!(footer.key_id_index, 0xFFFF);
+        assert!(!eappx.is_footer_encrypted(footer));
+        assert_eq!(eappx.stored_length(footer), footer.compressed_length);
+        assert!(!EAppxFile::is_stored_encrypted(EMSIXBUNDLE, &footer.into(), true).unwrap());
+
+        let (data, package) = bundle_with_encrypted_package();
+        let mut eappx = EAppxFile::from_source(&data).unwrap();
+        let (filename, footer) = eappx.bundle_payloads(&data).unwrap().remove(0);
+        assert!(eappx.is_footer_encrypted(footer));
+        assert_eq!(eappx.stored_length(footer), crate::utils::align_to_sector(package.len()) as u64);
+        assert!(EAppxFile::is_stored_encrypted(&data, &footer.into(), true).unwrap());
+        let report = eappx.verify_bundle_packages(&data).unwrap();
+        assert!(report.to_string().contains("Missing key"), "{report}");
+
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        assert!(eappx.verify_bundle_packages(&data).unwrap().is_valid());
+        let mut plain_bundle = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        plain_bundle.load_keys(&crate::sample::sample_keys()).unwrap();
+        let converted = eappx.convert_to_msix(&data, vec![]).unwrap();
+        assert_eq!(converted, plain_bundle.convert_to_msix(EMSIXBUNDLE, vec![]).unwrap());
+
+        let outdir = std::env::temp_dir().join(format!("eappx_test_bundle_package_encryption_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&outdir);
+        eappx.extract(&data, &outdir).unwrap();
+        assert_eq!(std::fs::read(outdir.join(&filename)).unwrap(), package);
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn extract_bundle_filtered() {
+        let mut eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        eappx.bundle_filter.architectures = vec!["arm64".into()];
+
+        let outdir = std::env::temp_dir().join("eappx_test_extract_bundle_filtered");
+        let _ = std::fs::remove_dir_all(&outdir);
+        let report = eappx.extract_with_report(EMSIXBUNDLE, &outdir).unwrap();
+        assert!(!outdir.join("TestApp_1.0.3.0_x64.msix").exists());
+        assert!(report.files.iter().all(|f| f.package_path != "TestApp_1.0.3.0_x64.msix"));
+
+        eappx.bundle_filter.architectures = vec!["x64".into()];
+        eappx.extract(EMSIXBUNDLE, &outdir).unwrap();
+        assert!(outdir.join("TestApp_1.0.3.0_x64.msix").is_file());
+
+        std::fs::remove_dir_all(&outdir).unwrap();
+    }
+
+    #[test]
+    pub fn read_footprint_data() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+
+        let blockmap = eappx.read_raw_blockmap(EMSIX).unwrap();
+        let parsed = AppxBlockMap::from_xml(&blockmap).unwrap();
+        assert_eq!(parsed, eappx.blockmap);
+        assert_eq!(parsed.to_xml().as_bytes(), blockmap);
+
+        assert_eq!(eappx.read_signature(EMSIX).unwrap(), None);
+        let catalog = eappx.read_code_integrity(EMSIX).unwrap().unwrap();
+        assert_eq!(catalog.len(), 0x2fc);
+    }
+
+    #[test]
+    pub fn verify_package() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+
+        let report = eappx.verify(EMSIX);
+        assert!(report.is_valid(), "{report}");
+        assert_eq!(report.files.len(), eappx.blockmap.files.len());
+        assert!(report.unverified_files().all(|file| file.filehash_matches.is_none()));
+        assert!(report.unverified_files().count() > 0);
+
+        // Each unverified file names the key it needs
+        assert_eq!(eappx.missing_keys(), eappx.header.key_ids);
+        assert!(report.unverified_files().all(|file| file.missing_key.as_ref() == Some(&eappx.header.key_ids[0])));
+        assert!(report.to_string().contains(&format!(", needs key {}", eappx.header.key_ids[0].to_keyfile_string())));
+        assert_eq!(report.timings(), None);
+    }
+
+    #[test]
+    pub fn verify_timings() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.collect_timings = true;
+
+        // Everything stored is read once, sector padding included, every block hashed as stored
+        let report = eappx.verify(EMSIX);
+        assert!(report.files.iter().all(|file| file.timings.is_some()));
+        let timings = report.timings().unwrap();
+        let stored = eappx.footers.iter()
+            .filter(|footer| eappx.blockmap.files.iter().any(|file| file.id() == footer.file_id))
+            .map(|footer| match footer.is_encrypted(false) && footer.compression_type != 0x1 {
+                true => eappx.params.align_to_sector(footer.compressed_length as usize) as u64,
+                false => footer.compressed_length,
+            })
+            .sum::<u64>();
+        assert_eq!(timings.read.bytes, stored);
+        assert!(timings.hash.bytes >= timings.read.bytes);
+        assert_eq!(timings.write.bytes, 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn verify_publisher() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        assert_eq!(eappx.verify(EMSIX).publisher_mismatch, None);
+
+        let pfn = eappx.header.package_full_name().replace(&eappx.header.publisher_id(), "8wekyb3d8bbwe");
+        eappx.header.package_full_name = pfn.encode_utf16().collect();
+        let report = eappx.verify(EMSIX);
+        let mismatch = report.publisher_mismatch.as_ref().unwrap();
+        assert_eq!((mismatch.expected.as_str(), mismatch.found.as_str()), ("nh20k94c8ngfj", "8wekyb3d8bbwe"));
+        assert!(!report.is_valid());
+        assert!(report.failed_files().next().is_none());
+    }
+
+    #[test]
+    pub fn verify_package_with_keys() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let report = eappx.verify(EMSIX);
+        assert!(report.files.iter().all(|file| file.status == FileStatus::Valid), "{report}");
+        assert_eq!(report.unverified_files().count(), 0);
+        // Every file carries a file hash, encrypted ones are checked on their plaintext
+        assert!(report.files.iter().all(|file| file.filehash_matches == Some(true)));
+
+        // Tweaks derive from the canonical path, whatever the separators
+        for file in eappx.blockmap.files.iter_mut() {
+            file.name = file.name.replace('\\', "/");
+        }
+        let report = eappx.verify(EMSIX);
+        assert!(report.files.iter().all(|file| file.status == FileStatus::Valid), "{report}");
+    }
+
+    #[test]
+    pub fn decrypt_region_of_file() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let (file, footer) = eappx.blockmap.files.iter()
+            .filter_map(|file| Some((file, eappx.find_footer_for_file(file.id())?)))
+            .find(|(_, footer)| footer.is_encrypted(false) && footer.compression_type == 0 && footer.uncompressed_length > 0x400)
+            .unwrap();
+        let key = eappx.get_cipher_for_key_index(footer.key_id_index).unwrap().unwrap();
+        let pfn = format!("{}_{}", eappx.header.app_name(), eappx.header.publisher_id());
+
+        let start = footer.offset_to_file as usize;
+        let mut whole = EMSIX[start..start + footer.stored_length(false) as usize].to_vec();
+        crate::crypto::decrypt_region(&key, &pfn, &file.name, 0, &mut whole).unwrap();
+        let mut plaintext = vec![];
+        eappx.file_reader(EMSIX, file).unwrap().read_to_end(&mut plaintext).unwrap();
+        assert_eq!(&whole[..plaintext.len()], plaintext);
+
+        // A sector carved from the middle decrypts on its own
+        let mut sector = EMSIX[start + 0x200..start + 0x400].to_vec();
+        crate::crypto::decrypt_region(&key, &pfn, &file.name, 0x200, &mut sector).unwrap();
+        assert_eq!(sector, whole[0x200..0x400]);
+
+        // So does a sector cut short on an AES block
+        let mut short = EMSIX[start + 0x200..start + 0x300].to_vec();
+        crate::crypto::decrypt_region(&key, &pfn, &file.name, 0x200, &mut short).unwrap();
+        assert_eq!(short, whole[0x200..0x300]);
+
+        assert!(crate::crypto::decrypt_region(&key, &pfn, &file.name, 0x100, &mut sector).is_err());
+        assert!(crate::crypto::decrypt_region(&key, &pfn, &file.name, 0x200, &mut sector[..0x8]).is_err());
+
+        // Same tweak when looked up with different case and separators
+        let context = eappx.crypto_context_for(&file.name.to_lowercase().replace('\\', "/")).unwrap();
+        let mut sector = EMSIX[start + 0x200..start + 0x400].to_vec();
+        context.decrypt_area(&mut sector, 1);
+        assert_eq!(sector, whole[0x200..0x400]);
+        assert!(eappx.crypto_context_for("missing.bin").is_err());
+        assert!(eappx.crypto_context_for("AppxManifest.xml").is_err_and(|e| e.to_string().ends_with("not encrypted")));
+    }
+
+    #[test]
+    pub fn display_summary() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let summary = eappx.to_string();
+        assert!(summary.contains(&format!("Footers: {} (encrypted: ", eappx.footers.len())));
+        assert!(summary.contains("Largest files:\n  0x000a TestApp.dll: "));
+        assert!(!summary.contains("EAppxFooter"));
+
+        let detailed = eappx.detailed().to_string();
+        assert_eq!(detailed.matches("EAppxFooter").count(), eappx.footers.len());
+    }
+
+    #[test]
+    pub fn read_file_without_padding() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let (file, footer) = eappx.blockmap.files.iter()
+            .filter_map(|file| Some((file, eappx.find_footer_for_file(file.id())?)))
+            .find(|(_, footer)| footer.is_encrypted(false) && footer.compression_type == 0 && footer.uncompressed_length > 0x400)
+            .unwrap();
+        let key = eappx.get_cipher_for_key_index(footer.key_id_index).unwrap().unwrap();
+        let pfn = format!("{}_{}", eappx.header.app_name(), eappx.header.publisher_id());
+        let ctx = || crate::crypto::CryptoFileContext::new(&key, &pfn, &file.name);
+        let mut plaintext = vec![];
+        eappx.file_reader(EMSIX, file).unwrap().read_to_end(&mut plaintext).unwrap();
+
+        for len in [0x10, 0x1F, 0x200, 0x210, 0x3FF, 0x411, plaintext.len()] {
+            let fileinfo = FileInfo {
+                key_id_index: footer.key_id_index,
+                compression_type: 0,
+                offset_to_file: 0,
+                uncompressed_length: len as u64,
+                compressed_length: len as u64,
+                filehash: Some(Sha256::digest(&plaintext[..len]).to_vec()),
+                block_hashes: None,
+                filehash_algorithm: Default::default(),
+                block_hash_algorithm: Default::default(),
+                block_sizes: None,
+                file_id: None,
+                params: Default::default(),
+            };
+
+            // Encrypted without padding, the last sector uses ciphertext stealing
+            let mut stored = plaintext[..len].to_vec();
+            ctx().encrypt_area(&mut stored, 0);
+            let mut out = vec![];
+            EAppxFile::read_file(&stored, &mut out, fileinfo, false, Some(ctx()), IntegrityPolicy::Full).unwrap();
+            assert_eq!(out, plaintext[..len], "{len:#x}");
+        }
+
+        // Padding cut on an AES block does not change the plaintext
+        let start = footer.offset_to_file as usize;
+        let len = plaintext.len().div_ceil(crate::crypto::AES_BLOCK_SIZE) * crate::crypto::AES_BLOCK_SIZE;
+        let fileinfo = FileInfo { offset_to_file: 0, ..FileInfo::from(footer) };
+        let mut out = vec![];
+        EAppxFile::read_file(&EMSIX[start..start + len], &mut out, fileinfo.clone(), false, Some(ctx()), IntegrityPolicy::None).unwrap();
+        assert_eq!(out, plaintext);
+
+        let err = EAppxFile::read_file(&EMSIX[start..start + plaintext.len() - 1], &mut vec![], fileinfo, false, Some(ctx()), IntegrityPolicy::None).unwrap_err();
+        assert_eq!(err.entry().map(|context| context.operation), Some(Operation::Read));
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    pub fn verify_package_parallel() {
+        let mut data = EMSIX.to_vec();
+        let mut eappx = EAppxFile::from_source(&data).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let footer = eappx.find_footer_for_file(0xa).unwrap();
+        data[footer.offset_to_file as usize + 0x10010] ^= 0xFF;
+
+        let report = eappx.verify_parallel(&data);
+        assert_eq!(report, eappx.verify(&data));
+        assert_eq!(report.failed_files().map(|file| file.name.as_str()).collect::<Vec<_>>(), ["TestApp.dll"]);
+
+        // Nothing to read, e.g. a truncated download
+        let report = eappx.verify_parallel(&data[..0]);
+        assert!(report.files.iter().all(|file| matches!(file.status, FileStatus::ReadError(_))));
+    }
+
+    #[test]
+    pub fn verify_corrupted_package() {
+        let mut data = EMSIX.to_vec();
+        let eappx = EAppxFile::from_source(&data).unwrap();
+
+        // Flip a byte in the second block of TestApp.dll
+        let footer = eappx.find_footer_for_file(0xa).unwrap();
+        data[footer.offset_to_file as usize + 0x10010] ^= 0xFF;
+
+        let report = eappx.verify(&data);
+        assert!(!report.is_valid());
+
+        let failed = report.failed_files().collect::<Vec<_>>();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].name, "TestApp.dll");
+        assert_eq!(failed[0].status, FileStatus::Corrupt);
+        assert_eq!(failed[0].failed_blocks, vec![1]);
+    }
+
+    #[test]
+    pub fn verify_boundary_sizes() {
+        use crate::{keys::{TEST_KEY, TEST_KEY_ID}, verify::FileReport, writer::{EntryOptions, PackageWriter, WriterOptions}, EAppxMagic};
+
+        let sizes = [0x1, 0x10, 0x1F, 0x200, 0x201, 0x211, 0x10000, 0x10010, 0x101FF];
+        let data = |len: usize| (0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, "TestApp_1.0.0.0_x64__nh20k94c8ngfj",
+            vec![(TEST_KEY_ID, TEST_KEY.to_vec())], WriterOptions::default()).unwrap();
+        writer.add_entry("AppxManifest.xml", &mut b"<Package/>".as_slice(), EntryOptions { compress: true, key_index: None }).unwrap();
+        for size in sizes {
+            writer.add_entry(&format!("{size:x}.bin"), &mut data(size).as_slice(), EntryOptions { compress: false, key_index: Some(0) }).unwrap();
+        }
+        let package = writer.finish().unwrap().into_inner();
+
+        let mut eappx = EAppxFile::from_source(&package).unwrap();
+        eappx.keys.insert(TEST_KEY_ID, TEST_KEY.to_vec());
+        assert!(eappx.verify(&package).is_valid());
+
+        for size in sizes {
+            let file = eappx.blockmap.find_file(&format!("{size:x}.bin")).unwrap();
+            let fileinfo = eappx.blockmap_fileinfo(file).unwrap();
+            let verify = |data: &[u8]| {
+                let crypto = eappx.crypto_context(fileinfo.key_id_index, &file.name).unwrap();
+                let mut report = FileReport::new(&file.name, file.id());
+                EAppxFile::verify_file_blocks(&data, fileinfo.clone(), false, crypto, &mut report).map(|_| report)
+            };
+
+            // Data following the payload is not read
+            let end = (fileinfo.offset_to_file + fileinfo.params.align_to_sector(size) as u64) as usize;
+            let mut followed = package[..end].to_vec();
+            followed.extend_from_slice(&[0xA5; 0x400]);
+            let report = verify(&followed).unwrap();
+            assert!(report.failed_blocks.is_empty() && report.filehash_matches == Some(true), "{size:#x}");
+
+            // Data ending on the AES block after the plaintext fails the last block, the file hash still matches
+            let start = fileinfo.offset_to_file as usize;
+            let cut = size.next_multiple_of(0x10);
+            let report = verify(&package[..start + cut]).unwrap();
+            match cut % 0x200 {
+                0 => assert!(report.is_valid(), "{size:#x}"),
+                _ => {
+                    assert_eq!(report.failed_blocks, [fileinfo.params.block_count(size as u64) - 1], "{size:#x}");
+                    assert_eq!(report.filehash_matches, Some(true), "{size:#x}");
+                },
+            }
+            // Less than an AES block of the last sector cannot be decrypted
+            if size % 0x200 != 0 && size % 0x200 < 0x10 {
+                assert!(verify(&package[..start + size]).is_err(), "{size:#x}");
+            }
+        }
+    }
+
+    #[test]
+    pub fn verify_malformed_hash() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let (index, _) = eappx.blockmap.find("TestApp.dll").unwrap();
+        eappx.blockmap.files[index].blocks[2].hash = "not base64!".into();
+
+        let err = eappx.blockmap.files[index].block_hashes().unwrap_err();
+        assert!(err.to_string().contains("block 2 of TestApp.dll"), "{err}");
+
+        // The rest of the file and package is still verified
+        let report = eappx.verify(EMSIX);
+        let failed = report.failed_files().collect::<Vec<_>>();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].failed_blocks, vec![2]);
+        assert!(eappx.verify_entry(EMSIX, "TestApp.dll", 0..2).is_err());
+    }
+
+    #[test]
+    pub fn verify_entry_blocks() {
+        let mut data = EMSIX.to_vec();
+        let eappx = EAppxFile::from_source(&data).unwrap();
+
+        // Flip a byte in the fourth block of TestApp.dll
+        let footer = eappx.find_footer_for_file(0xa).unwrap();
+        data[footer.offset_to_file as usize + 0x30010] ^= 0xFF;
+
+        let report = eappx.verify_entry(&data, "TestApp.dll", 0..3).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.filehash_matches, None);
+
+        let report = eappx.verify_entry(&data, "testapp.dll", 2..17).unwrap();
+        assert_eq!(report.status, FileStatus::Corrupt);
+        assert_eq!(report.failed_blocks, vec![3]);
+
+        // Compressed, blocks are inflated on their own
+        assert!(eappx.verify_entry(&data, "resources.pri", 0..1).unwrap().is_valid());
+
+        assert!(eappx.verify_entry(&data, "TestApp.dll", 16..18).is_err());
+        assert!(eappx.verify_entry(&data, "Missing.dll", 0..1).is_err());
+    }
+
+    #[test]
+    pub fn verify_entry_crafted_blockmap() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+
+        // More blocks listed than the file size covers
+        let dll = eappx.blockmap.files.iter_mut().find(|file| file.name == "TestApp.dll").unwrap();
+        let count = dll.blocks.len();
+        dll.blocks.push(dll.blocks[0].clone());
+        let err = eappx.verify_entry(EMSIX, "TestApp.dll", count..count + 1).unwrap_err();
+        assert!(err.to_string().contains(&format!("has {count} blocks")), "{err}");
+        assert!(eappx.verify_entry(EMSIX, "TestApp.dll", 0..1).unwrap().is_valid());
+
+        // Stored size no deflated block can have
+        let pri = eappx.blockmap.files.iter_mut().find(|file| file.name == "resources.pri").unwrap();
+        pri.blocks[0].size = Some(u32::MAX);
+        let err = eappx.verify_entry(EMSIX, "resources.pri", 0..1).unwrap_err();
+        assert!(err.to_string().contains("exceeds the block size"), "{err}");
+    }
+
+    #[test]
+    pub fn extract_error_context() {
+        let mut data = EMSIX.to_vec();
+        let mut eappx = EAppxFile::from_source(&data).unwrap();
+        let dll = eappx.blockmap.files.iter().find(|file| file.name == "TestApp.dll").unwrap().clone();
+
+        let err = eappx.file_reader(&data, &dll).err().unwrap();
+        assert_eq!(err.entry().unwrap().operation, Operation::Decrypt);
+        assert!(err.to_string().starts_with("Failed to decrypt TestApp.dll (id 0xa)"), "{err}");
+
+        let offset = eappx.find_footer_for_file(0xa).unwrap().offset_to_file;
+        data[offset as usize + 0x10010] ^= 0xFF;
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let outdir = std::env::temp_dir().join(format!("eappx-error-context-{}", std::process::id()));
+        let err = eappx.extract_blockmap_files(&data, &outdir).unwrap_err();
+        std::fs::remove_dir_all(&outdir).unwrap();
+        let context = err.entry().unwrap();
+        assert_eq!((context.operation, context.name.as_deref(), context.file_id), (Operation::Verify, Some("TestApp.dll"), Some(0xa)));
+        assert_eq!(context.offset, offset);
+        // Block hashes cover the encrypted data and catch the flipped byte in its block
+        assert_eq!(context.position, 0x10000);
+        assert!(err.to_string().ends_with("Invalid block hash for block 1"), "{err}");
+    }
+
+    #[test]
+    pub fn extract_continue_on_error() {
+        let mut data = EMSIX.to_vec();
+        let mut eappx = EAppxFile::from_source(&data).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let offset = eappx.find_footer_for_file(0xa).unwrap().offset_to_file;
+        data[offset as usize + 0x10010] ^= 0xFF;
+
+        let outdir = std::env::temp_dir().join(format!("eappx_test_extract_continue_on_error_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&outdir);
+        assert!(eappx.extract_with_report(&data, &outdir).is_err());
+
+        eappx.extract_options = eappx.extract_options.continue_on_error(true);
+        let report = eappx.extract_with_report(&data, &outdir).unwrap();
+        std::fs::remove_dir_all(&outdir).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.failures.len(), 1);
+        let failure = &report.failures[0];
+        assert_eq!(failure.package_path, "TestApp.dll");
+        assert_eq!(failure.context.as_ref().map(|context| (context.operation, context.position)), Some((Operation::Verify, 0x10000)));
+        assert!(failure.to_string().ends_with("Invalid block hash for block 1"), "{failure}");
+        // Everything else is extracted and intact
+        assert_eq!(report.files.len(), eappx.blockmap.files.len() + eappx.footprint_entries().len() - 1);
+        assert!(report.files.iter().all(|file| file.status == ExtractionStatus::Verified || file.status == ExtractionStatus::Unverified));
+
+        let mut sink = std::collections::BTreeMap::new();
+        let report = eappx.extract_to_sink(&data, &mut sink).unwrap();
+        assert_eq!(report.failures.iter().map(|failure| failure.package_path.as_str()).collect::<Vec<_>>(), ["TestApp.dll"]);
+        assert!(sink.contains_key("AppxManifest.xml"));
+    }
+
+    #[test]
+    pub fn extract_size_mismatch() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let footer = eappx.footers.iter_mut().find(|footer| footer.file_id == 0xa).unwrap();
+        footer.uncompressed_length += 1;
+
+        let outdir = std::env::temp_dir().join(format!("eappx_test_extract_size_mismatch_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&outdir);
+        let err = eappx.extract_with_report(EMSIX, &outdir).unwrap_err();
+        assert!(err.to_string().contains("BlockMap vs. Footer size mismatch for TestApp.dll"), "{err}");
+
+        eappx.extract_options = eappx.extract_options.continue_on_error(true);
+        let report = eappx.extract_with_report(EMSIX, &outdir).unwrap();
+        let extracted = outdir.join("TestApp.exe").exists();
+        std::fs::remove_dir_all(&outdir).unwrap();
+        assert_eq!(report.failures.iter().map(|failure| failure.package_path.as_str()).collect::<Vec<_>>(), ["TestApp.dll"]);
+        assert!(report.failures[0].message.contains("blockmap: "), "{}", report.failures[0].message);
+        assert_eq!(report.files.len(), eappx.blockmap.files.len() + eappx.footprint_entries().len() - 1);
+        assert!(extracted);
+    }
+
+    #[test]
+    pub fn extract_integrity_policies() {
+        let mut data = EMSIX.to_vec();
+        let mut eappx = EAppxFile::from_source(&data).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let offset = eappx.find_footer_for_file(0xa).unwrap().offset_to_file;
+        data[offset as usize + 0x10010] ^= 0xFF;
+
+        let outdir = std::env::temp_dir().join(format!("eappx-integrity-{}", std::process::id()));
+        let mut extract = |integrity| {
+            eappx.integrity = integrity;
+            let result = eappx.extract_blockmap_files(&data, &outdir);
+            std::fs::remove_dir_all(&outdir).unwrap();
+            result.err().map(|err| err.to_string())
+        };
+
+        assert_eq!(extract(IntegrityPolicy::None), None);
+        assert!(extract(IntegrityPolicy::FileHashes).unwrap().ends_with("Hash mismatch for file"));
+        assert!(extract(IntegrityPolicy::BlockHashes).unwrap().ends_with("Invalid block hash for block 1"));
+        assert!(extract(IntegrityPolicy::Full).unwrap().ends_with("Invalid block hash for block 1"));
+    }
+}
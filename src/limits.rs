@@ -0,0 +1,159 @@
+//! Resource limits for the read paths
+//!
+//! Sizes and counts come straight from the package, a hostile one can declare
+//! terabytes of uncompressed data or millions of files. Reads are checked
+//! against these limits before anything gets allocated or written.
+
+use crate::error::Error;
+
+/// Start from a preset and adjust single limits, e.g. `ResourceLimits::untrusted().max_file_count(0x1000)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ResourceLimits {
+    /// Largest file read into memory (blockmap, manifest, signature, ...)
+    pub max_in_memory_size: u64,
+    /// Total amount of bytes a single extraction may write
+    pub max_extraction_size: u64,
+    /// Most files (footers or blockmap entries) a package may declare
+    pub max_file_count: u64,
+    /// Most key ids a header may declare, packages use one or a handful
+    pub max_key_id_count: u64,
+    /// Largest header, including its key ids and strings
+    pub max_header_size: u64,
+}
+
+impl Default for ResourceLimits {
+    /// Generous enough for large games: the blockmap of a 200 GiB package
+    /// stays well below the in-memory limit, extraction size is unbounded.
+    fn default() -> Self {
+        Self {
+            max_in_memory_size: 512 * 1024 * 1024,
+            max_extraction_size: u64::MAX,
+            max_file_count: 1 << 20,
+            max_key_id_count: 0x100,
+            max_header_size: 0x10000,
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// No limits at all, only for trusted input
+    pub fn unlimited() -> Self {
+        Self {
+            max_in_memory_size: u64::MAX,
+            max_extraction_size: u64::MAX,
+            max_file_count: u64::MAX,
+            max_key_id_count: u64::MAX,
+            max_header_size: u64::MAX,
+        }
+    }
+
+    /// Tight enough for packages from unknown sources, see [`crate::EAppxFile::parse_untrusted`]
+    pub fn untrusted() -> Self {
+        Self {
+            max_in_memory_size: 64 * 1024 * 1024,
+            max_extraction_size: 4 * 1024 * 1024 * 1024,
+            max_file_count: 1 << 18,
+            max_key_id_count: 0x40,
+            max_header_size: 0x4000,
+        }
+    }
+
+    pub fn max_in_memory_size(mut self, max_in_memory_size: u64) -> Self {
+        self.max_in_memory_size = max_in_memory_size;
+        self
+    }
+
+    pub fn max_extraction_size(mut self, max_extraction_size: u64) -> Self {
+        self.max_extraction_size = max_extraction_size;
+        self
+    }
+
+    pub fn max_file_count(mut self, max_file_count: u64) -> Self {
+        self.max_file_count = max_file_count;
+        self
+    }
+
+    pub fn max_key_id_count(mut self, max_key_id_count: u64) -> Self {
+        self.max_key_id_count = max_key_id_count;
+        self
+    }
+
+    pub fn max_header_size(mut self, max_header_size: u64) -> Self {
+        self.max_header_size = max_header_size;
+        self
+    }
+
+    pub(crate) fn check(what: &str, value: u64, limit: u64) -> Result<(), Error> {
+        match value > limit {
+            true => Err(Error::LimitExceeded { what: what.to_owned(), value, limit }),
+            false => Ok(()),
+        }
+    }
+
+    pub fn check_in_memory_size(&self, what: &str, size: u64) -> Result<(), Error> {
+        Self::check(what, size, self.max_in_memory_size)
+    }
+
+    pub fn check_extraction_size(&self, size: u64) -> Result<(), Error> {
+        Self::check("Extraction size", size, self.max_extraction_size)
+    }
+
+    pub fn check_file_count(&self, what: &str, count: u64) -> Result<(), Error> {
+        Self::check(what, count, self.max_file_count)
+    }
+}
+
+#[cfg(all(test, feature = "package"))]
+mod tests {
+    use super::*;
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    fn open(limits: ResourceLimits) -> Result<EAppxFile, Error> {
+        EAppxFile::from_source_with_limits(EMSIX, limits)
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(open(ResourceLimits::default()).is_ok());
+
+        let err = open(ResourceLimits { max_file_count: 4, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { ref what, limit: 4, .. } if what == "Footer count"));
+
+        let err = open(ResourceLimits { max_in_memory_size: 0x100, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { limit: 0x100, .. }));
+
+        let eappx = open(ResourceLimits { max_extraction_size: 0x1000, ..Default::default() }).unwrap();
+        let outdir = std::env::temp_dir().join("eappx_test_limits");
+        let err = eappx.extract(EMSIX, &outdir).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { limit: 0x1000, .. }));
+        assert!(!outdir.exists(), "Nothing may be written once a limit is hit");
+    }
+
+    #[test]
+    fn test_header_limits() {
+        let err = open(ResourceLimits { max_key_id_count: 0, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { ref what, .. } if what == "Key id count"));
+        let err = open(ResourceLimits { max_header_size: 0x40, ..Default::default() }).unwrap_err();
+        assert!(matches!(err, Error::LimitExceeded { ref what, .. } if what == "Header size"));
+
+        let header = EAppxFile::peek_header(EMSIX).unwrap();
+        let patched = |offset: usize, value: &[u8]| {
+            let mut data = EMSIX.to_vec();
+            data[offset..offset + value.len()].copy_from_slice(value);
+            EAppxFile::from_source(&data).unwrap_err()
+        };
+
+        // Declared counts beyond what the footer table holds fail before anything is allocated
+        let err = patched(0x1E, &(1u64 << 40).to_le_bytes());
+        assert!(matches!(err, Error::LimitExceeded { ref what, .. } if what == "Footer count"));
+        let err = patched(0x1E, &(header.file_count + 1).to_le_bytes());
+        assert!(err.to_string().contains("does not fit"), "{err}");
+
+        // Fields reaching past the declared header size
+        let err = patched(0x04, &(header.header_size - 2).to_le_bytes());
+        assert!(err.to_string().contains("Header fields take"), "{err}");
+    }
+}
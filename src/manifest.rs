@@ -1,37 +1,388 @@
 use xmlserde_derives::{XmlDeserialize, XmlSerialize};
 
+use crate::{error::Error, identity::{PackageFullName, PackageVersion}, xml_tree::{Document, Element, Node}};
+
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>"#;
+
+/// Namespaces declared on manifests built from scratch
+const NAMESPACES: &[(&str, &str)] = &[
+    ("xmlns", "http://schemas.microsoft.com/appx/manifest/foundation/windows10"),
+    ("xmlns:mp", "http://schemas.microsoft.com/appx/2014/phone/manifest"),
+    ("xmlns:uap", "http://schemas.microsoft.com/appx/manifest/uap/windows10"),
+    ("xmlns:rescap", "http://schemas.microsoft.com/appx/manifest/foundation/windows10/restrictedcapabilities"),
+    ("xmlns:desktop", "http://schemas.microsoft.com/appx/manifest/desktop/windows10"),
+    ("xmlns:build", "http://schemas.microsoft.com/developer/appx/2015/build"),
+];
+
 fn default_ignorable_namespaces() -> String {
     "uap mp rescap build".into()
 }
 
-#[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
-#[xmlserde(root=b"Package")]
-#[xmlserde(with_ns = b"http://schemas.microsoft.com/appx/manifest/foundation/windows10")]
-#[xmlserde(with_custom_ns(b"mp", b"http://schemas.microsoft.com/appx/2014/phone/manifest"))]
-#[xmlserde(with_custom_ns(b"uap", b"http://schemas.microsoft.com/appx/manifest/uap/windows10"))]
-#[xmlserde(with_custom_ns(b"rescap", b"http://schemas.microsoft.com/appx/manifest/foundation/windows10/restrictedcapabilities"))]
-#[xmlserde(with_custom_ns(b"desktop", b"http://schemas.microsoft.com/appx/manifest/desktop/windows10"))]
-#[xmlserde(with_custom_ns(b"build", b"http://schemas.microsoft.com/developer/appx/2015/build"))]
+/// AppxManifest.xml
+///
+/// Only identity and capabilities are typed. The document they were read from
+/// is kept as a whole, [`AppxManifest::to_xml`] writes the typed fields back
+/// into it, so applications, extensions, comments and everything else the
+/// crate does not model survive a round trip unchanged.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AppxManifest {
-    #[xmlserde(name = b"IgnorableNamespaces", ty = "attr", default = "default_ignorable_namespaces")]
     ignorable_namespaces: String,
-
-    #[xmlserde(name = b"Identity", ty = "child")]
     pub identity: Identity,
+    pub capabilities: Option<Capabilities>,
+    /// Document as read, `None` for manifests built in code
+    #[cfg_attr(feature = "serde", serde(skip))]
+    document: Option<Document>,
+}
+
+impl AppxManifest {
+    pub fn from_xml(xml: &[u8]) -> Result<Self, Error> {
+        let document = Document::parse(xml, "AppxManifest.xml")?;
+        let root = &document.root;
+        if root.name != "Package" {
+            return Err(Error::DecodeError(format!("Unexpected manifest root element {}", root.name)));
+        }
+
+        let element = root.child("Identity")
+            .ok_or(Error::DecodeError("Manifest has no Identity".into()))?;
+        let required = |name: &str| element.attribute(name)
+            .map(str::to_owned)
+            .ok_or(Error::DecodeError(format!("Manifest Identity without {name}")));
+        let identity = Identity {
+            name: required("Name")?,
+            publisher: required("Publisher")?,
+            version: required("Version")?.parse()?,
+            arch: element.attribute("ProcessorArchitecture").map(str::to_owned),
+            resource_id: element.attribute("ResourceId").map(str::to_owned),
+        };
+
+        Ok(Self {
+            ignorable_namespaces: root.attribute("IgnorableNamespaces").map_or_else(default_ignorable_namespaces, str::to_owned),
+            identity,
+            capabilities: root.child("Capabilities").map(Capabilities::read).transpose()?,
+            document: Some(document),
+        })
+    }
+
+    /// Complete document, including the XML declaration
+    ///
+    /// Parsed manifests are written back as read, apart from the typed fields
+    /// that were changed.
+    pub fn to_xml(&self) -> String {
+        let mut document = match &self.document {
+            Some(document) => document.clone(),
+            None => {
+                let mut root = Element::new("Package");
+                for (name, namespace) in NAMESPACES {
+                    root.set_attribute(name, namespace);
+                }
+                if !self.ignorable_namespaces.is_empty() {
+                    root.set_attribute("IgnorableNamespaces", &self.ignorable_namespaces);
+                }
+                Document::new(format!("{XML_DECLARATION}\n"), root)
+            },
+        };
+        let root = &mut document.root;
+
+        if root.child("Identity").is_none() {
+            root.children.insert(0, Node::Element(Element::new("Identity")));
+        }
+        let identity = root.child_mut("Identity").unwrap();
+        identity.set_attribute("Name", &self.identity.name);
+        identity.set_attribute("Publisher", &self.identity.publisher);
+        identity.set_attribute("Version", &self.identity.version.to_string());
+        match &self.identity.arch {
+            Some(arch) => identity.set_attribute("ProcessorArchitecture", arch),
+            None => identity.remove_attribute("ProcessorArchitecture"),
+        }
+        match &self.identity.resource_id {
+            Some(resource_id) => identity.set_attribute("ResourceId", resource_id),
+            None => identity.remove_attribute("ResourceId"),
+        }
+
+        Capabilities::write(self.capabilities.as_ref(), root);
+        document.to_xml()
+    }
+
+    pub fn ignorable_namespaces(&self) -> &str {
+        &self.ignorable_namespaces
+    }
+
+    pub fn set_version(&mut self, version: PackageVersion) {
+        self.identity.version = version;
+    }
+
+    pub fn set_publisher(&mut self, publisher: &str) {
+        self.identity.publisher = publisher.to_owned();
+    }
+
+    /// Names of all declared capabilities, with their kind
+    pub fn capabilities(&self) -> Vec<(CapabilityKind, &str)> {
+        self.capabilities.iter()
+            .flat_map(|capabilities| CapabilityKind::ALL.into_iter()
+                .flat_map(move |kind| capabilities.list(kind).iter().map(move |capability| (kind, capability.name.as_str()))))
+            .collect()
+    }
+
+    /// [`AppxManifest::capabilities`] plus those of versioned namespaces
+    /// (e.g. `uap6:Capability`), which are only kept in the document
+    pub fn declared_capabilities(&self) -> Vec<(CapabilityKind, &str)> {
+        let untyped = self.document.iter()
+            .filter_map(|document| document.root.child("Capabilities"))
+            .flat_map(Element::elements)
+            .filter(|element| CapabilityKind::from_tag(&element.name).is_none())
+            .filter_map(|element| Some((CapabilityKind::from_versioned_tag(&element.name)?, element.attribute("Name")?)));
+        self.capabilities().into_iter().chain(untyped).collect()
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities().iter().any(|(_, known)| *known == name)
+    }
+
+    /// Declare a capability, nothing changes if it already is
+    pub fn add_capability(&mut self, kind: CapabilityKind, name: &str) {
+        let list = self.capabilities.get_or_insert_with(Default::default).list_mut(kind);
+        if !list.iter().any(|capability| capability.name == name) {
+            list.push(Capability { name: name.to_owned() });
+        }
+    }
+
+    /// Remove a capability of any kind, returns whether it was declared
+    pub fn remove_capability(&mut self, name: &str) -> bool {
+        let Some(capabilities) = self.capabilities.as_mut() else {
+            return false;
+        };
+
+        let mut removed = false;
+        for kind in CapabilityKind::ALL {
+            let list = capabilities.list_mut(kind);
+            let before = list.len();
+            list.retain(|capability| capability.name != name);
+            removed |= list.len() != before;
+        }
+        removed
+    }
+
+    /// Whether `Properties` mark this as a resource package, only carrying assets
+    pub fn is_resource_package(&self) -> bool {
+        self.document.as_ref()
+            .and_then(|document| document.root.child("Properties"))
+            .and_then(|properties| properties.child("ResourcePackage"))
+            .is_some_and(|element| element.text().trim() == "true")
+    }
+
+    /// Applications the package declares, in document order
+    ///
+    /// Read from the document, manifests built in code declare none.
+    pub fn applications(&self) -> Vec<Application> {
+        self.document.iter()
+            .filter_map(|document| document.root.child("Applications"))
+            .flat_map(Element::elements)
+            .filter(|element| element.name == "Application")
+            .map(Application::read)
+            .collect()
+    }
+}
+
+/// Reference: <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-application>
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Application {
+    pub id: String,
+    /// Path of the executable within the package, e.g. `TestApp.exe`
+    pub executable: Option<String>,
+    /// Activatable class or `Windows.FullTrustApplication`
+    pub entry_point: Option<String>,
+    /// Start page of JavaScript apps
+    pub start_page: Option<String>,
+    /// From `uap:VisualElements`, may be an `ms-resource:` reference
+    pub display_name: Option<String>,
+}
+
+impl Application {
+    fn read(element: &Element) -> Self {
+        let attribute = |name: &str| element.attribute(name).map(str::to_owned);
+        Self {
+            id: attribute("Id").unwrap_or_default(),
+            executable: attribute("Executable"),
+            entry_point: attribute("EntryPoint"),
+            start_page: attribute("StartPage"),
+            display_name: element.elements()
+                .find(|child| child.name == "VisualElements" || child.name.ends_with(":VisualElements"))
+                .and_then(|visual| visual.attribute("DisplayName"))
+                .map(str::to_owned),
+        }
+    }
+}
+
+impl std::fmt::Display for Application {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let details = [("executable", &self.executable), ("entry-point", &self.entry_point), ("start-page", &self.start_page), ("display-name", &self.display_name)]
+            .into_iter()
+            .filter_map(|(name, value)| value.as_ref().map(|value| format!("{name}={value}")))
+            .collect::<Vec<_>>();
+        match details.is_empty() {
+            true => write!(f, "{}", self.id),
+            false => write!(f, "{} ({})", self.id, details.join(", ")),
+        }
+    }
+}
+
+/// Namespace a capability is declared in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CapabilityKind {
+    /// `Capability`, general-use capabilities of the foundation namespace
+    General,
+    /// `uap:Capability`
+    Uap,
+    /// `rescap:Capability`, restricted capabilities
+    Restricted,
+    /// `DeviceCapability`
+    Device,
+}
+
+impl CapabilityKind {
+    /// In the order the schema requires them
+    pub const ALL: [Self; 4] = [Self::General, Self::Uap, Self::Restricted, Self::Device];
+
+    /// Element name as written in manifests
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Self::General => "Capability",
+            Self::Uap => "uap:Capability",
+            Self::Restricted => "rescap:Capability",
+            Self::Device => "DeviceCapability",
+        }
+    }
+
+    fn from_tag(tag: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.tag() == tag)
+    }
+
+    /// Kind of `uapN:Capability` and other capabilities of later schema versions
+    fn from_versioned_tag(tag: &str) -> Option<Self> {
+        let (prefix, name) = tag.split_once(':')?;
+        match name {
+            "Capability" if prefix.starts_with("uap") => Some(Self::Uap),
+            "Capability" if prefix.starts_with("rescap") => Some(Self::Restricted),
+            "DeviceCapability" => Some(Self::Device),
+            _ => None,
+        }
+    }
+}
+
+/// Reference: <https://learn.microsoft.com/en-us/uwp/schemas/appxpackage/uapmanifestschema/element-capabilities>
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    pub capability: Vec<Capability>,
+    pub uap_capability: Vec<Capability>,
+    pub restricted_capability: Vec<Capability>,
+    pub device_capability: Vec<Capability>,
+}
+
+impl Capabilities {
+    fn list(&self, kind: CapabilityKind) -> &Vec<Capability> {
+        match kind {
+            CapabilityKind::General => &self.capability,
+            CapabilityKind::Uap => &self.uap_capability,
+            CapabilityKind::Restricted => &self.restricted_capability,
+            CapabilityKind::Device => &self.device_capability,
+        }
+    }
+
+    fn list_mut(&mut self, kind: CapabilityKind) -> &mut Vec<Capability> {
+        match kind {
+            CapabilityKind::General => &mut self.capability,
+            CapabilityKind::Uap => &mut self.uap_capability,
+            CapabilityKind::Restricted => &mut self.restricted_capability,
+            CapabilityKind::Device => &mut self.device_capability,
+        }
+    }
+
+    /// Typed capabilities of a `Capabilities` element, others (e.g. `uap6:Capability`) are left in the document
+    fn read(element: &Element) -> Result<Self, Error> {
+        let mut capabilities = Self::default();
+        for child in element.elements() {
+            if let Some(kind) = CapabilityKind::from_tag(&child.name) {
+                let name = child.attribute("Name")
+                    .ok_or(Error::DecodeError(format!("Manifest {} without Name", child.name)))?;
+                capabilities.list_mut(kind).push(Capability { name: name.to_owned() });
+            }
+        }
+        Ok(capabilities)
+    }
+
+    /// Bring the typed capability elements below `root` in line with `capabilities`
+    ///
+    /// Only what changed is touched: removed capabilities are dropped, new ones
+    /// go behind the last capability of their group (device capabilities last,
+    /// as the schema requires).
+    fn write(capabilities: Option<&Self>, root: &mut Element) {
+        let wanted = capabilities.cloned().unwrap_or_default();
+        let current = match root.child("Capabilities") {
+            Some(element) => Self::read(element).unwrap_or_default(),
+            None if wanted == Self::default() => return,
+            None => {
+                // Behind the applications, before extensions
+                let position = root.position("Extensions").unwrap_or(root.children.len());
+                root.children.insert(position, Node::Element(Element::new("Capabilities")));
+                Self::default()
+            },
+        };
+        if current == wanted {
+            return;
+        }
+
+        let element = root.child_mut("Capabilities").unwrap();
+        element.children.retain(|node| match node {
+            Node::Element(child) => match CapabilityKind::from_tag(&child.name) {
+                Some(kind) => wanted.list(kind).iter().any(|capability| Some(capability.name.as_str()) == child.attribute("Name")),
+                None => true,
+            },
+            Node::Raw(_) => true,
+        });
+
+        for kind in CapabilityKind::ALL {
+            for capability in wanted.list(kind) {
+                if current.list(kind).contains(capability) {
+                    continue;
+                }
+
+                let mut child = Element::new(kind.tag());
+                child.set_attribute("Name", &capability.name);
+                let position = match kind {
+                    CapabilityKind::Device => element.children.len(),
+                    _ => element.children.iter()
+                        .rposition(|node| matches!(node, Node::Element(child) if child.name == "Capability" || child.name.ends_with(":Capability")))
+                        .map_or(0, |position| position + 1),
+                };
+                element.children.insert(position, Node::Element(child));
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capability {
+    pub name: String,
 }
 
 #[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Packages {
     #[xmlserde(name = b"Package", ty = "child")]
     pub package: Vec<Package>,
 }
 
 #[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Package {
     #[xmlserde(name = b"Type", ty = "attr")]
     pub typ: String,
     #[xmlserde(name = b"Version", ty = "attr")]
-    pub version: String,
+    pub version: PackageVersion,
     #[xmlserde(name = b"ResourceId", ty = "attr")]
     pub resource_id: Option<String>,
     #[xmlserde(name = b"Architecture", ty = "attr")]
@@ -42,9 +393,77 @@ pub struct Package {
     pub offset: u64, 
     #[xmlserde(name = b"Size", ty = "attr")]
     pub size: u64,
+    /// Languages, scales and DirectX feature levels the package applies to
+    #[xmlserde(name = b"Resources", ty = "child")]
+    pub resources: Option<Resources>,
+}
+
+impl Package {
+    /// Resource packages only carry assets, application packages the code
+    pub fn is_resource(&self) -> bool {
+        self.typ == "resource"
+    }
+
+    fn resource_values<'a>(&'a self, value: impl Fn(&'a Resource) -> Option<&'a String>) -> Vec<&'a str> {
+        self.resources.iter()
+            .flat_map(|resources| &resources.resource)
+            .filter_map(value)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Languages from `Resources`, as written in the manifest (e.g. `EN-US`)
+    pub fn languages(&self) -> Vec<&str> {
+        self.resource_values(|resource| resource.language.as_ref())
+    }
+
+    /// Scales from `Resources`, e.g. `200`
+    pub fn scales(&self) -> Vec<&str> {
+        self.resource_values(|resource| resource.scale.as_ref())
+    }
+
+    /// DirectX feature levels from `Resources`, e.g. `dx11`
+    pub fn dx_feature_levels(&self) -> Vec<&str> {
+        self.resource_values(|resource| resource.dx_feature_level.as_ref())
+    }
 }
 
+impl std::fmt::Display for Package {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (type={}, version={}, arch={}", self.filename, self.typ, self.version, self.arch.as_deref().unwrap_or("neutral"))?;
+        if let Some(resource_id) = &self.resource_id {
+            write!(f, ", resource-id={resource_id}")?;
+        }
+        for (name, values) in [("languages", self.languages()), ("scales", self.scales()), ("dx", self.dx_feature_levels())] {
+            if !values.is_empty() {
+                write!(f, ", {name}={}", values.join(","))?;
+            }
+        }
+        write!(f, ") offset={:#x} size={:#x}", self.offset, self.size)
+    }
+}
+
+#[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Resources {
+    #[xmlserde(name = b"Resource", ty = "child")]
+    pub resource: Vec<Resource>,
+}
+
+/// Reference: <https://learn.microsoft.com/en-us/uwp/schemas/bundlemanifestschema/element-resource>
 #[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Resource {
+    #[xmlserde(name = b"Language", ty = "attr")]
+    pub language: Option<String>,
+    #[xmlserde(name = b"Scale", ty = "attr")]
+    pub scale: Option<String>,
+    #[xmlserde(name = b"DXFeatureLevel", ty = "attr")]
+    pub dx_feature_level: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, XmlDeserialize, XmlSerialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Identity {
     /// Name
     #[xmlserde(name = b"Name", ty = "attr")]
@@ -54,10 +473,25 @@ pub struct Identity {
     pub publisher: String,
     /// Version
     #[xmlserde(name = b"Version", ty = "attr")]
-    pub version: String,
+    pub version: PackageVersion,
     /// Processor architecture
     #[xmlserde(name = b"ProcessorArchitecture", ty = "attr")]
     pub arch: Option<String>,
+    /// Resource id, part of the package full name
+    #[xmlserde(name = b"ResourceId", ty = "attr")]
+    pub resource_id: Option<String>,
+}
+
+impl Identity {
+    /// Package full name (`Name_Version_Architecture_ResourceId_PublisherId`)
+    pub fn package_full_name(&self) -> String {
+        self.full_name().to_string()
+    }
+
+    /// [`Identity::package_full_name`] split into its fields
+    pub fn full_name(&self) -> PackageFullName {
+        PackageFullName::new(&self.name, self.version, self.arch.as_deref(), self.resource_id.as_deref(), &self.publisher)
+    }
 }
 
 
@@ -65,34 +499,105 @@ pub struct Identity {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use xmlserde::{xml_deserialize_from_str, xml_serialize};
     const XML_DATA: &str = include_str!("../testdata/manifest.xml");
-    const XML_ENCODING: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+    const XML_DATA_FULL: &str = include_str!("../testdata/manifest_full.xml");
 
     #[test]
-    #[ignore]
     fn test_serialize() {
-        let map = AppxManifest {
+        let mut manifest = AppxManifest {
             identity: Identity {
                 name: "TestApp".into(),
                 publisher: "CN=SomeCommonName".into(),
-                version: "1.0.24.0".into(),
+                version: PackageVersion::new(1, 0, 24, 0),
                 arch: Some("x64".into()),
+                resource_id: None,
             },
             ..Default::default()
         };
+        manifest.add_capability(CapabilityKind::Restricted, "runFullTrust");
+        manifest.add_capability(CapabilityKind::General, "internetClient");
+
+        let xml = manifest.to_xml();
+        assert!(xml.starts_with(XML_DECLARATION));
+        assert!(xml.contains(r#"<Identity Name="TestApp" Publisher="CN=SomeCommonName" Version="1.0.24.0" ProcessorArchitecture="x64"/>"#));
+        assert!(xml.contains(r#"<Capabilities><Capability Name="internetClient"/><rescap:Capability Name="runFullTrust"/></Capabilities>"#));
+
+        // Stable across round trips
+        let parsed = AppxManifest::from_xml(xml.as_bytes()).unwrap();
+        assert_eq!(parsed.to_xml(), xml);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut manifest = AppxManifest::from_xml(XML_DATA.as_bytes()).unwrap();
+        manifest.set_version(PackageVersion::new(1, 0, 25, 0));
+        manifest.set_publisher("CN=Other");
+        manifest.add_capability(CapabilityKind::Device, "webcam");
+        manifest.add_capability(CapabilityKind::Device, "webcam");
+        assert_eq!(manifest.capabilities(), vec![(CapabilityKind::Device, "webcam")]);
+
+        let parsed = AppxManifest::from_xml(manifest.to_xml().as_bytes()).unwrap();
+        assert_eq!(parsed.identity.version, PackageVersion::new(1, 0, 25, 0));
+        assert_eq!(parsed.identity.publisher, "CN=Other");
+        assert!(parsed.has_capability("webcam"));
+        assert_eq!(parsed.ignorable_namespaces(), "uap mp rescap build");
 
-        let ser = xml_serialize(map);
-        assert_eq!(XML_ENCODING.to_owned() + "\n" + &ser, XML_DATA);
+        let mut manifest = parsed;
+        assert!(manifest.remove_capability("webcam"));
+        assert!(!manifest.remove_capability("webcam"));
+        assert!(manifest.capabilities().is_empty());
     }
 
     #[test]
     fn test_deserialize() {
-        let manifest = xml_deserialize_from_str::<AppxManifest>(XML_DATA).expect("Failed to deserialize XML");
+        let manifest = AppxManifest::from_xml(XML_DATA.as_bytes()).expect("Failed to deserialize XML");
         assert_eq!(manifest.ignorable_namespaces, "uap mp rescap build");
         assert_eq!(manifest.identity.name, "TestApp");
         assert_eq!(manifest.identity.publisher, "CN=SomeCommonName");
-        assert_eq!(manifest.identity.version, "1.0.24.0");
+        assert_eq!(manifest.identity.version, PackageVersion::new(1, 0, 24, 0));
         assert_eq!(manifest.identity.arch, Some("x64".into()));
+        assert_eq!(manifest.identity.package_full_name(), "TestApp_1.0.24.0_x64__bst25f6z33ccc");
+        assert_eq!(manifest.to_xml(), XML_DATA);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_preserve_unknown_content() {
+        let mut manifest = AppxManifest::from_xml(XML_DATA_FULL.as_bytes()).unwrap();
+        assert_eq!(manifest.identity.publisher, "CN=Contoso, O=Contoso & Co");
+        assert_eq!(manifest.capabilities(), vec![
+            (CapabilityKind::General, "internetClient"),
+            (CapabilityKind::Restricted, "runFullTrust"),
+            (CapabilityKind::Device, "microphone"),
+        ]);
+        assert_eq!(manifest.declared_capabilities()[3], (CapabilityKind::Uap, "graphicsCapture"));
+        assert_eq!(manifest.to_xml(), XML_DATA_FULL);
+        assert_eq!(manifest.applications(), vec![Application {
+            id: "App".into(),
+            executable: Some("TestApp.exe".into()),
+            entry_point: Some("Windows.FullTrustApplication".into()),
+            start_page: None,
+            display_name: Some("Test App".into()),
+        }]);
+        assert!(!manifest.is_resource_package());
+        let resource = XML_DATA_FULL.replace("<Properties>", "<Properties>\n    <ResourcePackage>true</ResourcePackage>");
+        assert!(AppxManifest::from_xml(resource.as_bytes()).unwrap().is_resource_package());
+
+        manifest.set_version(PackageVersion::new(1, 0, 4, 0));
+        manifest.remove_capability("microphone");
+        manifest.add_capability(CapabilityKind::Uap, "picturesLibrary");
+        manifest.add_capability(CapabilityKind::Device, "webcam");
+        let xml = manifest.to_xml();
+
+        // Only the edited parts changed
+        let expected = XML_DATA_FULL
+            .replace(r#"Version="1.0.3.0" ProcessorArchitecture="x64" />"#, r#"Version="1.0.4.0" ProcessorArchitecture="x64"/>"#)
+            .replace("    <rescap:Capability Name=\"runFullTrust\" />\n    <DeviceCapability Name=\"microphone\" />",
+                "    <rescap:Capability Name=\"runFullTrust\" /><uap:Capability Name=\"picturesLibrary\"/>\n    ")
+            .replace("\n  </Capabilities>", "\n  <DeviceCapability Name=\"webcam\"/></Capabilities>");
+        assert_eq!(xml, expected);
+
+        let parsed = AppxManifest::from_xml(xml.as_bytes()).unwrap();
+        assert_eq!(parsed.identity.version, PackageVersion::new(1, 0, 4, 0));
+        assert!(parsed.has_capability("picturesLibrary") && parsed.has_capability("webcam") && !parsed.has_capability("microphone"));
+    }
+}
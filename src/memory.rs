@@ -0,0 +1,105 @@
+//! Packages built and unpacked without touching the filesystem
+//!
+//! [`pack`] writes a package from `(name, bytes)` pairs into a `Vec<u8>`,
+//! [`unpack`] reads it back into a map of package paths to plaintext. Both are
+//! thin wrappers around [`PackageWriter`] and [`EAppxFile::extract_entry_to`]
+//! for tests and services transforming packages in memory.
+
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Write},
+};
+
+use crate::{
+    error::Error,
+    extraction_report::ExtractionStatus,
+    keys::{KeyCollection, KeyId},
+    sink::{EntrySink, SinkEntry},
+    writer::{PackageWriter, WriterOptions},
+    EAppxFile,
+    EAppxMagic,
+};
+
+/// Build a package from `files` in memory
+///
+/// As with `makeappx pack`, `AppxManifest.xml` goes first and stays
+/// unencrypted, every other file is encrypted with the first of `keys`, if
+/// any. Compression follows [`PackageWriter::add_file`].
+pub fn pack<N, D, I>(package_full_name: &str, keys: Vec<(KeyId, Vec<u8>)>, files: I) -> Result<Vec<u8>, Error>
+where
+    N: AsRef<str>,
+    D: AsRef<[u8]>,
+    I: IntoIterator<Item = (N, D)>,
+{
+    let key_index = (!keys.is_empty()).then_some(0);
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort_by_key(|(name, _)| name.as_ref() != "AppxManifest.xml");
+
+    let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, package_full_name, keys, WriterOptions::default())?;
+    for (name, data) in &files {
+        let name = name.as_ref();
+        let key_index = key_index.filter(|_| name != "AppxManifest.xml");
+        writer.add_file(name, &mut data.as_ref(), key_index)?;
+    }
+
+    Ok(writer.finish()?.into_inner())
+}
+
+/// Plaintext of every blockmap file, by package path
+struct ByPackagePath(BTreeMap<String, Vec<u8>>);
+
+impl EntrySink for ByPackagePath {
+    fn create(&mut self, entry: &SinkEntry) -> Result<Box<dyn Write + '_>, Error> {
+        let data = self.0.entry(entry.package_path.clone()).or_default();
+        data.clear();
+        Ok(Box::new(data))
+    }
+}
+
+/// Parse `package`, load `keys` and read every blockmap file
+///
+/// Keys of the map are package paths as recorded in the blockmap, with `\`
+/// separators. Files failing their hash checks or lacking a key are errors.
+pub fn unpack(package: &[u8], keys: &KeyCollection) -> Result<BTreeMap<String, Vec<u8>>, Error> {
+    let mut eappx = EAppxFile::from_source(package)?;
+    eappx.load_keys(keys)?;
+
+    let mut sink = ByPackagePath(BTreeMap::new());
+    for file in &eappx.blockmap.files {
+        let extracted = eappx.extract_entry_to(package, &file.name, &mut sink)?;
+        if !matches!(extracted.status, ExtractionStatus::Verified | ExtractionStatus::Unverified) {
+            return Err(Error::DataError(format!("Failed to unpack {}: {}", file.name, extracted.status)));
+        }
+    }
+
+    Ok(sink.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sample;
+
+    #[test]
+    fn test_memory_roundtrip() {
+        let files = sample::sample_files().into_iter()
+            .map(|(name, data, _)| (name, data))
+            .collect::<Vec<_>>();
+        let keys = vec![(sample::TEST_KEY_ID, sample::TEST_KEY.to_vec())];
+
+        let package = pack(&sample::sample_package_full_name(), keys, files.clone()).unwrap();
+        let unpacked = unpack(&package, &sample::sample_keys()).unwrap();
+        assert_eq!(unpacked, files.iter().map(|(name, data)| (name.to_string(), data.clone())).collect());
+
+        let eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        assert_eq!(eappx.blockmap.files[0].name, "AppxManifest.xml");
+        assert!(!eappx.find_footer_for_file(eappx.blockmap.files[0].id()).unwrap().is_encrypted(false));
+        assert!(eappx.find_footer_for_file(eappx.blockmap.files[1].id()).unwrap().is_encrypted(false));
+
+        // Encrypted files need their key
+        assert!(unpack(&package, &KeyCollection::default()).is_err());
+
+        let plain = pack(&sample::sample_package_full_name(), vec![], [("Readme.txt", b"plain".as_slice())]).unwrap();
+        assert_eq!(unpack(&plain, &KeyCollection::default()).unwrap()["Readme.txt"], b"plain");
+    }
+}
@@ -0,0 +1,185 @@
+//! Persistent cache of parsed package metadata, for fast re-opening
+//!
+//! Reading the footer table and parsing the blockmap of a package with
+//! hundreds of thousands of entries takes a noticeable moment on every open.
+//! [`EAppxFile::from_source_cached`] stores both as CBOR in a cache directory
+//! and reuses them as long as the package at that path keeps its size and
+//! header. The header carries the blockmap hash, so a changed blockmap
+//! changes the header as well. Every path has one cache file, replaced when
+//! the package changes.
+
+use std::{
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{blockmap::AppxBlockMap, container, error::Error, limits::ResourceLimits, source::PackageSource, EAppxFile, EAppxFooter};
+
+/// Bumped whenever the cached types change, older entries are ignored
+const FORMAT_VERSION: u32 = 1;
+
+/// Identifies a package: where it lives, its size and its header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// Canonical path if it resolves, else as given
+    pub path: PathBuf,
+    pub size: u64,
+    /// SHA-256 of the header as stored
+    pub header_hash: Vec<u8>,
+}
+
+impl CacheKey {
+    /// Key of the package at `path`, whose contents `source` holds
+    pub fn new<S: PackageSource + ?Sized>(path: &Path, source: &S) -> Result<Self, Error> {
+        let header_size = EAppxFile::peek_header(source)?.header_size;
+        let size = source.len()?;
+
+        let mut header = vec![0u8; header_size as usize];
+        source.read_exact_at(0, &mut header)?;
+
+        Ok(Self {
+            path: path.canonicalize().unwrap_or_else(|_| path.to_owned()),
+            size,
+            header_hash: Sha256::digest(&header).to_vec(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    version: u32,
+    key: &'a CacheKey,
+    footers: &'a [EAppxFooter],
+    blockmap: &'a AppxBlockMap,
+}
+
+#[derive(Deserialize)]
+struct Entry {
+    version: u32,
+    key: CacheKey,
+    footers: Vec<EAppxFooter>,
+    blockmap: AppxBlockMap,
+}
+
+/// Directory holding one cache file per package path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataCache {
+    pub dir: PathBuf,
+}
+
+impl MetadataCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// `EAPPX_CACHE_DIR`, else `eappx` in the user's cache directory
+    pub fn default_dir() -> Option<PathBuf> {
+        let var = |name: &str| std::env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+        if let Some(dir) = var("EAPPX_CACHE_DIR") {
+            return Some(dir);
+        }
+        let base = match cfg!(windows) {
+            true => var("LOCALAPPDATA"),
+            false => var("XDG_CACHE_HOME").or_else(|| var("HOME").map(|home| home.join(".cache"))),
+        };
+        base.map(|base| base.join("eappx"))
+    }
+
+    fn entry_path(&self, key: &CacheKey) -> PathBuf {
+        let name = hex::encode(Sha256::digest(key.path.to_string_lossy().as_bytes()));
+        self.dir.join(format!("{name}.cbor"))
+    }
+
+    /// Footers and blockmap cached for `key`, `None` if missing, stale or unreadable
+    pub fn load(&self, key: &CacheKey) -> Option<(Vec<EAppxFooter>, AppxBlockMap)> {
+        let file = std::fs::File::open(self.entry_path(key)).ok()?;
+        match ciborium::from_reader::<Entry, _>(BufReader::new(file)) {
+            Ok(entry) if entry.version == FORMAT_VERSION && entry.key == *key => Some((entry.footers, entry.blockmap)),
+            Ok(_) => None,
+            Err(err) => {
+                log::debug!("Ignoring unreadable metadata cache entry of {}: {err}", key.path.display());
+                None
+            },
+        }
+    }
+
+    /// Cache footers and blockmap of `eappx` under `key`, replacing what the path had
+    pub fn store(&self, key: &CacheKey, eappx: &EAppxFile) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.entry_path(key);
+        // Written aside and renamed, concurrent readers never see half an entry
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        let entry = EntryRef { version: FORMAT_VERSION, key, footers: &eappx.footers, blockmap: &eappx.blockmap };
+        let mut writer = BufWriter::new(std::fs::File::create(&partial)?);
+        ciborium::into_writer(&entry, &mut writer)
+            .map_err(|err| Error::data("Failed to write metadata cache entry", err))?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&partial, &path)?;
+        Ok(())
+    }
+}
+
+impl EAppxFile {
+    /// [`EAppxFile::from_source`] for the package at `path`, reusing parsed metadata from `cache`
+    ///
+    /// Misses parse the package as usual and fill the cache, failing to write
+    /// it only logs a warning.
+    pub fn from_source_cached<S: PackageSource + ?Sized>(source: &S, path: &Path, cache: &MetadataCache) -> Result<Self, Error> {
+        let limits = ResourceLimits::default();
+        let key = CacheKey::new(path, source)?;
+        let header = Self::peek_header(source)?;
+        if let Some((footers, blockmap)) = cache.load(&key) {
+            log::debug!("Using cached metadata of {}", key.path.display());
+            container::check_header(&header, &limits)?;
+            limits.check_file_count("Footer count", footers.len() as u64)?;
+            limits.check_file_count("Blockmap file count", blockmap.files.len() as u64)?;
+            let mut eappx = Self::new(header, key.size, footers, blockmap, limits);
+            eappx.detect_encrypted_payloads(source)?;
+            return Ok(eappx);
+        }
+
+        let eappx = Self::from_header(source, header, limits)?;
+        if let Err(err) = cache.store(&key, &eappx) {
+            log::warn!("Failed to cache metadata of {}: {err}", key.path.display());
+        }
+        Ok(eappx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_metadata_cache() {
+        let dir = std::env::temp_dir().join(format!("eappx-metadata-cache-{}", std::process::id()));
+        let cache = MetadataCache::new(&dir);
+        let path = Path::new("TestApp_1.0.3.0_x64.emsix");
+
+        let key = CacheKey::new(path, EMSIX).unwrap();
+        assert_eq!(cache.load(&key), None);
+        let parsed = EAppxFile::from_source_cached(EMSIX, path, &cache).unwrap();
+        let cached = EAppxFile::from_source_cached(EMSIX, path, &cache).unwrap();
+        assert_eq!(cache.load(&key), Some((parsed.footers.clone(), parsed.blockmap.clone())));
+        assert_eq!((&cached.header, cached.file_len, &cached.footers, &cached.blockmap),
+            (&parsed.header, parsed.file_len, &parsed.footers, &parsed.blockmap));
+
+        // Another package at the same path misses and replaces the entry
+        let mut changed = EMSIX.to_vec();
+        changed.extend([0u8; 0x10]);
+        let changed_key = CacheKey::new(path, changed.as_slice()).unwrap();
+        assert_eq!(cache.load(&changed_key), None);
+        EAppxFile::from_source_cached(changed.as_slice(), path, &cache).unwrap();
+        assert!(cache.load(&changed_key).is_some());
+        assert_eq!(cache.load(&key), None);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
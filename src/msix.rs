@@ -0,0 +1,1054 @@
+//! Conversion into standard, zip based msix/appx containers
+//!
+//! Payloads are decrypted and laid out the way MakeAppx does: every payload
+//! file carries a data descriptor and is deflated in independently flushed
+//! 64KiB blocks, whose compressed sizes end up in the blockmap. Payloads are
+//! followed by AppxBlockMap.xml, `[Content_Types].xml` and the code integrity
+//! catalog, the central directory always comes with zip64 records.
+//!
+//! Bundles are rebuilt by converting every embedded package into a stored
+//! entry of the outer zip and pointing the bundle manifest at the new offsets.
+//! Signatures do not survive conversion, they cover the blockmap of the
+//! encrypted container. Converted packages have to be re-signed before they
+//! install outside of developer mode, with the `signing` feature that happens
+//! during conversion if `EAppxFile::signer` is set.
+
+use std::io::{Read, Write};
+
+use base64ct::{Base64, Encoding};
+use flate2::Crc;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    code_integrity::{AuthenticodeHasher, CodeIntegrityCatalog},
+    compression::{self, ByExtension, CompressionPolicy},
+    content_types::{self, ContentTypes},
+    error::Error,
+    extension::ContainerKind,
+    limits::ResourceLimits,
+    manifest::AppxManifest,
+    source::PackageSource,
+    utils::{self, xml_escape},
+    writer::deflate_block,
+    EAppxFile,
+    EAppxFooter,
+    Manifest,
+};
+#[cfg(feature = "signing")]
+use crate::signing::{AppxDigest, SigningIdentity};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x08074b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const LOCAL_HEADER_SIZE: u64 = 30;
+const ZIP64_END_SIZE: u64 = 44;
+/// 4.5 introduced zip64, 2.0 deflate
+const VERSION_ZIP64: u16 = 45;
+const VERSION_DEFLATE: u16 = 20;
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+const FLAG_UTF8: u16 = 1 << 11;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+/// 1980-01-01 00:00, keeps the output reproducible
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = 0x21;
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>"#;
+const BLOCKMAP_NAMESPACE: &str = "http://schemas.microsoft.com/appx/2010/blockmap";
+const BLOCKMAP_2021_NAMESPACE: &str = "http://schemas.microsoft.com/appx/2021/blockmap";
+const HASH_METHOD: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const ZIP_MAGIC: &[u8; 4] = b"PK\x03\x04";
+
+/// Entry as listed in the central directory
+#[derive(Debug)]
+struct CentralEntry {
+    name: String,
+    version: u16,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    offset: u64,
+}
+
+/// Entry as listed in the blockmap
+#[derive(Debug)]
+struct BlockMapEntry {
+    /// Package path, backslash separated
+    name: String,
+    size: u64,
+    lfh_size: u64,
+    /// Hash of the uncompressed block and its compressed size
+    blocks: Vec<(Vec<u8>, Option<u64>)>,
+    filehash: Vec<u8>,
+}
+
+/// Generated footprint files of a package
+pub(crate) struct Footprint {
+    pub blockmap: String,
+    pub content_types: String,
+    pub code_integrity: Option<Vec<u8>>,
+}
+
+/// Streams a zip based package or bundle, no seeking needed
+pub struct MsixWriter<W: Write> {
+    sink: W,
+    position: u64,
+    entries: Vec<CentralEntry>,
+    blockmap: Vec<BlockMapEntry>,
+    content_types: ContentTypes,
+    /// PE files seen so far, if a catalog gets generated
+    catalog: Option<CodeIntegrityCatalog>,
+    /// Generated catalog derives its identity from the members
+    deterministic: bool,
+    /// Decides for [`MsixWriter::add_file`] whether to deflate
+    compression: Box<dyn CompressionPolicy>,
+    /// Deflate level of compressed entries
+    level: u32,
+    /// Hash over everything written, kept while a signature is pending
+    records: Option<Sha256>,
+    #[cfg(feature = "signing")]
+    signer: Option<SigningIdentity>,
+}
+
+/// Counts and checksums what gets written into a single entry
+struct EntryWriter<'a, W: Write> {
+    inner: &'a mut W,
+    records: Option<&'a mut Sha256>,
+    crc: Crc,
+    written: u64,
+}
+
+impl<W: Write> Write for EntryWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(records) = self.records.as_mut() {
+            records.update(&buf[..written]);
+        }
+        self.crc.update(&buf[..written]);
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Read up to one block, short reads only happen at the end of the stream
+fn read_block<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(utils::BLOCK_SIZE);
+    reader.take(utils::BLOCK_SIZE as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Value for a 32-bit size or offset field, moving it into the zip64 extra field if it does not fit
+fn zip64_field(value: u64, extra: &mut Vec<u8>) -> u32 {
+    match u32::try_from(value) {
+        Ok(value) if value != u32::MAX => value,
+        _ => {
+            extra.extend_from_slice(&value.to_le_bytes());
+            u32::MAX
+        },
+    }
+}
+
+impl<W: Write> MsixWriter<W> {
+    pub fn new(sink: W, kind: ContainerKind) -> Self {
+        Self {
+            sink,
+            position: 0,
+            entries: vec![],
+            blockmap: vec![],
+            content_types: ContentTypes::new(kind),
+            catalog: None,
+            deterministic: false,
+            compression: Box::new(ByExtension::default()),
+            level: compression::DEFAULT_LEVEL,
+            records: None,
+            #[cfg(feature = "signing")]
+            signer: None,
+        }
+    }
+
+    /// Writer adding AppxSignature.p7x signed by `identity` on [`MsixWriter::finish`]
+    #[cfg(feature = "signing")]
+    pub fn new_signed(sink: W, kind: ContainerKind, identity: SigningIdentity) -> Self {
+        Self { records: Some(Sha256::new()), signer: Some(identity), ..Self::new(sink, kind) }
+    }
+
+    /// Generate CodeIntegrity.cat for the PE files added, unless [`MsixWriter::finish`] gets one
+    pub fn generate_code_integrity(mut self) -> Self {
+        self.catalog = Some(CodeIntegrityCatalog::new());
+        self
+    }
+
+    /// Byte-identical output for identical files added in the same order
+    ///
+    /// Zip timestamps are fixed already, this makes a generated catalog
+    /// independent of time as well. ECDSA signatures stay randomized.
+    pub fn deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Policy [`MsixWriter::add_file`] picks compression by, [`ByExtension`] by default
+    pub fn compression_policy<P: CompressionPolicy + 'static>(mut self, policy: P) -> Self {
+        self.compression = Box::new(policy);
+        self
+    }
+
+    /// Deflate level of compressed entries, 0 to [`compression::MAX_LEVEL`]
+    pub fn compression_level(mut self, level: u32) -> Result<Self, Error> {
+        compression::check_level(level)?;
+        self.level = level;
+        Ok(self)
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.records.is_some()
+    }
+
+    /// Zip entry name of a package path, its percent-encoded part name
+    fn zip_name(name: &str) -> Result<String, Error> {
+        match content_types::part_name(name).strip_prefix('/') {
+            Some(zip_name) => Ok(zip_name.to_owned()),
+            None => Err(Error::DataError(format!("Invalid package path {name:?}"))),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.sink.write_all(data)?;
+        if let Some(records) = self.records.as_mut() {
+            records.update(data);
+        }
+        self.position += data.len() as u64;
+        Ok(())
+    }
+
+    /// Write a local file header, returns its offset
+    fn write_local_header(&mut self, name: &str, flags: u16, method: u16, version: u16, crc: u32, sizes: (u32, u32)) -> Result<u64, Error> {
+        let name_length = u16::try_from(name.len())
+            .map_err(|_| Error::DataError(format!("Name too long for zip: {name}")))?;
+        let flags = match name.is_ascii() {
+            true => flags,
+            false => flags | FLAG_UTF8,
+        };
+
+        let offset = self.position;
+        let header = [
+            &LOCAL_HEADER_SIGNATURE.to_le_bytes()[..],
+            &version.to_le_bytes(),
+            &flags.to_le_bytes(),
+            &method.to_le_bytes(),
+            &DOS_TIME.to_le_bytes(),
+            &DOS_DATE.to_le_bytes(),
+            &crc.to_le_bytes(),
+            &sizes.0.to_le_bytes(),
+            &sizes.1.to_le_bytes(),
+            &name_length.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            name.as_bytes(),
+        ].concat();
+        self.write(&header)?;
+
+        Ok(offset)
+    }
+
+    /// Data descriptors always carry 64-bit sizes, like MakeAppx writes them
+    fn write_data_descriptor(&mut self, crc: u32, compressed_size: u64, uncompressed_size: u64) -> Result<(), Error> {
+        let descriptor = [
+            &DATA_DESCRIPTOR_SIGNATURE.to_le_bytes()[..],
+            &crc.to_le_bytes(),
+            &compressed_size.to_le_bytes(),
+            &uncompressed_size.to_le_bytes(),
+        ].concat();
+        self.write(&descriptor)
+    }
+
+    /// Add a payload file covered by the blockmap, returns the SHA-256 of its content
+    ///
+    /// `name` is the package path, either separator works. Whether the file
+    /// is deflated is up to the compression policy.
+    pub fn add_file<R: Read>(&mut self, name: &str, reader: &mut R) -> Result<Vec<u8>, Error> {
+        let block = read_block(reader)?;
+        let compress = self.compression.should_compress(name, &block);
+        self.write_file(name, block, reader, compress)
+    }
+
+    /// [`MsixWriter::add_file`] with an explicit choice instead of the policy's
+    pub fn add_file_with<R: Read>(&mut self, name: &str, reader: &mut R, compress: bool) -> Result<Vec<u8>, Error> {
+        let block = read_block(reader)?;
+        self.write_file(name, block, reader, compress)
+    }
+
+    /// Write a payload file starting with `block`, the rest coming from `reader`
+    fn write_file<R: Read>(&mut self, name: &str, mut block: Vec<u8>, reader: &mut R, compress: bool) -> Result<Vec<u8>, Error> {
+        let zip_name = Self::zip_name(name)?;
+        let compress = compress && !block.is_empty();
+        let method = match compress {
+            true => METHOD_DEFLATED,
+            false => METHOD_STORED,
+        };
+
+        let offset = self.write_local_header(&zip_name, FLAG_DATA_DESCRIPTOR, method, VERSION_ZIP64, 0, (0, 0))?;
+        let mut pe_hasher = self.catalog.as_ref().and_then(|_| AuthenticodeHasher::new(&block));
+
+        let mut crc = Crc::new();
+        let mut filehash = Sha256::new();
+        let mut blocks = vec![];
+        let mut size = 0u64;
+        let mut compressed_size = 0u64;
+
+        while !block.is_empty() {
+            // Reading ahead tells whether the deflate stream has to end here
+            let next = read_block(reader)?;
+
+            crc.update(&block);
+            filehash.update(&block);
+            if let Some(pe_hasher) = pe_hasher.as_mut() {
+                pe_hasher.update(&block);
+            }
+            let hash = Sha256::digest(&block).to_vec();
+            size += block.len() as u64;
+
+            let (data, stored_size) = match compress {
+                true => {
+                    let data = deflate_block(&block, next.is_empty(), self.level)?;
+                    let stored_size = data.len() as u64;
+                    (data, Some(stored_size))
+                },
+                false => (block, None),
+            };
+            self.write(&data)?;
+            compressed_size += data.len() as u64;
+            blocks.push((hash, stored_size));
+
+            block = next;
+        }
+
+        self.write_data_descriptor(crc.sum(), compressed_size, size)?;
+        if let (Some(catalog), Some(pe_hasher)) = (self.catalog.as_mut(), pe_hasher) {
+            catalog.add(pe_hasher.finalize());
+        }
+
+        let filehash = filehash.finalize().to_vec();
+        self.content_types.add_file(name);
+        self.blockmap.push(BlockMapEntry {
+            name: name.replace('/', "\\"),
+            size,
+            lfh_size: LOCAL_HEADER_SIZE + zip_name.len() as u64,
+            blocks,
+            filehash: filehash.clone(),
+        });
+        self.entries.push(CentralEntry {
+            name: zip_name,
+            version: VERSION_ZIP64,
+            flags: FLAG_DATA_DESCRIPTOR,
+            method,
+            crc: crc.sum(),
+            compressed_size,
+            uncompressed_size: size,
+            offset,
+        });
+
+        Ok(filehash)
+    }
+
+    /// Add a stored file not covered by the blockmap, its content is written by `write`
+    ///
+    /// Used for the packages of a bundle, returns offset and size of the
+    /// content as referenced by the bundle manifest.
+    pub fn add_stored_with<F>(&mut self, name: &str, write: F) -> Result<(u64, u64), Error>
+    where
+        F: FnOnce(&mut dyn Write) -> Result<(), Error>,
+    {
+        let zip_name = Self::zip_name(name)?;
+        let offset = self.write_local_header(&zip_name, FLAG_DATA_DESCRIPTOR, METHOD_STORED, VERSION_ZIP64, 0, (0, 0))?;
+        let data_offset = self.position;
+
+        let mut entry = EntryWriter { inner: &mut self.sink, records: self.records.as_mut(), crc: Crc::new(), written: 0 };
+        write(&mut entry)?;
+        let (crc, size) = (entry.crc.sum(), entry.written);
+        self.position += size;
+
+        self.write_data_descriptor(crc, size, size)?;
+        self.content_types.add_file(name);
+        self.entries.push(CentralEntry {
+            name: zip_name,
+            version: VERSION_ZIP64,
+            flags: FLAG_DATA_DESCRIPTOR,
+            method: METHOD_STORED,
+            crc,
+            compressed_size: size,
+            uncompressed_size: size,
+            offset,
+        });
+
+        Ok((data_offset, size))
+    }
+
+    /// Add a footprint file, sizes are known upfront so no data descriptor is needed
+    fn add_footprint(&mut self, name: &str, data: &[u8]) -> Result<(), Error> {
+        let compressed = deflate_block(data, true, self.level)?;
+        let mut crc = Crc::new();
+        crc.update(data);
+
+        let sizes = match (u32::try_from(compressed.len()), u32::try_from(data.len())) {
+            (Ok(compressed_size), Ok(size)) => (compressed_size, size),
+            _ => return Err(Error::DataError(format!("{name} too large for zip ({:#x})", data.len()))),
+        };
+        let offset = self.write_local_header(name, 0, METHOD_DEFLATED, VERSION_DEFLATE, crc.sum(), sizes)?;
+        self.write(&compressed)?;
+
+        self.entries.push(CentralEntry {
+            name: name.to_owned(),
+            version: VERSION_DEFLATE,
+            flags: 0,
+            method: METHOD_DEFLATED,
+            crc: crc.sum(),
+            compressed_size: compressed.len() as u64,
+            uncompressed_size: data.len() as u64,
+            offset,
+        });
+
+        Ok(())
+    }
+
+    fn blockmap_xml(&self) -> String {
+        let mut xml = format!(
+            r#"{XML_DECLARATION}{}<BlockMap xmlns="{BLOCKMAP_NAMESPACE}" xmlns:b4="{BLOCKMAP_2021_NAMESPACE}" IgnorableNamespaces="b4" HashMethod="{HASH_METHOD}">"#,
+            "\r\n"
+        );
+
+        for file in &self.blockmap {
+            xml += &format!(r#"<File Name="{}" Size="{}" LfhSize="{}">"#, xml_escape(&file.name), file.size, file.lfh_size);
+            for (hash, size) in &file.blocks {
+                xml += &match size {
+                    Some(size) => format!(r#"<Block Hash="{}" Size="{size}"/>"#, Base64::encode_string(hash)),
+                    None => format!(r#"<Block Hash="{}"/>"#, Base64::encode_string(hash)),
+                };
+            }
+            // A single block's hash already covers the whole file
+            if file.blocks.len() > 1 {
+                xml += &format!(r#"<b4:FileHash Hash="{}"/>"#, Base64::encode_string(&file.filehash));
+            }
+            xml += "</File>";
+        }
+
+        xml + "</BlockMap>"
+    }
+
+    /// Central directory and end records for a directory starting at `offset`
+    fn central_directory(&self, offset: u64) -> Vec<u8> {
+        let mut directory = vec![];
+        for entry in &self.entries {
+            let mut extra = vec![];
+            let uncompressed_size = zip64_field(entry.uncompressed_size, &mut extra);
+            let compressed_size = zip64_field(entry.compressed_size, &mut extra);
+            let entry_offset = zip64_field(entry.offset, &mut extra);
+            if !extra.is_empty() {
+                extra = [&ZIP64_EXTRA_ID.to_le_bytes()[..], &(extra.len() as u16).to_le_bytes(), &extra].concat();
+            }
+            let version = match extra.is_empty() {
+                true => entry.version,
+                false => VERSION_ZIP64,
+            };
+            let flags = match entry.name.is_ascii() {
+                true => entry.flags,
+                false => entry.flags | FLAG_UTF8,
+            };
+
+            directory.extend([
+                &CENTRAL_HEADER_SIGNATURE.to_le_bytes()[..],
+                &VERSION_ZIP64.to_le_bytes(),
+                &version.to_le_bytes(),
+                &flags.to_le_bytes(),
+                &entry.method.to_le_bytes(),
+                &DOS_TIME.to_le_bytes(),
+                &DOS_DATE.to_le_bytes(),
+                &entry.crc.to_le_bytes(),
+                &compressed_size.to_le_bytes(),
+                &uncompressed_size.to_le_bytes(),
+                &(entry.name.len() as u16).to_le_bytes(),
+                &(extra.len() as u16).to_le_bytes(),
+                // Comment length, disk number, internal and external attributes
+                &0u16.to_le_bytes(),
+                &0u16.to_le_bytes(),
+                &0u16.to_le_bytes(),
+                &0u32.to_le_bytes(),
+                &entry_offset.to_le_bytes(),
+                entry.name.as_bytes(),
+                &extra,
+            ].concat());
+        }
+        let count = self.entries.len() as u64;
+        let size = directory.len() as u64;
+        let zip64_end_offset = offset + size;
+        let end = [
+            &ZIP64_END_SIGNATURE.to_le_bytes()[..],
+            &ZIP64_END_SIZE.to_le_bytes(),
+            &VERSION_ZIP64.to_le_bytes(),
+            &VERSION_ZIP64.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &count.to_le_bytes(),
+            &count.to_le_bytes(),
+            &size.to_le_bytes(),
+            &offset.to_le_bytes(),
+            // Locator
+            &ZIP64_LOCATOR_SIGNATURE.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &zip64_end_offset.to_le_bytes(),
+            &1u32.to_le_bytes(),
+            // Classic record, saturated like MakeAppx does so readers go to zip64.
+            // Also keeps it the same with and without the signature entry.
+            &END_SIGNATURE.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &0u16.to_le_bytes(),
+            &u16::MAX.to_le_bytes(),
+            &u16::MAX.to_le_bytes(),
+            &u32::MAX.to_le_bytes(),
+            &u32::MAX.to_le_bytes(),
+            &0u16.to_le_bytes(),
+        ].concat();
+
+        directory.extend(end);
+        directory
+    }
+
+    /// Sign everything written so far and add AppxSignature.p7x
+    #[cfg(feature = "signing")]
+    fn add_signature(&mut self, blockmap: &str, content_types: &str, code_integrity: Option<&[u8]>) -> Result<(), Error> {
+        let (Some(records), Some(identity)) = (self.records.take(), self.signer.take()) else {
+            return Ok(());
+        };
+
+        // The signature entry is left out of the signed directory, which starts where the entry goes
+        let digest = AppxDigest {
+            zip_records: records.finalize().to_vec(),
+            central_directory: Sha256::digest(self.central_directory(self.position)).to_vec(),
+            content_types: Sha256::digest(content_types).to_vec(),
+            blockmap: Sha256::digest(blockmap).to_vec(),
+            code_integrity: code_integrity.map(|catalog| Sha256::digest(catalog).to_vec()),
+        };
+        let signature = identity.sign(&digest, self.content_types.kind())?;
+        self.add_footprint("AppxSignature.p7x", &signature)
+    }
+
+    /// Generated catalog, signed along with the package
+    fn catalog_der(&self, catalog: &CodeIntegrityCatalog) -> Result<Vec<u8>, Error> {
+        log::info!("Generating code integrity catalog for {} PE files", catalog.len());
+        #[cfg(feature = "signing")]
+        if let Some(identity) = &self.signer {
+            return catalog.to_signed_der(identity);
+        }
+        Ok(catalog.to_der())
+    }
+
+    /// Blockmap and content types for the files added so far, along with the
+    /// catalog, `code_integrity` or the generated one
+    pub(crate) fn footprint(&mut self, code_integrity: Option<&[u8]>) -> Result<Footprint, Error> {
+        let code_integrity = match (code_integrity, self.catalog.take()) {
+            (Some(code_integrity), _) => Some(code_integrity.to_vec()),
+            (None, Some(catalog)) if !catalog.is_empty() => Some(self.catalog_der(&catalog.deterministic(self.deterministic))?),
+            _ => None,
+        };
+
+        let blockmap = self.blockmap_xml();
+        self.content_types.add_footprint(self.is_signed(), code_integrity.is_some());
+        Ok(Footprint { blockmap, content_types: self.content_types.to_xml(), code_integrity })
+    }
+
+    /// Write blockmap, content types, the optional code integrity catalog, the
+    /// signature of signed writers and the central directory, returns the sink
+    pub fn finish(mut self, code_integrity: Option<&[u8]>) -> Result<W, Error> {
+        let Footprint { blockmap, content_types, code_integrity } = self.footprint(code_integrity)?;
+        let code_integrity = code_integrity.as_deref();
+
+        self.add_footprint("AppxBlockMap.xml", blockmap.as_bytes())?;
+        self.add_footprint("[Content_Types].xml", content_types.as_bytes())?;
+        if let Some(catalog) = code_integrity {
+            self.add_footprint("AppxMetadata/CodeIntegrity.cat", catalog)?;
+        }
+        #[cfg(feature = "signing")]
+        self.add_signature(&blockmap, &content_types, code_integrity)?;
+
+        let directory = self.central_directory(self.position);
+        self.write(&directory)?;
+        self.sink.flush()?;
+
+        log::info!("Wrote {} zip entries, {:#x} bytes", self.entries.len(), self.position);
+        Ok(self.sink)
+    }
+}
+
+/// Where a converted package ended up inside the bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PackageLocation {
+    original_name: String,
+    name: String,
+    offset: u64,
+    size: u64,
+}
+
+/// Name of the decrypted package, `Foo.emsix` becomes `Foo.msix`
+fn decrypted_file_name(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if ["eappx", "emsix"].contains(&ext.to_lowercase().as_str()) => format!("{stem}.{}", &ext[1..]),
+        _ => name.to_owned(),
+    }
+}
+
+/// Byte range of the value of attribute `name` inside `tag`
+fn attribute_value(tag: &str, name: &str) -> Option<std::ops::Range<usize>> {
+    let pattern = format!("{name}=\"");
+    let mut search_from = 0;
+
+    while let Some(found) = tag[search_from..].find(&pattern) {
+        let start = search_from + found;
+        let value_start = start + pattern.len();
+        if tag[..start].ends_with(char::is_whitespace) {
+            let value_end = value_start + tag[value_start..].find('"')?;
+            return Some(value_start..value_end);
+        }
+        search_from = value_start;
+    }
+
+    None
+}
+
+fn set_attribute(tag: &str, name: &str, value: &str) -> String {
+    match attribute_value(tag, name) {
+        Some(range) => format!("{}{}{}", &tag[..range.start], xml_escape(value), &tag[range.end..]),
+        None => {
+            let end = match tag.ends_with("/>") {
+                true => tag.len() - 2,
+                false => tag.len() - 1,
+            };
+            format!("{} {name}=\"{}\"{}", &tag[..end], xml_escape(value), &tag[end..])
+        },
+    }
+}
+
+/// Point the `<Package>` elements of a bundle manifest at the converted packages
+///
+/// Attributes are edited in place instead of re-serializing the manifest, so
+/// content the manifest model does not cover (Resources, b4:Dependencies, ...)
+/// survives unchanged.
+/// Write a bundle package read from `region` to `out`, converted unless `package` is `None`
+fn convert_embedded<P: PackageSource + ?Sized>(package: Option<EAppxFile>, region: &P, out: &mut dyn Write, code_integrity: Option<Vec<u8>>) -> Result<(), Error> {
+    let Some(package) = package else {
+        // Already a zip based package
+        std::io::copy(&mut region.range(0, region.len()?), out)?;
+        return Ok(());
+    };
+    let code_integrity = match code_integrity {
+        Some(code_integrity) => Some(code_integrity),
+        None => package.read_code_integrity(region)?,
+    };
+    package.convert_package(region, out, code_integrity)?;
+    Ok(())
+}
+
+fn patch_bundle_manifest(xml: &str, packages: &[PackageLocation]) -> Result<String, Error> {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+    let mut patched = 0;
+
+    while let Some(start) = rest.find("<Package") {
+        out.push_str(&rest[..start]);
+        let tag_length = rest[start..].find('>')
+            .ok_or(Error::DataError("Unterminated Package element in bundle manifest".into()))? + 1;
+        let tag = &rest[start..start + tag_length];
+        rest = &rest[start + tag_length..];
+
+        // Skip <Packages>
+        let is_package = tag["<Package".len()..].starts_with(|c: char| c.is_whitespace());
+        let location = attribute_value(tag, "FileName")
+            .filter(|_| is_package)
+            .and_then(|range| {
+                let filename = tag[range].replace("&amp;", "&");
+                packages.iter().find(|package| package.original_name == filename)
+            });
+
+        match location {
+            Some(location) => {
+                let tag = set_attribute(tag, "FileName", &location.name);
+                let tag = set_attribute(&tag, "Offset", &location.offset.to_string());
+                out.push_str(&set_attribute(&tag, "Size", &location.size.to_string()));
+                patched += 1;
+            },
+            None => out.push_str(tag),
+        }
+    }
+    out.push_str(rest);
+
+    if patched != packages.len() {
+        return Err(Error::DataError(format!("Bundle manifest references {patched} of {} packages", packages.len())));
+    }
+
+    Ok(out)
+}
+
+impl EAppxFile {
+    /// Decrypt into a standard zip based package, or bundle for EXBH, written to `writer`
+    ///
+    /// Keys of all encrypted files have to be loaded, including those of the
+    /// packages inside a bundle. Unless [`EAppxFile::integrity`] skips file hashes,
+    /// every file is checked against its own and a wrong key fails the conversion
+    /// instead of producing garbage.
+    pub fn convert_to_msix<P: PackageSource + ?Sized, W: Write>(&self, source: &P, writer: W) -> Result<W, Error> {
+        let payloads = self.bundle_payloads(source)?;
+        if self.header.is_bundle() || !payloads.is_empty() {
+            return self.convert_bundle(source, writer);
+        }
+
+        let code_integrity = self.read_code_integrity(source)?;
+        self.convert_package(source, writer, code_integrity)
+    }
+
+    /// Writer for converted output, signed if `EAppxFile::signer` is set
+    fn msix_writer<W: Write>(&self, writer: W, kind: ContainerKind) -> MsixWriter<W> {
+        #[cfg(feature = "signing")]
+        if let Some(identity) = &self.signer {
+            return MsixWriter::new_signed(writer, kind, identity.clone());
+        }
+        MsixWriter::new(writer, kind)
+    }
+
+    fn convert_package<P: PackageSource + ?Sized, W: Write>(&self, source: &P, writer: W, code_integrity: Option<Vec<u8>>) -> Result<W, Error> {
+        let mut msix = self.msix_writer(writer, ContainerKind::Package);
+
+        // MakeAppx writes the manifest after all other payload files
+        let (manifest, files): (Vec<_>, Vec<_>) = self.blockmap.files.iter()
+            .partition(|file| file.name.eq_ignore_ascii_case("AppxManifest.xml"));
+
+        for file in files.into_iter().chain(manifest) {
+            log::info!("* Converting {}", file.name);
+            let filehash = msix.add_file(&file.name, &mut self.file_reader(source, file)?)?;
+            if self.integrity.checks_file() && file.filehash_bytes()?.is_some_and(|expected| expected != filehash) {
+                return Err(Error::DataError(format!("{} does not match its file hash, wrong key?", file.name)));
+            }
+        }
+
+        if self.header.is_signed() && !msix.is_signed() {
+            log::warn!("Signature does not cover the converted package and is dropped, re-sign before installing");
+        }
+        msix.finish(code_integrity.as_deref())
+    }
+
+    fn convert_bundle<P: PackageSource + ?Sized, W: Write>(&self, source: &P, writer: W) -> Result<W, Error> {
+        let manifest_file = self.blockmap.manifest_file()
+            .ok_or(Error::DataError("Could not find manifest in blockmap".into()))?;
+        let manifest_xml = std::io::read_to_string(self.file_reader(source, manifest_file)?)?;
+
+        let mut msix = self.msix_writer(writer, ContainerKind::Bundle);
+        let mut locations = vec![];
+
+        for (filename, footer) in self.bundle_payloads(source)? {
+            if footer.compression_type != 0 {
+                return Err(Error::DataError(format!("Bundle package {filename} is compressed, which is not supported")));
+            }
+            let (start, length) = (footer.offset_to_file, footer.uncompressed_length);
+            let plaintext = match self.is_footer_encrypted(footer) {
+                true => Some(self.read_encrypted_payload(source, footer, &filename)?),
+                false => None,
+            };
+            let package = match &plaintext {
+                Some(plaintext) => self.embedded_package(plaintext.as_slice())?,
+                None => self.embedded_package(&source.slice(start, length))?,
+            };
+
+            // The bundle stores catalogs of its packages behind them, at offsets relative to the bundle
+            let code_integrity = match package.as_ref().and_then(|package| package.header.code_integrity_fileinfo()) {
+                Some(fileinfo) if fileinfo.offset_to_file >= length => {
+                    Some(Self::read_file_to_buf(source, fileinfo, self.header.is_bundle(), self.limits.max_in_memory_size)?)
+                },
+                _ => None,
+            };
+
+            let name = decrypted_file_name(&filename);
+            log::info!("* Converting bundle package {filename} to {name}");
+            let (offset, size) = msix.add_stored_with(&name, |out| match &plaintext {
+                Some(plaintext) => convert_embedded(package, plaintext.as_slice(), out, code_integrity),
+                None => convert_embedded(package, &source.slice(start, length), out, code_integrity),
+            })?;
+
+            locations.push(PackageLocation { original_name: filename, name, offset, size });
+        }
+
+        let manifest_xml = patch_bundle_manifest(&manifest_xml, &locations)?;
+        msix.add_file(&manifest_file.name, &mut manifest_xml.as_bytes())?;
+
+        if self.header.is_signed() && !msix.is_signed() {
+            log::warn!("Signature does not cover the converted bundle and is dropped, re-sign before installing");
+        }
+        msix.finish(None)
+    }
+
+    /// Plaintext of a bundle package stored encrypted, see [`EAppxFile::is_footer_encrypted`]
+    ///
+    /// Opening a package reads it out of order, so it is decrypted into memory.
+    pub(crate) fn read_encrypted_payload<P: PackageSource + ?Sized>(&self, source: &P, footer: &EAppxFooter, filename: &str) -> Result<Vec<u8>, Error> {
+        ResourceLimits::check("In-memory file size", footer.uncompressed_length, self.limits.max_in_memory_size)?;
+        let mut plaintext = Vec::with_capacity(footer.uncompressed_length as usize);
+        self.payload_reader(source, footer, filename)?.read_to_end(&mut plaintext)?;
+        Ok(plaintext)
+    }
+
+    /// Manifest of the package `filename` embedded in this bundle
+    pub(crate) fn embedded_manifest<P: PackageSource + ?Sized>(&self, source: &P, footer: &EAppxFooter, filename: &str) -> Result<AppxManifest, Error> {
+        match self.is_footer_encrypted(footer) {
+            true => self.read_embedded_manifest(self.read_encrypted_payload(source, footer, filename)?.as_slice(), filename),
+            false => self.read_embedded_manifest(&source.slice(footer.offset_to_file, footer.uncompressed_length), filename),
+        }
+    }
+
+    fn read_embedded_manifest<P: PackageSource + ?Sized>(&self, region: &P, filename: &str) -> Result<AppxManifest, Error> {
+        let package = self.embedded_package(region)?
+            .ok_or_else(|| Error::DataError(format!("Bundle package {filename} is zip based")))?;
+        match package.read_manifest(region)? {
+            Manifest::Manifest(manifest) => Ok(manifest),
+            Manifest::BundleManifest(_) => Err(Error::DataError(format!("Bundle package {filename} is a bundle itself"))),
+        }
+    }
+
+    /// Parse a package embedded in this bundle, `None` if it is zip based already
+    fn embedded_package<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Option<EAppxFile>, Error> {
+        let mut magic = [0u8; 4];
+        source.read_exact_at(0, &mut magic)?;
+        if &magic == ZIP_MAGIC {
+            return Ok(None);
+        }
+
+        let mut package = EAppxFile::from_source_with_limits(source, self.limits)?;
+        package.keys = self.keys.clone();
+        #[cfg(feature = "signing")]
+        {
+            package.signer = self.signer.clone();
+        }
+        Ok(Some(package))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUNDLE_MANIFEST: &str = include_str!("../testdata/manifest_bundle.xml");
+
+    #[test]
+    fn test_decrypted_file_name() {
+        assert_eq!(decrypted_file_name("TestApp_1.0.3.0_x64.emsix"), "TestApp_1.0.3.0_x64.msix");
+        assert_eq!(decrypted_file_name("TestApp.EAPPX"), "TestApp.APPX");
+        assert_eq!(decrypted_file_name("TestApp_1.0.3.0_x64.msix"), "TestApp_1.0.3.0_x64.msix");
+    }
+
+    #[test]
+    fn test_patch_bundle_manifest() {
+        let location = PackageLocation {
+            original_name: "SomeGame_1.5.54.2_scale-100.msix".into(),
+            name: "SomeGame_1.5.54.2_scale-100.msix".into(),
+            offset: 54,
+            size: 1234,
+        };
+        let patched = patch_bundle_manifest(BUNDLE_MANIFEST, std::slice::from_ref(&location)).unwrap();
+        assert!(patched.contains(r#"FileName="SomeGame_1.5.54.2_scale-100.msix" Offset="54" Size="1234""#));
+        assert_eq!(patched.len(), BUNDLE_MANIFEST.len() - "392".len() + "54".len() - "576406".len() + "1234".len());
+
+        let missing = PackageLocation { original_name: "Missing.emsix".into(), ..location };
+        assert!(patch_bundle_manifest(BUNDLE_MANIFEST, &[missing]).is_err());
+    }
+
+    #[cfg(feature = "zip")]
+    mod zip_output {
+        use std::io::{Cursor, Seek};
+
+        use super::super::*;
+
+        const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+        const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+        const MSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.msix");
+
+        fn convert(package: &[u8]) -> Vec<u8> {
+            let mut eappx = EAppxFile::from_source(package).unwrap();
+            eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+            eappx.convert_to_msix(package, vec![]).unwrap()
+        }
+
+        fn read<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, name: &str) -> Vec<u8> {
+            let mut data = vec![];
+            archive.by_name(name).unwrap().read_to_end(&mut data).unwrap();
+            data
+        }
+
+        /// All hash values of a blockmap, sorted
+        fn blockmap_hashes(blockmap: &[u8]) -> Vec<String> {
+            let mut hashes = String::from_utf8_lossy(blockmap)
+                .split(" Hash=\"")
+                .skip(1)
+                .map(|value| value.split('"').next().unwrap().to_owned())
+                .collect::<Vec<_>>();
+            hashes.sort();
+            hashes
+        }
+
+        /// Recompute the Appx digest of a signed zip and compare with the one it carries
+        #[cfg(feature = "signing")]
+        fn check_appx_digest(package: &[u8]) {
+            use crate::signing::AppxDigest;
+
+            let mut archive = zip::ZipArchive::new(Cursor::new(package)).unwrap();
+            let signature = read(&mut archive, "AppxSignature.p7x");
+            let blob_start = signature.windows(8).position(|window| window == b"APPXAXPC").unwrap();
+            let blob_length = signature[blob_start - 1] as usize;
+            let carried = AppxDigest::from_bytes(&signature[blob_start..blob_start + blob_length]).unwrap();
+
+            // Signature entry comes last, drop it from the directory and rebase the end records
+            let signature_offset = archive.by_name("AppxSignature.p7x").unwrap().header_start() as usize;
+            let zip64_end = package.windows(4).rposition(|window| window == ZIP64_END_SIGNATURE.to_le_bytes()).unwrap();
+            let directory_offset = u64::from_le_bytes(package[zip64_end + 48..zip64_end + 56].try_into().unwrap()) as usize;
+            let mut entries = vec![];
+            let mut position = directory_offset;
+            while position < zip64_end {
+                let lengths = |at: usize| u16::from_le_bytes(package[position + at..position + at + 2].try_into().unwrap()) as usize;
+                let length = 46 + lengths(28) + lengths(30) + lengths(32);
+                entries.push(&package[position..position + length]);
+                position += length;
+            }
+            let directory = entries[..entries.len() - 1].concat();
+            let mut end = package[zip64_end..].to_vec();
+            end[24..32].copy_from_slice(&(entries.len() as u64 - 1).to_le_bytes());
+            end[32..40].copy_from_slice(&(entries.len() as u64 - 1).to_le_bytes());
+            end[40..48].copy_from_slice(&(directory.len() as u64).to_le_bytes());
+            end[48..56].copy_from_slice(&(signature_offset as u64).to_le_bytes());
+            end[64..72].copy_from_slice(&((signature_offset + directory.len()) as u64).to_le_bytes());
+
+            let catalog = archive.index_for_name("AppxMetadata/CodeIntegrity.cat").is_some()
+                .then(|| Sha256::digest(read(&mut archive, "AppxMetadata/CodeIntegrity.cat")).to_vec());
+            assert_eq!(carried, AppxDigest {
+                zip_records: Sha256::digest(&package[..signature_offset]).to_vec(),
+                central_directory: Sha256::digest([directory, end].concat()).to_vec(),
+                content_types: Sha256::digest(read(&mut archive, "[Content_Types].xml")).to_vec(),
+                blockmap: Sha256::digest(read(&mut archive, "AppxBlockMap.xml")).to_vec(),
+                code_integrity: catalog,
+            });
+        }
+
+        #[cfg(feature = "signing")]
+        #[test]
+        fn test_convert_signed() {
+            // Check the digest layout against MakeAppx first
+            check_appx_digest(MSIX);
+
+            let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+            eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+            eappx.signer = Some(crate::signing::test_identity());
+            let converted = eappx.convert_to_msix(EMSIX, vec![]).unwrap();
+            check_appx_digest(&converted);
+
+            let mut archive = zip::ZipArchive::new(Cursor::new(converted.as_slice())).unwrap();
+            assert_eq!(archive.file_names().last(), Some("AppxSignature.p7x"));
+            let content_types = String::from_utf8(read(&mut archive, "[Content_Types].xml")).unwrap();
+            assert!(content_types.contains(r#"<Override PartName="/AppxSignature.p7x" ContentType="application/vnd.ms-appx.signature"/>"#));
+        }
+
+        #[test]
+        fn test_convert_package() {
+            let mut converted = zip::ZipArchive::new(Cursor::new(convert(EMSIX))).unwrap();
+            let mut reference = zip::ZipArchive::new(Cursor::new(MSIX)).unwrap();
+
+            let names = converted.file_names().collect::<Vec<_>>();
+            assert_eq!(names.len(), reference.len() - 1, "Everything but the signature");
+            let tail = ["AppxManifest.xml", "AppxBlockMap.xml", "[Content_Types].xml", "AppxMetadata/CodeIntegrity.cat"];
+            assert_eq!(converted.file_names().collect::<Vec<_>>()[names.len() - 4..], tail);
+
+            for name in ["TestApp.dll", "Assets/StoreLogo.png", "AppxManifest.xml"] {
+                assert_eq!(read(&mut converted, name), read(&mut reference, name), "{name}");
+            }
+
+            // Same plaintext, same block and file hashes as MakeAppx output
+            let blockmap = read(&mut converted, "AppxBlockMap.xml");
+            assert_eq!(blockmap_hashes(&blockmap), blockmap_hashes(&read(&mut reference, "AppxBlockMap.xml")));
+            assert!(String::from_utf8_lossy(&blockmap).contains(r#"<File Name="Assets\StoreLogo.png" Size="1451" LfhSize="50">"#));
+        }
+
+        #[test]
+        fn test_generate_code_integrity() {
+            let eappx = EAppxFile::from_source(EMSIX).unwrap();
+            let expected = eappx.read_code_integrity(EMSIX).unwrap().unwrap();
+
+            let mut reference = zip::ZipArchive::new(Cursor::new(MSIX)).unwrap();
+            let mut writer = MsixWriter::new(Cursor::new(vec![]), ContainerKind::Package).generate_code_integrity();
+            let names = reference.file_names().map(str::to_owned).collect::<Vec<_>>();
+            for name in names.iter().filter(|name| !name.starts_with("Appx") && !name.starts_with('[')) {
+                writer.add_file(name, &mut read(&mut reference, name).as_slice()).unwrap();
+            }
+            let package = writer.finish(None).unwrap().into_inner();
+
+            // Only catalog identifier and timestamp differ from what MakeAppx generated
+            let mut archive = zip::ZipArchive::new(Cursor::new(package)).unwrap();
+            let catalog = read(&mut archive, "AppxMetadata/CodeIntegrity.cat");
+            assert_eq!(catalog.len(), expected.len());
+            assert!(catalog.iter().zip(&expected).filter(|(a, b)| a != b).count() <= 16 + 13);
+        }
+
+        #[test]
+        fn test_add_file_with() {
+            let mut writer = MsixWriter::new(Cursor::new(vec![]), ContainerKind::Package);
+            let data = [b'A'; 0x40];
+            writer.add_file("Assets\\Logo.png", &mut &data[..]).unwrap();
+            writer.add_file_with("Assets\\Forced.png", &mut &data[..], true).unwrap();
+            writer.add_file_with("notes.txt", &mut &data[..], false).unwrap();
+            writer.add_file_with("empty.txt", &mut b"".as_slice(), true).unwrap();
+            let package = writer.finish(None).unwrap().into_inner();
+
+            let mut archive = zip::ZipArchive::new(Cursor::new(package)).unwrap();
+            for (name, method) in [
+                ("Assets/Logo.png", zip::CompressionMethod::Stored),
+                ("Assets/Forced.png", zip::CompressionMethod::Deflated),
+                ("notes.txt", zip::CompressionMethod::Stored),
+                ("empty.txt", zip::CompressionMethod::Stored),
+            ] {
+                assert_eq!(archive.by_name(name).unwrap().compression(), method, "{name}");
+            }
+            assert_eq!(read(&mut archive, "Assets/Forced.png"), data);
+        }
+
+        #[test]
+        fn test_convert_bundle() {
+            let bundle = convert(EMSIXBUNDLE);
+            let mut converted = zip::ZipArchive::new(Cursor::new(bundle.as_slice())).unwrap();
+            assert_eq!(converted.file_names().collect::<Vec<_>>(), [
+                "TestApp_1.0.3.0_x64.msix",
+                "AppxMetadata/AppxBundleManifest.xml",
+                "AppxBlockMap.xml",
+                "[Content_Types].xml",
+            ]);
+
+            let (offset, size) = {
+                let package = converted.by_name("TestApp_1.0.3.0_x64.msix").unwrap();
+                (package.data_start(), package.size())
+            };
+            let manifest = String::from_utf8(read(&mut converted, "AppxMetadata/AppxBundleManifest.xml")).unwrap();
+            assert!(manifest.contains(&format!(r#"FileName="TestApp_1.0.3.0_x64.msix" Offset="{offset}" Size="{size}""#)));
+            assert!(manifest.contains("<b4:Dependencies>"));
+
+            let blockmap = String::from_utf8(read(&mut converted, "AppxBlockMap.xml")).unwrap();
+            assert_eq!(blockmap.matches("<File ").count(), 1);
+            assert!(blockmap.contains(r#"<File Name="AppxMetadata\AppxBundleManifest.xml""#));
+
+            // Inner package is a regular msix again, readable straight from the recorded offset
+            let package = &bundle[offset as usize..(offset + size) as usize];
+            let mut package = zip::ZipArchive::new(Cursor::new(package)).unwrap();
+            let mut reference = zip::ZipArchive::new(Cursor::new(MSIX)).unwrap();
+            assert_eq!(read(&mut package, "TestApp.dll"), read(&mut reference, "TestApp.dll"));
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! One-call summary of a package
+//!
+//! Identity from the manifest, sizes from the footer table and encryption
+//! and footprint details from the header, for catalog and dashboard tooling
+//! that does not want to stitch those together itself.
+
+use crate::{
+    error::Error,
+    footprint::FootprintFile,
+    identity::PackageVersion,
+    keys::KeyId,
+    manifest::Application,
+    signature::{SignatureInfo, SigningStatus},
+    source::PackageSource,
+    EAppxFile,
+    Manifest,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PackageInfo {
+    pub name: String,
+    pub publisher: String,
+    pub version: PackageVersion,
+    /// Processor architecture, bundles declare none
+    pub architecture: Option<String>,
+    /// As stored in the header
+    pub package_full_name: String,
+    pub is_bundle: bool,
+    /// Files listed in the blockmap
+    pub file_count: usize,
+    /// Payloads in a bundle, 0 for packages
+    pub package_count: usize,
+    /// Applications the manifest declares, bundles declare none
+    pub applications: Vec<Application>,
+    /// The package itself, or every payload listed in the bundle manifest
+    pub payloads: Vec<PayloadArchitecture>,
+    /// Sum of the uncompressed lengths of all footers
+    pub total_size: u64,
+    /// Sum of what all footers occupy in the container
+    pub stored_size: u64,
+    /// Size of the container itself
+    pub file_len: u64,
+    pub crypto_algo: String,
+    pub key_ids: Vec<KeyId>,
+    pub signed: bool,
+    /// Signer of AppxSignature.p7x, `None` if unsigned or unreadable
+    pub signature: Option<SignatureInfo>,
+    /// Signature, signer and test key condensed into one level
+    pub signing_status: SigningStatus,
+    pub code_integrity: bool,
+}
+
+impl EAppxFile {
+    /// Summarize the package, reads the manifest from `stream`
+    pub fn package_info<P: PackageSource + ?Sized>(&self, source: &P) -> Result<PackageInfo, Error> {
+        let is_bundle = self.header.is_bundle();
+        let (identity, package_count, applications, payloads) = match self.manifest(source)? {
+            Manifest::Manifest(manifest) => {
+                let payload = PayloadArchitecture { file_name: None, architecture: manifest.identity.arch.clone(), is_resource: manifest.is_resource_package() };
+                (manifest.identity.clone(), 0, manifest.applications(), vec![payload])
+            },
+            Manifest::BundleManifest(manifest) => {
+                let payloads = manifest.packages.package.iter()
+                    .map(|package| PayloadArchitecture { file_name: Some(package.filename.clone()), architecture: package.arch.clone(), is_resource: package.is_resource() })
+                    .collect();
+                (manifest.identity.clone(), manifest.packages.package.len(), vec![], payloads)
+            },
+        };
+
+        // A signature this crate cannot make sense of should not hide the rest
+        let signature = self.read_footprint_file(source, FootprintFile::Signature)?
+            .and_then(|p7x| SignatureInfo::from_p7x(&p7x)
+                .inspect_err(|e| log::warn!("Failed to parse AppxSignature.p7x: {e}"))
+                .ok());
+
+        Ok(PackageInfo {
+            name: identity.name,
+            publisher: identity.publisher,
+            version: identity.version,
+            architecture: identity.arch,
+            package_full_name: self.header.package_full_name(),
+            is_bundle,
+            file_count: self.blockmap.files.len(),
+            package_count,
+            applications,
+            payloads,
+            total_size: self.footers.iter().fold(0u64, |total, footer| total.saturating_add(footer.uncompressed_length)),
+            stored_size: self.footers.iter().fold(0u64, |total, footer| total.saturating_add(self.stored_length(footer))),
+            file_len: self.file_len,
+            crypto_algo: self.header.crypto_algo(),
+            key_ids: self.header.key_ids.clone(),
+            signed: self.header.is_signed(),
+            signing_status: SigningStatus::new(self.header.is_signed(), signature.as_ref(), &self.header.key_ids),
+            signature,
+            code_integrity: self.header.is_code_integrity_protected(),
+        })
+    }
+}
+
+impl PackageInfo {
+    /// Distinct architectures of the application payloads, `neutral` for those declaring none
+    pub fn architectures(&self) -> Vec<&str> {
+        let mut architectures = self.payloads.iter()
+            .filter(|payload| !payload.is_resource)
+            .map(PayloadArchitecture::name)
+            .collect::<Vec<_>>();
+        architectures.sort_unstable();
+        architectures.dedup();
+        architectures
+    }
+
+    /// Payloads only carrying assets, installed along any architecture
+    pub fn resource_count(&self) -> usize {
+        self.payloads.iter().filter(|payload| payload.is_resource).count()
+    }
+}
+
+/// Architecture of a package, or of one payload of a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadArchitecture {
+    /// Name of the payload in the bundle, `None` for the package itself
+    pub file_name: Option<String>,
+    /// As declared, `None` if the manifest declares none
+    pub architecture: Option<String>,
+    pub is_resource: bool,
+}
+
+impl PayloadArchitecture {
+    /// Declared architecture, `neutral` if there is none
+    pub fn name(&self) -> &str {
+        self.architecture.as_deref().unwrap_or("neutral")
+    }
+
+    pub fn is_neutral(&self) -> bool {
+        self.name().eq_ignore_ascii_case("neutral")
+    }
+}
+
+impl std::fmt::Display for PackageInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Name: {}", self.name)?;
+        writeln!(f, "Publisher: {}", self.publisher)?;
+        writeln!(f, "Version: {}", self.version)?;
+        writeln!(f, "Architecture: {}", self.architecture.as_deref().unwrap_or("-"))?;
+        match self.resource_count() {
+            0 => writeln!(f, "Architectures: {}", self.architectures().join(", "))?,
+            resources => writeln!(f, "Architectures: {} (+{resources} resource package(s))", self.architectures().join(", "))?,
+        }
+        writeln!(f, "Package full name: {}", self.package_full_name)?;
+        writeln!(f, "Bundle: {}", self.is_bundle)?;
+        writeln!(f, "Files: {}", self.file_count)?;
+        if self.is_bundle {
+            writeln!(f, "Packages: {}", self.package_count)?;
+        }
+        for application in &self.applications {
+            writeln!(f, "Application: {application}")?;
+        }
+        writeln!(f, "Size: {:#x} (stored: {:#x}, container: {:#x})", self.total_size, self.stored_size, self.file_len)?;
+        writeln!(f, "Crypto: {}", self.crypto_algo)?;
+        for key_id in &self.key_ids {
+            writeln!(f, "Key id: {key_id}")?;
+        }
+        writeln!(f, "Signed: {} ({})", self.signed, self.signing_status)?;
+        if let Some(signature) = &self.signature {
+            write!(f, "{signature}")?;
+        }
+        writeln!(f, "Code integrity: {}", self.code_integrity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PackageVersion, PayloadArchitecture};
+    use crate::{signature::SigningStatus, EAppxFile};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    #[test]
+    fn test_package_info() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let mut info = eappx.package_info(EMSIX).unwrap();
+        assert_eq!(info.name, "8b18b0ca-7bac-4263-8be8-9a7a1292c90d");
+        assert!(info.package_full_name.starts_with(&info.name));
+        assert_eq!(info.version, PackageVersion::new(1, 0, 3, 0));
+        assert_eq!(info.architecture.as_deref(), Some("x64"));
+        assert_eq!(info.package_full_name, eappx.header.package_full_name());
+        assert!(!info.is_bundle);
+        assert_eq!(info.file_count, eappx.blockmap.files.len());
+        assert!(info.stored_size <= info.file_len);
+        assert_eq!(info.crypto_algo, "XTS-AES");
+        assert_eq!(info.key_ids, eappx.header.key_ids);
+        assert_eq!(info.signature, None);
+        assert_eq!(info.signing_status, SigningStatus::Unsigned);
+        assert!(!info.applications.is_empty());
+        assert_eq!(info.architectures(), vec!["x64"]);
+        assert_eq!(info.payloads[0].file_name, None);
+
+        // Distinct application architectures, resources counted apart
+        let payload = |architecture: Option<&str>, is_resource| PayloadArchitecture { file_name: None, architecture: architecture.map(Into::into), is_resource };
+        info.payloads.extend([payload(Some("arm64"), false), payload(Some("x64"), false), payload(None, false), payload(None, true)]);
+        assert_eq!(info.architectures(), vec!["arm64", "neutral", "x64"]);
+        assert_eq!(info.resource_count(), 1);
+        assert!(info.applications.iter().all(|application| !application.id.is_empty()));
+
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let info = eappx.package_info(EMSIXBUNDLE).unwrap();
+        assert!(info.is_bundle);
+        assert_eq!(info.package_count, 1);
+        assert_eq!(info.architecture, None);
+        assert!(info.applications.is_empty());
+        assert_eq!(info.architectures(), vec!["x64"]);
+        assert_eq!(info.payloads.len(), info.package_count);
+        assert!(info.payloads.iter().all(|payload| payload.file_name.is_some() && !payload.is_neutral()));
+    }
+}
@@ -0,0 +1,389 @@
+//! Block pipeline behind [`EAppxFile::read_file`](crate::EAppxFile::read_file)
+//!
+//! The calling thread reads blocks as stored and writes their plaintext, one
+//! thread checks block hashes and decrypts, another one feeds the file hash.
+//! A fixed number of buffers circulates between them through bounded channels,
+//! so disk I/O overlaps with AES-XTS and SHA-256 instead of alternating with them.
+//!
+//! [`BlockVerifier`] does the same for verification, which records mismatches
+//! instead of stopping: blocks are checked on a second thread while the calling
+//! thread reads the next ones.
+//!
+//! Both measure the stages they run, reading is left to the caller.
+
+use std::sync::mpsc::sync_channel;
+
+use crate::{
+    container::ContainerParams,
+    crypto::CryptoFileContext,
+    digest::{HashAlgorithm, Hasher},
+    error::{EntryContext, Error, Operation},
+    timings::{Stage, Timings},
+};
+
+/// Blocks in flight, bounds every channel and the memory used
+const DEPTH: usize = 4;
+
+pub(crate) struct Block {
+    pub index: usize,
+    /// Block size of the container, the block starts at `index * size`
+    pub size: usize,
+    /// As stored, plaintext once decrypted
+    pub data: Vec<u8>,
+    /// Plaintext length, encrypted data is padded to sectors
+    pub len: usize,
+}
+
+impl Block {
+    fn new(index: usize, size: usize, data: Vec<u8>) -> Self {
+        Self { index, size, data, len: 0 }
+    }
+
+    pub fn pos(&self) -> u64 {
+        (self.index * self.size) as u64
+    }
+
+    pub fn plaintext(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    /// Whether `read` bytes hold the block as stored, `read_amount` with padding
+    ///
+    /// Only the last block of an encrypted file may lack the padding of its
+    /// last sector, when the data ends right after the file.
+    pub fn is_complete(&self, read: usize, read_amount: usize, padded_last: bool, params: &ContainerParams) -> bool {
+        read == read_amount || (padded_last && read >= self.len && params.is_decryptable_len(read))
+    }
+}
+
+pub(crate) struct BlockPipeline<'a> {
+    pub block_size: usize,
+    pub crypto: Option<&'a CryptoFileContext>,
+    /// Hashes of the blocks as stored, checked before decrypting
+    pub block_hashes: Option<&'a [Vec<u8>]>,
+    pub filehash: Option<&'a [u8]>,
+    pub block_hash_algorithm: HashAlgorithm,
+    pub filehash_algorithm: HashAlgorithm,
+    pub context: &'a (dyn Fn(Operation, u64) -> EntryContext + Sync),
+}
+
+impl BlockPipeline<'_> {
+    /// Run `block_count` blocks through, `read` fills a block as stored, `write` takes its plaintext
+    ///
+    /// Returns the time spent decrypting, hashing and writing.
+    pub fn run<R, W>(&self, block_count: usize, mut read: R, mut write: W) -> Result<Timings, Error>
+    where
+        R: FnMut(&mut Block) -> Result<(), Error>,
+        W: FnMut(&Block) -> Result<(), Error>,
+    {
+        let mut hasher = self.filehash.map(|_| self.filehash_algorithm.hasher());
+        let mut written = 0;
+        let mut timings = Timings::default();
+
+        // Not worth the threads
+        if block_count <= 1 {
+            for index in 0..block_count {
+                let mut block = Block::new(index, self.block_size, vec![]);
+                read(&mut block)?;
+                self.decrypt(&mut block, &mut timings)?;
+                if let Some(hasher) = hasher.as_mut() {
+                    timings.measure(Stage::Hash, block.len, || hasher.update(block.plaintext()));
+                }
+                timings.measure(Stage::Write, block.len, || write(&block))?;
+                written += block.len as u64;
+            }
+            self.check_filehash(hasher, written)?;
+            return Ok(timings);
+        }
+
+        std::thread::scope(|scope| {
+            let (to_decrypt, decrypt_queue) = sync_channel::<Block>(DEPTH);
+            let (to_hash, hash_queue) = sync_channel::<Result<Block, Error>>(DEPTH);
+            let (to_write, write_queue) = sync_channel::<Result<Block, Error>>(DEPTH);
+
+            let decrypting = scope.spawn(move || {
+                let mut timings = Timings::default();
+                for mut block in decrypt_queue {
+                    let result = self.decrypt(&mut block, &mut timings).map(|_| block);
+                    let failed = result.is_err();
+                    if to_hash.send(result).is_err() || failed {
+                        break;
+                    }
+                }
+                timings
+            });
+            let hashing = scope.spawn(move || {
+                let mut timings = Timings::default();
+                for result in hash_queue {
+                    if let (Ok(block), Some(hasher)) = (&result, hasher.as_mut()) {
+                        timings.measure(Stage::Hash, block.len, || hasher.update(block.plaintext()));
+                    }
+                    if to_write.send(result).is_err() {
+                        break;
+                    }
+                }
+                (hasher, timings)
+            });
+
+            // Nothing is sent while DEPTH blocks are in flight, so no stage blocks for good
+            let stopped = || Error::DataError("Block pipeline stopped unexpectedly".into());
+            let mut buffers = (0..DEPTH).map(|_| Vec::with_capacity(self.block_size)).collect::<Vec<_>>();
+            let (mut next, mut in_flight) = (0, 0);
+            while next < block_count || in_flight > 0 {
+                while next < block_count && in_flight < DEPTH {
+                    let mut block = Block::new(next, self.block_size, buffers.pop().unwrap_or_default());
+                    read(&mut block)?;
+                    if to_decrypt.send(block).is_err() {
+                        // A stage gave up, its error is on the way
+                        return Err(write_queue.iter().find_map(Result::err).unwrap_or_else(stopped));
+                    }
+                    next += 1;
+                    in_flight += 1;
+                }
+
+                let block = write_queue.recv().map_err(|_| stopped())??;
+                in_flight -= 1;
+                timings.measure(Stage::Write, block.len, || write(&block))?;
+                written += block.len as u64;
+                buffers.push(block.data);
+            }
+
+            drop(to_decrypt);
+            let (hasher, hash_timings) = hashing.join()
+                .map_err(|_| Error::DataError("Hashing thread panicked".into()))?;
+            timings += hash_timings;
+            timings += decrypting.join()
+                .map_err(|_| Error::DataError("Decrypting thread panicked".into()))?;
+            self.check_filehash(hasher, written)?;
+            Ok(timings)
+        })
+    }
+
+    /// Check the block hash, then decrypt in place
+    fn decrypt(&self, block: &mut Block, timings: &mut Timings) -> Result<(), Error> {
+        if let Some(block_hash) = self.block_hashes.and_then(|hashes| hashes.get(block.index)) {
+            log::trace!("Verifying block {}, block size: {:#X}", block.index, block.data.len());
+            let digest = timings.measure(Stage::Hash, block.data.len(), || self.block_hash_algorithm.digest(&block.data));
+            if digest != *block_hash {
+                return Err((self.context)(Operation::Verify, block.pos())
+                    .wrap(Error::DataError(format!("Invalid block hash for block {}", block.index))));
+            }
+        }
+
+        if let Some(crypto) = self.crypto {
+            let pos = block.pos();
+            timings.measure(Stage::Decrypt, block.data.len(), || crypto.decrypt_at(&mut block.data, pos));
+        }
+
+        Ok(())
+    }
+
+    fn check_filehash(&self, hasher: Option<Hasher>, end: u64) -> Result<(), Error> {
+        if let (Some(hasher), Some(filehash)) = (hasher, self.filehash) {
+            if hasher.finalize() != filehash {
+                return Err((self.context)(Operation::Verify, end).wrap(Error::DataError("Hash mismatch for file".into())));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks blocks for [`EAppxFile::verify`](crate::EAppxFile::verify), failures are recorded
+pub(crate) struct BlockVerifier<'a> {
+    pub block_size: usize,
+    /// Hashes of the blocks as stored, checked before decrypting
+    pub block_hashes: Option<&'a [Vec<u8>]>,
+    pub block_hash_algorithm: HashAlgorithm,
+    pub crypto: Option<&'a CryptoFileContext>,
+    /// Fed with the plaintext, `None` if the file hash is not checked
+    pub hasher: Option<Hasher>,
+    pub failed_blocks: Vec<usize>,
+    /// Time spent hashing and decrypting
+    pub timings: Timings,
+}
+
+impl BlockVerifier<'_> {
+    /// Check `block_count` blocks, `read` fills a block as stored and tells whether it inflated
+    pub fn run<R>(self, block_count: usize, read: R) -> Result<Self, Error>
+    where
+        R: FnMut(&mut Block) -> Result<bool, Error>,
+    {
+        // Not worth the threads, nor is a second one on a single core
+        let single_core = std::thread::available_parallelism().map_or(true, |cores| cores.get() == 1);
+        match block_count <= 1 || single_core {
+            true => self.run_inline(block_count, read),
+            false => self.run_threaded(block_count, read),
+        }
+    }
+
+    fn run_inline<R>(mut self, block_count: usize, mut read: R) -> Result<Self, Error>
+    where
+        R: FnMut(&mut Block) -> Result<bool, Error>,
+    {
+        let mut block = Block::new(0, self.block_size, vec![]);
+        for index in 0..block_count {
+            block.index = index;
+            let inflated = read(&mut block)?;
+            self.check(&mut block, inflated);
+        }
+        Ok(self)
+    }
+
+    fn run_threaded<R>(mut self, block_count: usize, mut read: R) -> Result<Self, Error>
+    where
+        R: FnMut(&mut Block) -> Result<bool, Error>,
+    {
+        let block_size = self.block_size;
+        std::thread::scope(|scope| {
+            let (to_check, check_queue) = sync_channel::<(Block, bool)>(DEPTH);
+            let (to_reuse, reuse_queue) = sync_channel::<Vec<u8>>(DEPTH);
+
+            let checking = scope.spawn(move || {
+                for (mut block, inflated) in check_queue {
+                    self.check(&mut block, inflated);
+                    // Dropped if the reader has enough buffers
+                    let _ = to_reuse.try_send(block.data);
+                }
+                self
+            });
+
+            for index in 0..block_count {
+                let data = reuse_queue.try_recv().unwrap_or_else(|_| Vec::with_capacity(block_size));
+                let mut block = Block::new(index, block_size, data);
+                let inflated = read(&mut block)?;
+                if to_check.send((block, inflated)).is_err() {
+                    break;
+                }
+            }
+
+            drop(to_check);
+            checking.join()
+                .map_err(|_| Error::DataError("Verifying thread panicked".into()))
+        })
+    }
+
+    /// Check the block hash, then decrypt in place and feed the file hash
+    fn check(&mut self, block: &mut Block, inflated: bool) {
+        if !inflated {
+            log::warn!("Failed to inflate block {}", block.index);
+            self.failed_blocks.push(block.index);
+        } else if let Some(block_hash) = self.block_hashes.and_then(|hashes| hashes.get(block.index)) {
+            log::trace!("Verifying block {}, block size: {:#X}", block.index, block.data.len());
+            let digest = self.timings.measure(Stage::Hash, block.data.len(), || self.block_hash_algorithm.digest(&block.data));
+            if digest != *block_hash {
+                log::warn!("Invalid block hash for block {}", block.index);
+                self.failed_blocks.push(block.index);
+            }
+        }
+
+        if let Some(hasher) = self.hasher.as_mut() {
+            if let Some(crypto) = self.crypto {
+                let pos = block.pos();
+                self.timings.measure(Stage::Decrypt, block.data.len(), || crypto.decrypt_at(&mut block.data, pos));
+            }
+            self.timings.measure(Stage::Hash, block.len, || hasher.update(block.plaintext()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use sha2::{Digest, Sha256};
+
+    use super::{Block, BlockPipeline, BlockVerifier};
+    use crate::{digest::HashAlgorithm, error::{EntryContext, Error, Operation}, utils};
+
+    fn run(data: &[u8], block_hashes: &[Vec<u8>], filehash: &[u8], filehash_algorithm: HashAlgorithm) -> Result<Vec<u8>, Error> {
+        let context = |operation, position| EntryContext { operation, name: None, file_id: None, offset: 0, position };
+        let pipeline = BlockPipeline {
+            block_size: utils::BLOCK_SIZE,
+            crypto: None,
+            block_hashes: Some(block_hashes),
+            filehash: Some(filehash),
+            block_hash_algorithm: HashAlgorithm::Sha256,
+            filehash_algorithm,
+            context: &context,
+        };
+
+        let mut reader = data;
+        let mut out = vec![];
+        pipeline.run(
+            data.len().div_ceil(utils::BLOCK_SIZE),
+            |block: &mut Block| {
+                block.len = std::cmp::min(block.size, data.len() - block.pos() as usize);
+                block.data.resize(block.len, 0);
+                Ok(reader.read_exact(&mut block.data)?)
+            },
+            |block: &Block| {
+                out.extend_from_slice(block.plaintext());
+                Ok(())
+            },
+        )?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let data = (0..utils::BLOCK_SIZE * 9 + 0x123).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut block_hashes = data.chunks(utils::BLOCK_SIZE).map(|block| Sha256::digest(block).to_vec()).collect::<Vec<_>>();
+        let filehash = Sha256::digest(&data).to_vec();
+
+        assert_eq!(run(&data, &block_hashes, &filehash, HashAlgorithm::Sha256).unwrap(), data);
+
+        // File hash of another digest than the blocks
+        let sha512 = HashAlgorithm::Sha512.digest(&data);
+        assert_eq!(run(&data, &block_hashes, &sha512, HashAlgorithm::Sha512).unwrap(), data);
+        assert!(run(&data, &block_hashes, &sha512, HashAlgorithm::Sha256).is_err());
+
+        let err = run(&data, &block_hashes, &[0u8; 32], HashAlgorithm::Sha256).unwrap_err();
+        assert_eq!(err.entry().map(|context| (context.operation, context.position)), Some((Operation::Verify, data.len() as u64)));
+
+        block_hashes[6][0] ^= 0xFF;
+        let err = run(&data, &block_hashes, &filehash, HashAlgorithm::Sha256).unwrap_err();
+        assert_eq!(err.entry().map(|context| (context.operation, context.position)), Some((Operation::Verify, 0x60000)));
+    }
+
+    #[test]
+    fn test_verifier() {
+        let data = (0..utils::BLOCK_SIZE * 9 + 0x123).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut block_hashes = data.chunks(utils::BLOCK_SIZE).map(|block| Sha256::digest(block).to_vec()).collect::<Vec<_>>();
+        block_hashes[6][0] ^= 0xFF;
+
+        let verify = |block_count: usize, threaded: bool| {
+            let mut reader = data.as_slice();
+            let verifier = BlockVerifier {
+                block_size: utils::BLOCK_SIZE,
+                block_hashes: Some(&block_hashes),
+                block_hash_algorithm: HashAlgorithm::Sha256,
+                crypto: None,
+                hasher: Some(HashAlgorithm::Sha512.hasher()),
+                failed_blocks: vec![],
+                timings: Default::default(),
+            };
+            let read = |block: &mut Block| {
+                let left = data.len().checked_sub(block.pos() as usize)
+                    .ok_or(Error::DataError("Read past the end".into()))?;
+                block.len = std::cmp::min(block.size, left);
+                block.data.resize(block.len, 0);
+                reader.read_exact(&mut block.data)?;
+                Ok(block.index != 2)
+            };
+            match threaded {
+                true => verifier.run_threaded(block_count, read),
+                false => verifier.run_inline(block_count, read),
+            }
+        };
+
+        for threaded in [false, true] {
+            // Failures are recorded in order, the file hash still covers every block
+            let verifier = verify(data.len().div_ceil(utils::BLOCK_SIZE), threaded).unwrap();
+            assert_eq!(verifier.failed_blocks, [2, 6]);
+            assert_eq!(verifier.hasher.unwrap().finalize(), HashAlgorithm::Sha512.digest(&data));
+
+            assert!(verify(1, threaded).unwrap().failed_blocks.is_empty());
+            assert!(verify(11, threaded).is_err());
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! The types most programs need, for a single glob import
+//!
+//! ```
+//! # #[cfg(feature = "package")] {
+//! use eappx::prelude::*;
+//!
+//! let package = eappx::sample::build_sample_package()?;
+//! let mut eappx = EAppxFile::from_source_with_limits(&package, ResourceLimits::untrusted())?;
+//! eappx.extract_options = ExtractOptions::default().flat(true);
+//! assert!(eappx.verify(&package).is_valid());
+//! # }
+//! # Ok::<(), eappx::error::Error>(())
+//! ```
+//!
+//! Everything else stays in its module. Structs the crate hands out, such as
+//! reports, are `#[non_exhaustive]`, and so are option structs, which are
+//! set through their builder methods. New fields therefore do not break
+//! downstream code.
+
+pub use crate::{
+    container::{EAppxFooter, EAppxHeader, EAppxMagic, FileInfo},
+    error::Error,
+    keys::{KeyCollection, KeyId},
+    limits::ResourceLimits,
+};
+
+#[cfg(feature = "package")]
+pub use crate::{
+    bundle_manifest::BundleFilter,
+    extraction_report::{ExtractedFile, ExtractionFailure, ExtractionReport, ExtractionStatus},
+    layout::ExtractOptions,
+    package_info::PackageInfo,
+    verify::{FileStatus, IntegrityPolicy, VerificationReport},
+    writer::{EntryOptions, PackageWriter, WriterOptions},
+    EAppxFile,
+    Manifest,
+};
+
+#[cfg(feature = "signature")]
+pub use crate::signature::{SignatureInfo, SigningStatus};
@@ -0,0 +1,193 @@
+//! Python bindings, built as the `eappx` extension module with the `python` feature
+//!
+//! ```python
+//! import eappx
+//! keys = eappx.KeyCollection.from_file("keys.txt")
+//! package = eappx.EAppxFile("TestApp.emsix")
+//! package.load_keys(keys)
+//! for entry in package.entries():
+//!     print(entry.name, entry.size)
+//! manifest = package.read("AppxManifest.xml")
+//! package.extract("out")
+//! ```
+
+use std::{io::Read, path::PathBuf, str::FromStr};
+
+use pyo3::{create_exception, exceptions::{PyException, PyIOError}, prelude::*, types::PyBytes};
+
+use crate::{error::Error, keys::KeyCollection, EAppxFile};
+
+create_exception!(eappx, EAppxError, PyException, "Invalid or unsupported package data");
+
+impl From<Error> for PyErr {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::IoError(e) => PyIOError::new_err(e.to_string()),
+            err => EAppxError::new_err(err.to_string()),
+        }
+    }
+}
+
+/// Content keys by key-id
+#[pyclass(name = "KeyCollection", module = "eappx")]
+#[derive(Default)]
+struct PyKeyCollection(KeyCollection);
+
+#[pymethods]
+impl PyKeyCollection {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse keyfile content
+    #[staticmethod]
+    fn parse(text: &str) -> Result<Self, Error> {
+        KeyCollection::from_str(text).map(Self)
+    }
+
+    #[staticmethod]
+    fn from_file(path: PathBuf) -> Result<Self, Error> {
+        KeyCollection::from_reader(&mut std::fs::File::open(path)?).map(Self)
+    }
+
+    /// The global test key, as `makeappx --kt`
+    #[staticmethod]
+    fn test_keys() -> Self {
+        Self(KeyCollection::with_test_key())
+    }
+
+    /// Take over all keys of `other`, leaving it empty
+    fn merge(&mut self, other: &mut Self) {
+        self.0.merge(std::mem::take(&mut other.0));
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.keys.len()
+    }
+}
+
+/// A blockmap file
+#[pyclass(name = "Entry", module = "eappx", get_all, frozen)]
+struct PyEntry {
+    /// Path inside the package, `\` separated
+    name: String,
+    size: u64,
+    compressed: bool,
+    encrypted: bool,
+}
+
+#[pymethods]
+impl PyEntry {
+    fn __repr__(&self) -> String {
+        format!("Entry({:?}, size={})", self.name, self.size)
+    }
+}
+
+/// An EAppx/EMsix package or bundle, opened from a path
+#[pyclass(name = "EAppxFile", module = "eappx")]
+struct PyEAppxFile {
+    path: PathBuf,
+    eappx: EAppxFile,
+}
+
+impl PyEAppxFile {
+    fn open(&self) -> Result<std::fs::File, Error> {
+        Ok(std::fs::File::open(&self.path)?)
+    }
+}
+
+#[pymethods]
+impl PyEAppxFile {
+    #[new]
+    fn new(path: PathBuf) -> Result<Self, Error> {
+        let eappx = EAppxFile::from_source(&std::fs::File::open(&path)?)?;
+        Ok(Self { path, eappx })
+    }
+
+    #[getter]
+    fn package_full_name(&self) -> String {
+        self.eappx.header.package_full_name()
+    }
+
+    #[getter]
+    fn is_bundle(&self) -> bool {
+        self.eappx.header.is_bundle()
+    }
+
+    /// Key-ids the package needs, as in keyfiles
+    #[getter]
+    fn key_ids(&self) -> Vec<String> {
+        self.eappx.header.key_ids.iter().map(|key_id| key_id.to_keyfile_string()).collect()
+    }
+
+    /// Whether `keys` holds every key the package needs
+    fn has_required_keys(&self, keys: &PyKeyCollection) -> bool {
+        keys.0.has_required_keys(&self.eappx.header.key_ids)
+    }
+
+    /// Key-ids the package needs that `keys` lacks, as in keyfiles
+    fn missing_keys(&self, keys: &PyKeyCollection) -> Vec<String> {
+        keys.0.missing_keys(&self.eappx.header.key_ids).iter().map(|key_id| key_id.to_keyfile_string()).collect()
+    }
+
+    fn load_keys(&mut self, keys: &PyKeyCollection) -> Result<(), Error> {
+        self.eappx.load_keys(&keys.0)
+    }
+
+    /// Blockmap files, in blockmap order
+    fn entries(&self) -> Vec<PyEntry> {
+        self.eappx.blockmap.files.iter()
+            .map(|file| {
+                let footer = self.eappx.find_footer_for_file(file.id());
+                PyEntry {
+                    name: file.name.clone(),
+                    size: file.size,
+                    compressed: footer.is_some_and(|footer| footer.compression_type == 0x1),
+                    encrypted: footer.is_some_and(|footer| self.eappx.is_footer_encrypted(footer)),
+                }
+            })
+            .collect()
+    }
+
+    /// Plaintext of the blockmap file `name`, matched case-insensitively
+    fn read<'py>(&self, py: Python<'py>, name: &str) -> PyResult<Bound<'py, PyBytes>> {
+        let data = py.allow_threads(|| -> Result<Vec<u8>, Error> {
+            let mut data = vec![];
+            self.eappx.entry_reader(self.open()?, name)?.read_to_end(&mut data)?;
+            Ok(data)
+        })?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    /// Extract everything below `directory`, returns the written paths relative to it
+    ///
+    /// Raises if a file does not match its hash.
+    fn extract(&self, py: Python<'_>, directory: PathBuf) -> Result<Vec<PathBuf>, Error> {
+        py.allow_threads(|| {
+            std::fs::create_dir_all(&directory)?;
+            let report = self.eappx.extract_with_report(&self.open()?, &directory)?;
+            if !report.is_valid() {
+                let names = report.mismatched().map(|file| file.package_path.as_str()).collect::<Vec<_>>();
+                return Err(Error::DataError(format!("Extracted files do not match their hashes: {}", names.join(", "))));
+            }
+            Ok(report.files.into_iter()
+                .filter(|file| file.status.is_written())
+                .map(|file| file.path)
+                .collect())
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!("EAppxFile({:?})", self.eappx.header.package_full_name())
+    }
+}
+
+#[pymodule]
+fn eappx(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("EAppxError", m.py().get_type::<EAppxError>())?;
+    m.add_class::<PyKeyCollection>()?;
+    m.add_class::<PyEntry>()?;
+    m.add_class::<PyEAppxFile>()?;
+    Ok(())
+}
@@ -0,0 +1,422 @@
+//! Salvage packages whose footer table is damaged
+//!
+//! The footer table is the only index of where payloads are stored. When it is
+//! lost, the blockmap still lists every file with its id, size, encryption and,
+//! for compressed files, the stored size of each block. Payloads are written
+//! back to back in file id order, so their extents can be rebuilt from that,
+//! working forward from the header and backward from the blockmap. Footer
+//! records that survived are picked up by scanning and used where they agree
+//! with the blockmap, the blockmap itself is found by the header's blockmap hash.
+
+use std::{collections::BTreeMap, io::{Cursor, Read}};
+
+use binrw::BinRead;
+use flate2::{Decompress, FlushDecompress};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    blockmap::{self, AppxBlockMap},
+    container,
+    error::Error,
+    limits::ResourceLimits,
+    source::PackageSource,
+    EAppxFile, EAppxFooter, EAppxHeader, FileInfo, Manifest,
+};
+
+/// Every footer record starts with its magic and size, little endian
+const FOOTER_PREFIX: [u8; 4] = [0x45, 0x46, 0x28, 0x00];
+/// Amount of the package held in memory while scanning
+const SCAN_WINDOW: u64 = 0x100000;
+/// Input enough to get past the header of a dynamic deflate block
+const INFLATE_PROBE: usize = 0x200;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecoveryReport {
+    /// Footers taken over from the damaged footer table
+    pub found: usize,
+    /// Footers rebuilt from the blockmap or bundle manifest
+    pub rebuilt: usize,
+    /// Files that could not be located, left out of the blockmap
+    pub missing: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl RecoveryReport {
+    /// Whether the package opened without any recovery
+    pub fn is_intact(&self) -> bool {
+        self.found == 0 && self.rebuilt == 0 && self.missing.is_empty() && self.warnings.is_empty()
+    }
+
+    fn warn(&mut self, warning: String) {
+        log::warn!("{warning}");
+        self.warnings.push(warning);
+    }
+}
+
+impl std::fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Footers found: {}, rebuilt: {}", self.found, self.rebuilt)?;
+        for name in &self.missing {
+            writeln!(f, "Missing: {name}")?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {warning}")?;
+        }
+        Ok(())
+    }
+}
+
+impl EAppxFile {
+    /// Open a package, rebuilding the footer table if it is damaged
+    ///
+    /// Intact packages open like with [`EAppxFile::from_source_with_limits`]. Rebuilt
+    /// extents are a best guess, extract with hash checks to tell what came out intact.
+    pub fn recover<P: PackageSource + ?Sized>(source: &P, limits: ResourceLimits) -> Result<(Self, RecoveryReport), Error> {
+        let mut report = RecoveryReport::default();
+        let header = Self::peek_header(source)?;
+        let error = match Self::from_header(source, header, limits) {
+            Ok(eappx) => return Ok((eappx, report)),
+            Err(error) => error,
+        };
+        report.warn(format!("Footer table is unusable, recovering: {error}"));
+
+        let header = Self::peek_header(source)?;
+        let file_len = source.len()?;
+        let candidates = scan_footers(source, &header, file_len)?;
+        log::info!("Found {} footer records", candidates.values().map(Vec::len).sum::<usize>());
+
+        let (blockmap_footer, mut blockmap) = locate_blockmap(source, &header, file_len, &candidates, &limits, &mut report)?;
+        limits.check_file_count("Blockmap file count", blockmap.files.len() as u64)?;
+
+        let mut footers = BTreeMap::from([(blockmap_footer.file_id, blockmap_footer)]);
+        let mut pending = BTreeMap::new();
+        for file in &blockmap.files {
+            let expected = rebuild_footer(&header, file, 0);
+            let survived = candidates.get(&file.id()).into_iter().flatten()
+                .find(|candidate| agrees(candidate, &expected));
+            match survived {
+                Some(footer) => {
+                    report.found += 1;
+                    footers.insert(footer.file_id, footer.clone());
+                },
+                None => {
+                    pending.insert(file.id(), file);
+                },
+            }
+        }
+
+        if header.key_ids.len() > 1 && pending.values().any(|file| file.is_encrypted()) {
+            report.warn(format!("Package uses {} keys, assuming the first one for rebuilt footers", header.key_ids.len()));
+        }
+        report.rebuilt += place_files(&header, &mut footers, &mut pending);
+
+        // Whatever lies outside the package went missing, e.g. with a truncated download
+        footers.retain(|_, footer| {
+            let what = format!("File {:#x}", footer.file_id);
+            container::check_region(&what, footer.offset_to_file, footer.stored_length(header.is_bundle()), file_len).is_ok()
+        });
+        blockmap.files.retain(|file| {
+            if footers.contains_key(&file.id()) {
+                return true;
+            }
+            report.warn(format!("Could not locate {}", file.name));
+            report.missing.push(file.name.clone());
+            false
+        });
+
+        let mut eappx = Self::new(header, file_len, footers.into_values().collect(), blockmap, limits);
+        eappx.footers.extend(bundle_footers(&eappx, source, &mut report)?);
+        eappx.footers.sort_by_key(|footer| footer.file_id);
+        eappx.detect_encrypted_payloads(source)?;
+
+        let mut extents = eappx.footers.iter().collect::<Vec<_>>();
+        extents.sort_by_key(|footer| footer.offset_to_file);
+        for pair in extents.windows(2) {
+            if pair[0].offset_to_file + eappx.stored_length(pair[0]) > pair[1].offset_to_file {
+                report.warn(format!("File {:#x} overlaps file {:#x}", pair[0].file_id, pair[1].file_id));
+            }
+        }
+
+        Ok((eappx, report))
+    }
+}
+
+/// Footer as the blockmap describes `file`
+fn rebuild_footer(header: &EAppxHeader, file: &blockmap::File, offset: u64) -> EAppxFooter {
+    let block_sizes = file.block_sizes().filter(|sizes| !sizes.is_empty());
+    let compressed_length = match &block_sizes {
+        Some(sizes) => sizes.iter().map(|size| *size as u64).sum(),
+        None => file.size,
+    };
+    let key_id_index = (file.is_encrypted() && !header.is_bundle()).then_some(0);
+
+    EAppxFooter::new(file.id(), offset, key_id_index, block_sizes.is_some(), file.size, compressed_length)
+}
+
+/// Whether a scanned footer describes the same payload as one rebuilt from the blockmap
+fn agrees(candidate: &EAppxFooter, expected: &EAppxFooter) -> bool {
+    candidate.file_id == expected.file_id
+        && candidate.compression_type == expected.compression_type
+        && candidate.uncompressed_length == expected.uncompressed_length
+        && candidate.compressed_length == expected.compressed_length
+        && candidate.is_encrypted(false) == expected.is_encrypted(false)
+}
+
+/// Lay out pending files around the known extents, returns how many were placed
+fn place_files(header: &EAppxHeader, footers: &mut BTreeMap<u64, EAppxFooter>, pending: &mut BTreeMap<u64, &blockmap::File>) -> usize {
+    let last_id = footers.keys().chain(pending.keys()).copied().max().unwrap_or(0);
+    let stored_length = |footer: &EAppxFooter| footer.stored_length(header.is_bundle());
+    let mut placed = 0;
+
+    // Forward from the header, up to the first id nothing is known of
+    let mut cursor = Some(header.header_size as u64);
+    for id in 0..=last_id {
+        cursor = match (footers.get(&id), cursor) {
+            (Some(footer), _) => Some(footer.offset_to_file + stored_length(footer)),
+            (None, Some(offset)) => pending.remove(&id).map(|file| {
+                let footer = rebuild_footer(header, file, offset);
+                let end = offset + stored_length(&footer);
+                footers.insert(id, footer);
+                placed += 1;
+                end
+            }),
+            (None, None) => None,
+        };
+    }
+
+    // Backward from the next known extent, e.g. for bundle files behind the packages
+    let mut cursor = None;
+    for id in (0..=last_id).rev() {
+        cursor = match (footers.get(&id), cursor) {
+            (Some(footer), _) => Some(footer.offset_to_file),
+            (None, Some(end)) => pending.remove(&id).and_then(|file| {
+                let footer = rebuild_footer(header, file, 0);
+                let offset = end.checked_sub(stored_length(&footer))?;
+                footers.insert(id, EAppxFooter { offset_to_file: offset, ..footer });
+                placed += 1;
+                Some(offset)
+            }),
+            (None, None) => None,
+        };
+    }
+
+    placed
+}
+
+/// Footer records anywhere behind the header, by file id
+fn scan_footers<P: PackageSource + ?Sized>(source: &P, header: &EAppxHeader, file_len: u64) -> Result<BTreeMap<u64, Vec<EAppxFooter>>, Error> {
+    let mut candidates: BTreeMap<u64, Vec<EAppxFooter>> = BTreeMap::new();
+    let overlap = EAppxFooter::SIZE as u64 - 1;
+
+    let mut window_start = header.header_size as u64;
+    while window_start < file_len {
+        let window = read_window(source, window_start, (SCAN_WINDOW + overlap).min(file_len - window_start))?;
+        let records = window.windows(EAppxFooter::SIZE as usize)
+            .take(SCAN_WINDOW as usize)
+            .filter(|record| record.starts_with(&FOOTER_PREFIX));
+
+        for record in records {
+            let Ok(footer) = EAppxFooter::read(&mut Cursor::new(record)) else {
+                continue;
+            };
+            let plausible = footer.compression_type <= 1
+                && (footer.key_id_index == 0xFFFF || (footer.key_id_index as usize) < header.key_ids.len())
+                && footer.offset_to_file >= header.header_size as u64
+                && container::check_region("Footer", footer.offset_to_file, footer.stored_length(header.is_bundle()), file_len).is_ok()
+                && container::check_lengths("Footer", footer.compression_type == 1, footer.uncompressed_length, footer.compressed_length).is_ok();
+            if plausible {
+                candidates.entry(footer.file_id).or_default().push(footer);
+            }
+        }
+        window_start += SCAN_WINDOW;
+    }
+
+    Ok(candidates)
+}
+
+/// Blockmap footer and document, the first that matches the header's blockmap hash
+fn locate_blockmap<P: PackageSource + ?Sized>(
+    source: &P,
+    header: &EAppxHeader,
+    file_len: u64,
+    candidates: &BTreeMap<u64, Vec<EAppxFooter>>,
+    limits: &ResourceLimits,
+    report: &mut RecoveryReport,
+) -> Result<(EAppxFooter, AppxBlockMap), Error> {
+    for candidate in candidates.get(&header.block_map_file_id).into_iter().flatten() {
+        let mut fileinfo = FileInfo::from(candidate);
+        fileinfo.filehash = Some(header.block_map_hash.clone());
+        if let Ok(xml) = EAppxFile::read_file_to_buf(source, fileinfo, header.is_bundle(), limits.max_in_memory_size) {
+            report.found += 1;
+            return Ok((candidate.clone(), AppxBlockMap::from_xml(&xml)?));
+        }
+    }
+
+    // The blockmap is stored last, search for it from the end
+    let mut inflater = Decompress::new(false);
+    let mut window_end = file_len;
+    while window_end > header.header_size as u64 {
+        let window_start = window_end.saturating_sub(SCAN_WINDOW).max(header.header_size as u64);
+        let window = read_window(source, window_start, (file_len - window_start).min(SCAN_WINDOW + INFLATE_PROBE as u64))?;
+
+        for position in (0..(window_end - window_start) as usize).rev() {
+            if !inflates_to_xml(&mut inflater, &window[position..]) {
+                continue;
+            }
+            let offset = window_start + position as u64;
+            if let Some((footer, xml)) = inflate_blockmap(source, header, offset, limits)? {
+                log::info!("Found blockmap at {offset:#x}");
+                report.rebuilt += 1;
+                return Ok((footer, AppxBlockMap::from_xml(&xml)?));
+            }
+        }
+        window_end = window_start;
+    }
+
+    Err(Error::DataError("Could not find the blockmap, nothing to recover".into()))
+}
+
+/// Whether `data` starts with a deflate stream of an XML document
+fn inflates_to_xml(inflater: &mut Decompress, data: &[u8]) -> bool {
+    let mut prefix = [0u8; 8];
+    inflater.reset(false);
+    // Garbage usually fails within the first few bytes
+    match inflater.decompress(&data[..data.len().min(INFLATE_PROBE)], &mut prefix, FlushDecompress::None) {
+        Ok(_) => prefix.starts_with(b"<?xml") || &prefix == b"\xEF\xBB\xBF<?xml",
+        Err(_) => false,
+    }
+}
+
+/// Inflate the stream at `offset`, if it is the blockmap the header refers to
+fn inflate_blockmap<P: PackageSource + ?Sized>(source: &P, header: &EAppxHeader, offset: u64, limits: &ResourceLimits) -> Result<Option<(EAppxFooter, Vec<u8>)>, Error> {
+    let mut decoder = flate2::bufread::DeflateDecoder::new(source.reader_at(offset));
+    let mut xml = vec![];
+    let complete = (&mut decoder).take(limits.max_in_memory_size.saturating_add(1)).read_to_end(&mut xml).is_ok()
+        && xml.len() as u64 <= limits.max_in_memory_size;
+    if !complete || Sha256::digest(&xml).as_slice() != header.block_map_hash {
+        return Ok(None);
+    }
+
+    let footer = EAppxFooter::new(header.block_map_file_id, offset, None, true, xml.len() as u64, decoder.total_in());
+    Ok(Some((footer, xml)))
+}
+
+/// Footers of packages embedded in a bundle, from the offsets in its manifest
+fn bundle_footers<P: PackageSource + ?Sized>(eappx: &EAppxFile, source: &P, report: &mut RecoveryReport) -> Result<Vec<EAppxFooter>, Error> {
+    let bundle_manifest = match eappx.read_manifest(source) {
+        Ok(Manifest::BundleManifest(bundle_manifest)) => bundle_manifest,
+        Ok(Manifest::Manifest(_)) => return Ok(vec![]),
+        Err(error) if eappx.header.is_bundle() => {
+            report.warn(format!("Could not read the bundle manifest: {error}"));
+            return Ok(vec![]);
+        },
+        Err(_) => return Ok(vec![]),
+    };
+
+    let mut footers = vec![];
+    for (idx, package) in bundle_manifest.packages.package.into_iter().enumerate() {
+        if eappx.find_footer_for_file(idx as u64).is_some() {
+            continue;
+        }
+        let what = format!("Package {}", package.filename);
+        if container::check_region(&what, package.offset, package.size, eappx.file_len).is_err() {
+            report.warn(format!("Could not locate {}", package.filename));
+            report.missing.push(package.filename);
+            continue;
+        }
+        report.rebuilt += 1;
+        footers.push(EAppxFooter::new(idx as u64, package.offset, None, false, package.size, package.size));
+    }
+
+    Ok(footers)
+}
+
+fn read_window<P: PackageSource + ?Sized>(source: &P, offset: u64, length: u64) -> Result<Vec<u8>, Error> {
+    let mut window = vec![0u8; length as usize];
+    source.read_exact_at(offset, &mut window)?;
+    Ok(window)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{limits::ResourceLimits, verify::FileStatus, EAppxFile, EAppxFooter};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    fn extents(footers: &[EAppxFooter]) -> Vec<(u64, u64, u64, u64)> {
+        footers.iter()
+            .map(|footer| (footer.file_id, footer.offset_to_file, footer.uncompressed_length, footer.compressed_length))
+            .collect()
+    }
+
+    fn wipe_footer_table(package: &[u8]) -> (EAppxFile, Vec<u8>) {
+        let eappx = EAppxFile::from_source(&package).unwrap();
+        let mut data = package.to_vec();
+        let table = eappx.header.footer_offset as usize..(eappx.header.footer_offset + eappx.header.footer_length) as usize;
+        data[table].fill(0);
+        (eappx, data)
+    }
+
+    #[test]
+    fn test_intact_package() {
+        let (eappx, report) = EAppxFile::recover(EMSIX, ResourceLimits::default()).unwrap();
+        assert!(report.is_intact());
+        assert_eq!(eappx.footers.len(), 13);
+    }
+
+    #[test]
+    fn test_rebuild_footers() {
+        let (original, data) = wipe_footer_table(EMSIX);
+        assert!(EAppxFile::from_source(&data).is_err());
+
+        let (mut eappx, report) = EAppxFile::recover(&data, ResourceLimits::default()).unwrap();
+        assert_eq!((report.found, report.rebuilt), (0, 13), "{report}");
+        assert!(report.missing.is_empty());
+        assert_eq!(eappx.footers, original.footers);
+
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let verification = eappx.verify(&data);
+        assert!(verification.files.iter().all(|file| file.status == FileStatus::Valid), "{verification}");
+    }
+
+    #[test]
+    fn test_scan_footers() {
+        // Footer table intact, but the header points elsewhere
+        let original = EAppxFile::from_source(EMSIX).unwrap();
+        let mut data = EMSIX.to_vec();
+        data[0x0E..0x16].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let (eappx, report) = EAppxFile::recover(&data, ResourceLimits::default()).unwrap();
+        assert_eq!((report.found, report.rebuilt), (13, 0), "{report}");
+        assert_eq!(eappx.footers, original.footers);
+    }
+
+    #[test]
+    fn test_rebuild_bundle_footers() {
+        let (original, data) = wipe_footer_table(EMSIXBUNDLE);
+
+        let (eappx, report) = EAppxFile::recover(&data, ResourceLimits::default()).unwrap();
+        assert!(report.missing.is_empty(), "{report}");
+        assert_eq!(extents(&eappx.footers), extents(&original.footers));
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        // The end of the last file is gone, the blockmap behind it is still there
+        let (original, mut data) = wipe_footer_table(EMSIX);
+        let blockmap = original.find_footer_for_file(original.header.block_map_file_id).unwrap();
+        let last = original.find_footer_for_file(0xb).unwrap();
+        let tail = data.split_off(blockmap.offset_to_file as usize);
+        data.truncate(last.offset_to_file as usize + 0x100);
+        data.extend(tail);
+
+        let (eappx, report) = EAppxFile::recover(&data, ResourceLimits::default()).unwrap();
+        let name = &original.blockmap.files.iter().find(|file| file.id() == 0xb).unwrap().name;
+        assert_eq!(report.missing, [name.as_str()], "{report}");
+        assert_eq!(eappx.blockmap.files.len(), original.blockmap.files.len() - 1);
+        assert!(eappx.find_footer_for_file(0xb).is_none());
+        assert_eq!(eappx.footers.len(), original.footers.len() - 1);
+    }
+}
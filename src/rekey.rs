@@ -0,0 +1,224 @@
+//! Re-encryption of a package with new content keys or a new identity
+//!
+//! Every file is decrypted with the old key and streamed straight into a
+//! [`PackageWriter`] encrypting with the new one, plaintext only ever exists
+//! block-wise in memory.
+
+use std::io::{Seek, Write};
+
+use crate::{
+    error::Error,
+    keys::KeyId,
+    source::PackageSource,
+    utils,
+    writer::{EntryOptions, PackageWriter, WriterOptions},
+    EAppxFile,
+    Manifest,
+};
+
+/// Re-encrypt all files of `eappx` with `new_keys`
+///
+/// The old keys have to be loaded into `eappx`. Either one new key per
+/// header key id is given, mapped by position, or a single key replacing all
+/// of them. Unencrypted files stay unencrypted.
+///
+/// Signature and code integrity catalog are not carried over, the signature
+/// would not match the new blockmap anymore.
+pub fn rekey<P: PackageSource + ?Sized, W: Write + Seek + Send + 'static>(
+    eappx: &EAppxFile,
+    source: &P,
+    new_keys: Vec<(KeyId, Vec<u8>)>,
+    sink: W,
+    options: WriterOptions,
+) -> Result<W, Error> {
+    if eappx.header.is_bundle() {
+        return Err(Error::DataError("Rekeying bundles is not supported, rekey their inner packages".into()));
+    }
+
+    let old_key_count = eappx.header.key_ids.len();
+    let new_key_count = new_keys.len();
+    let map_key_index = |old_index: u16| -> Result<u16, Error> {
+        match new_key_count {
+            1 => Ok(0),
+            n if n == old_key_count && (old_index as usize) < n => Ok(old_index),
+            n => Err(Error::DataError(format!("Cannot map key index {old_index} of {old_key_count} old keys onto {n} new keys"))),
+        }
+    };
+
+    let writer = PackageWriter::new(sink, eappx.header.magic, &eappx.header.package_full_name(), new_keys, options)?;
+    repack(eappx, source, writer, map_key_index, None)
+}
+
+/// Give `eappx` a new package name and/or publisher, re-encrypting every encrypted file
+///
+/// XTS tweaks derive from the package family name, so encrypted files are
+/// decrypted and encrypted again under the new name with the same keys, which
+/// have to be loaded into `eappx`. The manifest identity is updated, the other
+/// parts of the package full name (version, architecture, resource id) are kept.
+///
+/// Signature and code integrity catalog are not carried over, as for [`rekey`].
+pub fn rename<P: PackageSource + ?Sized, W: Write + Seek + Send + 'static>(
+    eappx: &EAppxFile,
+    source: &P,
+    name: Option<&str>,
+    publisher: Option<&str>,
+    sink: W,
+    options: WriterOptions,
+) -> Result<W, Error> {
+    if eappx.header.is_bundle() {
+        return Err(Error::DataError("Bundles cannot be renamed, rename the packages they contain".into()));
+    }
+    let Manifest::Manifest(mut manifest) = eappx.read_manifest(source)? else {
+        return Err(Error::DataError("Package holds a bundle manifest".into()));
+    };
+
+    if let Some(name) = name {
+        if name.is_empty() || name.contains('_') {
+            return Err(Error::DataError(format!("Invalid package name {name:?}")));
+        }
+        manifest.identity.name = name.to_owned();
+    }
+    if let Some(publisher) = publisher {
+        manifest.set_publisher(publisher);
+    }
+
+    let old_full_name = eappx.header.package_full_name();
+    let mut parts = old_full_name.split('_').collect::<Vec<_>>();
+    let publisher_id = utils::generate_publisher_id(&manifest.identity.publisher);
+    let package_full_name = match parts.as_mut_slice() {
+        [name, _, _, _, id] => {
+            *name = &manifest.identity.name;
+            *id = &publisher_id;
+            parts.join("_")
+        },
+        _ => return Err(Error::DataError(format!("Package full name {old_full_name} does not have five parts"))),
+    };
+    log::info!("Renaming {old_full_name} to {package_full_name}");
+
+    let keys = eappx.header.key_ids.iter()
+        .map(|key_id| (key_id.clone(), eappx.keys.get(key_id).cloned().unwrap_or_default()))
+        .collect();
+    let manifest_xml = manifest.to_xml().into_bytes();
+    let writer = PackageWriter::new(sink, eappx.header.magic, &package_full_name, keys, options)?;
+    repack(eappx, source, writer, Ok, Some(manifest_xml))
+}
+
+/// Write every file of `eappx` into `writer`, `manifest` replaces the manifest contents
+fn repack<P: PackageSource + ?Sized, W: Write + Seek + Send + 'static>(
+    eappx: &EAppxFile,
+    source: &P,
+    mut writer: PackageWriter<W>,
+    map_key_index: impl Fn(u16) -> Result<u16, Error>,
+    manifest: Option<Vec<u8>>,
+) -> Result<W, Error> {
+    let key_count = eappx.header.key_ids.len();
+    let missing = eappx.header.key_ids.iter().filter(|key_id| !eappx.keys.contains_key(key_id)).count();
+    if missing > 0 {
+        return Err(Error::DataError(format!("{missing} of {key_count} old keys are not loaded")));
+    }
+
+    if eappx.header.is_signed() || eappx.header.is_code_integrity_protected() {
+        log::warn!("Signature and code integrity catalog are dropped while repacking");
+    }
+
+    let manifest_file = eappx.blockmap.manifest_file().map(|file| file.name.as_str());
+    for file in &eappx.blockmap.files {
+        let footer = eappx.find_footer_for_file(file.id())
+            .ok_or(Error::DataError(format!("Failed to find footer for file {}", file.name)))?;
+
+        let key_index = match footer.is_encrypted(false) {
+            true => Some(map_key_index(footer.key_id_index)?),
+            false => None,
+        };
+        let options = EntryOptions {
+            compress: footer.compression_type == 0x1,
+            key_index,
+        };
+
+        log::debug!("Repacking {} (key index {:?} -> {key_index:?})", file.name, footer.is_encrypted(false).then_some(footer.key_id_index));
+        match manifest.as_deref().filter(|_| manifest_file == Some(file.name.as_str())) {
+            Some(mut manifest) => writer.add_entry(&file.name, &mut manifest, options)?,
+            None => writer.add_entry(&file.name, &mut eappx.file_reader(source, file)?, options)?,
+        }
+    }
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::keys::KeyCollection;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_rekey() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let new_keys = KeyCollection::generate().unwrap();
+        let new_key = new_keys.keys.iter().next().map(|(k, v)| (k.clone(), v.clone())).unwrap();
+        let out = rekey(&eappx, EMSIX, vec![new_key.clone()], Cursor::new(vec![]), WriterOptions::default())
+            .unwrap()
+            .into_inner();
+
+        let mut rekeyed = EAppxFile::from_source(out.as_slice()).unwrap();
+        assert_eq!(rekeyed.header.key_ids, vec![new_key.0]);
+        assert_eq!(rekeyed.header.package_full_name(), eappx.header.package_full_name());
+        assert!(rekeyed.verify(out.as_slice()).is_valid());
+
+        // Plaintext is unchanged
+        rekeyed.load_keys(&new_keys).unwrap();
+        for (old, new) in eappx.blockmap.files.iter().zip(&rekeyed.blockmap.files) {
+            assert_eq!(old.name, new.name);
+            assert_eq!(old.filehash, new.filehash);
+            assert_eq!(old.is_encrypted(), new.is_encrypted());
+
+            let mut plaintext = vec![];
+            std::io::copy(&mut rekeyed.file_reader(out.as_slice(), new).unwrap(), &mut plaintext).unwrap();
+            assert_eq!(plaintext.len() as u64, old.size);
+        }
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let out = rename(&eappx, EMSIX, Some("Contoso.Renamed"), Some("CN=Contoso"), Cursor::new(vec![]), WriterOptions::default())
+            .unwrap()
+            .into_inner();
+
+        let mut renamed = EAppxFile::from_source(out.as_slice()).unwrap();
+        renamed.load_keys(&crate::sample::sample_keys()).unwrap();
+        let publisher_id = utils::generate_publisher_id("CN=Contoso");
+        assert_eq!(renamed.header.package_full_name(), format!("Contoso.Renamed_0.0.0.0_x64__{publisher_id}"));
+        assert_eq!(renamed.header.key_ids, eappx.header.key_ids);
+        assert!(renamed.verify(out.as_slice()).is_valid());
+
+        let Manifest::Manifest(manifest) = renamed.read_manifest(out.as_slice()).unwrap() else { panic!("Expected a package manifest") };
+        assert_eq!(manifest.identity.name, "Contoso.Renamed");
+        assert_eq!(manifest.identity.publisher, "CN=Contoso");
+
+        // Encrypted files decrypt under the new name to the old plaintext
+        for (old, new) in eappx.blockmap.files.iter().zip(&renamed.blockmap.files).filter(|(old, _)| old.is_encrypted()) {
+            assert_eq!(old.filehash, new.filehash);
+            let mut plaintext = vec![];
+            std::io::copy(&mut renamed.file_reader(out.as_slice(), new).unwrap(), &mut plaintext).unwrap();
+            assert_eq!(plaintext.len() as u64, old.size);
+        }
+
+        assert!(rename(&eappx, EMSIX, Some("Bad_Name"), None, Cursor::new(vec![]), WriterOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_rekey_missing_old_key() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let new_key = (crate::sample::TEST_KEY_ID, vec![0; 32]);
+
+        assert!(rekey(&eappx, EMSIX, vec![new_key], Cursor::new(vec![]), WriterOptions::default()).is_err());
+    }
+}
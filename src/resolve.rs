@@ -0,0 +1,101 @@
+//! Pairing of blockmap files with footer table entries
+//!
+//! Blockmap and footer table are parsed independently and only meet through
+//! file ids. Resolving them up front reports every dangling reference at once,
+//! instead of the first one surfacing as an error halfway through extraction.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{blockmap, EAppxFile, EAppxFooter};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedFile<'a> {
+    pub file: &'a blockmap::File,
+    pub footer: &'a EAppxFooter,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Resolution<'a> {
+    /// Blockmap files with their footers, in blockmap order
+    pub files: Vec<ResolvedFile<'a>>,
+    /// Footer of AppxBlockMap.xml itself
+    pub blockmap_footer: Option<&'a EAppxFooter>,
+    /// Blockmap files no footer has the id of
+    pub missing_footers: Vec<&'a blockmap::File>,
+    /// Footers neither a blockmap file nor the blockmap refers to
+    ///
+    /// Packages embedded in a bundle are only named by the bundle manifest,
+    /// so their footers end up here.
+    pub orphan_footers: Vec<&'a EAppxFooter>,
+}
+
+impl Resolution<'_> {
+    /// Whether every blockmap file and the blockmap have a footer
+    pub fn is_complete(&self) -> bool {
+        self.missing_footers.is_empty() && self.blockmap_footer.is_some()
+    }
+}
+
+impl EAppxFile {
+    /// Pair every blockmap file with its footer and collect what is left over on either side
+    pub fn resolve(&self) -> Resolution<'_> {
+        // First footer wins for duplicate ids, as with `find_footer_for_file`
+        let mut by_id = HashMap::with_capacity(self.footers.len());
+        for footer in &self.footers {
+            by_id.entry(footer.file_id).or_insert(footer);
+        }
+
+        let mut resolution = Resolution {
+            blockmap_footer: by_id.get(&self.header.block_map_file_id).copied(),
+            ..Default::default()
+        };
+        let mut referenced = HashSet::from([self.header.block_map_file_id]);
+        for file in &self.blockmap.files {
+            match by_id.get(&file.id()) {
+                Some(footer) => {
+                    resolution.files.push(ResolvedFile { file, footer });
+                    referenced.insert(footer.file_id);
+                },
+                None => resolution.missing_footers.push(file),
+            }
+        }
+        resolution.orphan_footers = self.footers.iter()
+            .filter(|footer| !referenced.contains(&footer.file_id))
+            .collect();
+
+        resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    #[test]
+    fn test_resolve() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let resolution = eappx.resolve();
+        assert!(resolution.is_complete());
+        assert!(resolution.orphan_footers.is_empty());
+        assert_eq!(resolution.files.len(), eappx.blockmap.files.len());
+        assert!(resolution.files.iter().all(|resolved| resolved.file.id() == resolved.footer.file_id));
+        assert_eq!(resolution.blockmap_footer.map(|footer| footer.file_id), Some(eappx.header.block_map_file_id));
+
+        // One footer gone, one renumbered out of reach of the blockmap
+        eappx.footers.retain(|footer| footer.file_id != 0x8);
+        eappx.footers.iter_mut().find(|footer| footer.file_id == 0xa).unwrap().file_id = 0x100;
+        let resolution = eappx.resolve();
+        assert!(!resolution.is_complete());
+        assert_eq!(resolution.missing_footers.iter().map(|file| file.name.as_str()).collect::<Vec<_>>(), ["clrcompression.dll", "TestApp.dll"]);
+        assert_eq!(resolution.orphan_footers.iter().map(|footer| footer.file_id).collect::<Vec<_>>(), [0x100]);
+
+        // Embedded packages are not in the blockmap
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let resolution = eappx.resolve();
+        assert!(resolution.is_complete());
+        assert_eq!(resolution.orphan_footers.iter().map(|footer| footer.file_id).collect::<Vec<_>>(), [0]);
+    }
+}
@@ -0,0 +1,98 @@
+//! Tiny generated sample package
+//!
+//! Real-world packages are proprietary, so documentation and examples run
+//! against this package instead. It is built on the fly by [`PackageWriter`]
+//! from synthetic content and encrypted with the global test key, which makes
+//! the output deterministic and free to redistribute.
+
+use std::io::Cursor;
+
+pub use crate::keys::{TEST_KEY, TEST_KEY_ID};
+use crate::{
+    error::Error,
+    keys::KeyCollection,
+    writer::{EntryOptions, PackageWriter, WriterOptions},
+    EAppxMagic,
+};
+
+pub const SAMPLE_NAME: &str = "Contoso.Sample";
+pub const SAMPLE_PUBLISHER: &str = "CN=Contoso";
+pub const SAMPLE_VERSION: &str = "1.0.0.0";
+
+/// Keys required to decrypt the sample package
+pub fn sample_keys() -> KeyCollection {
+    KeyCollection::with_test_key()
+}
+
+pub fn sample_manifest() -> String {
+    format!(r#"<?xml version="1.0" encoding="utf-8" standalone="yes"?>
+<Package xmlns="http://schemas.microsoft.com/appx/manifest/foundation/windows10" xmlns:uap="http://schemas.microsoft.com/appx/manifest/uap/windows10" IgnorableNamespaces="uap">
+  <Identity Name="{SAMPLE_NAME}" Publisher="{SAMPLE_PUBLISHER}" Version="{SAMPLE_VERSION}" ProcessorArchitecture="x64"/>
+  <Properties>
+    <DisplayName>Sample</DisplayName>
+    <PublisherDisplayName>Contoso</PublisherDisplayName>
+    <Logo>Assets\Logo.txt</Logo>
+  </Properties>
+  <Dependencies>
+    <TargetDeviceFamily Name="Windows.Universal" MinVersion="10.0.17763.0" MaxVersionTested="10.0.22621.0"/>
+  </Dependencies>
+  <Resources>
+    <Resource Language="EN-US"/>
+  </Resources>
+</Package>
+"#)
+}
+
+/// Files of the sample package and whether they get encrypted
+pub fn sample_files() -> Vec<(&'static str, Vec<u8>, bool)> {
+    // Spans two blocks, so multi-block handling is exercised as well
+    let numbers = (0..0x11000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+
+    vec![
+        ("AppxManifest.xml", sample_manifest().into_bytes(), false),
+        ("Assets\\Logo.txt", b"Contoso sample logo placeholder\n".to_vec(), false),
+        ("Readme.txt", b"This package was generated by the eappx crate.\n".to_vec(), true),
+        ("Data\\numbers.bin", numbers, true),
+    ]
+}
+
+/// Package full name of the sample package
+pub fn sample_package_full_name() -> String {
+    format!("{SAMPLE_NAME}_{SAMPLE_VERSION}_x64__{}", crate::utils::generate_publisher_id(SAMPLE_PUBLISHER))
+}
+
+/// Build the encrypted sample package in memory
+pub fn build_sample_package() -> Result<Vec<u8>, Error> {
+    let keys = vec![(TEST_KEY_ID, TEST_KEY.to_vec())];
+    let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, &sample_package_full_name(), keys, WriterOptions::default())?;
+
+    for (name, data, encrypted) in sample_files() {
+        let options = EntryOptions {
+            compress: !encrypted,
+            key_index: encrypted.then_some(0),
+        };
+        writer.add_entry(name, &mut data.as_slice(), options)?;
+    }
+
+    Ok(writer.finish()?.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EAppxFile;
+
+    #[test]
+    fn test_sample_package() {
+        let package = build_sample_package().unwrap();
+        assert_eq!(package, build_sample_package().unwrap(), "Sample package must be deterministic");
+
+        let mut eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        eappx.load_keys(&sample_keys()).unwrap();
+
+        assert_eq!(eappx.header.package_full_name(), sample_package_full_name());
+        assert_eq!(eappx.blockmap.files.len(), sample_files().len());
+        assert!(eappx.verify(package.as_slice()).is_valid());
+        assert!(matches!(eappx.read_manifest(package.as_slice()).unwrap(), crate::Manifest::Manifest(m) if m.identity.name == SAMPLE_NAME));
+    }
+}
@@ -0,0 +1,114 @@
+//! Parsed package shared between threads
+//!
+//! Header, footers, blockmap and keys are parsed once and only read from then
+//! on, a [`SharedPackage`] hands them to any number of threads behind an
+//! [`Arc`]. Payloads are read at an offset from its [`PackageSource`], so
+//! threads share a single handle: a file, bytes in memory, a range request
+//! against an object store.
+
+use std::{io::Read, sync::Arc};
+
+use crate::{
+    entry_reader::EntryReader,
+    error::Error,
+    extraction_report::ExtractedFile,
+    keys::KeyCollection,
+    sink::EntrySink,
+    source::PackageSource,
+    EAppxFile,
+};
+
+/// Parsed package and where to read its payload from, cheap to clone
+#[derive(Debug)]
+pub struct SharedPackage<S: PackageSource> {
+    eappx: Arc<EAppxFile>,
+    source: Arc<S>,
+}
+
+impl<S: PackageSource> Clone for SharedPackage<S> {
+    fn clone(&self) -> Self {
+        Self { eappx: self.eappx.clone(), source: self.source.clone() }
+    }
+}
+
+impl<S: PackageSource> SharedPackage<S> {
+    /// Parse the package in `source` and load `keys`
+    pub fn open(source: S, keys: &KeyCollection) -> Result<Self, Error> {
+        let mut eappx = EAppxFile::from_source(&source)?;
+        eappx.load_keys(keys)?;
+        Ok(Self::new(eappx, source))
+    }
+
+    /// Share an already configured package, `source` has to hold the package it was parsed from
+    pub fn new(eappx: EAppxFile, source: S) -> Self {
+        Self { eappx: Arc::new(eappx), source: Arc::new(source) }
+    }
+
+    pub fn package(&self) -> &EAppxFile {
+        &self.eappx
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    /// [`EAppxFile::entry_reader`] on the shared source
+    pub fn entry_reader(&self, name: &str) -> Result<EntryReader<'_, &S>, Error> {
+        self.eappx.entry_reader(&*self.source, name)
+    }
+
+    /// Plaintext of the blockmap file `name`, refusing files over [`EAppxFile::limits`]
+    pub fn read_to_vec(&self, name: &str) -> Result<Vec<u8>, Error> {
+        let mut reader = self.entry_reader(name)?;
+        self.eappx.limits.check_in_memory_size(name, reader.len())?;
+        let mut data = Vec::with_capacity(reader.len() as usize);
+        reader.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// [`EAppxFile::extract_entry_to`] on the shared source
+    pub fn extract_entry_to(&self, name: &str, sink: &mut dyn EntrySink) -> Result<ExtractedFile, Error> {
+        self.eappx.extract_entry_to(&*self.source, name, sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use sha2::{Digest, Sha256};
+
+    use super::SharedPackage;
+    use crate::{sample::sample_keys, EAppxFile};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_package() {
+        assert_send_sync::<EAppxFile>();
+        assert_send_sync::<SharedPackage<Arc<[u8]>>>();
+
+        let package = SharedPackage::open(Arc::<[u8]>::from(EMSIX), &sample_keys()).unwrap();
+        let expected = package.read_to_vec("TestApp.dll").unwrap();
+        let file = package.package().blockmap.find_file("TestApp.dll").unwrap();
+        assert_eq!(Sha256::digest(&expected).as_slice(), file.filehash_bytes().unwrap().unwrap().as_slice());
+
+        std::thread::scope(|scope| {
+            let handles = (0..4)
+                .map(|_| {
+                    let package = package.clone();
+                    scope.spawn(move || package.read_to_vec("TestApp.dll").unwrap())
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), expected);
+            }
+        });
+
+        // Borrowed bytes work as a source too
+        let package = SharedPackage::open(EMSIX, &sample_keys()).unwrap();
+        assert_eq!(package.read_to_vec("TestApp.dll").unwrap(), expected);
+    }
+}
@@ -0,0 +1,477 @@
+//! Read-only inspection of AppxSignature.p7x
+//!
+//! Shows who signed a package and when, the signature itself is not
+//! verified. The signer is looked up among the certificates carried by the
+//! signature, its chain follows issuers as far as those certificates go.
+//! Signing time comes from the authenticated attributes, the timestamp from
+//! an RFC 3161 token or a legacy Authenticode countersignature.
+//! [`SigningStatus`] condenses all of it into a level policies can check.
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    der::{DerReader, DerValue, OID_SIGNED_DATA, TAG_CONTEXT_0, TAG_CONTEXT_1, TAG_INTEGER, TAG_OCTET_STRING, TAG_SEQUENCE, TAG_SET},
+    error::Error,
+    keys::{KeyId, TEST_KEY_ID},
+};
+#[cfg(feature = "package")]
+use crate::{footprint::FootprintFile, source::PackageSource, EAppxFile};
+
+const P7X_MAGIC: &[u8; 4] = b"PKCX";
+
+const OID_SIGNING_TIME: &[u64] = &[1, 2, 840, 113549, 1, 9, 5];
+const OID_COUNTER_SIGNATURE: &[u64] = &[1, 2, 840, 113549, 1, 9, 6];
+const OID_RFC3161_TIMESTAMP: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 3, 3, 1];
+
+/// Issuing CAs of the certificates the Microsoft Store signs packages with
+const STORE_ISSUERS: &[&str] = &["Microsoft Marketplace CA", "Microsoft MarketPlace PCA"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CertificateInfo {
+    /// Distinguished name as Windows shows it, e.g. `CN=Contoso, O=Contoso, C=US`
+    pub subject: String,
+    pub issuer: String,
+    /// Uppercase hex
+    pub serial_number: String,
+    /// SHA-1 over the certificate, uppercase hex as in the Windows certificate store
+    pub thumbprint: String,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+impl CertificateInfo {
+    pub fn is_self_signed(&self) -> bool {
+        self.subject == self.issuer
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SignatureInfo {
+    pub signer: CertificateInfo,
+    /// Issuers of the signer found in the signature, nearest first
+    pub chain: Vec<CertificateInfo>,
+    /// Digest algorithm of the signer, e.g. `SHA256`
+    pub digest_algorithm: String,
+    /// Claimed by the signer, `None` if the signature does not carry one
+    pub signing_time: Option<String>,
+    /// Vouched for by a timestamp authority
+    pub timestamp: Option<String>,
+}
+
+/// Certificate with the raw names used to find its issuer
+struct Certificate<'a> {
+    info: CertificateInfo,
+    subject: &'a [u8],
+    issuer: &'a [u8],
+    serial: &'a [u8],
+}
+
+fn parse_certificate(value: DerValue<'_>) -> Result<Certificate<'_>, Error> {
+    let mut tbs = value.children().expect(TAG_SEQUENCE)?.children();
+    tbs.optional(TAG_CONTEXT_0)?;
+    let serial = tbs.expect(TAG_INTEGER)?.content;
+    tbs.expect(TAG_SEQUENCE)?;
+    let issuer = tbs.expect(TAG_SEQUENCE)?;
+    let mut validity = tbs.expect(TAG_SEQUENCE)?.children();
+    let (not_before, not_after) = (validity.next()?.time()?, validity.next()?.time()?);
+    let subject = tbs.expect(TAG_SEQUENCE)?;
+
+    let magnitude = &serial[serial.iter().take_while(|byte| **byte == 0).count().min(serial.len().saturating_sub(1))..];
+    Ok(Certificate {
+        info: CertificateInfo {
+            subject: distinguished_name(subject)?,
+            issuer: distinguished_name(issuer)?,
+            serial_number: hex::encode_upper(magnitude),
+            thumbprint: hex::encode_upper(Sha1::digest(value.raw)),
+            not_before,
+            not_after,
+        },
+        subject: subject.raw,
+        issuer: issuer.raw,
+        serial,
+    })
+}
+
+/// Most specific attribute first, the order of publisher strings in manifests
+fn distinguished_name(name: DerValue<'_>) -> Result<String, Error> {
+    let mut parts = vec![];
+    let mut rdns = name.children();
+    while !rdns.is_empty() {
+        let mut attributes = rdns.expect(TAG_SET)?.children();
+        let mut rdn = vec![];
+        while !attributes.is_empty() {
+            let mut attribute = attributes.expect(TAG_SEQUENCE)?.children();
+            let arcs = attribute.next()?.oid()?;
+            let value = attribute.next()?.string()?;
+            rdn.push(format!("{}={}", attribute_name(&arcs), quote_value(&value)));
+        }
+        parts.push(rdn.join(" + "));
+    }
+    parts.reverse();
+    Ok(parts.join(", "))
+}
+
+/// Short names as used by Windows
+fn attribute_name(arcs: &[u64]) -> String {
+    let name = match arcs {
+        [2, 5, 4, 3] => "CN",
+        [2, 5, 4, 5] => "SERIALNUMBER",
+        [2, 5, 4, 6] => "C",
+        [2, 5, 4, 7] => "L",
+        [2, 5, 4, 8] => "S",
+        [2, 5, 4, 9] => "STREET",
+        [2, 5, 4, 10] => "O",
+        [2, 5, 4, 11] => "OU",
+        [1, 2, 840, 113549, 1, 9, 1] => "E",
+        [0, 9, 2342, 19200300, 100, 1, 25] => "DC",
+        _ => return format!("OID.{}", dotted(arcs)),
+    };
+    name.to_owned()
+}
+
+fn quote_value(value: &str) -> String {
+    let special = value.starts_with([' ', '#']) || value.ends_with(' ')
+        || value.contains([',', '+', '=', '"', '\n', '<', '>', ';']);
+    match special {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_owned(),
+    }
+}
+
+fn dotted(arcs: &[u64]) -> String {
+    arcs.iter().map(u64::to_string).collect::<Vec<_>>().join(".")
+}
+
+fn digest_algorithm_name(arcs: &[u64]) -> String {
+    let name = match arcs {
+        [1, 3, 14, 3, 2, 26] => "SHA1",
+        [2, 16, 840, 1, 101, 3, 4, 2, 1] => "SHA256",
+        [2, 16, 840, 1, 101, 3, 4, 2, 2] => "SHA384",
+        [2, 16, 840, 1, 101, 3, 4, 2, 3] => "SHA512",
+        _ => return dotted(arcs),
+    };
+    name.to_owned()
+}
+
+/// Values of the attribute `arcs` in a `[0]` or `[1]` attribute list
+fn attribute_values<'a>(attributes: Option<DerValue<'a>>, arcs: &[u64]) -> Result<Vec<DerValue<'a>>, Error> {
+    let mut values = vec![];
+    let Some(attributes) = attributes else {
+        return Ok(values);
+    };
+    let mut attributes = attributes.children();
+    while !attributes.is_empty() {
+        let mut attribute = attributes.expect(TAG_SEQUENCE)?.children();
+        if attribute.next()?.oid()? != arcs {
+            continue;
+        }
+        let mut set = attribute.expect(TAG_SET)?.children();
+        while !set.is_empty() {
+            values.push(set.next()?);
+        }
+    }
+    Ok(values)
+}
+
+struct SignerInfo<'a> {
+    /// Issuer name and serial, `None` for signers identified by key identifier
+    issuer_and_serial: Option<(&'a [u8], &'a [u8])>,
+    digest_algorithm: Vec<u64>,
+    signed_attributes: Option<DerValue<'a>>,
+    unsigned_attributes: Option<DerValue<'a>>,
+}
+
+fn parse_signer_info(value: DerValue<'_>) -> Result<SignerInfo<'_>, Error> {
+    let mut fields = value.children();
+    fields.expect(TAG_INTEGER)?;
+    let sid = fields.next()?;
+    let issuer_and_serial = match sid.tag {
+        TAG_SEQUENCE => {
+            let mut sid = sid.children();
+            Some((sid.expect(TAG_SEQUENCE)?.raw, sid.expect(TAG_INTEGER)?.content))
+        },
+        _ => None,
+    };
+    let digest_algorithm = fields.expect(TAG_SEQUENCE)?.children().next()?.oid()?;
+    let signed_attributes = fields.optional(TAG_CONTEXT_0)?;
+    fields.expect(TAG_SEQUENCE)?;
+    fields.expect(TAG_OCTET_STRING)?;
+    let unsigned_attributes = fields.optional(TAG_CONTEXT_1)?;
+    Ok(SignerInfo { issuer_and_serial, digest_algorithm, signed_attributes, unsigned_attributes })
+}
+
+fn signing_time(signer: &SignerInfo<'_>) -> Result<Option<String>, Error> {
+    attribute_values(signer.signed_attributes, OID_SIGNING_TIME)?
+        .first()
+        .map(DerValue::time)
+        .transpose()
+}
+
+/// `SignedData` inside a `ContentInfo`
+fn signed_data(content_info: DerValue<'_>) -> Result<DerValue<'_>, Error> {
+    let mut content_info = content_info.children();
+    if content_info.next()?.oid()? != OID_SIGNED_DATA {
+        return Err(Error::DecodeError("Signature is not PKCS#7 SignedData".into()));
+    }
+    content_info.expect(TAG_CONTEXT_0)?.children().expect(TAG_SEQUENCE)
+}
+
+/// `genTime` of the `TSTInfo` in an RFC 3161 timestamp token
+fn rfc3161_time(token: DerValue<'_>) -> Result<String, Error> {
+    let mut fields = signed_data(token)?.children();
+    fields.expect(TAG_INTEGER)?;
+    fields.expect(TAG_SET)?;
+    let mut content = fields.expect(TAG_SEQUENCE)?.children();
+    content.next()?;
+    let tst_info = content.expect(TAG_CONTEXT_0)?.children().expect(TAG_OCTET_STRING)?;
+    let mut tst_info = DerReader::new(tst_info.content).expect(TAG_SEQUENCE)?.children();
+    tst_info.expect(TAG_INTEGER)?;
+    tst_info.next()?;
+    tst_info.expect(TAG_SEQUENCE)?;
+    tst_info.expect(TAG_INTEGER)?;
+    tst_info.next()?.time()
+}
+
+fn timestamp(signer: &SignerInfo<'_>) -> Result<Option<String>, Error> {
+    if let Some(token) = attribute_values(signer.unsigned_attributes, OID_RFC3161_TIMESTAMP)?.into_iter().next() {
+        return rfc3161_time(token).map(Some);
+    }
+    match attribute_values(signer.unsigned_attributes, OID_COUNTER_SIGNATURE)?.into_iter().next() {
+        Some(counter_signature) => signing_time(&parse_signer_info(counter_signature)?),
+        None => Ok(None),
+    }
+}
+
+impl SignatureInfo {
+    /// Parse AppxSignature.p7x, with or without its `PKCX` prefix
+    pub fn from_p7x(p7x: &[u8]) -> Result<Self, Error> {
+        let der = p7x.strip_prefix(P7X_MAGIC).unwrap_or(p7x);
+        let mut fields = signed_data(DerReader::new(der).expect(TAG_SEQUENCE)?)?.children();
+        fields.expect(TAG_INTEGER)?;
+        fields.expect(TAG_SET)?;
+        fields.expect(TAG_SEQUENCE)?;
+
+        let mut certificates = vec![];
+        if let Some(list) = fields.optional(TAG_CONTEXT_0)? {
+            let mut list = list.children();
+            while !list.is_empty() {
+                certificates.push(parse_certificate(list.expect(TAG_SEQUENCE)?)?);
+            }
+        }
+        fields.optional(TAG_CONTEXT_1)?;
+        let signer = parse_signer_info(fields.expect(TAG_SET)?.children().expect(TAG_SEQUENCE)?)?;
+
+        let position = match signer.issuer_and_serial {
+            Some((issuer, serial)) => certificates.iter().position(|certificate| certificate.issuer == issuer && certificate.serial == serial),
+            None => (!certificates.is_empty()).then_some(0),
+        };
+        let Some(position) = position else {
+            return Err(Error::DecodeError("Signer certificate is not part of the signature".into()));
+        };
+
+        let mut chain = vec![];
+        let mut current = &certificates[position];
+        while !current.info.is_self_signed() && chain.len() < certificates.len() {
+            match certificates.iter().find(|certificate| certificate.subject == current.issuer) {
+                Some(issuer) => {
+                    chain.push(issuer.info.clone());
+                    current = issuer;
+                },
+                None => break,
+            }
+        }
+
+        Ok(Self {
+            signer: certificates[position].info.clone(),
+            chain,
+            digest_algorithm: digest_algorithm_name(&signer.digest_algorithm),
+            signing_time: signing_time(&signer)?,
+            timestamp: timestamp(&signer)?,
+        })
+    }
+}
+
+impl SignatureInfo {
+    /// Whether signer or chain were issued by a Microsoft Store CA, going by name
+    pub fn is_store_signed(&self) -> bool {
+        std::iter::once(&self.signer).chain(&self.chain).any(|certificate| {
+            let issuer = certificate.issuer.to_lowercase();
+            STORE_ISSUERS.iter().any(|store| issuer.contains(&store.to_lowercase()))
+        })
+    }
+}
+
+/// How far a package's signature can be trusted, least to most
+///
+/// Names are compared, certificates are not validated, so this is for policy
+/// checks on packages of known provenance, not a replacement for the OS
+/// verifying the signature on install.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SigningStatus {
+    /// No AppxSignature.p7x
+    Unsigned,
+    /// AppxSignature.p7x is present but cannot be parsed
+    Unreadable,
+    /// Self-signed certificate, or content encrypted with the well-known test key
+    TestSigned,
+    /// Certificate of another CA, as for sideloading and enterprise deployment
+    Signed,
+    /// Certificate of a Microsoft Store CA
+    StoreSigned,
+}
+
+impl SigningStatus {
+    /// Status of a package whose header says `signed`, with the parsed `signature` and the key ids it is encrypted with
+    pub fn new(signed: bool, signature: Option<&SignatureInfo>, key_ids: &[KeyId]) -> Self {
+        match signature {
+            None if signed => SigningStatus::Unreadable,
+            None => SigningStatus::Unsigned,
+            Some(signature) if signature.signer.is_self_signed() || key_ids.contains(&TEST_KEY_ID) => SigningStatus::TestSigned,
+            Some(signature) if signature.is_store_signed() => SigningStatus::StoreSigned,
+            Some(_) => SigningStatus::Signed,
+        }
+    }
+}
+
+impl std::fmt::Display for SigningStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SigningStatus::Unsigned => "unsigned",
+            SigningStatus::Unreadable => "unreadable",
+            SigningStatus::TestSigned => "test-signed",
+            SigningStatus::Signed => "signed",
+            SigningStatus::StoreSigned => "store-signed",
+        };
+        f.pad(name)
+    }
+}
+
+impl std::fmt::Display for SignatureInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Signer: {}", self.signer.subject)?;
+        writeln!(f, "Thumbprint: {}", self.signer.thumbprint)?;
+        writeln!(f, "Digest algorithm: {}", self.digest_algorithm)?;
+        writeln!(f, "Signing time: {}", self.signing_time.as_deref().unwrap_or("-"))?;
+        writeln!(f, "Timestamp: {}", self.timestamp.as_deref().unwrap_or("-"))?;
+        for certificate in std::iter::once(&self.signer).chain(&self.chain) {
+            writeln!(f, "Certificate: {} (issuer: {}, valid {} - {})",
+                certificate.subject, certificate.issuer, certificate.not_before, certificate.not_after)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "package")]
+impl EAppxFile {
+    /// Signer details of AppxSignature.p7x, `None` for unsigned packages
+    pub fn signature_info<P: PackageSource + ?Sized>(&self, source: &P) -> Result<Option<SignatureInfo>, Error> {
+        self.read_footprint_file(source, FootprintFile::Signature)?
+            .map(|p7x| SignatureInfo::from_p7x(&p7x))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::der::{attribute, der, integer, octet_string, oid, sequence, utc_time, TAG_GENERALIZED_TIME};
+
+    /// Taken from the MakeAppx signed test package
+    const P7X: &[u8] = include_bytes!("../testdata/AppxSignature.p7x");
+
+    #[test]
+    fn test_signature_info() {
+        let info = SignatureInfo::from_p7x(P7X).unwrap();
+        assert_eq!(info.signer.subject, "CN=dev");
+        assert!(info.signer.is_self_signed());
+        assert_eq!(info.signer.thumbprint, "443FCB5E09F0B34F544E2ABFD87A3135958BBD40");
+        assert_eq!(info.signer.serial_number, "1F0C31A166680DBB41669119C9D9406F");
+        assert_eq!(info.signer.not_before, "2024-03-01 06:20:21 UTC");
+        assert_eq!(info.digest_algorithm, "SHA256");
+        assert_eq!((info.signing_time, info.timestamp), (None, None));
+        assert!(info.chain.is_empty());
+        assert_eq!(SignatureInfo::from_p7x(&P7X[4..]).unwrap().signer, info.signer);
+
+        assert!(SignatureInfo::from_p7x(&P7X[..P7X.len() - 1]).is_err());
+        assert!(SignatureInfo::from_p7x(b"PKCX").is_err());
+    }
+
+    #[test]
+    fn test_signing_status() {
+        let info = SignatureInfo::from_p7x(P7X).unwrap();
+        assert_eq!(SigningStatus::new(false, None, &[]), SigningStatus::Unsigned);
+        assert_eq!(SigningStatus::new(true, None, &[]), SigningStatus::Unreadable);
+        assert_eq!(SigningStatus::new(true, Some(&info), &[]), SigningStatus::TestSigned);
+
+        let mut store = info.clone();
+        store.signer.issuer = "CN=Microsoft Marketplace CA G 027, O=Microsoft Corporation".into();
+        assert!(store.is_store_signed());
+        assert_eq!(SigningStatus::new(true, Some(&store), &[]), SigningStatus::StoreSigned);
+        // The test key is public, whoever signed the package
+        assert_eq!(SigningStatus::new(true, Some(&store), &[TEST_KEY_ID]), SigningStatus::TestSigned);
+
+        let mut developer = info.clone();
+        developer.signer.issuer = "CN=Contoso Code Signing CA".into();
+        assert_eq!(SigningStatus::new(true, Some(&developer), &[]), SigningStatus::Signed);
+        assert!(SigningStatus::Signed > SigningStatus::TestSigned && SigningStatus::TestSigned > SigningStatus::Unsigned);
+    }
+
+    #[cfg(feature = "package")]
+    #[test]
+    fn test_unsigned_package() {
+        let package: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+        let eappx = EAppxFile::from_source(package).unwrap();
+        assert_eq!(eappx.signature_info(package).unwrap(), None);
+    }
+
+    #[test]
+    fn test_signature_times() {
+        let name = |cn: &str| sequence(&[&der(TAG_SET, &sequence(&[&oid(&[2, 5, 4, 3]), &der(0x0c, cn.as_bytes())]))]);
+        let algorithm = sequence(&[&oid(&[1, 3, 14, 3, 2, 26])]);
+        let certificate = sequence(&[
+            &sequence(&[
+                &integer(&[7]),
+                &algorithm,
+                &name("Contoso, Ltd"),
+                &sequence(&[&utc_time(0), &der(TAG_GENERALIZED_TIME, b"20500101000000Z")]),
+                &sequence(&[&der(TAG_SET, &[
+                    sequence(&[&oid(&[2, 5, 4, 6]), &der(0x13, b"US")]),
+                    sequence(&[&oid(&[2, 5, 4, 3]), &der(0x0c, b"Contoso App")]),
+                ].concat())]),
+            ]),
+            &algorithm,
+        ]);
+        let signer_info = |signed: Vec<u8>, unsigned: Vec<u8>| sequence(&[
+            &integer(&[1]),
+            &sequence(&[&name("Contoso, Ltd"), &integer(&[7])]),
+            &algorithm,
+            &der(TAG_CONTEXT_0, &signed),
+            &algorithm,
+            &octet_string(&[0; 4]),
+            &unsigned,
+        ]);
+        let counter_signature = signer_info(attribute(OID_SIGNING_TIME, &utc_time(1710107759)), vec![]);
+        let signed_data = sequence(&[
+            &integer(&[1]),
+            &der(TAG_SET, &[]),
+            &sequence(&[&oid(&[1, 3, 6, 1, 4, 1, 311, 2, 1, 4])]),
+            &der(TAG_CONTEXT_0, &certificate),
+            &der(TAG_SET, &signer_info(
+                attribute(OID_SIGNING_TIME, &utc_time(1710107700)),
+                der(TAG_CONTEXT_1, &attribute(OID_COUNTER_SIGNATURE, &counter_signature)),
+            )),
+        ]);
+        let p7x = sequence(&[&oid(OID_SIGNED_DATA), &der(TAG_CONTEXT_0, &signed_data)]);
+
+        let info = SignatureInfo::from_p7x(&p7x).unwrap();
+        assert_eq!(info.signer.subject, "C=US + CN=Contoso App");
+        assert_eq!(info.signer.issuer, "CN=\"Contoso, Ltd\"");
+        assert_eq!(info.signer.not_after, "2050-01-01 00:00:00 UTC");
+        assert_eq!(info.digest_algorithm, "SHA1");
+        assert_eq!(info.signing_time.as_deref(), Some("2024-03-10 21:55:00 UTC"));
+        assert_eq!(info.timestamp.as_deref(), Some("2024-03-10 21:55:59 UTC"));
+        assert!(info.to_string().contains("Timestamp: 2024-03-10 21:55:59 UTC"));
+    }
+}
@@ -0,0 +1,341 @@
+//! Authenticode signatures for zip based containers (AppxSignature.p7x)
+//!
+//! The signature is a PKCS#7 SignedData over `SpcIndirectDataContent`, whose
+//! digest is not a single hash but an `APPX` blob of tagged SHA-256 hashes:
+//! the zip records up to the signature entry (`AXPC`), the central directory
+//! as it would look without the signature entry (`AXCD`), `[Content_Types].xml`
+//! (`AXCT`), the blockmap (`AXBM`) and, if present, the code integrity catalog
+//! (`AXCI`). The P7X file is that DER prefixed with `PKCX`.
+//!
+//! Only the digest layout of zip based containers is known, encrypted
+//! containers cannot be signed.
+
+use openssl::{
+    hash::MessageDigest,
+    pkcs12::Pkcs12,
+    pkey::{Id, PKey, Private},
+    sign::Signer,
+    x509::X509,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    der::{
+        algorithm, attribute, der, integer, octet_string, oid, sequence, set_of,
+        OID_SHA256, OID_SIGNED_DATA, OID_SPC_INDIRECT_DATA, TAG_CONTEXT_0, TAG_SEQUENCE, TAG_SET,
+    },
+    error::Error,
+    extension::ContainerKind,
+};
+
+pub const P7X_MAGIC: &[u8; 4] = b"PKCX";
+
+const OID_CONTENT_TYPE: &[u64] = &[1, 2, 840, 113549, 1, 9, 3];
+const OID_MESSAGE_DIGEST: &[u64] = &[1, 2, 840, 113549, 1, 9, 4];
+const OID_RSA_ENCRYPTION: &[u64] = &[1, 2, 840, 113549, 1, 1, 1];
+const OID_ECDSA_WITH_SHA256: &[u64] = &[1, 2, 840, 10045, 4, 3, 2];
+const OID_SPC_STATEMENT_TYPE: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 11];
+const OID_SPC_SP_OPUS_INFO: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 12];
+const OID_SPC_INDIVIDUAL_SP_KEY_PURPOSE: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 21];
+const OID_SPC_SIPINFO: &[u64] = &[1, 3, 6, 1, 4, 1, 311, 2, 1, 30];
+
+/// SIP version the Appx SIP expects in `SpcSipInfo`
+const SIP_VERSION: u32 = 0x01010000;
+/// {0AC5DF4B-CE07-4DE2-B76E-23C839A09FD1}, GUID bytes in Windows order
+const APPX_SIP_GUID: [u8; 16] = [
+    0x4b, 0xdf, 0xc5, 0x0a, 0x07, 0xce, 0xe2, 0x4d, 0xb7, 0x6e, 0x23, 0xc8, 0x39, 0xa0, 0x9f, 0xd1,
+];
+/// {0F5F58B3-AADE-4B9A-A434-95742D92ECEB}
+const APPX_BUNDLE_SIP_GUID: [u8; 16] = [
+    0xb3, 0x58, 0x5f, 0x0f, 0xde, 0xaa, 0x9a, 0x4b, 0xa4, 0x34, 0x95, 0x74, 0x2d, 0x92, 0xec, 0xeb,
+];
+
+/// Hashes the Appx SIP signs instead of the file as a whole
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppxDigest {
+    /// Local headers and data of every entry before the signature
+    pub zip_records: Vec<u8>,
+    /// Central directory and end records without the signature entry
+    pub central_directory: Vec<u8>,
+    pub content_types: Vec<u8>,
+    pub blockmap: Vec<u8>,
+    pub code_integrity: Option<Vec<u8>>,
+}
+
+impl AppxDigest {
+    /// The `APPX` blob carried as digest of the indirect data
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut blob = b"APPX".to_vec();
+        let hashes = [
+            (b"AXPC", Some(&self.zip_records)),
+            (b"AXCD", Some(&self.central_directory)),
+            (b"AXCT", Some(&self.content_types)),
+            (b"AXBM", Some(&self.blockmap)),
+            (b"AXCI", self.code_integrity.as_ref()),
+        ];
+        for (tag, hash) in hashes {
+            if let Some(hash) = hash {
+                blob.extend_from_slice(tag);
+                blob.extend_from_slice(hash);
+            }
+        }
+        blob
+    }
+
+    /// Parse an `APPX` blob, e.g. taken from an existing signature
+    pub fn from_bytes(blob: &[u8]) -> Result<Self, Error> {
+        let Some(mut rest) = blob.strip_prefix(b"APPX") else {
+            return Err(Error::DecodeError("Appx digest does not start with APPX".into()));
+        };
+
+        let mut digest = Self {
+            zip_records: vec![],
+            central_directory: vec![],
+            content_types: vec![],
+            blockmap: vec![],
+            code_integrity: None,
+        };
+        while !rest.is_empty() {
+            if rest.len() < 4 + 32 {
+                return Err(Error::DecodeError(format!("Truncated Appx digest entry ({} bytes left)", rest.len())));
+            }
+            let (tag, hash) = (&rest[..4], rest[4..36].to_vec());
+            match tag {
+                b"AXPC" => digest.zip_records = hash,
+                b"AXCD" => digest.central_directory = hash,
+                b"AXCT" => digest.content_types = hash,
+                b"AXBM" => digest.blockmap = hash,
+                b"AXCI" => digest.code_integrity = Some(hash),
+                _ => log::warn!("Skipping unknown Appx digest entry {}", String::from_utf8_lossy(tag)),
+            }
+            rest = &rest[36..];
+        }
+        Ok(digest)
+    }
+}
+
+/// Certificate and private key packages get signed with
+///
+/// The certificate subject has to match the `Publisher` of the manifest, or
+/// Windows refuses to install the package.
+#[derive(Clone)]
+pub struct SigningIdentity {
+    key: PKey<Private>,
+    certificate: X509,
+    /// Intermediates shipped along with the signature
+    chain: Vec<X509>,
+}
+
+impl std::fmt::Debug for SigningIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningIdentity")
+            .field("subject", &self.certificate.subject_name())
+            .field("chain", &self.chain.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn openssl_error(what: &str) -> impl FnOnce(openssl::error::ErrorStack) -> Error + '_ {
+    move |e| Error::data(what, e)
+}
+
+impl SigningIdentity {
+    pub fn new(certificate: X509, key: PKey<Private>, chain: Vec<X509>) -> Result<Self, Error> {
+        let public_key = certificate.public_key().map_err(openssl_error("Failed to read certificate key"))?;
+        if !public_key.public_eq(&key) {
+            return Err(Error::DataError("Private key does not belong to the signing certificate".into()));
+        }
+        if ![Id::RSA, Id::EC].contains(&key.id()) {
+            return Err(Error::DataError(format!("Unsupported signing key type {:?}", key.id())));
+        }
+        Ok(Self { key, certificate, chain })
+    }
+
+    /// Load from a PFX/PKCS#12 file, as exported by the Windows certificate store
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Result<Self, Error> {
+        let parsed = Pkcs12::from_der(der)
+            .and_then(|pkcs12| pkcs12.parse2(password))
+            .map_err(openssl_error("Failed to open PFX, wrong password?"))?;
+
+        match (parsed.cert, parsed.pkey) {
+            (Some(certificate), Some(key)) => {
+                let chain = parsed.ca.map(|ca| ca.into_iter().collect()).unwrap_or_default();
+                Self::new(certificate, key, chain)
+            },
+            _ => Err(Error::DataError("PFX lacks certificate or private key".into())),
+        }
+    }
+
+    /// Load from PEM, the first certificate is the signer, further ones its chain
+    pub fn from_pem(certificates: &[u8], key: &[u8]) -> Result<Self, Error> {
+        let mut certificates = X509::stack_from_pem(certificates)
+            .map_err(openssl_error("Failed to parse PEM certificates"))?;
+        let key = PKey::private_key_from_pem(key)
+            .map_err(openssl_error("Failed to parse PEM private key"))?;
+        if certificates.is_empty() {
+            return Err(Error::DataError("No certificate in PEM".into()));
+        }
+
+        let certificate = certificates.remove(0);
+        Self::new(certificate, key, certificates)
+    }
+
+    pub fn certificate(&self) -> &X509 {
+        &self.certificate
+    }
+
+    /// Build AppxSignature.p7x over `digest`
+    pub fn sign(&self, digest: &AppxDigest, kind: ContainerKind) -> Result<Vec<u8>, Error> {
+        let sip_guid = match kind {
+            ContainerKind::Package => APPX_SIP_GUID,
+            ContainerKind::Bundle => APPX_BUNDLE_SIP_GUID,
+        };
+        let zero = integer(&[0]);
+        let sip_info = sequence(&[
+            &integer(&SIP_VERSION.to_be_bytes()),
+            &octet_string(&sip_guid),
+            &zero, &zero, &zero, &zero, &zero,
+        ]);
+        let indirect_data = [
+            sequence(&[&oid(OID_SPC_SIPINFO), &sip_info]),
+            sequence(&[&algorithm(OID_SHA256), &octet_string(&digest.to_bytes())]),
+        ].concat();
+
+        let signed_data = self.signed_data(OID_SPC_INDIRECT_DATA, &indirect_data)?;
+        Ok([&P7X_MAGIC[..], &signed_data].concat())
+    }
+
+    /// PKCS#7 SignedData over the SEQUENCE of `content_type` whose content is `content`
+    ///
+    /// Attributes follow Authenticode, catalogs and P7X signatures share them.
+    pub(crate) fn signed_data(&self, content_type: &[u64], content: &[u8]) -> Result<Vec<u8>, Error> {
+        // The message digest covers the content without its own SEQUENCE header
+        let message_digest = Sha256::digest(content);
+        let attributes = set_of(vec![
+            attribute(OID_SPC_SP_OPUS_INFO, &sequence(&[])),
+            attribute(OID_CONTENT_TYPE, &oid(content_type)),
+            attribute(OID_SPC_STATEMENT_TYPE, &sequence(&[&oid(OID_SPC_INDIVIDUAL_SP_KEY_PURPOSE)])),
+            attribute(OID_MESSAGE_DIGEST, &octet_string(&message_digest)),
+        ]);
+
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)
+            .map_err(openssl_error("Failed to create signer"))?;
+        let signature = signer.sign_oneshot_to_vec(&attributes)
+            .map_err(openssl_error("Failed to sign"))?;
+        let signature_algorithm = match self.key.id() {
+            Id::EC => sequence(&[&oid(OID_ECDSA_WITH_SHA256)]),
+            _ => algorithm(OID_RSA_ENCRYPTION),
+        };
+
+        let issuer = self.certificate.issuer_name().to_der()
+            .map_err(openssl_error("Failed to encode certificate issuer"))?;
+        let serial = self.certificate.serial_number().to_bn()
+            .map_err(openssl_error("Failed to read certificate serial"))?
+            .to_vec();
+        // Signed attributes are stored as [0] IMPLICIT instead of SET
+        let signer_info = sequence(&[
+            &integer(&[1]),
+            &sequence(&[&issuer, &integer(&serial)]),
+            &algorithm(OID_SHA256),
+            &[&[TAG_CONTEXT_0], &attributes[1..]].concat(),
+            &signature_algorithm,
+            &octet_string(&signature),
+        ]);
+
+        let mut certificates = vec![];
+        for certificate in std::iter::once(&self.certificate).chain(&self.chain) {
+            certificates.extend(certificate.to_der().map_err(openssl_error("Failed to encode certificate"))?);
+        }
+
+        let signed_data = sequence(&[
+            &integer(&[1]),
+            &der(TAG_SET, &algorithm(OID_SHA256)),
+            &sequence(&[&oid(content_type), &der(TAG_CONTEXT_0, &der(TAG_SEQUENCE, content))]),
+            &der(TAG_CONTEXT_0, &certificates),
+            &der(TAG_SET, &signer_info),
+        ]);
+        Ok(sequence(&[&oid(OID_SIGNED_DATA), &der(TAG_CONTEXT_0, &signed_data)]))
+    }
+}
+
+/// Throwaway self-signed identity for tests
+#[cfg(test)]
+pub(crate) fn test_identity() -> SigningIdentity {
+    use openssl::{asn1::Asn1Time, bn::BigNum, rsa::Rsa, x509::X509NameBuilder};
+
+    let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "dev").unwrap();
+    let name = name.build();
+
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    builder.set_serial_number(&BigNum::from_u32(0x8badf00d).unwrap().to_asn1_integer().unwrap()).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(&name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    builder.sign(&key, MessageDigest::sha256()).unwrap();
+
+    SigningIdentity::new(builder.build(), key, vec![]).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use openssl::sign::Verifier;
+
+    use super::*;
+
+    /// Digest blob of the MakeAppx signed test package
+    #[test]
+    fn test_appx_digest_roundtrip() {
+        let blob = hex::decode(concat!(
+            "41505058",
+            "415850437de4c263eb21ebaf6fba8d29dffb9c3d1812e7b8f1811b805939c4224e3627ed",
+            "415843440c9a2c1ddb5a966426de4c82c1bef9dfe4090b6f2d68d525b12ed2be303548d0",
+            "41584354e1a69d6568158b11e8b6510733fcf947749ba4f85b880bfc4c397374867ba342",
+            "4158424d93ef2331b6a17599085edb257317e42ba109b059a2dc3c1d57623f53f3d64438",
+            "415843494589f5fa40dd37b605e78f6fc29e8b2efe9c9ac535552dc7d469cb9d19f05c34",
+        )).unwrap();
+
+        let digest = AppxDigest::from_bytes(&blob).unwrap();
+        assert_eq!(hex::encode(&digest.content_types), "e1a69d6568158b11e8b6510733fcf947749ba4f85b880bfc4c397374867ba342");
+        assert!(digest.code_integrity.is_some());
+        assert_eq!(digest.to_bytes(), blob);
+        assert!(AppxDigest::from_bytes(&blob[..30]).is_err());
+    }
+
+    #[test]
+    fn test_sign() {
+        let identity = test_identity();
+        let digest = AppxDigest {
+            zip_records: vec![1; 32],
+            central_directory: vec![2; 32],
+            content_types: vec![3; 32],
+            blockmap: vec![4; 32],
+            code_integrity: None,
+        };
+        let p7x = identity.sign(&digest, ContainerKind::Package).unwrap();
+        assert_eq!(&p7x[..4], P7X_MAGIC);
+
+        // Whole structure parses as PKCS#7 and carries the signer certificate
+        let pkcs7 = openssl::pkcs7::Pkcs7::from_der(&p7x[4..]).unwrap();
+        let signers = pkcs7.signed().unwrap().certificates().unwrap();
+        assert_eq!(signers.iter().next().unwrap().to_der().unwrap(), identity.certificate().to_der().unwrap());
+        assert!(p7x.windows(APPX_SIP_GUID.len()).any(|window| window == APPX_SIP_GUID));
+        assert!(p7x.windows(digest.to_bytes().len()).any(|window| window == digest.to_bytes()));
+
+        let info = crate::signature::SignatureInfo::from_p7x(&p7x).unwrap();
+        assert_eq!(info.signer.subject, "CN=dev");
+        assert_eq!(info.signer.serial_number, "8BADF00D");
+        assert_eq!(info.digest_algorithm, "SHA256");
+
+        // Signature covers the DER SET of the authenticated attributes
+        let attributes_start = p7x.windows(2).rposition(|window| window == [TAG_CONTEXT_0, 0x7c]).unwrap();
+        let attributes = &p7x[attributes_start + 2..attributes_start + 2 + 0x7c];
+        let signature = &p7x[p7x.len() - 256..];
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &identity.key).unwrap();
+        assert!(verifier.verify_oneshot(signature, &der(TAG_SET, attributes)).unwrap());
+    }
+}
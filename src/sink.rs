@@ -0,0 +1,229 @@
+//! Extraction into destinations other than the local filesystem
+//!
+//! An [`EntrySink`] is handed the output path of every entry and returns a
+//! writer for its plaintext, so extraction can feed object stores, in-memory
+//! filesystems or content addressed stores. Paths follow the same
+//! [`OutputLayout`] as [`EAppxFile::extract`], always with `/` separators.
+//! Hashes are checked while writing, [`EntrySink::finish`] only sees entries
+//! written in full.
+
+use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+use crate::{
+    error::Error,
+    extraction_report::{ExtractedFile, ExtractionReport, ExtractionStatus, HashingWriter},
+    layout::{ExtractOptions, MissingKeyPolicy, OutputLayout},
+    source::PackageSource,
+    EAppxFile, FileInfo,
+};
+
+/// Where an entry goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkEntry {
+    /// Path inside the package
+    pub package_path: String,
+    /// Output path, relative with `/` separators
+    pub path: String,
+    /// Plaintext size
+    pub size: u64,
+}
+
+/// Destination of extracted entries
+pub trait EntrySink {
+    /// Writer for the plaintext of `entry`
+    fn create(&mut self, entry: &SinkEntry) -> Result<Box<dyn Write + '_>, Error>;
+
+    /// Called once `entry` was written in full, `sha256` covers what was written
+    fn finish(&mut self, _entry: &SinkEntry, _sha256: &[u8]) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// In-memory filesystem, output paths to contents
+impl EntrySink for BTreeMap<String, Vec<u8>> {
+    fn create(&mut self, entry: &SinkEntry) -> Result<Box<dyn Write + '_>, Error> {
+        let data = self.entry(entry.path.clone()).or_default();
+        data.clear();
+        Ok(Box::new(data))
+    }
+}
+
+fn sink_path(relative_path: &std::path::Path) -> String {
+    relative_path.iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl EAppxFile {
+    /// Extract the blockmap file `name` into `sink`, matched case-insensitively
+    pub fn extract_entry_to<P: PackageSource + ?Sized>(&self, source: &P, name: &str, sink: &mut dyn EntrySink) -> Result<ExtractedFile, Error> {
+        let file = self.blockmap.find_file(name)
+            .ok_or(Error::DataError(format!("File {name} not found in blockmap")))?;
+        let relative_path = OutputLayout::new(self.extract_options).map(&file.name)?;
+        self.write_to_sink(source, self.blockmap_fileinfo(file)?, &file.name, relative_path, sink)
+    }
+
+    /// Extract everything into `sink`, the counterpart of [`EAppxFile::extract_with_report`]
+    pub fn extract_to_sink<P: PackageSource + ?Sized>(&self, source: &P, sink: &mut dyn EntrySink) -> Result<ExtractionReport, Error> {
+        let mut report = self.begin_extraction()?;
+
+        for footprint in self.footprint_entries() {
+            let result = self.read_footprint_file(source, footprint.file).and_then(|data| data.map(|data| {
+                let entry = SinkEntry {
+                    package_path: footprint.file.package_path().to_owned(),
+                    path: footprint.file.file_name().to_owned(),
+                    size: data.len() as u64,
+                };
+                let mut writer = HashingWriter::new(sink.create(&entry)?);
+                writer.write_all(&data)?;
+                let (_, sha256) = writer.finalize();
+                sink.finish(&entry, &sha256)?;
+
+                Ok(ExtractedFile {
+                    package_path: entry.package_path,
+                    path: entry.path.into(),
+                    size: entry.size,
+                    status: match footprint.file == crate::footprint::FootprintFile::BlockMap {
+                        true if sha256 == self.header.block_map_hash => ExtractionStatus::Verified,
+                        true => ExtractionStatus::HashMismatch,
+                        false => ExtractionStatus::Unverified,
+                    },
+                    sha256: Some(sha256),
+                    encrypted: false,
+                    compressed: footprint.compressed,
+                    timings: None,
+                    duplicate_of: None,
+                })
+            }).transpose());
+            if let Some(entry) = self.skip_failed(&mut report, footprint.file.package_path(), result)?.flatten() {
+                report.files.push(entry);
+            }
+        }
+
+        // Metadata files keep their directory, as with extraction to the filesystem
+        let mut metadata_layout = OutputLayout::new(ExtractOptions { flat: false, ..self.extract_options });
+        let mut layout = OutputLayout::new(self.extract_options);
+        for file in &self.blockmap.files {
+            let result = match file.is_metadata() {
+                true => metadata_layout.map(&file.name),
+                false => layout.map(&file.name),
+            }.and_then(|relative_path| self.write_to_sink(source, self.blockmap_fileinfo(file)?, &file.name, relative_path, sink));
+            if let Some(entry) = self.skip_failed(&mut report, &file.name, result)? {
+                report.files.push(entry);
+            }
+        }
+
+        for (name, footer) in self.applicable_bundle_payloads(source)? {
+            let result = layout.map(&name)
+                .and_then(|relative_path| self.write_to_sink(source, footer, &name, relative_path, sink));
+            if let Some(entry) = self.skip_failed(&mut report, &name, result)? {
+                report.files.push(entry);
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn write_to_sink<P: PackageSource + ?Sized, I: Into<FileInfo>>(
+        &self,
+        source: &P,
+        fileinfo: I,
+        package_path: &str,
+        relative_path: PathBuf,
+        sink: &mut dyn EntrySink,
+    ) -> Result<ExtractedFile, Error> {
+        let fileinfo = FileInfo { params: self.params, ..fileinfo.into() };
+        let entry = SinkEntry {
+            package_path: package_path.to_owned(),
+            path: sink_path(&relative_path),
+            size: fileinfo.uncompressed_length,
+        };
+        log::info!("* Extracting {package_path} to {}", entry.path);
+
+        let crypto = self.crypto_context(fileinfo.key_id_index, package_path)?;
+        let is_encrypted = self.is_entry_encrypted(&fileinfo);
+        let missing_key = is_encrypted && crypto.is_none();
+        let compressed = fileinfo.compression_type == 0x1;
+        let filehash = fileinfo.filehash.clone();
+
+        if missing_key && self.extract_options.on_missing_key == MissingKeyPolicy::SkipEncryptedWithoutKey {
+            log::warn!("Skipping {package_path}, its key is not loaded");
+            return Ok(ExtractedFile {
+                package_path: entry.package_path,
+                path: relative_path,
+                size: entry.size,
+                status: ExtractionStatus::SkippedKeyMissing,
+                sha256: None,
+                encrypted: is_encrypted,
+                compressed,
+                timings: None,
+                duplicate_of: None,
+            });
+        }
+        let as_ciphertext = missing_key && self.extract_options.on_missing_key == MissingKeyPolicy::ExtractCiphertext;
+        let entry = match as_ciphertext {
+            true => SinkEntry { size: fileinfo.params.align_to_sector(fileinfo.uncompressed_length as usize) as u64, ..entry },
+            false => entry,
+        };
+
+        let mut writer = HashingWriter::with_filehash(sink.create(&entry)?, fileinfo.filehash_algorithm);
+        let timings = match as_ciphertext {
+            true => {
+                log::warn!("Writing {package_path} as ciphertext, its key is not loaded");
+                Self::read_ciphertext(source, &mut writer, &fileinfo, package_path)?;
+                None
+            },
+            false => Some(Self::read_entry(source, &mut writer, fileinfo, Some(package_path), self.header.is_bundle(), crypto, self.integrity)?),
+        };
+        writer.flush()?;
+        let (size, sha256, digest) = writer.finalize_digests();
+        sink.finish(&entry, &sha256)?;
+
+        Ok(ExtractedFile {
+            package_path: entry.package_path,
+            path: relative_path,
+            size,
+            status: match as_ciphertext {
+                true => ExtractionStatus::Ciphertext,
+                false => ExtractionStatus::of_plaintext(filehash.as_deref(), &digest),
+            },
+            sha256: Some(sha256),
+            encrypted: is_encrypted,
+            compressed,
+            timings: timings.filter(|_| self.collect_timings),
+            duplicate_of: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Read};
+
+    use crate::{extraction_report::ExtractionStatus, EAppxFile};
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_extract_to_sink() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let mut files = BTreeMap::<String, Vec<u8>>::new();
+        let report = eappx.extract_to_sink(EMSIX, &mut files).unwrap();
+        assert!(report.is_valid());
+        assert_eq!(files.len(), report.files.len());
+        assert!(files.contains_key("AppxBlockMap.xml"));
+
+        let file = eappx.blockmap.find_file("TestApp.dll").unwrap();
+        let mut plaintext = vec![];
+        eappx.file_reader(EMSIX, file).unwrap().read_to_end(&mut plaintext).unwrap();
+        assert_eq!(files["TestApp.dll"], plaintext);
+
+        let mut single = BTreeMap::<String, Vec<u8>>::new();
+        let extracted = eappx.extract_entry_to(EMSIX, "assets\\lockscreenlogo.scale-200.png", &mut single).unwrap();
+        assert_eq!(extracted.status, ExtractionStatus::Verified);
+        assert_eq!(single["Assets/LockScreenLogo.scale-200.png"], files["Assets/LockScreenLogo.scale-200.png"]);
+    }
+}
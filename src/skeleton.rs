@@ -0,0 +1,83 @@
+//! Draft manifest and blockmap for a directory of loose files
+//!
+//! Lets a package be reviewed and tweaked before it is packed.
+//! [`draft_manifest`] is an AppxManifest.xml holding only the Identity,
+//! [`skeleton_blockmap`] lists the files in the order they are packed,
+//! AppxManifest.xml first, with the footer table id each one gets.
+//!
+//! Hashes are SHA-256 over the plaintext in 64KiB blocks, as an unencrypted
+//! package has them. Encrypted packages hash their stored ciphertext, only
+//! the file hashes carry over to them.
+
+use std::io::Read;
+
+use crate::{
+    blockmap::{self, AppxBlockMap},
+    digest::HashAlgorithm,
+    error::Error,
+    manifest::{AppxManifest, Identity},
+};
+
+/// Manifest with `identity` and nothing else, Properties, Dependencies,
+/// Resources and Applications are left to the author
+pub fn draft_manifest(identity: Identity) -> AppxManifest {
+    let mut manifest = AppxManifest::default();
+    manifest.identity = identity;
+    manifest
+}
+
+/// Blockmap entry of a file with footer table id `id`
+pub fn skeleton_file<R: Read>(name: &str, id: u64, reader: &mut R) -> Result<blockmap::File, Error> {
+    blockmap::hash_file(name, id, reader, HashAlgorithm::Sha256, None)
+}
+
+/// Blockmap of `files`, AppxManifest.xml moves to the front and ids follow the resulting order
+pub fn skeleton_blockmap<N, R, I>(files: I) -> Result<AppxBlockMap, Error>
+where
+    N: AsRef<str>,
+    R: Read,
+    I: IntoIterator<Item = (N, R)>,
+{
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort_by_key(|(name, _)| name.as_ref() != "AppxManifest.xml");
+
+    let mut blockmap = AppxBlockMap::default();
+    for (id, (name, mut reader)) in files.into_iter().enumerate() {
+        blockmap.files.push(skeleton_file(name.as_ref(), id as u64, &mut reader)?);
+    }
+    Ok(blockmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory, sample, EAppxFile};
+
+    #[test]
+    fn test_skeleton() {
+        let manifest = draft_manifest(AppxManifest::from_xml(sample::sample_manifest().as_bytes()).unwrap().identity);
+        let xml = manifest.to_xml();
+        assert_eq!(AppxManifest::from_xml(xml.as_bytes()).unwrap().identity.package_full_name(), sample::sample_package_full_name());
+
+        // Manifest listed last, still packed first
+        let mut files = sample::sample_files().into_iter()
+            .map(|(name, data, _)| (name, data))
+            .collect::<Vec<_>>();
+        files.rotate_left(1);
+        let blockmap = skeleton_blockmap(files.iter().map(|(name, data)| (name, data.as_slice()))).unwrap();
+        assert_eq!(blockmap.files[0].name, "AppxManifest.xml");
+        assert!(blockmap.files[3].blocks.len() > 1);
+        let xml = blockmap.to_xml();
+        assert_eq!(AppxBlockMap::from_xml(xml.as_bytes()).unwrap().to_xml(), xml);
+
+        // Same as the blockmap of the unencrypted package
+        let package = memory::pack(&sample::sample_package_full_name(), vec![], files).unwrap();
+        let eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        for (skeleton, packed) in blockmap.files.iter().zip(&eappx.blockmap.files) {
+            assert_eq!((&skeleton.name, skeleton.id(), skeleton.size), (&packed.name, packed.id(), packed.size));
+            assert_eq!(skeleton.block_hashes().unwrap(), packed.block_hashes().unwrap());
+            assert_eq!(skeleton.filehash_bytes().unwrap(), packed.filehash_bytes().unwrap());
+        }
+        assert_eq!(blockmap.files.len(), eappx.blockmap.files.len());
+    }
+}
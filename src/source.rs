@@ -0,0 +1,452 @@
+//! Random access to the bytes of a package
+//!
+//! A [`PackageSource`] reads at an offset instead of seeking first, so any
+//! number of readers can share one handle: a file, bytes in memory, a memory
+//! map or ranges requested from a server. Every read path of the crate takes
+//! one. [`SourceReader`] puts a buffered `BufRead + Seek` stream on top for
+//! parsers that want one, [`StreamSource`] goes the other way for streams
+//! without positioned reads.
+//!
+//! ```
+//! # #[cfg(feature = "package")] {
+//! use std::sync::Arc;
+//! use eappx::EAppxFile;
+//!
+//! let source: Arc<[u8]> = eappx::sample::build_sample_package()?.into();
+//! let eappx = EAppxFile::from_source(&source)?;
+//! assert!(eappx.verify(&source).is_valid());
+//! # }
+//! # Ok::<(), eappx::error::Error>(())
+//! ```
+
+use std::{
+    io::{BufRead, Read, Seek, SeekFrom},
+    sync::{Arc, Mutex},
+};
+
+/// Bytes read at every [`SourceReader`] refill, one block of a package
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Package bytes readable at any offset, from any thread
+pub trait PackageSource: Send + Sync {
+    /// Read into `buf` starting at `offset`, returns the amount read, `0` past the end
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize>;
+
+    /// Total size in bytes
+    fn len(&self) -> std::io::Result<u64>;
+
+    /// Another handle onto the same bytes, e.g. to hand to a thread
+    fn clone_handle(&self) -> std::io::Result<Self>
+    where
+        Self: Sized;
+
+    fn is_empty(&self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Fill `buf` from `offset`, failing with `UnexpectedEof` if the source ends first
+    fn read_exact_at(&self, mut offset: u64, mut buf: &mut [u8]) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(offset, buf) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(read) => {
+                    offset += read as u64;
+                    buf = &mut buf[read..];
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {},
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Buffered stream over a handle of its own, starting at offset 0
+    fn reader(&self) -> std::io::Result<SourceReader<Self>>
+    where
+        Self: Sized,
+    {
+        Ok(SourceReader::new(self.clone_handle()?))
+    }
+
+    /// Buffered stream borrowing this handle, starting at `offset`
+    fn reader_at(&self, offset: u64) -> SourceReader<&Self> {
+        let mut reader = SourceReader::new(self);
+        reader.position = offset;
+        reader
+    }
+
+    /// Buffered reader over `length` bytes from `offset`, borrowing this handle
+    fn range(&self, offset: u64, length: u64) -> std::io::Take<SourceReader<&Self>> {
+        let capacity = std::cmp::min(length, BUFFER_SIZE as u64) as usize;
+        let mut reader = SourceReader::with_capacity(self, capacity);
+        reader.position = offset;
+        reader.take(length)
+    }
+
+    /// The `length` bytes from `offset` as a source of their own, e.g. a package embedded in a bundle
+    fn slice(&self, offset: u64, length: u64) -> SourceSlice<'_, Self> {
+        SourceSlice { source: self, offset, length }
+    }
+}
+
+impl<S: PackageSource + ?Sized> PackageSource for &S {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        (**self).read_at(offset, buf)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        (**self).len()
+    }
+
+    fn clone_handle(&self) -> std::io::Result<Self> {
+        Ok(*self)
+    }
+}
+
+impl PackageSource for [u8] {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = std::cmp::min(offset, self.len() as u64) as usize;
+        let read = std::cmp::min(buf.len(), self.len() - start);
+        buf[..read].copy_from_slice(&self[start..start + read]);
+        Ok(read)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(<[u8]>::len(self) as u64)
+    }
+}
+
+impl PackageSource for Vec<u8> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.as_slice().read_at(offset, buf)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(Vec::len(self) as u64)
+    }
+
+    fn clone_handle(&self) -> std::io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+/// Bytes in memory or memory mapped, e.g. `Arc<[u8]>`, `Arc<Vec<u8>>` or an `Arc` around a map
+impl<T: AsRef<[u8]> + Send + Sync + ?Sized> PackageSource for Arc<T> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        (**self).as_ref().read_at(offset, buf)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok((**self).as_ref().len() as u64)
+    }
+
+    fn clone_handle(&self) -> std::io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+/// Positioned reads, which leave the cursor alone on unix and move it on Windows
+#[cfg(any(unix, windows))]
+impl PackageSource for std::fs::File {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        #[cfg(unix)]
+        return std::os::unix::fs::FileExt::read_at(self, buf, offset);
+        #[cfg(windows)]
+        return std::os::windows::fs::FileExt::seek_read(self, buf, offset);
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn clone_handle(&self) -> std::io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+/// Part of a [`PackageSource`], offsets count from its start, see [`PackageSource::slice`]
+#[derive(Debug)]
+pub struct SourceSlice<'a, S: ?Sized> {
+    source: &'a S,
+    offset: u64,
+    length: u64,
+}
+
+impl<S: ?Sized> Clone for SourceSlice<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: ?Sized> Copy for SourceSlice<'_, S> {}
+
+impl<S: PackageSource + ?Sized> PackageSource for SourceSlice<'_, S> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(available) = self.length.checked_sub(offset).filter(|available| *available > 0) else {
+            return Ok(0);
+        };
+        let amount = std::cmp::min(buf.len() as u64, available) as usize;
+        self.source.read_at(self.offset + offset, &mut buf[..amount])
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        Ok(self.length)
+    }
+
+    fn clone_handle(&self) -> std::io::Result<Self> {
+        Ok(*self)
+    }
+}
+
+/// [`PackageSource`] over a `Read + Seek` stream, for callers holding nothing else
+///
+/// Reads seek first, one at a time under a lock, and leave the stream
+/// wherever they ended. There is no second handle to the stream,
+/// [`PackageSource::clone_handle`] fails.
+#[derive(Debug)]
+pub struct StreamSource<S>(Mutex<S>);
+
+impl<S: Read + Seek + Send> StreamSource<S> {
+    pub fn new(stream: S) -> Self {
+        Self(Mutex::new(stream))
+    }
+
+    pub fn into_inner(self) -> S {
+        self.0.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, S> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S: Read + Seek + Send> PackageSource for StreamSource<S> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut stream = self.lock();
+        stream.seek(SeekFrom::Start(offset))?;
+        stream.read(buf)
+    }
+
+    fn len(&self) -> std::io::Result<u64> {
+        self.lock().seek(SeekFrom::End(0))
+    }
+
+    fn clone_handle(&self) -> std::io::Result<Self> {
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "A stream has a single handle"))
+    }
+}
+
+/// Buffered `BufRead + Seek` stream over a [`PackageSource`]
+///
+/// Seeking within the buffered bytes keeps them, seeking elsewhere drops
+/// them. Reads at least as large as the buffer bypass it.
+#[derive(Debug)]
+pub struct SourceReader<S> {
+    source: S,
+    /// Offset of the next byte handed out
+    position: u64,
+    buffer: Box<[u8]>,
+    /// Bytes of `buffer` holding data
+    filled: usize,
+    /// Bytes of `buffer` handed out already
+    consumed: usize,
+}
+
+impl<S: PackageSource> SourceReader<S> {
+    pub fn new(source: S) -> Self {
+        Self::with_capacity(source, BUFFER_SIZE)
+    }
+
+    pub fn with_capacity(source: S, capacity: usize) -> Self {
+        Self { source, position: 0, buffer: vec![0; capacity].into_boxed_slice(), filled: 0, consumed: 0 }
+    }
+
+    pub fn source(&self) -> &S {
+        &self.source
+    }
+
+    pub fn into_inner(self) -> S {
+        self.source
+    }
+}
+
+impl<S: PackageSource> Read for SourceReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.consumed == self.filled && buf.len() >= self.buffer.len() {
+            let read = self.source.read_at(self.position, buf)?;
+            self.position += read as u64;
+            return Ok(read);
+        }
+        let available = self.fill_buf()?;
+        let read = std::cmp::min(available.len(), buf.len());
+        buf[..read].copy_from_slice(&available[..read]);
+        self.consume(read);
+        Ok(read)
+    }
+}
+
+impl<S: PackageSource> BufRead for SourceReader<S> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.consumed == self.filled {
+            self.filled = self.source.read_at(self.position, &mut self.buffer)?;
+            self.consumed = 0;
+        }
+        Ok(&self.buffer[self.consumed..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = std::cmp::min(amt, self.filled - self.consumed);
+        self.consumed += amt;
+        self.position += amt as u64;
+    }
+}
+
+impl<S: PackageSource> Seek for SourceReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let (base, delta) = match pos {
+            SeekFrom::Start(offset) => (offset, 0),
+            SeekFrom::Current(delta) => (self.position, delta),
+            SeekFrom::End(delta) => (self.source.len()?, delta),
+        };
+        let target = base.checked_add_signed(delta)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Seek to a negative or overflowing offset"))?;
+
+        let buffer_start = self.position - self.consumed as u64;
+        match target.checked_sub(buffer_start) {
+            Some(index) if index <= self.filled as u64 => self.consumed = index as usize,
+            _ => (self.filled, self.consumed) = (0, 0),
+        }
+        self.position = target;
+        Ok(target)
+    }
+
+    fn stream_position(&mut self) -> std::io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+#[cfg(all(test, feature = "package"))]
+mod tests {
+    use std::{io::Cursor, sync::atomic::{AtomicUsize, Ordering}};
+
+    use super::*;
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    /// Stands in for a remote backend, counting its range requests
+    struct CountingSource {
+        data: Arc<[u8]>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl PackageSource for CountingSource {
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.data.read_at(offset, buf)
+        }
+
+        fn len(&self) -> std::io::Result<u64> {
+            self.data.len()
+        }
+
+        fn clone_handle(&self) -> std::io::Result<Self> {
+            Ok(Self { data: self.data.clone(), requests: self.requests.clone() })
+        }
+    }
+
+    #[test]
+    fn test_reader_matches_cursor() {
+        let data = (0..=255u8).cycle().take(3 * BUFFER_SIZE + 17).collect::<Vec<_>>();
+        let mut reader = SourceReader::new(Arc::new(data.clone()));
+        let mut cursor = Cursor::new(&data);
+
+        let mut buf = vec![0; 2 * BUFFER_SIZE];
+        let mut expected = buf.clone();
+        for (pos, amount) in [
+            (SeekFrom::Start(10), 100),
+            (SeekFrom::Current(-50), 30),
+            (SeekFrom::Current(BUFFER_SIZE as i64), 2 * BUFFER_SIZE),
+            (SeekFrom::End(-5), 20),
+            (SeekFrom::Start(0), BUFFER_SIZE + 1),
+        ] {
+            assert_eq!(reader.seek(pos).unwrap(), cursor.seek(pos).unwrap());
+            let read = reader.read(&mut buf[..amount]).unwrap();
+            assert_eq!(read, cursor.read(&mut expected[..amount]).unwrap());
+            assert_eq!(buf[..read], expected[..read]);
+            assert_eq!(reader.stream_position().unwrap(), cursor.position());
+        }
+        assert!(reader.seek(SeekFrom::Current(-(data.len() as i64) - 1)).is_err());
+
+        let mut buf = [0; 4];
+        assert_eq!(reader.source().read_at(data.len() as u64 + 10, &mut buf).unwrap(), 0);
+        assert!(reader.source().read_exact_at(data.len() as u64 - 2, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_slice() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let slice = data.slice(10, 20);
+        assert_eq!(slice.len().unwrap(), 20);
+
+        let mut buf = [0; 10];
+        assert_eq!(slice.read_at(15, &mut buf).unwrap(), 5);
+        assert_eq!(buf[..5], [25, 26, 27, 28, 29]);
+        assert_eq!(slice.read_at(20, &mut buf).unwrap(), 0);
+        assert_eq!(slice.slice(5, 3).read_at(0, &mut buf).unwrap(), 3);
+        assert_eq!(buf[..3], [15, 16, 17]);
+    }
+
+    #[test]
+    fn test_stream_source() {
+        let source = StreamSource::new(Cursor::new(EMSIX));
+        let eappx = EAppxFile::from_source(&source).unwrap();
+        assert!(eappx.verify(&source).is_valid());
+        assert_eq!(source.clone_handle().unwrap_err().kind(), std::io::ErrorKind::Unsupported);
+
+        let streamed = EAppxFile::from_stream(&mut Cursor::new(EMSIX)).unwrap();
+        assert_eq!(streamed.header, eappx.header);
+        assert_eq!(streamed.footers, eappx.footers);
+    }
+
+    #[test]
+    fn test_package_from_source() {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let source = CountingSource { data: Arc::from(EMSIX), requests: requests.clone() };
+        let mut eappx = EAppxFile::from_source(&source).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        assert!(requests.load(Ordering::Relaxed) > 0);
+
+        // Threads read through handles of their own
+        std::thread::scope(|scope| {
+            let handles = (0..2)
+                .map(|_| {
+                    let source = source.clone_handle().unwrap();
+                    let eappx = &eappx;
+                    scope.spawn(move || eappx.verify(&source))
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                let report = handle.join().unwrap();
+                assert!(report.is_valid());
+                assert_eq!(report.files.len(), eappx.blockmap.files.len());
+            }
+        });
+
+        let (mut expected, mut data) = (vec![], vec![]);
+        eappx.entry_reader(EMSIX, "TestApp.dll").unwrap().read_to_end(&mut expected).unwrap();
+        eappx.entry_reader(&source, "TestApp.dll").unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_file_source() {
+        let path = std::env::temp_dir().join(format!("eappx-source-{}.emsix", std::process::id()));
+        std::fs::write(&path, EMSIX).unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        assert_eq!(PackageSource::len(&file).unwrap(), EMSIX.len() as u64);
+        let eappx = EAppxFile::from_source(&file).unwrap();
+        assert_eq!(eappx.blockmap.files.len(), 12);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
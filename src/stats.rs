@@ -0,0 +1,200 @@
+//! Size statistics of a package, from footer table and blockmap alone
+//!
+//! Breaks the stored size down by compression, encryption and file extension,
+//! so a package that grew between versions can be traced to the kind of files
+//! responsible. Payloads are never read: a stored size close to the plaintext
+//! size is the hint for high entropy data, be it ciphertext deflate could not
+//! shrink or media that came compressed already.
+
+use std::collections::HashMap;
+
+use crate::{utils, EAppxFile};
+
+/// Entries storing at least this share of their size gained nothing from compression
+const INCOMPRESSIBLE_RATIO: f64 = 0.98;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeStats {
+    pub count: usize,
+    pub uncompressed: u64,
+    /// As stored in the container
+    pub stored: u64,
+}
+
+impl SizeStats {
+    fn add(&mut self, entry: &EntryStats) {
+        self.count += 1;
+        self.uncompressed = self.uncompressed.saturating_add(entry.uncompressed);
+        self.stored = self.stored.saturating_add(entry.stored);
+    }
+
+    /// Stored size relative to the uncompressed size, 1.0 for empty stats
+    pub fn ratio(&self) -> f64 {
+        match self.uncompressed {
+            0 => 1.0,
+            uncompressed => self.stored as f64 / uncompressed as f64,
+        }
+    }
+}
+
+impl std::fmt::Display for SizeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} files, {} stored as {} ({:.1}%)", self.count, utils::get_filesize_with_unit(self.uncompressed),
+            utils::get_filesize_with_unit(self.stored), self.ratio() * 100.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryStats {
+    /// Blockmap name, `None` for the blockmap itself and packages of a bundle
+    pub name: Option<String>,
+    pub file_id: u64,
+    pub uncompressed: u64,
+    pub stored: u64,
+    pub compressed: bool,
+    pub encrypted: bool,
+}
+
+impl EntryStats {
+    pub fn ratio(&self) -> f64 {
+        match self.uncompressed {
+            0 => 1.0,
+            uncompressed => self.stored as f64 / uncompressed as f64,
+        }
+    }
+
+    /// Lowercased extension of the name, empty if there is none
+    pub fn extension(&self) -> String {
+        let filename = self.name.as_deref()
+            .and_then(|name| name.rsplit(['\\', '/']).next())
+            .unwrap_or_default();
+        match filename.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => extension.to_lowercase(),
+            _ => String::new(),
+        }
+    }
+
+    /// Whether the stored size hints at high entropy content
+    pub fn is_incompressible(&self) -> bool {
+        self.uncompressed > 0 && self.ratio() >= INCOMPRESSIBLE_RATIO
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PackageStats {
+    /// Every footer, in footer table order
+    pub entries: Vec<EntryStats>,
+    pub total: SizeStats,
+    pub compressed: SizeStats,
+    pub encrypted: SizeStats,
+    /// Encrypted entries stored deflated, compression ran on ciphertext there
+    pub encrypted_compressed: SizeStats,
+    /// Entries stored at about their full size, see [`EntryStats::is_incompressible`]
+    pub incompressible: SizeStats,
+    /// Per lowercased extension, largest stored size first
+    pub by_extension: Vec<(String, SizeStats)>,
+}
+
+impl PackageStats {
+    /// The `count` entries occupying the most space in the container
+    pub fn largest(&self, count: usize) -> Vec<&EntryStats> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.stored));
+        entries.truncate(count);
+        entries
+    }
+}
+
+impl EAppxFile {
+    /// Size statistics of the package, no payload is read
+    pub fn stats(&self) -> PackageStats {
+        let names = self.blockmap.files.iter()
+            .map(|file| (file.id(), file.name.as_str()))
+            .collect::<HashMap<_, _>>();
+
+        let mut stats = PackageStats::default();
+        let mut by_extension = HashMap::<String, SizeStats>::new();
+        for footer in &self.footers {
+            let entry = EntryStats {
+                name: names.get(&footer.file_id).map(|name| name.to_string()),
+                file_id: footer.file_id,
+                uncompressed: footer.uncompressed_length,
+                stored: self.stored_length(footer),
+                compressed: footer.compression_type == 0x1,
+                encrypted: self.is_footer_encrypted(footer),
+            };
+
+            stats.total.add(&entry);
+            if entry.compressed {
+                stats.compressed.add(&entry);
+            }
+            if entry.encrypted {
+                stats.encrypted.add(&entry);
+            }
+            if entry.encrypted && entry.compressed {
+                stats.encrypted_compressed.add(&entry);
+            }
+            if entry.is_incompressible() {
+                stats.incompressible.add(&entry);
+            }
+            by_extension.entry(entry.extension()).or_default().add(&entry);
+            stats.entries.push(entry);
+        }
+
+        stats.by_extension = by_extension.into_iter().collect();
+        stats.by_extension.sort_by(|a, b| b.1.stored.cmp(&a.1.stored).then_with(|| a.0.cmp(&b.0)));
+        stats
+    }
+}
+
+impl std::fmt::Display for PackageStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Total: {}", self.total)?;
+        writeln!(f, "Compressed: {}", self.compressed)?;
+        writeln!(f, "Encrypted: {}", self.encrypted)?;
+        writeln!(f, "Encrypted and compressed: {}", self.encrypted_compressed)?;
+        writeln!(f, "Incompressible: {}", self.incompressible)?;
+        writeln!(f, "By extension:")?;
+        for (extension, size) in &self.by_extension {
+            let extension = match extension.is_empty() {
+                true => "(none)",
+                false => extension,
+            };
+            writeln!(f, "  {extension}: {size}")?;
+        }
+        writeln!(f, "Largest entries:")?;
+        for entry in self.largest(10) {
+            writeln!(f, "  {:#06x} {}: {} stored as {} ({:.1}%)", entry.file_id, entry.name.as_deref().unwrap_or("-"),
+                utils::get_filesize_with_unit(entry.uncompressed), utils::get_filesize_with_unit(entry.stored), entry.ratio() * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+
+    #[test]
+    fn test_stats() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let stats = eappx.stats();
+        assert_eq!(stats.total.count, eappx.footers.len());
+        assert_eq!(stats.entries.iter().map(|entry| entry.stored).sum::<u64>(), stats.total.stored);
+        assert_eq!(stats.by_extension.iter().map(|(_, size)| size.count).sum::<usize>(), stats.total.count);
+        assert_eq!(stats.compressed.count, 3);
+
+        // TestApp.dll dominates the package
+        assert_eq!(stats.by_extension[0].0, "dll");
+        assert_eq!(stats.largest(1)[0].name.as_deref(), Some("TestApp.dll"));
+
+        // Stored as is, ciphertext padded to sectors
+        let dll = stats.entries.iter().find(|entry| entry.name.as_deref() == Some("TestApp.dll")).unwrap();
+        assert!(dll.encrypted && dll.is_incompressible());
+        assert_eq!(dll.extension(), "dll");
+        assert!(stats.to_string().contains("By extension:\n  dll: "));
+    }
+}
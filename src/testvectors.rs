@@ -0,0 +1,316 @@
+//! Known-good vectors for the EAppx format
+//!
+//! Other implementations can validate their tweak derivation, publisher id
+//! generation, key-id encoding, key splitting and header parsing against
+//! these programmatically, instead of copying numbers out of this crate's
+//! unit tests.
+//!
+//! All hashes and byte sequences are lowercase hex strings.
+
+/// Publisher string -> publisher id
+#[derive(Debug, Clone, Copy)]
+pub struct PublisherIdVector {
+    pub publisher: &'static str,
+    pub publisher_id: &'static str,
+}
+
+/// File path + PFN -> tweak hash, folded hash and resulting base tweak
+#[derive(Debug, Clone, Copy)]
+pub struct TweakVector {
+    /// Package-relative path, as stored in the blockmap
+    pub filepath: &'static str,
+    /// Package family name, casing as given by the package
+    pub pfn: &'static str,
+    /// SHA256 over UTF-16LE(`\` + filepath) + UTF-16LE(lowercase(pfn))
+    pub tweak_hash: &'static str,
+    /// `tweak_hash` folded into 8 bytes by XOR
+    pub folded_hash: &'static str,
+    /// Folded hash interpreted as little-endian integer
+    pub tweak: u128,
+}
+
+/// SHA256 hash -> 8 byte XOR-folded hash
+#[derive(Debug, Clone, Copy)]
+pub struct FoldVector {
+    pub hash: &'static str,
+    pub folded: &'static str,
+}
+
+/// Base tweak + sector index -> 16 byte XTS tweak
+#[derive(Debug, Clone, Copy)]
+pub struct SectorTweakVector {
+    pub tweak: u128,
+    pub sector: u128,
+    pub sector_tweak: &'static str,
+}
+
+/// Raw header bytes plus the values they are expected to decode to
+#[derive(Debug, Clone, Copy)]
+pub struct HeaderVector {
+    /// Hex chunks, concatenate before decoding
+    pub bytes: &'static [&'static str],
+    pub header_size: u16,
+    pub footer_offset: u64,
+    pub footer_length: u64,
+    pub file_count: u64,
+    pub block_map_file_id: u64,
+    pub key_ids: &'static [(&'static str, &'static str)],
+    pub package_full_name: &'static str,
+    pub crypto_algo: &'static str,
+    pub block_map_hash_algo: &'static str,
+    pub block_map_hash: &'static str,
+}
+
+/// Key-id in its keyfile forms and as stored in a header
+#[derive(Debug, Clone, Copy)]
+pub struct KeyIdVector {
+    /// Base64 of the 16 byte short form, empty if the key-id has none
+    pub short: &'static str,
+    /// Base64 of the 32 header bytes
+    pub long: &'static str,
+    pub header_bytes: &'static str,
+}
+
+/// 32 byte key + 16 byte tweak -> one XTS-AES-128 data unit
+#[derive(Debug, Clone, Copy)]
+pub struct XtsVector {
+    /// Data key followed by tweak key
+    pub key: &'static str,
+    pub tweak: &'static str,
+    pub plaintext: &'static str,
+    pub ciphertext: &'static str,
+}
+
+impl HeaderVector {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        hex::decode(self.bytes.concat()).expect("Test vector must be valid hex")
+    }
+}
+
+pub const PUBLISHER_IDS: &[PublisherIdVector] = &[
+    PublisherIdVector {
+        publisher: "CN=Microsoft Corporation, O=Microsoft Corporation, L=Redmond, S=Washington, C=US",
+        publisher_id: "8wekyb3d8bbwe",
+    },
+    PublisherIdVector {
+        publisher: "CN=SomeDev",
+        publisher_id: "kp0adwb0dpv7r",
+    },
+    PublisherIdVector {
+        publisher: "CN=SomeCommonName",
+        publisher_id: "bst25f6z33ccc",
+    },
+    PublisherIdVector {
+        publisher: "CN=A68B71A2-D31D-464B-859A-CCB951AA6E69",
+        publisher_id: "j6xkx2x3snwbj",
+    },
+];
+
+pub const TWEAKS: &[TweakVector] = &[
+    TweakVector {
+        filepath: r#"\Assets\LockScreenLogo.scale-200.png"#,
+        pfn: "TestApp_bst25f6z33ccc",
+        tweak_hash: "98254280ac79f4b4799b1cd78bffb41ffeaa59f1ee70268b7f0c38dddc8ab195",
+        folded_hash: "60183f7b157cd7b5",
+        tweak: 0xB5D77C157B3F1860,
+    },
+    TweakVector {
+        filepath: r#"Assets\LockScreenLogo.scale-200.png"#,
+        pfn: "8b18b0ca-7bac-4263-8be8-9a7a1292c90d_nh20k94c8ngfj",
+        tweak_hash: "b7778c5e7b0f9054156071354b840bcfd6a32353017d3397b60ddaebe57e94b3",
+        folded_hash: "c2b904d3d4883cbf",
+        tweak: 0xBF3C88D4D304B9C2,
+    },
+    TweakVector {
+        filepath: "TestApp.exe",
+        pfn: "8b18b0ca-7bac-4263-8be8-9a7a1292c90d_nh20k94c8ngfj",
+        tweak_hash: "7d42032f29847926255d4df0f6e914a8793c4b8d8ba2797bad78803294d424db",
+        folded_hash: "8c5b8560c01b302e",
+        tweak: 0x2E301BC060855B8C,
+    },
+];
+
+pub const FOLDS: &[FoldVector] = &[
+    FoldVector {
+        hash: "446dc620c5e5a6bb3566b6314f129ae8dcb7b752f39e14640e2a61b72126551d",
+        folded: "a396a6f4584f7d2a",
+    },
+];
+
+pub const SECTOR_TWEAKS: &[SectorTweakVector] = &[
+    SectorTweakVector {
+        tweak: 0x2A7D4F58F4A696A3,
+        sector: 0,
+        sector_tweak: "a396a6f4584f7d2a0000000000000000",
+    },
+    SectorTweakVector {
+        tweak: 0x2A7D4F58F4A696A3,
+        sector: 0x10,
+        sector_tweak: "b396a6f4584f7d2a0000000000000000",
+    },
+    SectorTweakVector {
+        tweak: 0xFFFFFFFFFFFFFFFF,
+        sector: 1,
+        sector_tweak: "00000000000000000100000000000000",
+    },
+];
+
+/// Header of `TestApp_1.0.3.0_x64.emsix` (encrypted with the global test key)
+pub const HEADERS: &[HeaderVector] = &[
+    HeaderVector {
+        bytes: &[
+    "455850487e01000000000000010024bc11000000000008020000000000000d00",
+    "0000000000000000000000000000000000000000000000002cbe110000000000",
+    "0100fc0200009d0100000c0000000000000020000000010067cfafdd2c7b6d08",
+    "302b8adac1bdd3a7b8ae537d225962f0b1d77e09f5a187a03f007e0038006200",
+    "3100380062003000630061002d0037006200610063002d003400320036003300",
+    "2d0038006200650038002d003900610037006100310032003900320063003900",
+    "300064005f0030002e0030002e0030002e0030005f007800360034005f005f00",
+    "6e006800320030006b0039003400630038006e00670066006a000e0058005400",
+    "53002d0041004500530000004e0068007400740070003a002f002f0077007700",
+    "77002e00770033002e006f00720067002f0032003000300031002f0030003400",
+    "2f0078006d006c0065006e00630023007300680061003200350036002000ef34",
+    "38578884511eece78646eb3ea46a4527f90079a353fad13672faa66f26f7",
+        ],
+        header_size: 0x17e,
+        footer_offset: 0x11bc24,
+        footer_length: 0x208,
+        file_count: 0xd,
+        block_map_file_id: 0xc,
+        key_ids: &[("ddafcf67-7b2c-086d-302b-8adac1bdd3a7", "7d53aeb8-5922-f062-b1d7-7e09f5a187a0")],
+        package_full_name: "8b18b0ca-7bac-4263-8be8-9a7a1292c90d_0.0.0.0_x64__nh20k94c8ngfj",
+        crypto_algo: "XTS-AES",
+        block_map_hash_algo: "http://www.w3.org/2001/04/xmlenc#sha256",
+        block_map_hash: "ef3438578884511eece78646eb3ea46a4527f90079a353fad13672faa66f26f7",
+    },
+];
+
+pub const KEY_IDS: &[KeyIdVector] = &[
+    KeyIdVector {
+        short: "+Y48Klq7Se8Rh4l+DofiFQ==",
+        long: "21UXu1JQEEuyq/Or9cpbQfmOPCpau0nvEYeJfg6H4hU=",
+        header_bytes: "db5517bb5250104bb2abf3abf5ca5b41f98e3c2a5abb49ef1187897e0e87e215",
+    },
+    KeyIdVector {
+        short: "",
+        long: "8iBHoOceuO0lsmiRNJyAAvmOPCpau0nvEYeJfg6H4hU=",
+        header_bytes: "f22047a0e71eb8ed25b26891349c8002f98e3c2a5abb49ef1187897e0e87e215",
+    },
+];
+
+/// Vectors 1 and 2 of IEEE 1619-2007, data units of 32 bytes
+pub const XTS: &[XtsVector] = &[
+    XtsVector {
+        key: "0000000000000000000000000000000000000000000000000000000000000000",
+        tweak: "00000000000000000000000000000000",
+        plaintext: "0000000000000000000000000000000000000000000000000000000000000000",
+        ciphertext: "917cf69ebd68b2ec9b9fe9a3eadda692cd43d2f59598ed858c02c2652fbf922e",
+    },
+    XtsVector {
+        key: "1111111111111111111111111111111122222222222222222222222222222222",
+        tweak: "33333333330000000000000000000000",
+        plaintext: "4444444444444444444444444444444444444444444444444444444444444444",
+        ciphertext: "c454185e6a16936e39334038acef838bfb186fff7480adc4289382ecd6d394f0",
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use base64ct::{Base64, Encoding};
+    use binrw::BinRead;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{container::ContainerParams, crypto, keys::KeyId, utils, EAppxHeader};
+
+    #[test]
+    fn test_publisher_ids() {
+        for v in PUBLISHER_IDS {
+            assert_eq!(utils::generate_publisher_id(v.publisher), v.publisher_id, "{v:?}");
+        }
+    }
+
+    #[test]
+    fn test_tweaks() {
+        for v in TWEAKS {
+            let hash = crypto::hash_for_file_tweak(v.filepath, v.pfn);
+            assert_eq!(hex::encode(&hash), v.tweak_hash, "{v:?}");
+            assert_eq!(hex::encode(crypto::fold_hash_xor(&hash)), v.folded_hash, "{v:?}");
+            assert_eq!(crypto::get_tweak_value(v.filepath, v.pfn), v.tweak, "{v:?}");
+        }
+    }
+
+    #[test]
+    fn test_folds() {
+        for v in FOLDS {
+            let hash = hex::decode(v.hash).unwrap();
+            assert_eq!(hex::encode(crypto::fold_hash_xor(&hash)), v.folded, "{v:?}");
+        }
+    }
+
+    #[test]
+    fn test_sector_tweaks() {
+        for v in SECTOR_TWEAKS {
+            let ctx = crypto::CryptoFileContext {
+                cipher: crypto::create_cipher(&[0u8; 32]),
+                tweak: v.tweak,
+                params: ContainerParams::default(),
+            };
+            assert_eq!(hex::encode(ctx.for_sector(v.sector)), v.sector_tweak, "{v:?}");
+        }
+    }
+
+    #[test]
+    fn test_headers() {
+        for v in HEADERS {
+            let bytes = v.to_bytes();
+            assert_eq!(bytes.len(), v.header_size as usize);
+
+            let header = EAppxHeader::read(&mut Cursor::new(bytes)).unwrap();
+            assert_eq!(header.header_size, v.header_size);
+            assert_eq!(header.footer_offset, v.footer_offset);
+            assert_eq!(header.footer_length, v.footer_length);
+            assert_eq!(header.file_count, v.file_count);
+            assert_eq!(header.block_map_file_id, v.block_map_file_id);
+            assert_eq!(header.package_full_name(), v.package_full_name);
+            assert_eq!(header.crypto_algo(), v.crypto_algo);
+            assert_eq!(header.block_map_hash_algo(), v.block_map_hash_algo);
+            assert_eq!(hex::encode(&header.block_map_hash), v.block_map_hash);
+
+            let key_ids = v.key_ids.iter()
+                .map(|(a, b)| KeyId::Guid((Uuid::parse_str(a).unwrap(), Uuid::parse_str(b).unwrap())))
+                .collect::<Vec<_>>();
+            assert_eq!(header.key_ids, key_ids);
+        }
+    }
+
+    #[test]
+    fn test_key_ids() {
+        for v in KEY_IDS {
+            let key_id = KeyId::from_bytes(hex::decode(v.header_bytes).unwrap().try_into().unwrap());
+            assert_eq!(key_id.to_keyfile_string(), v.long, "{v:?}");
+            assert_eq!(key_id.to_short_keyfile_string().unwrap_or_default(), v.short, "{v:?}");
+            assert_eq!(key_id.to_bytes().as_slice(), Base64::decode_vec(v.long).unwrap(), "{v:?}");
+            if !v.short.is_empty() {
+                let short = Base64::decode_vec(v.short).unwrap().try_into().unwrap();
+                assert_eq!(KeyId::from_short_bytes(short), key_id, "{v:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_xts() {
+        for v in XTS {
+            let key: [u8; 32] = hex::decode(v.key).unwrap().try_into().unwrap();
+            let tweak: [u8; 16] = hex::decode(v.tweak).unwrap().try_into().unwrap();
+            let cipher = crypto::create_cipher(&key);
+            let mut data = hex::decode(v.plaintext).unwrap();
+            cipher.0.encrypt_sector(&mut data, tweak);
+            assert_eq!(hex::encode(&data), v.ciphertext, "{v:?}");
+            cipher.0.decrypt_sector(&mut data, tweak);
+            assert_eq!(hex::encode(&data), v.plaintext, "{v:?}");
+        }
+    }
+}
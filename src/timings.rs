@@ -0,0 +1,220 @@
+//! Time spent per stage when reading files, to tell disk from CPU bound runs
+//!
+//! Reading, inflating, decrypting, hashing and writing are measured per file,
+//! [`EAppxFile::collect_timings`](crate::EAppxFile::collect_timings) attaches
+//! them to extraction and verification reports. Stages run on their own
+//! threads, so their times overlap and add up to more than the wall clock.
+//! A slow `read` or `write` points at the disks, slow `inflate`, `decrypt` or
+//! `hash` at the CPU.
+
+use std::{
+    cell::Cell,
+    fmt,
+    io::Read,
+    ops::{Add, AddAssign},
+    time::{Duration, Instant},
+};
+
+use crate::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Stored data read from the package
+    Read,
+    Inflate,
+    Decrypt,
+    /// Block and file hashes
+    Hash,
+    /// Plaintext written to the output
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [Stage::Read, Stage::Inflate, Stage::Decrypt, Stage::Hash, Stage::Write];
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Read => "read",
+            Stage::Inflate => "inflate",
+            Stage::Decrypt => "decrypt",
+            Stage::Hash => "hash",
+            Stage::Write => "write",
+        };
+        f.pad(name)
+    }
+}
+
+/// Bytes a stage processed and the time it took
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub time: Duration,
+}
+
+impl Throughput {
+    /// Bytes per second, 0 if no time was measured
+    pub fn bytes_per_second(&self) -> f64 {
+        match self.time.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    fn add_since(&mut self, bytes: usize, start: Instant) {
+        self.bytes += bytes as u64;
+        self.time += start.elapsed();
+    }
+}
+
+impl Add for Throughput {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self { bytes: self.bytes + other.bytes, time: self.time + other.time }
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {:.3?} ({}/s)", utils::get_filesize_with_unit(self.bytes), self.time,
+            utils::get_filesize_with_unit(self.bytes_per_second() as u64))
+    }
+}
+
+/// Throughput of every stage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    pub read: Throughput,
+    pub inflate: Throughput,
+    pub decrypt: Throughput,
+    pub hash: Throughput,
+    pub write: Throughput,
+}
+
+impl Timings {
+    pub fn stage(&self, stage: Stage) -> &Throughput {
+        match stage {
+            Stage::Read => &self.read,
+            Stage::Inflate => &self.inflate,
+            Stage::Decrypt => &self.decrypt,
+            Stage::Hash => &self.hash,
+            Stage::Write => &self.write,
+        }
+    }
+
+    fn stage_mut(&mut self, stage: Stage) -> &mut Throughput {
+        match stage {
+            Stage::Read => &mut self.read,
+            Stage::Inflate => &mut self.inflate,
+            Stage::Decrypt => &mut self.decrypt,
+            Stage::Hash => &mut self.hash,
+            Stage::Write => &mut self.write,
+        }
+    }
+
+    /// Stage that took the longest, `None` if nothing was measured
+    pub fn slowest(&self) -> Option<Stage> {
+        Stage::ALL.into_iter()
+            .filter(|stage| !self.stage(*stage).time.is_zero())
+            .max_by_key(|stage| self.stage(*stage).time)
+    }
+
+    /// Add `bytes` processed by `stage` since `start`
+    pub(crate) fn record(&mut self, stage: Stage, bytes: usize, start: Instant) {
+        self.stage_mut(stage).add_since(bytes, start);
+    }
+
+    /// Run `f` on `bytes`, adding the time it took to `stage`
+    pub(crate) fn measure<T>(&mut self, stage: Stage, bytes: usize, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(stage, bytes, start);
+        result
+    }
+}
+
+impl Add for Timings {
+    type Output = Self;
+
+    fn add(mut self, other: Self) -> Self {
+        for stage in Stage::ALL {
+            *self.stage_mut(stage) = *self.stage(stage) + *other.stage(stage);
+        }
+        self
+    }
+}
+
+impl AddAssign for Timings {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl std::iter::Sum for Timings {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for stage in Stage::ALL {
+            let throughput = self.stage(stage);
+            if throughput.bytes > 0 || !throughput.time.is_zero() {
+                writeln!(f, "  {stage:<8} {throughput}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reader adding the bytes read from `inner` and the time it took to `spent`
+///
+/// Wraps the package stream below the inflater, so reading and inflating
+/// can be told apart.
+pub(crate) struct TimedRead<'a, R> {
+    pub inner: R,
+    pub spent: &'a Cell<Throughput>,
+}
+
+impl<R: Read> Read for TimedRead<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let start = Instant::now();
+        let read = self.inner.read(buf)?;
+        let mut spent = self.spent.get();
+        spent.add_since(read, start);
+        self.spent.set(spent);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let throughput = |bytes: u64, millis: u64| Throughput { bytes, time: Duration::from_millis(millis) };
+        assert_eq!(throughput(0x100000, 500).bytes_per_second(), 2097152.0);
+        assert_eq!(throughput(0x100000, 0).bytes_per_second(), 0.0);
+
+        let mut timings = Timings { read: throughput(0x100000, 40), decrypt: throughput(0x100000, 10), ..Default::default() };
+        assert_eq!(timings.slowest(), Some(Stage::Read));
+        timings += Timings { decrypt: throughput(0x100000, 50), ..Default::default() };
+        assert_eq!(timings.decrypt, throughput(0x200000, 60));
+        assert_eq!(timings.slowest(), Some(Stage::Decrypt));
+        assert_eq!(Timings::default().slowest(), None);
+
+        let total: Timings = [timings, timings].into_iter().sum();
+        assert_eq!(total.read, throughput(0x200000, 80));
+        assert_eq!(total.to_string().lines().count(), 2);
+
+        let spent = Cell::new(Throughput::default());
+        let mut reader = TimedRead { inner: [7u8; 100].as_slice(), spent: &spent };
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(spent.get().bytes, 100);
+    }
+}
@@ -0,0 +1,125 @@
+//! Parsing packages from untrusted sources
+//!
+//! [`EAppxFile::parse_untrusted`] reads header, footer table and blockmap from
+//! memory and returns an error for anything malformed instead of panicking.
+//! Allocations are bounded by the input and [`ResourceLimits::untrusted`],
+//! which makes it suitable behind fuzzers and for services ingesting
+//! uploaded packages.
+
+use base64ct::{Base64, Encoding};
+
+use crate::{
+    blockmap::AppxBlockMap,
+    error::Error,
+    limits::ResourceLimits,
+    EAppxFile,
+    EAppxFooter,
+    EAppxHeader,
+};
+
+/// Metadata of a package parsed by [`EAppxFile::parse_untrusted`]
+///
+/// File ids and hashes of the blockmap are checked, [`crate::blockmap::File::id`]
+/// and the `hash_bytes` accessors do not panic on it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParsedMetadata {
+    pub header: EAppxHeader,
+    pub footers: Vec<EAppxFooter>,
+    pub blockmap: AppxBlockMap,
+    /// Size of the input
+    pub file_len: u64,
+}
+
+impl EAppxFile {
+    /// Parse the metadata of a package from arbitrary bytes, never panics
+    ///
+    /// Nothing but the blockmap is decompressed, encrypted blockmaps are
+    /// rejected as no keys are involved.
+    pub fn parse_untrusted(bytes: &[u8]) -> Result<ParsedMetadata, Error> {
+        let limits = ResourceLimits::untrusted();
+        let header = Self::peek_header(bytes)?;
+        let (footers, file_len, blockmap_fileinfo) = Self::read_layout(bytes, &header, &limits)?;
+
+        let buf = Self::read_file_to_buf(bytes, blockmap_fileinfo, header.is_bundle(), limits.max_in_memory_size)?;
+        let blockmap = AppxBlockMap::from_xml(&buf)?;
+        limits.check_file_count("Blockmap file count", blockmap.files.len() as u64)?;
+        check_blockmap(&blockmap)?;
+
+        Ok(ParsedMetadata { header, footers, blockmap, file_len })
+    }
+}
+
+/// Reject what the blockmap accessors would panic on
+fn check_blockmap(blockmap: &AppxBlockMap) -> Result<(), Error> {
+    for file in &blockmap.files {
+        file.try_id()?;
+
+        let hashes = file.blocks.iter().map(|block| &block.hash)
+            .chain(file.filehash.iter().map(|filehash| &filehash.hash));
+        for hash in hashes {
+            Base64::decode_vec(hash)
+                .map_err(|_| Error::DecodeError(format!("File {} has invalid hash {hash:?}", file.name)))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    #[test]
+    fn test_parse_untrusted() {
+        for data in [EMSIX, EMSIXBUNDLE] {
+            let parsed = EAppxFile::parse_untrusted(data).unwrap();
+            let file = EAppxFile::from_source(&data).unwrap();
+            assert_eq!(parsed.header, file.header);
+            assert_eq!(parsed.footers, file.footers);
+            assert_eq!(parsed.blockmap, file.blockmap);
+            assert_eq!(parsed.file_len, data.len() as u64);
+        }
+
+        let mut blockmap = EAppxFile::parse_untrusted(EMSIX).unwrap().blockmap;
+        blockmap.files[0].blocks[0].hash = "not base64!".into();
+        assert!(check_blockmap(&blockmap).is_err());
+        blockmap.files[0].file_id = None;
+        blockmap.files[0].id = "zz".into();
+        assert!(check_blockmap(&blockmap).is_err());
+    }
+
+    #[test]
+    fn test_parse_untrusted_garbage() {
+        let header = EAppxFile::parse_untrusted(EMSIX).unwrap().header;
+        let (header_len, footer_offset) = (header.header_size as usize, header.footer_offset as usize);
+        let footer_end = footer_offset + header.footer_length as usize;
+
+        // Truncated anywhere in header or footer table
+        for len in (0..header_len).chain((footer_offset..footer_end).step_by(7)) {
+            assert!(EAppxFile::parse_untrusted(&EMSIX[..len]).is_err());
+        }
+
+        // Every single bit flip of the header, some of them still parse
+        let mut data = EMSIX.to_vec();
+        for bit in 0..header_len * 8 {
+            data[bit / 8] ^= 1 << (bit % 8);
+            let _ = EAppxFile::parse_untrusted(&data);
+            data[bit / 8] ^= 1 << (bit % 8);
+        }
+
+        // Whole bytes of the footer table set to extremes
+        for position in footer_offset..footer_end {
+            for value in [0x00, 0xFF] {
+                let original = std::mem::replace(&mut data[position], value);
+                let _ = EAppxFile::parse_untrusted(&data);
+                data[position] = original;
+            }
+        }
+
+        assert!(EAppxFile::parse_untrusted(&[]).is_err());
+        assert!(EAppxFile::parse_untrusted(&[0xFF; 0x1000]).is_err());
+    }
+}
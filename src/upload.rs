@@ -0,0 +1,348 @@
+//! Store submission containers (`.msixupload`, `.appxupload`)
+//!
+//! Uploads are plain zips wrapping the actual package or bundle, next to
+//! `.appxsym` zips of its debug symbols. [`UploadContainer::detect`] tells
+//! them from packages and zip based bundles, which carry a blockmap of their
+//! own. The inner package opens in place through [`UploadEntry::open`] when
+//! stored, deflated ones have to be copied out with [`UploadEntry::reader`]:
+//!
+//! ```no_run
+//! use eappx::{limits::ResourceLimits, upload::UploadContainer, EAppxFile};
+//!
+//! let file = std::fs::File::open("TestApp.msixupload")?;
+//! let upload = UploadContainer::read(&file, ResourceLimits::default())?;
+//! let package = upload.package_entry()?.open(&file)?;
+//! let eappx = EAppxFile::from_source(&package)?;
+//! # Ok::<(), eappx::error::Error>(())
+//! ```
+
+use std::io::Read;
+
+use crate::{
+    error::Error,
+    extension::KNOWN_EXTENSIONS,
+    limits::ResourceLimits,
+    source::{PackageSource, SourceSlice},
+};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const ZIP64_END_SIGNATURE: u32 = 0x06064b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x07064b50;
+const END_SIGNATURE: u32 = 0x06054b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+const LOCAL_HEADER_SIZE: u64 = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_SIZE: u64 = 22;
+const ZIP64_LOCATOR_SIZE: u64 = 20;
+const ZIP64_END_SIZE: usize = 56;
+/// End record plus the longest comment it may carry
+const END_SEARCH_SIZE: u64 = END_SIZE + u16::MAX as u64;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+/// Symbol archives accompanying the package
+const SYMBOL_EXTENSIONS: &[&str] = &["appxsym", "msixsym"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadEntry {
+    /// Path inside the upload, forward slashes as zip stores them
+    pub name: String,
+    /// Zip compression method, 0 stored, 8 deflated
+    pub method: u16,
+    pub compressed_size: u64,
+    pub size: u64,
+    /// Offset of the local file header
+    pub header_offset: u64,
+}
+
+impl UploadEntry {
+    fn extension(&self) -> String {
+        self.name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).unwrap_or_default()
+    }
+
+    /// Whether the entry is a package or bundle, encrypted or not
+    pub fn is_package(&self) -> bool {
+        !self.name.ends_with('/') && KNOWN_EXTENSIONS.contains(&self.extension().as_str())
+    }
+
+    pub fn is_bundle(&self) -> bool {
+        self.is_package() && self.extension().ends_with("bundle")
+    }
+
+    pub fn is_symbols(&self) -> bool {
+        SYMBOL_EXTENSIONS.contains(&self.extension().as_str())
+    }
+
+    /// Offset of the entry data, behind the local file header
+    pub fn data_offset<P: PackageSource + ?Sized>(&self, source: &P) -> Result<u64, Error> {
+        let mut header = [0u8; LOCAL_HEADER_SIZE as usize];
+        source.read_exact_at(self.header_offset, &mut header)?;
+        if u32_at(&header, 0) != LOCAL_HEADER_SIGNATURE {
+            return Err(Error::DecodeError(format!("No local file header for {} at {:#x}", self.name, self.header_offset)));
+        }
+        let variable = u16_at(&header, 26) as u64 + u16_at(&header, 28) as u64;
+        Ok(self.header_offset + LOCAL_HEADER_SIZE + variable)
+    }
+
+    /// A stored entry as a source of its own, e.g. for [`EAppxFile::from_source`](crate::EAppxFile::from_source)
+    pub fn open<'a, P: PackageSource + ?Sized>(&self, source: &'a P) -> Result<SourceSlice<'a, P>, Error> {
+        if self.method != METHOD_STORED {
+            return Err(Error::DataError(format!("{} is compressed, copy it out with UploadEntry::reader to open it", self.name)));
+        }
+        let start = self.data_offset(source)?;
+        Ok(source.slice(start, self.size))
+    }
+
+    /// Contents of the entry, inflated if needed
+    pub fn reader<'a, P: PackageSource + ?Sized>(&self, source: &'a P) -> Result<Box<dyn Read + 'a>, Error> {
+        let start = self.data_offset(source)?;
+        let data = source.range(start, self.compressed_size);
+        match self.method {
+            METHOD_STORED => Ok(Box::new(data)),
+            METHOD_DEFLATED => Ok(Box::new(flate2::read::DeflateDecoder::new(data).take(self.size))),
+            method => Err(Error::DataError(format!("{} uses unsupported compression method {method}", self.name))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadContainer {
+    /// Every entry of the central directory
+    pub entries: Vec<UploadEntry>,
+}
+
+impl UploadContainer {
+    /// Read the central directory of a zip
+    pub fn read<P: PackageSource + ?Sized>(source: &P, limits: ResourceLimits) -> Result<Self, Error> {
+        let (offset, size, count) = read_end(source)?;
+        limits.check_in_memory_size("Upload central directory size", size)?;
+        limits.check_file_count("Upload entry count", count)?;
+
+        let mut directory = vec![0u8; size as usize];
+        source.read_exact_at(offset, &mut directory)?;
+
+        let mut entries = vec![];
+        let mut pos = 0;
+        while entries.len() < count as usize {
+            let (entry, length) = parse_central_entry(&directory[pos..])?;
+            entries.push(entry);
+            pos += length;
+        }
+        Ok(Self { entries })
+    }
+
+    /// The upload in `source`, `None` for anything else, including zip based packages
+    ///
+    /// Uploads are zips holding a package or bundle and no blockmap of their own.
+    pub fn detect<P: PackageSource + ?Sized>(source: &P, limits: ResourceLimits) -> Result<Option<Self>, Error> {
+        let mut magic = [0u8; 4];
+        let is_zip = source.read_exact_at(0, &mut magic).is_ok() && u32::from_le_bytes(magic) == LOCAL_HEADER_SIGNATURE;
+        if !is_zip {
+            return Ok(None);
+        }
+
+        let upload = Self::read(source, limits)?;
+        let has_blockmap = upload.entries.iter().any(|entry| entry.name == "AppxBlockMap.xml");
+        let has_package = upload.entries.iter().any(UploadEntry::is_package);
+        Ok((has_package && !has_blockmap).then_some(upload))
+    }
+
+    /// Packages and bundles of the upload
+    pub fn packages(&self) -> impl Iterator<Item = &UploadEntry> {
+        self.entries.iter().filter(|entry| entry.is_package())
+    }
+
+    /// Symbol archives of the upload
+    pub fn symbols(&self) -> impl Iterator<Item = &UploadEntry> {
+        self.entries.iter().filter(|entry| entry.is_symbols())
+    }
+
+    /// The package the upload ships: its only bundle, else its only package
+    pub fn package_entry(&self) -> Result<&UploadEntry, Error> {
+        let bundles = self.packages().filter(|entry| entry.is_bundle()).collect::<Vec<_>>();
+        let candidates = match bundles.is_empty() {
+            true => self.packages().collect::<Vec<_>>(),
+            false => bundles,
+        };
+        match candidates.as_slice() {
+            [entry] => Ok(entry),
+            [] => Err(Error::DataError("Upload contains no package".into())),
+            _ => Err(Error::DataError(format!("Upload contains several packages: {}",
+                candidates.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>().join(", ")))),
+        }
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+}
+
+/// Offset, size and entry count of the central directory
+fn read_end<P: PackageSource + ?Sized>(source: &P) -> Result<(u64, u64, u64), Error> {
+    let len = source.len()?;
+    let tail_start = len.saturating_sub(END_SEARCH_SIZE);
+    let mut tail = vec![0u8; (len - tail_start) as usize];
+    source.read_exact_at(tail_start, &mut tail)?;
+
+    let end = (0..tail.len().saturating_sub(END_SIZE as usize - 1)).rev()
+        .find(|pos| u32_at(&tail, *pos) == END_SIGNATURE)
+        .ok_or(Error::DecodeError("No end of central directory record, not a zip".into()))?;
+    let record = &tail[end..];
+    let (count, size, offset) = (u16_at(record, 10) as u64, u32_at(record, 12) as u64, u32_at(record, 16) as u64);
+    let end_offset = tail_start + end as u64;
+    if count != u16::MAX as u64 && size != u32::MAX as u64 && offset != u32::MAX as u64 {
+        return Ok((offset, size, count));
+    }
+
+    // Zip64, the locator sits right in front of the end record
+    let mut locator = [0u8; ZIP64_LOCATOR_SIZE as usize];
+    source.read_exact_at(end_offset.checked_sub(ZIP64_LOCATOR_SIZE)
+        .ok_or(Error::DecodeError("Zip64 locator missing".into()))?, &mut locator)?;
+    if u32_at(&locator, 0) != ZIP64_LOCATOR_SIGNATURE {
+        return Err(Error::DecodeError("Zip64 locator missing".into()));
+    }
+    let mut record = [0u8; ZIP64_END_SIZE];
+    source.read_exact_at(u64_at(&locator, 8), &mut record)?;
+    if u32_at(&record, 0) != ZIP64_END_SIGNATURE {
+        return Err(Error::DecodeError("Zip64 end of central directory record missing".into()));
+    }
+    Ok((u64_at(&record, 48), u64_at(&record, 40), u64_at(&record, 32)))
+}
+
+/// Entry at the start of `data` and the length of its record
+fn parse_central_entry(data: &[u8]) -> Result<(UploadEntry, usize), Error> {
+    let truncated = || Error::DecodeError("Central directory ends inside an entry".into());
+    if data.len() < CENTRAL_HEADER_SIZE {
+        return Err(truncated());
+    }
+    if u32_at(data, 0) != CENTRAL_HEADER_SIGNATURE {
+        return Err(Error::DecodeError("Invalid central directory entry signature".into()));
+    }
+    let (name_len, extra_len, comment_len) = (u16_at(data, 28) as usize, u16_at(data, 30) as usize, u16_at(data, 32) as usize);
+    let length = CENTRAL_HEADER_SIZE + name_len + extra_len + comment_len;
+    if data.len() < length {
+        return Err(truncated());
+    }
+
+    let mut entry = UploadEntry {
+        name: String::from_utf8_lossy(&data[CENTRAL_HEADER_SIZE..CENTRAL_HEADER_SIZE + name_len]).into_owned(),
+        method: u16_at(data, 10),
+        compressed_size: u32_at(data, 20) as u64,
+        size: u32_at(data, 24) as u64,
+        header_offset: u32_at(data, 42) as u64,
+    };
+
+    // Zip64 extra fields hold the values whose 32-bit fields are saturated, in this order
+    let mut extra = &data[CENTRAL_HEADER_SIZE + name_len..CENTRAL_HEADER_SIZE + name_len + extra_len];
+    while extra.len() >= 4 {
+        let (id, size) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+        let field = extra.get(4..4 + size).ok_or_else(truncated)?;
+        if id == ZIP64_EXTRA_ID {
+            let mut values = field.chunks_exact(8).map(|value| u64_at(value, 0));
+            for target in [&mut entry.size, &mut entry.compressed_size, &mut entry.header_offset] {
+                if *target == u32::MAX as u64 {
+                    *target = values.next().ok_or_else(truncated)?;
+                }
+            }
+        }
+        extra = &extra[4 + size..];
+    }
+
+    Ok((entry, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::EAppxFile;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const MSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.msix");
+
+    /// Zip of `(name, data, deflate)` entries, without data descriptors or zip64
+    fn zip(entries: &[(&str, &[u8], bool)]) -> Vec<u8> {
+        let (mut out, mut directory) = (vec![], vec![]);
+        for &(name, data, deflate) in entries {
+            let stored = match deflate {
+                true => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(vec![], flate2::Compression::default());
+                    encoder.write_all(data).unwrap();
+                    encoder.finish().unwrap()
+                },
+                false => data.to_vec(),
+            };
+            let method = if deflate { METHOD_DEFLATED } else { METHOD_STORED };
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            let fields = [
+                &method.to_le_bytes()[..], &[0; 4], &crc.sum().to_le_bytes(), &(stored.len() as u32).to_le_bytes(),
+                &(data.len() as u32).to_le_bytes(), &(name.len() as u16).to_le_bytes(), &[0; 2],
+            ].concat();
+
+            let offset = out.len() as u32;
+            out.extend([&LOCAL_HEADER_SIGNATURE.to_le_bytes()[..], &[20, 0, 0, 0], &fields, name.as_bytes(), &stored].concat());
+            directory.extend([&CENTRAL_HEADER_SIGNATURE.to_le_bytes()[..], &[20, 0, 20, 0, 0, 0], &fields, &[0; 10],
+                &offset.to_le_bytes(), name.as_bytes()].concat());
+        }
+        let count = (entries.len() as u16).to_le_bytes();
+        let end = [&END_SIGNATURE.to_le_bytes()[..], &[0; 4], &count, &count, &(directory.len() as u32).to_le_bytes(),
+            &(out.len() as u32).to_le_bytes(), &[0; 2]].concat();
+        [out, directory, end].concat()
+    }
+
+    #[test]
+    fn test_upload() {
+        let symbols = zip(&[("TestApp.pdb", b"symbols", true)]);
+        for deflate in [false, true] {
+            let upload = zip(&[("TestApp_1.0.3.0_x64.appxsym", &symbols, true), ("TestApp_1.0.3.0_x64.emsix", EMSIX, deflate)]);
+            let container = UploadContainer::detect(&upload, ResourceLimits::default()).unwrap().unwrap();
+            assert_eq!(container.symbols().count(), 1);
+
+            let entry = container.package_entry().unwrap();
+            assert_eq!((entry.name.as_str(), entry.size), ("TestApp_1.0.3.0_x64.emsix", EMSIX.len() as u64));
+            let mut package = vec![];
+            entry.reader(&upload).unwrap().read_to_end(&mut package).unwrap();
+            assert_eq!(package, EMSIX);
+
+            // Opened in place when stored
+            match entry.open(&upload) {
+                Ok(package) => {
+                    let eappx = EAppxFile::from_source(&package).unwrap();
+                    assert!(eappx.verify(&package).is_valid());
+                },
+                Err(_) => assert!(deflate),
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        // Packages themselves, zip based or not, are no uploads
+        assert_eq!(UploadContainer::detect(EMSIX, ResourceLimits::default()).unwrap(), None);
+        assert_eq!(UploadContainer::detect(MSIX, ResourceLimits::default()).unwrap(), None);
+        assert!(UploadContainer::read(MSIX, ResourceLimits::default()).unwrap().entries.len() > 1);
+
+        let ambiguous = zip(&[("x86/TestApp.msix", MSIX, false), ("x64/TestApp.msix", MSIX, false)]);
+        let container = UploadContainer::detect(&ambiguous, ResourceLimits::default()).unwrap().unwrap();
+        assert!(container.package_entry().unwrap_err().to_string().contains("x86/TestApp.msix, x64/TestApp.msix"));
+
+        // A bundle wins over loose packages
+        let bundled = zip(&[("TestApp.msix", MSIX, false), ("TestApp.msixbundle", b"", false)]);
+        let container = UploadContainer::read(&bundled, ResourceLimits::default()).unwrap();
+        assert_eq!(container.package_entry().unwrap().name, "TestApp.msixbundle");
+
+        assert!(UploadContainer::read(&b"PK\x03\x04 not a zip"[..], ResourceLimits::default()).is_err());
+    }
+}
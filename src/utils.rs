@@ -26,6 +26,7 @@ pub fn get_filesize_with_unit(bytes: u64) -> String {
 /// ```
 /// use eappx::utils::{self};
 /// 
+/// assert_eq!(utils::align_to_sector(0), 0);
 /// assert_eq!(utils::align_to_sector(0x200), 0x200);
 /// assert_eq!(utils::align_to_sector(0x221), 0x400);
 /// assert_eq!(utils::align_to_sector(0x3FF), 0x400);
@@ -33,7 +34,49 @@ pub fn get_filesize_with_unit(bytes: u64) -> String {
 /// assert_eq!(utils::align_to_sector(0x1FFFFF), 0x200000);
 /// ```
 pub fn align_to_sector(total_size: usize) -> usize {
-    (((total_size - 1) / SECTOR_SIZE) + 1) * SECTOR_SIZE
+    total_size.div_ceil(SECTOR_SIZE) * SECTOR_SIZE
+}
+
+/// Fill `buf` from `reader` as far as it has data, returns how much was read
+///
+/// Unlike `read_exact`, running out of data early is not an error.
+pub fn read_up_to<R: std::io::Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Largest deflate output for `len` input bytes, zlib's bound plus the sync flush marker
+pub fn max_deflated_size(len: usize) -> usize {
+    len + (len >> 12) + (len >> 14) + (len >> 25) + 13 + 5
+}
+
+/// Inflate one block of a compressed file into `out`, which it has to fill exactly
+///
+/// Blocks end on a flush, so they can be fed one at a time to the same decompressor.
+#[cfg(feature = "compression")]
+pub fn inflate_block(decompress: &mut flate2::Decompress, stored: &[u8], out: &mut [u8]) -> bool {
+    let (start_in, start_out) = (decompress.total_in(), decompress.total_out());
+    loop {
+        let consumed = (decompress.total_in() - start_in) as usize;
+        let produced = (decompress.total_out() - start_out) as usize;
+        if consumed == stored.len() {
+            return produced == out.len();
+        }
+
+        let result = decompress.decompress(&stored[consumed..], &mut out[produced..], flate2::FlushDecompress::Sync);
+        let progress = decompress.total_in() - start_in > consumed as u64 || decompress.total_out() - start_out > produced as u64;
+        if result.is_err() || !progress {
+            return false;
+        }
+    }
 }
 
 /// Convert a string slice to UTF-16 bytes (without BOM)
@@ -92,6 +135,42 @@ pub fn generate_publisher_id(publisher: &str) -> String {
     result.to_lowercase()
 }
 
+/// Alphabet publisher-ids are encoded with, a base32 without I, L, O and U
+const PUBLISHER_ID_ALPHABET: &str = "0123456789abcdefghjkmnpqrstvwxyz";
+
+/// Whether `publisher_id` could have come from [`generate_publisher_id`]
+///
+/// That is 13 characters of its alphabet, ignoring case. The last one only
+/// carries four bits of the hash followed by a zero bit of padding.
+///
+/// Examples
+/// ```
+/// # use eappx::utils::validate_publisher_id;
+/// assert!(validate_publisher_id("8wekyb3d8bbwe"));
+/// assert!(!validate_publisher_id("8wekyb3d8bbwf"));
+/// assert!(!validate_publisher_id("8wekyb3d8bbul"));
+/// ```
+pub fn validate_publisher_id(publisher_id: &str) -> bool {
+    let digits: Option<Vec<usize>> = publisher_id.chars()
+        .map(|c| PUBLISHER_ID_ALPHABET.find(c.to_ascii_lowercase()))
+        .collect();
+    digits.is_some_and(|digits| digits.len() == 13 && digits[12] % 2 == 0)
+}
+
+/// Whether a package family or full name carries the publisher-id of `publisher`
+///
+/// Examples
+/// ```
+/// # use eappx::utils::pfn_matches_publisher;
+/// assert!(pfn_matches_publisher("MyCoolCalculator_kp0adwb0dpv7r", "CN=SomeDev"));
+/// assert!(pfn_matches_publisher("MyCoolCalculator_1.0.0.0_x64__kp0adwb0dpv7r", "CN=SomeDev"));
+/// assert!(!pfn_matches_publisher("MyCoolCalculator_kp0adwb0dpv7r", "CN=OtherDev"));
+/// ```
+pub fn pfn_matches_publisher(pfn: &str, publisher: &str) -> bool {
+    pfn.rsplit('_').next()
+        .is_some_and(|publisher_id| publisher_id.eq_ignore_ascii_case(&generate_publisher_id(publisher)))
+}
+
 /// Generate package family name
 /// 
 /// Examples
@@ -104,7 +183,211 @@ pub fn generate_pfn(app_name: &str, publisher: &str) -> String {
     format!("{app_name}_{}", generate_publisher_id(publisher))
 }
 
+/// Escape a string for use in XML text or attribute values
+///
+/// Examples
+/// ```
+/// # use eappx::utils::xml_escape;
+/// assert_eq!(xml_escape(r#"Tom & "Jerry" <3"#), "Tom &amp; &quot;Jerry&quot; &lt;3");
+/// ```
+pub fn xml_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Windows refuses directories from this length on, even though `MAX_PATH` is 260
+const MAX_SHORT_PATH: usize = 248;
+
+/// Path Windows can create files at beyond `MAX_PATH`, `path` as is elsewhere
+///
+/// Long paths are made absolute and get the `\\?\` prefix (`\\?\UNC\` for
+/// shares), which lifts the limit without the `LongPathsEnabled` opt-in.
+/// Shorter paths are returned unchanged.
+pub fn long_path(path: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+    if !cfg!(windows) {
+        return Ok(path.to_path_buf());
+    }
+
+    let absolute = std::path::absolute(path)?;
+    Ok(match absolute.to_str().and_then(verbatim_path) {
+        Some(verbatim) => verbatim.into(),
+        None => path.to_path_buf(),
+    })
+}
+
+/// `\\?\` form of an absolute Windows path too long for the regular APIs
+fn verbatim_path(absolute: &str) -> Option<String> {
+    if absolute.encode_utf16().count() < MAX_SHORT_PATH || absolute.starts_with(r"\\?\") || absolute.starts_with(r"\\.\") {
+        return None;
+    }
+
+    Some(match absolute.strip_prefix(r"\\") {
+        Some(share) => format!(r"\\?\UNC\{share}"),
+        None => format!(r"\\?\{absolute}"),
+    })
+}
+
+/// Bytes the current user may still write on the volume holding `path`
+///
+/// `path` does not have to exist yet, its closest existing ancestor is asked.
+/// `None` where the platform offers no way to tell.
+#[cfg(feature = "package")]
+pub fn available_space(path: &std::path::Path) -> std::io::Result<Option<u64>> {
+    let absolute = std::path::absolute(path)?;
+    let existing = absolute.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(&absolute);
+    volume_space(existing)
+}
+
+#[cfg(all(feature = "package", unix))]
+fn volume_space(path: &std::path::Path) -> std::io::Result<Option<u64>> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL-terminated, `stat` is only read after statvfs filled it
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::unnecessary_cast)]
+    Ok(Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64)))
+}
+
+#[cfg(all(feature = "package", windows))]
+fn volume_space(path: &std::path::Path) -> std::io::Result<Option<u64>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let path = path.as_os_str().encode_wide().chain(Some(0)).collect::<Vec<u16>>();
+    let mut available = 0u64;
+    // SAFETY: `path` is NUL-terminated, the totals we do not need may be null
+    if unsafe { GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut()) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(Some(available))
+}
+
+#[cfg(all(feature = "package", not(any(unix, windows))))]
+fn volume_space(_path: &std::path::Path) -> std::io::Result<Option<u64>> {
+    Ok(None)
+}
+
+/// Serves a payload of known length in reads of any size, while pulling it
+/// from `inner` in whole blocks
+///
+/// Needed on top of `AesXtsReader`, which only decrypts correctly when every
+/// read covers whole sectors.
+pub struct BlockReader<R: std::io::Read> {
+    inner: R,
+    remaining: u64,
+    block_size: usize,
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: std::io::Read> BlockReader<R> {
+    pub fn new(inner: R, length: u64) -> Self {
+        Self::with_block_size(inner, length, BLOCK_SIZE)
+    }
+
+    /// Reads `block_size` bytes at a time, a multiple of the container's sector size
+    pub fn with_block_size(inner: R, length: u64, block_size: usize) -> Self {
+        Self {
+            inner,
+            remaining: length,
+            block_size,
+            buf: vec![],
+            pos: 0,
+        }
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for BlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos == self.buf.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+
+            let len = std::cmp::min(self.block_size as u64, self.remaining) as usize;
+            self.buf.resize(len, 0);
+            self.inner.read_exact(&mut self.buf)?;
+            self.remaining -= len as u64;
+            self.pos = 0;
+        }
+
+        let amount = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..amount].copy_from_slice(&self.buf[self.pos..self.pos + amount]);
+        self.pos += amount;
+        Ok(amount)
+    }
+}
+
+/// (De)serialize UTF-16 code units as a string, for the header's name fields
+#[cfg(feature = "serde")]
+pub(crate) mod utf16_string {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[u16], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&String::from_utf16_lossy(value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u16>, D::Error> {
+        Ok(String::deserialize(deserializer)?.encode_utf16().collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    
+    use std::io::Read;
+
+    use super::*;
+
+    /// Rejects reads that don't cover whole blocks
+    struct BlockOnly<'a>(&'a [u8]);
+
+    impl Read for BlockOnly<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            assert!(buf.len() == BLOCK_SIZE || buf.len() == self.0.len(), "Unaligned read of {:#x}", buf.len());
+            self.0.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_block_reader() {
+        let data = (0..BLOCK_SIZE * 2 + 0x123).map(|i| i as u8).collect::<Vec<_>>();
+        let mut reader = BlockReader::new(BlockOnly(&data), data.len() as u64);
+
+        let mut out = vec![0u8; 0x31];
+        reader.read_exact(&mut out).unwrap();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_long_path() {
+        let deep = "\\Game\\Content".repeat(20);
+        assert_eq!(verbatim_path(r"C:\Packages\Game"), None);
+        assert_eq!(verbatim_path(&format!(r"C:\Out{deep}\file.bin")), Some(format!(r"\\?\C:\Out{deep}\file.bin")));
+        assert_eq!(verbatim_path(&format!(r"\\server\share{deep}")), Some(format!(r"\\?\UNC\server\share{deep}")));
+        assert_eq!(verbatim_path(&format!(r"\\?\C:\Out{deep}")), None);
+
+        #[cfg(not(windows))]
+        assert_eq!(long_path(std::path::Path::new(&deep)).unwrap(), std::path::PathBuf::from(&deep));
+    }
+
+    #[cfg(feature = "package")]
+    #[test]
+    fn test_available_space() {
+        let dir = std::env::temp_dir();
+        let available = available_space(&dir).unwrap();
+        assert!(available.is_some_and(|bytes| bytes > 0));
+        // Not yet created directories are on the volume of their parent
+        assert_eq!(available_space(&dir.join("not/created/yet")).unwrap().is_some(), available.is_some());
+    }
 }
\ No newline at end of file
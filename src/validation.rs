@@ -0,0 +1,579 @@
+use std::collections::HashSet;
+
+use crate::{
+    blockmap::{APPX_MANIFEST, BUNDLE_MANIFEST},
+    identity::{PackageFullName, PackageVersion},
+    keys::{self, KEY_LENGTH},
+    source::PackageSource,
+    EAppxFile,
+    EAppxFooter,
+    EAppxMagic,
+    Manifest,
+};
+
+/// Windows refuses to deploy packages containing paths longer than MAX_PATH
+pub const MAX_PACKAGE_PATH_LENGTH: usize = 260;
+
+/// Characters the AppxPackaging APIs reject inside package-relative paths
+const INVALID_PATH_CHARS: &[char] = &['<', '>', ':', '"', '/', '|', '?', '*'];
+
+/// Only hash algorithm MakeAppx writes blockmaps with
+const SHA256_HASH_METHOD: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+const SHA256_LENGTH: usize = 32;
+const CRYPTO_ALGO: &str = "XTS-AES";
+
+/// Footprint files are stored outside of the blockmap file list
+const FOOTPRINT_NAMES: &[&str] = &[
+    "AppxBlockMap.xml",
+    "AppxSignature.p7x",
+    "[Content_Types].xml",
+    "AppxMetadata\\CodeIntegrity.cat",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Parses fine, but Windows might complain
+    Warning,
+    /// Windows will refuse to deploy the package
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    /// Header fields are plausible and supported
+    Header,
+    /// Footer entries are well-formed
+    Footer,
+    /// Blockmap hash algorithm declarations agree and are supported
+    HashAlgorithm,
+    /// Footprint files present and in the expected order
+    Footprint,
+    /// Path length and character restrictions
+    PathName,
+    /// Blockmap entries agree with the footer table
+    BlockMapConsistency,
+    /// Signature / code integrity payloads placed after the file payloads
+    SignaturePlacement,
+    /// Bundle manifest packages agree with footers and blockmap
+    BundleConsistency,
+    /// Manifest Identity agrees with the package full name in the header
+    Identity,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{:?}] {:?}: {}", self.severity, self.rule, self.message)
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+#[non_exhaustive]
+pub struct ValidationReport {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    fn push(&mut self, severity: Severity, rule: Rule, message: String) {
+        log::debug!("Validation {severity:?} ({rule:?}): {message}");
+        self.issues.push(ValidationIssue { severity, rule, message });
+    }
+
+    /// True if no rule produced an error (warnings are allowed)
+    pub fn is_valid(&self) -> bool {
+        !self.issues.iter().any(|i| i.severity == Severity::Error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &ValidationIssue> {
+        self.issues.iter().filter(|i| i.severity == Severity::Warning)
+    }
+}
+
+/// Check a package against the rules Windows' AppxPackaging enforces on deployment
+///
+/// Meant to be run on freshly produced packages, so packages that parse
+/// fine here but would fail to deploy on a real device are caught early.
+pub fn validate_package(eappx: &EAppxFile) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    check_header(eappx, &mut report);
+    check_footers(eappx, &mut report);
+    check_hash_algorithm(eappx, &mut report);
+    check_footprint(eappx, &mut report);
+    check_path_names(eappx, &mut report);
+    check_blockmap_consistency(eappx, &mut report);
+    check_signature_placement(eappx, &mut report);
+
+    report
+}
+
+/// Cross-check the package table of a bundle manifest against footers and blockmap
+///
+/// Needs the stream to read the bundle manifest, packages without one yield
+/// an empty report.
+pub fn validate_bundle<P: PackageSource + ?Sized>(eappx: &EAppxFile, source: &P) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let bundle_manifest = match eappx.bundle_manifest(source) {
+        Ok(Some(bundle_manifest)) => bundle_manifest,
+        Ok(None) if eappx.header.magic == EAppxMagic::EXBH => {
+            report.push(Severity::Error, Rule::BundleConsistency, "Bundle carries a package manifest".into());
+            return report;
+        },
+        Ok(None) => return report,
+        Err(err) => {
+            report.push(Severity::Error, Rule::BundleConsistency, format!("Failed to read bundle manifest: {err}"));
+            return report;
+        },
+    };
+    let packages = &bundle_manifest.packages.package;
+
+    let mut names = HashSet::new();
+    let mut regions = vec![];
+    for (file_id, package) in packages.iter().enumerate() {
+        let name = &package.filename;
+        if !names.insert(name.to_lowercase()) {
+            report.push(Severity::Error, Rule::BundleConsistency, format!("Package {name} is listed more than once"));
+        }
+
+        let Some(footer) = eappx.find_footer_for_file(file_id as u64) else {
+            report.push(Severity::Error, Rule::BundleConsistency, format!("No footer for package {name} (id: {file_id:#x})"));
+            continue;
+        };
+
+        if package.offset != footer.offset_to_file {
+            report.push(Severity::Error, Rule::BundleConsistency,
+                format!("Offset mismatch for {name} (manifest: {:#x}, footer: {:#x})", package.offset, footer.offset_to_file));
+        }
+        if package.size != footer.uncompressed_length {
+            report.push(Severity::Error, Rule::BundleConsistency,
+                format!("Size mismatch for {name} (manifest: {:#x}, footer: {:#x})", package.size, footer.uncompressed_length));
+        }
+        if footer.compression_type != 0 || footer.compressed_length != footer.uncompressed_length {
+            report.push(Severity::Error, Rule::BundleConsistency, format!("Package {name} is not stored uncompressed"));
+        }
+        if package.offset.saturating_add(package.size) > eappx.file_len {
+            report.push(Severity::Error, Rule::BundleConsistency,
+                format!("Package {name} at {:#x} (size: {:#x}) exceeds file length {:#x}", package.offset, package.size, eappx.file_len));
+        }
+        regions.push((package.offset, package.offset.saturating_add(package.size), name));
+    }
+
+    regions.sort();
+    for pair in regions.windows(2) {
+        if pair[1].0 < pair[0].1 {
+            report.push(Severity::Error, Rule::BundleConsistency, format!("Packages {} and {} overlap", pair[0].2, pair[1].2));
+        }
+    }
+
+    for file in &eappx.blockmap.files {
+        if file.id() < packages.len() as u64 {
+            report.push(Severity::Error, Rule::BundleConsistency,
+                format!("Blockmap file {} uses id {:#x} of package {}", file.name, file.id(), packages[file.id() as usize].filename));
+        }
+    }
+
+    let mut footer_ids = HashSet::new();
+    for footer in &eappx.footers {
+        if !footer_ids.insert(footer.file_id) {
+            report.push(Severity::Error, Rule::BundleConsistency, format!("Footer id {:#x} is used more than once", footer.file_id));
+        }
+        let referenced = footer.file_id < packages.len() as u64
+            || footer.file_id == eappx.header.block_map_file_id
+            || eappx.blockmap.files.iter().any(|file| file.id() == footer.file_id);
+        if !referenced {
+            report.push(Severity::Warning, Rule::BundleConsistency,
+                format!("Footer id {:#x} is neither a package nor a blockmap file", footer.file_id));
+        }
+    }
+
+    // Packages, blockmap files and the blockmap itself
+    let expected_count = packages.len() + eappx.blockmap.files.len() + 1;
+    if eappx.header.file_count as usize != expected_count {
+        report.push(Severity::Error, Rule::BundleConsistency,
+            format!("Header file count ({}) differs from packages, blockmap files and blockmap ({expected_count})", eappx.header.file_count));
+    }
+
+    report
+}
+
+/// Compare the package full name in the header against the one the manifest Identity yields
+///
+/// Needs the stream to read the manifest. A header version of 0.0.0.0 is
+/// accepted, MakeAppx writes that into encrypted packages.
+pub fn validate_identity<P: PackageSource + ?Sized>(eappx: &EAppxFile, source: &P) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let expected = match eappx.read_manifest(source) {
+        Ok(Manifest::Manifest(manifest)) => manifest.identity.full_name(),
+        // Bundles are neutral and carry the `~` resource id
+        Ok(Manifest::BundleManifest(manifest)) => {
+            let identity = &manifest.identity;
+            PackageFullName::new(&identity.name, identity.version, None, Some("~"), &identity.publisher)
+        },
+        Err(err) => {
+            report.push(Severity::Error, Rule::Identity, format!("Failed to read manifest: {err}"));
+            return report;
+        },
+    };
+    let Ok(found) = eappx.header.full_name() else {
+        report.push(Severity::Error, Rule::Identity,
+            format!("Package full name {:?} does not match {:?} from the manifest", eappx.header.package_full_name(), expected.to_string()));
+        return report;
+    };
+
+    let version_matches = found.version == expected.version || found.version == PackageVersion::ZERO;
+    let fields = [
+        ("Name", found.name, expected.name),
+        ("Version", found.version.to_string(), expected.version.to_string()),
+        ("Architecture", found.architecture, expected.architecture),
+        ("ResourceId", found.resource_id, expected.resource_id),
+        ("PublisherId", found.publisher_id, expected.publisher_id),
+    ];
+    for (field, found, expected) in fields {
+        let matches = match field {
+            "Version" => version_matches,
+            // Package identities compare case-insensitively
+            _ => found.eq_ignore_ascii_case(&expected),
+        };
+        if !matches {
+            report.push(Severity::Error, Rule::Identity,
+                format!("{field} in package full name is {found:?}, manifest says {expected:?}"));
+        }
+    }
+
+    report
+}
+
+fn check_header(eappx: &EAppxFile, report: &mut ValidationReport) {
+    let header = &eappx.header;
+
+    let version = header.header_version();
+    if !version.is_known() {
+        report.push(Severity::Warning, Rule::Header, format!("Unknown header version {version}"));
+    }
+
+    match header.to_bytes() {
+        Ok(bytes) if bytes.len() != header.header_size as usize => report.push(Severity::Warning, Rule::Header,
+            format!("Header size {:#x} differs from its content ({:#x})", header.header_size, bytes.len())),
+        Ok(_) => {},
+        Err(err) => report.push(Severity::Error, Rule::Header, format!("Header cannot be serialized: {err}")),
+    }
+
+    if header.footer_length < header.file_count.saturating_mul(EAppxFooter::SIZE as u64) {
+        report.push(Severity::Error, Rule::Header,
+            format!("Footer length {:#x} is too short for {} footers", header.footer_length, header.file_count));
+    }
+
+    let key_length = keys::key_length_for_algorithm(&header.crypto_algo()).unwrap_or(KEY_LENGTH);
+    if !header.key_ids.is_empty() && header.key_length as usize != key_length {
+        report.push(Severity::Error, Rule::Header,
+            format!("Key length {:#x}, expected {key_length:#x}", header.key_length));
+    }
+
+    let strings = [
+        ("Package full name", header.package_full_name_raw()),
+        ("Crypto algorithm", header.crypto_algo_raw()),
+        ("Blockmap hash algorithm", header.block_map_hash_algo_raw()),
+    ];
+    for (field, raw) in strings {
+        if String::from_utf16(raw).is_err() {
+            report.push(Severity::Error, Rule::Header, format!("{field} is not valid UTF-16: {raw:04x?}"));
+        }
+    }
+
+    if header.crypto_algo() != CRYPTO_ALGO {
+        report.push(Severity::Error, Rule::Header, format!("Unsupported crypto algorithm {:?}", header.crypto_algo()));
+    }
+
+    if header.full_name().is_err() {
+        report.push(Severity::Warning, Rule::Header,
+            format!("Package full name {:?} does not have the form Name_Version_Arch_ResourceId_PublisherId", header.package_full_name()));
+    }
+}
+
+fn check_footers(eappx: &EAppxFile, report: &mut ValidationReport) {
+    for footer in &eappx.footers {
+        let id = footer.file_id;
+
+        if footer.magic != EAppxFooter::MAGIC || footer.footer_size < EAppxFooter::SIZE {
+            report.push(Severity::Error, Rule::Footer,
+                format!("Footer {id:#x} has magic {:#06x} and size {:#x}", footer.magic, footer.footer_size));
+        } else if footer.footer_size > EAppxFooter::SIZE {
+            report.push(Severity::Warning, Rule::Footer,
+                format!("Footer {id:#x} has size {:#x}, newer than the known format", footer.footer_size));
+        }
+
+        if footer.compression_type > 1 {
+            report.push(Severity::Error, Rule::Footer,
+                format!("Footer {id:#x} has unknown compression type {:#x}", footer.compression_type));
+        }
+
+        if footer.key_id_index != 0xFFFF && footer.key_id_index as usize >= eappx.header.key_ids.len() {
+            report.push(Severity::Error, Rule::Footer,
+                format!("Footer {id:#x} uses key index {:#x} of {} key ids", footer.key_id_index, eappx.header.key_ids.len()));
+        }
+    }
+}
+
+fn check_hash_algorithm(eappx: &EAppxFile, report: &mut ValidationReport) {
+    let header_algo = eappx.header.block_map_hash_algo();
+    let blockmap_algo = eappx.blockmap.hash_method();
+
+    if header_algo != blockmap_algo {
+        report.push(Severity::Error, Rule::HashAlgorithm,
+            format!("Header declares {header_algo}, blockmap {blockmap_algo}"));
+    }
+
+    for algo in [header_algo.as_str(), blockmap_algo] {
+        if algo != SHA256_HASH_METHOD {
+            report.push(Severity::Error, Rule::HashAlgorithm, format!("Unsupported hash algorithm {algo}"));
+        }
+    }
+
+    if eappx.header.block_map_hash.len() != SHA256_LENGTH {
+        report.push(Severity::Error, Rule::HashAlgorithm,
+            format!("Blockmap hash has {} bytes, expected {SHA256_LENGTH}", eappx.header.block_map_hash.len()));
+    }
+}
+
+fn check_footprint(eappx: &EAppxFile, report: &mut ValidationReport) {
+    if eappx.find_footer_for_file(eappx.header.block_map_file_id).is_none() {
+        report.push(Severity::Error, Rule::Footprint,
+            format!("Blockmap file id {:#x} has no footer", eappx.header.block_map_file_id));
+    }
+
+    let expected_manifest = match eappx.header.magic {
+        EAppxMagic::EXBH => BUNDLE_MANIFEST,
+        EAppxMagic::EXPH => APPX_MANIFEST,
+        // EXSH does not tell which kind of manifest it carries
+        EAppxMagic::EXSH => eappx.blockmap.files
+            .iter()
+            .map(|f| f.name.as_str())
+            .find(|name| name.eq_ignore_ascii_case(BUNDLE_MANIFEST))
+            .unwrap_or(APPX_MANIFEST),
+    };
+
+    match eappx.blockmap.files.iter().position(|f| f.name.eq_ignore_ascii_case(expected_manifest)) {
+        None => report.push(Severity::Error, Rule::Footprint,
+            format!("{expected_manifest} missing from blockmap")),
+        Some(0) => {},
+        Some(idx) => report.push(Severity::Warning, Rule::Footprint,
+            format!("{expected_manifest} is blockmap entry {idx}, expected it to be the first entry")),
+    }
+
+    for file in &eappx.blockmap.files {
+        if FOOTPRINT_NAMES.iter().any(|name| file.name.eq_ignore_ascii_case(name)) {
+            report.push(Severity::Error, Rule::Footprint,
+                format!("Footprint file {} must not be listed in the blockmap", file.name));
+        }
+    }
+}
+
+fn check_path_names(eappx: &EAppxFile, report: &mut ValidationReport) {
+    for file in &eappx.blockmap.files {
+        let name = &file.name;
+
+        if name.encode_utf16().count() > MAX_PACKAGE_PATH_LENGTH {
+            report.push(Severity::Error, Rule::PathName,
+                format!("Path exceeds {MAX_PACKAGE_PATH_LENGTH} characters: {name}"));
+        }
+
+        if let Some(c) = name.chars().find(|c| INVALID_PATH_CHARS.contains(c) || c.is_control()) {
+            report.push(Severity::Error, Rule::PathName,
+                format!("Invalid character {c:?} in path: {name}"));
+        }
+
+        if name.starts_with('\\') || name.split('\\').any(|seg| seg.is_empty() || seg == "." || seg == "..") {
+            report.push(Severity::Error, Rule::PathName,
+                format!("Path is not a canonical relative path: {name}"));
+        }
+
+        if name.split('\\').any(|seg| seg.ends_with('.') || seg.ends_with(' ')) {
+            report.push(Severity::Warning, Rule::PathName,
+                format!("Path segment ends with '.' or ' ': {name}"));
+        }
+    }
+}
+
+fn check_blockmap_consistency(eappx: &EAppxFile, report: &mut ValidationReport) {
+    for file in &eappx.blockmap.files {
+        let Some(footer) = eappx.find_footer_for_file(file.id()) else {
+            report.push(Severity::Error, Rule::BlockMapConsistency,
+                format!("No footer for blockmap file {} (id: {:#x})", file.name, file.id()));
+            continue;
+        };
+
+        if footer.uncompressed_length != file.size {
+            report.push(Severity::Error, Rule::BlockMapConsistency,
+                format!("Size mismatch for {} (blockmap: {:#x}, footer: {:#x})", file.name, file.size, footer.uncompressed_length));
+        }
+
+        let expected_blocks = eappx.params.block_count(file.size);
+        if file.blocks.len() != expected_blocks {
+            report.push(Severity::Error, Rule::BlockMapConsistency,
+                format!("Block count mismatch for {} (blockmap: {}, expected: {expected_blocks})", file.name, file.blocks.len()));
+        }
+
+        let footer_encrypted = eappx.is_footer_encrypted(footer);
+        if file.is_encrypted() != footer_encrypted {
+            let flag = file.encrypted.map_or("absent".to_string(), |encrypted| encrypted.to_string());
+            report.push(Severity::Warning, Rule::BlockMapConsistency,
+                format!("Encryption flag mismatch for {} (blockmap: {flag}, footer key index: {:#x})", file.name, footer.key_id_index));
+        }
+    }
+
+    if eappx.header.file_count as usize != eappx.footers.len() {
+        report.push(Severity::Warning, Rule::BlockMapConsistency,
+            format!("Header file count ({}) differs from footer count ({})", eappx.header.file_count, eappx.footers.len()));
+    }
+}
+
+fn check_signature_placement(eappx: &EAppxFile, report: &mut ValidationReport) {
+    let payload_end = eappx.footers
+        .iter()
+        .map(|f| f.offset_to_file + f.compressed_length)
+        .max()
+        .unwrap_or(0);
+
+    let footprints = [
+        ("Signature", eappx.header.appx_signature_fileinfo()),
+        ("Code integrity", eappx.header.code_integrity_fileinfo()),
+    ];
+
+    for (name, fileinfo) in footprints {
+        let Some(fileinfo) = fileinfo else {
+            continue;
+        };
+
+        if fileinfo.offset_to_file < payload_end {
+            report.push(Severity::Error, Rule::SignaturePlacement,
+                format!("{name} at {:#x} overlaps file payloads (ending at {payload_end:#x})", fileinfo.offset_to_file));
+        }
+
+        if fileinfo.offset_to_file + fileinfo.compressed_length > eappx.file_len {
+            report.push(Severity::Error, Rule::SignaturePlacement,
+                format!("{name} at {:#x} (size: {:#x}) exceeds file length {:#x}", fileinfo.offset_to_file, fileinfo.compressed_length, eappx.file_len));
+        }
+    }
+
+    if !eappx.header.is_signed() {
+        report.push(Severity::Warning, Rule::SignaturePlacement,
+            "Package is not signed, deployment requires a trusted signature".into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+    const EMSIXBUNDLE: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsixbundle");
+
+    #[test]
+    fn test_validate_package() {
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let report = validate_package(&eappx);
+
+        assert!(report.is_valid(), "{:?}", report.issues);
+        assert_eq!(report.warnings().count(), 1);
+    }
+
+    #[test]
+    fn test_validate_bundle() {
+        let eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let report = validate_package(&eappx);
+
+        assert!(report.is_valid(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn test_validate_identity() {
+        for data in [EMSIX, EMSIXBUNDLE] {
+            let mut eappx = EAppxFile::from_source(&data).unwrap();
+            let report = validate_identity(&eappx, &data);
+            assert!(report.issues.is_empty(), "{:?}", report.issues);
+
+            let pfn = eappx.header.package_full_name().replace("0.0.0.0", "1.0.2.0").replace("nh20k94c8ngfj", "8wekyb3d8bbwe");
+            eappx.header.package_full_name = pfn.encode_utf16().collect();
+            let report = validate_identity(&eappx, &data);
+            let messages: Vec<_> = report.errors().map(|i| i.message.as_str()).collect();
+            assert_eq!(messages, [
+                r#"Version in package full name is "1.0.2.0", manifest says "1.0.3.0""#,
+                r#"PublisherId in package full name is "8wekyb3d8bbwe", manifest says "nh20k94c8ngfj""#,
+            ]);
+        }
+    }
+
+    #[test]
+    fn test_validate_bundle_consistency() {
+        let mut eappx = EAppxFile::from_source(EMSIXBUNDLE).unwrap();
+        let report = validate_bundle(&eappx, EMSIXBUNDLE);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+
+        eappx.footers[0].uncompressed_length += 1;
+        eappx.footers.push(crate::EAppxFooter::new(7, 0x188, None, false, 0, 0));
+        eappx.header.file_count = 4;
+        let report = validate_bundle(&eappx, EMSIXBUNDLE);
+        let messages: Vec<_> = report.issues.iter().map(|i| i.message.as_str()).collect();
+        assert!(messages.iter().any(|m| m.starts_with("Size mismatch for TestApp_1.0.3.0_x64.msix")), "{messages:?}");
+        assert!(messages.iter().any(|m| m.starts_with("Package TestApp_1.0.3.0_x64.msix is not stored uncompressed")));
+        assert!(messages.iter().any(|m| m.starts_with("Footer id 0x7 is neither")));
+        assert!(messages.iter().any(|m| m.starts_with("Header file count (4)")));
+
+        let eappx = EAppxFile::from_source(EMSIX).unwrap();
+        assert!(validate_bundle(&eappx, EMSIX).issues.is_empty());
+    }
+
+    #[test]
+    fn test_validate_structure() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.footers[0].magic = 0;
+        eappx.footers[1].key_id_index = 3;
+        eappx.header.key_length = 0x10;
+        eappx.header.package_full_name[0] = 0xD800;
+        eappx.blockmap = crate::blockmap::AppxBlockMap::from_xml(
+            br#"<BlockMap xmlns="http://schemas.microsoft.com/appx/2010/blockmap" HashMethod="http://www.w3.org/2000/09/xmldsig#sha1"/>"#).unwrap();
+
+        let report = validate_package(&eappx);
+        let rules: Vec<_> = report.errors().map(|i| i.rule).collect();
+        assert_eq!(rules.iter().filter(|rule| **rule == Rule::Footer).count(), 2);
+        assert_eq!(rules.iter().filter(|rule| **rule == Rule::Header).count(), 2);
+        assert_eq!(rules.iter().filter(|rule| **rule == Rule::HashAlgorithm).count(), 2);
+    }
+
+    #[test]
+    fn test_validate_encrypted_flag() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        let index = eappx.blockmap.files.iter().position(|file| file.is_encrypted()).unwrap();
+        let name = eappx.blockmap.files[index].name.clone();
+        eappx.blockmap.files[index].encrypted = None;
+        assert!(eappx.is_file_encrypted(&eappx.blockmap.files[index]));
+
+        let report = validate_package(&eappx);
+        let messages: Vec<_> = report.warnings().map(|i| i.message.as_str()).collect();
+        assert!(messages.iter().any(|m| m.starts_with(&format!("Encryption flag mismatch for {name} (blockmap: absent"))), "{messages:?}");
+    }
+
+    #[test]
+    fn test_validate_path_names() {
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.blockmap.files[1].name = "Assets\\..\\evil?.png".into();
+        eappx.blockmap.files[2].name = "A\\".repeat(200);
+
+        let report = validate_package(&eappx);
+        let path_errors = report.errors().filter(|i| i.rule == Rule::PathName).count();
+        assert_eq!(path_errors, 4);
+    }
+}
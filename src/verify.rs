@@ -0,0 +1,240 @@
+use crate::{footprint::SkippedFootprint, keys::KeyId, timings::Timings};
+
+/// Hashes checked while reading files out of a package
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IntegrityPolicy {
+    /// Trust the payload, nothing is hashed
+    None,
+    /// Hash over the whole plaintext, a mismatch only shows once the file was read
+    FileHashes,
+    /// Hash of every stored block, checked before it is decrypted
+    BlockHashes,
+    /// Block and file hashes
+    #[default]
+    Full,
+}
+
+impl IntegrityPolicy {
+    pub fn checks_blocks(self) -> bool {
+        matches!(self, Self::BlockHashes | Self::Full)
+    }
+
+    pub fn checks_file(self) -> bool {
+        matches!(self, Self::FileHashes | Self::Full)
+    }
+}
+
+/// Verification outcome of a single blockmap file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FileStatus {
+    /// All hashes that could be checked matched
+    Valid,
+    /// One or more hashes did not match
+    Corrupt,
+    /// Block hashes matched, but the file hash covers the plaintext of an
+    /// encrypted file and its key is not loaded
+    KeyMissing,
+    /// Blockmap entry has no corresponding footer
+    MissingFooter,
+    /// Payload could not be read
+    ReadError(String),
+}
+
+/// Blockmap and footer disagree about the uncompressed size of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SizeMismatch {
+    pub blockmap: u64,
+    pub footer: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FileReport {
+    pub name: String,
+    pub file_id: u64,
+    pub status: FileStatus,
+    /// Indices of blocks whose hash did not match
+    pub failed_blocks: Vec<usize>,
+    /// Result of the whole-file hash check, `None` if it could not be checked
+    pub filehash_matches: Option<bool>,
+    pub size_mismatch: Option<SizeMismatch>,
+    /// Key-id the file is encrypted with, set if its status is [`FileStatus::KeyMissing`]
+    pub missing_key: Option<KeyId>,
+    /// Time spent per stage, set if [`EAppxFile::collect_timings`](crate::EAppxFile::collect_timings) is
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub timings: Option<Timings>,
+}
+
+impl FileReport {
+    pub(crate) fn new(name: &str, file_id: u64) -> Self {
+        Self {
+            name: name.to_owned(),
+            file_id,
+            status: FileStatus::Valid,
+            failed_blocks: vec![],
+            filehash_matches: None,
+            size_mismatch: None,
+            missing_key: None,
+            timings: None,
+        }
+    }
+
+    /// No hash mismatched, files lacking their key count as valid
+    pub fn is_valid(&self) -> bool {
+        matches!(self.status, FileStatus::Valid | FileStatus::KeyMissing)
+    }
+}
+
+/// Manifest publisher does not hash to the publisher-id in the package full name
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PublisherMismatch {
+    /// Publisher of the manifest Identity
+    pub publisher: String,
+    /// Publisher-id `publisher` hashes to
+    pub expected: String,
+    /// Publisher-id of the header's package full name
+    pub found: String,
+}
+
+/// Result of `EAppxFile::verify`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VerificationReport {
+    pub files: Vec<FileReport>,
+    /// `None` if the publishers agree or the manifest could not be read
+    pub publisher_mismatch: Option<PublisherMismatch>,
+    /// Footprint files beyond the end of the package, a warning only
+    pub skipped_footprints: Vec<SkippedFootprint>,
+}
+
+impl VerificationReport {
+    /// True if every file verified successfully and the publishers agree
+    pub fn is_valid(&self) -> bool {
+        self.files.iter().all(|f| f.is_valid()) && self.publisher_mismatch.is_none()
+    }
+
+    /// True if footprint files are missing from the end of the package
+    pub fn is_truncated(&self) -> bool {
+        !self.skipped_footprints.is_empty()
+    }
+
+    pub fn failed_files(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|f| !f.is_valid())
+    }
+
+    /// Encrypted files whose plaintext could not be verified for lack of a key
+    pub fn unverified_files(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|f| f.status == FileStatus::KeyMissing)
+    }
+
+    /// Time spent per stage across all files, `None` if none were collected
+    pub fn timings(&self) -> Option<Timings> {
+        self.files.iter().filter_map(|f| f.timings).reduce(|total, timings| total + timings)
+    }
+}
+
+impl std::fmt::Display for VerificationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in &self.files {
+            write!(f, "* {} (id: {:#x}): {:?}", file.name, file.file_id, file.status)?;
+            if !file.failed_blocks.is_empty() {
+                write!(f, ", failed blocks: {:?}", file.failed_blocks)?;
+            }
+            if let Some(mismatch) = &file.size_mismatch {
+                write!(f, ", size mismatch (blockmap: {:#x}, footer: {:#x})", mismatch.blockmap, mismatch.footer)?;
+            }
+            if let Some(key_id) = &file.missing_key {
+                write!(f, ", needs key {}", key_id.to_keyfile_string())?;
+            }
+            writeln!(f)?;
+        }
+
+        let failed = self.failed_files().count();
+        let unverified = self.unverified_files().count();
+        if unverified > 0 {
+            writeln!(f, "{unverified} encrypted files not verifiable without key, only their block hashes were checked")?;
+        }
+        if let Some(mismatch) = &self.publisher_mismatch {
+            writeln!(f, "Manifest publisher {:?} hashes to {}, package full name has {}", mismatch.publisher, mismatch.expected, mismatch.found)?;
+        }
+        for skipped in &self.skipped_footprints {
+            writeln!(f, "Warning: {skipped}, the package looks truncated")?;
+        }
+        writeln!(f, "Verified {} files, {} failed => {}",
+            self.files.len(), failed, if self.is_valid() { "PASS" } else { "FAIL" })
+    }
+}
+
+/// Verification outcome of a package embedded in a bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BundlePackageReport {
+    pub name: String,
+    pub file_id: u64,
+    /// Size the bundle manifest declares
+    pub manifest_size: u64,
+    /// Uncompressed size of the footer, `None` without one
+    pub footer_size: Option<u64>,
+    /// Hash check of a bundle blockmap entry of the same name, `None` if there is none
+    pub blocks: Option<FileReport>,
+    /// Why the stored package does not check out, e.g. its blockmap not matching its header
+    pub error: Option<String>,
+}
+
+impl BundlePackageReport {
+    pub fn is_valid(&self) -> bool {
+        self.footer_size == Some(self.manifest_size)
+            && self.blocks.as_ref().is_none_or(FileReport::is_valid)
+            && self.error.is_none()
+    }
+}
+
+impl std::fmt::Display for BundlePackageReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (id: {:#x}): {}", self.name, self.file_id, if self.is_valid() { "OK" } else { "FAILED" })?;
+        if let Some(footer_size) = self.footer_size.filter(|size| *size != self.manifest_size) {
+            write!(f, ", size mismatch (manifest: {:#x}, footer: {footer_size:#x})", self.manifest_size)?;
+        }
+        if let Some(blocks) = self.blocks.as_ref().filter(|blocks| !blocks.is_valid()) {
+            write!(f, ", blockmap entry {:?}", blocks.status)?;
+            if !blocks.failed_blocks.is_empty() {
+                write!(f, ", failed blocks: {:?}", blocks.failed_blocks)?;
+            }
+        }
+        if let Some(error) = &self.error {
+            write!(f, ", {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of `EAppxFile::verify_bundle_packages`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BundleVerificationReport {
+    pub packages: Vec<BundlePackageReport>,
+}
+
+impl BundleVerificationReport {
+    pub fn is_valid(&self) -> bool {
+        self.packages.iter().all(BundlePackageReport::is_valid)
+    }
+
+    pub fn failed_packages(&self) -> impl Iterator<Item = &BundlePackageReport> {
+        self.packages.iter().filter(|package| !package.is_valid())
+    }
+}
+
+impl std::fmt::Display for BundleVerificationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for package in &self.packages {
+            writeln!(f, "* {package}")?;
+        }
+        writeln!(f, "Checked {} bundle packages, {} failed => {}",
+            self.packages.len(), self.failed_packages().count(), if self.is_valid() { "PASS" } else { "FAIL" })
+    }
+}
@@ -0,0 +1,960 @@
+//! Pipelined package writer
+//!
+//! Packing runs as a pipeline of stages connected by bounded queues:
+//!
+//! ```text
+//! reader (caller) -> worker pool (encrypt, block hash, deflate) -> sink (reorder, write)
+//! ```
+//!
+//! The calling thread reads entries in 64KiB blocks and feeds the file hash,
+//! which has to see the plaintext in order. Once its sector offset is known a
+//! block does not depend on any other block, so encryption, block hashing and
+//! compression run on a pool of workers. Every block is deflated by a fresh
+//! compressor and ends on a sync flush, which makes the compressed blocks
+//! concatenate into one valid deflate stream and gives the blockmap its
+//! per-block sizes. The sink restores block order and writes the payloads.
+//!
+//! Neither the amount of entries nor their sizes need to be known upfront:
+//! footers and blockmap records are buffered while entries stream through,
+//! header and footer table are emitted by [`PackageWriter::finish`] once the
+//! blockmap has been generated from the collected hashes.
+//!
+//! Output only depends on the entries, their order and the options: entries
+//! are laid out in the order they are added, blocks in sequence regardless of
+//! the worker count, with the configured deflate level (the deflate backend
+//! flate2 was built with matters too). Encrypted payloads are zero padded to
+//! the next 0x200 byte sector, payload offsets are only aligned as requested
+//! by [`WriterOptions::alignment`]. The only varying part is the code
+//! integrity catalog, which gets a random identifier and the current time
+//! unless [`WriterOptions::deterministic`] is set.
+
+use std::{
+    collections::BTreeMap,
+    io::{Read, Seek, SeekFrom, Write},
+    sync::{mpsc, Arc, Mutex},
+    thread::JoinHandle,
+};
+
+use base64ct::{Base64, Encoding};
+use flate2::{Compress, Compression, FlushCompress, Status};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    blockmap::{self, AppxBlockMap},
+    builder::{EAppxHeaderBuilder, FootprintPayload},
+    code_integrity::{AuthenticodeHasher, CodeIntegrityCatalog},
+    compression::{self, ByExtension, CompressionPolicy},
+    container::ContainerParams,
+    crypto::{create_cipher, get_tweak_for_file, CryptoFileContext},
+    error::Error,
+    keys::{self, KeyId},
+    utils,
+    validation::{self, ValidationReport},
+    EAppxFile, EAppxFooter, EAppxHeader, EAppxMagic,
+};
+
+const BLOCKMAP_HASH_LENGTH: usize = 0x20;
+
+/// Set through the builder methods, e.g. `WriterOptions::default().alignment(1)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WriterOptions {
+    /// Threads encrypting, hashing and compressing blocks
+    pub workers: usize,
+    /// Blocks that may be queued between two stages
+    pub queue_depth: usize,
+    /// Deflate level (0-9), see [`compression`](crate::compression) for the backend
+    pub compression_level: u32,
+    /// Generate a code integrity catalog for the PE files packed
+    pub code_integrity: bool,
+    /// Derive the catalog identity from its members, for byte-identical output
+    pub deterministic: bool,
+    /// Payload offsets are zero padded to a multiple of this power of two,
+    /// e.g. [`utils::SECTOR_SIZE`] or [`utils::BLOCK_SIZE`], 1 packs them tightly
+    pub alignment: u64,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+
+        Self {
+            workers,
+            queue_depth: workers * 4,
+            compression_level: compression::DEFAULT_LEVEL,
+            code_integrity: false,
+            deterministic: false,
+            alignment: 1,
+        }
+    }
+}
+
+impl WriterOptions {
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    pub fn queue_depth(mut self, queue_depth: usize) -> Self {
+        self.queue_depth = queue_depth;
+        self
+    }
+
+    pub fn compression_level(mut self, compression_level: u32) -> Self {
+        self.compression_level = compression_level;
+        self
+    }
+
+    pub fn code_integrity(mut self, code_integrity: bool) -> Self {
+        self.code_integrity = code_integrity;
+        self
+    }
+
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.deterministic = deterministic;
+        self
+    }
+
+    pub fn alignment(mut self, alignment: u64) -> Self {
+        self.alignment = alignment;
+        self
+    }
+}
+
+/// How a single entry is stored, e.g. `EntryOptions::default().compress(true)`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EntryOptions {
+    pub compress: bool,
+    /// Index into the keys passed to [`PackageWriter::new`], `None` stores the entry unencrypted
+    pub key_index: Option<u16>,
+}
+
+impl EntryOptions {
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn key_index(mut self, key_index: Option<u16>) -> Self {
+        self.key_index = key_index;
+        self
+    }
+}
+
+/// Entry handed to [`PackageWriter::add_entries`]
+#[derive(Debug)]
+pub struct PackEntry<R: Read> {
+    /// Package-relative path
+    pub name: String,
+    pub reader: R,
+    pub options: EntryOptions,
+}
+
+/// Entry as seen by the reader stage
+#[derive(Debug)]
+struct EntryMeta {
+    name: String,
+    size: u64,
+    filehash: Vec<u8>,
+    options: EntryOptions,
+}
+
+/// Entry as written by the sink stage
+#[derive(Debug, Default)]
+struct WrittenEntry {
+    offset: u64,
+    stored_length: u64,
+    /// Block hash and stored size of every block
+    blocks: Vec<(Vec<u8>, u64)>,
+}
+
+struct BlockJob {
+    seq: u64,
+    entry: usize,
+    block: u64,
+    data: Vec<u8>,
+    last: bool,
+    compress: bool,
+    crypto: Option<Arc<CryptoFileContext>>,
+}
+
+struct ProcessedBlock {
+    seq: u64,
+    entry: usize,
+    /// `None` for the placeholder block of an empty entry
+    hash: Option<Vec<u8>>,
+    data: Vec<u8>,
+}
+
+type SinkResult<W> = Result<(W, u64, Vec<WrittenEntry>), Error>;
+
+struct Pipeline<W> {
+    jobs: mpsc::SyncSender<BlockJob>,
+    workers: Vec<JoinHandle<()>>,
+    sink: JoinHandle<SinkResult<W>>,
+}
+
+/// Writes an encrypted package, see the module documentation for the pipeline layout
+pub struct PackageWriter<W: Write + Seek + Send + 'static> {
+    pipeline: Option<Pipeline<W>>,
+    magic: EAppxMagic,
+    package_full_name: String,
+    header_size: u64,
+    key_ids: Vec<KeyId>,
+    keys: Vec<[u8; 32]>,
+    pfn: (String, String),
+    options: WriterOptions,
+    entries: Vec<EntryMeta>,
+    catalog: Option<CodeIntegrityCatalog>,
+    /// Decides for [`PackageWriter::add_file`] whether to deflate
+    compression: Box<dyn CompressionPolicy>,
+    seq: u64,
+}
+
+impl<W: Write + Seek + Send + 'static> PackageWriter<W> {
+    /// Start writing a package into `sink`, which is expected to be empty
+    ///
+    /// The header is reserved right away and patched by [`Self::finish`].
+    pub fn new(
+        mut sink: W,
+        magic: EAppxMagic,
+        package_full_name: &str,
+        keys: Vec<(KeyId, Vec<u8>)>,
+        options: WriterOptions,
+    ) -> Result<Self, Error> {
+        compression::check_level(options.compression_level)?;
+        let alignment = options.alignment.max(1);
+        if !alignment.is_power_of_two() {
+            return Err(Error::DataError(format!("Alignment {alignment:#x} is not a power of two")));
+        }
+        // Encrypted payloads are padded to whole sectors and have to stay on that grid
+        if !keys.is_empty() && alignment > 1 && !alignment.is_multiple_of(utils::SECTOR_SIZE as u64) {
+            return Err(Error::DataError(format!("Alignment {alignment:#x} breaks sector alignment of encrypted payloads, use a multiple of {:#x}", utils::SECTOR_SIZE)));
+        }
+
+        let (key_ids, keys): (Vec<KeyId>, Vec<Vec<u8>>) = keys.into_iter().unzip();
+        let header = Self::header_builder(magic, package_full_name, &key_ids)
+            .block_map(0, vec![0; BLOCKMAP_HASH_LENGTH])
+            .build()?;
+        // The header declares the key length readers check keys against
+        let keys = keys.into_iter()
+            .map(|mut key| {
+                let converted = <[u8; 32]>::try_from(key.as_slice())
+                    .ok()
+                    .filter(|_| key.len() == header.key_length as usize)
+                    .ok_or_else(|| Error::DataError(format!("Invalid key length {:#x}, the header declares {:#x}", key.len(), header.key_length)));
+                keys::wipe(&mut key);
+                converted
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let pfn = (header.app_name(), header.publisher_id());
+
+        sink.seek(SeekFrom::Start(0))?;
+        sink.write_all(&vec![0u8; header.header_size as usize])?;
+
+        let options = WriterOptions {
+            workers: options.workers.max(1),
+            queue_depth: options.queue_depth.max(1),
+            alignment,
+            ..options
+        };
+
+        Ok(Self {
+            pipeline: Some(Self::spawn_pipeline(sink, header.header_size as u64, &options)),
+            magic,
+            package_full_name: package_full_name.into(),
+            header_size: header.header_size as u64,
+            key_ids,
+            keys,
+            pfn,
+            options,
+            entries: vec![],
+            catalog: options.code_integrity.then(|| CodeIntegrityCatalog::new().deterministic(options.deterministic)),
+            compression: Box::new(ByExtension::default()),
+            seq: 0,
+        })
+    }
+
+    /// Policy [`PackageWriter::add_file`] picks compression by, [`ByExtension`] by default
+    pub fn compression_policy<P: CompressionPolicy + 'static>(mut self, policy: P) -> Self {
+        self.compression = Box::new(policy);
+        self
+    }
+
+    fn header_builder(magic: EAppxMagic, package_full_name: &str, key_ids: &[KeyId]) -> EAppxHeaderBuilder {
+        EAppxHeaderBuilder::new(magic)
+            .package_full_name(package_full_name)
+            .key_ids(key_ids.to_vec())
+    }
+
+    fn spawn_pipeline(sink: W, position: u64, options: &WriterOptions) -> Pipeline<W> {
+        let (job_tx, job_rx) = mpsc::sync_channel::<BlockJob>(options.queue_depth);
+        let (result_tx, result_rx) = mpsc::sync_channel::<Result<ProcessedBlock, Error>>(options.queue_depth);
+        let job_rx = Arc::new(Mutex::new(job_rx));
+
+        let workers = (0..options.workers)
+            .map(|_| {
+                let job_rx = job_rx.clone();
+                let result_tx = result_tx.clone();
+                let level = options.compression_level;
+                std::thread::spawn(move || loop {
+                    let job = match job_rx.lock().unwrap().recv() {
+                        Ok(job) => job,
+                        Err(_) => break,
+                    };
+                    if result_tx.send(process_block(job, level)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        let alignment = options.alignment;
+        let sink = std::thread::spawn(move || run_sink(sink, position, alignment, result_rx));
+
+        Pipeline { jobs: job_tx, workers, sink }
+    }
+
+    /// Add an entry, `name` is the package-relative path
+    ///
+    /// Returns once the entry was read completely, processing continues in
+    /// the background.
+    pub fn add_entry<R: Read>(&mut self, name: &str, reader: &mut R, options: EntryOptions) -> Result<(), Error> {
+        let name = name.replace('/', "\\");
+        let crypto = match options.key_index {
+            Some(index) => {
+                let key = self.keys.get(index as usize)
+                    .ok_or(Error::DataError(format!("Key index {index} out of range for {name}")))?;
+                Some(Arc::new(CryptoFileContext {
+                    cipher: create_cipher(key),
+                    tweak: get_tweak_for_file(&self.pfn.0, &self.pfn.1, &name),
+                    params: ContainerParams::default(),
+                }))
+            },
+            None => None,
+        };
+
+        let entry = self.entries.len();
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut block = 0u64;
+        let mut buf = read_block(reader)?;
+        let mut pe_hasher = self.catalog.as_ref().and_then(|_| AuthenticodeHasher::new(&buf));
+
+        loop {
+            let next = match buf.len() {
+                utils::BLOCK_SIZE => read_block(reader)?,
+                _ => vec![],
+            };
+            let last = next.is_empty();
+
+            hasher.update(&buf);
+            size += buf.len() as u64;
+            if let Some(pe_hasher) = pe_hasher.as_mut() {
+                pe_hasher.update(&buf);
+            }
+
+            // Empty entries still send a placeholder so the sink learns their offset
+            if !buf.is_empty() || block == 0 {
+                let job = BlockJob {
+                    seq: self.seq,
+                    entry,
+                    block,
+                    data: buf,
+                    last,
+                    compress: options.compress,
+                    crypto: crypto.clone(),
+                };
+                self.send(job)?;
+                self.seq += 1;
+            }
+
+            if last {
+                break;
+            }
+            buf = next;
+            block += 1;
+        }
+
+        if let (Some(catalog), Some(pe_hasher)) = (self.catalog.as_mut(), pe_hasher) {
+            catalog.add(pe_hasher.finalize());
+        }
+
+        log::debug!("Queued {name} ({size:#x} bytes, {} blocks)", block + 1);
+        self.entries.push(EntryMeta {
+            name,
+            size,
+            filehash: hasher.finalize().to_vec(),
+            options: EntryOptions { compress: options.compress && size > 0, ..options },
+        });
+
+        Ok(())
+    }
+
+    /// Add an entry, deflated if the compression policy says so
+    ///
+    /// Ciphertext does not compress, encrypted entries are always stored.
+    pub fn add_file<R: Read>(&mut self, name: &str, reader: &mut R, key_index: Option<u16>) -> Result<(), Error> {
+        let block = read_block(reader)?;
+        let compress = key_index.is_none() && self.compression.should_compress(name, &block);
+        self.add_entry(name, &mut block.as_slice().chain(reader), EntryOptions { compress, key_index })
+    }
+
+    /// Add all entries an iterator yields, returns the amount of entries added
+    ///
+    /// Entries are pulled one at a time, so the iterator may generate them on the fly.
+    pub fn add_entries<R: Read, I: IntoIterator<Item = PackEntry<R>>>(&mut self, entries: I) -> Result<usize, Error> {
+        let mut count = 0;
+        for mut entry in entries {
+            self.add_entry(&entry.name, &mut entry.reader, entry.options)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Entries added so far
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn send(&mut self, job: BlockJob) -> Result<(), Error> {
+        let pipeline = self.pipeline.as_ref()
+            .ok_or(Error::DataError("Writer pipeline already stopped".into()))?;
+
+        if pipeline.jobs.send(job).is_ok() {
+            return Ok(());
+        }
+
+        // A stage went down, the sink holds the actual error
+        match self.shutdown() {
+            Err(err) => Err(err),
+            Ok(_) => Err(Error::DataError("Writer pipeline stopped unexpectedly".into())),
+        }
+    }
+
+    fn shutdown(&mut self) -> SinkResult<W> {
+        let pipeline = self.pipeline.take()
+            .ok_or(Error::DataError("Writer pipeline already stopped".into()))?;
+
+        drop(pipeline.jobs);
+        for worker in pipeline.workers {
+            worker.join()
+                .map_err(|_| Error::DataError("Writer worker panicked".into()))?;
+        }
+
+        pipeline.sink.join()
+            .map_err(|_| Error::DataError("Writer sink panicked".into()))?
+    }
+
+    /// Write blockmap, footer table and header, returns the sink
+    pub fn finish(mut self) -> Result<W, Error> {
+        let (mut sink, mut position, written) = self.shutdown()?;
+        if written.len() != self.entries.len() {
+            return Err(Error::DataError(format!("Sink wrote {} of {} entries", written.len(), self.entries.len())));
+        }
+
+        let mut footers = vec![];
+        let mut blockmap = AppxBlockMap::default();
+
+        for (file_id, (meta, written)) in self.entries.iter().zip(&written).enumerate() {
+            let compressed_length = match meta.options.compress {
+                true => written.stored_length,
+                false => meta.size,
+            };
+            footers.push(EAppxFooter::new(file_id as u64, written.offset, meta.options.key_index,
+                meta.options.compress, meta.size, compressed_length));
+
+            let blocks = written.blocks.iter()
+                .map(|(hash, stored_size)| {
+                    let size = match meta.options.compress {
+                        true => Some(u32::try_from(*stored_size)
+                            .map_err(|_| Error::DataError(format!("Compressed block of {} too large for blockmap ({stored_size:#x})", meta.name)))?),
+                        false => None,
+                    };
+                    Ok(blockmap::Block { hash: Base64::encode_string(hash), size, ..Default::default() })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            blockmap.files.push(blockmap::File {
+                name: meta.name.clone(),
+                id: format!("{file_id:X}"),
+                size: meta.size,
+                encrypted: Some(meta.options.key_index.is_some()),
+                blocks,
+                filehash: Some(blockmap::FileHash { hash: Base64::encode_string(&meta.filehash), ..Default::default() }),
+                ..Default::default()
+            });
+        }
+
+        // Blockmap itself is a regular, compressed and unencrypted file entry
+        let blockmap_xml = blockmap.to_xml();
+        let blockmap_hash = Sha256::digest(blockmap_xml.as_bytes()).to_vec();
+        let blockmap_id = self.entries.len() as u64;
+        let blockmap_data = deflate_all(blockmap_xml.as_bytes(), self.options.compression_level)?;
+
+        position = write_padding(&mut sink, position, self.options.alignment)?;
+        sink.write_all(&blockmap_data)?;
+        footers.push(EAppxFooter::new(blockmap_id, position, None, true,
+            blockmap_xml.len() as u64, blockmap_data.len() as u64));
+        position += blockmap_data.len() as u64;
+
+        let footer_offset = position;
+        for footer in &footers {
+            sink.write_all(&footer.to_bytes()?)?;
+        }
+        position += footers.len() as u64 * EAppxFooter::SIZE as u64;
+
+        // Like MakeAppx, the catalog follows the footer table
+        let mut code_integrity = FootprintPayload::default();
+        if let Some(catalog) = self.catalog.as_ref().filter(|catalog| !catalog.is_empty()) {
+            log::info!("Generating code integrity catalog for {} PE files", catalog.len());
+            let catalog = catalog.to_der();
+            let catalog_data = deflate_all(&catalog, self.options.compression_level)?;
+            sink.write_all(&catalog_data)?;
+            code_integrity = FootprintPayload {
+                offset: position,
+                compressed: true,
+                uncompressed_length: catalog.len() as u32,
+                length: catalog_data.len() as u32,
+            };
+        }
+
+        let header: EAppxHeader = Self::header_builder(self.magic, &self.package_full_name, &self.key_ids)
+            .footer_table(footer_offset, footers.len() as u64)
+            .block_map(blockmap_id, blockmap_hash)
+            .code_integrity(code_integrity)
+            .build()?;
+        if header.header_size as u64 != self.header_size {
+            return Err(Error::DataError(format!("Header size changed from {:#x} to {:#x}", self.header_size, header.header_size)));
+        }
+
+        sink.seek(SeekFrom::Start(0))?;
+        sink.write_all(&header.to_bytes()?)?;
+        sink.seek(SeekFrom::End(0))?;
+        sink.flush()?;
+
+        log::info!("Packed {} files, footer table at {footer_offset:#x}", self.entries.len());
+        Ok(sink)
+    }
+}
+
+impl<W: Read + Write + Seek + Send + 'static> PackageWriter<W> {
+    /// [`PackageWriter::finish`], then check the package just written with [`validation::validate_package`]
+    ///
+    /// Catches packages that parse fine but Windows would refuse to deploy,
+    /// e.g. for paths longer than [`validation::MAX_PACKAGE_PATH_LENGTH`].
+    /// The sink is left at its end.
+    pub fn finish_validated(self) -> Result<(W, ValidationReport), Error> {
+        let mut sink = self.finish()?;
+        sink.rewind()?;
+        let eappx = EAppxFile::from_stream(&mut std::io::BufReader::new(&mut sink))?;
+        let report = validation::validate_package(&eappx);
+        sink.seek(SeekFrom::End(0))?;
+        Ok((sink, report))
+    }
+}
+
+impl<W: Write + Seek + Send + 'static> Drop for PackageWriter<W> {
+    fn drop(&mut self) {
+        // Unfinished writer, let the threads wind down
+        if self.pipeline.is_some() {
+            let _ = self.shutdown();
+        }
+        self.keys.iter_mut().for_each(|key| keys::wipe(key));
+    }
+}
+
+/// Read up to one block, short reads only happen at the end of the stream
+pub(crate) fn read_block<R: Read>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut buf = Vec::with_capacity(utils::BLOCK_SIZE);
+    reader.take(utils::BLOCK_SIZE as u64).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// Worker stage: encrypt, hash and compress a single block
+fn process_block(job: BlockJob, level: u32) -> Result<ProcessedBlock, Error> {
+    let BlockJob { seq, entry, block, mut data, last, compress, crypto } = job;
+
+    if data.is_empty() {
+        return Ok(ProcessedBlock { seq, entry, hash: None, data });
+    }
+
+    // Block hashes cover the uncompressed, encrypted data
+    blockmap::stored_block(&mut data, block, crypto.as_deref());
+    let hash = Sha256::digest(&data).to_vec();
+
+    if compress {
+        data = deflate_block(&data, last, level)?;
+    }
+
+    Ok(ProcessedBlock { seq, entry, hash: Some(hash), data })
+}
+
+/// Deflate a block with a fresh compressor
+///
+/// Non-final blocks end on a sync flush, so the output of consecutive calls
+/// can be concatenated into a single deflate stream.
+pub(crate) fn deflate_block(data: &[u8], last: bool, level: u32) -> Result<Vec<u8>, Error> {
+    let mut compress = Compress::new(Compression::new(level), false);
+    let mut out = Vec::with_capacity(data.len() / 2 + 0x100);
+    let flush = match last {
+        true => FlushCompress::Finish,
+        false => FlushCompress::Sync,
+    };
+
+    loop {
+        let consumed = compress.total_in() as usize;
+        let status = compress.compress_vec(&data[consumed..], &mut out, flush)
+            .map_err(|e| Error::data("Deflate failed", e))?;
+
+        let input_done = compress.total_in() as usize == data.len();
+        let flushed = match status {
+            Status::StreamEnd => true,
+            _ => !last && out.len() < out.capacity(),
+        };
+        if input_done && flushed {
+            break;
+        }
+
+        out.reserve(0x4000);
+    }
+
+    Ok(out)
+}
+
+/// Deflate a complete buffer, block by block like the pipeline does
+pub(crate) fn deflate_all(data: &[u8], level: u32) -> Result<Vec<u8>, Error> {
+    let mut out = vec![];
+    let mut chunks = data.chunks(utils::BLOCK_SIZE).peekable();
+    while let Some(chunk) = chunks.next() {
+        out.extend(deflate_block(chunk, chunks.peek().is_none(), level)?);
+    }
+    Ok(out)
+}
+
+/// Zero pad `position` up to the next multiple of `alignment`, returns the new position
+fn write_padding<W: Write>(sink: &mut W, position: u64, alignment: u64) -> Result<u64, Error> {
+    let aligned = position.next_multiple_of(alignment);
+    sink.write_all(&vec![0u8; (aligned - position) as usize])?;
+    Ok(aligned)
+}
+
+/// Sink stage: restore block order and write payloads
+fn run_sink<W: Write>(
+    mut sink: W,
+    mut position: u64,
+    alignment: u64,
+    results: mpsc::Receiver<Result<ProcessedBlock, Error>>,
+) -> SinkResult<W> {
+    let mut pending = BTreeMap::new();
+    let mut next_seq = 0u64;
+    let mut entries: Vec<WrittenEntry> = vec![];
+
+    for result in results {
+        let block = result?;
+        pending.insert(block.seq, block);
+
+        while let Some(block) = pending.remove(&next_seq) {
+            if block.entry == entries.len() {
+                position = write_padding(&mut sink, position, alignment)?;
+                entries.push(WrittenEntry { offset: position, ..Default::default() });
+            }
+            let entry = entries.last_mut()
+                .ok_or(Error::DataError(format!("Block {} arrived without entry", block.seq)))?;
+
+            sink.write_all(&block.data)?;
+            position += block.data.len() as u64;
+            entry.stored_length += block.data.len() as u64;
+            if let Some(hash) = block.hash {
+                entry.blocks.push((hash, block.data.len() as u64));
+            }
+
+            next_seq += 1;
+        }
+    }
+
+    if !pending.is_empty() {
+        return Err(Error::DataError(format!("{} blocks never got written", pending.len())));
+    }
+
+    Ok((sink, position, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use uuid::uuid;
+
+    use super::*;
+    use crate::validation::{Rule, Severity};
+
+    const PACKAGE_FULL_NAME: &str = "TestApp_1.0.0.0_x64__nh20k94c8ngfj";
+
+    fn test_key() -> (KeyId, Vec<u8>) {
+        (
+            KeyId::Guid((
+                uuid!("ddafcf67-7b2c-086d-302b-8adac1bdd3a7"),
+                uuid!("7d53aeb8-5922-f062-b1d7-7e09f5a187a0"),
+            )),
+            hex::decode("9fe75f879e95a5d7f3715c30fce71067fc346efd680fa25e3c737d76acb72b9d").unwrap(),
+        )
+    }
+
+    fn sample_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8 ^ (i / 4096) as u8).collect()
+    }
+
+    #[test]
+    fn test_deflate_blocks_concatenate() {
+        let data = sample_data(3 * utils::BLOCK_SIZE + 0x123);
+        let compressed = deflate_all(&data, 6).unwrap();
+
+        let mut out = vec![];
+        flate2::read::DeflateDecoder::new(compressed.as_slice()).read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        let manifest = b"<Package/>".to_vec();
+        let big = sample_data(5 * utils::BLOCK_SIZE + 0x596);
+        let small = sample_data(0x596);
+
+        let options = WriterOptions { workers: 3, queue_depth: 2, compression_level: 6, code_integrity: false, deterministic: false, alignment: 1 };
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], options).unwrap();
+
+        let plain = EntryOptions { compress: true, key_index: None };
+        let encrypted = EntryOptions { compress: false, key_index: Some(0) };
+        let both = EntryOptions { compress: true, key_index: Some(0) };
+
+        writer.add_entry("AppxManifest.xml", &mut manifest.as_slice(), plain).unwrap();
+        writer.add_entry("Assets/big.bin", &mut big.as_slice(), encrypted).unwrap();
+        writer.add_entry("small.bin", &mut small.as_slice(), both).unwrap();
+        writer.add_entry("empty.bin", &mut [].as_slice(), encrypted).unwrap();
+        let package = writer.finish().unwrap().into_inner();
+
+        let mut eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        eappx.keys.insert(test_key().0, test_key().1);
+
+        assert_eq!(eappx.header.package_full_name(), PACKAGE_FULL_NAME);
+        assert_eq!(eappx.header.file_count, 5);
+        assert_eq!(eappx.blockmap.files.len(), 4);
+        assert_eq!(eappx.blockmap.files[1].name, "Assets\\big.bin");
+        assert!(eappx.verify(package.as_slice()).is_valid());
+
+        // Uncompressed encrypted payloads are stored sector aligned
+        let footer = eappx.find_footer_for_file(1).unwrap();
+        assert_eq!(footer.stored_length(false), utils::align_to_sector(big.len()) as u64);
+
+        let dir = std::env::temp_dir().join(format!("eappx-writer-{}", std::process::id()));
+        eappx.extract_blockmap_files(package.as_slice(), &dir).unwrap();
+        assert_eq!(std::fs::read(dir.join("AppxManifest.xml")).unwrap(), manifest);
+        assert_eq!(std::fs::read(dir.join("Assets").join("big.bin")).unwrap(), big);
+        assert_eq!(std::fs::read(dir.join("small.bin")).unwrap(), small);
+        assert!(std::fs::read(dir.join("empty.bin")).unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pack_code_integrity() {
+        const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+        let expected = eappx.read_code_integrity(EMSIX).unwrap().unwrap();
+
+        let options = WriterOptions { code_integrity: true, ..Default::default() };
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], options).unwrap();
+        for file in &eappx.blockmap.files {
+            let options = EntryOptions { compress: false, key_index: Some(0) };
+            writer.add_entry(&file.name, &mut eappx.file_reader(EMSIX, file).unwrap(), options).unwrap();
+        }
+        let package = writer.finish().unwrap().into_inner();
+
+        let packed = EAppxFile::from_source(package.as_slice()).unwrap();
+        assert!(packed.verify(package.as_slice()).is_valid());
+
+        // Only catalog identifier and timestamp differ from what MakeAppx generated
+        let catalog = packed.read_code_integrity(package.as_slice()).unwrap().unwrap();
+        assert_eq!(catalog.len(), expected.len());
+        assert!(catalog.iter().zip(&expected).filter(|(a, b)| a != b).count() <= 16 + 13);
+    }
+
+    #[test]
+    fn test_pack_deterministic() {
+        const EMSIX: &[u8] = include_bytes!("../testdata/TestApp_1.0.3.0_x64.emsix");
+        let mut eappx = EAppxFile::from_source(EMSIX).unwrap();
+        eappx.load_keys(&crate::sample::sample_keys()).unwrap();
+
+        let pack = |workers| {
+            let options = WriterOptions { workers, queue_depth: 2, code_integrity: true, deterministic: true, ..Default::default() };
+            let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], options).unwrap();
+            for file in &eappx.blockmap.files {
+                let options = EntryOptions { compress: true, key_index: Some(0) };
+                writer.add_entry(&file.name, &mut eappx.file_reader(EMSIX, file).unwrap(), options).unwrap();
+            }
+            writer.finish().unwrap().into_inner()
+        };
+        let package = pack(1);
+        assert_eq!(package, pack(4));
+
+        let packed = EAppxFile::from_source(package.as_slice()).unwrap();
+        assert!(packed.header.is_code_integrity_protected());
+        assert!(packed.verify(package.as_slice()).is_valid());
+    }
+
+    #[test]
+    fn test_pack_compression_policy() {
+        let data = sample_data(2 * utils::BLOCK_SIZE + 0x123);
+        let policy = |name: &str, first_block: &[u8]| !name.ends_with(".pak") && first_block.len() == utils::BLOCK_SIZE;
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], WriterOptions::default())
+            .unwrap()
+            .compression_policy(policy);
+        writer.add_file("data.bin", &mut data.as_slice(), None).unwrap();
+        writer.add_file("data.pak", &mut data.as_slice(), None).unwrap();
+        writer.add_file("small.bin", &mut &data[..0x100], None).unwrap();
+        writer.add_file("encrypted.bin", &mut data.as_slice(), Some(0)).unwrap();
+        let package = writer.finish().unwrap().into_inner();
+
+        let mut eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        eappx.keys.insert(test_key().0, test_key().1);
+        assert!(eappx.verify(package.as_slice()).is_valid());
+        let compressed: Vec<bool> = (0..4).map(|id| eappx.find_footer_for_file(id).unwrap().compression_type == 0x1).collect();
+        assert_eq!(compressed, [true, false, false, false]);
+    }
+
+    #[test]
+    fn test_pack_alignment() {
+        let data = sample_data(utils::BLOCK_SIZE + 0x123);
+        for alignment in [utils::SECTOR_SIZE as u64, utils::BLOCK_SIZE as u64] {
+            let options = WriterOptions { alignment, ..Default::default() };
+            let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], options).unwrap();
+            writer.add_entry("compressed.bin", &mut data.as_slice(), EntryOptions { compress: true, key_index: None }).unwrap();
+            writer.add_entry("encrypted.bin", &mut data.as_slice(), EntryOptions { compress: false, key_index: Some(0) }).unwrap();
+            writer.add_entry("empty.bin", &mut [].as_slice(), EntryOptions::default()).unwrap();
+            writer.add_entry("small.bin", &mut &data[..0x11], EntryOptions::default()).unwrap();
+            let package = writer.finish().unwrap().into_inner();
+
+            let mut eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+            eappx.keys.insert(test_key().0, test_key().1);
+            assert!(eappx.verify(package.as_slice()).is_valid());
+            assert!(eappx.footers.iter().all(|footer| footer.offset_to_file % alignment == 0));
+        }
+
+        for alignment in [0x100, 0x600] {
+            let options = WriterOptions { alignment, ..Default::default() };
+            assert!(PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], options).is_err());
+        }
+        let options = WriterOptions { alignment: 0x100, ..Default::default() };
+        assert!(PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![], options).is_ok());
+    }
+
+    /// Incompressible blocks grow beyond 64KiB, corruption stays local to its block
+    #[test]
+    fn test_pack_large_compressed_blocks() {
+        let mut noise = vec![0u8; 4 * utils::BLOCK_SIZE];
+        let mut state = 0x2545f491u32;
+        for byte in noise.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *byte = state as u8;
+        }
+
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], WriterOptions::default()).unwrap();
+        writer.add_entry("noise.bin", &mut noise.as_slice(), EntryOptions { compress: true, key_index: Some(0) }).unwrap();
+        let mut package = writer.finish().unwrap().into_inner();
+
+        let eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        let sizes = eappx.blockmap.files[0].block_sizes().unwrap();
+        assert!(sizes.iter().all(|size| *size as usize > utils::BLOCK_SIZE));
+        assert!(eappx.verify(package.as_slice()).is_valid());
+
+        let footer = eappx.find_footer_for_file(0).unwrap();
+        let third_block = footer.offset_to_file as usize + (sizes[0] + sizes[1]) as usize;
+        package[third_block + 0x100] ^= 0xff;
+        let report = eappx.verify(package.as_slice());
+        assert_eq!(report.files[0].failed_blocks, vec![2]);
+    }
+
+    #[test]
+    fn test_pack_generated_entries() {
+        let entries = (0..40usize).map(|i| PackEntry {
+            name: format!("gen\\{i}.bin"),
+            reader: std::io::Cursor::new(sample_data(i * 0x1111)),
+            options: EntryOptions { compress: i % 2 == 0, key_index: (i % 2 == 1).then_some(0) },
+        });
+
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], WriterOptions::default()).unwrap();
+        assert_eq!(writer.add_entries(entries).unwrap(), 40);
+        assert_eq!(writer.entry_count(), 40);
+        let package = writer.finish().unwrap().into_inner();
+
+        let eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        assert_eq!(eappx.header.file_count, 41);
+        assert_eq!(eappx.header.block_map_file_id, 40);
+        assert_eq!(eappx.header.footer_offset + eappx.header.footer_length, package.len() as u64);
+        assert!(eappx.verify(package.as_slice()).is_valid());
+    }
+
+    #[test]
+    fn test_pack_numeric_key_id() {
+        let data = sample_data(utils::BLOCK_SIZE + 0x123);
+        let key = (KeyId::Numeric(1), test_key().1);
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![key], WriterOptions::default()).unwrap();
+        writer.add_entry("AppxManifest.xml", &mut b"<Package/>".as_slice(), EntryOptions::default()).unwrap();
+        writer.add_file("data.bin", &mut data.as_slice(), Some(0)).unwrap();
+        let package = writer.finish().unwrap().into_inner();
+
+        let mut eappx = EAppxFile::from_source(package.as_slice()).unwrap();
+        assert_eq!(eappx.header.key_ids, vec![KeyId::Numeric(1)]);
+
+        // Stored as key-id count, nil GUID and the number in the last GUID byte
+        let mut raw = vec![1, 0];
+        raw.extend_from_slice(&[0; 31]);
+        raw.push(1);
+        assert!(package[..eappx.header.header_size as usize].windows(raw.len()).any(|window| window == raw));
+
+        // Found by its number as well as by its 32-byte form
+        let key = base64ct::Base64::encode_string(&test_key().1);
+        for key_id in ["1", "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAE="] {
+            let keyfile = format!("[Keys]\n\"{key_id}\" \"{key}\"\n");
+            eappx.keys.clear();
+            eappx.load_keys(&keyfile.parse().unwrap()).unwrap();
+            assert!(eappx.missing_keys().is_empty());
+            assert!(eappx.verify(package.as_slice()).is_valid());
+
+            let mut plaintext = vec![];
+            eappx.file_reader(package.as_slice(), eappx.blockmap.find_file("data.bin").unwrap()).unwrap().read_to_end(&mut plaintext).unwrap();
+            assert_eq!(plaintext, data);
+        }
+    }
+
+    #[test]
+    fn test_pack_validated() {
+        let long_name = format!("Assets\\{}.bin", "a".repeat(validation::MAX_PACKAGE_PATH_LENGTH));
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![test_key()], WriterOptions::default()).unwrap();
+        writer.add_entry("AppxManifest.xml", &mut b"<Package/>".as_slice(), EntryOptions::default()).unwrap();
+        writer.add_file(&long_name, &mut sample_data(0x123).as_slice(), Some(0)).unwrap();
+        let (package, report) = writer.finish_validated().unwrap();
+        assert_eq!(package.position(), package.get_ref().len() as u64);
+
+        let issue = report.issues.iter().find(|issue| issue.rule == Rule::PathName).unwrap();
+        assert_eq!(issue.severity, Severity::Error);
+        assert!(issue.message.contains(&long_name), "{issue}");
+    }
+
+    #[test]
+    fn test_invalid_key_index() {
+        let mut writer = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, vec![], WriterOptions::default()).unwrap();
+        let res = writer.add_entry("a.bin", &mut [1u8].as_slice(), EntryOptions { compress: false, key_index: Some(0) });
+        assert!(res.is_err());
+
+        let short_key = vec![(test_key().0, vec![0u8; 0x10])];
+        let err = PackageWriter::new(Cursor::new(vec![]), EAppxMagic::EXPH, PACKAGE_FULL_NAME, short_key, WriterOptions::default()).err().unwrap();
+        assert_eq!(err.to_string(), Error::DataError("Invalid key length 0x10, the header declares 0x20".into()).to_string());
+    }
+}
@@ -0,0 +1,227 @@
+//! Lossless XML tree for documents the crate only partly models
+//!
+//! Elements are parsed down to their attributes and children, everything else
+//! (declaration, comments, whitespace, text) is kept as written. Start tags are
+//! written back as read until an attribute changes, so a parsed document
+//! serializes byte for byte unless it was modified.
+
+use xmlserde::quick_xml::{events::{BytesStart, Event}, Reader};
+
+use crate::{error::Error, utils::xml_escape};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Document {
+    /// XML declaration and whatever else precedes the root element
+    prolog: String,
+    pub root: Element,
+    /// Whatever follows the root element
+    epilog: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Node {
+    Element(Element),
+    /// Text, comments, whitespace and the like, as written
+    Raw(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Element {
+    pub name: String,
+    /// Names and unescaped values in document order
+    attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+    /// Start tag as written, dropped once an attribute changes
+    raw_start: Option<String>,
+    /// Whether `raw_start` closes the element (`<name/>`)
+    self_closing: bool,
+}
+
+impl Document {
+    pub fn parse(xml: &[u8], what: &str) -> Result<Self, Error> {
+        let utf8 = |data: &[u8]| String::from_utf8(data.to_vec())
+            .map_err(|e| Error::decode(format!("{what} is not valid UTF-8"), e));
+
+        let mut reader = Reader::from_reader(xml);
+        let mut open: Vec<Element> = vec![];
+        let (mut prolog, mut root, mut epilog) = (String::new(), None, String::new());
+
+        loop {
+            let offset = reader.buffer_position();
+            let event = reader.read_event()
+                .map_err(|e| Error::decode(format!("Invalid {what} XML at {offset:#x}"), e))?;
+            let raw = utf8(&xml[offset..reader.buffer_position()])?;
+
+            let node = match event {
+                Event::Start(start) => {
+                    open.push(Element::read(&start, raw, false, what)?);
+                    continue;
+                },
+                Event::Empty(start) => Node::Element(Element::read(&start, raw, true, what)?),
+                Event::End(_) => Node::Element(open.pop()
+                    .ok_or(Error::DecodeError(format!("Unexpected end tag in {what} at {offset:#x}")))?),
+                Event::Eof => break,
+                _ => Node::Raw(raw),
+            };
+
+            match (open.last_mut(), node) {
+                (Some(parent), node) => parent.children.push(node),
+                (None, Node::Element(element)) => {
+                    // Whatever follows the root is kept as is
+                    root = Some(element);
+                    epilog = utf8(&xml[reader.buffer_position()..])?;
+                    break;
+                },
+                (None, Node::Raw(raw)) => prolog += &raw,
+            }
+        }
+
+        if !open.is_empty() {
+            return Err(Error::DecodeError(format!("{what} ends inside an element")));
+        }
+        let root = root.ok_or(Error::DecodeError(format!("{what} has no root element")))?;
+        Ok(Self { prolog, root, epilog })
+    }
+
+    pub fn new(prolog: String, root: Element) -> Self {
+        Self { prolog, root, epilog: String::new() }
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = self.prolog.clone();
+        self.root.write(&mut xml);
+        xml + &self.epilog
+    }
+}
+
+impl Element {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.to_owned(), attributes: vec![], children: vec![], raw_start: None, self_closing: false }
+    }
+
+    fn read(start: &BytesStart, raw_start: String, self_closing: bool, what: &str) -> Result<Self, Error> {
+        let name = String::from_utf8(start.name().as_ref().to_vec())
+            .map_err(|e| Error::decode(format!("{what} is not valid UTF-8"), e))?;
+
+        let mut attributes = vec![];
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| Error::decode(format!("Invalid attribute in {what}"), e))?;
+            let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+            let value = attribute.unescape_value()
+                .map_err(|e| Error::decode(format!("Invalid value of {what} attribute {key}"), e))?;
+            attributes.push((key, value.into_owned()));
+        }
+
+        Ok(Self { name, attributes, children: vec![], raw_start: Some(raw_start), self_closing })
+    }
+
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Set an attribute, appended if the element does not have it yet
+    pub fn set_attribute(&mut self, name: &str, value: &str) {
+        match self.attributes.iter_mut().find(|(key, _)| key == name) {
+            Some((_, known)) if known == value => return,
+            Some((_, known)) => *known = value.to_owned(),
+            None => self.attributes.push((name.to_owned(), value.to_owned())),
+        }
+        self.raw_start = None;
+    }
+
+    pub fn remove_attribute(&mut self, name: &str) {
+        let before = self.attributes.len();
+        self.attributes.retain(|(key, _)| key != name);
+        if self.attributes.len() != before {
+            self.raw_start = None;
+        }
+    }
+
+    pub fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|node| match node {
+            Node::Element(element) => Some(element),
+            Node::Raw(_) => None,
+        })
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.elements().find(|element| element.name == name)
+    }
+
+    pub fn child_mut(&mut self, name: &str) -> Option<&mut Element> {
+        self.children.iter_mut().find_map(|node| match node {
+            Node::Element(element) if element.name == name => Some(element),
+            _ => None,
+        })
+    }
+
+    /// Text content as written, without child elements
+    pub fn text(&self) -> String {
+        self.children.iter()
+            .filter_map(|node| match node {
+                Node::Raw(raw) => Some(raw.as_str()),
+                Node::Element(_) => None,
+            })
+            .collect()
+    }
+
+    /// Position of the first child element named `name` among all child nodes
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.children.iter()
+            .position(|node| matches!(node, Node::Element(element) if element.name == name))
+    }
+
+    fn write(&self, xml: &mut String) {
+        let closed = match &self.raw_start {
+            Some(raw_start) if !self.self_closing || self.children.is_empty() => {
+                *xml += raw_start;
+                self.self_closing
+            },
+            _ => {
+                *xml += "<";
+                *xml += &self.name;
+                for (key, value) in &self.attributes {
+                    *xml += &format!(r#" {key}="{}""#, xml_escape(value));
+                }
+                *xml += if self.children.is_empty() { "/>" } else { ">" };
+                self.children.is_empty()
+            },
+        };
+        if closed {
+            return;
+        }
+
+        for child in &self.children {
+            match child {
+                Node::Element(element) => element.write(xml),
+                Node::Raw(raw) => *xml += raw,
+            }
+        }
+        *xml += &format!("</{}>", self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let xml = "<?xml version=\"1.0\"?>\n<!-- head -->\n<a x='1'  y=\"&amp;\"><b/>text<![CDATA[<raw>]]><c></c><!-- c --></a>\n";
+        let mut document = Document::parse(xml.as_bytes(), "Test").unwrap();
+        assert_eq!(document.to_xml(), xml);
+        assert_eq!(document.root.attribute("y"), Some("&"));
+
+        document.root.set_attribute("x", "1");
+        assert_eq!(document.to_xml(), xml);
+        document.root.set_attribute("x", "<2>");
+        document.root.child_mut("b").unwrap().children.push(Node::Element(Element::new("d")));
+        document.root.child_mut("c").unwrap().remove_attribute("none");
+        assert_eq!(document.to_xml(), "<?xml version=\"1.0\"?>\n<!-- head -->\n<a x=\"&lt;2&gt;\" y=\"&amp;\"><b><d/></b>text<![CDATA[<raw>]]><c></c><!-- c --></a>\n");
+
+        assert!(Document::parse(b"<a><b></a>", "Test").is_err());
+        assert!(Document::parse(b"<!-- only -->", "Test").is_err());
+    }
+}